@@ -1,10 +1,12 @@
-use criterion::{criterion_group, criterion_main, Criterion};
-use sha3::Sha3_256;
+use criterion::{criterion_group, Criterion};
+use sha3::{Digest, Sha3_256};
 
-use merkle_tree_rs::MerkleTree;
+use merkle_tree_rs::{
+    verify, verify_proofs_batch, DeferredMerkleTree, MerkleTree, SmallMerkleTree,
+};
 
 fn bench_initialization(c: &mut Criterion) {
-    let initial_value = [0x00; 32].try_into().unwrap();
+    let initial_value = [0x00; 32].into();
     c.benchmark_group("initialization")
         .bench_function("initialization_5", |b| {
             b.iter(|| MerkleTree::<Sha3_256>::new(5, &initial_value))
@@ -17,40 +19,156 @@ fn bench_initialization(c: &mut Criterion) {
         });
 }
 
+fn bench_from_leaves(c: &mut Criterion) {
+    let leaves: Vec<_> = (0..1u32 << 16)
+        .map(|i| Sha3_256::digest(i.to_le_bytes()))
+        .collect();
+    let padding = [0x00; 32].into();
+    let mut group = c.benchmark_group("from_leaves");
+    group.bench_function("from_leaves_17", |b| {
+        b.iter(|| MerkleTree::<Sha3_256>::from_leaves(&leaves, &padding))
+    });
+    #[cfg(feature = "rayon")]
+    group.bench_function("par_from_leaves_17", |b| {
+        b.iter(|| MerkleTree::<Sha3_256>::par_from_leaves(&leaves, &padding))
+    });
+    // with the SHA extensions both take the scalar path
+    #[cfg(feature = "simd")]
+    {
+        use merkle_tree_rs::{MultiLane, Plain};
+        use sha2::Sha256;
+
+        let leaves: Vec<_> = (0..1u32 << 16)
+            .map(|i| Sha256::digest(i.to_le_bytes()))
+            .collect();
+        group.bench_function("sha256_from_leaves_17", |b| {
+            b.iter(|| MerkleTree::<Sha256>::from_leaves(&leaves, &padding))
+        });
+        group.bench_function("multi_lane_from_leaves_17", |b| {
+            b.iter(|| MerkleTree::<Sha256, MultiLane<Plain>>::from_leaves(&leaves, &padding))
+        });
+    }
+    group.finish();
+}
+
+fn bench_small_tree(c: &mut Criterion) {
+    let leaves: [_; 8] = std::array::from_fn(|i| Sha3_256::digest((i as u32).to_le_bytes()));
+    let padding = [0x00; 32].into();
+    c.benchmark_group("small_tree")
+        .bench_function("from_leaves_4", |b| {
+            b.iter(|| MerkleTree::<Sha3_256>::from_leaves(&leaves, &padding))
+        })
+        .bench_function("small_from_leaves_4", |b| {
+            b.iter(|| SmallMerkleTree::<Sha3_256, 8>::from_leaves(leaves))
+        });
+}
+
 fn bench_set(c: &mut Criterion) {
     let initial_value = [0x00; 32];
-    let mut tree = MerkleTree::<Sha3_256>::new(20, &initial_value.try_into().unwrap());
-    let updated_value = [0x11; 32].try_into().unwrap();
+    let mut tree = MerkleTree::<Sha3_256>::new(20, &initial_value.into());
+    let updated_value = [0x11; 32].into();
     c.bench_function("set", |b| b.iter(|| tree.set(5, &updated_value)));
 }
 
+/// updates 100k leaves of a tree with 2^19 leaves and reads the root once
+fn bench_batch_update(c: &mut Criterion) {
+    let initial_value = [0x00; 32].into();
+    let updates: Vec<(usize, _)> = (0..100_000)
+        .map(|i| (i * 5, Sha3_256::digest((i as u32).to_le_bytes())))
+        .collect();
+    let mut group = c.benchmark_group("batch_update");
+    let mut tree = MerkleTree::<Sha3_256>::new(20, &initial_value);
+    group.bench_function("eager_set_100k", |b| {
+        b.iter(|| {
+            for (offset, value) in &updates {
+                tree.set(*offset, value);
+            }
+            *tree.root_hash()
+        })
+    });
+    let mut tree = DeferredMerkleTree::new(MerkleTree::<Sha3_256>::new(20, &initial_value));
+    group.bench_function("deferred_set_100k", |b| {
+        b.iter(|| {
+            for (offset, value) in &updates {
+                tree.set(*offset, value);
+            }
+            *tree.root_hash()
+        })
+    });
+    group.finish();
+}
+
 fn bench_create_proof(c: &mut Criterion) {
     let initial_value = [0x00; 32];
-    let mut tree = MerkleTree::<Sha3_256>::new(20, &initial_value.try_into().unwrap());
+    let mut tree = MerkleTree::<Sha3_256>::new(20, &initial_value.into());
     for i in 0..tree.num_leaves() {
         let updated_value = [(i * 0x11) as u8; 32];
-        tree.set(i, &updated_value.try_into().unwrap());
+        tree.set(i, &updated_value.into());
     }
     c.bench_function("create_proof", |b| b.iter(|| tree.create_proof(5)));
 }
 
 fn bench_verify_proof(c: &mut Criterion) {
     let initial_value = [0x00; 32];
-    let mut tree = MerkleTree::<Sha3_256>::new(20, &initial_value.try_into().unwrap());
+    let mut tree = MerkleTree::<Sha3_256>::new(20, &initial_value.into());
     for i in 0..tree.num_leaves() {
         let updated_value = [(i * 0x11) as u8; 32];
-        tree.set(i, &updated_value.try_into().unwrap());
+        tree.set(i, &updated_value.into());
     }
-    let leaf_5 = [5 * 0x11 as u8; 32].try_into().unwrap();
+    let leaf_5 = [5 * 0x11_u8; 32].into();
     let proof = tree.create_proof(5);
     c.bench_function("verify_proof", |b| {
         b.iter(|| tree.verify_proof(&leaf_5, &proof))
     });
 }
 
+/// verifies the proofs of every 16th leaf of a tree with 2^16 leaves
+fn bench_verify_proofs_batch(c: &mut Criterion) {
+    let leaves: Vec<_> = (0..1u32 << 16)
+        .map(|i| Sha3_256::digest(i.to_le_bytes()))
+        .collect();
+    let tree = MerkleTree::<Sha3_256>::from_leaves(&leaves, &[0x00; 32].into());
+    let root = *tree.root_hash();
+    let items: Vec<_> = (0..4096)
+        .map(|i| (leaves[i * 16], tree.create_proof(i * 16)))
+        .collect();
+    let mut group = c.benchmark_group("verify_proofs_batch");
+    group.bench_function("verify_4096", |b| {
+        b.iter(|| {
+            items
+                .iter()
+                .map(|(leaf, proof)| verify(&root, leaf, proof))
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("verify_proofs_batch_4096", |b| {
+        b.iter(|| verify_proofs_batch(&root, &items))
+    });
+    group.finish();
+}
+
 criterion_group!(
     name = benches;
     config = Criterion::default().sample_size(20);
-    targets = bench_initialization, bench_set, bench_create_proof, bench_verify_proof
+    targets = bench_initialization, bench_from_leaves, bench_small_tree, bench_set, bench_batch_update, bench_create_proof, bench_verify_proof, bench_verify_proofs_batch
 );
-criterion_main!(benches);
+
+/// writes the machine-readable report to the file named by `MERKLE_BENCH_JSON`, see
+/// `merkle_tree_rs::bench`
+#[cfg(feature = "bench")]
+fn export_json() {
+    use merkle_tree_rs::bench::{run_benchmarks, BenchReport};
+    use std::time::Duration;
+
+    if let Some(path) = std::env::var_os("MERKLE_BENCH_JSON") {
+        let report = BenchReport::new(run_benchmarks(Duration::from_millis(500)));
+        std::fs::write(&path, report.to_json()).expect("failed to write the benchmark report");
+    }
+}
+
+fn main() {
+    benches();
+    #[cfg(feature = "bench")]
+    export_json();
+    Criterion::default().configure_from_args().final_summary();
+}
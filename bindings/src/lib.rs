@@ -0,0 +1,14 @@
+//! The bindings of `merkle_tree_rs::bindings` linked into a shared library
+//!
+//! The main crate is only built as a Rust library, so it builds for targets without an allocator
+//! or panic handler. Build this package with the feature of the bindings instead:
+//!
+//! ```text
+//! cargo build --manifest-path bindings/Cargo.toml --release --features uniffi
+//! wasm-pack build bindings --target web -- --features wasm
+//! ```
+
+#[cfg(feature = "uniffi")]
+pub use merkle_tree_rs::bindings::mobile::*;
+#[cfg(feature = "wasm")]
+pub use merkle_tree_rs::bindings::wasm::*;
@@ -0,0 +1,72 @@
+//! Generates the root and the claim proofs of a token airdrop
+//!
+//! Every recipient is a leaf holding the hash of `<address>:<amount>`. The root is published, the
+//! proofs are handed out to the recipients which claim their tokens by presenting their leaf and
+//! proof. The output is JSON using the `serde` feature.
+//!
+//! ```text
+//! cargo run --example airdrop --features serde
+//! ```
+
+use merkle_tree_rs::{verify, MerkleTree, Proof};
+use serde::Serialize;
+use sha3::{Digest, Keccak256};
+
+/// a claim handed out to a recipient
+#[derive(Serialize)]
+struct Claim<'a> {
+    address: &'a str,
+    amount: u64,
+    proof: Proof<Keccak256>,
+}
+
+/// the generated airdrop
+#[derive(Serialize)]
+struct Airdrop<'a> {
+    root: String,
+    claims: Vec<Claim<'a>>,
+}
+
+/// returns the leaf payload of a recipient
+fn leaf_data(address: &str, amount: u64) -> String {
+    format!("{address}:{amount}")
+}
+
+fn main() {
+    let recipients = [
+        ("0x5b38da6a701c568545dcfcb03fcb875f56beddc4", 100),
+        ("0xab8483f64d9c6d1ecf9b849ae677dd3315835cb2", 250),
+        ("0x4b20993bc481177ec7e8f571cecae8a9e22c02db", 75),
+        ("0x78731d3ca6b7e34ac0f824c42a7cc18a495cabab", 1000),
+        ("0x617f2e2fd72fd9d5503197092ac168c91465e7f2", 5),
+    ];
+    let data: Vec<String> = recipients
+        .iter()
+        .map(|(address, amount)| leaf_data(address, *amount))
+        .collect();
+    // unused leaves hold a value no recipient can produce a preimage for
+    let tree = MerkleTree::<Keccak256>::from_leaf_data(&data, &Default::default());
+
+    // the proofs of the padding leaves are dropped by the zip
+    let claims: Vec<Claim> = recipients
+        .iter()
+        .zip(tree.create_all_proofs())
+        .map(|((address, amount), proof)| Claim {
+            address,
+            amount: *amount,
+            proof,
+        })
+        .collect();
+
+    // this is what the claim contract checks
+    for claim in &claims {
+        let leaf = Keccak256::digest(leaf_data(claim.address, claim.amount));
+        assert!(verify(tree.root_hash(), &leaf, &claim.proof));
+    }
+
+    let airdrop = Airdrop {
+        root: hex::encode(tree.root_hash()),
+        claims,
+    };
+    println!("{}", serde_json::to_string_pretty(&airdrop).unwrap());
+}
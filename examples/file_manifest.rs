@@ -0,0 +1,127 @@
+//! Creates and verifies a manifest of the files in a directory
+//!
+//! `create` hashes every file into a leaf and writes the depth, the layer blobs of the tree and the
+//! file names to the manifest. `verify` trusts only the root given on the command line, checks the
+//! layers against it with a `LayerProver` and then checks every file against its leaf.
+//!
+//! ```text
+//! cargo run --example file_manifest -- create <dir> <manifest>
+//! cargo run --example file_manifest -- verify <dir> <manifest> <root>
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use digest::Output;
+use merkle_tree_rs::{LayerProver, MerkleTree};
+use sha3::{Digest, Sha3_256};
+
+/// returns the leaf of a file, binding its name to its content
+fn file_leaf(name: &str, content: &[u8]) -> Output<Sha3_256> {
+    let mut hasher = Sha3_256::new();
+    hasher.update((name.len() as u64).to_le_bytes());
+    hasher.update(name);
+    hasher.update(content);
+    hasher.finalize()
+}
+
+/// returns the sorted names of all files in a directory
+fn file_names(dir: &Path) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// writes the manifest and returns the root
+fn create(dir: &Path, manifest: &Path) -> io::Result<String> {
+    let names = file_names(dir)?;
+    let leaves = names
+        .iter()
+        .map(|name| Ok(file_leaf(name, &fs::read(dir.join(name))?)))
+        .collect::<io::Result<Vec<_>>>()?;
+    let tree = MerkleTree::<Sha3_256>::from_leaves(&leaves, &Default::default());
+    let depth = tree.num_leaves().trailing_zeros() as usize + 1;
+
+    let mut content = format!("{depth}\n");
+    for layer in 0..depth {
+        let blob = tree.export_layer(layer).map_err(io::Error::other)?;
+        content.push_str(&hex::encode(blob));
+        content.push('\n');
+    }
+    for name in &names {
+        content.push_str(name);
+        content.push('\n');
+    }
+    fs::write(manifest, content)?;
+    Ok(hex::encode(tree.root_hash()))
+}
+
+/// checks every file listed in the manifest against the trusted root
+fn verify(dir: &Path, manifest: &Path, root: &str) -> io::Result<bool> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let content = fs::read_to_string(manifest)?;
+    let mut lines = content.lines();
+    let depth: usize = lines
+        .next()
+        .and_then(|line| line.parse().ok())
+        .ok_or_else(|| invalid("invalid depth"))?;
+    let root = hex::decode(root).map_err(|_| invalid("invalid root"))?;
+    if root.len() != Sha3_256::output_size() {
+        return Err(invalid("invalid root"));
+    }
+
+    let mut prover = LayerProver::<Sha3_256>::new(Output::<Sha3_256>::from_slice(&root), depth)
+        .map_err(io::Error::other)?;
+    for _ in 0..depth {
+        let blob = lines
+            .next()
+            .and_then(|line| hex::decode(line).ok())
+            .ok_or_else(|| invalid("invalid layer blob"))?;
+        prover.add_layer_blob(&blob).map_err(io::Error::other)?;
+    }
+
+    let mut ok = true;
+    for (offset, name) in lines.enumerate() {
+        // proving the leaf checks the layers against the root
+        let leaf = prover.leaf(offset).copied();
+        let valid = match (leaf, prover.create_proof(offset), fs::read(dir.join(name))) {
+            (Some(leaf), Ok(_), Ok(content)) => file_leaf(name, &content) == leaf,
+            _ => false,
+        };
+        println!("{} {name}", if valid { "ok" } else { "FAILED" });
+        ok &= valid;
+    }
+    Ok(ok)
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["create", dir, manifest] => {
+            println!("{}", create(Path::new(dir), Path::new(manifest))?);
+        }
+        ["verify", dir, manifest, root] => {
+            if !verify(Path::new(dir), Path::new(manifest), root)? {
+                std::process::exit(1);
+            }
+        }
+        _ => {
+            eprintln!("usage: file_manifest create <dir> <manifest>");
+            eprintln!("       file_manifest verify <dir> <manifest> <root>");
+            std::process::exit(2);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+//! A persistent RFC 6962 log served over TCP
+//!
+//! Entries are appended to a file, one hex encoded entry per line, and replayed into a `LogTree` on
+//! startup. The server speaks a line based protocol:
+//!
+//! - `add <text>` appends an entry and answers with its index
+//! - `sth` answers with the tree size and the root hash
+//! - `proof <index> <tree size>` answers with the inclusion proof of an entry
+//! - `consistency <old size> <new size>` answers with a consistency proof
+//!
+//! ```text
+//! cargo run --example log_server -- log.txt 127.0.0.1:6962
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+
+use merkle_tree_rs::LogTree;
+use sha2::Sha256;
+
+/// the log and the file it is persisted to
+struct Log {
+    tree: LogTree<Sha256>,
+    file: File,
+}
+
+impl Log {
+    /// opens the log file and replays all entries
+    fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut tree = LogTree::new();
+        for line in BufReader::new(&file).lines() {
+            let entry = hex::decode(line?)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            tree.push_leaf_data(&entry);
+        }
+        Ok(Self { tree, file })
+    }
+
+    /// persists an entry before adding it to the tree
+    fn add(&mut self, entry: &[u8]) -> io::Result<usize> {
+        writeln!(self.file, "{}", hex::encode(entry))?;
+        self.file.sync_data()?;
+        Ok(self.tree.push_leaf_data(entry))
+    }
+
+    /// answers a single request line
+    fn handle(&mut self, line: &str) -> io::Result<String> {
+        let mut parts = line.splitn(2, ' ');
+        let command = parts.next().unwrap_or_default();
+        let args = parts.next().unwrap_or_default();
+        let numbers: Vec<usize> = args
+            .split_whitespace()
+            .filter_map(|arg| arg.parse().ok())
+            .collect();
+        Ok(match (command, numbers.as_slice()) {
+            ("add", _) => self.add(args.as_bytes())?.to_string(),
+            ("sth", _) => format!("{} {}", self.tree.len(), hex::encode(self.tree.root_hash())),
+            ("proof", [index, size]) => match self.tree.create_proof_at(*index, *size) {
+                Some(proof) => hex_list(proof.path()),
+                None => "error: no such entry".to_string(),
+            },
+            ("consistency", [old, new]) => match self.tree.create_consistency_proof(*old, *new) {
+                Some(proof) => hex_list(proof.path()),
+                None => "error: invalid tree sizes".to_string(),
+            },
+            _ => "error: unknown command".to_string(),
+        })
+    }
+}
+
+/// formats hashes as space separated hex strings
+fn hex_list<T: AsRef<[u8]>>(hashes: &[T]) -> String {
+    hashes.iter().map(hex::encode).collect::<Vec<_>>().join(" ")
+}
+
+/// answers all requests of one client
+fn serve(log: &mut Log, stream: TcpStream) -> io::Result<()> {
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    for line in BufReader::new(stream).lines() {
+        let response = log.handle(line?.trim_end())?;
+        writeln!(writer, "{response}")?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap_or_else(|| "log.txt".to_string());
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:6962".to_string());
+
+    let mut log = Log::open(&path)?;
+    let listener = TcpListener::bind(&addr)?;
+    println!(
+        "serving {} entries from {path} on {}",
+        log.tree.len(),
+        listener.local_addr()?
+    );
+    for stream in listener.incoming() {
+        if let Err(err) = serve(&mut log, stream?) {
+            eprintln!("client error: {err}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+//! An authenticated key-value store on top of a sparse `StoredMerkleTree`
+//!
+//! Every key is assigned the slot given by the first bytes of its hash in a tree with 2^32 leaves.
+//! Only the written nodes are kept in the `MemoryStore`, untouched subtrees use the precomputed
+//! default hashes. A client that knows the root can check a lookup with the returned proof.
+//!
+//! ```text
+//! cargo run --example sparse_kv
+//! ```
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+use digest::Output;
+use merkle_tree_rs::{verify, MemoryStore, Proof, StoredMerkleTree, StoredTreeError};
+use sha3::{Digest, Sha3_256};
+
+/// depth of the tree, 2^32 slots
+const DEPTH: usize = 33;
+
+/// a verifiable key-value store
+struct SparseKv {
+    tree: StoredMerkleTree<Sha3_256, MemoryStore<Sha3_256>>,
+    values: HashMap<String, String>,
+}
+
+impl SparseKv {
+    fn new() -> Self {
+        Self {
+            tree: StoredMerkleTree::new(MemoryStore::new(), DEPTH, &Default::default()),
+            values: HashMap::new(),
+        }
+    }
+
+    /// returns the slot of a key
+    /// two keys sharing a slot are not supported by this example
+    fn slot(key: &str) -> usize {
+        let hash = Sha3_256::digest(key);
+        u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]]) as usize
+    }
+
+    /// returns the leaf committing to a key and its value
+    fn leaf(key: &str, value: &str) -> Output<Sha3_256> {
+        let mut hasher = Sha3_256::new();
+        hasher.update((key.len() as u64).to_le_bytes());
+        hasher.update(key);
+        hasher.update(value);
+        hasher.finalize()
+    }
+
+    fn insert(&mut self, key: &str, value: &str) -> Result<(), StoredTreeError<Infallible>> {
+        self.tree.set(Self::slot(key), &Self::leaf(key, value))?;
+        self.values.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    /// returns the value of a key together with the proof of its slot
+    fn get(
+        &self,
+        key: &str,
+    ) -> Result<Option<(&str, Proof<Sha3_256>)>, StoredTreeError<Infallible>> {
+        let Some(value) = self.values.get(key) else {
+            return Ok(None);
+        };
+        Ok(Some((value, self.tree.create_proof(Self::slot(key))?)))
+    }
+
+    fn root(&self) -> Result<Output<Sha3_256>, Infallible> {
+        self.tree.root_hash()
+    }
+}
+
+fn main() -> Result<(), StoredTreeError<Infallible>> {
+    let mut kv = SparseKv::new();
+    kv.insert("alice", "1 BTC")?;
+    kv.insert("bob", "2 ETH")?;
+    kv.insert("carol", "3 DOGE")?;
+    let root = kv.root().map_err(StoredTreeError::Store)?;
+    println!("root {}", hex::encode(root));
+
+    for key in ["alice", "bob", "carol", "dave"] {
+        match kv.get(key)? {
+            Some((value, proof)) => {
+                let valid = verify(&root, &SparseKv::leaf(key, value), &proof);
+                println!("{key} = {value} (proof valid: {valid})");
+                // the proof doesn't verify for another value
+                assert!(!verify(&root, &SparseKv::leaf(key, "forged"), &proof));
+            }
+            None => println!("{key} not found"),
+        }
+    }
+    println!("{} nodes stored", kv.tree.store().len());
+    Ok(())
+}
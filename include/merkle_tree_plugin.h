@@ -0,0 +1,35 @@
+/*
+ * C ABI of merkle-tree-rs hasher plugins
+ *
+ * A plugin is a shared library exporting `merkle_tree_hasher_plugin`, which returns a pointer to a
+ * function table that stays valid until `release` is called. The hash functions write
+ * `output_size` bytes to `out` and return 0 on success or a plugin specific error code. They may
+ * be called from several threads at the same time.
+ */
+
+#ifndef MERKLE_TREE_PLUGIN_H
+#define MERKLE_TREE_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define MERKLE_TREE_PLUGIN_ABI_VERSION 1
+
+typedef struct merkle_tree_hasher_vtable {
+    /* has to be MERKLE_TREE_PLUGIN_ABI_VERSION */
+    uint32_t abi_version;
+    /* size of the hashes in bytes */
+    uint32_t output_size;
+    /* opaque context passed to all functions */
+    void *context;
+    /* hashes a leaf payload of len bytes */
+    int (*hash_leaf)(void *context, const uint8_t *data, size_t len, uint8_t *out);
+    /* hashes two child nodes of output_size bytes each into their parent */
+    int (*hash_node)(void *context, const uint8_t *left, const uint8_t *right, uint8_t *out);
+    /* releases the context, may be NULL */
+    void (*release)(void *context);
+} merkle_tree_hasher_vtable;
+
+const merkle_tree_hasher_vtable *merkle_tree_hasher_plugin(void);
+
+#endif
@@ -0,0 +1,300 @@
+//! Append-only accumulators with logarithmic state
+//!
+//! A `MerkleAccumulator` has the root of a `MerkleTree` of fixed depth whose leaves are filled from
+//! left to right while the unused leaves hold the padding, like the incremental tree of Ethereum's
+//! deposit contract. Instead of all nodes it keeps the frontier, for every layer the last node
+//! that is the left child of its parent, and the roots of the empty subtrees of every height. An
+//! append hashes the path of the new leaf once, every layer above it is either joined with the
+//! frontier on the left or with an empty subtree on the right.
+//!
+//! Proofs are kept for the most recently appended leaves only, `with_proof_window` sets how many.
+//! Every append replaces one sibling of each kept proof, the one where the paths of the two leaves
+//! join, so the state grows by one proof per kept leaf and not with the number of leaves.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTreeError, Plain, Proof, MAX_DEPTH};
+
+/// An append-only Merkle tree storing one node per layer, see the module docs
+pub struct MerkleAccumulator<D: Digest, S = Plain> {
+    depth: usize,
+    /// the last node of every layer below the root that is a left child, the leaf layer first
+    frontier: Vec<Output<D>>,
+    /// the root of an empty subtree of every height, the padding first
+    zeros: Vec<Output<D>>,
+    root: Output<D>,
+    len: usize,
+    /// the number of recent leaves whose proofs are kept
+    window: usize,
+    /// the offsets and siblings of the kept proofs, the oldest first
+    proofs: VecDeque<(usize, Vec<Output<D>>)>,
+    scheme: PhantomData<fn() -> S>,
+}
+
+impl<D, S> MerkleAccumulator<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates an accumulator for `2^(depth - 1)` leaves without any appended leaf
+    /// panics if the depth is 0 or larger than `MAX_DEPTH`, see `try_new` for a fallible version
+    pub fn new(depth: usize, padding: &Output<D>) -> Self {
+        match Self::try_new(depth, padding) {
+            Ok(accumulator) => accumulator,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates an accumulator for `2^(depth - 1)` leaves without any appended leaf
+    /// returns an error if the depth is 0 or larger than `MAX_DEPTH`
+    pub fn try_new(depth: usize, padding: &Output<D>) -> Result<Self, MerkleTreeError> {
+        if depth == 0 || depth > MAX_DEPTH {
+            return Err(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            });
+        }
+        let mut zeros = Vec::with_capacity(depth);
+        zeros.push(*padding);
+        for height in 1..depth {
+            let zero = &zeros[height - 1];
+            zeros.push(S::hash_node(zero, zero));
+        }
+        Ok(Self {
+            depth,
+            frontier: zeros[..depth - 1].to_vec(),
+            root: zeros[depth - 1],
+            zeros,
+            len: 0,
+            window: 0,
+            proofs: VecDeque::new(),
+            scheme: PhantomData,
+        })
+    }
+
+    /// keeps the proofs of the last `window` appended leaves, starting with the next append
+    /// a smaller window drops the oldest of the kept proofs
+    pub fn with_proof_window(mut self, window: usize) -> Self {
+        self.window = window;
+        while self.proofs.len() > window {
+            self.proofs.pop_front();
+        }
+        self
+    }
+
+    /// returns the depth of the tree, the number of layers including the root and the leaves
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// returns the number of leaves of the tree
+    pub fn capacity(&self) -> usize {
+        1 << (self.depth - 1)
+    }
+
+    /// returns the number of appended leaves
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// returns true if no leaves were appended
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// returns the root of the tree
+    pub fn root(&self) -> &Output<D> {
+        &self.root
+    }
+
+    /// appends a leaf and returns its offset
+    /// panics if all leaves are used, see `try_append` for a fallible version
+    pub fn append(&mut self, leaf: &Output<D>) -> usize {
+        match self.try_append(leaf) {
+            Ok(offset) => offset,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// appends a leaf and returns its offset
+    /// returns an error if all leaves are used
+    pub fn try_append(&mut self, leaf: &Output<D>) -> Result<usize, MerkleTreeError> {
+        let offset = self.len;
+        if offset == self.capacity() {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.capacity(),
+            });
+        }
+        let mut siblings = Vec::with_capacity(self.depth - 1);
+        let mut node = *leaf;
+        for height in 0..self.depth - 1 {
+            // kept proofs get the node on the new path as sibling where the paths join
+            for (kept, proof) in self.proofs.iter_mut() {
+                if *kept >> height == (offset >> height) ^ 1 {
+                    proof[height] = node;
+                }
+            }
+            if (offset >> height) & 1 == 0 {
+                self.frontier[height] = node;
+                siblings.push(self.zeros[height]);
+                node = S::hash_node(&node, &self.zeros[height]);
+            } else {
+                siblings.push(self.frontier[height]);
+                node = S::hash_node(&self.frontier[height], &node);
+            }
+        }
+        self.root = node;
+        self.len += 1;
+        if self.window > 0 {
+            while self.proofs.len() >= self.window {
+                self.proofs.pop_front();
+            }
+            self.proofs.push_back((offset, siblings));
+        }
+        Ok(offset)
+    }
+
+    /// creates a proof for a recently appended leaf against the current root
+    /// panics if the proof of the leaf isn't kept, see `try_create_proof` for a fallible version
+    pub fn create_proof(&self, offset: usize) -> Proof<D, S> {
+        match self.try_create_proof(offset) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a proof for a recently appended leaf against the current root
+    /// returns an error if the leaf wasn't appended yet or its proof isn't kept
+    pub fn try_create_proof(&self, offset: usize) -> Result<Proof<D, S>, MerkleTreeError> {
+        if offset >= self.len {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.len,
+            });
+        }
+        self.proofs
+            .iter()
+            .find(|(kept, _)| *kept == offset)
+            .map(|(_, siblings)| Proof::new(offset, siblings.clone()))
+            .ok_or(MerkleTreeError::ProofNotRetained { offset })
+    }
+}
+
+impl<D, S> Clone for MerkleAccumulator<D, S>
+where
+    D: Digest,
+    Output<D>: Copy,
+{
+    fn clone(&self) -> Self {
+        Self {
+            depth: self.depth,
+            frontier: self.frontier.clone(),
+            zeros: self.zeros.clone(),
+            root: self.root,
+            len: self.len,
+            window: self.window,
+            proofs: self.proofs.clone(),
+            scheme: PhantomData,
+        }
+    }
+}
+
+impl<D: Digest, S> Debug for MerkleAccumulator<D, S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MerkleAccumulator")
+            .field("root", &hex::encode(&self.root))
+            .field("depth", &self.depth)
+            .field("len", &self.len)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    use crate::{verify, Rfc6962};
+
+    type MerkleTree = crate::MerkleTree<Sha3_256, Rfc6962>;
+    type MerkleAccumulator = super::MerkleAccumulator<Sha3_256, Rfc6962>;
+
+    #[test]
+    fn test_append() {
+        let padding = [0; 32].into();
+        let mut accumulator = MerkleAccumulator::new(5, &padding).with_proof_window(3);
+        let mut tree = MerkleTree::new(5, &padding);
+        assert_eq!(accumulator.root(), tree.root_hash());
+        assert!(accumulator.is_empty());
+
+        for i in 0..16u8 {
+            assert_eq!(accumulator.append(&[i + 1; 32].into()), i as usize);
+            tree.set(i as usize, &[i + 1; 32].into());
+            assert_eq!(accumulator.root(), tree.root_hash());
+
+            let first = (i as usize + 1).saturating_sub(3);
+            for offset in first..=i as usize {
+                let proof = accumulator.create_proof(offset);
+                assert_eq!(proof, tree.create_proof(offset));
+                assert!(verify(
+                    accumulator.root(),
+                    tree.get(offset).unwrap(),
+                    &proof
+                ));
+            }
+            if first > 0 {
+                assert_eq!(
+                    accumulator.try_create_proof(first - 1),
+                    Err(MerkleTreeError::ProofNotRetained { offset: first - 1 })
+                );
+            }
+        }
+        assert_eq!(accumulator.len(), accumulator.capacity());
+        assert!(accumulator.try_append(&[0xff; 32].into()).is_err());
+        assert!(accumulator.clone().try_create_proof(16).is_err());
+        assert!(MerkleAccumulator::try_new(0, &padding).is_err());
+
+        let mut single = MerkleAccumulator::new(1, &padding).with_proof_window(1);
+        single.append(&[7; 32].into());
+        assert_eq!(single.root(), &[7; 32].into());
+        assert!(single.create_proof(0).is_empty());
+    }
+
+    #[test]
+    fn test_shrink_proof_window() {
+        let padding = [0; 32].into();
+        let mut accumulator = MerkleAccumulator::new(4, &padding).with_proof_window(4);
+        let mut tree = MerkleTree::new(4, &padding);
+        for i in 0..4u8 {
+            accumulator.append(&[i + 1; 32].into());
+            tree.set(i as usize, &[i + 1; 32].into());
+        }
+
+        let mut accumulator = accumulator.with_proof_window(2);
+        assert_eq!(
+            accumulator.try_create_proof(1),
+            Err(MerkleTreeError::ProofNotRetained { offset: 1 })
+        );
+        assert_eq!(accumulator.create_proof(2), tree.create_proof(2));
+
+        accumulator.append(&[5; 32].into());
+        tree.set(4, &[5; 32].into());
+        assert!(accumulator.try_create_proof(2).is_err());
+        for offset in 3..5 {
+            assert_eq!(accumulator.create_proof(offset), tree.create_proof(offset));
+        }
+
+        let mut accumulator = accumulator.with_proof_window(0);
+        accumulator.append(&[6; 32].into());
+        assert!(accumulator.try_create_proof(5).is_err());
+        assert!(accumulator.try_create_proof(4).is_err());
+    }
+}
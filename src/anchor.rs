@@ -0,0 +1,567 @@
+//! Anchoring roots in external systems
+//!
+//! An `Anchor` writes a root into a system third parties can read, a blockchain or a transparency
+//! service, and returns an `AnchorRef` telling where the root was written. Anyone holding the
+//! reference resolves it to the anchored root with the same kind of anchor. Products only depend on
+//! the trait and can switch between anchoring targets, `AnchorSink` plugs any anchor into a
+//! `Publisher`.
+//!
+//! `MultiAnchor` publishes every root to several anchors and resolves the resulting `AnchorSet`
+//! only if a quorum of the anchors agrees on the root, so a single compromised or unavailable
+//! anchor can neither forge a root nor block verification.
+//!
+//! With the `anchor` feature `bitcoin::BitcoinAnchor` embeds roots in `OP_RETURN` outputs through
+//! the RPC interface of a Bitcoin Core wallet and `https::HttpsAnchor` posts them to a transparency
+//! endpoint. With the `ethereum` feature `publish::ethereum::EthereumSink` anchors roots in a
+//! contract.
+
+use std::fmt::{self, Debug};
+use std::io;
+use std::str::FromStr;
+
+use digest::{Digest, Output};
+
+use crate::publish::{Publication, RootSink, SinkError};
+
+#[cfg(feature = "anchor")]
+pub mod bitcoin;
+#[cfg(feature = "anchor")]
+pub mod https;
+
+/// Errors returned by anchors
+#[derive(Debug)]
+pub enum AnchorError {
+    /// reading from or writing to the connection failed
+    Io(io::Error),
+    /// the URL of the anchoring system is not valid
+    InvalidUrl { url: String },
+    /// the endpoint answered with a non-success status code
+    HttpStatus { status: u16 },
+    /// a request to the anchoring system failed
+    Request { message: String },
+    /// the anchoring system rejected the root
+    Rejected { reason: String },
+    /// the reference belongs to another anchoring system
+    WrongSystem { expected: String, actual: String },
+    /// nothing is anchored at the reference
+    NotFound { locator: String },
+    /// the root is not confirmed by the anchoring system yet
+    Unconfirmed { locator: String },
+    /// the anchored data or the reference is malformed
+    Malformed { reason: String },
+    /// fewer anchors than the quorum agree on a root
+    NoQuorum { agreeing: usize, quorum: usize },
+}
+
+impl fmt::Display for AnchorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "I/O error: {err}"),
+            Self::InvalidUrl { url } => write!(f, "invalid URL {url}"),
+            Self::HttpStatus { status } => write!(f, "endpoint answered with status {status}"),
+            Self::Request { message } => write!(f, "request failed: {message}"),
+            Self::Rejected { reason } => write!(f, "root was rejected: {reason}"),
+            Self::WrongSystem { expected, actual } => {
+                write!(f, "expected an anchor in {expected}, got one in {actual}")
+            }
+            Self::NotFound { locator } => write!(f, "nothing is anchored at {locator}"),
+            Self::Unconfirmed { locator } => write!(f, "anchor {locator} is not confirmed yet"),
+            Self::Malformed { reason } => write!(f, "malformed anchor: {reason}"),
+            Self::NoQuorum { agreeing, quorum } => {
+                write!(
+                    f,
+                    "{agreeing} anchors agree on a root, the quorum is {quorum}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for AnchorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for AnchorError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<SinkError> for AnchorError {
+    fn from(err: SinkError) -> Self {
+        match err {
+            SinkError::Io(err) => Self::Io(err),
+            SinkError::InvalidUrl { url } => Self::InvalidUrl { url },
+            SinkError::HttpStatus { status } => Self::HttpStatus { status },
+            SinkError::Rejected { reason } => Self::Rejected { reason },
+            SinkError::Rpc { message } => Self::Request { message },
+        }
+    }
+}
+
+impl From<AnchorError> for SinkError {
+    fn from(err: AnchorError) -> Self {
+        match err {
+            AnchorError::Io(err) => Self::Io(err),
+            AnchorError::InvalidUrl { url } => Self::InvalidUrl { url },
+            AnchorError::HttpStatus { status } => Self::HttpStatus { status },
+            AnchorError::Request { message } => Self::Rpc { message },
+            AnchorError::Rejected { reason } => Self::Rejected { reason },
+            err => Self::Rejected {
+                reason: err.to_string(),
+            },
+        }
+    }
+}
+
+/// Where a root was anchored
+/// The reference is written as `<system>:<locator>`, e.g. `bitcoin:<txid>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AnchorRef {
+    /// the anchoring system, e.g. `ethereum` or `bitcoin`
+    pub system: String,
+    /// the location of the root in the system, e.g. a transaction hash
+    pub locator: String,
+}
+
+impl AnchorRef {
+    /// creates a reference to a root at the locator in the system
+    pub fn new(system: impl Into<String>, locator: impl Into<String>) -> Self {
+        Self {
+            system: system.into(),
+            locator: locator.into(),
+        }
+    }
+
+    /// returns an error if the reference doesn't belong to the given system
+    pub fn check_system(&self, system: &str) -> Result<(), AnchorError> {
+        if self.system != system {
+            return Err(AnchorError::WrongSystem {
+                expected: system.to_string(),
+                actual: self.system.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AnchorRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.system, self.locator)
+    }
+}
+
+impl FromStr for AnchorRef {
+    type Err = AnchorError;
+
+    fn from_str(s: &str) -> Result<Self, AnchorError> {
+        match s.split_once(':') {
+            Some((system, locator)) if !system.is_empty() && !locator.is_empty() => {
+                Ok(Self::new(system, locator))
+            }
+            _ => Err(AnchorError::Malformed {
+                reason: format!("{s} is not of the form <system>:<locator>"),
+            }),
+        }
+    }
+}
+
+/// Anchors roots in an external system
+pub trait Anchor<D: Digest> {
+    /// anchors the root, returns where it was anchored
+    fn publish(&mut self, root: &Output<D>) -> Result<AnchorRef, AnchorError>;
+
+    /// returns the root anchored at the reference
+    fn resolve(&self, anchor: &AnchorRef) -> Result<Output<D>, AnchorError>;
+}
+
+/// returns the root of the anchored bytes, an error if they are not a root of the digest
+/// A helper for `resolve` implementations.
+pub fn root_from_bytes<D: Digest>(bytes: &[u8]) -> Result<Output<D>, AnchorError> {
+    if bytes.len() != <D as Digest>::output_size() {
+        return Err(AnchorError::Malformed {
+            reason: format!(
+                "anchored {} bytes, roots have {}",
+                bytes.len(),
+                <D as Digest>::output_size()
+            ),
+        });
+    }
+    Ok(Output::<D>::clone_from_slice(bytes))
+}
+
+/// converts the error of an HTTP request
+#[cfg(feature = "anchor")]
+fn http_error(err: ureq::Error) -> AnchorError {
+    match err {
+        ureq::Error::Io(err) => AnchorError::Io(err),
+        ureq::Error::StatusCode(status) => AnchorError::HttpStatus { status },
+        err => AnchorError::Request {
+            message: err.to_string(),
+        },
+    }
+}
+
+/// A `RootSink` anchoring every publication, remembering where each version was anchored
+pub struct AnchorSink<A> {
+    anchor: A,
+    anchors: Vec<(u64, AnchorRef)>,
+}
+
+impl<A: Debug> Debug for AnchorSink<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnchorSink")
+            .field("anchor", &self.anchor)
+            .field("anchors", &self.anchors)
+            .finish()
+    }
+}
+
+impl<A> AnchorSink<A> {
+    /// creates a sink publishing roots with the anchor
+    pub fn new(anchor: A) -> Self {
+        Self {
+            anchor,
+            anchors: Vec::new(),
+        }
+    }
+
+    /// returns the anchor
+    pub fn anchor(&self) -> &A {
+        &self.anchor
+    }
+
+    /// returns the anchored versions with their references in publication order
+    pub fn anchors(&self) -> &[(u64, AnchorRef)] {
+        &self.anchors
+    }
+
+    /// returns the reference of the last anchor of the version
+    pub fn anchor_of(&self, version: u64) -> Option<&AnchorRef> {
+        self.anchors
+            .iter()
+            .rev()
+            .find(|(anchored, _)| *anchored == version)
+            .map(|(_, anchor)| anchor)
+    }
+}
+
+impl<D: Digest, A: Anchor<D>> RootSink<D> for AnchorSink<A> {
+    fn publish(&mut self, publication: &Publication<D>) -> Result<(), SinkError> {
+        let anchor = self.anchor.publish(&publication.root)?;
+        self.anchors.push((publication.version, anchor));
+        Ok(())
+    }
+}
+
+/// The references of a root published to the anchors of a `MultiAnchor`, one per anchor
+/// Anchors which failed to publish the root have no reference.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AnchorSet {
+    refs: Vec<Option<AnchorRef>>,
+}
+
+impl AnchorSet {
+    /// creates a set from the references in the order of the anchors
+    pub fn new(refs: Vec<Option<AnchorRef>>) -> Self {
+        Self { refs }
+    }
+
+    /// returns the references in the order of the anchors
+    pub fn refs(&self) -> &[Option<AnchorRef>] {
+        &self.refs
+    }
+
+    /// returns the number of anchors holding the root
+    pub fn len(&self) -> usize {
+        self.refs.iter().flatten().count()
+    }
+
+    /// returns true if no anchor holds the root
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Publishes roots to several anchors, a root resolves only if a quorum of them agrees on it
+pub struct MultiAnchor<D: Digest> {
+    anchors: Vec<Box<dyn Anchor<D> + Send>>,
+    quorum: usize,
+}
+
+impl<D: Digest> Debug for MultiAnchor<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultiAnchor")
+            .field("anchors", &self.anchors.len())
+            .field("quorum", &self.quorum)
+            .finish()
+    }
+}
+
+impl<D: Digest> MultiAnchor<D> {
+    /// creates a multi anchor without anchors requiring the given number of them to agree
+    /// A quorum of more than half of the anchors guarantees that no two roots reach it.
+    pub fn new(quorum: usize) -> Self {
+        Self {
+            anchors: Vec::new(),
+            quorum,
+        }
+    }
+
+    /// adds an anchor
+    pub fn with_anchor(mut self, anchor: impl Anchor<D> + Send + 'static) -> Self {
+        self.anchors.push(Box::new(anchor));
+        self
+    }
+
+    /// returns the number of anchors that have to agree on a root
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+
+    /// returns the number of anchors
+    pub fn len(&self) -> usize {
+        self.anchors.len()
+    }
+
+    /// returns true if there are no anchors
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+
+    /// publishes the root to all anchors, returns the references of the anchors that published it
+    /// returns an error if fewer anchors than the quorum published the root, the root can't be
+    /// resolved then
+    pub fn publish(&mut self, root: &Output<D>) -> Result<AnchorSet, AnchorError> {
+        let refs: Vec<_> = self
+            .anchors
+            .iter_mut()
+            .map(|anchor| anchor.publish(root).ok())
+            .collect();
+        let set = AnchorSet::new(refs);
+        if set.len() < self.quorum {
+            return Err(AnchorError::NoQuorum {
+                agreeing: set.len(),
+                quorum: self.quorum,
+            });
+        }
+        Ok(set)
+    }
+
+    /// resolves the references with their anchors, returns the root at least a quorum of them
+    /// agrees on
+    /// Anchors that fail to resolve their reference don't count. Returns an error if no root or
+    /// more than one root reaches the quorum.
+    pub fn resolve(&self, set: &AnchorSet) -> Result<Output<D>, AnchorError> {
+        let mut votes: Vec<(Output<D>, usize)> = Vec::new();
+        for (anchor, anchor_ref) in self.anchors.iter().zip(&set.refs) {
+            let Some(root) = anchor_ref.as_ref().and_then(|r| anchor.resolve(r).ok()) else {
+                continue;
+            };
+            match votes.iter_mut().find(|(voted, _)| *voted == root) {
+                Some((_, count)) => *count += 1,
+                None => votes.push((root, 1)),
+            }
+        }
+        let mut reached = votes.iter().filter(|(_, count)| *count >= self.quorum);
+        match (reached.next(), reached.next()) {
+            (Some((root, _)), None) if self.quorum > 0 => Ok(root.clone()),
+            _ => Err(AnchorError::NoQuorum {
+                agreeing: votes.iter().map(|(_, count)| *count).max().unwrap_or(0),
+                quorum: self.quorum,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::time::SystemTime;
+
+    use sha2::Sha256;
+
+    /// serves HTTP on a local port, answering every request with the status and body returned for
+    /// the method, path, headers and body of the request, returns the base URL
+    #[cfg(feature = "anchor")]
+    pub(crate) fn serve<F>(answer: F) -> String
+    where
+        F: Fn(&str, &str, &std::collections::BTreeMap<String, String>, &str) -> (u16, String)
+            + Send
+            + 'static,
+    {
+        use std::collections::BTreeMap;
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut parts = request_line.split_whitespace();
+                let (method, path) = (parts.next().unwrap(), parts.next().unwrap());
+                let mut headers = BTreeMap::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    match line.trim_end().split_once(':') {
+                        Some((name, value)) => {
+                            headers.insert(name.to_ascii_lowercase(), value.trim().to_string())
+                        }
+                        None => break,
+                    };
+                }
+                let length = headers
+                    .get("content-length")
+                    .map_or(0, |v| v.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let (status, response) =
+                    answer(method, path, &headers, &String::from_utf8(body).unwrap());
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} Status\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                    response.len()
+                )
+                .unwrap();
+            }
+        });
+        url
+    }
+
+    /// anchors roots in memory
+    #[derive(Debug, Default)]
+    pub(crate) struct MemoryAnchor {
+        pub(crate) roots: Vec<Output<Sha256>>,
+        /// the root a compromised anchor resolves every reference to
+        pub(crate) forged: Option<Output<Sha256>>,
+        pub(crate) offline: bool,
+    }
+
+    impl Anchor<Sha256> for MemoryAnchor {
+        fn publish(&mut self, root: &Output<Sha256>) -> Result<AnchorRef, AnchorError> {
+            if self.offline {
+                return Err(AnchorError::Request {
+                    message: "offline".to_string(),
+                });
+            }
+            self.roots.push(*root);
+            Ok(AnchorRef::new("memory", (self.roots.len() - 1).to_string()))
+        }
+
+        fn resolve(&self, anchor: &AnchorRef) -> Result<Output<Sha256>, AnchorError> {
+            anchor.check_system("memory")?;
+            if let Some(forged) = self.forged {
+                return Ok(forged);
+            }
+            let index: usize = anchor.locator.parse().map_err(|_| AnchorError::Malformed {
+                reason: anchor.locator.clone(),
+            })?;
+            self.roots
+                .get(index)
+                .copied()
+                .ok_or_else(|| AnchorError::NotFound {
+                    locator: anchor.locator.clone(),
+                })
+        }
+    }
+
+    #[test]
+    fn test_anchor_sink() {
+        let mut sink = AnchorSink::new(MemoryAnchor::default());
+        for (version, data) in ["first", "second"].iter().enumerate() {
+            let publication = Publication::<Sha256> {
+                version: version as u64,
+                root: Sha256::digest(data),
+                updates: 1,
+                published_at: SystemTime::now(),
+            };
+            sink.publish(&publication).unwrap();
+        }
+        assert_eq!(sink.anchors().len(), 2);
+        let anchor = sink.anchor_of(1).unwrap();
+        assert_eq!(anchor.to_string(), "memory:1");
+        assert_eq!(&"memory:1".parse::<AnchorRef>().unwrap(), anchor);
+        assert!("memory".parse::<AnchorRef>().is_err());
+        assert!(":1".parse::<AnchorRef>().is_err());
+
+        let memory = sink.anchor();
+        assert_eq!(memory.resolve(anchor).unwrap(), Sha256::digest("second"));
+        assert!(matches!(
+            memory.resolve(&AnchorRef::new("bitcoin", "1")),
+            Err(AnchorError::WrongSystem { .. })
+        ));
+        assert!(matches!(
+            memory.resolve(&AnchorRef::new("memory", "2")),
+            Err(AnchorError::NotFound { .. })
+        ));
+        assert!(root_from_bytes::<Sha256>(&[0; 20]).is_err());
+    }
+
+    #[test]
+    fn test_multi_anchor() {
+        let forged = Sha256::digest("forged");
+        let compromised = || MemoryAnchor {
+            forged: Some(forged),
+            ..MemoryAnchor::default()
+        };
+        let mut multi = MultiAnchor::new(2)
+            .with_anchor(MemoryAnchor::default())
+            .with_anchor(compromised())
+            .with_anchor(MemoryAnchor::default())
+            .with_anchor(MemoryAnchor {
+                offline: true,
+                ..MemoryAnchor::default()
+            });
+        let root = Sha256::digest("root");
+        let set = multi.publish(&root).unwrap();
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.refs()[3], None);
+        // the compromised anchor is outvoted
+        assert_eq!(multi.resolve(&set).unwrap(), root);
+
+        // a single anchor doesn't reach the quorum
+        let single = AnchorSet::new(vec![set.refs()[0].clone(), None, None, None]);
+        assert!(matches!(
+            multi.resolve(&single),
+            Err(AnchorError::NoQuorum {
+                agreeing: 1,
+                quorum: 2
+            })
+        ));
+
+        let mut strict = MultiAnchor::new(3)
+            .with_anchor(MemoryAnchor::default())
+            .with_anchor(compromised())
+            .with_anchor(MemoryAnchor::default());
+        let set = strict.publish(&root).unwrap();
+        assert!(strict.resolve(&set).is_err());
+
+        // two roots reaching a low quorum are ambiguous
+        let mut split = MultiAnchor::new(1)
+            .with_anchor(MemoryAnchor::default())
+            .with_anchor(compromised());
+        let set = split.publish(&root).unwrap();
+        assert!(split.resolve(&set).is_err());
+
+        let mut offline = MultiAnchor::<Sha256>::new(1).with_anchor(MemoryAnchor {
+            offline: true,
+            ..MemoryAnchor::default()
+        });
+        assert!(matches!(
+            offline.publish(&root),
+            Err(AnchorError::NoQuorum { agreeing: 0, .. })
+        ));
+    }
+}
@@ -0,0 +1,389 @@
+//! Anchoring roots in Bitcoin `OP_RETURN` outputs
+//!
+//! `BitcoinAnchor` talks to the JSON-RPC interface of a Bitcoin Core wallet. Publishing creates a
+//! transaction with a single `OP_RETURN` output holding the root, optionally behind a protocol tag,
+//! lets the wallet fund and sign it and broadcasts it. The anchor reference is the transaction id.
+//!
+//! Resolving doesn't need the wallet that published the root: the transaction is read with
+//! `getrawtransaction` from any Bitcoin Core node running with `-txindex`, and the confirmations
+//! are those of the block header holding it. For nodes without the index the locator may carry
+//! the hash of that block behind the transaction id, `<txid>:<blockhash>`.
+
+use std::fmt;
+use std::time::Duration;
+
+use digest::{Digest, Output};
+use serde_json::{json, Value};
+use ureq::Agent;
+
+use super::{http_error, root_from_bytes, Anchor, AnchorError, AnchorRef};
+
+/// the system name of Bitcoin anchor references
+pub const SYSTEM: &str = "bitcoin";
+
+/// the opcode starting a data carrier output
+const OP_RETURN: u8 = 0x6a;
+/// the opcode pushing the data of the length in the following byte
+const OP_PUSHDATA1: u8 = 0x4c;
+/// the largest payload of a standard `OP_RETURN` output
+const MAX_PAYLOAD: usize = 80;
+/// the RPC error code of unknown transactions
+const RPC_INVALID_ADDRESS_OR_KEY: i64 = -5;
+
+/// Anchors roots in `OP_RETURN` outputs through a Bitcoin Core wallet
+pub struct BitcoinAnchor {
+    agent: Agent,
+    url: String,
+    authorization: Option<String>,
+    tag: Vec<u8>,
+    confirmations: u64,
+}
+
+impl fmt::Debug for BitcoinAnchor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BitcoinAnchor")
+            .field("url", &self.url)
+            .field("tag", &hex::encode(&self.tag))
+            .field("confirmations", &self.confirmations)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BitcoinAnchor {
+    /// creates an anchor using the wallet RPC endpoint at the URL, e.g.
+    /// `http://127.0.0.1:8332/wallet/anchors`
+    /// By default roots are anchored without a tag and resolve once they have one confirmation.
+    pub fn new(rpc_url: &str) -> Result<Self, AnchorError> {
+        if !rpc_url.starts_with("http://") && !rpc_url.starts_with("https://") {
+            return Err(AnchorError::InvalidUrl {
+                url: rpc_url.to_string(),
+            });
+        }
+        let config = Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(Duration::from_secs(30)))
+            .build();
+        Ok(Self {
+            agent: Agent::new_with_config(config),
+            url: rpc_url.to_string(),
+            authorization: None,
+            tag: Vec::new(),
+            confirmations: 1,
+        })
+    }
+
+    /// sets the user and password of the RPC interface, the `rpcauth` or cookie credentials
+    pub fn with_auth(mut self, user: &str, password: &str) -> Self {
+        let credentials = base64(format!("{user}:{password}").as_bytes());
+        self.authorization = Some(format!("Basic {credentials}"));
+        self
+    }
+
+    /// sets the bytes written in front of every root, e.g. a protocol identifier
+    /// The tag and the root have to fit into the 80 bytes of a standard `OP_RETURN` output.
+    pub fn with_tag(mut self, tag: &[u8]) -> Self {
+        self.tag = tag.to_vec();
+        self
+    }
+
+    /// sets the number of confirmations a transaction needs to resolve to its root
+    pub fn with_confirmations(mut self, confirmations: u64) -> Self {
+        self.confirmations = confirmations;
+        self
+    }
+
+    /// calls the RPC method with the parameters, returns the result
+    fn call(&self, method: &str, params: Value) -> Result<Value, AnchorError> {
+        self.try_call(method, params)?
+            .map_err(|(_, message)| AnchorError::Request {
+                message: format!("{method} failed: {message}"),
+            })
+    }
+
+    /// calls an RPC method looking up the anchored transaction, returns the result
+    /// returns `NotFound` if the node doesn't know the transaction or its block
+    fn lookup(
+        &self,
+        method: &str,
+        params: Value,
+        anchor: &AnchorRef,
+    ) -> Result<Value, AnchorError> {
+        match self.try_call(method, params)? {
+            Ok(result) => Ok(result),
+            Err((RPC_INVALID_ADDRESS_OR_KEY, _)) => Err(AnchorError::NotFound {
+                locator: anchor.locator.clone(),
+            }),
+            Err((_, message)) => Err(AnchorError::Request {
+                message: format!("{method} failed: {message}"),
+            }),
+        }
+    }
+
+    /// calls the RPC method with the parameters, returns the result or the code and message of the
+    /// JSON-RPC error
+    fn try_call(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<Result<Value, (i64, String)>, AnchorError> {
+        let request = json!({"jsonrpc": "1.0", "id": method, "method": method, "params": params});
+        let mut post = self
+            .agent
+            .post(&self.url)
+            .header("Content-Type", "application/json");
+        if let Some(authorization) = &self.authorization {
+            post = post.header("Authorization", authorization);
+        }
+        let mut response = post.send(request.to_string()).map_err(http_error)?;
+        let status = response.status().as_u16();
+        // bitcoind reports errors with an error status and a JSON-RPC error object
+        let body = response.body_mut().read_to_string().map_err(http_error)?;
+        let mut response: Value = match serde_json::from_str(&body) {
+            Ok(response) => response,
+            Err(_) if !(200..300).contains(&status) => {
+                return Err(AnchorError::HttpStatus { status })
+            }
+            Err(err) => {
+                return Err(AnchorError::Request {
+                    message: format!("invalid response to {method}: {err}"),
+                })
+            }
+        };
+        Ok(match response["error"].take() {
+            Value::Null => Ok(response["result"].take()),
+            error => Err((
+                error["code"].as_i64().unwrap_or(0),
+                error["message"].as_str().unwrap_or("").to_string(),
+            )),
+        })
+    }
+}
+
+impl<D: Digest> Anchor<D> for BitcoinAnchor {
+    fn publish(&mut self, root: &Output<D>) -> Result<AnchorRef, AnchorError> {
+        let mut payload = self.tag.clone();
+        payload.extend_from_slice(root);
+        if payload.len() > MAX_PAYLOAD {
+            return Err(AnchorError::Rejected {
+                reason: format!("{} bytes don't fit into an OP_RETURN output", payload.len()),
+            });
+        }
+        let raw = self.call(
+            "createrawtransaction",
+            json!([[], [{"data": hex::encode(&payload)}]]),
+        )?;
+        let funded = self.call("fundrawtransaction", json!([raw]))?;
+        let signed = self.call("signrawtransactionwithwallet", json!([funded["hex"]]))?;
+        if signed["complete"] != Value::Bool(true) {
+            return Err(AnchorError::Rejected {
+                reason: "the wallet couldn't sign the transaction".to_string(),
+            });
+        }
+        match self.call("sendrawtransaction", json!([signed["hex"]]))? {
+            Value::String(txid) => Ok(AnchorRef::new(SYSTEM, txid)),
+            other => Err(AnchorError::Request {
+                message: format!("sendrawtransaction returned {other}"),
+            }),
+        }
+    }
+
+    fn resolve(&self, anchor: &AnchorRef) -> Result<Output<D>, AnchorError> {
+        anchor.check_system(SYSTEM)?;
+        let (txid, block_hash) = match anchor.locator.split_once(':') {
+            Some((txid, block_hash)) => (txid, Some(block_hash)),
+            None => (anchor.locator.as_str(), None),
+        };
+        if [Some(txid), block_hash]
+            .into_iter()
+            .flatten()
+            .any(|hash| hash.len() != 64 || hex::decode(hash).is_err())
+        {
+            return Err(AnchorError::Malformed {
+                reason: format!("{} is not a transaction id", anchor.locator),
+            });
+        }
+        // the decoded transaction, with the hash of its block once it was mined
+        let params = match block_hash {
+            Some(block_hash) => json!([txid, true, block_hash]),
+            None => json!([txid, true]),
+        };
+        let tx = self.lookup("getrawtransaction", params, anchor)?;
+        let confirmations = match tx["blockhash"].as_str() {
+            // blocks that fell out of the best chain have negative confirmations
+            Some(block_hash) => self.lookup("getblockheader", json!([block_hash]), anchor)?
+                ["confirmations"]
+                .as_i64()
+                .unwrap_or(0),
+            None => 0,
+        };
+        if confirmations < self.confirmations as i64 {
+            return Err(AnchorError::Unconfirmed {
+                locator: anchor.locator.clone(),
+            });
+        }
+        let outputs = tx["vout"].as_array().map(Vec::as_slice);
+        let payload = outputs
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|output| output["scriptPubKey"]["hex"].as_str())
+            .filter_map(|script| hex::decode(script).ok())
+            .find_map(|script| op_return_payload(&script))
+            .ok_or_else(|| AnchorError::Malformed {
+                reason: format!("transaction {txid} has no OP_RETURN output"),
+            })?;
+        let root =
+            payload
+                .strip_prefix(self.tag.as_slice())
+                .ok_or_else(|| AnchorError::Malformed {
+                    reason: format!("OP_RETURN output of {txid} doesn't start with the tag"),
+                })?;
+        root_from_bytes::<D>(root)
+    }
+}
+
+/// returns the data pushed by an `OP_RETURN` script
+fn op_return_payload(script: &[u8]) -> Option<Vec<u8>> {
+    let (len, data) = match script {
+        [OP_RETURN, OP_PUSHDATA1, len, data @ ..] => (*len as usize, data),
+        [OP_RETURN, len @ 1..=0x4b, data @ ..] => (*len as usize, data),
+        _ => return None,
+    };
+    (data.len() == len).then(|| data.to_vec())
+}
+
+/// returns the standard base64 encoding of the bytes
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let word = chunk.iter().enumerate().fold(0u32, |word, (i, byte)| {
+            word | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(word >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use sha2::Sha256;
+
+    use crate::anchor::tests::serve;
+
+    #[test]
+    fn test_bitcoin_anchor() {
+        assert_eq!(base64(b"user:pass"), "dXNlcjpwYXNz");
+        assert_eq!(base64(b"ab"), "YWI=");
+
+        // a wallet confirming every transaction once it was sent
+        let sent = Arc::new(Mutex::new(Vec::<String>::new()));
+        let url = serve({
+            let sent = Arc::clone(&sent);
+            move |_, _, headers, body| {
+                assert_eq!(headers["authorization"], "Basic dXNlcjpwYXNz");
+                let request: Value = serde_json::from_str(body).unwrap();
+                let params = &request["params"];
+                let result = match request["method"].as_str().unwrap() {
+                    "createrawtransaction" => {
+                        json!(format!("raw{}", params[1][0]["data"].as_str().unwrap()))
+                    }
+                    "fundrawtransaction" => json!({"hex": params[0], "fee": 0.0001}),
+                    "signrawtransactionwithwallet" => json!({"hex": params[0], "complete": true}),
+                    "sendrawtransaction" => {
+                        let mut sent = sent.lock().unwrap();
+                        sent.push(params[0].as_str().unwrap()[3..].to_string());
+                        json!(format!("{:064x}", sent.len()))
+                    }
+                    // a node without a wallet, the first transaction was mined, the others wait in
+                    // the mempool
+                    "getrawtransaction" => {
+                        assert_eq!(params[1], true);
+                        let txid = usize::from_str_radix(params[0].as_str().unwrap(), 16).unwrap();
+                        let Some(data) = sent.lock().unwrap().get(txid - 1).cloned() else {
+                            let error = json!({
+                                "code": -5,
+                                "message": "No such mempool or blockchain transaction"
+                            });
+                            return (500, json!({"result": null, "error": error}).to_string());
+                        };
+                        let script = format!("6a{:02x}{data}", data.len() / 2);
+                        let mut tx = json!({"vout": [
+                            {"scriptPubKey": {"hex": "0014".to_string() + &"00".repeat(20)}},
+                            {"scriptPubKey": {"hex": script}},
+                        ]});
+                        if txid == 1 {
+                            tx["blockhash"] = json!("b".repeat(64));
+                        }
+                        tx
+                    }
+                    "getblockheader" => {
+                        assert_eq!(params[0], "b".repeat(64));
+                        json!({"hash": params[0], "confirmations": 2})
+                    }
+                    method => panic!("unexpected call of {method}"),
+                };
+                (200, json!({"result": result, "error": null}).to_string())
+            }
+        });
+
+        let mut bitcoin = BitcoinAnchor::new(&url)
+            .unwrap()
+            .with_auth("user", "pass")
+            .with_tag(b"MTRS")
+            .with_confirmations(2);
+        let root = Sha256::digest("root");
+        let anchor = Anchor::<Sha256>::publish(&mut bitcoin, &root).unwrap();
+        assert_eq!(anchor, AnchorRef::new(SYSTEM, format!("{:064x}", 1)));
+        assert_eq!(
+            sent.lock().unwrap()[0],
+            hex::encode(b"MTRS") + &hex::encode(root)
+        );
+        assert_eq!(Anchor::<Sha256>::resolve(&bitcoin, &anchor).unwrap(), root);
+
+        // the block hash in the locator is passed on for nodes without a transaction index
+        let with_block = AnchorRef::new(SYSTEM, format!("{:064x}:{}", 1, "b".repeat(64)));
+        assert_eq!(
+            Anchor::<Sha256>::resolve(&bitcoin, &with_block).unwrap(),
+            root
+        );
+        let malformed = AnchorRef::new(SYSTEM, format!("{:064x}:block", 1));
+        assert!(matches!(
+            Anchor::<Sha256>::resolve(&bitcoin, &malformed),
+            Err(AnchorError::Malformed { .. })
+        ));
+
+        // a transaction in the mempool has no confirmations
+        let pending = Anchor::<Sha256>::publish(&mut bitcoin, &root).unwrap();
+        assert!(matches!(
+            Anchor::<Sha256>::resolve(&bitcoin, &pending),
+            Err(AnchorError::Unconfirmed { .. })
+        ));
+        let missing = AnchorRef::new(SYSTEM, format!("{:064x}", 3));
+        assert!(matches!(
+            Anchor::<Sha256>::resolve(&bitcoin, &missing),
+            Err(AnchorError::NotFound { .. })
+        ));
+        let unconfirmed = bitcoin.with_confirmations(3);
+        assert!(matches!(
+            Anchor::<Sha256>::resolve(&unconfirmed, &anchor),
+            Err(AnchorError::Unconfirmed { .. })
+        ));
+        // without the tag the payload is longer than a root
+        let untagged = unconfirmed.with_tag(b"").with_confirmations(1);
+        assert!(matches!(
+            Anchor::<Sha256>::resolve(&untagged, &anchor),
+            Err(AnchorError::Malformed { .. })
+        ));
+        assert!(BitcoinAnchor::new("127.0.0.1:8332").is_err());
+    }
+}
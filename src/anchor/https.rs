@@ -0,0 +1,187 @@
+//! Anchoring roots at an HTTPS transparency endpoint
+//!
+//! `HttpsAnchor` speaks a minimal JSON protocol with a transparency service at a base URL:
+//!
+//! - `POST <base>/roots` with `{"root": "<hex>"}` stores a root and answers `{"id": "<id>"}`
+//! - `GET <base>/roots/<id>` answers `{"root": "<hex>"}` or 404 if there is no such root
+//!
+//! The anchor reference is the id assigned by the service. Plain `http://` URLs are accepted for
+//! services behind a local proxy.
+
+use std::fmt;
+use std::time::Duration;
+
+use digest::{Digest, Output};
+use serde_json::{json, Value};
+use ureq::http::Response;
+use ureq::{Agent, Body};
+
+use super::{http_error, root_from_bytes, Anchor, AnchorError, AnchorRef};
+
+/// the system name of transparency endpoint anchor references
+pub const SYSTEM: &str = "https";
+
+/// Anchors roots at a transparency endpoint
+pub struct HttpsAnchor {
+    agent: Agent,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl fmt::Debug for HttpsAnchor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpsAnchor")
+            .field("base_url", &self.base_url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HttpsAnchor {
+    /// creates an anchor posting roots to the service at the base URL, e.g.
+    /// `https://transparency.example.com/v1`
+    pub fn new(base_url: &str) -> Result<Self, AnchorError> {
+        if !base_url.starts_with("https://") && !base_url.starts_with("http://") {
+            return Err(AnchorError::InvalidUrl {
+                url: base_url.to_string(),
+            });
+        }
+        let config = Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(Duration::from_secs(30)))
+            .build();
+        Ok(Self {
+            agent: Agent::new_with_config(config),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: None,
+        })
+    }
+
+    /// sets the bearer token sent with every request
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(format!("Bearer {token}"));
+        self
+    }
+
+    /// returns the JSON body of a successful response
+    fn json_body(response: Result<Response<Body>, ureq::Error>) -> Result<Value, AnchorError> {
+        let mut response = response.map_err(http_error)?;
+        let status = response.status().as_u16();
+        if !(200..300).contains(&status) {
+            return Err(AnchorError::HttpStatus { status });
+        }
+        let body = response.body_mut().read_to_string().map_err(http_error)?;
+        serde_json::from_str(&body).map_err(|err| AnchorError::Request {
+            message: format!("invalid response: {err}"),
+        })
+    }
+}
+
+impl<D: Digest> Anchor<D> for HttpsAnchor {
+    fn publish(&mut self, root: &Output<D>) -> Result<AnchorRef, AnchorError> {
+        let mut post = self
+            .agent
+            .post(format!("{}/roots", self.base_url))
+            .header("Content-Type", "application/json");
+        if let Some(token) = &self.token {
+            post = post.header("Authorization", token);
+        }
+        let body = json!({"root": hex::encode(root)}).to_string();
+        match &Self::json_body(post.send(body))?["id"] {
+            Value::String(id) => Ok(AnchorRef::new(SYSTEM, id.clone())),
+            Value::Number(id) => Ok(AnchorRef::new(SYSTEM, id.to_string())),
+            _ => Err(AnchorError::Request {
+                message: "the response has no id".to_string(),
+            }),
+        }
+    }
+
+    fn resolve(&self, anchor: &AnchorRef) -> Result<Output<D>, AnchorError> {
+        anchor.check_system(SYSTEM)?;
+        let id = &anchor.locator;
+        // the id becomes a path segment
+        if !id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+        {
+            return Err(AnchorError::Malformed {
+                reason: format!("{id} is not a valid id"),
+            });
+        }
+        let mut get = self.agent.get(format!("{}/roots/{id}", self.base_url));
+        if let Some(token) = &self.token {
+            get = get.header("Authorization", token);
+        }
+        let root = match Self::json_body(get.call()) {
+            Ok(root) => root,
+            Err(AnchorError::HttpStatus { status: 404 }) => {
+                return Err(AnchorError::NotFound {
+                    locator: id.clone(),
+                })
+            }
+            Err(err) => return Err(err),
+        };
+        let root = root["root"]
+            .as_str()
+            .and_then(|root| hex::decode(root).ok())
+            .ok_or_else(|| AnchorError::Malformed {
+                reason: format!("the response for {id} has no hex root"),
+            })?;
+        root_from_bytes::<D>(&root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use sha2::Sha256;
+
+    use crate::anchor::tests::serve;
+
+    #[test]
+    fn test_https_anchor() {
+        // a service numbering the posted roots
+        let roots = Arc::new(Mutex::new(Vec::<String>::new()));
+        let url = serve({
+            let roots = Arc::clone(&roots);
+            move |method, path, headers, body| {
+                assert_eq!(headers["authorization"], "Bearer secret");
+                let mut roots = roots.lock().unwrap();
+                match (method, path) {
+                    ("POST", "/v1/roots") => {
+                        let root: Value = serde_json::from_str(body).unwrap();
+                        roots.push(root["root"].as_str().unwrap().to_string());
+                        (201, json!({"id": format!("r-{}", roots.len())}).to_string())
+                    }
+                    ("GET", path) => {
+                        let id: usize = path.strip_prefix("/v1/roots/r-").unwrap().parse().unwrap();
+                        match roots.get(id - 1) {
+                            Some(root) => (200, json!({"root": root}).to_string()),
+                            None => (404, "{}".to_string()),
+                        }
+                    }
+                    _ => (405, "{}".to_string()),
+                }
+            }
+        });
+
+        let mut https = HttpsAnchor::new(&format!("{url}/v1/"))
+            .unwrap()
+            .with_token("secret");
+        let root = Sha256::digest("root");
+        let anchor = Anchor::<Sha256>::publish(&mut https, &root).unwrap();
+        assert_eq!(anchor.to_string(), "https:r-1");
+        assert_eq!(Anchor::<Sha256>::resolve(&https, &anchor).unwrap(), root);
+        assert!(matches!(
+            Anchor::<Sha256>::resolve(&https, &AnchorRef::new(SYSTEM, "r-2")),
+            Err(AnchorError::NotFound { .. })
+        ));
+        assert!(matches!(
+            Anchor::<Sha256>::resolve(&https, &AnchorRef::new(SYSTEM, "../r-1")),
+            Err(AnchorError::Malformed { .. })
+        ));
+        assert!(HttpsAnchor::new("ftp://example.com").is_err());
+    }
+}
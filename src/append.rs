@@ -0,0 +1,271 @@
+//! Concurrent appends to a log tree
+//!
+//! `ConcurrentAppender` lets any number of producer threads append leaves to a `LogTree`. Producers
+//! only reserve the index of their leaf with an atomic counter and hand the leaf to a combiner
+//! thread over a channel, so they never wait on a lock or on each other. The combiner is the only
+//! thread writing to the tree: it applies the leaves in index order, buffering the ones that
+//! arrive before their predecessors, and updates the frontier of complete subtrees in batches.
+//!
+//! Linearization: an append takes effect at the increment of the index counter in `append`, the
+//! returned index is its position in the total order of all appends. The tree is always a prefix
+//! of that order, the leaf becomes visible to readers once the combiner has applied it together
+//! with all leaves with smaller indices and published the new size (`len`).
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard};
+use std::thread::{self, JoinHandle};
+
+use digest::{Digest, Output};
+
+use crate::scheme::{HashScheme, Rfc6962};
+use crate::LogTree;
+
+/// state shared by the producers and the combiner
+struct Shared<D: Digest, S> {
+    tree: RwLock<LogTree<D, S>>,
+    /// the index of the next reserved leaf
+    reserved: AtomicUsize,
+    /// the number of leaves applied to the tree, guarded for waiting on progress
+    applied: Mutex<usize>,
+    progress: Condvar,
+}
+
+/// Appends leaves from many threads to a `LogTree` through a single combiner thread
+pub struct ConcurrentAppender<D: Digest, S = Rfc6962> {
+    shared: Arc<Shared<D, S>>,
+    sender: Option<Sender<(usize, Output<D>)>>,
+    combiner: Option<JoinHandle<()>>,
+}
+
+impl<D, S> ConcurrentAppender<D, S>
+where
+    D: Digest + Default + Clone + Debug + Send + Sync + 'static,
+    Output<D>: Copy + Send + Sync,
+    S: HashScheme<D> + Send + Sync + 'static,
+{
+    /// starts the combiner thread appending to the given tree
+    pub fn new(tree: LogTree<D, S>) -> Self {
+        let len = tree.len();
+        let shared = Arc::new(Shared {
+            tree: RwLock::new(tree),
+            reserved: AtomicUsize::new(len),
+            applied: Mutex::new(len),
+            progress: Condvar::new(),
+        });
+        let (sender, receiver) = mpsc::channel::<(usize, Output<D>)>();
+        let combiner = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                let mut pending = BTreeMap::new();
+                while let Ok((index, leaf)) = receiver.recv() {
+                    pending.insert(index, leaf);
+                    // apply everything that arrived in the meantime in one batch
+                    pending.extend(receiver.try_iter());
+                    Self::apply(&shared, &mut pending);
+                }
+            })
+        };
+        Self {
+            shared,
+            sender: Some(sender),
+            combiner: Some(combiner),
+        }
+    }
+
+    /// appends an already hashed leaf, returns the index of the new leaf
+    /// the leaf is added to the tree asynchronously by the combiner, see `wait_for`
+    pub fn append(&self, leaf: Output<D>) -> usize {
+        // linearization point of the append
+        let index = self.shared.reserved.fetch_add(1, Ordering::Relaxed);
+        let sent = match &self.sender {
+            Some(sender) => sender.send((index, leaf)).is_ok(),
+            None => false,
+        };
+        if !sent {
+            panic!("the combiner thread of the appender has stopped");
+        }
+        index
+    }
+
+    /// appends a leaf payload, returns the index of the new leaf
+    pub fn append_data(&self, data: &[u8]) -> usize {
+        self.append(S::hash_leaf(data))
+    }
+
+    /// returns the number of reserved leaves, including the ones not yet applied to the tree
+    pub fn reserved(&self) -> usize {
+        self.shared.reserved.load(Ordering::Relaxed)
+    }
+
+    /// returns the number of leaves applied to the tree
+    pub fn len(&self) -> usize {
+        *self.lock_applied()
+    }
+
+    /// returns true if no leaves were applied to the tree
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// blocks until the leaf with the given index has been applied to the tree
+    /// the index has to be reserved already, otherwise this waits until another thread reserves it
+    pub fn wait_for(&self, index: usize) {
+        let mut applied = self.lock_applied();
+        while *applied <= index {
+            applied = self
+                .shared
+                .progress
+                .wait(applied)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// blocks until all leaves reserved before the call have been applied to the tree
+    pub fn flush(&self) {
+        let reserved = self.reserved();
+        if reserved > 0 {
+            self.wait_for(reserved - 1);
+        }
+    }
+
+    /// returns read access to the tree holding all applied leaves
+    /// the combiner can't apply new leaves while the guard is held
+    pub fn tree(&self) -> RwLockReadGuard<'_, LogTree<D, S>> {
+        self.shared
+            .tree
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// stops the combiner once all reserved leaves are applied and returns the tree
+    pub fn finish(mut self) -> LogTree<D, S> {
+        self.stop();
+        let shared = Arc::clone(&self.shared);
+        drop(self);
+        match Arc::try_unwrap(shared) {
+            Ok(shared) => shared
+                .tree
+                .into_inner()
+                .unwrap_or_else(PoisonError::into_inner),
+            Err(_) => unreachable!("the combiner has exited"),
+        }
+    }
+
+    /// closes the channel and waits for the combiner to drain it
+    fn stop(&mut self) {
+        self.sender = None;
+        if let Some(combiner) = self.combiner.take() {
+            if let Err(panic) = combiner.join() {
+                std::panic::resume_unwind(panic);
+            }
+        }
+    }
+
+    /// pushes the pending leaves continuing the tree and publishes the new size
+    fn apply(shared: &Shared<D, S>, pending: &mut BTreeMap<usize, Output<D>>) {
+        let mut tree = shared.tree.write().unwrap_or_else(PoisonError::into_inner);
+        while let Some(entry) = pending.first_entry() {
+            if *entry.key() != tree.len() {
+                break;
+            }
+            tree.push_leaf_hash(entry.remove());
+        }
+        let len = tree.len();
+        drop(tree);
+        *shared
+            .applied
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = len;
+        shared.progress.notify_all();
+    }
+
+    fn lock_applied(&self) -> MutexGuard<'_, usize> {
+        self.shared
+            .applied
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<D: Digest, S> Drop for ConcurrentAppender<D, S> {
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(combiner) = self.combiner.take() {
+            // a panic of the combiner is already reported by the thread
+            let _ = combiner.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha2::Sha256;
+
+    type LogTree = crate::LogTree<Sha256>;
+
+    #[test]
+    fn test_concurrent_append() {
+        let appender = ConcurrentAppender::new(LogTree::new());
+        let indices: Vec<Vec<usize>> = thread::scope(|scope| {
+            let producers: Vec<_> = (0..4u32)
+                .map(|producer| {
+                    let appender = &appender;
+                    scope.spawn(move || {
+                        (0..250u32)
+                            .map(|i| appender.append_data(&(producer * 1000 + i).to_le_bytes()))
+                            .collect()
+                    })
+                })
+                .collect();
+            producers
+                .into_iter()
+                .map(|producer| producer.join().unwrap())
+                .collect()
+        });
+        appender.flush();
+        assert_eq!(appender.len(), 1000);
+        assert_eq!(appender.reserved(), 1000);
+
+        // every leaf ended up at its reserved index
+        let mut expected = vec![Output::<Sha256>::default(); 1000];
+        for (producer, indices) in indices.iter().enumerate() {
+            for (i, &index) in indices.iter().enumerate() {
+                expected[index] = <Rfc6962 as HashScheme<Sha256>>::hash_leaf(
+                    &((producer * 1000 + i) as u32).to_le_bytes(),
+                );
+                assert_eq!(appender.tree().leaf_hash(index), Some(&expected[index]));
+            }
+        }
+        let mut sequential = LogTree::new();
+        for leaf in expected {
+            sequential.push_leaf_hash(leaf);
+        }
+        assert_eq!(appender.tree().root_hash(), sequential.root_hash());
+        assert_eq!(appender.finish().root_hash(), sequential.root_hash());
+    }
+
+    #[test]
+    fn test_continue_tree() {
+        let mut tree = LogTree::new();
+        tree.push_leaf_data(b"first");
+        let appender = ConcurrentAppender::new(tree);
+        assert_eq!(appender.len(), 1);
+        let index = appender.append_data(b"second");
+        assert_eq!(index, 1);
+        appender.wait_for(index);
+        assert_eq!(appender.tree().len(), 2);
+        appender.append_data(b"third");
+        // finishing applies all reserved leaves
+        let tree = appender.finish();
+        assert_eq!(tree.len(), 3);
+        assert_eq!(
+            tree.leaf_hash(2),
+            Some(&<Rfc6962 as HashScheme<Sha256>>::hash_leaf(b"third"))
+        );
+    }
+}
@@ -0,0 +1,570 @@
+//! Long-term archives of trees
+//!
+//! An `Archive` stores a tree with everything needed to check it decades later: the fingerprint
+//! of its digest and hash scheme (see `parameter_fingerprint`), the references of the anchors
+//! holding its root and a schedule of planned re-hashes. Digests weaken over a retention period
+//! of decades, so the schedule records when the archive has to be migrated to which digest.
+//!
+//! `rehash_with_data` performs such a migration like the hash-tree renewal of RFC 4998: every new
+//! leaf hashes the data object of the old leaf followed by the old leaf with the new digest, so the
+//! new tree doesn't depend on the weakened digest alone. Re-hashing the old leaf hashes by themselves
+//! would only carry the weakness over, hence the migration needs the original data objects. The new
+//! archive embeds the complete old archive, so the chain back to the original anchors stays
+//! verifiable with `predecessor` and `verify_predecessor`. Anchors are checked with
+//! `verify_anchors` and renewed with `reanchor`, e.g. after a migration or when an anchoring
+//! service is shut down.
+
+use std::fmt::{self, Debug};
+use std::io::{self, Read};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use digest::{Digest, Output};
+
+use crate::anchor::{Anchor, AnchorError, AnchorRef};
+use crate::custody::{
+    invalid_data, parameter_fingerprint, read_bytes, read_hash, read_hashes, read_u64, write_bytes,
+    write_hashes,
+};
+use crate::{HashScheme, MerkleTree, Plain};
+
+/// magic bytes at the start of a serialized archive
+const ARCHIVE_MAGIC: &[u8; 4] = b"MTAR";
+/// version of the serialization format
+const FORMAT_VERSION: u8 = 1;
+
+/// The reason an archive failed verification
+#[derive(Debug)]
+pub enum ArchiveError {
+    /// no anchor confirmed the root of the archive
+    NotAnchored,
+    /// the anchor holds another root than the archive
+    AnchorMismatch { anchor: AnchorRef },
+    /// an anchor couldn't be resolved
+    Anchor(AnchorError),
+    /// the archive doesn't embed the predecessor or its leaves are not the re-hashed leaves of it
+    PredecessorMismatch,
+    /// the data objects don't cover the leaves of the archive, `leaves` counts the leaves up to the
+    /// last one that isn't padding
+    LeafDataMismatch { leaves: usize, data: usize },
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::NotAnchored => write!(f, "no anchor confirmed the archived root"),
+            ArchiveError::AnchorMismatch { anchor } => {
+                write!(f, "anchor {anchor} holds another root")
+            }
+            ArchiveError::Anchor(err) => write!(f, "anchor error: {err}"),
+            ArchiveError::PredecessorMismatch => {
+                write!(f, "archive is not a re-hash of the predecessor")
+            }
+            ArchiveError::LeafDataMismatch { leaves, data } => {
+                write!(f, "got {data} data objects for {leaves} leaves")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArchiveError::Anchor(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<AnchorError> for ArchiveError {
+    fn from(err: AnchorError) -> Self {
+        ArchiveError::Anchor(err)
+    }
+}
+
+/// A planned migration of an archive to another digest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RehashPlan {
+    /// time the migration is due at in milliseconds since the Unix epoch
+    pub due: u64,
+    /// name of the digest to migrate to, e.g. `sha3-512`
+    pub digest: String,
+    /// why the migration is planned
+    pub reason: String,
+}
+
+impl RehashPlan {
+    /// creates a plan to migrate to the digest at the given time
+    pub fn new(due: SystemTime, digest: &str, reason: &str) -> Self {
+        Self {
+            due: millis(due),
+            digest: digest.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    /// returns the time the migration is due at
+    pub fn due_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.due)
+    }
+
+    /// returns true if the migration is due at the given time
+    pub fn is_due(&self, now: SystemTime) -> bool {
+        self.due <= millis(now)
+    }
+}
+
+/// A tree archived together with its parameters, anchors and re-hash schedule
+pub struct Archive<D: Digest, S = Plain> {
+    tree: MerkleTree<D, S>,
+    /// time the archive was created at in milliseconds since the Unix epoch
+    created_at: u64,
+    anchors: Vec<AnchorRef>,
+    schedule: Vec<RehashPlan>,
+    /// the serialized archive this one was re-hashed from
+    predecessor: Option<Vec<u8>>,
+}
+
+impl<D, S> Debug for Archive<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Archive")
+            .field("root", &hex::encode(self.tree.root_hash()))
+            .field("created_at", &self.created_at)
+            .field("anchors", &self.anchors)
+            .field("schedule", &self.schedule)
+            .field("predecessor", &self.predecessor.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<D, S> Archive<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates an archive of the tree without anchors and re-hash plans
+    pub fn new(tree: MerkleTree<D, S>, created_at: SystemTime) -> Self {
+        Self {
+            tree,
+            created_at: millis(created_at),
+            anchors: Vec::new(),
+            schedule: Vec::new(),
+            predecessor: None,
+        }
+    }
+
+    /// adds a planned re-hash to the schedule
+    pub fn with_rehash(mut self, plan: RehashPlan) -> Self {
+        self.schedule.push(plan);
+        self
+    }
+
+    /// returns the archived tree
+    pub fn tree(&self) -> &MerkleTree<D, S> {
+        &self.tree
+    }
+
+    /// returns the root of the archived tree
+    pub fn root(&self) -> &Output<D> {
+        self.tree.root_hash()
+    }
+
+    /// returns the time the archive was created at
+    pub fn created_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.created_at)
+    }
+
+    /// returns the references of the anchors holding the root in the order they were added
+    pub fn anchors(&self) -> &[AnchorRef] {
+        &self.anchors
+    }
+
+    /// adds the reference of an anchor holding the root
+    pub fn add_anchor(&mut self, anchor: AnchorRef) {
+        self.anchors.push(anchor);
+    }
+
+    /// returns the planned re-hashes
+    pub fn schedule(&self) -> &[RehashPlan] {
+        &self.schedule
+    }
+
+    /// returns the planned re-hashes that are due at the given time
+    pub fn due_rehashes(&self, now: SystemTime) -> impl Iterator<Item = &RehashPlan> + '_ {
+        self.schedule.iter().filter(move |plan| plan.is_due(now))
+    }
+
+    /// publishes the root with the anchor and adds the returned reference to the archive
+    pub fn reanchor(&mut self, anchor: &mut impl Anchor<D>) -> Result<&AnchorRef, AnchorError> {
+        let anchor = anchor.publish(self.tree.root_hash())?;
+        self.anchors.push(anchor);
+        Ok(&self.anchors[self.anchors.len() - 1])
+    }
+
+    /// resolves the references of the anchor's system, returns how many of them hold the root
+    /// References of other systems are skipped, check them with their own anchors. Returns an
+    /// error if a reference holds another root or can't be resolved, or if no reference holds the
+    /// root.
+    pub fn verify_anchors(&self, anchor: &impl Anchor<D>) -> Result<usize, ArchiveError> {
+        let mut confirmed = 0;
+        for anchor_ref in &self.anchors {
+            match anchor.resolve(anchor_ref) {
+                Ok(root) if root == *self.tree.root_hash() => confirmed += 1,
+                Ok(_) => {
+                    return Err(ArchiveError::AnchorMismatch {
+                        anchor: anchor_ref.clone(),
+                    })
+                }
+                Err(AnchorError::WrongSystem { .. }) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        if confirmed == 0 {
+            return Err(ArchiveError::NotAnchored);
+        }
+        Ok(confirmed)
+    }
+
+    /// migrates the archive to the digest `D2` and hash scheme `S2`
+    /// `data` holds the data objects the leaves were hashed from in order, every new leaf hashes the
+    /// data object followed by the old leaf as a leaf of the new scheme. Data may be omitted for
+    /// trailing padding, the padding is hashed as a leaf by itself. The new archive embeds this one
+    /// and carries over the planned re-hashes that are not due yet. It has no anchors, publish its
+    /// root with `reanchor`.
+    /// returns an error if the data objects don't cover all leaves up to the last one that isn't
+    /// padding or if there are more data objects than leaves
+    pub fn rehash_with_data<D2, S2>(
+        &self,
+        data: &[impl AsRef<[u8]>],
+        created_at: SystemTime,
+    ) -> Result<Archive<D2, S2>, ArchiveError>
+    where
+        D2: Digest + Default + Clone + Debug,
+        Output<D2>: Copy,
+        S2: HashScheme<D2>,
+    {
+        let padding = self.tree.padding();
+        let used = self
+            .tree
+            .leaves()
+            .rposition(|leaf| leaf != padding)
+            .map_or(0, |last| last + 1);
+        if data.len() < used || data.len() > self.tree.num_leaves() {
+            return Err(ArchiveError::LeafDataMismatch {
+                leaves: used,
+                data: data.len(),
+            });
+        }
+        let mut renewed = Vec::new();
+        let leaves: Vec<Output<D2>> = self
+            .tree
+            .leaves()
+            .enumerate()
+            .map(|(offset, leaf)| match data.get(offset) {
+                Some(data) => {
+                    renewed.clear();
+                    renewed.extend_from_slice(data.as_ref());
+                    renewed.extend_from_slice(leaf);
+                    S2::hash_leaf(&renewed)
+                }
+                None => S2::hash_leaf(leaf),
+            })
+            .collect();
+        let padding = S2::hash_leaf(padding);
+        Ok(Archive {
+            tree: MerkleTree::from_leaves(&leaves, &padding),
+            created_at: millis(created_at),
+            anchors: Vec::new(),
+            schedule: self
+                .schedule
+                .iter()
+                .filter(|plan| !plan.is_due(created_at))
+                .cloned()
+                .collect(),
+            predecessor: Some(self.to_bytes()),
+        })
+    }
+
+    /// returns true if the archive was created by `rehash_with_data`
+    pub fn has_predecessor(&self) -> bool {
+        self.predecessor.is_some()
+    }
+
+    /// decodes the archive this one was re-hashed from with its digest `D1` and hash scheme `S1`
+    /// returns None if the archive was not created by `rehash_with_data`
+    pub fn predecessor<D1, S1>(&self) -> Option<io::Result<Archive<D1, S1>>>
+    where
+        D1: Digest + Default + Clone + Debug,
+        Output<D1>: Copy,
+        S1: HashScheme<D1>,
+    {
+        self.predecessor
+            .as_deref()
+            .map(|bytes| Archive::from_bytes(bytes))
+    }
+
+    /// checks that the archive is the re-hash of the predecessor with the data objects of its leaves
+    pub fn verify_predecessor<D1, S1>(
+        &self,
+        predecessor: &Archive<D1, S1>,
+        data: &[impl AsRef<[u8]>],
+    ) -> Result<(), ArchiveError>
+    where
+        D1: Digest + Default + Clone + Debug,
+        Output<D1>: Copy,
+        S1: HashScheme<D1>,
+    {
+        let expected = predecessor.rehash_with_data::<D, S>(data, self.created_at())?;
+        if self.predecessor != expected.predecessor || *self.root() != *expected.root() {
+            return Err(ArchiveError::PredecessorMismatch);
+        }
+        Ok(())
+    }
+
+    /// serializes the archive
+    /// All leaf slots of the tree are stored, a deserialized tree holds all of them as used.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(ARCHIVE_MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.push(<D as Digest>::output_size() as u8);
+        bytes.extend_from_slice(&parameter_fingerprint::<D, S>());
+        bytes.extend_from_slice(&self.created_at.to_le_bytes());
+        bytes.extend_from_slice(self.tree.root_hash());
+        bytes.extend_from_slice(self.tree.padding());
+        write_hashes::<D>(&mut bytes, self.tree.leaves().as_slice());
+        bytes.extend_from_slice(&(self.anchors.len() as u64).to_le_bytes());
+        for anchor in &self.anchors {
+            write_bytes(&mut bytes, anchor.system.as_bytes());
+            write_bytes(&mut bytes, anchor.locator.as_bytes());
+        }
+        bytes.extend_from_slice(&(self.schedule.len() as u64).to_le_bytes());
+        for plan in &self.schedule {
+            bytes.extend_from_slice(&plan.due.to_le_bytes());
+            write_bytes(&mut bytes, plan.digest.as_bytes());
+            write_bytes(&mut bytes, plan.reason.as_bytes());
+        }
+        write_bytes(&mut bytes, self.predecessor.as_deref().unwrap_or_default());
+        bytes
+    }
+
+    /// deserializes an archive serialized with `to_bytes`
+    /// returns an error if the archive was created with another digest or hash scheme or if the
+    /// leaves don't hash to the archived root
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut reader = bytes;
+        let mut header = [0; 6];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != ARCHIVE_MAGIC {
+            return Err(invalid_data("invalid magic bytes"));
+        }
+        if header[4] != FORMAT_VERSION {
+            return Err(invalid_data("unsupported format version"));
+        }
+        if header[5] as usize != <D as Digest>::output_size() {
+            return Err(invalid_data("hash size doesn't match the digest"));
+        }
+        if read_hash::<D>(&mut reader)? != parameter_fingerprint::<D, S>() {
+            return Err(invalid_data(
+                "archive was created with different tree parameters",
+            ));
+        }
+        let created_at = read_u64(&mut reader)?;
+        let root = read_hash::<D>(&mut reader)?;
+        let padding = read_hash::<D>(&mut reader)?;
+        let leaves = read_hashes::<D>(&mut reader)?;
+        if !leaves.len().is_power_of_two() && !leaves.is_empty() {
+            return Err(invalid_data("the number of leaves is not a power of two"));
+        }
+        let tree = MerkleTree::from_leaves(&leaves, &padding);
+        if *tree.root_hash() != root {
+            return Err(invalid_data("leaves don't hash to the archived root"));
+        }
+        let mut anchors = Vec::new();
+        for _ in 0..read_u64(&mut reader)? {
+            anchors.push(AnchorRef::new(
+                read_string(&mut reader)?,
+                read_string(&mut reader)?,
+            ));
+        }
+        let mut schedule = Vec::new();
+        for _ in 0..read_u64(&mut reader)? {
+            schedule.push(RehashPlan {
+                due: read_u64(&mut reader)?,
+                digest: read_string(&mut reader)?,
+                reason: read_string(&mut reader)?,
+            });
+        }
+        let predecessor = read_bytes(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(invalid_data("trailing bytes after archive"));
+        }
+        Ok(Self {
+            tree,
+            created_at,
+            anchors,
+            schedule,
+            predecessor: (!predecessor.is_empty()).then_some(predecessor),
+        })
+    }
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+fn read_string(reader: &mut &[u8]) -> io::Result<String> {
+    String::from_utf8(read_bytes(reader)?).map_err(|_| invalid_data("invalid UTF-8 string"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha2::Sha256;
+    use sha3::Sha3_256;
+
+    use crate::anchor::tests::MemoryAnchor;
+    use crate::Rfc6962;
+
+    type Archive = super::Archive<Sha256, Rfc6962>;
+
+    fn year(year: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs((year - 1970) * 365 * 24 * 3600)
+    }
+
+    /// the data objects of the sample leaves
+    fn data() -> Vec<[u8; 1]> {
+        (0..5u8).map(|i| [i]).collect()
+    }
+
+    fn sample() -> Archive {
+        let leaves: Vec<_> = data()
+            .iter()
+            .map(MerkleTree::<Sha256, Rfc6962>::hash_leaf)
+            .collect();
+        let tree = MerkleTree::from_leaves(&leaves, &[0; 32].into());
+        Archive::new(tree, year(2026))
+            .with_rehash(RehashPlan::new(year(2040), "sha3-256", "sha-256 review"))
+            .with_rehash(RehashPlan::new(year(2050), "sha3-512", "retention policy"))
+    }
+
+    #[test]
+    fn test_archive_roundtrip() {
+        let mut archive = sample();
+        let mut anchor = MemoryAnchor::default();
+        archive.reanchor(&mut anchor).unwrap();
+        let decoded = Archive::from_bytes(&archive.to_bytes()).unwrap();
+        assert_eq!(decoded.root(), archive.root());
+        assert_eq!(decoded.created_at(), year(2026));
+        assert_eq!(decoded.anchors(), archive.anchors());
+        assert_eq!(decoded.schedule(), archive.schedule());
+        assert!(!decoded.has_predecessor());
+        assert_eq!(decoded.to_bytes(), archive.to_bytes());
+
+        let empty = Archive::new(MerkleTree::empty(&[0; 32].into()), year(2026));
+        assert_eq!(
+            Archive::from_bytes(&empty.to_bytes()).unwrap().root(),
+            empty.root()
+        );
+
+        // other parameters, truncated and tampered archives are rejected
+        let bytes = archive.to_bytes();
+        assert!(super::Archive::<Sha256>::from_bytes(&bytes).is_err());
+        assert!(Archive::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut tampered = bytes.clone();
+        tampered[6 + 32 + 8 + 32 + 32 + 8] ^= 1;
+        assert!(Archive::from_bytes(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_archive_anchors() {
+        let mut archive = sample();
+        let mut anchor = MemoryAnchor::default();
+        assert!(matches!(
+            archive.verify_anchors(&anchor),
+            Err(ArchiveError::NotAnchored)
+        ));
+        archive.reanchor(&mut anchor).unwrap();
+        archive.add_anchor(AnchorRef::new("https", "r-1"));
+        assert_eq!(archive.verify_anchors(&anchor).unwrap(), 1);
+        archive.reanchor(&mut anchor).unwrap();
+        assert_eq!(archive.verify_anchors(&anchor).unwrap(), 2);
+
+        let compromised = MemoryAnchor {
+            forged: Some(Sha256::digest("forged")),
+            ..MemoryAnchor::default()
+        };
+        assert!(matches!(
+            archive.verify_anchors(&compromised),
+            Err(ArchiveError::AnchorMismatch { .. })
+        ));
+        assert!(matches!(
+            archive.verify_anchors(&MemoryAnchor::default()),
+            Err(ArchiveError::Anchor(AnchorError::NotFound { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_archive_rehash() {
+        let mut archive = sample();
+        archive.reanchor(&mut MemoryAnchor::default()).unwrap();
+        assert_eq!(archive.due_rehashes(year(2030)).count(), 0);
+        let due: Vec<_> = archive.due_rehashes(year(2041)).collect();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].digest, "sha3-256");
+
+        let migrated = archive
+            .rehash_with_data::<Sha3_256, Rfc6962>(&data(), year(2041))
+            .unwrap();
+        assert_eq!(migrated.tree().depth(), archive.tree().depth());
+        // the new leaves cover the data objects, not only the old leaf hashes
+        let leaf = archive.tree().leaves().next().unwrap();
+        assert_eq!(
+            migrated.tree().leaves().next().unwrap(),
+            &<Rfc6962 as HashScheme<Sha3_256>>::hash_leaf(&[&[0][..], leaf].concat())
+        );
+        assert_ne!(
+            migrated.tree().leaves().next().unwrap(),
+            &<Rfc6962 as HashScheme<Sha3_256>>::hash_leaf(leaf)
+        );
+        assert!(migrated.anchors().is_empty());
+        assert_eq!(migrated.schedule().len(), 1);
+        assert_eq!(migrated.schedule()[0].digest, "sha3-512");
+
+        let migrated =
+            super::Archive::<Sha3_256, Rfc6962>::from_bytes(&migrated.to_bytes()).unwrap();
+        let predecessor = migrated.predecessor::<Sha256, Rfc6962>().unwrap().unwrap();
+        assert_eq!(predecessor.root(), archive.root());
+        assert_eq!(predecessor.anchors(), archive.anchors());
+        migrated.verify_predecessor(&predecessor, &data()).unwrap();
+
+        let other = sample().with_rehash(RehashPlan::new(year(2060), "sha3-512", ""));
+        assert!(matches!(
+            migrated.verify_predecessor(&other, &data()),
+            Err(ArchiveError::PredecessorMismatch)
+        ));
+        let mut forged = data();
+        forged[2] = [9];
+        assert!(matches!(
+            migrated.verify_predecessor(&predecessor, &forged),
+            Err(ArchiveError::PredecessorMismatch)
+        ));
+        // data for every used leaf is required, at most one per leaf slot
+        assert!(matches!(
+            archive.rehash_with_data::<Sha3_256, Rfc6962>(&data()[..4], year(2041)),
+            Err(ArchiveError::LeafDataMismatch { leaves: 5, data: 4 })
+        ));
+        assert!(matches!(
+            archive.rehash_with_data::<Sha3_256, Rfc6962>(&[[0]; 9], year(2041)),
+            Err(ArchiveError::LeafDataMismatch { leaves: 5, data: 9 })
+        ));
+        assert!(archive.predecessor::<Sha256, Rfc6962>().is_none());
+    }
+}
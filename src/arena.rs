@@ -0,0 +1,184 @@
+//! Buffer reuse for repeated tree builds
+//!
+//! Building a tree allocates all of its nodes at once. Services building many short lived trees
+//! can keep a `TreeArena` around instead: trees built from it borrow the arena's buffer and hand it
+//! back when they are dropped, so after warm-up no build allocates anymore.
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::ops::{Deref, DerefMut};
+
+use digest::{Digest, Output};
+
+use crate::MerkleTree;
+
+/// A reusable node buffer for building trees
+pub struct TreeArena<D: Digest> {
+    /// buffer handed to the next tree
+    buffer: Vec<Output<D>>,
+}
+
+/// A tree built in a `TreeArena`, its nodes return to the arena when it is dropped
+pub struct ArenaTree<'a, D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    arena: &'a mut TreeArena<D>,
+    /// the built tree, only taken out by `into_tree`
+    tree: Option<MerkleTree<D>>,
+}
+
+impl<D> Default for TreeArena<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> TreeArena<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// creates an arena with an empty buffer, it grows to the size of the largest tree built
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    /// creates an arena with a buffer for the given number of nodes
+    pub fn with_capacity(nodes: usize) -> Self {
+        Self {
+            buffer: Vec::with_capacity(nodes),
+        }
+    }
+
+    /// returns the number of nodes the buffer holds without reallocating
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// builds a tree from the given leaves like `MerkleTree::from_leaves`
+    pub fn from_leaves(&mut self, leaves: &[Output<D>], padding: &Output<D>) -> ArenaTree<'_, D> {
+        let buffer = core::mem::take(&mut self.buffer);
+        ArenaTree {
+            tree: Some(MerkleTree::from_leaves_in(buffer, leaves, padding)),
+            arena: self,
+        }
+    }
+
+    /// builds a tree from raw leaf payloads like `MerkleTree::from_leaf_data`
+    pub fn from_leaf_data<T: AsRef<[u8]>>(
+        &mut self,
+        data: &[T],
+        padding: &Output<D>,
+    ) -> ArenaTree<'_, D> {
+        let leaves: Vec<Output<D>> = data.iter().map(|item| D::digest(item)).collect();
+        self.from_leaves(&leaves, padding)
+    }
+}
+
+impl<D> ArenaTree<'_, D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// detaches the tree from the arena, its nodes won't be returned to the arena
+    pub fn into_tree(mut self) -> MerkleTree<D> {
+        self.tree.take().unwrap()
+    }
+}
+
+impl<D> Deref for ArenaTree<'_, D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    type Target = MerkleTree<D>;
+
+    fn deref(&self) -> &MerkleTree<D> {
+        self.tree.as_ref().unwrap()
+    }
+}
+
+impl<D> DerefMut for ArenaTree<'_, D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    fn deref_mut(&mut self) -> &mut MerkleTree<D> {
+        self.tree.as_mut().unwrap()
+    }
+}
+
+impl<D> Drop for ArenaTree<'_, D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    fn drop(&mut self) {
+        if let Some(tree) = &mut self.tree {
+            self.arena.buffer = tree.take_nodes();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    type TreeArena = super::TreeArena<Sha3_256>;
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+
+    fn leaves(count: usize) -> Vec<Output<Sha3_256>> {
+        (0..count).map(|i| [i as u8; 32].into()).collect()
+    }
+
+    #[test]
+    fn test_buffer_is_reused() {
+        let padding = [0xab; 32].into();
+        let mut arena = TreeArena::new();
+
+        let first = {
+            let tree = arena.from_leaves(&leaves(16), &padding);
+            assert_eq!(
+                tree.root_hash(),
+                MerkleTree::from_leaves(&leaves(16), &padding).root_hash()
+            );
+            tree.root_hash() as *const _
+        };
+        assert!(arena.capacity() >= 31);
+
+        // smaller and equally large trees are built in the same buffer
+        for count in [5, 16, 1] {
+            let mut tree = arena.from_leaves(&leaves(count), &padding);
+            assert_eq!(tree.root_hash() as *const _, first);
+            assert_eq!(
+                tree.root_hash(),
+                MerkleTree::from_leaves(&leaves(count), &padding).root_hash()
+            );
+            tree.set(0, &padding);
+            let proof = tree.create_proof(0);
+            assert_eq!(tree.verify_proof(&padding, &proof), *tree.root_hash());
+        }
+    }
+
+    #[test]
+    fn test_into_tree() {
+        let padding = [0x00; 32].into();
+        let mut arena = TreeArena::with_capacity(64);
+        let data = [b"a", b"b", b"c"];
+        let tree = arena.from_leaf_data(&data, &padding).into_tree();
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::from_leaf_data(&data, &padding).root_hash()
+        );
+        // the detached tree keeps the buffer
+        assert_eq!(arena.capacity(), 0);
+    }
+}
@@ -0,0 +1,165 @@
+//! Verification of many proofs against one root
+//!
+//! Proofs of leaves of the same tree share the nodes near the root. `verify_proofs_batch`
+//! remembers every node authenticated by a proof that verified, together with the siblings on its
+//! path, and stops hashing a later proof as soon as it reaches one of them: from there on the
+//! proof has to carry the remembered siblings, which is checked by comparing hashes. The results
+//! are the same as those of `verify` for every item, only fewer hashes are computed, about one per
+//! distinct node on the paths of the valid proofs.
+//!
+//! Proofs with more siblings than a tree of `MAX_DEPTH` has are rejected without hashing.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, Proof, MAX_DEPTH};
+
+/// minimum number of proofs verified by a single rayon task, every task remembers its own nodes
+#[cfg(feature = "rayon")]
+const PAR_MIN_PROOFS: usize = 256;
+
+/// returns for every leaf and proof whether they hash up to the root, like `verify` on each item
+pub fn verify_proofs_batch<D, S>(root: &Output<D>, items: &[(Output<D>, Proof<D, S>)]) -> Vec<bool>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    let mut verifier = BatchVerifier::new(root);
+    items
+        .iter()
+        .map(|(leaf, proof)| verifier.verify(leaf, proof))
+        .collect()
+}
+
+/// returns the results of `verify_proofs_batch` using all rayon threads
+#[cfg(feature = "rayon")]
+pub fn par_verify_proofs_batch<D, S>(
+    root: &Output<D>,
+    items: &[(Output<D>, Proof<D, S>)],
+) -> Vec<bool>
+where
+    D: Digest + Default + Clone + Debug + Send + Sync,
+    Output<D>: Copy + Send + Sync,
+    S: HashScheme<D>,
+{
+    use rayon::prelude::*;
+
+    let chunk = items
+        .len()
+        .div_ceil(rayon::current_num_threads())
+        .max(PAR_MIN_PROOFS);
+    items
+        .par_chunks(chunk)
+        .flat_map_iter(|items| verify_proofs_batch(root, items))
+        .collect()
+}
+
+/// The nodes authenticated by the proofs verified so far
+struct BatchVerifier<'a, D: Digest> {
+    root: &'a Output<D>,
+    /// the nodes by the number of siblings of their proofs, their height above the leaves and
+    /// their offset within the layer
+    nodes: BTreeMap<(usize, usize, usize), Output<D>>,
+}
+
+impl<'a, D> BatchVerifier<'a, D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    fn new(root: &'a Output<D>) -> Self {
+        Self {
+            root,
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    fn verify<S: HashScheme<D>>(&mut self, leaf: &Output<D>, proof: &Proof<D, S>) -> bool {
+        let len = proof.len();
+        if len >= MAX_DEPTH {
+            return false;
+        }
+        // bits of the leaf index above the proof length don't affect verification
+        let index = proof.leaf_index() & ((1 << len) - 1);
+        let siblings = proof.siblings();
+        let mut path = Vec::with_capacity(len);
+        let mut node = *leaf;
+        for (height, (sibling, is_left)) in proof.iter().enumerate() {
+            if self.nodes.get(&(len, height, index >> height)) == Some(&node) {
+                // the rest of the path is known, the proof has to carry its siblings
+                let valid = (height..len).all(|height| {
+                    self.nodes.get(&(len, height, (index >> height) ^ 1)) == Some(&siblings[height])
+                });
+                if valid {
+                    self.remember(len, index, &path, siblings);
+                }
+                return valid;
+            }
+            path.push(node);
+            node = if is_left {
+                S::hash_node(&node, sibling)
+            } else {
+                S::hash_node(sibling, &node)
+            };
+        }
+        let valid = node == *self.root;
+        if valid {
+            self.remember(len, index, &path, siblings);
+        }
+        valid
+    }
+
+    /// stores the nodes of the lowest layers of a verified path and their siblings
+    fn remember(&mut self, len: usize, index: usize, path: &[Output<D>], siblings: &[Output<D>]) {
+        for (height, (node, sibling)) in path.iter().zip(siblings).enumerate() {
+            self.nodes.insert((len, height, index >> height), *node);
+            self.nodes
+                .insert((len, height, (index >> height) ^ 1), *sibling);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    use crate::verify;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type Proof = crate::Proof<Sha3_256>;
+
+    #[test]
+    fn test_verify_proofs_batch() {
+        let leaves: Vec<Output<Sha3_256>> = (0..100).map(|i| [i as u8; 32].into()).collect();
+        let tree = MerkleTree::from_leaves(&leaves, &[0; 32].into());
+        let root = *tree.root_hash();
+        let mut items: Vec<(Output<Sha3_256>, Proof)> = (0..128)
+            .map(|offset| (*tree.get(offset).unwrap(), tree.create_proof(offset)))
+            .collect();
+        // a wrong leaf, a tampered sibling near the root, a truncated proof and a proof of a leaf
+        // that was verified before
+        items[3].0 = [0xff; 32].into();
+        let mut siblings = items[70].1.siblings().to_vec();
+        siblings[5] = [0xee; 32].into();
+        items[70].1 = Proof::new(70, siblings);
+        items[90].1 = Proof::new(90, items[90].1.siblings()[..6].to_vec());
+        items.push(items[10].clone());
+        items.push((leaves[0], Proof::new(0, vec![[0; 32].into(); MAX_DEPTH])));
+
+        let expected: Vec<bool> = items
+            .iter()
+            .map(|(leaf, proof)| verify(&root, leaf, proof))
+            .collect();
+        assert_eq!(expected.iter().filter(|valid| !**valid).count(), 4);
+        assert_eq!(verify_proofs_batch(&root, &items), expected);
+        #[cfg(feature = "rayon")]
+        assert_eq!(par_verify_proofs_batch(&root, &items), expected);
+        assert!(verify_proofs_batch::<Sha3_256, crate::Plain>(&root, &[]).is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+//! Machine-readable benchmark results
+//!
+//! `run_benchmarks` times the operations of the criterion benchmarks with a simple harness and
+//! returns one `BenchResult` per operation, `BenchReport::to_json` turns them into JSON for
+//! dashboards tracking the performance across releases. The criterion benchmarks write the report
+//! to the file named by the `MERKLE_BENCH_JSON` environment variable when built with the `bench`
+//! feature.
+//!
+//! ```text
+//! MERKLE_BENCH_JSON=bench.json cargo bench --features bench
+//! ```
+
+use std::collections::BTreeMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use sha3::{Digest, Sha3_256};
+
+use crate::{DeferredMerkleTree, MerkleTree};
+
+/// The timing of a single operation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BenchResult {
+    /// name of the operation, e.g. `from_leaves`
+    pub operation: String,
+    /// parameters of the run, e.g. the depth of the tree
+    pub params: BTreeMap<String, u64>,
+    /// number of times the operation was run
+    pub iterations: u64,
+    /// mean duration of the operation in nanoseconds
+    pub ns_per_op: f64,
+    /// processed elements per second if the operation processes more than one, e.g. leaves
+    pub throughput: Option<f64>,
+}
+
+/// The results of a benchmark run together with the version of the crate
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BenchReport {
+    /// version of the crate that was benchmarked
+    pub version: &'static str,
+    /// the timings of all operations
+    pub results: Vec<BenchResult>,
+}
+
+impl BenchReport {
+    /// creates a report of the results for the version of this crate
+    pub fn new(results: Vec<BenchResult>) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            results,
+        }
+    }
+
+    /// returns the report as a JSON object
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("reports serialize to JSON")
+    }
+}
+
+/// runs the operation repeatedly for at least the target time and returns its mean duration
+/// `elements` is the number of elements processed per run used for the throughput, e.g. the
+/// number of leaves hashed
+pub fn measure<R>(
+    operation: &str,
+    params: &[(&str, u64)],
+    elements: u64,
+    target: Duration,
+    mut run: impl FnMut() -> R,
+) -> BenchResult {
+    // warm up caches and allocations before measuring
+    black_box(run());
+    let mut iterations = 0u64;
+    let mut batch = 1u64;
+    let mut elapsed = Duration::ZERO;
+    // runs batches of growing size, so the clock is read rarely for fast operations
+    loop {
+        let start = Instant::now();
+        for _ in 0..batch {
+            black_box(run());
+        }
+        elapsed += start.elapsed();
+        iterations += batch;
+        if elapsed >= target {
+            break;
+        }
+        batch = batch.saturating_mul(2);
+    }
+    let ns_per_op = elapsed.as_nanos() as f64 / iterations as f64;
+    BenchResult {
+        operation: operation.to_owned(),
+        params: params
+            .iter()
+            .map(|(name, value)| ((*name).to_owned(), *value))
+            .collect(),
+        iterations,
+        ns_per_op,
+        throughput: (elements > 1).then(|| elements as f64 * 1e9 / ns_per_op),
+    }
+}
+
+/// runs the operations of the criterion benchmarks, each for at least the target time
+pub fn run_benchmarks(target: Duration) -> Vec<BenchResult> {
+    let padding = [0x00; 32].into();
+    let mut results = Vec::new();
+    for depth in [5, 10, 20] {
+        results.push(measure(
+            "initialization",
+            &[("depth", depth)],
+            1 << (depth - 1),
+            target,
+            || MerkleTree::<Sha3_256>::new(depth as usize, &padding),
+        ));
+    }
+
+    let leaves: Vec<_> = (0..1u32 << 16)
+        .map(|i| Sha3_256::digest(i.to_le_bytes()))
+        .collect();
+    results.push(measure(
+        "from_leaves",
+        &[("leaves", leaves.len() as u64)],
+        leaves.len() as u64,
+        target,
+        || MerkleTree::<Sha3_256>::from_leaves(&leaves, &padding),
+    ));
+    #[cfg(feature = "rayon")]
+    results.push(measure(
+        "par_from_leaves",
+        &[("leaves", leaves.len() as u64)],
+        leaves.len() as u64,
+        target,
+        || MerkleTree::<Sha3_256>::par_from_leaves(&leaves, &padding),
+    ));
+
+    let mut tree = MerkleTree::<Sha3_256>::new(20, &padding);
+    for i in 0..tree.num_leaves() {
+        tree.set(i, &[(i * 0x11) as u8; 32].into());
+    }
+    let leaf = [5 * 0x11_u8; 32].into();
+    let proof = tree.create_proof(5);
+    results.push(measure("create_proof", &[("depth", 20)], 1, target, || {
+        tree.create_proof(5)
+    }));
+    results.push(measure("verify_proof", &[("depth", 20)], 1, target, || {
+        tree.verify_proof(&leaf, &proof)
+    }));
+    let updated_value = [0x11; 32].into();
+    results.push(measure("set", &[("depth", 20)], 1, target, || {
+        tree.set(5, &updated_value)
+    }));
+
+    let updates: Vec<(usize, _)> = (0..100_000)
+        .map(|i| (i * 5, Sha3_256::digest((i as u32).to_le_bytes())))
+        .collect();
+    let params = [("depth", 20), ("updates", updates.len() as u64)];
+    results.push(measure(
+        "eager_batch_update",
+        &params,
+        updates.len() as u64,
+        target,
+        || {
+            for (offset, value) in &updates {
+                tree.set(*offset, value);
+            }
+            *tree.root_hash()
+        },
+    ));
+    let mut deferred = DeferredMerkleTree::new(tree);
+    results.push(measure(
+        "deferred_batch_update",
+        &params,
+        updates.len() as u64,
+        target,
+        || {
+            for (offset, value) in &updates {
+                deferred.set(*offset, value);
+            }
+            *deferred.root_hash()
+        },
+    ));
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_json() {
+        let padding = [0x00; 32].into();
+        let result = measure(
+            "initialization",
+            &[("depth", 3)],
+            4,
+            Duration::from_millis(1),
+            || MerkleTree::<Sha3_256>::new(3, &padding),
+        );
+        assert_eq!(result.operation, "initialization");
+        assert_eq!(result.params["depth"], 3);
+        assert!(result.iterations > 0 && result.ns_per_op > 0.0);
+        assert_eq!(result.throughput, Some(4e9 / result.ns_per_op));
+        let single = measure("noop", &[], 1, Duration::ZERO, || ());
+        assert_eq!(single.iterations, 1);
+        assert_eq!(single.throughput, None);
+
+        let report = BenchReport::new(vec![result.clone()]);
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["results"][0]["operation"], "initialization");
+        assert_eq!(json["results"][0]["params"]["depth"], 3);
+        assert_eq!(json["results"][0]["iterations"], result.iterations);
+        assert!(json["results"][0]["ns_per_op"].is_f64());
+        assert!(json["results"][0]["throughput"].is_f64());
+    }
+}
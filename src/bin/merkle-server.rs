@@ -0,0 +1,353 @@
+//! HTTP service wrapping a tree of SHA3-256 leaves
+//!
+//! ```text
+//! merkle-server [--addr 127.0.0.1:8080] [--depth 20] [--threads 4]
+//! ```
+//!
+//! - `POST /leaves/{i}` with the body `{"leaf": "<hex>"}` of at most 1024 bytes sets the leaf and
+//!   answers with the new root
+//! - `GET /root` answers with the root and the number of leaves
+//! - `GET /proof/{i}` answers with the leaf, its proof in the JSON format of the `merkle` tool and
+//!   the root the proof leads to
+//!
+//! The tree is a `ConcurrentMerkleTree` with one subtree per worker thread. Requests for leaves in
+//! different subtrees are served in parallel, reads only wait for writes to the same subtree. All
+//! responses are JSON, errors are `{"error": "<message>"}` with a 4xx status.
+
+use std::fmt;
+use std::io::Read;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::thread;
+
+use merkle_tree_rs::{hash_from_hex, ConcurrentMerkleTree, MerkleTree, MerkleTreeError, Proof};
+use serde::{Deserialize, Serialize};
+use sha3::Sha3_256;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const USAGE: &str = "usage:
+  merkle-server [--addr <host:port>] [--depth <n>] [--threads <n>]
+
+options:
+  --addr <host:port>  address to listen on (default 127.0.0.1:8080)
+  --depth <n>         depth of the tree, it has 2^(n - 1) leaves (default 20)
+  --threads <n>       number of worker threads and subtrees (default 4)";
+
+/// the largest accepted request body, a leaf in hex with some room for whitespace
+const MAX_BODY: u64 = 1024;
+
+type Tree = ConcurrentMerkleTree<Sha3_256>;
+
+/// the command line options
+struct Args {
+    addr: String,
+    depth: usize,
+    threads: usize,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args {
+            addr: "127.0.0.1:8080".into(),
+            depth: 20,
+            threads: 4,
+        };
+        while let Some(option) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {option}"))?;
+            let invalid = || format!("invalid value for {option}: {value}");
+            match option.as_str() {
+                "--addr" => parsed.addr = value,
+                "--depth" => parsed.depth = value.parse().map_err(|_| invalid())?,
+                "--threads" => {
+                    parsed.threads = value
+                        .parse()
+                        .ok()
+                        .filter(|&threads| threads > 0)
+                        .ok_or_else(invalid)?
+                }
+                _ => return Err(format!("unknown option {option}")),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// The reason a request failed
+enum ApiError {
+    NotFound,
+    MethodNotAllowed,
+    BadRequest(String),
+    PayloadTooLarge,
+    Tree(MerkleTreeError),
+}
+
+impl ApiError {
+    fn status(&self) -> u16 {
+        match self {
+            Self::NotFound | Self::Tree(MerkleTreeError::LeafIndexOutOfBounds { .. }) => 404,
+            Self::MethodNotAllowed => 405,
+            Self::PayloadTooLarge => 413,
+            Self::BadRequest(_) | Self::Tree(_) => 400,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "not found"),
+            Self::MethodNotAllowed => write!(f, "method not allowed"),
+            Self::BadRequest(message) => write!(f, "{message}"),
+            Self::PayloadTooLarge => write!(f, "request body is larger than {MAX_BODY} bytes"),
+            Self::Tree(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl From<MerkleTreeError> for ApiError {
+    fn from(err: MerkleTreeError) -> Self {
+        Self::Tree(err)
+    }
+}
+
+/// the body of `POST /leaves/{i}`
+#[derive(Deserialize)]
+struct SetLeaf {
+    leaf: String,
+}
+
+/// The responses of the routes
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Reply {
+    Root {
+        root: String,
+        num_leaves: usize,
+    },
+    Proof {
+        leaf: String,
+        proof: Proof<Sha3_256>,
+        root: String,
+    },
+    Error {
+        error: String,
+    },
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    let tree = match MerkleTree::try_new(args.depth, &[0; 32].into()) {
+        Ok(tree) => Arc::new(Tree::new(tree, args.threads)),
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::from(2);
+        }
+    };
+    let server = match Server::http(&args.addr) {
+        Ok(server) => Arc::new(server),
+        Err(err) => {
+            eprintln!("failed to listen on {}: {err}", args.addr);
+            return ExitCode::FAILURE;
+        }
+    };
+    eprintln!("listening on {}", args.addr);
+
+    let workers: Vec<_> = (0..args.threads)
+        .map(|_| {
+            let (server, tree) = (Arc::clone(&server), Arc::clone(&tree));
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    respond(&tree, request);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+    ExitCode::SUCCESS
+}
+
+/// answers a request, failures to send the response only affect the client
+fn respond(tree: &Tree, mut request: Request) {
+    let result = read_body(&mut request)
+        .and_then(|body| route(tree, request.method(), request.url(), &body));
+    let (status, reply) = match result {
+        Ok(reply) => (200, reply),
+        Err(err) => (
+            err.status(),
+            Reply::Error {
+                error: err.to_string(),
+            },
+        ),
+    };
+    let body = serde_json::to_string(&reply).expect("replies serialize to JSON");
+    let header = Header::from_bytes("Content-Type", "application/json").expect("valid header");
+    let response = Response::from_string(body)
+        .with_status_code(status)
+        .with_header(header);
+    let _ = request.respond(response);
+}
+
+/// reads the body of a request, one byte more than `MAX_BODY` at most so `route` can reject it
+fn read_body(request: &mut Request) -> Result<Vec<u8>, ApiError> {
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_end(&mut body)
+        .map_err(|err| ApiError::BadRequest(err.to_string()))?;
+    Ok(body)
+}
+
+/// routes a request with its URL and body to the tree
+fn route(tree: &Tree, method: &Method, url: &str, body: &[u8]) -> Result<Reply, ApiError> {
+    if body.len() as u64 > MAX_BODY {
+        return Err(ApiError::PayloadTooLarge);
+    }
+    let path: Vec<&str> = url
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches('/')
+        .split('/')
+        .collect();
+    match (method, path.as_slice()) {
+        (Method::Get, ["root"]) => Ok(root(tree)),
+        (Method::Get, ["proof", offset]) => {
+            let offset = parse_offset(offset)?;
+            let (leaf, proof) = tree.try_get_with_proof(offset)?;
+            let root = proof.compute_root(&leaf);
+            Ok(Reply::Proof {
+                leaf: hex::encode(leaf),
+                proof,
+                root: hex::encode(root),
+            })
+        }
+        (Method::Post, ["leaves", offset]) => {
+            let offset = parse_offset(offset)?;
+            let body: SetLeaf = serde_json::from_slice(body)
+                .map_err(|err| ApiError::BadRequest(format!("invalid body: {err}")))?;
+            tree.try_set(offset, &hash_from_hex::<Sha3_256>(&body.leaf)?)?;
+            Ok(root(tree))
+        }
+        (_, ["root"] | ["proof", _] | ["leaves", _]) => Err(ApiError::MethodNotAllowed),
+        _ => Err(ApiError::NotFound),
+    }
+}
+
+fn root(tree: &Tree) -> Reply {
+    Reply::Root {
+        root: hex::encode(tree.root_hash()),
+        num_leaves: tree.num_leaves(),
+    }
+}
+
+fn parse_offset(offset: &str) -> Result<usize, ApiError> {
+    offset
+        .parse()
+        .map_err(|_| ApiError::BadRequest(format!("invalid leaf index {offset}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use digest::Output;
+    use merkle_tree_rs::verify;
+    use serde_json::{json, Value};
+
+    fn tree() -> Tree {
+        Tree::new(MerkleTree::new(4, &[0; 32].into()), 2)
+    }
+
+    /// returns the root of an untouched tree
+    fn tree_root() -> Output<Sha3_256> {
+        *MerkleTree::<Sha3_256>::new(4, &[0; 32].into()).root_hash()
+    }
+
+    /// routes a request and returns the status and body of the response
+    fn request(tree: &Tree, method: Method, url: &str, body: &str) -> (u16, Value) {
+        match route(tree, &method, url, body.as_bytes()) {
+            Ok(reply) => (200, serde_json::to_value(reply).unwrap()),
+            Err(err) => (err.status(), json!({ "error": err.to_string() })),
+        }
+    }
+
+    #[test]
+    fn test_routes() {
+        let tree = tree();
+        let (status, reply) = request(&tree, Method::Get, "/root", "");
+        assert_eq!(status, 200);
+        assert_eq!(reply["num_leaves"], 8);
+        assert_eq!(reply["root"], hex::encode(tree.root_hash()));
+
+        let leaf = hex::encode([0x11; 32]);
+        let body = format!(r#"{{"leaf": "{leaf}"}}"#);
+        let (status, reply) = request(&tree, Method::Post, "/leaves/3", &body);
+        assert_eq!(status, 200);
+        assert_eq!(reply["root"], hex::encode(tree.root_hash()));
+
+        // the query string is ignored
+        let (status, reply) = request(&tree, Method::Get, "/proof/3?pretty", "");
+        assert_eq!(status, 200);
+        assert_eq!(reply["leaf"], leaf);
+        assert_eq!(reply["root"], hex::encode(tree.root_hash()));
+        let proof: Proof<Sha3_256> = serde_json::from_value(reply["proof"].clone()).unwrap();
+        assert!(verify(&tree.root_hash(), &[0x11; 32].into(), &proof));
+    }
+
+    #[test]
+    fn test_route_errors() {
+        let tree = tree();
+        let leaf = format!(r#"{{"leaf": "{}"}}"#, hex::encode([0x11; 32]));
+        for (method, url, body, expected) in [
+            (Method::Get, "/proof/8", "", 404),
+            (Method::Post, "/leaves/8", leaf.as_str(), 404),
+            (Method::Get, "/unknown", "", 404),
+            (Method::Get, "/proof/3/4", "", 404),
+            (Method::Delete, "/root", "", 405),
+            (Method::Get, "/leaves/3", "", 405),
+            (Method::Post, "/proof/3", "", 405),
+            (Method::Get, "/proof/three", "", 400),
+            (Method::Post, "/leaves/3", "not json", 400),
+            (Method::Post, "/leaves/3", r#"{"leaf": "zz"}"#, 400),
+            (Method::Post, "/leaves/3", r#"{"value": "00"}"#, 400),
+        ] {
+            let (status, reply) = request(&tree, method.clone(), url, body);
+            assert_eq!(status, expected, "{method} {url}: {reply}");
+            assert!(reply["error"].is_string());
+        }
+        // failed requests leave the tree untouched
+        assert_eq!(tree.root_hash(), tree_root());
+    }
+
+    #[test]
+    fn test_max_body() {
+        let tree = tree();
+        let leaf = hex::encode([0x11; 32]);
+        // whitespace fills the body up to the limit
+        let padded = |len: usize| {
+            let body = format!(r#"{{"leaf": "{leaf}"}}"#);
+            format!("{body}{}", " ".repeat(len - body.len()))
+        };
+        let (status, _) = request(&tree, Method::Post, "/leaves/1", &padded(MAX_BODY as usize));
+        assert_eq!(status, 200);
+        let (status, reply) = request(
+            &tree,
+            Method::Post,
+            "/leaves/1",
+            &padded(MAX_BODY as usize + 1),
+        );
+        assert_eq!(status, 413);
+        assert_eq!(reply["error"], "request body is larger than 1024 bytes");
+    }
+}
@@ -0,0 +1,737 @@
+//! Command line tool for building trees from files, creating proofs and verifying them
+//!
+//! ```text
+//! merkle build --input leaves.txt [--hash sha3-256] [--raw]
+//! merkle prove --input leaves.txt --index 5 [--output proof.json]
+//! merkle verify --root <hex> --proof proof.json [--leaf <hex>]
+//! merkle hash-file <path> [--chunk-size 16KiB] [--chunk 3 | --range 0-65535] [--output proof.json]
+//! merkle verify-archive <path> [--hash sha3-256]
+//! ```
+//!
+//! Every line of the input is a hex encoded leaf, with `--raw` the lines are hashed instead.
+//! `hash-file` streams a file and uses the hashes of its fixed-size chunks as leaves. Proofs are
+//! written as JSON holding the leaf and its proof, or a list of them for the chunks of a byte range.
+//! `verify-archive` checks the parameters and the root of an `Archive` and lists its anchors and
+//! the re-hashes that are due.
+//!
+//! Every command produces a `Report` and fails with a `CliError`, both are only rendered as text
+//! at the very end. With `--format json` they are printed as JSON instead: reports are tagged with
+//! the `command`, errors with a stable `code` and carry their parameters as fields, so front-ends
+//! can render their own, e.g. localized, messages and scripts don't have to parse English text.
+
+use std::fmt::{self, Debug};
+use std::fs;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::SystemTime;
+
+use digest::{Digest, Output};
+use merkle_tree_rs::{verify, Archive, MerkleTree, Proof};
+use serde::{Deserialize, Serialize};
+use sha3::{Keccak256, Sha3_256, Sha3_512};
+
+const USAGE: &str = "usage:
+  merkle build --input <file> [--hash <algorithm>] [--raw]
+  merkle prove --input <file> --index <n> [--hash <algorithm>] [--raw] [--output <file>]
+  merkle verify --root <hex> --proof <file> [--leaf <hex>] [--hash <algorithm>]
+  merkle hash-file <path> [--chunk-size <size>] [--chunk <n> | --range <start>-<end>]
+                   [--hash <algorithm>] [--output <file>]
+  merkle verify-archive <path> [--hash <algorithm>]
+
+options:
+  --input <file>      newline-separated hex leaves, - reads from stdin
+  --raw               hash the lines of the input instead of decoding them
+  --hash <algorithm>  sha3-256 (default), sha3-512 or keccak256
+  --output <file>     write the proof to the file instead of stdout
+  --leaf <hex>        verify this leaf instead of the one stored in the proof
+  --chunk-size <size> size of the file chunks, e.g. 4096, 64KiB or 1MiB (default 16KiB)
+  --chunk <n>         prove the chunk with the given index
+  --range <start>-<end>
+                      prove all chunks overlapping the inclusive byte range
+  --format <format>   text (default) or json for results and errors";
+
+/// the default chunk size of `hash-file`
+const DEFAULT_CHUNK_SIZE: usize = 16 * 1024;
+
+/// the supported hash algorithms
+#[derive(Debug, Clone, Copy)]
+enum Algorithm {
+    Sha3_256,
+    Sha3_512,
+    Keccak256,
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = CliError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "sha3-256" => Ok(Self::Sha3_256),
+            "sha3-512" => Ok(Self::Sha3_512),
+            "keccak256" => Ok(Self::Keccak256),
+            _ => Err(CliError::UnknownAlgorithm {
+                algorithm: name.into(),
+            }),
+        }
+    }
+}
+
+/// how results and errors are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = CliError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(CliError::InvalidValue {
+                option: "--format".into(),
+                value: name.into(),
+            }),
+        }
+    }
+}
+
+/// the subcommands
+#[derive(Debug)]
+enum Command {
+    Build,
+    Prove,
+    Verify,
+    HashFile,
+    VerifyArchive,
+}
+
+/// the parsed command line
+#[derive(Debug)]
+struct Args {
+    command: Command,
+    input: Option<String>,
+    raw: bool,
+    hash: Algorithm,
+    format: Format,
+    index: Option<usize>,
+    output: Option<PathBuf>,
+    root: Option<String>,
+    proof: Option<PathBuf>,
+    leaf: Option<String>,
+    path: Option<PathBuf>,
+    chunk_size: usize,
+    chunk: Option<usize>,
+    range: Option<(u64, u64)>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, CliError> {
+        let command = match args.next().as_deref() {
+            Some("build") => Command::Build,
+            Some("prove") => Command::Prove,
+            Some("verify") => Command::Verify,
+            Some("hash-file") => Command::HashFile,
+            Some("verify-archive") => Command::VerifyArchive,
+            Some(command) => {
+                return Err(CliError::UnknownCommand {
+                    command: command.into(),
+                })
+            }
+            None => return Err(CliError::MissingCommand),
+        };
+        let mut parsed = Self {
+            command,
+            input: None,
+            raw: false,
+            hash: Algorithm::Sha3_256,
+            format: Format::Text,
+            index: None,
+            output: None,
+            root: None,
+            proof: None,
+            leaf: None,
+            path: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            chunk: None,
+            range: None,
+        };
+        while let Some(flag) = args.next() {
+            if flag == "--raw" {
+                parsed.raw = true;
+                continue;
+            }
+            if !flag.starts_with("--") {
+                match parsed.command {
+                    Command::HashFile | Command::VerifyArchive if parsed.path.is_none() => {
+                        parsed.path = Some(flag.into())
+                    }
+                    _ => return Err(CliError::UnexpectedArgument { argument: flag }),
+                }
+                continue;
+            }
+            let value = args.next().ok_or_else(|| CliError::MissingValue {
+                option: flag.clone(),
+            })?;
+            let invalid = || CliError::InvalidValue {
+                option: flag.clone(),
+                value: value.clone(),
+            };
+            match flag.as_str() {
+                "--input" => parsed.input = Some(value),
+                "--hash" => parsed.hash = value.parse()?,
+                "--format" => parsed.format = value.parse()?,
+                "--index" => parsed.index = Some(value.parse().map_err(|_| invalid())?),
+                "--output" => parsed.output = Some(value.into()),
+                "--root" => parsed.root = Some(value),
+                "--proof" => parsed.proof = Some(value.into()),
+                "--leaf" => parsed.leaf = Some(value),
+                "--chunk-size" => parsed.chunk_size = parse_size(&value).ok_or_else(invalid)?,
+                "--chunk" => parsed.chunk = Some(value.parse().map_err(|_| invalid())?),
+                "--range" => parsed.range = Some(parse_range(&value).ok_or_else(invalid)?),
+                _ => return Err(CliError::UnknownOption { option: flag }),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Errors of the commands, serialized with a stable code and their parameters
+#[derive(Debug, Serialize)]
+#[serde(tag = "code", rename_all = "kebab-case")]
+enum CliError {
+    MissingCommand,
+    UnknownCommand {
+        command: String,
+    },
+    UnexpectedArgument {
+        argument: String,
+    },
+    UnknownOption {
+        option: String,
+    },
+    MissingValue {
+        option: String,
+    },
+    InvalidValue {
+        option: String,
+        value: String,
+    },
+    UnknownAlgorithm {
+        algorithm: String,
+    },
+    /// a required option or argument was not given
+    Missing {
+        argument: String,
+    },
+    ConflictingOptions {
+        options: Vec<String>,
+    },
+    Io {
+        path: PathBuf,
+        message: String,
+    },
+    InvalidProofFile {
+        message: String,
+    },
+    InvalidHex {
+        message: String,
+    },
+    InvalidHashLength {
+        expected: usize,
+        actual: usize,
+    },
+    /// a line of the input doesn't hold a valid leaf
+    InvalidLeaf {
+        line: usize,
+        reason: Box<CliError>,
+    },
+    IndexOutOfRange {
+        index: usize,
+        leaves: usize,
+    },
+    ChunkOutOfRange {
+        chunk: usize,
+        chunks: usize,
+    },
+    LeafNeedsSingleProof,
+    InvalidArchive {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl CliError {
+    /// returns true if the command line is malformed and the usage should be printed
+    fn is_usage(&self) -> bool {
+        matches!(
+            self,
+            Self::MissingCommand
+                | Self::UnknownCommand { .. }
+                | Self::UnexpectedArgument { .. }
+                | Self::UnknownOption { .. }
+                | Self::MissingValue { .. }
+                | Self::InvalidValue { .. }
+                | Self::UnknownAlgorithm { .. }
+        )
+    }
+
+    fn io(path: &Path, err: io::Error) -> Self {
+        Self::Io {
+            path: path.into(),
+            message: err.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingCommand => write!(f, "missing command"),
+            Self::UnknownCommand { command } => write!(f, "unknown command {command}"),
+            Self::UnexpectedArgument { argument } => write!(f, "unexpected argument {argument}"),
+            Self::UnknownOption { option } => write!(f, "unknown option {option}"),
+            Self::MissingValue { option } => write!(f, "missing value for {option}"),
+            Self::InvalidValue { option, value } => write!(f, "invalid value {value} for {option}"),
+            Self::UnknownAlgorithm { algorithm } => write!(f, "unknown hash algorithm {algorithm}"),
+            Self::Missing { argument } => write!(f, "missing {argument}"),
+            Self::ConflictingOptions { options } => {
+                write!(f, "use either {}", options.join(" or "))
+            }
+            Self::Io { path, message } => write!(f, "{}: {message}", path.display()),
+            Self::InvalidProofFile { message } => write!(f, "invalid proof file: {message}"),
+            Self::InvalidHex { message } => write!(f, "{message}"),
+            Self::InvalidHashLength { expected, actual } => {
+                write!(f, "hashes must be {expected} bytes long, got {actual}")
+            }
+            Self::InvalidLeaf { line, reason } => write!(f, "line {line}: {reason}"),
+            Self::IndexOutOfRange { index, leaves } => {
+                write!(f, "index {index} is out of range for {leaves} leaves")
+            }
+            Self::ChunkOutOfRange { chunk, chunks } => {
+                write!(f, "chunk {chunk} is out of range for {chunks} chunks")
+            }
+            Self::LeafNeedsSingleProof => write!(f, "--leaf needs a file with a single proof"),
+            Self::InvalidArchive { path, message } => write!(f, "{}: {message}", path.display()),
+        }
+    }
+}
+
+/// a leaf together with its inclusion proof
+#[derive(Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "Proof<D>: Serialize",
+    deserialize = "Proof<D>: Deserialize<'de>"
+))]
+struct ProofFile<D: Digest> {
+    leaf: String,
+    proof: Proof<D>,
+}
+
+/// the content of a proof file, a single proof or the proofs of several chunks
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+#[serde(bound(
+    serialize = "Proof<D>: Serialize",
+    deserialize = "Proof<D>: Deserialize<'de>"
+))]
+enum ProofFiles<D: Digest> {
+    One(ProofFile<D>),
+    Many(Vec<ProofFile<D>>),
+}
+
+/// a re-hash of an archive that is due
+#[derive(Serialize)]
+struct DueRehash {
+    digest: String,
+    reason: String,
+    /// milliseconds since the Unix epoch
+    due: u64,
+}
+
+/// The result of a command, tagged with the command in JSON
+#[derive(Serialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+#[serde(bound(serialize = "Proof<D>: Serialize"))]
+enum Report<D: Digest> {
+    Build {
+        root: String,
+    },
+    Prove {
+        proofs: ProofFiles<D>,
+        /// the file the proofs were written to
+        output: Option<PathBuf>,
+    },
+    Verify {
+        valid: bool,
+    },
+    HashFile {
+        root: String,
+        proofs: Option<ProofFiles<D>>,
+        output: Option<PathBuf>,
+    },
+    VerifyArchive {
+        root: String,
+        anchors: Vec<String>,
+        due: Vec<DueRehash>,
+        valid: bool,
+    },
+}
+
+impl<D: Digest> Report<D>
+where
+    Proof<D>: Serialize,
+{
+    /// returns false if a proof doesn't verify or an archive is due for a re-hash
+    fn is_success(&self) -> bool {
+        match self {
+            Self::Verify { valid } | Self::VerifyArchive { valid, .. } => *valid,
+            _ => true,
+        }
+    }
+
+    /// prints the report for humans
+    fn print_text(&self) {
+        match self {
+            Self::Build { root } => println!("{root}"),
+            Self::Prove { proofs, output } => print_proofs(proofs, output),
+            Self::Verify { valid } => println!("{}", if *valid { "valid" } else { "invalid" }),
+            Self::HashFile {
+                root,
+                proofs,
+                output,
+            } => {
+                println!("{root}");
+                if let Some(proofs) = proofs {
+                    print_proofs(proofs, output);
+                }
+            }
+            Self::VerifyArchive {
+                root,
+                anchors,
+                due,
+                valid,
+            } => {
+                println!("{root}");
+                for anchor in anchors {
+                    println!("anchor {anchor}");
+                }
+                for plan in due {
+                    println!("re-hash to {} is due: {}", plan.digest, plan.reason);
+                }
+                println!("{}", if *valid { "valid" } else { "re-hash due" });
+            }
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let raw: Vec<String> = std::env::args().skip(1).collect();
+    // errors of the command line itself are printed in the requested format as well
+    let format = match raw.windows(2).any(|pair| pair == ["--format", "json"]) {
+        true => Format::Json,
+        false => Format::Text,
+    };
+    let args = match Args::parse(raw.into_iter()) {
+        Ok(args) => args,
+        Err(err) => {
+            print_error(format, &err);
+            return ExitCode::from(2);
+        }
+    };
+    match args.hash {
+        Algorithm::Sha3_256 => run_and_print::<Sha3_256>(&args),
+        Algorithm::Sha3_512 => run_and_print::<Sha3_512>(&args),
+        Algorithm::Keccak256 => run_and_print::<Keccak256>(&args),
+    }
+}
+
+/// runs the command and prints its report or error
+fn run_and_print<D>(args: &Args) -> ExitCode
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    match run::<D>(args) {
+        Ok(report) => {
+            match args.format {
+                Format::Text => report.print_text(),
+                Format::Json => println!("{}", to_json(&report)),
+            }
+            match report.is_success() {
+                true => ExitCode::SUCCESS,
+                false => ExitCode::FAILURE,
+            }
+        }
+        Err(err) => {
+            print_error(args.format, &err);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// prints the error to stderr, with the usage for malformed command lines in text format
+fn print_error(format: Format, err: &CliError) {
+    match format {
+        Format::Text if err.is_usage() => eprintln!("{err}\n\n{USAGE}"),
+        Format::Text => eprintln!("error: {err}"),
+        Format::Json => eprintln!("{}", to_json(&serde_json::json!({ "error": err }))),
+    }
+}
+
+/// runs the command
+fn run<D>(args: &Args) -> Result<Report<D>, CliError>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    match args.command {
+        Command::Build => {
+            let tree = MerkleTree::<D>::from_leaves(&read_leaves::<D>(args)?, &Default::default());
+            Ok(Report::Build {
+                root: hex::encode(tree.root_hash()),
+            })
+        }
+        Command::Prove => {
+            let index = args.index.ok_or_else(|| missing("--index"))?;
+            let leaves = read_leaves::<D>(args)?;
+            let leaf = leaves.get(index).ok_or(CliError::IndexOutOfRange {
+                index,
+                leaves: leaves.len(),
+            })?;
+            let tree = MerkleTree::<D>::from_leaves(&leaves, &Default::default());
+            let proofs = ProofFiles::One(ProofFile {
+                leaf: hex::encode(leaf),
+                proof: tree.create_proof(index),
+            });
+            write_proofs(args, &proofs)?;
+            Ok(Report::Prove {
+                proofs,
+                output: args.output.clone(),
+            })
+        }
+        Command::Verify => {
+            let root = parse_hash::<D>(args.root.as_deref().ok_or_else(|| missing("--root"))?)?;
+            let path = args.proof.as_ref().ok_or_else(|| missing("--proof"))?;
+            let json = fs::read_to_string(path).map_err(|e| CliError::io(path, e))?;
+            let files = match serde_json::from_str::<ProofFiles<D>>(&json).map_err(|e| {
+                CliError::InvalidProofFile {
+                    message: e.to_string(),
+                }
+            })? {
+                ProofFiles::One(mut file) => {
+                    if let Some(leaf) = &args.leaf {
+                        file.leaf.clone_from(leaf);
+                    }
+                    vec![file]
+                }
+                ProofFiles::Many(_) if args.leaf.is_some() => {
+                    return Err(CliError::LeafNeedsSingleProof)
+                }
+                ProofFiles::Many(files) if files.is_empty() => {
+                    return Err(CliError::InvalidProofFile {
+                        message: "the file holds no proofs".into(),
+                    })
+                }
+                ProofFiles::Many(files) => files,
+            };
+            let mut valid = true;
+            for file in files {
+                valid &= verify(&root, &parse_hash::<D>(&file.leaf)?, &file.proof);
+            }
+            Ok(Report::Verify { valid })
+        }
+        Command::HashFile => {
+            let leaves = hash_chunks::<D>(args)?;
+            let tree = MerkleTree::<D>::from_leaves(&leaves, &Default::default());
+            let root = hex::encode(tree.root_hash());
+            let out_of_range = |chunk| CliError::ChunkOutOfRange {
+                chunk,
+                chunks: leaves.len(),
+            };
+            let chunks = match (args.chunk, args.range) {
+                (Some(_), Some(_)) => {
+                    return Err(CliError::ConflictingOptions {
+                        options: vec!["--chunk".into(), "--range".into()],
+                    })
+                }
+                (Some(chunk), None) => {
+                    chunk..chunk.checked_add(1).ok_or_else(|| out_of_range(chunk))?
+                }
+                (None, Some((start, end))) => {
+                    let size = args.chunk_size as u64;
+                    let first = usize::try_from(start / size).unwrap_or(usize::MAX);
+                    let last = usize::try_from(end / size).unwrap_or(usize::MAX);
+                    first..last.checked_add(1).ok_or_else(|| out_of_range(last))?
+                }
+                (None, None) => {
+                    return Ok(Report::HashFile {
+                        root,
+                        proofs: None,
+                        output: None,
+                    })
+                }
+            };
+            if chunks.end > leaves.len() {
+                return Err(out_of_range(chunks.end - 1));
+            }
+            let mut files: Vec<ProofFile<D>> = chunks
+                .map(|chunk| ProofFile {
+                    leaf: hex::encode(leaves[chunk]),
+                    proof: tree.create_proof(chunk),
+                })
+                .collect();
+            let proofs = match (args.chunk, files.len()) {
+                (Some(_), 1) => ProofFiles::One(files.remove(0)),
+                _ => ProofFiles::Many(files),
+            };
+            write_proofs(args, &proofs)?;
+            Ok(Report::HashFile {
+                root,
+                proofs: Some(proofs),
+                output: args.output.clone(),
+            })
+        }
+        Command::VerifyArchive => {
+            let path = args.path.as_ref().ok_or_else(|| missing("archive"))?;
+            let bytes = fs::read(path).map_err(|e| CliError::io(path, e))?;
+            let archive =
+                Archive::<D>::from_bytes(&bytes).map_err(|e| CliError::InvalidArchive {
+                    path: path.clone(),
+                    message: e.to_string(),
+                })?;
+            let due: Vec<DueRehash> = archive
+                .due_rehashes(SystemTime::now())
+                .map(|plan| DueRehash {
+                    digest: plan.digest.clone(),
+                    reason: plan.reason.clone(),
+                    due: plan.due,
+                })
+                .collect();
+            Ok(Report::VerifyArchive {
+                root: hex::encode(archive.root()),
+                anchors: archive.anchors().iter().map(|a| a.to_string()).collect(),
+                valid: due.is_empty(),
+                due,
+            })
+        }
+    }
+}
+
+fn missing(argument: &str) -> CliError {
+    CliError::Missing {
+        argument: argument.into(),
+    }
+}
+
+fn to_json(value: &impl Serialize) -> String {
+    serde_json::to_string_pretty(value).expect("reports serialize to JSON")
+}
+
+/// writes the proofs as JSON to the output file if there is one
+fn write_proofs<D: Digest>(args: &Args, files: &ProofFiles<D>) -> Result<(), CliError>
+where
+    Proof<D>: Serialize,
+{
+    match &args.output {
+        Some(path) => fs::write(path, to_json(files) + "\n").map_err(|e| CliError::io(path, e)),
+        None => Ok(()),
+    }
+}
+
+/// prints the proofs as JSON unless they were written to a file
+fn print_proofs<D: Digest>(files: &ProofFiles<D>, output: &Option<PathBuf>)
+where
+    Proof<D>: Serialize,
+{
+    if output.is_none() {
+        println!("{}", to_json(files));
+    }
+}
+
+/// streams the file and hashes its chunks
+fn hash_chunks<D: Digest>(args: &Args) -> Result<Vec<Output<D>>, CliError> {
+    let path = args.path.as_ref().ok_or_else(|| missing("file"))?;
+    let error = |e: io::Error| CliError::io(path, e);
+    let mut file = BufReader::new(fs::File::open(path).map_err(error)?);
+    let mut chunk = Vec::with_capacity(args.chunk_size);
+    let mut leaves = Vec::new();
+    loop {
+        chunk.clear();
+        (&mut file)
+            .take(args.chunk_size as u64)
+            .read_to_end(&mut chunk)
+            .map_err(error)?;
+        if chunk.is_empty() {
+            return Ok(leaves);
+        }
+        leaves.push(D::digest(&chunk));
+    }
+}
+
+/// reads the leaves from the input file
+fn read_leaves<D: Digest>(args: &Args) -> Result<Vec<Output<D>>, CliError> {
+    let input = args.input.as_deref().ok_or_else(|| missing("--input"))?;
+    let mut content = String::new();
+    match input {
+        "-" => io::stdin().read_to_string(&mut content),
+        path => fs::File::open(path).and_then(|mut file| file.read_to_string(&mut content)),
+    }
+    .map_err(|e| CliError::io(Path::new(input), e))?;
+    if args.raw {
+        return Ok(content.lines().map(D::digest).collect());
+    }
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            parse_hash::<D>(line).map_err(|reason| CliError::InvalidLeaf {
+                line: number + 1,
+                reason: Box::new(reason),
+            })
+        })
+        .collect()
+}
+
+/// parses a size in bytes with an optional binary unit like `16KiB`
+fn parse_size(size: &str) -> Option<usize> {
+    let digits = size.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match &size[digits.len()..] {
+        "" | "B" => 1,
+        "K" | "KiB" => 1 << 10,
+        "M" | "MiB" => 1 << 20,
+        "G" | "GiB" => 1 << 30,
+        _ => return None,
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .filter(|size| *size > 0)
+}
+
+/// parses an inclusive byte range like `100-199`
+fn parse_range(range: &str) -> Option<(u64, u64)> {
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+    (start <= end).then_some((start, end))
+}
+
+/// decodes a hex encoded hash
+fn parse_hash<D: Digest>(hex: &str) -> Result<Output<D>, CliError> {
+    let bytes =
+        hex::decode(hex.trim().trim_start_matches("0x")).map_err(|e| CliError::InvalidHex {
+            message: e.to_string(),
+        })?;
+    if bytes.len() != <D as Digest>::output_size() {
+        return Err(CliError::InvalidHashLength {
+            expected: <D as Digest>::output_size(),
+            actual: bytes.len(),
+        });
+    }
+    Ok(Output::<D>::clone_from_slice(&bytes))
+}
@@ -0,0 +1,7 @@
+//! Bindings for other languages, each behind its own feature
+//! The bindings wrap a `MerkleTree<Sha3_256>` and exchange hashes as byte arrays.
+
+#[cfg(feature = "uniffi")]
+pub mod mobile;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,209 @@
+//! Kotlin and Swift bindings generated with uniffi
+//!
+//! Build the shared library of the `bindings` package and generate the bindings with
+//!
+//! ```text
+//! cargo build --manifest-path bindings/Cargo.toml --release --features uniffi
+//! cargo run --features uniffi --bin uniffi-bindgen -- generate \
+//!     --library bindings/target/release/libmerkle_tree_bindings.so --language kotlin --out-dir out
+//! ```
+//!
+//! (use `--language swift` for Swift bindings)
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+use digest::Output;
+use sha3::Sha3_256;
+
+use crate::{MerkleTree, MerkleTreeError, Proof};
+
+/// Errors exposed to the bindings
+#[derive(Debug, uniffi::Error)]
+pub enum MobileError {
+    /// the requested depth is not supported
+    DepthOutOfRange { depth: u64, max: u64 },
+    /// the leaf offset is not smaller than the number of leaves
+    LeafIndexOutOfBounds { offset: u64, num_leaves: u64 },
+    /// the proof doesn't have one sibling per layer below the root
+    InvalidProofLength { expected: u64, actual: u64 },
+    /// a hash doesn't have a length of 32 bytes
+    InvalidHashLength { length: u64 },
+    /// any other error
+    Other { message: String },
+}
+
+impl std::fmt::Display for MobileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidHashLength { length } => {
+                write!(f, "hashes must be 32 bytes long, got {length}")
+            }
+            Self::Other { message } => write!(f, "{message}"),
+            Self::DepthOutOfRange { depth, max } => MerkleTreeError::DepthOutOfRange {
+                depth: *depth as usize,
+                max: *max as usize,
+            }
+            .fmt(f),
+            Self::LeafIndexOutOfBounds { offset, num_leaves } => {
+                MerkleTreeError::LeafIndexOutOfBounds {
+                    offset: *offset as usize,
+                    num_leaves: *num_leaves as usize,
+                }
+                .fmt(f)
+            }
+            Self::InvalidProofLength { expected, actual } => MerkleTreeError::InvalidProofLength {
+                expected: *expected as usize,
+                actual: *actual as usize,
+            }
+            .fmt(f),
+        }
+    }
+}
+
+impl From<MerkleTreeError> for MobileError {
+    fn from(err: MerkleTreeError) -> Self {
+        match err {
+            MerkleTreeError::DepthOutOfRange { depth, max } => Self::DepthOutOfRange {
+                depth: depth as u64,
+                max: max as u64,
+            },
+            MerkleTreeError::LeafIndexOutOfBounds { offset, num_leaves } => {
+                Self::LeafIndexOutOfBounds {
+                    offset: offset as u64,
+                    num_leaves: num_leaves as u64,
+                }
+            }
+            MerkleTreeError::InvalidProofLength { expected, actual } => Self::InvalidProofLength {
+                expected: expected as u64,
+                actual: actual as u64,
+            },
+            err => Self::Other {
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+/// An inclusion proof for a single leaf
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct MobileProof {
+    /// offset of the proven leaf
+    pub leaf_index: u64,
+    /// sibling hashes starting at the leaf layer, 32 bytes each
+    pub siblings: Vec<Vec<u8>>,
+}
+
+impl From<Proof<Sha3_256>> for MobileProof {
+    fn from(proof: Proof<Sha3_256>) -> Self {
+        Self {
+            leaf_index: proof.leaf_index() as u64,
+            siblings: proof.siblings().iter().map(|s| s.to_vec()).collect(),
+        }
+    }
+}
+
+impl TryFrom<MobileProof> for Proof<Sha3_256> {
+    type Error = MobileError;
+
+    fn try_from(proof: MobileProof) -> Result<Self, Self::Error> {
+        let siblings = proof
+            .siblings
+            .iter()
+            .map(|s| to_hash(s))
+            .collect::<Result<_, _>>()?;
+        Ok(Proof::new(proof.leaf_index as usize, siblings))
+    }
+}
+
+/// A SHA3-256 Merkle tree shared with Kotlin or Swift
+#[derive(uniffi::Object)]
+pub struct MobileMerkleTree {
+    tree: Mutex<MerkleTree<Sha3_256>>,
+}
+
+#[uniffi::export]
+impl MobileMerkleTree {
+    /// creates a new Merkle tree with the given depth and initial value for the leaves
+    #[uniffi::constructor]
+    pub fn new(depth: u32, initial_value: Vec<u8>) -> Result<Arc<Self>, MobileError> {
+        let tree = MerkleTree::try_new(depth as usize, &to_hash(&initial_value)?)?;
+        Ok(Arc::new(Self {
+            tree: Mutex::new(tree),
+        }))
+    }
+
+    /// returns the root hash of the tree
+    pub fn root_hash(&self) -> Vec<u8> {
+        self.tree().root_hash().to_vec()
+    }
+
+    /// returns the number of leaves in the tree
+    pub fn num_leaves(&self) -> u64 {
+        self.tree().num_leaves() as u64
+    }
+
+    /// updates the value of a leaf node
+    pub fn set(&self, offset: u64, value: Vec<u8>) -> Result<(), MobileError> {
+        Ok(self.tree().try_set(offset as usize, &to_hash(&value)?)?)
+    }
+
+    /// creates a proof for a leaf node
+    pub fn create_proof(&self, offset: u64) -> Result<MobileProof, MobileError> {
+        Ok(self.tree().try_create_proof(offset as usize)?.into())
+    }
+}
+
+impl MobileMerkleTree {
+    fn tree(&self) -> std::sync::MutexGuard<'_, MerkleTree<Sha3_256>> {
+        self.tree.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// verifies that the leaf value and the proof hash up to the given root
+#[uniffi::export]
+pub fn verify_proof(root: Vec<u8>, leaf: Vec<u8>, proof: MobileProof) -> Result<bool, MobileError> {
+    Ok(crate::verify(
+        &to_hash(&root)?,
+        &to_hash(&leaf)?,
+        &proof.try_into()?,
+    ))
+}
+
+/// converts a byte slice to a hash
+fn to_hash(bytes: &[u8]) -> Result<Output<Sha3_256>, MobileError> {
+    if bytes.len() != 32 {
+        return Err(MobileError::InvalidHashLength {
+            length: bytes.len() as u64,
+        });
+    }
+    Ok(Output::<Sha3_256>::clone_from_slice(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mobile_tree() {
+        let tree = MobileMerkleTree::new(4, vec![0; 32]).unwrap();
+        for i in 0..tree.num_leaves() {
+            tree.set(i, vec![(i * 0x11) as u8; 32]).unwrap();
+        }
+        let proof = tree.create_proof(5).unwrap();
+        assert!(verify_proof(tree.root_hash(), vec![0x55; 32], proof.clone()).unwrap());
+        assert!(!verify_proof(tree.root_hash(), vec![0x66; 32], proof).unwrap());
+
+        assert!(matches!(
+            tree.set(16, vec![0; 32]),
+            Err(MobileError::LeafIndexOutOfBounds { .. })
+        ));
+        assert!(matches!(
+            tree.set(0, vec![0; 31]),
+            Err(MobileError::InvalidHashLength { length: 31 })
+        ));
+        assert!(matches!(
+            MobileMerkleTree::new(crate::MAX_DEPTH as u32 + 1, vec![0; 32]),
+            Err(MobileError::DepthOutOfRange { .. })
+        ));
+    }
+}
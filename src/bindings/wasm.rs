@@ -0,0 +1,185 @@
+//! JavaScript bindings generated with wasm-bindgen
+//!
+//! Build the npm package from the `bindings` directory with
+//!
+//! ```text
+//! wasm-pack build bindings --target web -- --features wasm
+//! ```
+//!
+//! Hashes and proofs are exchanged as `Uint8Array`s, every function taking or returning bytes has a
+//! `Hex` variant using hex strings instead. Proofs use the compact encoding of `Proof::to_bytes`.
+
+use digest::Output;
+use sha3::Sha3_256;
+use wasm_bindgen::prelude::*;
+
+use crate::{MerkleTree, Proof};
+
+/// A SHA3-256 Merkle tree usable from JavaScript
+#[wasm_bindgen]
+pub struct WasmMerkleTree {
+    tree: MerkleTree<Sha3_256>,
+}
+
+#[wasm_bindgen]
+impl WasmMerkleTree {
+    /// creates a new Merkle tree with the given depth and initial value for the leaves
+    #[wasm_bindgen(constructor)]
+    pub fn new(depth: u32, initial_value: &[u8]) -> Result<WasmMerkleTree, JsError> {
+        let tree = MerkleTree::try_new(depth as usize, &to_hash(initial_value)?)?;
+        Ok(Self { tree })
+    }
+
+    /// creates a new Merkle tree from the concatenated 32 byte leaves, padded with the padding value
+    #[wasm_bindgen(js_name = fromLeaves)]
+    pub fn from_leaves(leaves: &[u8], padding: &[u8]) -> Result<WasmMerkleTree, JsError> {
+        Ok(Self {
+            tree: MerkleTree::from_leaves(&to_hashes(leaves)?, &to_hash(padding)?),
+        })
+    }
+
+    /// returns the root hash of the tree
+    #[wasm_bindgen(js_name = rootHash)]
+    pub fn root_hash(&self) -> Vec<u8> {
+        self.tree.root_hash().to_vec()
+    }
+
+    /// returns the root hash of the tree as hex string
+    #[wasm_bindgen(js_name = rootHashHex)]
+    pub fn root_hash_hex(&self) -> String {
+        hex::encode(self.tree.root_hash())
+    }
+
+    /// returns the number of leaves in the tree
+    #[wasm_bindgen(js_name = numLeaves)]
+    pub fn num_leaves(&self) -> usize {
+        self.tree.num_leaves()
+    }
+
+    /// updates the value of a leaf node
+    pub fn set(&mut self, offset: usize, value: &[u8]) -> Result<(), JsError> {
+        Ok(self.tree.try_set(offset, &to_hash(value)?)?)
+    }
+
+    /// updates the value of a leaf node given as hex string
+    #[wasm_bindgen(js_name = setHex)]
+    pub fn set_hex(&mut self, offset: usize, value: &str) -> Result<(), JsError> {
+        self.set(offset, &from_hex(value)?)
+    }
+
+    /// creates the encoded proof for a leaf node
+    #[wasm_bindgen(js_name = createProof)]
+    pub fn create_proof(&self, offset: usize) -> Result<Vec<u8>, JsError> {
+        Ok(self.tree.try_create_proof(offset)?.to_bytes())
+    }
+
+    /// creates the encoded proof for a leaf node as hex string
+    #[wasm_bindgen(js_name = createProofHex)]
+    pub fn create_proof_hex(&self, offset: usize) -> Result<String, JsError> {
+        Ok(hex::encode(self.create_proof(offset)?))
+    }
+}
+
+/// verifies that the leaf value and the encoded proof hash up to the given root
+#[wasm_bindgen(js_name = verifyProof)]
+pub fn verify_proof_js(root: &[u8], leaf: &[u8], proof: &[u8]) -> Result<bool, JsError> {
+    let proof = Proof::<Sha3_256>::from_bytes(proof)?;
+    Ok(crate::verify(&to_hash(root)?, &to_hash(leaf)?, &proof))
+}
+
+/// verifies a proof like `verifyProof` with all arguments given as hex strings
+#[wasm_bindgen(js_name = verifyProofHex)]
+pub fn verify_proof_hex(root: &str, leaf: &str, proof: &str) -> Result<bool, JsError> {
+    verify_proof_js(&from_hex(root)?, &from_hex(leaf)?, &from_hex(proof)?)
+}
+
+/// Errors of the argument conversions
+#[derive(Debug)]
+enum ArgumentError {
+    /// a hash doesn't have a length of 32 bytes
+    InvalidHashLength { length: usize },
+    /// a string is not valid hex
+    InvalidHex(hex::FromHexError),
+}
+
+impl std::fmt::Display for ArgumentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidHashLength { length } => {
+                write!(f, "hashes must be 32 bytes long, got {length}")
+            }
+            Self::InvalidHex(err) => write!(f, "invalid hex string: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ArgumentError {}
+
+/// converts a byte slice to a hash
+fn to_hash(bytes: &[u8]) -> Result<Output<Sha3_256>, ArgumentError> {
+    if bytes.len() != 32 {
+        return Err(ArgumentError::InvalidHashLength {
+            length: bytes.len(),
+        });
+    }
+    Ok(Output::<Sha3_256>::clone_from_slice(bytes))
+}
+
+/// splits concatenated hashes
+fn to_hashes(bytes: &[u8]) -> Result<Vec<Output<Sha3_256>>, ArgumentError> {
+    if !bytes.len().is_multiple_of(32) {
+        return Err(ArgumentError::InvalidHashLength {
+            length: bytes.len() % 32,
+        });
+    }
+    Ok(bytes
+        .chunks_exact(32)
+        .map(Output::<Sha3_256>::clone_from_slice)
+        .collect())
+}
+
+/// decodes a hex string
+fn from_hex(value: &str) -> Result<Vec<u8>, ArgumentError> {
+    hex::decode(value).map_err(ArgumentError::InvalidHex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // errors can't be converted to JavaScript values outside of wasm, so only the conversions are
+    // checked for failures here
+    #[test]
+    fn test_wasm_tree() {
+        let leaves: Vec<u8> = (0..5u8).flat_map(|i| [i; 32]).collect();
+        let mut tree = WasmMerkleTree::from_leaves(&leaves, &[0xab; 32]).unwrap();
+        assert_eq!(tree.num_leaves(), 8);
+        tree.set_hex(6, &"66".repeat(32)).unwrap();
+
+        let proof = tree.create_proof(6).unwrap();
+        assert!(verify_proof_js(&tree.root_hash(), &[0x66; 32], &proof).unwrap());
+        assert!(!verify_proof_js(&tree.root_hash(), &[0x67; 32], &proof).unwrap());
+        assert!(verify_proof_hex(
+            &tree.root_hash_hex(),
+            &"66".repeat(32),
+            &tree.create_proof_hex(6).unwrap()
+        )
+        .unwrap());
+
+        let tree = WasmMerkleTree::new(3, &[0; 32]).unwrap();
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::<Sha3_256>::new(3, &[0; 32].into())
+                .root_hash()
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert!(to_hash(&[0; 31]).is_err());
+        assert!(to_hashes(&[0; 65]).is_err());
+        assert_eq!(to_hashes(&[0; 64]).unwrap().len(), 2);
+        assert!(from_hex("zz").is_err());
+    }
+}
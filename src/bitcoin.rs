@@ -0,0 +1,241 @@
+//! Block Merkle trees following Bitcoin's rules
+//!
+//! Transactions are identified by their txid, the double SHA-256 hash of the serialized
+//! transaction. Each pair of nodes is hashed with double SHA-256 (`DoubleHash`) and the last node
+//! of a layer with an odd number of nodes is paired with itself, so there is no padding value.
+//!
+//! All hashes are in the internal byte order used in blocks and headers. Block explorers and RPC
+//! calls display txids and Merkle roots with their bytes reversed, `txid_from_hex` and
+//! `txid_to_hex` convert between the two.
+//!
+//! Duplicating the last node makes different transaction lists share a root (CVE-2012-2459),
+//! `is_mutated` detects the lists Bitcoin Core rejects for this reason.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use digest::{Digest, Output};
+use sha2::Sha256;
+
+use crate::scheme::{DoubleHash, HashScheme};
+use crate::MerkleTreeError;
+
+/// length of a serialized block header
+pub const HEADER_SIZE: usize = 80;
+
+/// returns the txid in internal byte order given its displayed hex form
+pub fn txid_from_hex(hex: &str) -> Result<Output<Sha256>, hex::FromHexError> {
+    let mut txid = Output::<Sha256>::default();
+    hex::decode_to_slice(hex, &mut txid)?;
+    txid.reverse();
+    Ok(txid)
+}
+
+/// returns the displayed hex form of a txid or Merkle root in internal byte order
+pub fn txid_to_hex(txid: &Output<Sha256>) -> String {
+    let mut reversed = *txid;
+    reversed.reverse();
+    hex::encode(reversed)
+}
+
+/// returns the txid of a serialized transaction
+pub fn txid(transaction: &[u8]) -> Output<Sha256> {
+    <DoubleHash as HashScheme<Sha256>>::hash_leaf(transaction)
+}
+
+/// returns the Merkle root of the txids of a block or `None` if there are no txids
+pub fn merkle_root(txids: &[Output<Sha256>]) -> Option<Output<Sha256>> {
+    let mut layer = txids.to_vec();
+    if layer.is_empty() {
+        return None;
+    }
+    while layer.len() > 1 {
+        layer = next_layer(&layer);
+    }
+    Some(layer[0])
+}
+
+/// returns true if a layer of the tree contains a pair of identical nodes, in which case the
+/// txids share their root with a shorter list
+pub fn is_mutated(txids: &[Output<Sha256>]) -> bool {
+    let mut layer = txids.to_vec();
+    while layer.len() > 1 {
+        if layer.chunks_exact(2).any(|pair| pair[0] == pair[1]) {
+            return true;
+        }
+        layer = next_layer(&layer);
+    }
+    false
+}
+
+/// returns the Merkle root stored in a serialized block header
+pub fn header_merkle_root(header: &[u8; HEADER_SIZE]) -> Output<Sha256> {
+    Output::<Sha256>::clone_from_slice(&header[36..68])
+}
+
+/// returns the hash of a serialized block header in internal byte order
+pub fn block_hash(header: &[u8; HEADER_SIZE]) -> Output<Sha256> {
+    Sha256::digest(Sha256::digest(header))
+}
+
+/// returns true if the txids lead to the Merkle root of the block header and are not mutated
+pub fn verify_block(header: &[u8; HEADER_SIZE], txids: &[Output<Sha256>]) -> bool {
+    merkle_root(txids) == Some(header_merkle_root(header)) && !is_mutated(txids)
+}
+
+/// returns a proof that the txid at the given index is part of the block
+/// returns an error if the index is out of bounds
+pub fn create_proof(
+    txids: &[Output<Sha256>],
+    index: usize,
+) -> Result<BitcoinProof, MerkleTreeError> {
+    if index >= txids.len() {
+        return Err(MerkleTreeError::LeafIndexOutOfBounds {
+            offset: index,
+            num_leaves: txids.len(),
+        });
+    }
+    let mut siblings = Vec::new();
+    let mut layer = txids.to_vec();
+    let mut offset = index;
+    while layer.len() > 1 {
+        // the last node of an odd layer is its own sibling
+        let sibling = (offset ^ 1).min(layer.len() - 1);
+        siblings.push(layer[sibling]);
+        layer = next_layer(&layer);
+        offset /= 2;
+    }
+    Ok(BitcoinProof { index, siblings })
+}
+
+/// A proof that a transaction is part of a block, as returned by
+/// `blockchain.transaction.get_merkle` of Electrum servers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitcoinProof {
+    /// position of the transaction in the block
+    pub index: usize,
+    /// siblings from the txid layer up to the layer below the root
+    pub siblings: Vec<Output<Sha256>>,
+}
+
+impl BitcoinProof {
+    /// returns the Merkle root the txid leads to
+    pub fn compute_root(&self, txid: &Output<Sha256>) -> Output<Sha256> {
+        let mut hash = *txid;
+        for (layer, sibling) in self.siblings.iter().enumerate() {
+            hash = if (self.index >> layer) & 1 == 0 {
+                <DoubleHash as HashScheme<Sha256>>::hash_node(&hash, sibling)
+            } else {
+                <DoubleHash as HashScheme<Sha256>>::hash_node(sibling, &hash)
+            };
+        }
+        hash
+    }
+
+    /// returns true if the txid leads to the Merkle root of the block header
+    pub fn verify(&self, txid: &Output<Sha256>, header: &[u8; HEADER_SIZE]) -> bool {
+        self.compute_root(txid) == header_merkle_root(header)
+    }
+}
+
+/// returns the parent layer, pairing the last node of an odd layer with itself
+fn next_layer(layer: &[Output<Sha256>]) -> Vec<Output<Sha256>> {
+    layer
+        .chunks(2)
+        .map(|pair| {
+            let right = pair.get(1).unwrap_or(&pair[0]);
+            <DoubleHash as HashScheme<Sha256>>::hash_node(&pair[0], right)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the transactions of mainnet block 100000
+    const TXIDS: [&str; 4] = [
+        "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+        "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+        "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+        "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+    ];
+
+    /// the header of mainnet block 100000
+    const HEADER: &str = "0100000050120119172a610421a6c3011dd330d9df07b63616c2cc1f1cd00200000000006657a9252aacd5c0b2940996ecff952228c3067cc38d4885efb5a4ac4247e9f337221b4d4c86041b0f2b5710";
+
+    fn txids() -> Vec<Output<Sha256>> {
+        TXIDS
+            .iter()
+            .map(|txid| txid_from_hex(txid).unwrap())
+            .collect()
+    }
+
+    fn header() -> [u8; HEADER_SIZE] {
+        let mut header = [0; HEADER_SIZE];
+        hex::decode_to_slice(HEADER, &mut header).unwrap();
+        header
+    }
+
+    #[test]
+    fn test_block_root() {
+        let header = header();
+        assert_eq!(
+            txid_to_hex(&block_hash(&header)),
+            "000000000003ba27aa200b1cecaad478d2b00432346c3f1f3986da1afd33e506"
+        );
+        let root = merkle_root(&txids()).unwrap();
+        assert_eq!(
+            txid_to_hex(&root),
+            "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766"
+        );
+        assert!(verify_block(&header, &txids()));
+        assert!(!verify_block(&header, &txids()[..3]));
+
+        // a single transaction is the root itself
+        assert_eq!(merkle_root(&txids()[..1]), Some(txids()[0]));
+        assert_eq!(merkle_root(&[]), None);
+    }
+
+    #[test]
+    fn test_odd_layers() {
+        let txids = txids();
+        // the third txid is paired with itself
+        let root = merkle_root(&txids[..3]).unwrap();
+        assert_eq!(
+            txid_to_hex(&root),
+            "fa435470825de273081dcc706b25514c936fa6dc80ab965ce6970d68ddd0b553"
+        );
+        assert!(!is_mutated(&txids[..3]));
+
+        // duplicating the last txid doesn't change the root but is detected
+        let mutated = [&txids[..3], &txids[2..3]].concat();
+        assert_eq!(merkle_root(&mutated), Some(root));
+        assert!(is_mutated(&mutated));
+    }
+
+    #[test]
+    fn test_proofs() {
+        let header = header();
+        let txids = txids();
+        for (index, txid) in txids.iter().enumerate() {
+            let proof = create_proof(&txids, index).unwrap();
+            assert_eq!(proof.siblings.len(), 2);
+            assert!(proof.verify(txid, &header));
+            assert!(!proof.verify(&txids[(index + 1) % 4], &header));
+        }
+
+        let root = merkle_root(&txids[..3]).unwrap();
+        for index in 0..3 {
+            let proof = create_proof(&txids[..3], index).unwrap();
+            assert_eq!(proof.compute_root(&txids[index]), root);
+        }
+        assert_eq!(
+            create_proof(&txids, 4),
+            Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: 4,
+                num_leaves: 4
+            })
+        );
+    }
+}
@@ -0,0 +1,335 @@
+//! File trees of BitTorrent v2 (BEP 52)
+//!
+//! A v2 torrent identifies every file by its pieces root: the file is split into blocks of 16 KiB,
+//! the last one possibly shorter, and the SHA-256 hashes of the blocks are the leaves of a binary
+//! tree padded with zero hashes to the next power of two. Nodes are the SHA-256 hash of both
+//! children (`Plain`), so `FileTree` wraps a `MerkleTree<Sha256>` with one leaf per block.
+//!
+//! Files larger than the piece length additionally list their piece layer in the torrent, the layer
+//! of the tree with one node per piece. Only the nodes of pieces holding file data are listed, the
+//! subtrees of the remaining pieces consist of zero hashes. `piece_hash` hashes the data of a
+//! downloaded piece for comparison with its node, `verify_piece_layer` checks a piece layer
+//! against the pieces root and `FileTree::create_piece_proof` proves a single node of it, as
+//! hash requests of peers do.
+//!
+//! Empty files have no pieces root.
+
+use alloc::vec::Vec;
+
+use digest::{Digest, Output};
+use sha2::Sha256;
+
+use crate::{MerkleTree, MerkleTreeError, Proof};
+
+/// size of the blocks hashed into leaves, the last block of a file may be shorter
+pub const BLOCK_SIZE: usize = 16 * 1024;
+
+/// The tree of the blocks of a file, see the module docs
+#[derive(Debug)]
+pub struct FileTree {
+    tree: MerkleTree<Sha256>,
+    /// the length of the file in bytes
+    len: u64,
+}
+
+impl FileTree {
+    /// hashes the blocks of the file's content
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let blocks: Vec<_> = data.chunks(BLOCK_SIZE).map(Sha256::digest).collect();
+        Self::from_blocks(&blocks, data.len() as u64)
+    }
+
+    /// hashes the blocks of the file's content read until the end of the reader
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: std::io::Read>(mut reader: R) -> std::io::Result<Self> {
+        let mut blocks = Vec::new();
+        let mut len = 0u64;
+        let mut block = alloc::vec![0u8; BLOCK_SIZE];
+        loop {
+            // short reads don't end a block, only the end of the file does
+            let mut filled = 0;
+            while filled < BLOCK_SIZE {
+                match reader.read(&mut block[filled..]) {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            if filled == 0 {
+                break;
+            }
+            blocks.push(Sha256::digest(&block[..filled]));
+            len += filled as u64;
+            if filled < BLOCK_SIZE {
+                break;
+            }
+        }
+        Ok(Self::from_blocks(&blocks, len))
+    }
+
+    fn from_blocks(blocks: &[Output<Sha256>], len: u64) -> Self {
+        Self {
+            tree: MerkleTree::from_leaves(blocks, &Output::<Sha256>::default()),
+            len,
+        }
+    }
+
+    /// returns the tree of the block hashes, its unused leaves are zero hashes
+    pub fn tree(&self) -> &MerkleTree<Sha256> {
+        &self.tree
+    }
+
+    /// returns the length of the file in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// returns true if the file is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// returns the number of blocks of the file
+    pub fn num_blocks(&self) -> usize {
+        self.tree.next_offset()
+    }
+
+    /// returns the pieces root of the file, None if it is empty
+    pub fn pieces_root(&self) -> Option<Output<Sha256>> {
+        (!self.is_empty()).then(|| *self.tree.root_hash())
+    }
+
+    /// returns the nodes of the pieces holding file data, empty if the file isn't larger than a
+    /// piece as such files have no piece layer
+    /// returns an error if the piece length is not a power of two of at least `BLOCK_SIZE`
+    pub fn piece_layer(&self, piece_length: u64) -> Result<Vec<Output<Sha256>>, MerkleTreeError> {
+        let layer = self.piece_layer_index(piece_length)?;
+        if self.len <= piece_length {
+            return Ok(Vec::new());
+        }
+        let num_pieces = self.len.div_ceil(piece_length) as usize;
+        Ok(self
+            .tree
+            .iter_layer(layer)
+            .take(num_pieces)
+            .copied()
+            .collect())
+    }
+
+    /// returns the piece layer as stored in the `piece layers` dictionary of a torrent, the
+    /// concatenated nodes of `piece_layer`
+    /// returns an error if the piece length is not a power of two of at least `BLOCK_SIZE`
+    pub fn piece_layer_bytes(&self, piece_length: u64) -> Result<Vec<u8>, MerkleTreeError> {
+        Ok(self.piece_layer(piece_length)?.concat())
+    }
+
+    /// creates a proof of the node of a piece against the pieces root, verify it with `verify`
+    /// and the node as leaf, the proof of a file that isn't larger than a piece is empty
+    /// returns an error if the piece length is not a power of two of at least `BLOCK_SIZE` or the
+    /// piece holds no file data
+    pub fn create_piece_proof(
+        &self,
+        piece_length: u64,
+        piece: usize,
+    ) -> Result<Proof<Sha256>, MerkleTreeError> {
+        let piece_layer = self.piece_layer_index(piece_length)?;
+        let num_pieces = self.len.div_ceil(piece_length) as usize;
+        if piece >= num_pieces {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: piece,
+                num_leaves: num_pieces,
+            });
+        }
+        let mut offset = piece;
+        let mut siblings = Vec::with_capacity(piece_layer);
+        for layer in (1..=piece_layer).rev() {
+            siblings.push(self.tree.iter_layer(layer).as_slice()[offset ^ 1]);
+            offset /= 2;
+        }
+        Ok(Proof::new(piece, siblings))
+    }
+
+    /// creates a proof of the hash of a block against the pieces root
+    /// returns an error if the file has no such block
+    pub fn create_block_proof(&self, block: usize) -> Result<Proof<Sha256>, MerkleTreeError> {
+        if block >= self.num_blocks() {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: block,
+                num_leaves: self.num_blocks(),
+            });
+        }
+        self.tree.try_create_proof(block)
+    }
+
+    /// returns the layer of the tree holding the nodes of the pieces, the root layer if a piece
+    /// covers the whole tree
+    fn piece_layer_index(&self, piece_length: u64) -> Result<usize, MerkleTreeError> {
+        let blocks_per_piece = blocks_per_piece(piece_length)?;
+        let levels = blocks_per_piece.trailing_zeros() as usize;
+        Ok(self.tree.depth().saturating_sub(levels + 1))
+    }
+}
+
+/// returns the node of a piece in the piece layer given its data, the last piece of a file may be
+/// shorter than the piece length
+/// The tree of a file that isn't larger than a piece is only padded to the next power of two of its
+/// blocks, its data has to be checked against the pieces root instead.
+/// returns an error if the piece length is not a power of two of at least `BLOCK_SIZE` or the data
+/// is longer than a piece
+pub fn piece_hash(data: &[u8], piece_length: u64) -> Result<Output<Sha256>, MerkleTreeError> {
+    let blocks_per_piece = blocks_per_piece(piece_length)?;
+    if data.len() as u64 > piece_length {
+        return Err(MerkleTreeError::LeafIndexOutOfBounds {
+            offset: data.len().div_ceil(BLOCK_SIZE) - 1,
+            num_leaves: blocks_per_piece,
+        });
+    }
+    let mut blocks: Vec<_> = data.chunks(BLOCK_SIZE).map(Sha256::digest).collect();
+    blocks.resize(blocks_per_piece, Output::<Sha256>::default());
+    Ok(*MerkleTree::<Sha256>::from_leaves(&blocks, &Output::<Sha256>::default()).root_hash())
+}
+
+/// returns true if the piece layer of a file of the given length leads to the pieces root
+/// Files that aren't larger than a piece have no piece layer, for them only an empty layer is
+/// accepted and the pieces root has to be checked against the data itself.
+pub fn verify_piece_layer(
+    pieces_root: &Output<Sha256>,
+    file_len: u64,
+    piece_length: u64,
+    piece_layer: &[Output<Sha256>],
+) -> bool {
+    let Ok(pad) = piece_hash(&[], piece_length) else {
+        return false;
+    };
+    if file_len <= piece_length {
+        return piece_layer.is_empty();
+    }
+    if piece_layer.len() as u64 != file_len.div_ceil(piece_length) {
+        return false;
+    }
+    MerkleTree::<Sha256>::from_leaves(piece_layer, &pad).root_hash() == pieces_root
+}
+
+/// returns the number of blocks in a piece
+fn blocks_per_piece(piece_length: u64) -> Result<usize, MerkleTreeError> {
+    if !piece_length.is_power_of_two() || piece_length < BLOCK_SIZE as u64 {
+        return Err(MerkleTreeError::InvalidPieceLength { piece_length });
+    }
+    usize::try_from(piece_length / BLOCK_SIZE as u64)
+        .map_err(|_| MerkleTreeError::InvalidPieceLength { piece_length })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{verify, Plain};
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn node(left: &Output<Sha256>, right: &Output<Sha256>) -> Output<Sha256> {
+        <Plain as crate::HashScheme<Sha256>>::hash_node(left, right)
+    }
+
+    #[test]
+    fn test_pieces_root() {
+        assert_eq!(FileTree::from_bytes(&[]).pieces_root(), None);
+
+        let file = data(100);
+        assert_eq!(
+            FileTree::from_bytes(&file).pieces_root(),
+            Some(Sha256::digest(&file))
+        );
+
+        // three blocks, the last one short, padded with a zero hash
+        let file = data(2 * BLOCK_SIZE + 10);
+        let blocks: Vec<_> = file.chunks(BLOCK_SIZE).map(Sha256::digest).collect();
+        let expected = node(
+            &node(&blocks[0], &blocks[1]),
+            &node(&blocks[2], &Output::<Sha256>::default()),
+        );
+        let tree = FileTree::from_bytes(&file);
+        assert_eq!(tree.pieces_root(), Some(expected));
+        assert_eq!(tree.num_blocks(), 3);
+        assert_eq!(tree.len(), file.len() as u64);
+        let read = FileTree::from_reader(file.as_slice()).unwrap();
+        assert_eq!(read.pieces_root(), tree.pieces_root());
+        assert_eq!(read.len(), tree.len());
+
+        let proof = tree.create_block_proof(2).unwrap();
+        assert!(verify(&expected, &blocks[2], &proof));
+        assert!(tree.create_block_proof(3).is_err());
+    }
+
+    #[test]
+    fn test_piece_layer() {
+        let piece_length = 2 * BLOCK_SIZE as u64;
+        // five pieces, the last one with a single short block
+        let file = data(8 * BLOCK_SIZE + 5);
+        let tree = FileTree::from_bytes(&file);
+        let root = tree.pieces_root().unwrap();
+
+        let layer = tree.piece_layer(piece_length).unwrap();
+        assert_eq!(layer.len(), 5);
+        for (piece, hash) in file.chunks(piece_length as usize).zip(&layer) {
+            assert_eq!(&piece_hash(piece, piece_length).unwrap(), hash);
+        }
+        assert_eq!(
+            tree.piece_layer_bytes(piece_length).unwrap(),
+            layer.concat()
+        );
+        assert!(verify_piece_layer(
+            &root,
+            file.len() as u64,
+            piece_length,
+            &layer
+        ));
+        assert!(!verify_piece_layer(
+            &root,
+            file.len() as u64,
+            piece_length,
+            &layer[..4]
+        ));
+        let mut tampered = layer.clone();
+        tampered[4] = [1; 32].into();
+        assert!(!verify_piece_layer(
+            &root,
+            file.len() as u64,
+            piece_length,
+            &tampered
+        ));
+
+        for (piece, hash) in layer.iter().enumerate() {
+            let proof = tree.create_piece_proof(piece_length, piece).unwrap();
+            assert_eq!(proof.len(), 3);
+            assert!(verify(&root, hash, &proof));
+        }
+        assert!(tree.create_piece_proof(piece_length, 5).is_err());
+
+        // files not larger than a piece have no piece layer
+        let small = FileTree::from_bytes(&data(BLOCK_SIZE + 1));
+        assert_eq!(small.piece_layer(piece_length).unwrap(), Vec::new());
+        assert!(small
+            .create_piece_proof(piece_length, 0)
+            .unwrap()
+            .is_empty());
+        assert!(verify_piece_layer(
+            &small.pieces_root().unwrap(),
+            BLOCK_SIZE as u64 + 1,
+            piece_length,
+            &[]
+        ));
+
+        assert_eq!(
+            tree.piece_layer(3 * BLOCK_SIZE as u64),
+            Err(MerkleTreeError::InvalidPieceLength {
+                piece_length: 3 * BLOCK_SIZE as u64
+            })
+        );
+        assert!(tree.piece_layer(BLOCK_SIZE as u64 / 2).is_err());
+        assert!(piece_hash(&data(BLOCK_SIZE + 1), BLOCK_SIZE as u64).is_err());
+    }
+}
@@ -0,0 +1,305 @@
+//! Step-by-step configuration of a `MerkleTree`
+//!
+//! `MerkleTreeBuilder` collects the options of a tree, the depth, the initial value of the leaves,
+//! the leaves themselves, the hash scheme and where the nodes are stored, and checks them together
+//! in `build`. Every option has a default, so only the ones that differ need to be set:
+//!
+//! - without a depth the tree is just deep enough for its leaves like `MerkleTree::from_leaves`,
+//!   with a depth it has `2^(depth - 1)` leaves and the given leaves fill the first of them
+//! - the initial leaf, the value of all leaves not given and the padding of the tree, defaults to
+//!   the all-zero hash
+//! - the scheme is a type parameter and defaults to `Plain`, `scheme::<Rfc6962>()` switches it
+//! - the nodes are kept on the heap unless a file is given as `StorageBackend` (`mmap` feature)
+
+use alloc::vec::Vec;
+use core::fmt::{self, Debug};
+use core::marker::PhantomData;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError, Plain};
+
+/// Where the nodes of a built tree are stored
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// nodes in a heap allocation
+    #[default]
+    Memory,
+    /// nodes in a memory mapped file at the path, see `MerkleTree::create`
+    #[cfg(feature = "mmap")]
+    File(std::path::PathBuf),
+}
+
+/// The reason a tree couldn't be built
+#[derive(Debug)]
+pub enum BuildError {
+    /// the options are inconsistent or the nodes can't be allocated
+    Tree(MerkleTreeError),
+    /// the file backing the tree couldn't be created
+    #[cfg(feature = "mmap")]
+    Io(std::io::Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Tree(err) => write!(f, "{err}"),
+            #[cfg(feature = "mmap")]
+            BuildError::Io(err) => write!(f, "failed to create the tree file: {err}"),
+        }
+    }
+}
+
+impl core::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            BuildError::Tree(err) => Some(err),
+            #[cfg(feature = "mmap")]
+            BuildError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<MerkleTreeError> for BuildError {
+    fn from(err: MerkleTreeError) -> Self {
+        BuildError::Tree(err)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl From<std::io::Error> for BuildError {
+    fn from(err: std::io::Error) -> Self {
+        BuildError::Io(err)
+    }
+}
+
+/// Collects the options of a `MerkleTree`, see `MerkleTree::builder`
+pub struct MerkleTreeBuilder<D: Digest, S = Plain> {
+    depth: Option<usize>,
+    initial_leaf: Output<D>,
+    leaves: Vec<Output<D>>,
+    storage: StorageBackend,
+    scheme: PhantomData<fn() -> S>,
+}
+
+impl<D, S> Default for MerkleTreeBuilder<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D, S> MerkleTreeBuilder<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a builder with the default options, it builds an empty tree
+    pub fn new() -> Self {
+        Self {
+            depth: None,
+            initial_leaf: Output::<D>::default(),
+            leaves: Vec::new(),
+            storage: StorageBackend::Memory,
+            scheme: PhantomData,
+        }
+    }
+
+    /// sets the depth of the tree, a depth of 0 builds an empty tree
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// sets the value of the leaves that are not given and the padding used when the tree grows
+    pub fn initial_leaf(mut self, initial_leaf: &Output<D>) -> Self {
+        self.initial_leaf = *initial_leaf;
+        self
+    }
+
+    /// sets the values of the first leaves, the next pushed leaf is written behind them
+    pub fn leaves(mut self, leaves: &[Output<D>]) -> Self {
+        self.leaves = leaves.to_vec();
+        self
+    }
+
+    /// hashes raw leaf payloads with `hash_leaf` of the scheme and sets them as the first leaves
+    /// set the scheme first, the payloads are hashed right away
+    pub fn leaf_data<T: AsRef<[u8]>>(mut self, data: &[T]) -> Self {
+        self.leaves = data
+            .iter()
+            .map(|item| S::hash_leaf(item.as_ref()))
+            .collect();
+        self
+    }
+
+    /// switches the hash scheme of the tree, leaves given before are kept as they are
+    pub fn scheme<T: HashScheme<D>>(self) -> MerkleTreeBuilder<D, T> {
+        MerkleTreeBuilder {
+            depth: self.depth,
+            initial_leaf: self.initial_leaf,
+            leaves: self.leaves,
+            storage: self.storage,
+            scheme: PhantomData,
+        }
+    }
+
+    /// sets where the nodes of the tree are stored
+    pub fn storage(mut self, storage: StorageBackend) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// builds the tree
+    /// returns an error if the depth is larger than `MAX_DEPTH`, the leaves don't fit into a tree of
+    /// the depth, the nodes can't be allocated or the file backing the tree can't be created
+    pub fn build(self) -> Result<MerkleTree<D, S>, BuildError> {
+        let mut tree = match (&self.storage, self.depth) {
+            (StorageBackend::Memory, None) => {
+                return Ok(MerkleTree::from_leaves(&self.leaves, &self.initial_leaf));
+            }
+            (StorageBackend::Memory, Some(depth)) => {
+                MerkleTree::try_new(depth, &self.initial_leaf)?
+            }
+            #[cfg(feature = "mmap")]
+            (StorageBackend::File(path), depth) => {
+                let depth = depth.unwrap_or_else(|| Self::depth_for(self.leaves.len()));
+                MerkleTree::create(path, depth, &self.initial_leaf)?
+            }
+        };
+        tree.fill_leaves(&self.leaves)?;
+        Ok(tree)
+    }
+
+    /// returns the depth of the smallest tree holding the given number of leaves
+    #[cfg(feature = "mmap")]
+    fn depth_for(num_leaves: usize) -> usize {
+        match num_leaves {
+            0 => 0,
+            n => n.next_power_of_two().trailing_zeros() as usize + 1,
+        }
+    }
+}
+
+impl<D, S> MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// returns a builder for a tree with the scheme `S`, see `MerkleTreeBuilder`
+    pub fn builder() -> MerkleTreeBuilder<D, S> {
+        MerkleTreeBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    use crate::Rfc6962;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+
+    #[test]
+    fn test_builder() {
+        let leaves: Vec<Output<Sha3_256>> = (1..6).map(|i| [i as u8; 32].into()).collect();
+
+        let tree = MerkleTree::builder().build().unwrap();
+        assert!(tree.is_empty());
+
+        let tree = MerkleTree::builder()
+            .depth(4)
+            .initial_leaf(&[7; 32].into())
+            .build()
+            .unwrap();
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::new(4, &[7; 32].into()).root_hash()
+        );
+
+        let tree = MerkleTree::builder().leaves(&leaves).build().unwrap();
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::from_leaves(&leaves, &[0; 32].into()).root_hash()
+        );
+
+        // a given depth is filled with the leaves first and the initial leaf after them
+        let mut tree = MerkleTree::builder()
+            .depth(5)
+            .leaves(&leaves)
+            .initial_leaf(&[9; 32].into())
+            .build()
+            .unwrap();
+        let mut expected = MerkleTree::new(5, &[9; 32].into());
+        for (offset, leaf) in leaves.iter().enumerate() {
+            expected.set(offset, leaf);
+        }
+        assert_eq!(tree.root_hash(), expected.root_hash());
+        assert_eq!(tree.check_integrity(), Ok(()));
+        assert_eq!(tree.push(&[0xaa; 32].into()), 5);
+
+        let tree = crate::MerkleTree::<Sha3_256, Rfc6962>::builder()
+            .leaf_data(&[b"a", b"b", b"c"])
+            .build()
+            .unwrap();
+        let expected = MerkleTree::builder()
+            .scheme::<Rfc6962>()
+            .leaf_data(&[b"a", b"b", b"c"])
+            .build()
+            .unwrap();
+        assert_eq!(tree.root_hash(), expected.root_hash());
+        assert_eq!(
+            tree.root_hash(),
+            crate::MerkleTree::<Sha3_256, Rfc6962>::from_leaf_data(
+                &[b"a", b"b", b"c"],
+                &[0; 32].into()
+            )
+            .root_hash()
+        );
+    }
+
+    #[test]
+    fn test_builder_rejects_invalid_options() {
+        let leaves: Vec<Output<Sha3_256>> = (1..6).map(|i| [i as u8; 32].into()).collect();
+        assert!(matches!(
+            MerkleTree::builder().depth(3).leaves(&leaves).build(),
+            Err(BuildError::Tree(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: 4,
+                num_leaves: 4
+            }))
+        ));
+        assert!(matches!(
+            MerkleTree::builder().depth(crate::MAX_DEPTH + 1).build(),
+            Err(BuildError::Tree(MerkleTreeError::DepthOutOfRange { .. }))
+        ));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_builder_file_storage() {
+        let leaves: Vec<Output<Sha3_256>> = (1..6).map(|i| [i as u8; 32].into()).collect();
+        let path =
+            std::env::temp_dir().join(format!("merkle-tree-rs-{}-builder.mt", std::process::id()));
+        let tree = MerkleTree::builder()
+            .leaves(&leaves)
+            .storage(StorageBackend::File(path.clone()))
+            .build()
+            .unwrap();
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::from_leaves(&leaves, &[0; 32].into()).root_hash()
+        );
+        drop(tree);
+        let reopened = MerkleTree::open(&path).unwrap();
+        assert_eq!(reopened.next_offset(), 5);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+//! Cancellation and progress reporting for long-running operations
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::MerkleTreeError;
+
+/// number of nodes processed between two cancellation checks and progress reports
+pub(crate) const CHECK_INTERVAL: usize = 4096;
+
+/// A token to cancel a long-running operation, possibly from another thread
+/// Clones share the same state, cancelling one of them cancels all of them.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// creates a new token that is not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// requests cancellation of all operations using this token
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// returns true if cancellation was requested
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// returns an error if cancellation was requested
+    pub(crate) fn check(&self) -> Result<(), MerkleTreeError> {
+        if self.is_cancelled() {
+            return Err(MerkleTreeError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// Progress of a long-running operation, counted in processed nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// number of nodes processed so far
+    pub completed: usize,
+    /// total number of nodes the operation processes
+    pub total: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancellation_token() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!token.is_cancelled());
+        assert_eq!(token.check(), Ok(()));
+
+        clone.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(token.check(), Err(MerkleTreeError::Cancelled));
+    }
+}
@@ -0,0 +1,269 @@
+//! Concurrent leaf updates
+//!
+//! A `ConcurrentMerkleTree` splits a tree into `K` subtrees of equal size (`K` a power of two) like
+//! the sharded construction, each behind a lock of its own, and keeps the layers above them in a
+//! small top tree whose leaves are the subtree roots. `set` only takes `&self`: writers of leaves
+//! in different subtrees update their paths in parallel and only serialize on the few hashes of
+//! the top tree.
+//!
+//! A writer holds the lock of its subtree until the new subtree root is written to the top tree, so
+//! the top tree always holds the roots of the current subtrees. The root therefore only depends on
+//! the final value of every leaf, not on the order in which the writers were scheduled.
+
+use std::fmt::Debug;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError, Plain, Proof};
+
+/// A Merkle tree whose leaves can be updated from many threads at once
+pub struct ConcurrentMerkleTree<D: Digest, S = Plain> {
+    /// the subtrees holding the leaves, empty for a tree without leaves
+    shards: Vec<Mutex<MerkleTree<D, S>>>,
+    /// the layers above the subtrees with the subtree roots as leaves, the whole tree if it is empty
+    top: Mutex<MerkleTree<D, S>>,
+    /// the number of leaves of every subtree
+    shard_leaves: usize,
+    depth: usize,
+    padding: Output<D>,
+}
+
+impl<D, S> ConcurrentMerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// splits the tree into up to `shards` subtrees that can be updated concurrently
+    /// the number of shards is rounded up to the next power of two and limited to the number of
+    /// leaves
+    pub fn new(tree: MerkleTree<D, S>, shards: usize) -> Self {
+        let depth = tree.depth();
+        let padding = *tree.padding();
+        if depth == 0 {
+            return Self {
+                shards: Vec::new(),
+                top: Mutex::new(tree),
+                shard_leaves: 0,
+                depth,
+                padding,
+            };
+        }
+        // the subtree roots are on this layer
+        let shard_layer = shards.max(1).next_power_of_two().trailing_zeros() as usize;
+        let shard_layer = shard_layer.min(depth - 1);
+        let roots = tree.iter_layer(shard_layer).as_slice();
+        let top = MerkleTree::from_leaves(roots, &padding);
+        let shards = (0..roots.len())
+            .map(|shard| Mutex::new(tree.extract_subtree(shard_layer, shard)))
+            .collect();
+        Self {
+            shards,
+            top: Mutex::new(top),
+            shard_leaves: 1 << (depth - 1 - shard_layer),
+            depth,
+            padding,
+        }
+    }
+
+    /// returns the number of subtrees that can be updated concurrently
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// returns the depth of the tree, the number of layers including the root and the leaves
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// returns the number of leaves in the tree
+    pub fn num_leaves(&self) -> usize {
+        self.shards.len() * self.shard_leaves
+    }
+
+    /// returns the root hash of the tree including all completed updates
+    pub fn root_hash(&self) -> Output<D> {
+        *lock(&self.top).root_hash()
+    }
+
+    /// returns the value of a leaf node, None if the offset is out of bounds
+    pub fn get(&self, offset: usize) -> Option<Output<D>> {
+        let shard = self.shards.get(offset.checked_div(self.shard_leaves)?)?;
+        lock(shard).get(offset % self.shard_leaves).copied()
+    }
+
+    /// updates the value of a leaf node, waiting only for writers of the same subtree
+    /// panics if the offset is out of bounds, see `try_set` for a fallible version
+    pub fn set(&self, offset: usize, value: &Output<D>) {
+        if let Err(err) = self.try_set(offset, value) {
+            panic!("{err}");
+        }
+    }
+
+    /// updates the value of a leaf node, waiting only for writers of the same subtree
+    /// returns an error if the offset is out of bounds
+    pub fn try_set(&self, offset: usize, value: &Output<D>) -> Result<(), MerkleTreeError> {
+        let index = offset.checked_div(self.shard_leaves).unwrap_or(offset);
+        let shard = self
+            .shards
+            .get(index)
+            .ok_or(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.num_leaves(),
+            })?;
+        let mut shard = lock(shard);
+        shard.try_set(offset % self.shard_leaves, value)?;
+        // the subtree stays locked until its root is in the top tree
+        lock(&self.top).set(index, shard.root_hash());
+        Ok(())
+    }
+
+    /// returns the value of a leaf node and its proof against the current root
+    /// The subtree of the leaf stays locked while both are read, so the proof verifies the value
+    /// even while other threads update the tree.
+    /// panics if the offset is out of bounds, see `try_get_with_proof` for a fallible version
+    pub fn get_with_proof(&self, offset: usize) -> (Output<D>, Proof<D, S>) {
+        match self.try_get_with_proof(offset) {
+            Ok(leaf_and_proof) => leaf_and_proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// returns the value of a leaf node and its proof against the current root
+    /// returns an error if the offset is out of bounds
+    pub fn try_get_with_proof(
+        &self,
+        offset: usize,
+    ) -> Result<(Output<D>, Proof<D, S>), MerkleTreeError> {
+        let index = offset.checked_div(self.shard_leaves).unwrap_or(offset);
+        let shard = self
+            .shards
+            .get(index)
+            .ok_or(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.num_leaves(),
+            })?;
+        // locked in the order of the writers, subtree first
+        let shard = lock(shard);
+        let lower = shard.try_create_proof(offset % self.shard_leaves)?;
+        let leaf = shard.leaves().as_slice()[offset % self.shard_leaves];
+        let upper = lock(&self.top).try_create_proof(index)?;
+        let siblings = [lower.siblings(), upper.siblings()].concat();
+        Ok((leaf, Proof::new(offset, siblings)))
+    }
+
+    /// joins the subtrees back into one tree
+    pub fn into_inner(self) -> MerkleTree<D, S> {
+        if self.shards.is_empty() {
+            return self
+                .top
+                .into_inner()
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        let shards: Vec<_> = self
+            .shards
+            .into_iter()
+            .map(|shard| shard.into_inner().unwrap_or_else(PoisonError::into_inner))
+            .collect();
+        match MerkleTree::from_shards(&shards, &self.padding) {
+            Ok(tree) => tree,
+            Err(_) => unreachable!("the subtrees were split from one tree"),
+        }
+    }
+}
+
+/// locks a part of the tree, a writer that panicked left its part consistent at every step
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use sha3::Sha3_256;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type ConcurrentMerkleTree = super::ConcurrentMerkleTree<Sha3_256>;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_concurrent_set() {
+        assert_send_sync::<ConcurrentMerkleTree>();
+
+        let leaves: Vec<Output<Sha3_256>> = (0..200).map(|i| [i as u8; 32].into()).collect();
+        let tree = MerkleTree::from_leaves(&leaves, &[0; 32].into());
+        let mut expected = MerkleTree::from_leaves(&leaves, &[0; 32].into());
+        let tree = ConcurrentMerkleTree::new(tree, 6);
+        assert_eq!(tree.shards(), 8);
+        assert_eq!(tree.num_leaves(), 256);
+        assert_eq!(tree.root_hash(), *expected.root_hash());
+
+        // four writers with disjoint leaf ranges that cross the subtree boundaries
+        thread::scope(|scope| {
+            for writer in 0..4u8 {
+                let tree = &tree;
+                scope.spawn(move || {
+                    for offset in (writer as usize * 64 + 10)..(writer as usize * 64 + 60) {
+                        tree.set(offset, &[writer ^ 0xf0; 32].into());
+                    }
+                });
+            }
+        });
+        for writer in 0..4u8 {
+            for offset in (writer as usize * 64 + 10)..(writer as usize * 64 + 60) {
+                expected.set(offset, &[writer ^ 0xf0; 32].into());
+            }
+        }
+        assert_eq!(tree.root_hash(), *expected.root_hash());
+        assert_eq!(tree.get(80), Some([0xf1; 32].into()));
+        assert_eq!(tree.get(256), None);
+        assert_eq!(
+            tree.try_set(256, &[0; 32].into()),
+            Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: 256,
+                num_leaves: 256
+            })
+        );
+
+        for offset in [0, 80, 255] {
+            let (leaf, proof) = tree.get_with_proof(offset);
+            assert_eq!(proof, expected.create_proof(offset));
+            assert!(crate::verify(&tree.root_hash(), &leaf, &proof));
+        }
+        assert!(tree.try_get_with_proof(256).is_err());
+
+        let joined = tree.into_inner();
+        assert_eq!(joined.root_hash(), expected.root_hash());
+        assert_eq!(joined.next_offset(), 200);
+        assert_eq!(joined.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_small_trees() {
+        // a single leaf is a single subtree and the root at once
+        let tree = ConcurrentMerkleTree::new(
+            MerkleTree::from_leaves(&[[1; 32].into()], &[0; 32].into()),
+            4,
+        );
+        assert_eq!(tree.shards(), 1);
+        tree.set(0, &[2; 32].into());
+        assert_eq!(tree.root_hash(), [2; 32].into());
+        assert_eq!(
+            tree.get_with_proof(0),
+            ([2; 32].into(), crate::Proof::new(0, Vec::new()))
+        );
+        assert_eq!(tree.into_inner().get(0), Some(&[2; 32].into()));
+
+        let tree = ConcurrentMerkleTree::new(MerkleTree::empty(&[0; 32].into()), 4);
+        assert_eq!(tree.shards(), 0);
+        assert_eq!(tree.get(0), None);
+        assert!(tree.try_set(0, &[1; 32].into()).is_err());
+        assert!(tree.try_get_with_proof(0).is_err());
+        assert!(tree.into_inner().is_empty());
+    }
+}
@@ -0,0 +1,688 @@
+//! Merkle counting trees committing to the number of non-default leaves
+//!
+//! Every intermediate node stores the number of leaves below it that differ from the default value
+//! and this count is part of its hash: `H(left || right || count)` with the count as little endian
+//! u64. The root therefore commits to the exact number of entries, and every inclusion proof also
+//! proves the total count and the rank of the leaf, i.e. the number of entries in front of it.
+//! A `RankProof` turns this into statements like "leaf X is the k-th entry", which prove the order
+//! of paginated results, and a `CompletenessProof` proves that a query result holds all entries of
+//! an index range.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::ops::Range;
+
+use digest::{Digest, Output};
+
+use crate::{MerkleTree, MerkleTreeError, MAX_DEPTH};
+
+/// A binary Merkle tree whose nodes commit to the number of non-default leaves below them
+#[derive(Debug, Clone)]
+pub struct CountingTree<D: Digest> {
+    /// depth of the tree
+    depth: usize,
+    /// hashes of the nodes in breadth-first order
+    nodes: Vec<Output<D>>,
+    /// number of non-default leaves below every node in breadth-first order
+    counts: Vec<u64>,
+    /// value of leaves that are not counted
+    default: Output<D>,
+}
+
+/// A proof for the inclusion of a single leaf in a `CountingTree`
+/// Besides the sibling hashes it holds the counts of the siblings, which prove the total count and
+/// the rank of the leaf.
+#[derive(Debug, Clone)]
+pub struct CountingProof<D: Digest> {
+    /// offset of the proven leaf
+    leaf_index: usize,
+    /// sibling hashes and counts starting at the leaf layer
+    siblings: Vec<(Output<D>, u64)>,
+}
+
+impl<D: Digest> PartialEq for CountingProof<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf_index == other.leaf_index && self.siblings == other.siblings
+    }
+}
+
+impl<D: Digest> Eq for CountingProof<D> {}
+
+/// A proof that a non-default leaf is the entry with the given rank, counted from 0
+/// It proves both "leaf X is the k-th entry" and "the k-th entry is leaf X".
+#[derive(Debug, Clone)]
+pub struct RankProof<D: Digest> {
+    /// value of the proven leaf
+    leaf: Output<D>,
+    /// proof of the leaf, its left siblings add up to the rank
+    proof: CountingProof<D>,
+}
+
+impl<D: Digest> PartialEq for RankProof<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf == other.leaf && self.proof == other.proof
+    }
+}
+
+impl<D: Digest> Eq for RankProof<D> {}
+
+/// A proof that a list of entries holds exactly the non-default leaves of an index range
+/// The proofs of the first leaf of the range and of the first leaf behind it bound the number of
+/// entries in the range by their ranks, the entries prove consecutive ranks in between.
+#[derive(Debug, Clone)]
+pub struct CompletenessProof<D: Digest> {
+    /// value and proof of the first leaf of the range
+    start: (Output<D>, CountingProof<D>),
+    /// value and proof of the first leaf behind the range, None if the range ends with the tree
+    end: Option<(Output<D>, CountingProof<D>)>,
+    /// values and proofs of all non-default leaves in the range
+    entries: Vec<(Output<D>, CountingProof<D>)>,
+}
+
+impl<D: Digest> PartialEq for CompletenessProof<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.start == other.start && self.end == other.end && self.entries == other.entries
+    }
+}
+
+impl<D: Digest> Eq for CompletenessProof<D> {}
+
+/// returns the hash of an intermediate node
+fn hash_counted<D: Digest>(left: &Output<D>, right: &Output<D>, count: u64) -> Output<D> {
+    let mut hasher = D::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.update(count.to_le_bytes());
+    hasher.finalize()
+}
+
+impl<D> CountingProof<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// creates a proof from the offset of the proven leaf and the sibling hashes and counts starting
+    /// at the leaf layer
+    pub fn new(leaf_index: usize, siblings: Vec<(Output<D>, u64)>) -> Self {
+        Self {
+            leaf_index,
+            siblings,
+        }
+    }
+
+    /// returns the offset of the proven leaf
+    pub fn leaf_index(&self) -> usize {
+        self.leaf_index
+    }
+
+    /// returns the sibling hashes and counts starting at the leaf layer
+    pub fn siblings(&self) -> &[(Output<D>, u64)] {
+        &self.siblings
+    }
+
+    /// returns the number of non-default leaves in front of the proven leaf
+    /// this is only meaningful once the proof was verified against a trusted root
+    pub fn rank(&self) -> u64 {
+        self.siblings
+            .iter()
+            .enumerate()
+            .filter(|(layer, _)| self.leaf_index >> layer & 1 == 1)
+            .map(|(_, (_, count))| count)
+            .sum()
+    }
+
+    /// computes the root hash and the total count from the leaf value and the default value of the
+    /// tree
+    /// returns None if the counts overflow
+    pub fn compute_root(&self, leaf: &Output<D>, default: &Output<D>) -> Option<(Output<D>, u64)> {
+        let mut hash = *leaf;
+        let mut count = u64::from(leaf != default);
+        for (layer, (sibling, sibling_count)) in self.siblings.iter().enumerate() {
+            count = count.checked_add(*sibling_count)?;
+            hash = if self.leaf_index >> layer & 1 == 0 {
+                hash_counted::<D>(&hash, sibling, count)
+            } else {
+                hash_counted::<D>(sibling, &hash, count)
+            };
+        }
+        Some((hash, count))
+    }
+
+    /// verifies the proof against a trusted root of a tree with the given depth
+    /// returns the total count committed to by the root, or None if the proof is invalid
+    pub fn verify(
+        &self,
+        root: &Output<D>,
+        depth: usize,
+        leaf: &Output<D>,
+        default: &Output<D>,
+    ) -> Option<u64> {
+        if !(1..=MAX_DEPTH).contains(&depth)
+            || self.siblings.len() + 1 != depth
+            || self.leaf_index >> self.siblings.len() != 0
+        {
+            return None;
+        }
+        let (hash, count) = self.compute_root(leaf, default)?;
+        (hash == *root).then_some(count)
+    }
+}
+
+impl<D> RankProof<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// creates a rank proof from the value of the leaf and its proof
+    pub fn new(leaf: Output<D>, proof: CountingProof<D>) -> Self {
+        Self { leaf, proof }
+    }
+
+    /// returns the value of the proven leaf
+    pub fn leaf(&self) -> &Output<D> {
+        &self.leaf
+    }
+
+    /// returns the offset of the proven leaf
+    pub fn leaf_index(&self) -> usize {
+        self.proof.leaf_index()
+    }
+
+    /// returns the claimed rank of the leaf
+    pub fn rank(&self) -> u64 {
+        self.proof.rank()
+    }
+
+    /// returns the inclusion proof of the leaf
+    pub fn proof(&self) -> &CountingProof<D> {
+        &self.proof
+    }
+
+    /// verifies that the leaf is the entry with the given rank in the tree with the given root and
+    /// depth
+    pub fn verify(&self, root: &Output<D>, depth: usize, default: &Output<D>, rank: u64) -> bool {
+        self.leaf != *default
+            && self.rank() == rank
+            && self
+                .proof
+                .verify(root, depth, &self.leaf, default)
+                .is_some()
+    }
+}
+
+impl<D> CompletenessProof<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// returns the offsets and values of the entries in the range
+    pub fn entries(&self) -> Vec<(usize, Output<D>)> {
+        self.entries
+            .iter()
+            .map(|(leaf, proof)| (proof.leaf_index(), *leaf))
+            .collect()
+    }
+
+    /// verifies that the entries are all non-default leaves of the given range in the tree with the
+    /// given root and depth
+    pub fn verify(
+        &self,
+        root: &Output<D>,
+        depth: usize,
+        default: &Output<D>,
+        range: Range<usize>,
+    ) -> bool {
+        let (start_leaf, start_proof) = &self.start;
+        if start_proof.leaf_index() != range.start || range.start > range.end {
+            return false;
+        }
+        // the start proof is checked against the depth first, so the shift doesn't overflow
+        let Some(total) = start_proof.verify(root, depth, start_leaf, default) else {
+            return false;
+        };
+        let first_rank = start_proof.rank();
+        let end_rank = match &self.end {
+            None if range.end == 1 << (depth - 1) => total,
+            Some((leaf, proof))
+                if proof.leaf_index() == range.end
+                    && proof.verify(root, depth, leaf, default).is_some() =>
+            {
+                proof.rank()
+            }
+            _ => return false,
+        };
+        if end_rank.checked_sub(first_rank) != Some(self.entries.len() as u64) {
+            return false;
+        }
+        self.entries
+            .iter()
+            .zip(first_rank..)
+            .all(|((leaf, proof), rank)| {
+                range.contains(&proof.leaf_index())
+                    && RankProof::new(*leaf, proof.clone()).verify(root, depth, default, rank)
+            })
+    }
+}
+
+impl<D> CountingTree<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// creates a new tree of the given depth with all leaves set to the default value
+    /// panics if the depth is not between 1 and `MAX_DEPTH`, see `try_new` for a fallible version
+    pub fn new(depth: usize, default: &Output<D>) -> Self {
+        match Self::try_new(depth, default) {
+            Ok(tree) => tree,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a new tree of the given depth with all leaves set to the default value
+    /// returns an error if the depth is not between 1 and `MAX_DEPTH`
+    pub fn try_new(depth: usize, default: &Output<D>) -> Result<Self, MerkleTreeError> {
+        if !(1..=MAX_DEPTH).contains(&depth) {
+            return Err(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            });
+        }
+        Ok(Self::from_leaves_with_depth(&[], depth, default))
+    }
+
+    /// creates a new tree from the given leaves, padded with the default value to the next power of
+    /// two
+    pub fn from_leaves(leaves: &[Output<D>], default: &Output<D>) -> Self {
+        let depth = leaves.len().max(1).next_power_of_two().trailing_zeros() as usize + 1;
+        Self::from_leaves_with_depth(leaves, depth, default)
+    }
+
+    /// creates a tree of the given depth holding the given leaves, building all layers bottom-up
+    fn from_leaves_with_depth(leaves: &[Output<D>], depth: usize, default: &Output<D>) -> Self {
+        let total = MerkleTree::<D>::index(depth, 0);
+        let first_leaf = MerkleTree::<D>::index(depth - 1, 0);
+        let mut nodes = vec![*default; total];
+        let mut counts = vec![0; total];
+        for (offset, leaf) in leaves.iter().enumerate() {
+            nodes[first_leaf + offset] = *leaf;
+            counts[first_leaf + offset] = u64::from(leaf != default);
+        }
+        let mut tree = Self {
+            depth,
+            nodes,
+            counts,
+            default: *default,
+        };
+        for index in (0..first_leaf).rev() {
+            tree.update_node(index);
+        }
+        tree
+    }
+
+    /// returns the root hash of the tree
+    pub fn root_hash(&self) -> &Output<D> {
+        &self.nodes[0]
+    }
+
+    /// returns the number of non-default leaves
+    pub fn count(&self) -> u64 {
+        self.counts[0]
+    }
+
+    /// returns the depth of the tree
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// returns the number of leaves in the tree
+    pub fn num_leaves(&self) -> usize {
+        1 << (self.depth - 1)
+    }
+
+    /// returns the value of leaves that are not counted
+    pub fn default_value(&self) -> &Output<D> {
+        &self.default
+    }
+
+    /// returns the value of the leaf at the given offset
+    pub fn leaf(&self, offset: usize) -> Option<&Output<D>> {
+        self.check_offset(offset).ok()?;
+        Some(&self.nodes[MerkleTree::<D>::index(self.depth - 1, offset)])
+    }
+
+    /// updates the value of a leaf
+    /// panics if the offset is out of bounds, see `try_set` for a fallible version
+    pub fn set(&mut self, offset: usize, value: &Output<D>) {
+        if let Err(err) = self.try_set(offset, value) {
+            panic!("{err}");
+        }
+    }
+
+    /// updates the value of a leaf
+    /// returns an error if the offset is out of bounds
+    pub fn try_set(&mut self, offset: usize, value: &Output<D>) -> Result<(), MerkleTreeError> {
+        self.check_offset(offset)?;
+        let mut index = MerkleTree::<D>::index(self.depth - 1, offset);
+        self.nodes[index] = *value;
+        self.counts[index] = u64::from(*value != self.default);
+        while index > 0 {
+            index = (index - 1) / 2;
+            self.update_node(index);
+        }
+        Ok(())
+    }
+
+    /// creates a proof for the leaf at the given offset
+    /// panics if the offset is out of bounds, see `try_create_proof` for a fallible version
+    pub fn create_proof(&self, offset: usize) -> CountingProof<D> {
+        match self.try_create_proof(offset) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a proof for the leaf at the given offset
+    /// returns an error if the offset is out of bounds
+    pub fn try_create_proof(&self, offset: usize) -> Result<CountingProof<D>, MerkleTreeError> {
+        self.check_offset(offset)?;
+        let siblings = MerkleTree::<D>::sibling_indices(self.depth, offset)
+            .into_iter()
+            .map(|(index, _)| (self.nodes[index], self.counts[index]))
+            .collect();
+        Ok(CountingProof::new(offset, siblings))
+    }
+
+    /// returns the number of non-default leaves in front of the given offset
+    /// returns None if the offset is out of bounds
+    pub fn rank(&self, offset: usize) -> Option<u64> {
+        self.check_offset(offset).ok()?;
+        let rank = MerkleTree::<D>::sibling_indices(self.depth, offset)
+            .into_iter()
+            .filter(|(_, is_left)| !is_left)
+            .map(|(index, _)| self.counts[index])
+            .sum();
+        Some(rank)
+    }
+
+    /// returns the offset of the non-default leaf with the given rank, counted from 0
+    /// returns None if the tree has no more than `rank` non-default leaves
+    pub fn select(&self, rank: u64) -> Option<usize> {
+        if rank >= self.count() {
+            return None;
+        }
+        // descend into the child holding the entry, skipping the entries of left children
+        let mut rank = rank;
+        let mut index = 0;
+        for _ in 1..self.depth {
+            let left = 2 * index + 1;
+            if rank < self.counts[left] {
+                index = left;
+            } else {
+                rank -= self.counts[left];
+                index = left + 1;
+            }
+        }
+        Some(index - MerkleTree::<D>::index(self.depth - 1, 0))
+    }
+
+    /// proves that the non-default leaf at the given offset is the entry with its rank
+    /// returns None if the offset is out of bounds or the leaf has the default value
+    pub fn create_rank_proof(&self, offset: usize) -> Option<RankProof<D>> {
+        let leaf = *self.leaf(offset)?;
+        if leaf == self.default {
+            return None;
+        }
+        Some(RankProof::new(leaf, self.try_create_proof(offset).ok()?))
+    }
+
+    /// proves which leaf is the entry with the given rank, counted from 0
+    /// returns None if the tree has no more than `rank` non-default leaves
+    pub fn create_select_proof(&self, rank: u64) -> Option<RankProof<D>> {
+        self.create_rank_proof(self.select(rank)?)
+    }
+
+    /// proves that the returned entries are all non-default leaves in the given range
+    /// returns an error if the range is not within the tree or its start is behind its end
+    pub fn create_completeness_proof(
+        &self,
+        range: Range<usize>,
+    ) -> Result<CompletenessProof<D>, MerkleTreeError> {
+        self.check_offset(range.start)?;
+        if range.end < range.start || range.end > self.num_leaves() {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: range.end,
+                num_leaves: self.num_leaves(),
+            });
+        }
+        let bound = |offset: usize| -> Result<_, MerkleTreeError> {
+            Ok((
+                self.nodes[MerkleTree::<D>::index(self.depth - 1, offset)],
+                self.try_create_proof(offset)?,
+            ))
+        };
+        let start = bound(range.start)?;
+        let end = match range.end < self.num_leaves() {
+            true => Some(bound(range.end)?),
+            false => None,
+        };
+        let first_rank = start.1.rank();
+        let end_rank = end.as_ref().map_or(self.count(), |(_, proof)| proof.rank());
+        let entries = (first_rank..end_rank)
+            .filter_map(|rank| self.select(rank))
+            .map(bound)
+            .collect::<Result<_, _>>()?;
+        Ok(CompletenessProof {
+            start,
+            end,
+            entries,
+        })
+    }
+
+    /// recomputes the hash and count of an intermediate node from its children
+    fn update_node(&mut self, index: usize) {
+        let (left, right) = (2 * index + 1, 2 * index + 2);
+        let count = self.counts[left] + self.counts[right];
+        self.counts[index] = count;
+        self.nodes[index] = hash_counted::<D>(&self.nodes[left], &self.nodes[right], count);
+    }
+
+    fn check_offset(&self, offset: usize) -> Result<(), MerkleTreeError> {
+        if offset >= self.num_leaves() {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.num_leaves(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ops::Range;
+
+    use sha3::{digest::Output, Sha3_256};
+
+    type CountingTree = super::CountingTree<Sha3_256>;
+    type CountingProof = super::CountingProof<Sha3_256>;
+    type RankProof = super::RankProof<Sha3_256>;
+
+    fn leaf(i: u8) -> Output<Sha3_256> {
+        [i; 32].into()
+    }
+
+    #[test]
+    fn test_count() {
+        let default = leaf(0);
+        let mut tree = CountingTree::new(4, &default);
+        assert_eq!(tree.count(), 0);
+        let empty_root = *tree.root_hash();
+
+        tree.set(3, &leaf(3));
+        tree.set(5, &leaf(5));
+        tree.set(5, &leaf(6));
+        assert_eq!(tree.count(), 2);
+        let root = *tree.root_hash();
+
+        // writing the default value to an unused leaf changes nothing
+        tree.set(7, &default);
+        assert_eq!(tree.root_hash(), &root);
+        tree.set(3, &default);
+        tree.set(5, &default);
+        assert_eq!(tree.count(), 0);
+        assert_eq!(tree.root_hash(), &empty_root);
+
+        let leaves: Vec<_> = (1..=5).map(leaf).collect();
+        let tree = CountingTree::from_leaves(&leaves, &default);
+        assert_eq!(tree.count(), 5);
+        assert_eq!(tree.num_leaves(), 8);
+        let mut reference = CountingTree::new(4, &default);
+        for (offset, leaf) in leaves.iter().enumerate() {
+            reference.set(offset, leaf);
+        }
+        assert_eq!(tree.root_hash(), reference.root_hash());
+        assert!(reference.try_set(8, &default).is_err());
+    }
+
+    #[test]
+    fn test_proof() {
+        let default = leaf(0);
+        let mut tree = CountingTree::new(4, &default);
+        for offset in [1, 2, 4, 6] {
+            tree.set(offset, &leaf(offset as u8));
+        }
+        let root = *tree.root_hash();
+
+        for offset in 0..8 {
+            let value = *tree.leaf(offset).unwrap();
+            let proof = tree.create_proof(offset);
+            assert_eq!(proof.verify(&root, 4, &value, &default), Some(4));
+            let expected_rank = [1, 2, 4, 6].iter().filter(|&&o| o < offset).count();
+            assert_eq!(proof.rank(), expected_rank as u64);
+        }
+
+        // a wrong leaf or a forged count doesn't verify
+        let proof = tree.create_proof(4);
+        assert_eq!(proof.verify(&root, 4, &leaf(9), &default), None);
+        assert_eq!(proof.verify(&root, 4, &default, &default), None);
+        let mut siblings = proof.siblings().to_vec();
+        siblings[1].1 += 1;
+        let forged = CountingProof::new(4, siblings);
+        assert_eq!(forged.verify(&root, 4, &leaf(4), &default), None);
+        assert_eq!(
+            CountingProof::new(8, proof.siblings().to_vec()).verify(&root, 4, &leaf(4), &default),
+            None
+        );
+        assert_eq!(proof.verify(&root, 5, &leaf(4), &default), None);
+        // a proof of a subtree doesn't verify against the root of the subtree with the tree depth
+        let shortened = CountingProof::new(0, proof.siblings()[..2].to_vec());
+        let (subtree_root, _) = shortened.compute_root(&leaf(4), &default).unwrap();
+        assert_eq!(
+            shortened.verify(&subtree_root, 3, &leaf(4), &default),
+            Some(2)
+        );
+        assert_eq!(shortened.verify(&subtree_root, 4, &leaf(4), &default), None);
+    }
+
+    #[test]
+    fn test_rank_and_select() {
+        let default = leaf(0);
+        let mut tree = CountingTree::new(5, &default);
+        let entries = [2, 3, 7, 8, 13, 15];
+        for offset in entries {
+            tree.set(offset, &leaf(offset as u8));
+        }
+        let root = *tree.root_hash();
+
+        for (rank, offset) in entries.iter().enumerate() {
+            assert_eq!(tree.select(rank as u64), Some(*offset));
+            assert_eq!(tree.rank(*offset), Some(rank as u64));
+
+            let proof = tree.create_select_proof(rank as u64).unwrap();
+            assert_eq!(proof.leaf_index(), *offset);
+            assert_eq!(proof.leaf(), &leaf(*offset as u8));
+            assert!(proof.verify(&root, 5, &default, rank as u64));
+            assert!(!proof.verify(&root, 5, &default, rank as u64 + 1));
+            assert_eq!(tree.create_rank_proof(*offset), Some(proof));
+        }
+        assert_eq!(tree.select(6), None);
+        assert_eq!(tree.rank(4), Some(2));
+        assert_eq!(tree.rank(16), None);
+
+        // default leaves are no entries
+        assert_eq!(tree.create_rank_proof(4), None);
+        let proof = tree.create_proof(4);
+        assert!(!RankProof::new(default, proof).verify(&root, 5, &default, 2));
+
+        // a proof of another tree doesn't verify
+        let proof = tree.create_select_proof(3).unwrap();
+        tree.set(0, &leaf(1));
+        assert!(!proof.verify(tree.root_hash(), 5, &default, 3));
+        assert!(tree
+            .create_select_proof(4)
+            .unwrap()
+            .verify(tree.root_hash(), 5, &default, 4));
+    }
+
+    #[test]
+    fn test_completeness_proof() {
+        let default = leaf(0);
+        let mut tree = CountingTree::new(5, &default);
+        for offset in [2, 3, 7, 8, 13, 15] {
+            tree.set(offset, &leaf(offset as u8));
+        }
+        let root = *tree.root_hash();
+
+        for (range, expected) in [
+            (3..9, vec![3, 7, 8]),
+            (0..16, vec![2, 3, 7, 8, 13, 15]),
+            (9..13, vec![]),
+            (15..16, vec![15]),
+            (4..4, vec![]),
+        ] {
+            let proof = tree.create_completeness_proof(range.clone()).unwrap();
+            let offsets: Vec<usize> = proof.entries().iter().map(|(o, _)| *o).collect();
+            assert_eq!(offsets, expected);
+            assert!(proof.verify(&root, 5, &default, range.clone()));
+            // the proof doesn't cover a wider range
+            assert!(!proof.verify(&root, 5, &default, range.start..range.end + 1));
+        }
+
+        // omitting an entry is detected
+        let mut proof = tree.create_completeness_proof(3..9).unwrap();
+        proof.entries.remove(1);
+        assert!(!proof.verify(&root, 5, &default, 3..9));
+        // as well as replacing it by an entry outside of the range
+        proof.entries.insert(1, proof.end.clone().unwrap());
+        assert!(!proof.verify(&root, 5, &default, 3..9));
+
+        // a proof of another depth doesn't verify, even if it matches the root
+        let proof = tree.create_completeness_proof(3..9).unwrap();
+        assert!(!proof.verify(&root, 4, &default, 3..9));
+        assert!(!proof.verify(&root, 6, &default, 3..9));
+        let (start_leaf, start_proof) = proof.start.clone();
+        let shortened = CountingProof::new(
+            start_proof.leaf_index(),
+            start_proof.siblings()[..3].to_vec(),
+        );
+        let (subtree_root, _) = shortened.compute_root(&start_leaf, &default).unwrap();
+        let mut tampered = proof.clone();
+        tampered.start = (start_leaf, shortened);
+        assert!(!tampered.verify(&subtree_root, 5, &default, 3..9));
+        let mut tampered = proof;
+        let (end_leaf, end_proof) = tampered.end.clone().unwrap();
+        let extended = CountingProof::new(
+            end_proof.leaf_index(),
+            [end_proof.siblings(), &[(leaf(1), 0)]].concat(),
+        );
+        tampered.end = Some((end_leaf, extended));
+        assert!(!tampered.verify(&root, 5, &default, 3..9));
+
+        assert!(tree.create_completeness_proof(16..16).is_err());
+        assert!(tree.create_completeness_proof(5..17).is_err());
+        assert!(tree
+            .create_completeness_proof(Range { start: 5, end: 4 })
+            .is_err());
+    }
+}
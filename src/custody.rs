@@ -0,0 +1,671 @@
+//! Chain-of-custody evidence for entries of a log
+//!
+//! A `CustodyLog` keeps the payloads and salts of its entries next to a `LogTree` and publishes
+//! signed tree heads (STHs). `export_evidence` bundles everything an external auditor needs to
+//! check a single entry into one `EvidenceBundle`, which serializes to a self-contained file.
+
+use std::fmt::{self, Debug};
+use std::io::{self, Read};
+use std::marker::PhantomData;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use digest::{Digest, Output};
+
+use crate::scheme::{HashScheme, Rfc6962};
+use crate::{ConsistencyProof, InclusionProof, LogTree};
+
+/// magic bytes at the start of a serialized evidence bundle
+const EVIDENCE_MAGIC: &[u8; 4] = b"MTEV";
+/// version of the serialization format
+const FORMAT_VERSION: u8 = 1;
+/// domain separation prefix of signed tree head messages
+const STH_PREFIX: &[u8] = b"merkle-tree-rs/sth/v1";
+/// domain separation prefix of parameter fingerprints
+const FINGERPRINT_PREFIX: &[u8] = b"merkle-tree-rs/params/v1";
+
+/// Signs tree heads
+pub trait SthSigner {
+    /// returns the signature of a message
+    fn sign(&self, message: &[u8]) -> Vec<u8>;
+
+    /// returns the signature of a message, or the error of a signer that can fail like an HSM
+    /// the default calls `sign`, signers that can fail override it
+    fn try_sign(&self, message: &[u8]) -> Result<Vec<u8>, SignError> {
+        Ok(self.sign(message))
+    }
+}
+
+/// The error of a signer that couldn't sign a tree head
+#[derive(Debug)]
+pub struct SignError(Box<dyn std::error::Error + Send + Sync>);
+
+impl SignError {
+    /// wraps the error of the signer
+    pub fn new(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        Self(err.into())
+    }
+}
+
+impl fmt::Display for SignError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "signing the tree head failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for SignError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.0)
+    }
+}
+
+/// Verifies signatures of tree heads
+pub trait SthVerifier {
+    /// returns true if the signature of the message is valid
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// The reason an evidence bundle failed verification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvidenceError {
+    /// the bundle was created with another digest or hash scheme
+    FingerprintMismatch,
+    /// the leaf is not included in the signed tree head's root
+    InvalidInclusionProof,
+    /// a signed tree head has an invalid signature
+    InvalidSignature,
+    /// the signed tree head is not a prefix of the latest tree head
+    InvalidConsistencyProof,
+}
+
+impl fmt::Display for EvidenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvidenceError::FingerprintMismatch => {
+                write!(f, "evidence was created with different tree parameters")
+            }
+            EvidenceError::InvalidInclusionProof => {
+                write!(f, "leaf is not included in the signed tree head")
+            }
+            EvidenceError::InvalidSignature => write!(f, "invalid tree head signature"),
+            EvidenceError::InvalidConsistencyProof => {
+                write!(
+                    f,
+                    "signed tree head is not consistent with the latest tree head"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvidenceError {}
+
+/// A signed tree head
+#[derive(Debug, Clone)]
+pub struct SignedTreeHead<D: Digest> {
+    /// number of leaves covered by the root
+    pub tree_size: u64,
+    /// time the head was published at in milliseconds since the Unix epoch, as in RFC 6962
+    pub timestamp: u64,
+    /// root hash of the tree at `tree_size`
+    pub root: Output<D>,
+    /// signature over `message()`
+    pub signature: Vec<u8>,
+}
+
+impl<D: Digest> PartialEq for SignedTreeHead<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.tree_size == other.tree_size
+            && self.timestamp == other.timestamp
+            && self.root == other.root
+            && self.signature == other.signature
+    }
+}
+
+impl<D: Digest> Eq for SignedTreeHead<D> {}
+
+impl<D: Digest> SignedTreeHead<D> {
+    /// returns the signed message, the tree size, timestamp and root with a domain separation
+    /// prefix
+    pub fn message(&self) -> Vec<u8> {
+        sth_message::<D>(self.tree_size, self.timestamp, &self.root)
+    }
+
+    /// returns the time the head was published at
+    pub fn published_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.timestamp)
+    }
+
+    /// returns true if the signature is valid
+    pub fn verify(&self, verifier: &impl SthVerifier) -> bool {
+        verifier.verify(&self.message(), &self.signature)
+    }
+}
+
+fn sth_message<D: Digest>(tree_size: u64, timestamp: u64, root: &Output<D>) -> Vec<u8> {
+    let mut message = Vec::with_capacity(STH_PREFIX.len() + 16 + root.len());
+    message.extend_from_slice(STH_PREFIX);
+    message.extend_from_slice(&tree_size.to_le_bytes());
+    message.extend_from_slice(&timestamp.to_le_bytes());
+    message.extend_from_slice(root);
+    message
+}
+
+/// returns a fingerprint of the digest and hash scheme used by a tree
+/// The fingerprint commits to the scheme name and to hashes of fixed inputs, so it changes
+/// whenever the digest or the way leaves and nodes are hashed changes.
+pub fn parameter_fingerprint<D: Digest, S: HashScheme<D>>() -> Output<D> {
+    let leaf = S::hash_leaf(FINGERPRINT_PREFIX);
+    let mut hasher = D::new();
+    hasher.update(FINGERPRINT_PREFIX);
+    hasher.update((S::NAME.len() as u64).to_le_bytes());
+    hasher.update(S::NAME);
+    hasher.update((<D as Digest>::output_size() as u64).to_le_bytes());
+    hasher.update(S::empty_root());
+    hasher.update(S::hash_node(&leaf, &leaf));
+    hasher.finalize()
+}
+
+/// Everything an auditor needs to verify a single log entry
+#[derive(Debug, Clone)]
+pub struct EvidenceBundle<D: Digest, S = Rfc6962> {
+    /// payload of the entry
+    pub payload: Vec<u8>,
+    /// salt hashed in front of the payload
+    pub salt: Vec<u8>,
+    /// inclusion proof of the entry in the tree head
+    pub proof: InclusionProof<D, S>,
+    /// first published tree head containing the entry
+    pub tree_head: SignedTreeHead<D>,
+    /// latest published tree head
+    pub latest_head: SignedTreeHead<D>,
+    /// proof that `tree_head` is a prefix of `latest_head`
+    pub consistency: ConsistencyProof<D, S>,
+    /// fingerprint of the tree parameters, see `parameter_fingerprint`
+    pub fingerprint: Output<D>,
+    scheme: PhantomData<S>,
+}
+
+impl<D: Digest, S> PartialEq for EvidenceBundle<D, S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.payload == other.payload
+            && self.salt == other.salt
+            && self.proof == other.proof
+            && self.tree_head == other.tree_head
+            && self.latest_head == other.latest_head
+            && self.consistency == other.consistency
+            && self.fingerprint == other.fingerprint
+    }
+}
+
+impl<D: Digest, S> Eq for EvidenceBundle<D, S> {}
+
+impl<D, S> EvidenceBundle<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// returns the leaf hash of the salted payload
+    pub fn leaf_hash(&self) -> Output<D> {
+        salted_leaf_hash::<D, S>(&self.salt, &self.payload)
+    }
+
+    /// verifies all parts of the bundle
+    pub fn verify(&self, verifier: &impl SthVerifier) -> Result<(), EvidenceError> {
+        if self.fingerprint != parameter_fingerprint::<D, S>() {
+            return Err(EvidenceError::FingerprintMismatch);
+        }
+        self.verify_contents(verifier)
+    }
+
+    /// verifies the signatures and proofs of the bundle with `D` and `S` but not its fingerprint,
+    /// for callers deciding on the accepted fingerprints themselves like `PolicyVerifier`
+    pub fn verify_contents(&self, verifier: &impl SthVerifier) -> Result<(), EvidenceError> {
+        if !self.tree_head.verify(verifier) || !self.latest_head.verify(verifier) {
+            return Err(EvidenceError::InvalidSignature);
+        }
+        if self.proof.tree_size() as u64 != self.tree_head.tree_size
+            || !self.proof.verify(&self.leaf_hash(), &self.tree_head.root)
+        {
+            return Err(EvidenceError::InvalidInclusionProof);
+        }
+        if self.consistency.old_size() as u64 != self.tree_head.tree_size
+            || self.consistency.new_size() as u64 != self.latest_head.tree_size
+            || !self
+                .consistency
+                .verify(&self.tree_head.root, &self.latest_head.root)
+        {
+            return Err(EvidenceError::InvalidConsistencyProof);
+        }
+        Ok(())
+    }
+
+    /// serializes the bundle
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(EVIDENCE_MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.push(<D as Digest>::output_size() as u8);
+        bytes.extend_from_slice(&self.fingerprint);
+        write_bytes(&mut bytes, &self.payload);
+        write_bytes(&mut bytes, &self.salt);
+        bytes.extend_from_slice(&(self.proof.leaf_index() as u64).to_le_bytes());
+        write_hashes::<D>(&mut bytes, self.proof.path());
+        for head in [&self.tree_head, &self.latest_head] {
+            bytes.extend_from_slice(&head.tree_size.to_le_bytes());
+            bytes.extend_from_slice(&head.timestamp.to_le_bytes());
+            bytes.extend_from_slice(&head.root);
+            write_bytes(&mut bytes, &head.signature);
+        }
+        write_hashes::<D>(&mut bytes, self.consistency.path());
+        bytes
+    }
+
+    /// deserializes a bundle serialized with `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut reader = bytes;
+        let mut header = [0; 6];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != EVIDENCE_MAGIC {
+            return Err(invalid_data("invalid magic bytes"));
+        }
+        if header[4] != FORMAT_VERSION {
+            return Err(invalid_data("unsupported format version"));
+        }
+        if header[5] as usize != <D as Digest>::output_size() {
+            return Err(invalid_data("hash size doesn't match the digest"));
+        }
+        let fingerprint = read_hash::<D>(&mut reader)?;
+        let payload = read_bytes(&mut reader)?;
+        let salt = read_bytes(&mut reader)?;
+        let leaf_index = read_u64(&mut reader)?;
+        let path = read_hashes::<D>(&mut reader)?;
+        let mut heads = Vec::with_capacity(2);
+        for _ in 0..2 {
+            heads.push(SignedTreeHead {
+                tree_size: read_u64(&mut reader)?,
+                timestamp: read_u64(&mut reader)?,
+                root: read_hash::<D>(&mut reader)?,
+                signature: read_bytes(&mut reader)?,
+            });
+        }
+        let consistency_path = read_hashes::<D>(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(invalid_data("trailing bytes after evidence bundle"));
+        }
+        let latest_head = heads.pop().unwrap();
+        let tree_head = heads.pop().unwrap();
+        let proof =
+            InclusionProof::new(to_usize(leaf_index)?, to_usize(tree_head.tree_size)?, path);
+        let consistency = ConsistencyProof::new(
+            to_usize(tree_head.tree_size)?,
+            to_usize(latest_head.tree_size)?,
+            consistency_path,
+        );
+        Ok(Self {
+            payload,
+            salt,
+            proof,
+            tree_head,
+            latest_head,
+            consistency,
+            fingerprint,
+            scheme: PhantomData,
+        })
+    }
+}
+
+/// A log keeping the payloads of its entries to export chain-of-custody evidence
+pub struct CustodyLog<D: Digest, S = Rfc6962> {
+    /// tree over the salted entries
+    tree: LogTree<D, S>,
+    /// payload and salt of each entry
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+    /// published tree heads in publication order
+    heads: Vec<SignedTreeHead<D>>,
+}
+
+impl<D, S> Default for CustodyLog<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D, S> CustodyLog<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a new empty log
+    pub fn new() -> Self {
+        Self {
+            tree: LogTree::new(),
+            entries: Vec::new(),
+            heads: Vec::new(),
+        }
+    }
+
+    /// returns the tree over the salted entries
+    pub fn tree(&self) -> &LogTree<D, S> {
+        &self.tree
+    }
+
+    /// returns the published tree heads in publication order
+    pub fn heads(&self) -> &[SignedTreeHead<D>] {
+        &self.heads
+    }
+
+    /// appends an entry, returns its offset
+    pub fn append(&mut self, payload: &[u8], salt: &[u8]) -> usize {
+        self.entries.push((payload.to_vec(), salt.to_vec()));
+        self.tree
+            .push_leaf_hash(salted_leaf_hash::<D, S>(salt, payload))
+    }
+
+    /// signs and publishes the current tree head with the current time
+    /// panics if the signer fails, see `try_publish_head` for a fallible version
+    pub fn publish_head(&mut self, signer: &impl SthSigner) -> &SignedTreeHead<D> {
+        self.publish_head_at(signer, SystemTime::now())
+    }
+
+    /// signs and publishes the current tree head with the current time
+    /// returns an error and publishes nothing if the signer fails
+    pub fn try_publish_head(
+        &mut self,
+        signer: &impl SthSigner,
+    ) -> Result<&SignedTreeHead<D>, SignError> {
+        self.try_publish_head_at(signer, SystemTime::now())
+    }
+
+    /// signs and publishes the current tree head with the given time
+    /// panics if the time is before the time of the previous head or the signer fails, see
+    /// `try_publish_head_at` for a fallible version
+    pub fn publish_head_at(
+        &mut self,
+        signer: &impl SthSigner,
+        time: SystemTime,
+    ) -> &SignedTreeHead<D> {
+        match self.try_publish_head_at(signer, time) {
+            Ok(head) => head,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// signs and publishes the current tree head with the given time
+    /// returns an error and publishes nothing if the signer fails
+    /// panics if the time is before the time of the previous head
+    pub fn try_publish_head_at(
+        &mut self,
+        signer: &impl SthSigner,
+        time: SystemTime,
+    ) -> Result<&SignedTreeHead<D>, SignError> {
+        let timestamp = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        if let Some(previous) = self.heads.last() {
+            assert!(
+                timestamp >= previous.timestamp,
+                "tree head timestamps must not decrease"
+            );
+        }
+        let tree_size = self.tree.len() as u64;
+        let root = self.tree.root_hash();
+        let signature = signer.try_sign(&sth_message::<D>(tree_size, timestamp, &root))?;
+        self.heads.push(SignedTreeHead {
+            tree_size,
+            timestamp,
+            root,
+            signature,
+        });
+        Ok(self.heads.last().unwrap())
+    }
+
+    /// exports the evidence for an entry
+    /// returns None if the entry is not covered by a published tree head yet
+    pub fn export_evidence(&self, offset: usize) -> Option<EvidenceBundle<D, S>> {
+        let (payload, salt) = self.entries.get(offset)?;
+        let tree_head = self
+            .heads
+            .iter()
+            .find(|head| head.tree_size as usize > offset)?;
+        let latest_head = self.heads.last()?;
+        let proof = self
+            .tree
+            .create_proof_at(offset, tree_head.tree_size as usize)?;
+        let consistency = self.tree.create_consistency_proof(
+            tree_head.tree_size as usize,
+            latest_head.tree_size as usize,
+        )?;
+        Some(EvidenceBundle {
+            payload: payload.clone(),
+            salt: salt.clone(),
+            proof,
+            tree_head: tree_head.clone(),
+            latest_head: latest_head.clone(),
+            consistency,
+            fingerprint: parameter_fingerprint::<D, S>(),
+            scheme: PhantomData,
+        })
+    }
+}
+
+/// returns the leaf hash of a salted payload
+fn salted_leaf_hash<D: Digest, S: HashScheme<D>>(salt: &[u8], payload: &[u8]) -> Output<D> {
+    let mut data = Vec::with_capacity(8 + salt.len() + payload.len());
+    data.extend_from_slice(&(salt.len() as u64).to_le_bytes());
+    data.extend_from_slice(salt);
+    data.extend_from_slice(payload);
+    S::hash_leaf(&data)
+}
+
+pub(crate) fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+    bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(data);
+}
+
+pub(crate) fn write_hashes<D: Digest>(bytes: &mut Vec<u8>, hashes: &[Output<D>]) {
+    bytes.extend_from_slice(&(hashes.len() as u64).to_le_bytes());
+    for hash in hashes {
+        bytes.extend_from_slice(hash);
+    }
+}
+
+pub(crate) fn read_u64(reader: &mut &[u8]) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_bytes(reader: &mut &[u8]) -> io::Result<Vec<u8>> {
+    let len = to_usize(read_u64(reader)?)?;
+    if len > reader.len() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let (data, rest) = reader.split_at(len);
+    *reader = rest;
+    Ok(data.to_vec())
+}
+
+pub(crate) fn read_hash<D: Digest>(reader: &mut &[u8]) -> io::Result<Output<D>> {
+    let mut hash = Output::<D>::default();
+    reader.read_exact(&mut hash)?;
+    Ok(hash)
+}
+
+pub(crate) fn read_hashes<D: Digest>(reader: &mut &[u8]) -> io::Result<Vec<Output<D>>> {
+    let count = to_usize(read_u64(reader)?)?;
+    if count > reader.len() / <D as Digest>::output_size() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    (0..count).map(|_| read_hash::<D>(reader)).collect()
+}
+
+pub(crate) fn to_usize(value: u64) -> io::Result<usize> {
+    usize::try_from(value).map_err(|_| invalid_data("value doesn't fit into usize"))
+}
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha2::Sha256;
+    use sha3::Sha3_256;
+
+    type CustodyLog = super::CustodyLog<Sha256>;
+    type EvidenceBundle = super::EvidenceBundle<Sha256>;
+
+    /// keyed hash standing in for a real signature scheme
+    struct TestKey(&'static [u8]);
+
+    impl SthSigner for TestKey {
+        fn sign(&self, message: &[u8]) -> Vec<u8> {
+            Sha256::new()
+                .chain_update(self.0)
+                .chain_update(message)
+                .finalize()
+                .to_vec()
+        }
+    }
+
+    impl SthVerifier for TestKey {
+        fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+            self.sign(message) == signature
+        }
+    }
+
+    fn test_log() -> CustodyLog {
+        let key = TestKey(b"key");
+        let mut log = CustodyLog::new();
+        for i in 0..5u32 {
+            log.append(format!("entry {i}").as_bytes(), &i.to_le_bytes());
+        }
+        log.publish_head(&key);
+        for i in 5..13u32 {
+            log.append(format!("entry {i}").as_bytes(), &i.to_le_bytes());
+        }
+        log.publish_head(&key);
+        log.append(b"unpublished", b"salt");
+        log
+    }
+
+    #[test]
+    fn test_export_and_verify() {
+        let key = TestKey(b"key");
+        let log = test_log();
+        for offset in 0..13 {
+            let bundle = log.export_evidence(offset).unwrap();
+            assert_eq!(bundle.payload, format!("entry {offset}").as_bytes());
+            let expected_size = if offset < 5 { 5 } else { 13 };
+            assert_eq!(bundle.tree_head.tree_size, expected_size);
+            assert_eq!(bundle.latest_head.tree_size, 13);
+            assert_eq!(bundle.verify(&key), Ok(()));
+
+            let decoded = EvidenceBundle::from_bytes(&bundle.to_bytes()).unwrap();
+            assert_eq!(decoded, bundle);
+            assert_eq!(decoded.verify(&key), Ok(()));
+        }
+        assert_eq!(log.export_evidence(13), None);
+        assert_eq!(log.export_evidence(14), None);
+    }
+
+    #[test]
+    fn test_tampered_evidence() {
+        let key = TestKey(b"key");
+        let bundle = test_log().export_evidence(2).unwrap();
+
+        assert_eq!(
+            bundle.verify(&TestKey(b"other key")),
+            Err(EvidenceError::InvalidSignature)
+        );
+
+        let mut tampered = bundle.clone();
+        tampered.payload = b"entry 3".to_vec();
+        assert_eq!(
+            tampered.verify(&key),
+            Err(EvidenceError::InvalidInclusionProof)
+        );
+
+        let mut tampered = bundle.clone();
+        tampered.salt = 3u32.to_le_bytes().to_vec();
+        assert_eq!(
+            tampered.verify(&key),
+            Err(EvidenceError::InvalidInclusionProof)
+        );
+
+        let mut tampered = bundle.clone();
+        let mut path = tampered.consistency.path().to_vec();
+        path.pop();
+        tampered.consistency = ConsistencyProof::new(
+            bundle.consistency.old_size(),
+            bundle.consistency.new_size(),
+            path,
+        );
+        assert_eq!(
+            tampered.verify(&key),
+            Err(EvidenceError::InvalidConsistencyProof)
+        );
+
+        let mut tampered = bundle.clone();
+        tampered.fingerprint = parameter_fingerprint::<Sha256, crate::Plain>();
+        assert_eq!(
+            tampered.verify(&key),
+            Err(EvidenceError::FingerprintMismatch)
+        );
+
+        let bytes = bundle.to_bytes();
+        assert!(EvidenceBundle::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let other_digest = super::EvidenceBundle::<Sha3_256>::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            other_digest.verify(&key),
+            Err(EvidenceError::FingerprintMismatch)
+        );
+    }
+
+    #[test]
+    fn test_fingerprint_depends_on_parameters() {
+        let rfc6962 = parameter_fingerprint::<Sha256, Rfc6962>();
+        assert_eq!(rfc6962, parameter_fingerprint::<Sha256, Rfc6962>());
+        assert_ne!(rfc6962, parameter_fingerprint::<Sha256, crate::Plain>());
+        assert_ne!(
+            rfc6962.to_vec(),
+            parameter_fingerprint::<Sha3_256, Rfc6962>().to_vec()
+        );
+    }
+
+    #[test]
+    fn test_failing_signer() {
+        /// a signer whose token was removed
+        struct RemovedToken;
+
+        impl SthSigner for RemovedToken {
+            fn sign(&self, _message: &[u8]) -> Vec<u8> {
+                unreachable!("the log signs with try_sign")
+            }
+
+            fn try_sign(&self, _message: &[u8]) -> Result<Vec<u8>, SignError> {
+                Err(SignError::new("token removed"))
+            }
+        }
+
+        let mut log = test_log();
+        let heads = log.heads().len();
+        let err = log.try_publish_head(&RemovedToken).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "signing the tree head failed: token removed"
+        );
+        assert_eq!(log.heads().len(), heads);
+        assert!(log.try_publish_head(&TestKey(b"key")).is_ok());
+        assert_eq!(log.heads().len(), heads + 1);
+    }
+}
@@ -0,0 +1,170 @@
+//! Deferred recomputation for write-heavy workloads
+//!
+//! `MerkleTree::set` rehashes the whole path of the leaf right away, so `n` updates cost
+//! `n * (depth - 1)` hashes even when their paths share most of their ancestors. A
+//! `DeferredMerkleTree` only writes the leaf and marks it dirty. The ancestors of all dirty leaves
+//! are recomputed layer by layer on the next read of the root or a proof, every shared ancestor
+//! once. Updating a large part of the leaves and reading the root once then hashes every touched
+//! node a single time.
+//!
+//! Reads take `&mut self` as they may have to recompute nodes first. `push` recomputes the pending
+//! updates before it appends, growing the tree needs the current root.
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError, Plain, Proof};
+
+/// A Merkle tree that recomputes the ancestors of updated leaves lazily
+pub struct DeferredMerkleTree<D: Digest, S = Plain> {
+    tree: MerkleTree<D, S>,
+    /// the offsets of the leaves written since the last recomputation, unsorted and with duplicates
+    dirty: Vec<usize>,
+}
+
+impl<D, S> DeferredMerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// defers the recomputation of the tree's nodes, the tree is consistent to begin with
+    pub fn new(tree: MerkleTree<D, S>) -> Self {
+        Self {
+            tree,
+            dirty: Vec::new(),
+        }
+    }
+
+    /// returns the tree after recomputing the pending updates
+    pub fn tree(&mut self) -> &MerkleTree<D, S> {
+        self.flush();
+        &self.tree
+    }
+
+    /// returns the tree after recomputing the pending updates
+    pub fn into_inner(mut self) -> MerkleTree<D, S> {
+        self.flush();
+        self.tree
+    }
+
+    /// returns the number of leaf writes whose ancestors are not recomputed yet, repeated writes of
+    /// a leaf may be counted once
+    pub fn pending(&self) -> usize {
+        self.dirty.len()
+    }
+
+    /// returns the value of a leaf node, None if the offset is out of bounds
+    pub fn get(&self, offset: usize) -> Option<&Output<D>> {
+        self.tree.get(offset)
+    }
+
+    /// returns the root hash after recomputing the pending updates
+    pub fn root_hash(&mut self) -> &Output<D> {
+        self.flush();
+        self.tree.root_hash()
+    }
+
+    /// updates the value of a leaf node, its ancestors are recomputed on the next read
+    /// panics if the offset is out of bounds, see `try_set` for a fallible version
+    pub fn set(&mut self, offset: usize, value: &Output<D>) {
+        if let Err(err) = self.try_set(offset, value) {
+            panic!("{err}");
+        }
+    }
+
+    /// updates the value of a leaf node, its ancestors are recomputed on the next read
+    /// returns an error if the offset is out of bounds
+    pub fn try_set(&mut self, offset: usize, value: &Output<D>) -> Result<(), MerkleTreeError> {
+        self.tree.write_leaf(offset, value)?;
+        // repeated writes of the same leaves don't grow the buffer beyond the number of leaves
+        if self.dirty.len() >= self.tree.num_leaves() {
+            self.dirty.sort_unstable();
+            self.dirty.dedup();
+        }
+        self.dirty.push(offset);
+        Ok(())
+    }
+
+    /// recomputes the pending updates and appends a leaf after the last pushed leaf, returns its
+    /// offset
+    /// panics if the tree can't grow any further, see `try_push` for a fallible version
+    pub fn push(&mut self, leaf: &Output<D>) -> usize {
+        match self.try_push(leaf) {
+            Ok(offset) => offset,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// recomputes the pending updates and appends a leaf after the last pushed leaf, returns its
+    /// offset
+    /// returns an error if the tree would exceed `MAX_DEPTH` or its nodes can't be allocated
+    pub fn try_push(&mut self, leaf: &Output<D>) -> Result<usize, MerkleTreeError> {
+        self.flush();
+        self.tree.try_push(leaf)
+    }
+
+    /// creates a proof for the leaf at the given offset after recomputing the pending updates
+    /// panics if the offset is out of bounds, see `try_create_proof` for a fallible version
+    pub fn create_proof(&mut self, offset: usize) -> Proof<D, S> {
+        self.flush();
+        self.tree.create_proof(offset)
+    }
+
+    /// creates a proof for the leaf at the given offset after recomputing the pending updates
+    /// returns an error if the offset is out of bounds
+    pub fn try_create_proof(&mut self, offset: usize) -> Result<Proof<D, S>, MerkleTreeError> {
+        self.flush();
+        self.tree.try_create_proof(offset)
+    }
+
+    /// recomputes the ancestors of all leaves written since the last recomputation
+    pub fn flush(&mut self) {
+        if !self.dirty.is_empty() {
+            self.tree.rehash_paths(&mut self.dirty);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha3::Sha3_256;
+
+    use crate::verify;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type DeferredMerkleTree = super::DeferredMerkleTree<Sha3_256>;
+
+    #[test]
+    fn test_deferred_updates() {
+        let mut eager = MerkleTree::new(7, &[0; 32].into());
+        let mut deferred = DeferredMerkleTree::new(MerkleTree::new(7, &[0; 32].into()));
+        for i in 0..100 {
+            let offset = (i * 37) % 64;
+            let value = [i as u8; 32].into();
+            eager.set(offset, &value);
+            deferred.set(offset, &value);
+        }
+        assert_ne!(deferred.pending(), 0);
+        assert_eq!(deferred.get(37), eager.get(37));
+        assert_eq!(deferred.root_hash(), eager.root_hash());
+        assert_eq!(deferred.pending(), 0);
+        assert_eq!(deferred.tree().check_integrity(), Ok(()));
+
+        deferred.set(3, &[0xaa; 32].into());
+        eager.set(3, &[0xaa; 32].into());
+        let proof = deferred.create_proof(3);
+        assert!(verify(eager.root_hash(), &[0xaa; 32].into(), &proof));
+        assert!(deferred.try_set(64, &[1; 32].into()).is_err());
+
+        deferred.set(5, &[0xbb; 32].into());
+        eager.set(5, &[0xbb; 32].into());
+        deferred.push(&[0xcc; 32].into());
+        eager.push(&[0xcc; 32].into());
+        let tree = deferred.into_inner();
+        assert_eq!(tree.root_hash(), eager.root_hash());
+        assert_eq!(tree.check_integrity(), Ok(()));
+    }
+}
@@ -0,0 +1,143 @@
+use core::fmt;
+
+/// Errors returned by the fallible operations of the Merkle trees in this crate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MerkleTreeError {
+    /// the requested depth is not supported (it has to be between 1 and `MAX_DEPTH`)
+    DepthOutOfRange { depth: usize, max: usize },
+    /// the leaf offset is not smaller than the number of leaves
+    LeafIndexOutOfBounds { offset: usize, num_leaves: usize },
+    /// the proof doesn't have one sibling per layer below the root
+    InvalidProofLength { expected: usize, actual: usize },
+    /// the operation was cancelled through its cancellation token
+    Cancelled,
+    /// memory for the given number of nodes could not be allocated
+    AllocationFailed { nodes: usize },
+    /// the hash of the node doesn't match the hash of its children
+    InconsistentNode { index: usize },
+    /// the encoded proof doesn't have the length implied by its header
+    InvalidProofEncoding { expected: usize, actual: usize },
+    /// the direction bitmap of the encoded proof has bits set beyond the proof length
+    NonCanonicalProofEncoding,
+    /// the tree has no layer with the given index
+    LayerOutOfRange { layer: usize, depth: usize },
+    /// the layer blob is malformed
+    InvalidLayerEncoding,
+    /// the hash of the node at the given layer and offset is not known
+    MissingNode { layer: usize, offset: usize },
+    /// the assembled proof doesn't lead to the trusted root
+    RootMismatch,
+    /// the trees don't have the same depth
+    DepthMismatch { expected: usize, actual: usize },
+    /// the leaf doesn't hold the default value of the tree
+    LeafNotEmpty { offset: usize },
+    /// the range of leaves is empty
+    EmptyRange,
+    /// the layer of the tree has no node at the offset
+    NodeOutOfRange { layer: usize, offset: usize },
+    /// more updates were to be rolled back than are uncommitted
+    RollbackOutOfRange { requested: usize, pending: usize },
+    /// the string has an odd number of digits or a character that isn't a hex digit
+    InvalidHex,
+    /// the decoded hash doesn't have the output size of the digest
+    InvalidHashLength { expected: usize, actual: usize },
+    /// no root was recorded with the sequence number
+    UnknownSequence { sequence: u64 },
+    /// the piece length is not a power of two of at least 16 KiB
+    InvalidPieceLength { piece_length: u64 },
+    /// the proof of the leaf is no longer kept, only the most recently appended leaves have proofs
+    ProofNotRetained { offset: usize },
+    /// the nodes of a memory mapped tree couldn't be synced to empty its write-ahead log
+    CheckpointFailed,
+    /// the commit timestamp is earlier than the timestamp of the previous version
+    DecreasingTimestamp,
+}
+
+impl fmt::Display for MerkleTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DepthOutOfRange { depth, max } => {
+                write!(
+                    f,
+                    "Merkle tree depth must be between 1 and {max}, got {depth}"
+                )
+            }
+            Self::LeafIndexOutOfBounds { offset, num_leaves } => {
+                write!(
+                    f,
+                    "leaf index {offset} is out of bounds for a tree with {num_leaves} leaves"
+                )
+            }
+            Self::InvalidProofLength { expected, actual } => {
+                write!(f, "proof must contain {expected} siblings, got {actual}")
+            }
+            Self::Cancelled => write!(f, "operation was cancelled"),
+            Self::AllocationFailed { nodes } => {
+                write!(f, "failed to allocate memory for {nodes} nodes")
+            }
+            Self::InconsistentNode { index } => {
+                write!(f, "node {index} doesn't match the hash of its children")
+            }
+            Self::InvalidProofEncoding { expected, actual } => {
+                write!(
+                    f,
+                    "encoded proof must be {expected} bytes long, got {actual}"
+                )
+            }
+            Self::NonCanonicalProofEncoding => {
+                write!(f, "encoded proof has direction bits set beyond its length")
+            }
+            Self::LayerOutOfRange { layer, depth } => {
+                write!(
+                    f,
+                    "layer {layer} is out of range for a tree of depth {depth}"
+                )
+            }
+            Self::InvalidLayerEncoding => write!(f, "malformed layer blob"),
+            Self::MissingNode { layer, offset } => {
+                write!(f, "hash of node {offset} in layer {layer} is missing")
+            }
+            Self::RootMismatch => write!(f, "proof doesn't lead to the trusted root"),
+            Self::DepthMismatch { expected, actual } => {
+                write!(f, "trees must have the same depth {expected}, got {actual}")
+            }
+            Self::LeafNotEmpty { offset } => {
+                write!(f, "leaf {offset} doesn't hold the default value")
+            }
+            Self::EmptyRange => write!(f, "range of leaves is empty"),
+            Self::NodeOutOfRange { layer, offset } => {
+                write!(f, "layer {layer} has no node at offset {offset}")
+            }
+            Self::RollbackOutOfRange { requested, pending } => {
+                write!(
+                    f,
+                    "can't roll back {requested} updates, only {pending} are uncommitted"
+                )
+            }
+            Self::InvalidHex => write!(f, "invalid hex string"),
+            Self::InvalidHashLength { expected, actual } => {
+                write!(f, "hash must be {expected} bytes long, got {actual}")
+            }
+            Self::UnknownSequence { sequence } => {
+                write!(f, "no root was recorded with sequence number {sequence}")
+            }
+            Self::InvalidPieceLength { piece_length } => {
+                write!(
+                    f,
+                    "piece length {piece_length} is not a power of two of at least 16 KiB"
+                )
+            }
+            Self::ProofNotRetained { offset } => {
+                write!(f, "the proof of leaf {offset} is no longer kept")
+            }
+            Self::CheckpointFailed => {
+                write!(f, "the write-ahead log could not be checkpointed")
+            }
+            Self::DecreasingTimestamp => {
+                write!(f, "commit timestamps must not decrease")
+            }
+        }
+    }
+}
+
+impl core::error::Error for MerkleTreeError {}
@@ -0,0 +1,120 @@
+//! Trees and proofs compatible with OpenZeppelin's `MerkleProof.sol`
+//!
+//! `OzMerkleTree` is a `LogTree` hashing with keccak256 and the `SortedPair` scheme, so its roots
+//! and inclusion proofs verify with `MerkleProof.verify(proof, root, leaf)`. Leaves of
+//! OpenZeppelin's `StandardMerkleTree` are the double keccak256 hash of the ABI encoded values, see
+//! `standard_leaf_hash`. Proofs are exported as the `bytes32[]` argument Solidity expects, either
+//! ABI encoded or as a literal for tools like `cast`.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use digest::{Digest, Output};
+use sha3::Keccak256;
+
+use crate::scheme::{HashScheme, SortedPair};
+use crate::{InclusionProof, LogTree};
+
+/// A keccak256 tree hashing sorted pairs like OpenZeppelin's `MerkleProof`
+pub type OzMerkleTree = LogTree<Keccak256, SortedPair>;
+
+/// An inclusion proof of an `OzMerkleTree`
+pub type OzProof = InclusionProof<Keccak256, SortedPair>;
+
+/// returns the leaf hash of OpenZeppelin's `StandardMerkleTree` given the ABI encoded values,
+/// `keccak256(bytes.concat(keccak256(abi.encode(values))))`
+pub fn standard_leaf_hash(encoded: &[u8]) -> Output<Keccak256> {
+    Keccak256::digest(Keccak256::digest(encoded))
+}
+
+/// verifies a proof like `MerkleProof.verify`, the siblings are combined with the leaf in sorted
+/// order starting at the leaf layer
+pub fn verify_sorted<D: Digest>(root: &Output<D>, leaf: &Output<D>, proof: &[Output<D>]) -> bool {
+    let computed = proof.iter().fold(leaf.clone(), |hash, sibling| {
+        <SortedPair as HashScheme<D>>::hash_node(&hash, sibling)
+    });
+    &computed == root
+}
+
+/// returns the ABI encoding of the proof as a single `bytes32[]` argument: the offset of the
+/// array, its length and the hashes, each as a 32 byte word
+pub fn abi_encode_proof(proof: &[Output<Keccak256>]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(64 + 32 * proof.len());
+    encoded.extend_from_slice(&abi_word(32));
+    encoded.extend_from_slice(&abi_word(proof.len() as u64));
+    for hash in proof {
+        encoded.extend_from_slice(hash);
+    }
+    encoded
+}
+
+/// returns the proof as a `bytes32[]` literal like `[0x…,0x…]`, as accepted by `cast` and most
+/// Ethereum libraries
+pub fn solidity_literal(proof: &[Output<Keccak256>]) -> String {
+    let hashes: Vec<String> = proof
+        .iter()
+        .map(|hash| alloc::format!("0x{}", hex::encode(hash)))
+        .collect();
+    alloc::format!("[{}]", hashes.join(","))
+}
+
+/// returns a number as a big endian 32 byte ABI word
+pub(crate) fn abi_word(value: u64) -> [u8; 32] {
+    let mut word = [0; 32];
+    word[24..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(i: u8) -> Output<Keccak256> {
+        Keccak256::digest([i])
+    }
+
+    #[test]
+    fn test_sorted_proofs() {
+        let mut tree = OzMerkleTree::new();
+        for i in 0..7 {
+            tree.push_leaf_hash(leaf(i));
+        }
+        let root = tree.root_hash();
+        for i in 0..7 {
+            let proof = tree.create_proof(i as usize).unwrap();
+            assert!(proof.verify(&leaf(i), &root));
+            // the siblings alone are enough, the side of each sibling doesn't matter
+            assert!(verify_sorted::<Keccak256>(&root, &leaf(i), proof.path()));
+            assert!(!verify_sorted::<Keccak256>(
+                &root,
+                &leaf(i + 1),
+                proof.path()
+            ));
+        }
+
+        // hashing is independent of the order of the children
+        let (a, b) = (leaf(1), leaf(2));
+        assert_eq!(
+            <SortedPair as HashScheme<Keccak256>>::hash_node(&a, &b),
+            <SortedPair as HashScheme<Keccak256>>::hash_node(&b, &a)
+        );
+    }
+
+    #[test]
+    fn test_proof_export() {
+        let proof = [[0x11; 32].into(), [0x22; 32].into()];
+        let encoded = abi_encode_proof(&proof);
+        assert_eq!(encoded.len(), 4 * 32);
+        assert_eq!(encoded[31], 0x20);
+        assert_eq!(encoded[63], 2);
+        assert_eq!(&encoded[64..96], &[0x11; 32]);
+        assert_eq!(&encoded[96..], &[0x22; 32]);
+        assert_eq!(abi_encode_proof(&[]), [abi_word(32), abi_word(0)].concat());
+
+        assert_eq!(
+            solidity_literal(&proof),
+            alloc::format!("[0x{},0x{}]", "11".repeat(32), "22".repeat(32))
+        );
+        assert_eq!(solidity_literal(&[]), "[]");
+    }
+}
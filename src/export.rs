@@ -0,0 +1,315 @@
+//! Binary snapshots of a `MerkleTree`
+//!
+//! `MerkleTree::export` writes all nodes of a tree behind a header, `MerkleTree::import` reads them
+//! back without hashing a single node, so shipped trees are loaded in the time it takes to read
+//! them. A snapshot consists of:
+//!
+//! | bytes | content |
+//! |-------|---------|
+//! | 4 | magic `MTSN` |
+//! | 1 | format version |
+//! | 1 | depth of the tree |
+//! | 1 | hash size in bytes |
+//! | 1 | reserved, zero |
+//! | 8 | offset of the next pushed leaf, little endian |
+//! | hash size | hash algorithm id, the parameter fingerprint of the digest |
+//! | hash size | padding value |
+//! | hash size per node | the nodes in breadth-first order, the root first |
+//! | hash size | checksum, the digest of all bytes before it |
+//!
+//! Both directions buffer the writer or reader internally. `export_cancellable` and
+//! `import_cancellable` check a `CancellationToken` and report the progress in nodes while copying
+//! the nodes of a large tree.
+
+use std::fmt::Debug;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use digest::{Digest, Output};
+
+use crate::cancel::CHECK_INTERVAL;
+use crate::custody::{invalid_data, parameter_fingerprint};
+use crate::{CancellationToken, HashScheme, MerkleTree, Progress, MAX_DEPTH};
+
+/// magic bytes at the start of every snapshot
+const MAGIC: &[u8; 4] = b"MTSN";
+/// version of the snapshot format
+const VERSION: u8 = 1;
+/// length of the fixed part of the header
+const FIXED_HEADER_LEN: usize = 16;
+
+impl<D, S> MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// writes a snapshot of the tree including all intermediate nodes
+    pub fn export(&self, writer: impl Write) -> io::Result<()> {
+        self.export_cancellable(writer, &CancellationToken::new(), &mut |_| {})
+    }
+
+    /// writes a snapshot of the tree like `export`
+    /// The cancellation token is checked and the progress (in nodes written) is reported while
+    /// writing the nodes, a cancelled export returns an error wrapping `MerkleTreeError::Cancelled`
+    /// and leaves a truncated snapshot behind.
+    pub fn export_cancellable(
+        &self,
+        writer: impl Write,
+        cancel: &CancellationToken,
+        progress: &mut dyn FnMut(Progress),
+    ) -> io::Result<()> {
+        let mut writer = ChecksumWriter::<D, _>::new(BufWriter::new(writer));
+        let mut header = [0; FIXED_HEADER_LEN];
+        header[..4].copy_from_slice(MAGIC);
+        header[4] = VERSION;
+        header[5] = self.depth() as u8;
+        header[6] = <D as Digest>::output_size() as u8;
+        header[8..].copy_from_slice(&(self.next_offset() as u64).to_le_bytes());
+        writer.write(&header)?;
+        writer.write(&parameter_fingerprint::<D, S>())?;
+        writer.write(self.padding())?;
+        let total = Self::node_count(self.depth());
+        let mut start = 0;
+        while start < total {
+            cancel.check().map_err(io::Error::other)?;
+            let end = (start + CHECK_INTERVAL).min(total);
+            for index in start..end {
+                writer.write(self.node(index))?;
+            }
+            start = end;
+            progress(Progress {
+                completed: start,
+                total,
+            });
+        }
+        writer.finish()
+    }
+
+    /// reads a snapshot written by `export`
+    /// returns an error if the snapshot is truncated, corrupted or was written with a different
+    /// digest or hash scheme
+    /// The reader is buffered internally and may be read past the end of the snapshot.
+    pub fn import(reader: impl Read) -> io::Result<Self> {
+        Self::import_cancellable(reader, &CancellationToken::new(), &mut |_| {})
+    }
+
+    /// reads a snapshot written by `export` like `import`
+    /// The cancellation token is checked and the progress (in nodes read) is reported while reading
+    /// the nodes, a cancelled import returns an error wrapping `MerkleTreeError::Cancelled`.
+    pub fn import_cancellable(
+        reader: impl Read,
+        cancel: &CancellationToken,
+        progress: &mut dyn FnMut(Progress),
+    ) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let hash_size = <D as Digest>::output_size();
+        let mut checksum = D::new();
+        let mut header = [0; FIXED_HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid_data("not a merkle tree snapshot"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid_data("unsupported snapshot version"));
+        }
+        if header[6] as usize != hash_size {
+            return Err(invalid_data("hash size does not match the digest"));
+        }
+        let depth = header[5] as usize;
+        if depth > MAX_DEPTH {
+            return Err(invalid_data("depth out of range"));
+        }
+        let next_offset = u64::from_le_bytes(header[8..].try_into().unwrap());
+        if next_offset > ((1 << depth) >> 1) {
+            return Err(invalid_data("next offset out of bounds"));
+        }
+        checksum.update(header);
+
+        let mut read_hash = |reader: &mut dyn Read| -> io::Result<Output<D>> {
+            let mut hash = Output::<D>::default();
+            reader.read_exact(&mut hash)?;
+            checksum.update(hash);
+            Ok(hash)
+        };
+        if read_hash(&mut reader)? != parameter_fingerprint::<D, S>() {
+            return Err(invalid_data("hash algorithm does not match the digest"));
+        }
+        let padding = read_hash(&mut reader)?;
+        let total = Self::node_count(depth);
+        let mut nodes = Vec::new();
+        while nodes.len() < total {
+            cancel.check().map_err(io::Error::other)?;
+            let end = (nodes.len() + CHECK_INTERVAL).min(total);
+            // grow with the data actually read, a corrupt depth must not allocate up front
+            while nodes.len() < end {
+                nodes.push(read_hash(&mut reader)?);
+            }
+            progress(Progress {
+                completed: nodes.len(),
+                total,
+            });
+        }
+
+        let mut expected = Output::<D>::default();
+        reader.read_exact(&mut expected)?;
+        if checksum.finalize() != expected {
+            return Err(invalid_data("snapshot checksum mismatch"));
+        }
+        Ok(Self::from_node_buf(
+            depth,
+            nodes.into(),
+            next_offset as usize,
+            padding,
+        ))
+    }
+
+    /// returns the number of nodes in a snapshot, an empty tree still holds its root
+    fn node_count(depth: usize) -> usize {
+        ((1 << depth) - 1).max(1)
+    }
+}
+
+/// writes bytes and hashes them for the checksum at the end of a snapshot
+struct ChecksumWriter<D, W> {
+    writer: W,
+    checksum: D,
+}
+
+impl<D: Digest, W: Write> ChecksumWriter<D, W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer,
+            checksum: D::new(),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.checksum.update(bytes);
+        self.writer.write_all(bytes)
+    }
+
+    /// writes the checksum of everything written before
+    fn finish(mut self) -> io::Result<()> {
+        self.writer.write_all(&self.checksum.finalize())?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    use crate::{MerkleTreeError, Rfc6962};
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+
+    #[test]
+    fn test_export_import() {
+        let leaves: Vec<Output<Sha3_256>> = (0..11).map(|i| [i as u8; 32].into()).collect();
+        let tree = MerkleTree::from_leaves(&leaves, &[0xff; 32].into());
+        let mut bytes = Vec::new();
+        tree.export(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 16 + 32 * (2 + 31 + 1));
+
+        let mut imported = MerkleTree::import(&bytes[..]).unwrap();
+        assert_eq!(imported.root_hash(), tree.root_hash());
+        assert_eq!(imported.check_integrity(), Ok(()));
+        assert_eq!(imported.next_offset(), 11);
+        // the padding is restored for pushed leaves and the growth of the tree
+        imported.push(&[0xaa; 32].into());
+        let mut pushed = leaves.clone();
+        pushed.push([0xaa; 32].into());
+        assert_eq!(
+            imported.root_hash(),
+            MerkleTree::from_leaves(&pushed, &[0xff; 32].into()).root_hash()
+        );
+
+        let mut empty = Vec::new();
+        MerkleTree::empty(&[0; 32].into())
+            .export(&mut empty)
+            .unwrap();
+        assert!(MerkleTree::import(&empty[..]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_import_rejects_invalid_snapshots() {
+        let leaves: Vec<Output<Sha3_256>> = (0..4).map(|i| [i as u8; 32].into()).collect();
+        let mut bytes = Vec::new();
+        MerkleTree::from_leaves(&leaves, &[0; 32].into())
+            .export(&mut bytes)
+            .unwrap();
+
+        let mut corrupted = bytes.clone();
+        corrupted[100] ^= 1;
+        assert!(MerkleTree::import(&corrupted[..]).is_err());
+        assert!(MerkleTree::import(&bytes[..bytes.len() - 1]).is_err());
+        assert!(crate::MerkleTree::<Sha3_256, Rfc6962>::import(&bytes[..]).is_err());
+        let mut other_version = bytes.clone();
+        other_version[4] = 2;
+        assert!(MerkleTree::import(&other_version[..]).is_err());
+    }
+
+    #[test]
+    fn test_export_import_cancellable() {
+        let leaves: Vec<Output<Sha3_256>> = (0..4096).map(|i| [i as u8; 32].into()).collect();
+        let tree = MerkleTree::from_leaves(&leaves, &[0; 32].into());
+        let total = 2 * 4096 - 1;
+
+        let mut bytes = Vec::new();
+        let mut reports = Vec::new();
+        tree.export_cancellable(&mut bytes, &CancellationToken::new(), &mut |progress| {
+            reports.push(progress)
+        })
+        .unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(
+            reports[1],
+            Progress {
+                completed: total,
+                total
+            }
+        );
+
+        let mut reports = Vec::new();
+        let imported = MerkleTree::import_cancellable(
+            &bytes[..],
+            &CancellationToken::new(),
+            &mut |progress| reports.push(progress),
+        )
+        .unwrap();
+        assert_eq!(imported.root_hash(), tree.root_hash());
+        assert_eq!(
+            reports.last(),
+            Some(&Progress {
+                completed: total,
+                total
+            })
+        );
+
+        // cancelling after the first batch of nodes stops both directions
+        let is_cancelled = |err: io::Error| {
+            err.into_inner()
+                .and_then(|err| err.downcast::<MerkleTreeError>().ok())
+                .is_some_and(|err| *err == MerkleTreeError::Cancelled)
+        };
+        let token = CancellationToken::new();
+        let mut partial = Vec::new();
+        let err = tree
+            .export_cancellable(&mut partial, &token, &mut |_| token.cancel())
+            .unwrap_err();
+        assert!(is_cancelled(err));
+        assert!(partial.len() < bytes.len());
+
+        let token = CancellationToken::new();
+        let mut reports = 0;
+        let err = MerkleTree::import_cancellable(&bytes[..], &token, &mut |_| {
+            reports += 1;
+            token.cancel();
+        })
+        .unwrap_err();
+        assert!(is_cancelled(err));
+        assert_eq!(reports, 1);
+    }
+}
@@ -0,0 +1,157 @@
+//! Hex string conversions for hashes, trees and proofs
+//!
+//! Hashes and encoded proofs are written as lowercase hex with a `0x` prefix. Parsing accepts both
+//! cases and an optional `0x` or `0X` prefix and checks that a hash has the output size of the
+//! digest, so callers working with hex don't have to convert to byte arrays themselves.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError, Proof};
+
+/// returns the bytes as lowercase hex with a `0x` prefix
+pub fn to_hex(bytes: impl AsRef<[u8]>) -> String {
+    let mut hex = String::from("0x");
+    hex.push_str(&hex::encode(bytes));
+    hex
+}
+
+/// decodes a hash of the digest from hex with an optional `0x` prefix
+/// returns an error if the string is not valid hex or the hash doesn't have the output size of the
+/// digest
+pub fn hash_from_hex<D: Digest>(hex: &str) -> Result<Output<D>, MerkleTreeError> {
+    let bytes = decode(hex)?;
+    let expected = <D as Digest>::output_size();
+    if bytes.len() != expected {
+        return Err(MerkleTreeError::InvalidHashLength {
+            expected,
+            actual: bytes.len(),
+        });
+    }
+    Ok(Output::<D>::clone_from_slice(&bytes))
+}
+
+/// decodes hex with an optional `0x` prefix
+fn decode(hex: &str) -> Result<Vec<u8>, MerkleTreeError> {
+    let digits = hex
+        .strip_prefix("0x")
+        .or_else(|| hex.strip_prefix("0X"))
+        .unwrap_or(hex);
+    hex::decode(digits).map_err(|_| MerkleTreeError::InvalidHex)
+}
+
+impl<D, S> MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a new Merkle tree like `try_new` with the initial value of the leaves given as hex
+    /// returns an error if the value is not a valid hash or the depth is out of range
+    pub fn new_hex(depth: usize, initial_value: &str) -> Result<Self, MerkleTreeError> {
+        Self::try_new(depth, &hash_from_hex::<D>(initial_value)?)
+    }
+
+    /// returns the root hash of the tree as `0x` prefixed hex
+    pub fn root_hex(&self) -> String {
+        to_hex(self.root_hash())
+    }
+
+    /// returns the value of a leaf node as `0x` prefixed hex, None if the offset is out of bounds
+    pub fn get_hex(&self, offset: usize) -> Option<String> {
+        self.get(offset).map(to_hex)
+    }
+
+    /// updates the value of a leaf node given as hex
+    /// returns an error and leaves the tree untouched if the value is not a valid hash or the
+    /// offset is out of bounds
+    pub fn set_hex(&mut self, offset: usize, value: &str) -> Result<(), MerkleTreeError> {
+        self.try_set(offset, &hash_from_hex::<D>(value)?)
+    }
+}
+
+impl<D, S> Proof<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// encodes the proof like `to_bytes` as `0x` prefixed hex
+    pub fn to_hex(&self) -> String {
+        to_hex(self.to_bytes())
+    }
+
+    /// decodes a proof encoded by `to_hex`, the `0x` prefix is optional
+    /// returns an error if the string is not valid hex or not a valid encoded proof
+    pub fn from_hex(hex: &str) -> Result<Self, MerkleTreeError> {
+        Self::from_bytes(&decode(hex)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    use crate::verify;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type Proof = crate::Proof<Sha3_256>;
+
+    #[test]
+    fn test_hex_strings() {
+        let mut tree = MerkleTree::new_hex(3, &format!("0x{}", "00".repeat(32))).unwrap();
+        assert_eq!(
+            *tree.root_hash(),
+            *MerkleTree::new(3, &[0; 32].into()).root_hash()
+        );
+
+        tree.set_hex(2, &"AB".repeat(32)).unwrap();
+        assert_eq!(tree.get(2), Some(&[0xab; 32].into()));
+        assert_eq!(tree.get_hex(2), Some(format!("0x{}", "ab".repeat(32))));
+        assert_eq!(
+            tree.root_hex(),
+            format!("0x{}", hex::encode(tree.root_hash()))
+        );
+
+        let proof = tree.create_proof(2);
+        let encoded = proof.to_hex();
+        assert!(encoded.starts_with("0x02"));
+        let decoded = Proof::from_hex(&encoded).unwrap();
+        assert_eq!(decoded, proof);
+        assert_eq!(Proof::from_hex(&encoded[2..]), Ok(proof));
+        assert!(verify(
+            &hash_from_hex::<Sha3_256>(&tree.root_hex()).unwrap(),
+            &[0xab; 32].into(),
+            &decoded
+        ));
+    }
+
+    #[test]
+    fn test_invalid_hex() {
+        let mut tree = MerkleTree::new(2, &[0; 32].into());
+        assert_eq!(
+            tree.set_hex(0, "0x1234"),
+            Err(MerkleTreeError::InvalidHashLength {
+                expected: 32,
+                actual: 2
+            })
+        );
+        assert_eq!(
+            tree.set_hex(0, &"zz".repeat(32)),
+            Err(MerkleTreeError::InvalidHex)
+        );
+        assert_eq!(tree.set_hex(0, "0x123"), Err(MerkleTreeError::InvalidHex));
+        assert!(matches!(
+            tree.set_hex(2, &"11".repeat(32)),
+            Err(MerkleTreeError::LeafIndexOutOfBounds { .. })
+        ));
+        assert_eq!(tree.get(0), Some(&[0; 32].into()));
+        assert!(MerkleTree::new_hex(crate::MAX_DEPTH + 1, &"00".repeat(32)).is_err());
+        assert!(Proof::from_hex("0x01").is_err());
+    }
+}
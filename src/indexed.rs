@@ -0,0 +1,183 @@
+//! Leaf lookup by value
+//!
+//! An `IndexedMerkleTree` keeps a reverse index from leaf values to the offsets holding them and
+//! updates it on every `set` and `push`, so leaves can be found and proven by their value without
+//! scanning the tree. The index costs one entry per used leaf. Trees that don't need lookups stay
+//! plain `MerkleTree`s and don't pay for it.
+//!
+//! Leaves holding the padding value are not indexed, a sparse tree would otherwise index all of its
+//! unused leaves.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError, Plain, Proof};
+
+/// A Merkle tree with a reverse index from leaf values to offsets
+pub struct IndexedMerkleTree<D: Digest, S = Plain> {
+    tree: MerkleTree<D, S>,
+    /// the offsets of every leaf value except the padding
+    index: BTreeMap<Output<D>, BTreeSet<usize>>,
+}
+
+impl<D, S> IndexedMerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// indexes all leaves of the tree that don't hold the padding value
+    pub fn new(tree: MerkleTree<D, S>) -> Self {
+        let mut index: BTreeMap<Output<D>, BTreeSet<usize>> = BTreeMap::new();
+        let padding = *tree.padding();
+        for (offset, leaf) in tree.leaves().enumerate() {
+            if *leaf != padding {
+                index.entry(*leaf).or_default().insert(offset);
+            }
+        }
+        Self { tree, index }
+    }
+
+    /// returns the indexed tree
+    pub fn tree(&self) -> &MerkleTree<D, S> {
+        &self.tree
+    }
+
+    /// returns the tree, dropping the index
+    pub fn into_inner(self) -> MerkleTree<D, S> {
+        self.tree
+    }
+
+    /// returns true if a leaf holds the value, always false for the padding value
+    pub fn contains(&self, leaf: &Output<D>) -> bool {
+        self.index.contains_key(leaf)
+    }
+
+    /// returns the offsets of all leaves holding the value in ascending order
+    pub fn indices_of(&self, leaf: &Output<D>) -> Vec<usize> {
+        self.index
+            .get(leaf)
+            .map_or_else(Vec::new, |offsets| offsets.iter().copied().collect())
+    }
+
+    /// returns the smallest offset of a leaf holding the value
+    pub fn index_of(&self, leaf: &Output<D>) -> Option<usize> {
+        self.index.get(leaf)?.first().copied()
+    }
+
+    /// finds the first leaf holding the value and creates its proof
+    /// returns None if no leaf holds the value
+    pub fn prove_value(&self, leaf: &Output<D>) -> Option<Proof<D, S>> {
+        Some(self.tree.create_proof(self.index_of(leaf)?))
+    }
+
+    /// updates the value of a leaf node and the index
+    /// panics if the offset is out of bounds, see `try_set` for a fallible version
+    pub fn set(&mut self, offset: usize, value: &Output<D>) {
+        if let Err(err) = self.try_set(offset, value) {
+            panic!("{err}");
+        }
+    }
+
+    /// updates the value of a leaf node and the index
+    /// returns an error if the offset is out of bounds
+    pub fn try_set(&mut self, offset: usize, value: &Output<D>) -> Result<(), MerkleTreeError> {
+        let previous =
+            self.tree
+                .get(offset)
+                .copied()
+                .ok_or(MerkleTreeError::LeafIndexOutOfBounds {
+                    offset,
+                    num_leaves: self.tree.num_leaves(),
+                })?;
+        self.tree.try_set(offset, value)?;
+        self.remove(&previous, offset);
+        self.insert(value, offset);
+        Ok(())
+    }
+
+    /// appends a leaf after the last pushed leaf and indexes it, returns its offset
+    /// panics if the tree can't grow any further, see `try_push` for a fallible version
+    pub fn push(&mut self, leaf: &Output<D>) -> usize {
+        match self.try_push(leaf) {
+            Ok(offset) => offset,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// appends a leaf after the last pushed leaf and indexes it, returns its offset
+    /// returns an error if the tree would exceed `MAX_DEPTH` or its nodes can't be allocated
+    pub fn try_push(&mut self, leaf: &Output<D>) -> Result<usize, MerkleTreeError> {
+        // pushing overwrites the next leaf if it was set before
+        let previous = self.tree.get(self.tree.next_offset()).copied();
+        let offset = self.tree.try_push(leaf)?;
+        if let Some(previous) = previous {
+            self.remove(&previous, offset);
+        }
+        self.insert(leaf, offset);
+        Ok(offset)
+    }
+
+    fn insert(&mut self, leaf: &Output<D>, offset: usize) {
+        if leaf != self.tree.padding() {
+            self.index.entry(*leaf).or_default().insert(offset);
+        }
+    }
+
+    fn remove(&mut self, leaf: &Output<D>, offset: usize) {
+        if let Some(offsets) = self.index.get_mut(leaf) {
+            offsets.remove(&offset);
+            if offsets.is_empty() {
+                self.index.remove(leaf);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    use crate::verify;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type IndexedMerkleTree = super::IndexedMerkleTree<Sha3_256>;
+
+    #[test]
+    fn test_lookup_by_value() {
+        let leaves: Vec<Output<Sha3_256>> =
+            [1u8, 2, 1, 3, 4].iter().map(|&i| [i; 32].into()).collect();
+        let mut tree = IndexedMerkleTree::new(MerkleTree::from_leaves(&leaves, &[0; 32].into()));
+        assert_eq!(tree.indices_of(&[1; 32].into()), [0, 2]);
+        assert_eq!(tree.index_of(&[3; 32].into()), Some(3));
+        assert!(!tree.contains(&[9; 32].into()));
+        // the padding of the unused leaves is not indexed
+        assert!(!tree.contains(&[0; 32].into()));
+
+        tree.set(0, &[9; 32].into());
+        tree.set(7, &[1; 32].into());
+        assert_eq!(tree.indices_of(&[1; 32].into()), [2, 7]);
+        assert_eq!(tree.indices_of(&[9; 32].into()), [0]);
+        tree.set(3, &[0; 32].into());
+        assert!(!tree.contains(&[3; 32].into()));
+        assert!(tree.try_set(8, &[1; 32].into()).is_err());
+
+        // pushing fills the unused leaves and grows the tree
+        assert_eq!(tree.push(&[5; 32].into()), 5);
+        tree.push(&[6; 32].into());
+        assert_eq!(tree.push(&[7; 32].into()), 7);
+        assert_eq!(tree.push(&[8; 32].into()), 8);
+        assert_eq!(tree.indices_of(&[1; 32].into()), [2]);
+        assert_eq!(tree.index_of(&[8; 32].into()), Some(8));
+
+        let proof = tree.prove_value(&[4; 32].into()).unwrap();
+        assert_eq!(proof.leaf_index(), 4);
+        assert!(verify(tree.tree().root_hash(), &[4; 32].into(), &proof));
+        assert!(tree.prove_value(&[3; 32].into()).is_none());
+    }
+}
@@ -0,0 +1,149 @@
+//! Undo journal for leaf updates
+//!
+//! A `JournaledMerkleTree` records the previous value of every leaf it overwrites. `rollback`
+//! restores the leaves of the last uncommitted updates in reverse order, which restores the root
+//! the tree had before them, `commit` drops the journal and makes the updates final. This supports
+//! optimistic execution: a batch is applied right away and reverted if it turns out to be invalid.
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError, Plain};
+
+/// A Merkle tree that can undo its uncommitted leaf updates
+pub struct JournaledMerkleTree<D: Digest, S = Plain> {
+    tree: MerkleTree<D, S>,
+    /// offset and previous value of every uncommitted update in the order of the updates
+    journal: Vec<(usize, Output<D>)>,
+}
+
+impl<D, S> JournaledMerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// starts journaling the updates of the tree, its current state counts as committed
+    pub fn new(tree: MerkleTree<D, S>) -> Self {
+        Self {
+            tree,
+            journal: Vec::new(),
+        }
+    }
+
+    /// returns the current state of the tree, including uncommitted updates
+    pub fn tree(&self) -> &MerkleTree<D, S> {
+        &self.tree
+    }
+
+    /// returns the tree including uncommitted updates, dropping the journal
+    pub fn into_inner(self) -> MerkleTree<D, S> {
+        self.tree
+    }
+
+    /// returns the number of uncommitted updates
+    pub fn pending(&self) -> usize {
+        self.journal.len()
+    }
+
+    /// updates the value of a leaf node and records its previous value
+    /// panics if the offset is out of bounds, see `try_set` for a fallible version
+    pub fn set(&mut self, offset: usize, value: &Output<D>) {
+        if let Err(err) = self.try_set(offset, value) {
+            panic!("{err}");
+        }
+    }
+
+    /// updates the value of a leaf node and records its previous value
+    /// returns an error if the offset is out of bounds
+    pub fn try_set(&mut self, offset: usize, value: &Output<D>) -> Result<(), MerkleTreeError> {
+        let previous = *self
+            .tree
+            .get(offset)
+            .ok_or(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.tree.num_leaves(),
+            })?;
+        self.tree.try_set(offset, value)?;
+        self.journal.push((offset, previous));
+        Ok(())
+    }
+
+    /// undoes the last `n` uncommitted updates, returns the restored root
+    /// panics if fewer updates are uncommitted, see `try_rollback` for a fallible version
+    pub fn rollback(&mut self, n: usize) -> &Output<D> {
+        if let Err(err) = self.try_rollback(n) {
+            panic!("{err}");
+        }
+        self.tree.root_hash()
+    }
+
+    /// undoes the last `n` uncommitted updates, returns the restored root
+    /// returns an error and leaves the tree untouched if fewer updates are uncommitted
+    pub fn try_rollback(&mut self, n: usize) -> Result<&Output<D>, MerkleTreeError> {
+        if n > self.journal.len() {
+            return Err(MerkleTreeError::RollbackOutOfRange {
+                requested: n,
+                pending: self.journal.len(),
+            });
+        }
+        let start = self.journal.len() - n;
+        for (offset, previous) in self.journal.drain(start..).rev() {
+            self.tree.set(offset, &previous);
+        }
+        Ok(self.tree.root_hash())
+    }
+
+    /// makes all uncommitted updates final, returns their number
+    pub fn commit(&mut self) -> usize {
+        let committed = self.journal.len();
+        self.journal.clear();
+        committed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    type JournaledMerkleTree = super::JournaledMerkleTree<Sha3_256>;
+
+    #[test]
+    fn test_rollback() {
+        let leaves: Vec<Output<Sha3_256>> = (0..8).map(|i| [i as u8; 32].into()).collect();
+        let mut tree = JournaledMerkleTree::new(MerkleTree::from_leaves(&leaves, &[0; 32].into()));
+        let committed = *tree.tree().root_hash();
+
+        tree.set(3, &[0xaa; 32].into());
+        let after_first = *tree.tree().root_hash();
+        tree.set(5, &[0xbb; 32].into());
+        tree.set(3, &[0xcc; 32].into());
+        assert_eq!(tree.pending(), 3);
+
+        // the same leaf updated twice is restored step by step
+        assert_eq!(*tree.rollback(2), after_first);
+        assert_eq!(tree.tree().get(3), Some(&[0xaa; 32].into()));
+        assert_eq!(*tree.rollback(1), committed);
+        assert_eq!(tree.pending(), 0);
+
+        tree.set(0, &[0xdd; 32].into());
+        assert_eq!(tree.commit(), 1);
+        let committed = *tree.tree().root_hash();
+        tree.set(7, &[0xee; 32].into());
+        assert_eq!(
+            tree.try_rollback(2),
+            Err(MerkleTreeError::RollbackOutOfRange {
+                requested: 2,
+                pending: 1
+            })
+        );
+        assert_eq!(*tree.rollback(1), committed);
+        assert!(tree.try_set(8, &[0; 32].into()).is_err());
+        assert_eq!(tree.pending(), 0);
+        assert_eq!(tree.into_inner().get(0), Some(&[0xdd; 32].into()));
+    }
+}
@@ -0,0 +1,340 @@
+//! Merkle trees hashing with a secret key
+//!
+//! Roots of trees over public leaf values can be precomputed by anyone who knows the values.
+//! `KeyedMerkleTree` mixes a secret key into every hash, so roots and proofs can only be computed
+//! and verified by parties holding the key, e.g. one key per deployment. Leaves and nodes are
+//! hashed with HMAC and prefixed like in RFC 6962 (`0x00` for leaves, `0x01` for nodes).
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug};
+
+use digest::core_api::BlockSizeUser;
+use digest::{Digest, Output};
+use hmac::{Mac, SimpleHmac};
+
+use crate::{MerkleTree, MerkleTreeError, Rfc6962, MAX_DEPTH};
+
+/// A secret key hashing leaves and nodes with HMAC
+#[derive(Clone)]
+pub struct HashKey<D: Digest + BlockSizeUser> {
+    /// HMAC initialized with the key, cloned for every hash
+    mac: SimpleHmac<D>,
+}
+
+impl<D: Digest + BlockSizeUser> Debug for HashKey<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HashKey").finish_non_exhaustive()
+    }
+}
+
+impl<D> HashKey<D>
+where
+    D: Digest + BlockSizeUser + Clone,
+{
+    /// creates a key from secret bytes of any length
+    pub fn new(key: &[u8]) -> Self {
+        Self {
+            mac: <SimpleHmac<D> as Mac>::new_from_slice(key)
+                .expect("HMAC accepts keys of any length"),
+        }
+    }
+
+    /// returns the keyed hash of a leaf payload
+    pub fn hash_leaf(&self, data: &[u8]) -> Output<D> {
+        let mut mac = self.mac.clone();
+        mac.update(&[Rfc6962::LEAF_PREFIX]);
+        mac.update(data);
+        mac.finalize().into_bytes()
+    }
+
+    /// returns the keyed hash of an interior node given its children
+    pub fn hash_node(&self, left: &Output<D>, right: &Output<D>) -> Output<D> {
+        let mut mac = self.mac.clone();
+        mac.update(&[Rfc6962::NODE_PREFIX]);
+        mac.update(left);
+        mac.update(right);
+        mac.finalize().into_bytes()
+    }
+}
+
+/// An inclusion proof of a `KeyedMerkleTree`, verified with the key of the tree
+#[derive(Debug, Clone)]
+pub struct KeyedProof<D: Digest> {
+    leaf_index: usize,
+    siblings: Vec<Output<D>>,
+}
+
+impl<D: Digest> PartialEq for KeyedProof<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf_index == other.leaf_index && self.siblings == other.siblings
+    }
+}
+
+impl<D: Digest> Eq for KeyedProof<D> {}
+
+impl<D> KeyedProof<D>
+where
+    D: Digest + BlockSizeUser + Clone,
+{
+    /// creates a proof from the offset of the leaf and the sibling hashes starting at the leaf layer
+    pub fn new(leaf_index: usize, siblings: Vec<Output<D>>) -> Self {
+        Self {
+            leaf_index,
+            siblings,
+        }
+    }
+
+    /// returns the offset of the proven leaf
+    pub fn leaf_index(&self) -> usize {
+        self.leaf_index
+    }
+
+    /// returns the sibling hashes starting at the leaf layer
+    pub fn siblings(&self) -> &[Output<D>] {
+        &self.siblings
+    }
+
+    /// returns the root hash computed from the leaf value and the proof with the given key
+    pub fn compute_root(&self, key: &HashKey<D>, leaf: &Output<D>) -> Output<D> {
+        let mut hash = leaf.clone();
+        for (layer, sibling) in self.siblings.iter().enumerate() {
+            hash = match (self.leaf_index >> layer) & 1 {
+                0 => key.hash_node(&hash, sibling),
+                _ => key.hash_node(sibling, &hash),
+            };
+        }
+        hash
+    }
+
+    /// returns true if the leaf value and the proof hash up to the given root with the given key
+    pub fn verify(&self, key: &HashKey<D>, root: &Output<D>, leaf: &Output<D>) -> bool {
+        self.siblings.len() < MAX_DEPTH
+            && self.leaf_index >> self.siblings.len() == 0
+            && &self.compute_root(key, leaf) == root
+    }
+}
+
+/// A binary Merkle tree hashing its nodes with a secret key
+/// The tree is laid out like `MerkleTree`, leaves are given as leaf hashes of the same key, see
+/// `HashKey::hash_leaf`.
+#[derive(Debug, Clone)]
+pub struct KeyedMerkleTree<D: Digest + BlockSizeUser> {
+    key: HashKey<D>,
+    depth: usize,
+    /// all nodes layer by layer starting at the root
+    nodes: Vec<Output<D>>,
+}
+
+impl<D> KeyedMerkleTree<D>
+where
+    D: Digest + BlockSizeUser + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// creates a new tree of the given depth with all leaves set to the initial value
+    /// panics if the depth is 0 or larger than `MAX_DEPTH`, see `try_new` for a fallible version
+    pub fn new(key: HashKey<D>, depth: usize, initial_value: &Output<D>) -> Self {
+        match Self::try_new(key, depth, initial_value) {
+            Ok(tree) => tree,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a new tree of the given depth with all leaves set to the initial value
+    /// returns an error if the depth is 0 or larger than `MAX_DEPTH`
+    pub fn try_new(
+        key: HashKey<D>,
+        depth: usize,
+        initial_value: &Output<D>,
+    ) -> Result<Self, MerkleTreeError> {
+        if depth == 0 || depth > MAX_DEPTH {
+            return Err(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            });
+        }
+        Ok(Self::build(key, depth, &[], initial_value))
+    }
+
+    /// creates a new tree from the given leaves, padded with the padding value to the next power of
+    /// two like `MerkleTree::from_leaves`
+    pub fn from_leaves(key: HashKey<D>, leaves: &[Output<D>], padding: &Output<D>) -> Self {
+        let depth = leaves.len().max(1).next_power_of_two().trailing_zeros() as usize + 1;
+        Self::build(key, depth, leaves, padding)
+    }
+
+    /// creates a new tree from raw leaf payloads hashed with the key
+    pub fn from_leaf_data<T: AsRef<[u8]>>(
+        key: HashKey<D>,
+        data: &[T],
+        padding: &Output<D>,
+    ) -> Self {
+        let leaves: Vec<Output<D>> = data
+            .iter()
+            .map(|item| key.hash_leaf(item.as_ref()))
+            .collect();
+        Self::from_leaves(key, &leaves, padding)
+    }
+
+    /// returns the key hashing the nodes
+    pub fn key(&self) -> &HashKey<D> {
+        &self.key
+    }
+
+    /// returns the root hash of the tree
+    pub fn root_hash(&self) -> &Output<D> {
+        &self.nodes[0]
+    }
+
+    /// returns the number of leaves in the tree
+    pub fn num_leaves(&self) -> usize {
+        1 << (self.depth - 1)
+    }
+
+    /// returns the leaf at the given offset or None if the offset is out of bounds
+    pub fn get(&self, offset: usize) -> Option<&Output<D>> {
+        if offset >= self.num_leaves() {
+            return None;
+        }
+        Some(&self.nodes[MerkleTree::<D>::index(self.depth - 1, offset)])
+    }
+
+    /// updates the value of a leaf node
+    /// panics if the offset is out of bounds, see `try_set` for a fallible version
+    pub fn set(&mut self, offset: usize, value: &Output<D>) {
+        if let Err(err) = self.try_set(offset, value) {
+            panic!("{err}");
+        }
+    }
+
+    /// updates the value of a leaf node
+    /// returns an error if the offset is out of bounds
+    pub fn try_set(&mut self, offset: usize, value: &Output<D>) -> Result<(), MerkleTreeError> {
+        self.check_offset(offset)?;
+        let mut index = MerkleTree::<D>::index(self.depth - 1, offset);
+        self.nodes[index] = *value;
+        while index > 0 {
+            index = (index - 1) / 2;
+            self.update_node(index);
+        }
+        Ok(())
+    }
+
+    /// creates a proof for a leaf node
+    /// panics if the offset is out of bounds, see `try_create_proof` for a fallible version
+    pub fn create_proof(&self, offset: usize) -> KeyedProof<D> {
+        match self.try_create_proof(offset) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a proof for a leaf node
+    /// returns an error if the offset is out of bounds
+    pub fn try_create_proof(&self, offset: usize) -> Result<KeyedProof<D>, MerkleTreeError> {
+        self.check_offset(offset)?;
+        let siblings = MerkleTree::<D>::sibling_indices(self.depth, offset)
+            .into_iter()
+            .map(|(index, _)| self.nodes[index])
+            .collect();
+        Ok(KeyedProof::new(offset, siblings))
+    }
+
+    fn build(key: HashKey<D>, depth: usize, leaves: &[Output<D>], padding: &Output<D>) -> Self {
+        let mut nodes = vec![*padding; (1 << depth) - 1];
+        let first_leaf = MerkleTree::<D>::index(depth - 1, 0);
+        nodes[first_leaf..first_leaf + leaves.len()].copy_from_slice(leaves);
+        let mut tree = Self { key, depth, nodes };
+        for index in (0..first_leaf).rev() {
+            tree.update_node(index);
+        }
+        tree
+    }
+
+    /// recomputes an intermediate node from its children
+    fn update_node(&mut self, index: usize) {
+        self.nodes[index] = self
+            .key
+            .hash_node(&self.nodes[2 * index + 1], &self.nodes[2 * index + 2]);
+    }
+
+    fn check_offset(&self, offset: usize) -> Result<(), MerkleTreeError> {
+        if offset >= self.num_leaves() {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.num_leaves(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha2::Sha256;
+    use sha3::Sha3_256;
+
+    #[test]
+    fn test_keyed_tree() {
+        let key = HashKey::<Sha3_256>::new(b"deployment secret");
+        let data = ["alice", "bob", "carol"];
+        let padding = [0x00; 32].into();
+        let mut tree = KeyedMerkleTree::from_leaf_data(key.clone(), &data, &padding);
+        assert_eq!(tree.num_leaves(), 4);
+        assert_eq!(tree.get(1), Some(&key.hash_leaf(b"bob")));
+
+        for (offset, item) in data.iter().enumerate() {
+            let proof = tree.create_proof(offset);
+            let leaf = key.hash_leaf(item.as_bytes());
+            assert!(proof.verify(&key, tree.root_hash(), &leaf));
+            assert!(!proof.verify(&key, tree.root_hash(), &key.hash_leaf(b"mallory")));
+        }
+
+        // the same leaves under another key have an unrelated root and proofs don't verify
+        let other = HashKey::<Sha3_256>::new(b"other secret");
+        let other_tree = KeyedMerkleTree::from_leaf_data(other.clone(), &data, &padding);
+        assert_ne!(other_tree.root_hash(), tree.root_hash());
+        let proof = tree.create_proof(0);
+        assert!(!proof.verify(&other, tree.root_hash(), &key.hash_leaf(b"alice")));
+        // nor do they match an unkeyed tree
+        assert_ne!(
+            tree.root_hash(),
+            MerkleTree::<Sha3_256>::from_leaf_data(&data, &padding).root_hash()
+        );
+
+        tree.set(3, &key.hash_leaf(b"dave"));
+        let rebuilt = KeyedMerkleTree::from_leaf_data(
+            key.clone(),
+            &["alice", "bob", "carol", "dave"],
+            &padding,
+        );
+        assert_eq!(tree.root_hash(), rebuilt.root_hash());
+        assert!(tree.try_set(4, &padding).is_err());
+        assert!(tree.try_create_proof(4).is_err());
+        assert!(KeyedMerkleTree::try_new(key, 0, &padding).is_err());
+    }
+
+    #[test]
+    fn test_hmac() {
+        // RFC 4231 test case 2 with the leaf prefix prepended to the message
+        let key = HashKey::<Sha256>::new(b"Jefe");
+        let expected = {
+            let mut mac = <SimpleHmac<Sha256> as Mac>::new_from_slice(b"Jefe").unwrap();
+            mac.update(b"\x00what do ya want for nothing?");
+            mac.finalize().into_bytes()
+        };
+        assert_eq!(key.hash_leaf(b"what do ya want for nothing?"), expected);
+        assert_eq!(
+            hex::encode(
+                <SimpleHmac<Sha256> as Mac>::new_from_slice(b"Jefe")
+                    .unwrap()
+                    .chain_update(b"what do ya want for nothing?")
+                    .finalize()
+                    .into_bytes()
+            ),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! Multi-lane SHA-256 for rebuilding layers
+//!
+//! `MultiLane<S>` hashes like the scheme `S` (`Plain` or `Rfc6962`) over SHA-256 but rebuilds whole
+//! layers, in construction, pushes growing the tree and deferred recomputation, `LANES` pairs at a
+//! time: the compression function runs on `LANES` messages at once with every 32 bit word of the
+//! state held in an array of one word per lane, which the compiler turns into vector
+//! instructions. On x86-64 with `std` the AVX2 version is selected at runtime.
+//!
+//! CPUs with the SHA extensions hash a single message faster than the lanes hash a batch, so on
+//! those the scalar `Digest` path is taken, as it is for single updates like `set`. Trees and
+//! proofs are the same as with `S`, so are the parameter fingerprints.
+
+use core::marker::PhantomData;
+
+use digest::Output;
+use sha2::Sha256;
+
+use crate::{HashScheme, Plain, Rfc6962};
+
+/// number of pairs hashed at once
+pub const LANES: usize = 8;
+
+/// one 32 bit word per lane
+type Words = [u32; LANES];
+
+/// A scheme hashing like `S` with multi-lane SHA-256 for whole layers, see the module docs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MultiLane<S>(PhantomData<S>);
+
+/// the node prefix of the schemes supported by `MultiLane`
+pub trait NodePrefix {
+    /// the byte hashed before the children of every node, if any
+    const NODE_PREFIX: Option<u8>;
+}
+
+impl NodePrefix for Plain {
+    const NODE_PREFIX: Option<u8> = None;
+}
+
+impl NodePrefix for Rfc6962 {
+    const NODE_PREFIX: Option<u8> = Some(Rfc6962::NODE_PREFIX);
+}
+
+impl<S: HashScheme<Sha256> + NodePrefix> HashScheme<Sha256> for MultiLane<S> {
+    const NAME: &'static str = S::NAME;
+
+    fn hash_leaf(data: &[u8]) -> Output<Sha256> {
+        S::hash_leaf(data)
+    }
+
+    fn hash_node(left: &Output<Sha256>, right: &Output<Sha256>) -> Output<Sha256> {
+        S::hash_node(left, right)
+    }
+
+    fn hash_nodes(children: &[Output<Sha256>], parents: &mut [Output<Sha256>]) {
+        if has_sha_extensions() {
+            for (parent, pair) in parents.iter_mut().zip(children.chunks_exact(2)) {
+                *parent = S::hash_node(&pair[0], &pair[1]);
+            }
+            return;
+        }
+        hash_nodes_lanes(S::NODE_PREFIX, children, parents);
+        // the pairs left over after the last full batch
+        let done = parents.len() / LANES * LANES;
+        for (parent, pair) in parents[done..]
+            .iter_mut()
+            .zip(children[2 * done..].chunks_exact(2))
+        {
+            *parent = S::hash_node(&pair[0], &pair[1]);
+        }
+    }
+
+    fn empty_root() -> Output<Sha256> {
+        S::empty_root()
+    }
+}
+
+/// returns true if the CPU has instructions for SHA-256, which beat the lanes
+fn has_sha_extensions() -> bool {
+    #[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        std::is_x86_feature_detected!("sha")
+    }
+    #[cfg(all(feature = "std", target_arch = "aarch64"))]
+    {
+        std::arch::is_aarch64_feature_detected!("sha2")
+    }
+    #[cfg(not(all(
+        feature = "std",
+        any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    {
+        false
+    }
+}
+
+/// hashes all full batches of `LANES` pairs with the widest available instructions
+fn hash_nodes_lanes(
+    prefix: Option<u8>,
+    children: &[Output<Sha256>],
+    parents: &mut [Output<Sha256>],
+) {
+    #[cfg(all(feature = "std", target_arch = "x86_64"))]
+    if std::is_x86_feature_detected!("avx2") {
+        // SAFETY: the CPU supports AVX2
+        unsafe { hash_batches_avx2(prefix, children, parents) };
+        return;
+    }
+    hash_batches(prefix, children, parents);
+}
+
+/// `hash_batches` compiled with AVX2
+#[cfg(all(feature = "std", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn hash_batches_avx2(
+    prefix: Option<u8>,
+    children: &[Output<Sha256>],
+    parents: &mut [Output<Sha256>],
+) {
+    hash_batches(prefix, children, parents);
+}
+
+/// the initial state of SHA-256
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// the round constants of SHA-256
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// hashes the pairs of every full batch of `LANES` parents, the remaining parents are left alone
+#[inline(always)]
+fn hash_batches(prefix: Option<u8>, children: &[Output<Sha256>], parents: &mut [Output<Sha256>]) {
+    // the message of a node, the optional prefix and both children, fits into two blocks with
+    // its padding
+    let len = usize::from(prefix.is_some()) + 64;
+    let mut message = [[0u8; 128]; LANES];
+    for block in message.iter_mut() {
+        block[len] = 0x80;
+        block[120..].copy_from_slice(&((len as u64) * 8).to_be_bytes());
+    }
+    for (parents, children) in parents
+        .chunks_exact_mut(LANES)
+        .zip(children.chunks_exact(2 * LANES))
+    {
+        for (block, pair) in message.iter_mut().zip(children.chunks_exact(2)) {
+            let start = match prefix {
+                Some(prefix) => {
+                    block[0] = prefix;
+                    1
+                }
+                None => 0,
+            };
+            block[start..start + 32].copy_from_slice(&pair[0]);
+            block[start + 32..start + 64].copy_from_slice(&pair[1]);
+        }
+        let mut state = H0.map(|word| [word; LANES]);
+        for half in 0..2 {
+            let mut words = [[0u32; LANES]; 16];
+            for (i, word) in words.iter_mut().enumerate() {
+                for (lane, block) in message.iter().enumerate() {
+                    let at = half * 64 + i * 4;
+                    word[lane] = u32::from_be_bytes(block[at..at + 4].try_into().unwrap());
+                }
+            }
+            compress(&mut state, &words);
+        }
+        for (lane, parent) in parents.iter_mut().enumerate() {
+            for (i, word) in state.iter().enumerate() {
+                parent[i * 4..i * 4 + 4].copy_from_slice(&word[lane].to_be_bytes());
+            }
+        }
+    }
+}
+
+/// runs the SHA-256 compression function on one block of every lane
+#[inline(always)]
+fn compress(state: &mut [Words; 8], block: &[Words; 16]) {
+    let mut w = [[0u32; LANES]; 64];
+    w[..16].copy_from_slice(block);
+    for i in 16..64 {
+        let s0 = xor3(rotr(w[i - 15], 7), rotr(w[i - 15], 18), shr(w[i - 15], 3));
+        let s1 = xor3(rotr(w[i - 2], 17), rotr(w[i - 2], 19), shr(w[i - 2], 10));
+        w[i] = add(add(w[i - 16], s0), add(w[i - 7], s1));
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for i in 0..64 {
+        let s1 = xor3(rotr(e, 6), rotr(e, 11), rotr(e, 25));
+        let ch = lanes(|l| (e[l] & f[l]) ^ (!e[l] & g[l]));
+        let t1 = add(add(h, s1), add(ch, add([K[i]; LANES], w[i])));
+        let s0 = xor3(rotr(a, 2), rotr(a, 13), rotr(a, 22));
+        let maj = lanes(|l| (a[l] & b[l]) ^ (a[l] & c[l]) ^ (b[l] & c[l]));
+        let t2 = add(s0, maj);
+        h = g;
+        g = f;
+        f = e;
+        e = add(d, t1);
+        d = c;
+        c = b;
+        b = a;
+        a = add(t1, t2);
+    }
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = add(*word, value);
+    }
+}
+
+#[inline(always)]
+fn lanes(f: impl Fn(usize) -> u32) -> Words {
+    core::array::from_fn(f)
+}
+
+#[inline(always)]
+fn add(x: Words, y: Words) -> Words {
+    lanes(|l| x[l].wrapping_add(y[l]))
+}
+
+#[inline(always)]
+fn rotr(x: Words, n: u32) -> Words {
+    lanes(|l| x[l].rotate_right(n))
+}
+
+#[inline(always)]
+fn shr(x: Words, n: u32) -> Words {
+    lanes(|l| x[l] >> n)
+}
+
+#[inline(always)]
+fn xor3(x: Words, y: Words, z: Words) -> Words {
+    lanes(|l| x[l] ^ y[l] ^ z[l])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha2::Digest;
+
+    #[test]
+    fn test_lanes_match_scalar_hashing() {
+        for len in [1, 7, 8, 9, 16, 21] {
+            let children: Vec<Output<Sha256>> = (0..2 * len as u32)
+                .map(|i| Sha256::digest(i.to_le_bytes()))
+                .collect();
+            let mut expected = vec![Output::<Sha256>::default(); len];
+            <Rfc6962 as HashScheme<Sha256>>::hash_nodes(&children, &mut expected);
+
+            let mut parents = vec![Output::<Sha256>::default(); len];
+            hash_nodes_lanes(Some(Rfc6962::NODE_PREFIX), &children, &mut parents);
+            // only full batches are hashed by the lanes
+            let done = len / LANES * LANES;
+            assert_eq!(parents[..done], expected[..done]);
+
+            let mut parents = vec![Output::<Sha256>::default(); len];
+            <MultiLane<Rfc6962> as HashScheme<Sha256>>::hash_nodes(&children, &mut parents);
+            assert_eq!(parents, expected);
+
+            <Plain as HashScheme<Sha256>>::hash_nodes(&children, &mut expected);
+            hash_batches(None, &children, &mut parents);
+            assert_eq!(parents[..done], expected[..done]);
+        }
+    }
+
+    #[test]
+    fn test_multi_lane_tree() {
+        let leaves: Vec<Output<Sha256>> = (0..100u32)
+            .map(|i| Sha256::digest(i.to_le_bytes()))
+            .collect();
+        let tree =
+            crate::MerkleTree::<Sha256, MultiLane<Plain>>::from_leaves(&leaves, &[0; 32].into());
+        let expected = crate::MerkleTree::<Sha256>::from_leaves(&leaves, &[0; 32].into());
+        assert_eq!(tree.root_hash(), expected.root_hash());
+        assert_eq!(tree.check_integrity(), Ok(()));
+    }
+}
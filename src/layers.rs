@@ -0,0 +1,401 @@
+//! Layer files for static proof hosting
+//!
+//! `MerkleTree::export_layer` writes all hashes of one layer into a compact blob that can be
+//! served as a static file. Clients download the layers (or segments of them) and assemble proofs
+//! offline with a `LayerProver`, which only needs the trusted root.
+//!
+//! A layer blob consists of the magic bytes `MTLY`, a format version byte, the hash size in bytes,
+//! the layer (0 is the root layer), the offset of the first hash as little endian u64 and the
+//! concatenated hashes.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError, Plain, Proof, MAX_DEPTH};
+
+/// magic bytes at the start of a layer blob
+const LAYER_MAGIC: &[u8; 4] = b"MTLY";
+/// version of the layer blob format
+const FORMAT_VERSION: u8 = 1;
+/// length of the layer blob header
+const HEADER_LEN: usize = 4 + 1 + 1 + 1 + 8;
+
+/// A contiguous run of hashes from one layer of a tree
+#[derive(Debug, Clone)]
+pub struct LayerSegment<D: Digest> {
+    /// layer the hashes belong to, 0 is the root layer
+    pub layer: usize,
+    /// offset of the first hash within the layer
+    pub offset: usize,
+    /// hashes of the segment
+    pub hashes: Vec<Output<D>>,
+}
+
+impl<D: Digest> PartialEq for LayerSegment<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.layer == other.layer && self.offset == other.offset && self.hashes == other.hashes
+    }
+}
+
+impl<D: Digest> Eq for LayerSegment<D> {}
+
+impl<D: Digest> LayerSegment<D> {
+    /// encodes the segment as a layer blob
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let hash_size = <D as Digest>::output_size();
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.hashes.len() * hash_size);
+        bytes.extend_from_slice(LAYER_MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.push(hash_size as u8);
+        bytes.push(self.layer as u8);
+        bytes.extend_from_slice(&(self.offset as u64).to_le_bytes());
+        for hash in &self.hashes {
+            bytes.extend_from_slice(hash);
+        }
+        bytes
+    }
+
+    /// decodes a layer blob
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MerkleTreeError> {
+        let hash_size = <D as Digest>::output_size();
+        if bytes.len() < HEADER_LEN
+            || &bytes[..4] != LAYER_MAGIC
+            || bytes[4] != FORMAT_VERSION
+            || bytes[5] as usize != hash_size
+        {
+            return Err(MerkleTreeError::InvalidLayerEncoding);
+        }
+        let layer = bytes[6] as usize;
+        let offset = u64::from_le_bytes(bytes[7..HEADER_LEN].try_into().unwrap());
+        let hashes = &bytes[HEADER_LEN..];
+        if layer >= MAX_DEPTH || !hashes.len().is_multiple_of(hash_size) {
+            return Err(MerkleTreeError::InvalidLayerEncoding);
+        }
+        let count = hashes.len() / hash_size;
+        let offset = usize::try_from(offset).map_err(|_| MerkleTreeError::InvalidLayerEncoding)?;
+        if offset.checked_add(count).is_none_or(|end| end > 1 << layer) {
+            return Err(MerkleTreeError::InvalidLayerEncoding);
+        }
+        Ok(Self {
+            layer,
+            offset,
+            hashes: hashes
+                .chunks_exact(hash_size)
+                .map(Output::<D>::clone_from_slice)
+                .collect(),
+        })
+    }
+}
+
+impl<D, S> MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// returns all hashes of a layer as a layer blob, layer 0 holds the root
+    /// returns an error if the tree has no such layer
+    pub fn export_layer(&self, layer: usize) -> Result<Vec<u8>, MerkleTreeError> {
+        let width = if layer < self.depth() { 1 << layer } else { 0 };
+        self.layer_segment(layer, 0, width)
+            .map(|segment| segment.to_bytes())
+    }
+
+    /// returns `count` hashes of a layer starting at `offset`, layer 0 holds the root
+    /// returns an error if the tree has no such layer or the range is out of bounds
+    pub fn layer_segment(
+        &self,
+        layer: usize,
+        offset: usize,
+        count: usize,
+    ) -> Result<LayerSegment<D>, MerkleTreeError> {
+        if layer >= self.depth() {
+            return Err(MerkleTreeError::LayerOutOfRange {
+                layer,
+                depth: self.depth(),
+            });
+        }
+        let width = 1 << layer;
+        let end = offset.saturating_add(count);
+        if end > width {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: end - 1,
+                num_leaves: width,
+            });
+        }
+        let start = Self::index(layer, offset);
+        Ok(LayerSegment {
+            layer,
+            offset,
+            hashes: (start..start + count)
+                .map(|index| *self.node(index))
+                .collect(),
+        })
+    }
+}
+
+/// Assembles and verifies proofs from downloaded layer segments
+/// The scheme has to be the one of the exported tree, the proofs are verified with it.
+pub struct LayerProver<D: Digest, S = Plain> {
+    /// trusted root of the tree
+    root: Output<D>,
+    /// depth of the tree
+    depth: usize,
+    /// downloaded hashes by node index
+    nodes: BTreeMap<usize, Output<D>>,
+    scheme: PhantomData<fn() -> S>,
+}
+
+impl<D, S> LayerProver<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a prover for the tree with the given trusted root and depth
+    /// returns an error if the depth is not between 1 and `MAX_DEPTH`
+    pub fn new(root: &Output<D>, depth: usize) -> Result<Self, MerkleTreeError> {
+        if !(1..=MAX_DEPTH).contains(&depth) {
+            return Err(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            });
+        }
+        Ok(Self {
+            root: *root,
+            depth,
+            nodes: BTreeMap::new(),
+            scheme: PhantomData,
+        })
+    }
+
+    /// adds the hashes of a downloaded segment
+    /// returns an error if the segment doesn't fit into the tree
+    pub fn add_segment(&mut self, segment: &LayerSegment<D>) -> Result<(), MerkleTreeError> {
+        if segment.layer >= self.depth {
+            return Err(MerkleTreeError::LayerOutOfRange {
+                layer: segment.layer,
+                depth: self.depth,
+            });
+        }
+        let width = 1 << segment.layer;
+        let end = segment.offset.saturating_add(segment.hashes.len());
+        if end > width {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: end - 1,
+                num_leaves: width,
+            });
+        }
+        let first = MerkleTree::<D>::index(segment.layer, segment.offset);
+        for (index, hash) in (first..).zip(&segment.hashes) {
+            self.nodes.insert(index, *hash);
+        }
+        Ok(())
+    }
+
+    /// decodes a downloaded layer blob and adds its hashes
+    pub fn add_layer_blob(&mut self, bytes: &[u8]) -> Result<(), MerkleTreeError> {
+        self.add_segment(&LayerSegment::from_bytes(bytes)?)
+    }
+
+    /// returns the (layer, offset) pairs of all nodes needed to prove a leaf: the leaf itself and
+    /// its siblings starting at the leaf layer
+    pub fn required_nodes(&self, offset: usize) -> Vec<(usize, usize)> {
+        let leaf_layer = self.depth - 1;
+        core::iter::once((leaf_layer, offset))
+            .chain((1..self.depth).rev().map(|layer| {
+                let node = offset >> (leaf_layer - layer);
+                (layer, node ^ 1)
+            }))
+            .collect()
+    }
+
+    /// returns the (layer, offset) pairs of the nodes needed to prove a leaf that were not added yet
+    pub fn missing_nodes(&self, offset: usize) -> Vec<(usize, usize)> {
+        self.required_nodes(offset)
+            .into_iter()
+            .filter(|&(layer, offset)| {
+                !self
+                    .nodes
+                    .contains_key(&MerkleTree::<D>::index(layer, offset))
+            })
+            .collect()
+    }
+
+    /// returns the downloaded value of a leaf
+    pub fn leaf(&self, offset: usize) -> Option<&Output<D>> {
+        self.check_offset(offset).ok()?;
+        self.nodes
+            .get(&MerkleTree::<D>::index(self.depth - 1, offset))
+    }
+
+    /// assembles the proof of a leaf from the downloaded hashes and verifies it against the
+    /// trusted root
+    /// returns an error if a hash is missing or the downloaded hashes don't lead to the root
+    pub fn create_proof(&self, offset: usize) -> Result<Proof<D, S>, MerkleTreeError> {
+        self.check_offset(offset)?;
+        let mut nodes = self
+            .required_nodes(offset)
+            .into_iter()
+            .map(|(layer, offset)| {
+                self.nodes
+                    .get(&MerkleTree::<D>::index(layer, offset))
+                    .copied()
+                    .ok_or(MerkleTreeError::MissingNode { layer, offset })
+            });
+        let leaf = nodes.next().unwrap()?;
+        let proof = Proof::new(offset, nodes.collect::<Result<_, _>>()?);
+        if proof.compute_root(&leaf) != self.root {
+            return Err(MerkleTreeError::RootMismatch);
+        }
+        Ok(proof)
+    }
+
+    fn check_offset(&self, offset: usize) -> Result<(), MerkleTreeError> {
+        let num_leaves = 1 << (self.depth - 1);
+        if offset >= num_leaves {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds { offset, num_leaves });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    use crate::Rfc6962;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type LayerSegment = super::LayerSegment<Sha3_256>;
+
+    fn test_tree() -> MerkleTree {
+        let leaves: Vec<Output<Sha3_256>> = (0..8).map(|i| [i as u8; 32].into()).collect();
+        MerkleTree::from_leaves(&leaves, &[0x00; 32].into())
+    }
+
+    #[test]
+    fn test_export_layer() {
+        let tree = test_tree();
+        let root = LayerSegment::from_bytes(&tree.export_layer(0).unwrap()).unwrap();
+        assert_eq!(root.hashes, [*tree.root_hash()]);
+
+        let bytes = tree.export_layer(3).unwrap();
+        assert_eq!(bytes.len(), HEADER_LEN + 8 * 32);
+        let leaves = LayerSegment::from_bytes(&bytes).unwrap();
+        assert_eq!(leaves.layer, 3);
+        assert_eq!(leaves.offset, 0);
+        assert_eq!(leaves.hashes[5], [5; 32].into());
+
+        assert_eq!(
+            tree.export_layer(4),
+            Err(MerkleTreeError::LayerOutOfRange { layer: 4, depth: 4 })
+        );
+    }
+
+    #[test]
+    fn test_segments() {
+        let tree = test_tree();
+        let segment = tree.layer_segment(2, 1, 2).unwrap();
+        assert_eq!(segment.hashes.len(), 2);
+        assert_eq!(LayerSegment::from_bytes(&segment.to_bytes()), Ok(segment));
+        assert!(tree.layer_segment(2, 3, 2).is_err());
+
+        let bytes = tree.export_layer(2).unwrap();
+        assert_eq!(
+            LayerSegment::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(MerkleTreeError::InvalidLayerEncoding)
+        );
+        let mut too_long = bytes.clone();
+        too_long.extend_from_slice(&[0; 32]);
+        assert_eq!(
+            LayerSegment::from_bytes(&too_long),
+            Err(MerkleTreeError::InvalidLayerEncoding)
+        );
+        let mut wrong_magic = bytes;
+        wrong_magic[0] = b'X';
+        assert_eq!(
+            LayerSegment::from_bytes(&wrong_magic),
+            Err(MerkleTreeError::InvalidLayerEncoding)
+        );
+    }
+
+    #[test]
+    fn test_layer_prover() {
+        let tree = test_tree();
+        let mut prover = LayerProver::new(tree.root_hash(), 4).unwrap();
+        assert_eq!(prover.required_nodes(5), [(3, 5), (3, 4), (2, 3), (1, 0)]);
+        assert_eq!(
+            prover.create_proof(5),
+            Err(MerkleTreeError::MissingNode {
+                layer: 3,
+                offset: 5
+            })
+        );
+
+        // whole layers served as static files
+        for layer in 1..4 {
+            prover
+                .add_layer_blob(&tree.export_layer(layer).unwrap())
+                .unwrap();
+        }
+        for offset in 0..8 {
+            assert!(prover.missing_nodes(offset).is_empty());
+            let proof = prover.create_proof(offset).unwrap();
+            assert_eq!(proof, tree.create_proof(offset));
+            assert_eq!(prover.leaf(offset), Some(&[offset as u8; 32].into()));
+        }
+        assert!(prover.create_proof(8).is_err());
+    }
+
+    #[test]
+    fn test_layer_prover_segments() {
+        let tree = test_tree();
+        let mut prover = LayerProver::new(tree.root_hash(), 4).unwrap();
+        for (layer, offset) in prover.required_nodes(2) {
+            prover
+                .add_segment(&tree.layer_segment(layer, offset, 1).unwrap())
+                .unwrap();
+        }
+        assert_eq!(prover.create_proof(2), Ok(tree.create_proof(2)));
+
+        // tampered downloads are detected
+        prover
+            .add_segment(&LayerSegment {
+                layer: 3,
+                offset: 3,
+                hashes: vec![[0xff; 32].into()],
+            })
+            .unwrap();
+        assert_eq!(prover.create_proof(2), Err(MerkleTreeError::RootMismatch));
+
+        // the prover hashes with the scheme of the exported tree
+        let leaves: Vec<Output<Sha3_256>> = (0..8).map(|i| [i as u8; 32].into()).collect();
+        let tree = crate::MerkleTree::<Sha3_256, Rfc6962>::from_leaves(&leaves, &[0; 32].into());
+        let mut prover = LayerProver::<Sha3_256, Rfc6962>::new(tree.root_hash(), 4).unwrap();
+        for layer in 1..4 {
+            prover
+                .add_layer_blob(&tree.export_layer(layer).unwrap())
+                .unwrap();
+        }
+        assert_eq!(prover.create_proof(6), Ok(tree.create_proof(6)));
+
+        assert!(prover
+            .add_segment(
+                &tree
+                    .layer_segment(3, 0, 1)
+                    .map(|mut segment| {
+                        segment.layer = 4;
+                        segment
+                    })
+                    .unwrap()
+            )
+            .is_err());
+    }
+}
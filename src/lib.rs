@@ -1,3 +1,162 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod accumulator;
+#[cfg(feature = "std")]
+pub mod anchor;
+#[cfg(feature = "std")]
+pub mod append;
+#[cfg(feature = "std")]
+pub mod archive;
+pub mod arena;
+pub mod batch;
+#[cfg(feature = "bench")]
+pub mod bench;
+#[cfg(any(feature = "uniffi", feature = "wasm"))]
+pub mod bindings;
+pub mod bitcoin;
+pub mod bittorrent;
+pub mod builder;
+pub mod cancel;
+#[cfg(feature = "std")]
+pub mod concurrent;
+pub mod counting;
+#[cfg(feature = "std")]
+pub mod custody;
+pub mod deferred;
+mod error;
+pub mod ethereum;
+#[cfg(feature = "std")]
+mod export;
+mod hex_strings;
+pub mod indexed;
+pub mod journal;
+pub mod keyed;
+#[cfg(feature = "simd")]
+pub mod lanes;
+pub mod layers;
+pub mod log;
+pub mod merkle_map;
 pub mod merkle_tree;
+#[cfg(feature = "mmap")]
+mod mmap;
+pub mod mmr;
+pub mod nary;
+pub mod node_hasher;
+mod nodes;
+pub mod observed;
+pub mod padding;
+pub mod partial;
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
+#[cfg(feature = "plugin")]
+pub mod plugin;
+#[cfg(feature = "std")]
+pub mod policy;
+mod population;
+#[cfg(feature = "poseidon")]
+pub mod poseidon;
+pub mod pruned;
+#[cfg(feature = "std")]
+pub mod publish;
+pub mod render;
+pub mod root_history;
+#[cfg(feature = "std")]
+pub mod root_index;
+pub mod scheme;
+#[cfg(feature = "serde")]
+mod serde_impl;
+#[cfg(feature = "std")]
+pub mod shard;
+pub mod small;
+pub mod snapshot;
+pub mod solidity;
+#[cfg(feature = "std")]
+pub mod storage;
+pub mod stream;
+pub mod sync;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+#[cfg(feature = "std")]
+pub mod verifiable_kv;
+#[cfg(feature = "std")]
+pub mod versioned;
+#[cfg(feature = "mmap")]
+mod wal;
+
+pub use accumulator::MerkleAccumulator;
+#[cfg(feature = "std")]
+pub use anchor::{Anchor, AnchorError, AnchorRef, AnchorSet, AnchorSink, MultiAnchor};
+#[cfg(feature = "std")]
+pub use append::ConcurrentAppender;
+#[cfg(feature = "std")]
+pub use archive::{Archive, ArchiveError, RehashPlan};
+pub use arena::{ArenaTree, TreeArena};
+#[cfg(feature = "rayon")]
+pub use batch::par_verify_proofs_batch;
+pub use batch::verify_proofs_batch;
+pub use builder::{BuildError, MerkleTreeBuilder, StorageBackend};
+pub use cancel::{CancellationToken, Progress};
+#[cfg(feature = "std")]
+pub use concurrent::ConcurrentMerkleTree;
+pub use counting::{CompletenessProof, CountingProof, CountingTree, RankProof};
+#[cfg(feature = "std")]
+pub use custody::{
+    CustodyLog, EvidenceBundle, EvidenceError, SignError, SignedTreeHead, SthSigner, SthVerifier,
+};
+pub use deferred::DeferredMerkleTree;
+pub use error::MerkleTreeError;
+pub use hex_strings::{hash_from_hex, to_hex};
+pub use indexed::IndexedMerkleTree;
+pub use journal::JournaledMerkleTree;
+pub use keyed::{HashKey, KeyedMerkleTree, KeyedProof};
+#[cfg(feature = "simd")]
+pub use lanes::{MultiLane, NodePrefix, LANES};
+pub use layers::{LayerProver, LayerSegment};
+pub use log::{ConsistencyProof, InclusionProof, LogTree};
+pub use merkle_map::{MapProof, MerkleMap};
+pub use merkle_tree::{
+    verify, verify_non_inclusion, verify_proof_ct, verify_range_proof, MerkleTree, MultiProof,
+    NonInclusionProof, Proof, ProofDelta, ProofUpdate, RangeProof, MAX_DEPTH,
+};
+pub use mmr::{Mmr, MmrProof};
+pub use nary::{NaryLevel, NaryMerkleTree, NaryProof};
+pub use node_hasher::{DigestHasher, HasherMerkleTree, HasherProof, NodeHasher};
+pub use observed::{ObservedMerkleTree, RootChange};
+pub use padding::{PaddedMerkleTree, Padding};
+pub use partial::PartialTree;
+#[cfg(feature = "pkcs11")]
+pub use pkcs11::{Pkcs11Error, Pkcs11Hasher, Pkcs11Sessions, Pkcs11Signer};
+#[cfg(feature = "plugin")]
+pub use plugin::{HasherPlugin, HasherVTable, PluginError, PluginMerkleTree, PluginProof};
+#[cfg(feature = "std")]
+pub use policy::{PolicyError, PolicyVerifier};
+#[cfg(feature = "poseidon")]
+pub use poseidon::{PoseidonBn254, PoseidonMerkleTree, PoseidonProof};
+pub use pruned::PrunedMerkleTree;
+#[cfg(feature = "std")]
+pub use publish::{
+    ContractSink, FileSink, HttpSink, Publication, PublicationPolicy, PublishError, Publisher,
+    RootSink, SinkError,
+};
+pub use render::{TreeRender, DEFAULT_RENDER_DEPTH};
+pub use root_history::{RootHistoryTree, RootRecord};
+#[cfg(feature = "std")]
+pub use root_index::{RootBloom, RootIndex, RootMembershipProof};
+pub use scheme::{DoubleHash, HashScheme, Plain, Rfc6962, SortedPair};
+#[cfg(feature = "std")]
+pub use shard::{BuildCoordinator, PackageError, SubtreePackage};
+pub use small::{SmallMerkleTree, SMALL_TREE_MAX_LEAVES};
+pub use snapshot::TreeVersion;
+#[cfg(feature = "std")]
+pub use storage::{DeadlineError, MemoryStore, NodeStore, StoredMerkleTree, StoredTreeError};
+pub use stream::StreamingRoot;
+pub use sync::{differing_ranges, RemotePeer, SyncError};
+#[cfg(feature = "std")]
+pub use verifiable_kv::{KvSnapshot, VerifiableKv, VerifiedRead};
+#[cfg(feature = "std")]
+pub use versioned::VersionedMerkleTree;
 
-pub use merkle_tree::MerkleTree;
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
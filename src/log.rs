@@ -0,0 +1,604 @@
+//! Append-only log trees
+//!
+//! Unlike `MerkleTree`, a `LogTree` holds any number of leaves. Trees whose size is not a power of
+//! two are split at the largest power of two smaller than their size, as specified in RFC 6962.
+//! With the default `Rfc6962` scheme roots and inclusion proofs match Certificate Transparency logs.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+
+use digest::{Digest, Output};
+
+use crate::scheme::{HashScheme, Rfc6962};
+
+/// An append-only Merkle tree of arbitrary size
+pub struct LogTree<D: Digest, S = Rfc6962> {
+    /// hashes of all complete subtrees, `layers[k][i]` covers the leaves `i * 2^k..(i + 1) * 2^k`
+    layers: Vec<Vec<Output<D>>>,
+    scheme: PhantomData<S>,
+}
+
+/// A proof that a leaf is included in a log tree of a certain size
+#[derive(Debug, Clone)]
+pub struct InclusionProof<D: Digest, S = Rfc6962> {
+    /// index of the proven leaf
+    leaf_index: usize,
+    /// size of the tree the proof was created for
+    tree_size: usize,
+    /// hashes of the audit path starting at the leaf layer
+    path: Vec<Output<D>>,
+    scheme: PhantomData<S>,
+}
+
+impl<D: Digest, S> PartialEq for InclusionProof<D, S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf_index == other.leaf_index
+            && self.tree_size == other.tree_size
+            && self.path == other.path
+    }
+}
+
+impl<D: Digest, S> Eq for InclusionProof<D, S> {}
+
+impl<D, S> InclusionProof<D, S>
+where
+    D: Digest,
+    S: HashScheme<D>,
+{
+    /// creates a proof from the index of the proven leaf, the size of the tree and the audit path
+    pub fn new(leaf_index: usize, tree_size: usize, path: Vec<Output<D>>) -> Self {
+        Self {
+            leaf_index,
+            tree_size,
+            path,
+            scheme: PhantomData,
+        }
+    }
+
+    /// returns the index of the proven leaf
+    pub fn leaf_index(&self) -> usize {
+        self.leaf_index
+    }
+
+    /// returns the size of the tree the proof was created for
+    pub fn tree_size(&self) -> usize {
+        self.tree_size
+    }
+
+    /// returns the hashes of the audit path starting at the leaf layer
+    pub fn path(&self) -> &[Output<D>] {
+        &self.path
+    }
+
+    /// verifies the proof for a leaf hash against the root of a tree of `tree_size()` leaves
+    pub fn verify(&self, leaf_hash: &Output<D>, root: &Output<D>) -> bool {
+        self.compute_root(leaf_hash).as_ref() == Some(root)
+    }
+
+    /// verifies the proof for a leaf payload against the root of a tree of `tree_size()` leaves
+    pub fn verify_data(&self, data: &[u8], root: &Output<D>) -> bool {
+        self.verify(&S::hash_leaf(data), root)
+    }
+
+    /// computes the root implied by the proof and a leaf hash, see RFC 9162 section 2.1.3.2
+    /// returns None if the audit path doesn't fit the leaf index and tree size
+    pub fn compute_root(&self, leaf_hash: &Output<D>) -> Option<Output<D>> {
+        if self.leaf_index >= self.tree_size {
+            return None;
+        }
+        let mut index = self.leaf_index;
+        let mut last = self.tree_size - 1;
+        let mut hash = leaf_hash.clone();
+        for sibling in &self.path {
+            if last == 0 {
+                return None;
+            }
+            if index & 1 == 1 || index == last {
+                hash = S::hash_node(sibling, &hash);
+                // skip the layers on which the node has no right sibling
+                while index & 1 == 0 && index != 0 {
+                    index >>= 1;
+                    last >>= 1;
+                }
+            } else {
+                hash = S::hash_node(&hash, sibling);
+            }
+            index >>= 1;
+            last >>= 1;
+        }
+        (last == 0).then_some(hash)
+    }
+}
+
+/// A proof that a log tree of a certain size is a prefix of a larger log tree
+#[derive(Debug, Clone)]
+pub struct ConsistencyProof<D: Digest, S = Rfc6962> {
+    /// size of the old tree
+    old_size: usize,
+    /// size of the new tree
+    new_size: usize,
+    /// hashes of the consistency path, see RFC 9162 section 2.1.4
+    path: Vec<Output<D>>,
+    scheme: PhantomData<S>,
+}
+
+impl<D: Digest, S> PartialEq for ConsistencyProof<D, S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.old_size == other.old_size
+            && self.new_size == other.new_size
+            && self.path == other.path
+    }
+}
+
+impl<D: Digest, S> Eq for ConsistencyProof<D, S> {}
+
+impl<D, S> ConsistencyProof<D, S>
+where
+    D: Digest,
+    S: HashScheme<D>,
+{
+    /// creates a proof from the sizes of the old and the new tree and the consistency path
+    pub fn new(old_size: usize, new_size: usize, path: Vec<Output<D>>) -> Self {
+        Self {
+            old_size,
+            new_size,
+            path,
+            scheme: PhantomData,
+        }
+    }
+
+    /// returns the size of the old tree
+    pub fn old_size(&self) -> usize {
+        self.old_size
+    }
+
+    /// returns the size of the new tree
+    pub fn new_size(&self) -> usize {
+        self.new_size
+    }
+
+    /// returns the hashes of the consistency path
+    pub fn path(&self) -> &[Output<D>] {
+        &self.path
+    }
+
+    /// verifies that the tree with `old_root` at `old_size()` is a prefix of the tree with
+    /// `new_root` at `new_size()`, see RFC 9162 section 2.1.4.2
+    pub fn verify(&self, old_root: &Output<D>, new_root: &Output<D>) -> bool {
+        if self.old_size > self.new_size {
+            return false;
+        }
+        if self.old_size == self.new_size {
+            return self.path.is_empty() && old_root == new_root;
+        }
+        if self.old_size == 0 {
+            // every tree extends the empty tree
+            return self.path.is_empty();
+        }
+
+        // the old root starts the path if it is the root of a complete subtree of the new tree
+        let mut nodes = self.path.iter();
+        let first = if self.old_size.is_power_of_two() {
+            old_root
+        } else {
+            match nodes.next() {
+                Some(first) => first,
+                None => return false,
+            }
+        };
+        let mut index = self.old_size - 1;
+        let mut last = self.new_size - 1;
+        while index & 1 == 1 {
+            index >>= 1;
+            last >>= 1;
+        }
+
+        let mut old_hash = first.clone();
+        let mut new_hash = first.clone();
+        for node in nodes {
+            if last == 0 {
+                return false;
+            }
+            if index & 1 == 1 || index == last {
+                old_hash = S::hash_node(node, &old_hash);
+                new_hash = S::hash_node(node, &new_hash);
+                while index & 1 == 0 && index != 0 {
+                    index >>= 1;
+                    last >>= 1;
+                }
+            } else {
+                new_hash = S::hash_node(&new_hash, node);
+            }
+            index >>= 1;
+            last >>= 1;
+        }
+        last == 0 && &old_hash == old_root && &new_hash == new_root
+    }
+}
+
+impl<D, S> Default for LogTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D, S> LogTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a new empty log tree
+    pub fn new() -> Self {
+        Self {
+            layers: vec![Vec::new()],
+            scheme: PhantomData,
+        }
+    }
+
+    /// returns the number of leaves
+    pub fn len(&self) -> usize {
+        self.layers[0].len()
+    }
+
+    /// returns true if the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.layers[0].is_empty()
+    }
+
+    /// appends a leaf payload, returns the index of the new leaf
+    pub fn push_leaf_data(&mut self, data: &[u8]) -> usize {
+        self.push_leaf_hash(S::hash_leaf(data))
+    }
+
+    /// appends an already hashed leaf, returns the index of the new leaf
+    pub fn push_leaf_hash(&mut self, hash: Output<D>) -> usize {
+        let index = self.len();
+        self.layers[0].push(hash);
+
+        // hash every subtree completed by the new leaf
+        let mut depth = 0;
+        while self.layers[depth].len().is_multiple_of(2) {
+            let layer = &self.layers[depth];
+            let hash = S::hash_node(&layer[layer.len() - 2], &layer[layer.len() - 1]);
+            if self.layers.len() == depth + 1 {
+                self.layers.push(Vec::new());
+            }
+            self.layers[depth + 1].push(hash);
+            depth += 1;
+        }
+        index
+    }
+
+    /// returns the hash of a leaf
+    pub fn leaf_hash(&self, index: usize) -> Option<&Output<D>> {
+        self.layers[0].get(index)
+    }
+
+    /// returns the root hash of the tree
+    pub fn root_hash(&self) -> Output<D> {
+        self.root_at(self.len()).unwrap()
+    }
+
+    /// returns the root hash the tree had when it held `tree_size` leaves
+    pub fn root_at(&self, tree_size: usize) -> Option<Output<D>> {
+        match tree_size {
+            0 => Some(S::empty_root()),
+            size if size <= self.len() => Some(self.subtree_hash(0, size)),
+            _ => None,
+        }
+    }
+
+    /// creates a proof that a leaf is included in the tree at its current size
+    pub fn create_proof(&self, leaf_index: usize) -> Option<InclusionProof<D, S>> {
+        self.create_proof_at(leaf_index, self.len())
+    }
+
+    /// creates a proof that a leaf is included in the tree as it was with `tree_size` leaves
+    pub fn create_proof_at(
+        &self,
+        leaf_index: usize,
+        tree_size: usize,
+    ) -> Option<InclusionProof<D, S>> {
+        if leaf_index >= tree_size || tree_size > self.len() {
+            return None;
+        }
+        let mut path = Vec::new();
+        self.audit_path(leaf_index, 0, tree_size, &mut path);
+        Some(InclusionProof::new(leaf_index, tree_size, path))
+    }
+
+    /// creates a proof that the tree as it was with `old_size` leaves is a prefix of the tree as it
+    /// was with `new_size` leaves, see RFC 9162 section 2.1.4.1
+    pub fn create_consistency_proof(
+        &self,
+        old_size: usize,
+        new_size: usize,
+    ) -> Option<ConsistencyProof<D, S>> {
+        if old_size > new_size || new_size > self.len() {
+            return None;
+        }
+        let mut path = Vec::new();
+        if old_size > 0 && old_size < new_size {
+            self.consistency_subproof(old_size, 0, new_size, true, &mut path);
+        }
+        Some(ConsistencyProof::new(old_size, new_size, path))
+    }
+
+    /// appends the consistency proof of the first `old_size` leaves of `start..end` to `path`
+    /// `complete` is true as long as the old tree's root is known to the verifier
+    fn consistency_subproof(
+        &self,
+        old_size: usize,
+        start: usize,
+        end: usize,
+        complete: bool,
+        path: &mut Vec<Output<D>>,
+    ) {
+        if old_size == end - start {
+            if !complete {
+                path.push(self.subtree_hash(start, end));
+            }
+            return;
+        }
+        let split = split_point(end - start);
+        if old_size <= split {
+            self.consistency_subproof(old_size, start, start + split, complete, path);
+            path.push(self.subtree_hash(start + split, end));
+        } else {
+            self.consistency_subproof(old_size - split, start + split, end, false, path);
+            path.push(self.subtree_hash(start, start + split));
+        }
+    }
+
+    /// appends the audit path of a leaf within the leaves `start..end` to `path`, leaf layer first
+    fn audit_path(&self, leaf_index: usize, start: usize, end: usize, path: &mut Vec<Output<D>>) {
+        if end - start == 1 {
+            return;
+        }
+        let split = start + split_point(end - start);
+        if leaf_index < split {
+            self.audit_path(leaf_index, start, split, path);
+            path.push(self.subtree_hash(split, end));
+        } else {
+            self.audit_path(leaf_index, split, end, path);
+            path.push(self.subtree_hash(start, split));
+        }
+    }
+
+    /// returns the hash of the subtree over the leaves `start..end`
+    /// `start` must be aligned to the largest power of two not greater than `end - start`
+    pub(crate) fn subtree_hash(&self, start: usize, end: usize) -> Output<D> {
+        let size = end - start;
+        if size.is_power_of_two() {
+            let depth = size.trailing_zeros() as usize;
+            return self.layers[depth][start >> depth];
+        }
+        let split = start + split_point(size);
+        S::hash_node(
+            &self.subtree_hash(start, split),
+            &self.subtree_hash(split, end),
+        )
+    }
+}
+
+/// returns the largest power of two smaller than `size`, `size` must be at least 2
+pub(crate) fn split_point(size: usize) -> usize {
+    1 << (usize::BITS - 1 - (size - 1).leading_zeros())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha2::Sha256;
+
+    type LogTree = super::LogTree<Sha256>;
+
+    /// leaves of the RFC 6962 test vectors used by trillian and certificate-transparency-go
+    fn test_leaves() -> Vec<Vec<u8>> {
+        vec![
+            vec![],
+            vec![0x00],
+            vec![0x10],
+            vec![0x20, 0x21],
+            vec![0x30, 0x31],
+            vec![0x40, 0x41, 0x42, 0x43],
+            (0x50..0x58).collect(),
+            (0x60..0x70).collect(),
+        ]
+    }
+
+    fn test_tree() -> LogTree {
+        let mut tree = LogTree::new();
+        for leaf in test_leaves() {
+            tree.push_leaf_data(&leaf);
+        }
+        tree
+    }
+
+    fn hash(hex: &str) -> Output<Sha256> {
+        let bytes: [u8; 32] = hex::decode(hex).unwrap().try_into().unwrap();
+        bytes.into()
+    }
+
+    #[test]
+    fn test_split_point() {
+        assert_eq!(split_point(2), 1);
+        assert_eq!(split_point(3), 2);
+        assert_eq!(split_point(4), 2);
+        assert_eq!(split_point(5), 4);
+        assert_eq!(split_point(8), 4);
+        assert_eq!(split_point(9), 8);
+    }
+
+    #[test]
+    fn test_roots_match_ct_vectors() {
+        let roots = [
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+            "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+            "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+            "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+            "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+            "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+        ];
+        let tree = test_tree();
+        assert_eq!(
+            tree.root_at(0).unwrap(),
+            hash("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+        for (i, root) in roots.iter().enumerate() {
+            assert_eq!(tree.root_at(i + 1).unwrap(), hash(root));
+        }
+        assert_eq!(tree.root_hash(), hash(roots[7]));
+        assert_eq!(tree.root_at(9), None);
+    }
+
+    #[test]
+    fn test_inclusion_proofs_match_ct_vectors() {
+        let vectors: [(usize, usize, &[&str]); 5] = [
+            (0, 1, &[]),
+            (
+                0,
+                8,
+                &[
+                    "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+                ],
+            ),
+            (
+                5,
+                8,
+                &[
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                    "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                    "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+                ],
+            ),
+            (
+                2,
+                3,
+                &["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"],
+            ),
+            (
+                1,
+                5,
+                &[
+                    "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                ],
+            ),
+        ];
+        let tree = test_tree();
+        let leaves = test_leaves();
+        for (leaf_index, tree_size, path) in vectors {
+            let proof = tree.create_proof_at(leaf_index, tree_size).unwrap();
+            let expected: Vec<_> = path.iter().map(|h| hash(h)).collect();
+            assert_eq!(proof.path(), expected.as_slice());
+
+            let root = tree.root_at(tree_size).unwrap();
+            assert!(proof.verify_data(&leaves[leaf_index], &root));
+            assert!(!proof.verify_data(b"wrong", &root));
+        }
+    }
+
+    #[test]
+    fn test_consistency_proofs_match_ct_vectors() {
+        let vectors: [(usize, usize, &[&str]); 4] = [
+            (1, 1, &[]),
+            (
+                1,
+                8,
+                &[
+                    "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+                ],
+            ),
+            (
+                6,
+                8,
+                &[
+                    "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                    "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                    "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+                ],
+            ),
+            (
+                2,
+                5,
+                &[
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                ],
+            ),
+        ];
+        let tree = test_tree();
+        for (old_size, new_size, path) in vectors {
+            let proof = tree.create_consistency_proof(old_size, new_size).unwrap();
+            let expected: Vec<_> = path.iter().map(|h| hash(h)).collect();
+            assert_eq!(proof.path(), expected.as_slice());
+
+            let old_root = tree.root_at(old_size).unwrap();
+            let new_root = tree.root_at(new_size).unwrap();
+            assert!(proof.verify(&old_root, &new_root));
+        }
+    }
+
+    #[test]
+    fn test_consistency_proofs_for_all_sizes() {
+        let mut tree = LogTree::new();
+        for i in 0..37u32 {
+            tree.push_leaf_data(&i.to_le_bytes());
+        }
+        let other_root = Sha256::digest(b"other");
+        for new_size in 1..=37 {
+            let new_root = tree.root_at(new_size).unwrap();
+            for old_size in 0..=new_size {
+                let old_root = tree.root_at(old_size).unwrap();
+                let proof = tree.create_consistency_proof(old_size, new_size).unwrap();
+                assert!(proof.verify(&old_root, &new_root));
+                if old_size > 0 && old_size < new_size {
+                    assert!(!proof.verify(&other_root, &new_root));
+                    assert!(!proof.verify(&old_root, &other_root));
+                }
+            }
+        }
+        assert_eq!(tree.create_consistency_proof(5, 4), None);
+        assert_eq!(tree.create_consistency_proof(5, 38), None);
+    }
+
+    #[test]
+    fn test_inclusion_proofs_for_all_sizes() {
+        let mut tree = LogTree::new();
+        for i in 0..37u32 {
+            tree.push_leaf_data(&i.to_le_bytes());
+        }
+        for size in 1..=37 {
+            let root = tree.root_at(size).unwrap();
+            for leaf_index in 0..size {
+                let proof = tree.create_proof_at(leaf_index, size).unwrap();
+                let leaf = tree.leaf_hash(leaf_index).unwrap();
+                assert!(proof.verify(leaf, &root));
+
+                // a truncated audit path must not verify
+                if let Some((_, path)) = proof.path().split_last() {
+                    let truncated = InclusionProof::<Sha256>::new(leaf_index, size, path.to_vec());
+                    assert!(!truncated.verify(leaf, &root));
+                }
+            }
+        }
+        assert_eq!(tree.create_proof(37), None);
+    }
+}
@@ -0,0 +1,372 @@
+//! Authenticated key-value maps on a sparse Merkle tree
+//!
+//! `MerkleMap` places every key at the leaf given by the bits of its hash, so a map hashing with
+//! a 256 bit digest is a sparse tree of depth 256 with a leaf for every possible key. Like the
+//! sparse `StoredMerkleTree`, only nodes that differ from an untouched subtree are stored and the
+//! hashes of empty subtrees are precomputed per layer. The tree is kept in a map of its own
+//! because `StoredMerkleTree` addresses nodes with `usize` indices, which can't hold 256 bit paths.
+//!
+//! A leaf holding a value is the hash of `0x00`, the key hash and the value, empty leaves are all
+//! zero. `prove` returns a proof that a key maps to its value, `prove_absence` one that a key has
+//! no value, both verify against the root with only the key and value at hand. Proofs only carry
+//! the siblings that are not the root of an empty subtree, together with a bitmap marking them.
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use digest::{Digest, Output};
+use sha3::Sha3_256;
+
+use crate::MerkleTree;
+
+/// prefix of the hashes of leaves holding a value
+const LEAF_PREFIX: u8 = 0x00;
+
+/// A proof that a key maps to a value or has no value in a `MerkleMap`
+#[derive(Debug, Clone)]
+pub struct MapProof<D: Digest> {
+    /// one bit per layer starting at the leaves, set if the sibling is not an empty subtree
+    bitmap: Vec<u8>,
+    /// the siblings that are not empty subtrees starting at the leaves
+    siblings: Vec<Output<D>>,
+}
+
+impl<D: Digest> PartialEq for MapProof<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bitmap == other.bitmap && self.siblings == other.siblings
+    }
+}
+
+impl<D: Digest> Eq for MapProof<D> {}
+
+impl<D> MapProof<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// creates a proof from the bitmap of non-empty siblings and these siblings
+    pub fn new(bitmap: Vec<u8>, siblings: Vec<Output<D>>) -> Self {
+        Self { bitmap, siblings }
+    }
+
+    /// returns the bitmap marking the siblings that are not empty subtrees, starting at the leaves
+    pub fn bitmap(&self) -> &[u8] {
+        &self.bitmap
+    }
+
+    /// returns the siblings that are not empty subtrees, starting at the leaves
+    pub fn siblings(&self) -> &[Output<D>] {
+        &self.siblings
+    }
+
+    /// returns true if the key maps to the value in the map with the given root
+    pub fn verify_inclusion(&self, root: &Output<D>, key: &[u8], value: &[u8]) -> bool {
+        let path = D::digest(key);
+        self.compute_root(&path, &leaf_hash::<D>(&path, value))
+            .as_ref()
+            == Some(root)
+    }
+
+    /// returns true if the key has no value in the map with the given root
+    pub fn verify_absence(&self, root: &Output<D>, key: &[u8]) -> bool {
+        let path = D::digest(key);
+        self.compute_root(&path, &Output::<D>::default()).as_ref() == Some(root)
+    }
+
+    /// returns the root computed from the leaf at the given path and the proof
+    /// returns None if the bitmap doesn't match the depth or the number of siblings
+    pub fn compute_root(&self, path: &Output<D>, leaf: &Output<D>) -> Option<Output<D>> {
+        let depth = path.len() * 8;
+        let non_empty = self
+            .bitmap
+            .iter()
+            .map(|byte| byte.count_ones())
+            .sum::<u32>();
+        if self.bitmap.len() != path.len() || non_empty as usize != self.siblings.len() {
+            return None;
+        }
+        let mut siblings = self.siblings.iter();
+        let mut empty = Output::<D>::default();
+        let mut node = *leaf;
+        for height in 0..depth {
+            let sibling = match bit(&self.bitmap, height) {
+                0 => &empty,
+                _ => siblings.next()?,
+            };
+            node = match path_bit(path, height) {
+                0 => MerkleTree::<D>::hash_pair(&node, sibling),
+                _ => MerkleTree::<D>::hash_pair(sibling, &node),
+            };
+            empty = MerkleTree::<D>::hash_pair(&empty, &empty);
+        }
+        Some(node)
+    }
+}
+
+/// An authenticated map from keys to values
+/// Keys and values are hashed as bytes, a key is placed at the leaf given by its hash with `D`.
+#[derive(Debug, Clone)]
+pub struct MerkleMap<K, V, D: Digest = Sha3_256> {
+    /// keys and values by the hash of the key
+    entries: BTreeMap<Output<D>, (K, V)>,
+    /// nodes that are not the root of an empty subtree by their height above the leaves and the
+    /// path of the leaves below them with the lower `height` bits cleared
+    nodes: BTreeMap<(usize, Output<D>), Output<D>>,
+    /// root of an empty subtree per height, starting at the leaves
+    empty: Vec<Output<D>>,
+}
+
+impl<K, V, D> Default for MerkleMap<K, V, D>
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, D> MerkleMap<K, V, D>
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// creates a new empty map
+    pub fn new() -> Self {
+        let depth = <D as Digest>::output_size() * 8;
+        let mut empty = vec![Output::<D>::default(); depth + 1];
+        for height in 1..=depth {
+            empty[height] = MerkleTree::<D>::hash_pair(&empty[height - 1], &empty[height - 1]);
+        }
+        Self {
+            entries: BTreeMap::new(),
+            nodes: BTreeMap::new(),
+            empty,
+        }
+    }
+
+    /// returns the number of keys in the map
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// returns true if the map holds no keys
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// returns the root hash of the map
+    pub fn root_hash(&self) -> Output<D> {
+        self.node(self.depth(), &Output::<D>::default())
+    }
+
+    /// returns the value of a key
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries
+            .get(&D::digest(key.as_ref()))
+            .map(|(_, value)| value)
+    }
+
+    /// sets the value of a key, returns the previous value
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let path = D::digest(key.as_ref());
+        self.update_path(&path, leaf_hash::<D>(&path, value.as_ref()));
+        self.entries
+            .insert(path, (key, value))
+            .map(|(_, previous)| previous)
+    }
+
+    /// removes a key, returns its value
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let path = D::digest(key.as_ref());
+        let (_, value) = self.entries.remove(&path)?;
+        self.update_path(&path, Output::<D>::default());
+        Some(value)
+    }
+
+    /// creates a proof that the key maps to its value
+    /// returns None if the key has no value, see `prove_absence`
+    pub fn prove(&self, key: &K) -> Option<MapProof<D>> {
+        let path = D::digest(key.as_ref());
+        self.entries
+            .contains_key(&path)
+            .then(|| self.create_proof(&path))
+    }
+
+    /// creates a proof that the key has no value
+    /// returns None if the key has a value, see `prove`
+    pub fn prove_absence(&self, key: &K) -> Option<MapProof<D>> {
+        let path = D::digest(key.as_ref());
+        (!self.entries.contains_key(&path)).then(|| self.create_proof(&path))
+    }
+
+    /// returns an iterator over the keys and values ordered by the hashes of the keys
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.values().map(|(key, value)| (key, value))
+    }
+
+    /// returns the depth of the tree, the number of bits of a key hash
+    fn depth(&self) -> usize {
+        self.empty.len() - 1
+    }
+
+    /// returns the node at the given height above the leaf at the path
+    fn node(&self, height: usize, prefix: &Output<D>) -> Output<D> {
+        self.nodes
+            .get(&(height, *prefix))
+            .copied()
+            .unwrap_or(self.empty[height])
+    }
+
+    /// sets the leaf at the path and recomputes the nodes above it
+    fn update_path(&mut self, path: &Output<D>, leaf: Output<D>) {
+        let mut prefix = *path;
+        let mut node = leaf;
+        for height in 0..self.depth() {
+            self.store_node(height, prefix, node);
+            let mut sibling_prefix = prefix;
+            flip_path_bit(&mut sibling_prefix, height);
+            let sibling = self.node(height, &sibling_prefix);
+            node = match path_bit(path, height) {
+                0 => MerkleTree::<D>::hash_pair(&node, &sibling),
+                _ => MerkleTree::<D>::hash_pair(&sibling, &node),
+            };
+            clear_path_bit(&mut prefix, height);
+        }
+        self.store_node(self.depth(), prefix, node);
+    }
+
+    /// stores a node unless it is the root of an empty subtree
+    fn store_node(&mut self, height: usize, prefix: Output<D>, node: Output<D>) {
+        if node == self.empty[height] {
+            self.nodes.remove(&(height, prefix));
+        } else {
+            self.nodes.insert((height, prefix), node);
+        }
+    }
+
+    fn create_proof(&self, path: &Output<D>) -> MapProof<D> {
+        let mut bitmap = vec![0; path.len()];
+        let mut siblings = Vec::new();
+        let mut prefix = *path;
+        for height in 0..self.depth() {
+            let mut sibling_prefix = prefix;
+            flip_path_bit(&mut sibling_prefix, height);
+            if let Some(sibling) = self.nodes.get(&(height, sibling_prefix)) {
+                bitmap[height / 8] |= 1 << (height % 8);
+                siblings.push(*sibling);
+            }
+            clear_path_bit(&mut prefix, height);
+        }
+        MapProof::new(bitmap, siblings)
+    }
+}
+
+/// returns the hash of the leaf holding a value
+fn leaf_hash<D: Digest>(path: &Output<D>, value: &[u8]) -> Output<D> {
+    let mut hasher = D::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(path);
+    hasher.update(value);
+    hasher.finalize()
+}
+
+/// returns bit `index` of a little endian bitmap
+fn bit(bitmap: &[u8], index: usize) -> u8 {
+    (bitmap[index / 8] >> (index % 8)) & 1
+}
+
+/// returns the bit of the path choosing the child at the given height, the last bit of the path
+/// chooses between two leaves
+fn path_bit(path: &[u8], height: usize) -> u8 {
+    (path[path.len() - 1 - height / 8] >> (height % 8)) & 1
+}
+
+fn flip_path_bit(path: &mut [u8], height: usize) {
+    let len = path.len();
+    path[len - 1 - height / 8] ^= 1 << (height % 8);
+}
+
+fn clear_path_bit(path: &mut [u8], height: usize) {
+    let len = path.len();
+    path[len - 1 - height / 8] &= !(1 << (height % 8));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type MerkleMap = super::MerkleMap<&'static str, &'static str>;
+
+    #[test]
+    fn test_insert_get_remove() {
+        let mut map = MerkleMap::new();
+        let empty_root = map.root_hash();
+        assert!(map.is_empty());
+        assert_eq!(map.insert("alice", "1 BTC"), None);
+        assert_eq!(map.insert("bob", "2 ETH"), None);
+        assert_eq!(map.insert("alice", "3 BTC"), Some("1 BTC"));
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&"alice"), Some(&"3 BTC"));
+        assert_eq!(map.get(&"carol"), None);
+
+        // the root only depends on the contents
+        let mut other = MerkleMap::new();
+        other.insert("bob", "2 ETH");
+        other.insert("alice", "3 BTC");
+        assert_eq!(map.root_hash(), other.root_hash());
+        other.insert("bob", "4 ETH");
+        assert_ne!(map.root_hash(), other.root_hash());
+
+        assert_eq!(map.remove(&"carol"), None);
+        assert_eq!(map.remove(&"alice"), Some("3 BTC"));
+        assert_eq!(map.remove(&"bob"), Some("2 ETH"));
+        // removing all keys leaves no stored nodes behind
+        assert_eq!(map.root_hash(), empty_root);
+        assert!(map.nodes.is_empty());
+    }
+
+    #[test]
+    fn test_proofs() {
+        let mut map = MerkleMap::new();
+        let keys = ["alice", "bob", "carol", "dave", "eve"];
+        for key in keys {
+            map.insert(key, key);
+        }
+        let root = map.root_hash();
+        for key in keys {
+            let proof = map.prove(&key).unwrap();
+            // only a few siblings are not empty subtrees
+            assert!(proof.siblings().len() < 16);
+            assert!(proof.verify_inclusion(&root, key.as_bytes(), key.as_bytes()));
+            assert!(!proof.verify_inclusion(&root, key.as_bytes(), b"other"));
+            assert!(!proof.verify_absence(&root, key.as_bytes()));
+            assert_eq!(map.prove_absence(&key), None);
+        }
+
+        let proof = map.prove_absence(&"mallory").unwrap();
+        assert!(proof.verify_absence(&root, b"mallory"));
+        assert!(!proof.verify_inclusion(&root, b"mallory", b"mallory"));
+        assert_eq!(map.prove(&"mallory"), None);
+
+        // proofs of an empty map have no siblings
+        let empty = MerkleMap::new();
+        let proof = empty.prove_absence(&"alice").unwrap();
+        assert!(proof.siblings().is_empty());
+        assert!(proof.verify_absence(&empty.root_hash(), b"alice"));
+
+        // malformed proofs don't verify
+        let truncated = MapProof::<Sha3_256>::new(proof.bitmap()[1..].to_vec(), Vec::new());
+        assert!(!truncated.verify_absence(&empty.root_hash(), b"alice"));
+        let mut bitmap = proof.bitmap().to_vec();
+        bitmap[0] = 1;
+        let missing = MapProof::<Sha3_256>::new(bitmap, Vec::new());
+        assert!(!missing.verify_absence(&empty.root_hash(), b"alice"));
+    }
+}
@@ -1,42 +1,846 @@
-use std::fmt::Debug;
+use alloc::borrow::ToOwned;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug};
+use core::marker::PhantomData;
+use core::ops::Range;
 
 use digest::{Digest, Output};
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+#[cfg(feature = "zeroize")]
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::cancel::CHECK_INTERVAL;
+use crate::nodes::NodeBuf;
+use crate::population::Population;
+use crate::scheme::{HashScheme, Plain};
+use crate::snapshot::NodeHistory;
+use crate::{CancellationToken, MerkleTreeError, Progress};
+
+/// maximum supported depth of a tree, limited by the number of nodes fitting into a usize
+pub const MAX_DEPTH: usize = usize::BITS as usize - 1;
+
+/// minimum number of nodes hashed by a single rayon task
+#[cfg(feature = "rayon")]
+const PAR_MIN_LEN: usize = 256;
 
 /// A simple Merkle tree implementation
-pub struct MerkleTree<D: Digest> {
+/// Nodes are hashed with the hash scheme `S`. The default `Plain` scheme hashes leaves and nodes
+/// alike, `Rfc6962` prefixes leaf hashes with `0x00` and node hashes with `0x01` so that no leaf
+/// can be passed off as an intermediate node. Leaves are given as leaf hashes, see `hash_leaf`.
+pub struct MerkleTree<D: Digest, S = Plain> {
     /// depth of the tree
     depth: usize,
     /// nodes of the tree in breadth-first traversal order
-    nodes: Vec<Output<D>>,
+    nodes: NodeBuf<D>,
+    /// offset the next pushed leaf is written to
+    next_offset: usize,
+    /// value of unused leaves, fills the new leaves when the tree grows
+    padding: Output<D>,
+    /// old values of nodes overwritten after a snapshot
+    history: NodeHistory<D>,
+    /// number of non-default leaves below every intermediate node
+    population: Population,
+    scheme: PhantomData<fn() -> S>,
 }
 
-impl<D> MerkleTree<D>
+impl<D, S> Debug for MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MerkleTree")
+            .field("root", &hex::encode(self.root_hash()))
+            .field("depth", &self.depth)
+            .field("next_offset", &self.next_offset)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A proof for the inclusion of a single leaf
+/// The proof holds the hashes of the siblings of all nodes on the path from the leaf to the root.
+/// Whether the path node is the left or the right child on each layer follows from the leaf index.
+/// The proof is verified with the hash scheme `S` of the tree it was created for.
+#[derive(Debug, Clone)]
+pub struct Proof<D: Digest, S = Plain> {
+    /// offset of the proven leaf
+    leaf_index: usize,
+    /// sibling hashes starting at the leaf layer
+    siblings: Vec<Output<D>>,
+    scheme: PhantomData<fn() -> S>,
+}
+
+impl<D, S> Proof<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a proof from the offset of the proven leaf and the sibling hashes starting at the leaf layer
+    pub fn new(leaf_index: usize, siblings: Vec<Output<D>>) -> Self {
+        Self {
+            leaf_index,
+            siblings,
+            scheme: PhantomData,
+        }
+    }
+
+    /// returns the offset of the proven leaf
+    pub fn leaf_index(&self) -> usize {
+        self.leaf_index
+    }
+
+    /// returns the sibling hashes starting at the leaf layer
+    pub fn siblings(&self) -> &[Output<D>] {
+        &self.siblings
+    }
+
+    /// returns the number of sibling hashes, which is the depth of the tree minus one
+    pub fn len(&self) -> usize {
+        self.siblings.len()
+    }
+
+    /// returns true if the proof contains no siblings, i.e. the leaf is the root
+    pub fn is_empty(&self) -> bool {
+        self.siblings.is_empty()
+    }
+
+    /// returns an iterator over the (hash, is_left) pairs of the proof
+    /// is_left is true if the node on the path is the left child, i.e. the sibling is the right one
+    pub fn iter(&self) -> impl Iterator<Item = (&Output<D>, bool)> + '_ {
+        self.siblings
+            .iter()
+            .enumerate()
+            .map(|(layer, hash)| (hash, (self.leaf_index >> layer).is_multiple_of(2)))
+    }
+
+    /// encodes the proof compactly: one byte holding the number of siblings, a bitmap of the
+    /// directions starting at the leaf layer (bit set if the path node is the right child) and the
+    /// concatenated sibling hashes
+    /// bits of the leaf index above the number of siblings don't affect the proof and are dropped
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let len = u8::try_from(self.siblings.len()).expect("proofs have at most 255 siblings");
+        let bitmap_len = self.siblings.len().div_ceil(8);
+        let mut bytes =
+            Vec::with_capacity(1 + bitmap_len + self.siblings.len() * <D as Digest>::output_size());
+        bytes.push(len);
+        bytes.extend((0..bitmap_len).map(|byte| {
+            (0..8)
+                .filter(|bit| {
+                    let layer = byte * 8 + bit;
+                    layer < self.siblings.len() && (self.leaf_index >> layer) & 1 == 1
+                })
+                .fold(0u8, |bits, bit| bits | 1 << bit)
+        }));
+        for sibling in &self.siblings {
+            bytes.extend_from_slice(sibling);
+        }
+        bytes
+    }
+
+    /// decodes a proof encoded by `to_bytes`
+    /// returns an error if the length doesn't match the header or the bitmap is not canonical
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MerkleTreeError> {
+        let Some((&len, rest)) = bytes.split_first() else {
+            return Err(MerkleTreeError::InvalidProofEncoding {
+                expected: 1,
+                actual: 0,
+            });
+        };
+        let len = len as usize;
+        if len > MAX_DEPTH - 1 {
+            return Err(MerkleTreeError::InvalidProofLength {
+                expected: MAX_DEPTH - 1,
+                actual: len,
+            });
+        }
+        let bitmap_len = len.div_ceil(8);
+        let expected = 1 + bitmap_len + len * <D as Digest>::output_size();
+        if bytes.len() != expected {
+            return Err(MerkleTreeError::InvalidProofEncoding {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        let (bitmap, hashes) = rest.split_at(bitmap_len);
+        let mut leaf_index = 0;
+        for (byte, bits) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if bits & (1 << bit) == 0 {
+                    continue;
+                }
+                let layer = byte * 8 + bit;
+                if layer >= len {
+                    return Err(MerkleTreeError::NonCanonicalProofEncoding);
+                }
+                leaf_index |= 1 << layer;
+            }
+        }
+        let siblings = hashes
+            .chunks_exact(<D as Digest>::output_size())
+            .map(Output::<D>::clone_from_slice)
+            .collect();
+        Ok(Self::new(leaf_index, siblings))
+    }
+
+    /// computes the root hash implied by the proof for the given leaf value
+    pub fn compute_root(&self, leaf: &Output<D>) -> Output<D> {
+        let mut current_value = *leaf;
+        for (hash, is_left) in self.iter() {
+            current_value = if is_left {
+                S::hash_node(&current_value, hash)
+            } else {
+                S::hash_node(hash, &current_value)
+            };
+        }
+        current_value
+    }
+
+    /// patches the proof after the leaf of the update changed, see `MerkleTree::proof_update`
+    /// Only the sibling at the layer where the paths of the two leaves join changes, it is
+    /// replaced by the new hash of the changed leaf's path node. A change of the proven leaf itself
+    /// leaves the siblings unchanged.
+    /// returns false and leaves the proof untouched if the update is for a tree of another depth
+    pub fn apply_update(&mut self, update: &ProofUpdate<D>) -> bool {
+        if update.path.len() != self.siblings.len() || update.offset >> self.siblings.len() != 0 {
+            return false;
+        }
+        let differing_layers =
+            (usize::BITS - (self.leaf_index ^ update.offset).leading_zeros()) as usize;
+        if let Some(layer) = differing_layers.checked_sub(1) {
+            self.siblings[layer] = update.path[layer];
+        }
+        true
+    }
+}
+
+impl<D: Digest, S> PartialEq for Proof<D, S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf_index == other.leaf_index && self.siblings == other.siblings
+    }
+}
+
+impl<D: Digest, S> Eq for Proof<D, S> {}
+
+impl<D: Digest, S> From<Vec<(Output<D>, bool)>> for Proof<D, S> {
+    /// converts a list of (hash, is_left) pairs as returned by earlier versions of `create_proof`
+    fn from(pairs: Vec<(Output<D>, bool)>) -> Self {
+        let mut leaf_index = 0;
+        let mut siblings = Vec::with_capacity(pairs.len());
+        for (layer, (hash, is_left)) in pairs.into_iter().enumerate() {
+            if !is_left {
+                leaf_index |= 1 << layer;
+            }
+            siblings.push(hash);
+        }
+        Self {
+            leaf_index,
+            siblings,
+            scheme: PhantomData,
+        }
+    }
+}
+
+impl<D: Digest, S> From<Proof<D, S>> for Vec<(Output<D>, bool)> {
+    /// converts the proof into a list of (hash, is_left) pairs
+    fn from(mut proof: Proof<D, S>) -> Self {
+        let leaf_index = proof.leaf_index;
+        // taken instead of moved out, proofs implement `Drop` with the `zeroize` feature
+        core::mem::take(&mut proof.siblings)
+            .into_iter()
+            .enumerate()
+            .map(|(layer, hash)| (hash, (leaf_index >> layer).is_multiple_of(2)))
+            .collect()
+    }
+}
+
+/// Verify a proof for a leaf node without access to the tree
+/// Returns true if the leaf value and the proof hash up to the given root
+pub fn verify<D, S>(root: &Output<D>, leaf: &Output<D>, proof: &Proof<D, S>) -> bool
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    proof.compute_root(leaf) == *root
+}
+
+/// Verify a proof like `verify` without timing side channels on the leaf and the hashes
+/// The children of every layer are ordered with constant-time selects instead of a branch on the
+/// leaf index and the computed root is compared with `subtle`'s constant-time equality, so the
+/// running time only depends on the length of the proof. Schemes ordering the children by their
+/// value, like `SortedPair`, still compare hashes while hashing.
+/// Returns a `Choice` that is set if the leaf value and the proof hash up to the given root
+pub fn verify_proof_ct<D, S>(root: &Output<D>, leaf: &Output<D>, proof: &Proof<D, S>) -> Choice
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    let mut current_value = *leaf;
+    for (layer, hash) in proof.siblings.iter().enumerate() {
+        let is_right = Choice::from(((proof.leaf_index >> layer) & 1) as u8);
+        let left = ct_select::<D>(&current_value, hash, is_right);
+        let right = ct_select::<D>(hash, &current_value, is_right);
+        current_value = S::hash_node(&left, &right);
+    }
+    current_value.as_slice().ct_eq(root.as_slice())
+}
+
+/// returns `b` if the choice is set and `a` otherwise without branching on it
+fn ct_select<D: Digest>(a: &Output<D>, b: &Output<D>, choice: Choice) -> Output<D> {
+    let mut selected = a.clone();
+    for (byte, (a, b)) in selected.iter_mut().zip(a.iter().zip(b.iter())) {
+        *byte = u8::conditional_select(a, b, choice);
+    }
+    selected
+}
+
+/// A proof that a leaf slot still holds the default value of the tree, e.g. an unused nullifier
+/// The proof carries the same siblings as an inclusion proof but is a separate type, so it can't
+/// be mistaken for a proof that some value was included. It is verified against the default leaf
+/// the verifier expects, never against a leaf taken from the prover.
+#[derive(Debug, Clone)]
+pub struct NonInclusionProof<D: Digest, S = Plain> {
+    proof: Proof<D, S>,
+}
+
+impl<D, S> NonInclusionProof<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a proof for an empty leaf from the path of the leaf
+    pub fn new(proof: Proof<D, S>) -> Self {
+        Self { proof }
+    }
+
+    /// returns the offset of the empty leaf
+    pub fn leaf_index(&self) -> usize {
+        self.proof.leaf_index()
+    }
+
+    /// returns the sibling hashes starting at the leaf layer
+    pub fn siblings(&self) -> &[Output<D>] {
+        self.proof.siblings()
+    }
+
+    /// returns the path of the leaf, e.g. to encode it with `Proof::to_bytes`
+    pub fn path(&self) -> &Proof<D, S> {
+        &self.proof
+    }
+
+    /// returns true if the leaf holds the default leaf in the tree with the given root
+    pub fn verify(&self, root: &Output<D>, default_leaf: &Output<D>) -> bool {
+        verify(root, default_leaf, &self.proof)
+    }
+}
+
+impl<D: Digest, S> PartialEq for NonInclusionProof<D, S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.proof == other.proof
+    }
+}
+
+impl<D: Digest, S> Eq for NonInclusionProof<D, S> {}
+
+/// Verify a non-inclusion proof without access to the tree
+/// Returns true if the default leaf and the proof hash up to the given root
+pub fn verify_non_inclusion<D, S>(
+    root: &Output<D>,
+    default_leaf: &Output<D>,
+    proof: &NonInclusionProof<D, S>,
+) -> bool
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    proof.verify(root, default_leaf)
+}
+
+/// A proof for the values of a contiguous run of leaves
+/// Only the siblings at the boundaries of the run are included, at most two per layer, all nodes
+/// above the run are computed from its leaves.
+#[derive(Debug, Clone)]
+pub struct RangeProof<D: Digest, S = Plain> {
+    /// depth of the tree the proof was created for
+    depth: usize,
+    /// boundary siblings starting at the leaf layer, the left one before the right one per layer
+    hashes: Vec<Output<D>>,
+    scheme: PhantomData<fn() -> S>,
+}
+
+impl<D, S> RangeProof<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a proof from the depth of the tree and the boundary siblings in verification order
+    pub fn new(depth: usize, hashes: Vec<Output<D>>) -> Self {
+        Self {
+            depth,
+            hashes,
+            scheme: PhantomData,
+        }
+    }
+
+    /// returns the depth of the tree the proof was created for
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// returns the boundary siblings contained in the proof
+    pub fn hashes(&self) -> &[Output<D>] {
+        &self.hashes
+    }
+
+    /// computes the root hash implied by the proof for the leaves starting at the given offset
+    /// returns None if the leaves don't fit into the tree or don't match the number of siblings
+    pub fn compute_root(&self, start: usize, leaves: &[Output<D>]) -> Option<Output<D>> {
+        if leaves.is_empty() || self.depth == 0 || self.depth > MAX_DEPTH {
+            return None;
+        }
+        let num_leaves = 1 << (self.depth - 1);
+        if start >= num_leaves || leaves.len() > num_leaves - start {
+            return None;
+        }
+        let mut hashes = self.hashes.iter();
+        let mut start = start;
+        let mut layer = leaves.to_vec();
+        for _ in 1..self.depth {
+            let mut nodes = Vec::with_capacity(layer.len() + 2);
+            if start % 2 == 1 {
+                nodes.push(*hashes.next()?);
+            }
+            nodes.extend_from_slice(&layer);
+            if nodes.len() % 2 == 1 {
+                nodes.push(*hashes.next()?);
+            }
+            layer = nodes
+                .chunks_exact(2)
+                .map(|pair| S::hash_node(&pair[0], &pair[1]))
+                .collect();
+            start /= 2;
+        }
+        if hashes.next().is_some() {
+            return None;
+        }
+        Some(layer[0])
+    }
+}
+
+impl<D: Digest, S> PartialEq for RangeProof<D, S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.depth == other.depth && self.hashes == other.hashes
+    }
+}
+
+impl<D: Digest, S> Eq for RangeProof<D, S> {}
+
+/// Verify a range proof without access to the tree
+/// Returns true if the leaves starting at the given offset and the proof hash up to the given root
+pub fn verify_range_proof<D, S>(
+    root: &Output<D>,
+    start: usize,
+    leaves: &[Output<D>],
+    proof: &RangeProof<D, S>,
+) -> bool
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    proof.compute_root(start, leaves).as_ref() == Some(root)
+}
+
+/// A proof for the inclusion of multiple leaves at once
+/// Siblings that are shared between the paths of the proven leaves, or that can be computed from
+/// the proven leaves themselves, are only included once (or not at all)
+#[derive(Debug, Clone)]
+pub struct MultiProof<D: Digest, S = Plain> {
+    /// depth of the tree the proof was created for
+    depth: usize,
+    /// offsets of the proven leaves, sorted and deduplicated
+    offsets: Vec<usize>,
+    /// sibling hashes in the order they are consumed during verification (bottom-up, left to right)
+    hashes: Vec<Output<D>>,
+    scheme: PhantomData<fn() -> S>,
+}
+
+impl<D: Digest, S> PartialEq for MultiProof<D, S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.depth == other.depth && self.offsets == other.offsets && self.hashes == other.hashes
+    }
+}
+
+impl<D: Digest, S> Eq for MultiProof<D, S> {}
+
+impl<D, S> MultiProof<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// returns the depth of the tree the proof was created for
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// returns the offsets of the proven leaves in ascending order
+    /// the leaves passed to `compute_root` and `verify` must be given in this order
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    /// returns the sibling hashes contained in the proof
+    pub fn hashes(&self) -> &[Output<D>] {
+        &self.hashes
+    }
+
+    /// computes the root hash implied by the proof for the leaves given in the order of `offsets`
+    /// returns None if the leaves or hashes don't match the structure of the proof
+    pub fn compute_root(&self, leaves: &[Output<D>]) -> Option<Output<D>> {
+        if leaves.is_empty() || leaves.len() != self.offsets.len() || self.depth < 1 {
+            return None;
+        }
+
+        let mut hashes = self.hashes.iter();
+        let mut current: Vec<(usize, Output<D>)> = self
+            .offsets
+            .iter()
+            .copied()
+            .zip(leaves.iter().copied())
+            .collect();
+        let mut current_layer = self.depth - 1;
+        while current_layer > 0 {
+            let mut parents = Vec::with_capacity(current.len());
+            let mut i = 0;
+            while i < current.len() {
+                let (current_offset, current_value) = current[i];
+                let hash = match current.get(i + 1) {
+                    Some((next_offset, next_value))
+                        if current_offset.is_multiple_of(2)
+                            && *next_offset == current_offset + 1 =>
+                    {
+                        i += 2;
+                        S::hash_node(&current_value, next_value)
+                    }
+                    _ => {
+                        let sibling = hashes.next()?;
+                        i += 1;
+                        if current_offset.is_multiple_of(2) {
+                            S::hash_node(&current_value, sibling)
+                        } else {
+                            S::hash_node(sibling, &current_value)
+                        }
+                    }
+                };
+                parents.push((current_offset / 2, hash));
+            }
+            current = parents;
+            current_layer -= 1;
+        }
+
+        // all hashes must be consumed and we must end up with exactly the root
+        if hashes.next().is_some() || current.len() != 1 || current[0].0 != 0 {
+            return None;
+        }
+        Some(current[0].1)
+    }
+
+    /// returns true if the leaves given in the order of `offsets` and the proof hash up to the
+    /// trusted root
+    pub fn verify(&self, root: &Output<D>, leaves: &[Output<D>]) -> bool {
+        self.compute_root(leaves).as_ref() == Some(root)
+    }
+}
+
+/// The siblings in which the proofs of two leaves differ
+/// The paths of two leaves join at their lowest common ancestor, so their proofs share all siblings
+/// above it. A delta holds the siblings of the second proof below that point only.
+#[derive(Debug, Clone)]
+pub struct ProofDelta<D: Digest> {
+    /// offset of the leaf whose proof the delta applies to
+    from: usize,
+    /// offset of the leaf whose proof the delta produces
+    to: usize,
+    /// siblings of the produced proof that differ, starting at the leaf layer
+    siblings: Vec<Output<D>>,
+}
+
+impl<D: Digest> PartialEq for ProofDelta<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.from == other.from && self.to == other.to && self.siblings == other.siblings
+    }
+}
+
+impl<D: Digest> Eq for ProofDelta<D> {}
+
+impl<D> ProofDelta<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// returns the offset of the leaf whose proof the delta applies to
+    pub fn from(&self) -> usize {
+        self.from
+    }
+
+    /// returns the offset of the leaf whose proof the delta produces
+    pub fn to(&self) -> usize {
+        self.to
+    }
+
+    /// returns the siblings that differ, starting at the leaf layer
+    pub fn siblings(&self) -> &[Output<D>] {
+        &self.siblings
+    }
+
+    /// creates the proof of leaf `to()` from the proof of leaf `from()`
+    /// returns None if the base proof is not a proof of `from()` or too short for the delta
+    pub fn apply<S: HashScheme<D>>(&self, base: &Proof<D, S>) -> Option<Proof<D, S>> {
+        if base.leaf_index() != self.from || base.len() < self.siblings.len() {
+            return None;
+        }
+        let mut siblings = self.siblings.clone();
+        siblings.extend_from_slice(&base.siblings()[self.siblings.len()..]);
+        Some(Proof::new(self.to, siblings))
+    }
+}
+
+/// The new hashes on the path of a changed leaf, used to patch proofs handed out before the change
+/// Every other proof shares exactly one node with the path of the changed leaf as a sibling, so
+/// clients can keep their proofs valid with `Proof::apply_update` instead of downloading new ones.
+/// After several changes, applying the updates of all changed leaves taken from the final tree
+/// yields the current proof regardless of their order.
+#[derive(Debug, Clone)]
+pub struct ProofUpdate<D: Digest> {
+    /// offset of the changed leaf
+    offset: usize,
+    /// hashes of the nodes on the path of the leaf starting with the leaf, without the root
+    path: Vec<Output<D>>,
+}
+
+impl<D: Digest> PartialEq for ProofUpdate<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.offset == other.offset && self.path == other.path
+    }
+}
+
+impl<D: Digest> Eq for ProofUpdate<D> {}
+
+impl<D: Digest> ProofUpdate<D> {
+    /// creates an update from the offset of the changed leaf and the hashes of the nodes on its
+    /// path starting with the leaf, without the root
+    pub fn new(offset: usize, path: Vec<Output<D>>) -> Self {
+        Self { offset, path }
+    }
+
+    /// returns the offset of the changed leaf
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// returns the hashes of the nodes on the path of the changed leaf starting with the leaf
+    pub fn path(&self) -> &[Output<D>] {
+        &self.path
+    }
+}
+
+impl<D, S> MerkleTree<D, S>
 where
     D: Digest + Default + Clone + Debug,
     Output<D>: Copy, // big performance hit if not Copy
+    S: HashScheme<D>,
 {
     /// creates a new Merkle tree with the given depth and initial value for the leaves
+    /// a depth of 0 creates an empty tree, see `empty`
+    /// panics if the depth is larger than `MAX_DEPTH`, see `try_new` for a fallible version
     pub fn new(depth: usize, initial_value: &Output<D>) -> Self {
-        // panic if depth < 1
-        if depth < 1 {
-            panic!("Merkle tree depth must be at least 1");
+        match Self::try_new(depth, initial_value) {
+            Ok(tree) => tree,
+            Err(err) => panic!("{err}"),
         }
+    }
+
+    /// creates a new Merkle tree with the given depth and initial value for the leaves
+    /// a depth of 0 creates an empty tree, see `empty`
+    /// returns an error if the depth is larger than `MAX_DEPTH`
+    pub fn try_new(depth: usize, initial_value: &Output<D>) -> Result<Self, MerkleTreeError> {
+        Self::new_cancellable(depth, initial_value, &CancellationToken::new(), &mut |_| {})
+    }
 
-        let mut nodes = vec![initial_value.to_owned(); Self::nodes_in_tree(depth)];
+    /// creates a new Merkle tree with the given depth and initial value for the leaves
+    /// The cancellation token is checked and the progress (in nodes written) is reported while
+    /// filling the tree, so building a large tree can be aborted. Also returns an error instead of
+    /// aborting if the nodes can't be allocated.
+    pub fn new_cancellable(
+        depth: usize,
+        initial_value: &Output<D>,
+        cancel: &CancellationToken,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<Self, MerkleTreeError> {
+        if depth > MAX_DEPTH {
+            return Err(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            });
+        }
+        if depth == 0 {
+            return Ok(Self::empty(initial_value));
+        }
 
-        // update all the hashes of the intermediate layers. Note that all hashes within one layer are the same
+        // compute the hashes of the intermediate layers. Note that all hashes within one layer are the same
+        let mut layer_hashes = vec![initial_value.to_owned(); depth];
         for d in (0..depth - 1).rev() {
-            // compute hash of (d, 0)
-            let mut hasher = D::new();
-            hasher.update(nodes[Self::first_child_index(d, 0)]);
-            hasher.update(nodes[Self::second_child_index(d, 0)]);
-            let hash = hasher.finalize();
-            // set all nodes in the layer to the same hash
-            for i in 0..(1 << d) {
-                nodes[Self::index(d, i)] = hash;
+            layer_hashes[d] = Self::hash_pair(&layer_hashes[d + 1], &layer_hashes[d + 1]);
+        }
+
+        let total = Self::nodes_in_tree(depth);
+        let mut nodes = Vec::new();
+        nodes
+            .try_reserve_exact(total)
+            .map_err(|_| MerkleTreeError::AllocationFailed { nodes: total })?;
+
+        // fill the layers in breadth-first order
+        for (d, hash) in layer_hashes.iter().enumerate() {
+            let mut remaining = 1usize << d;
+            while remaining > 0 {
+                cancel.check()?;
+                let count = remaining.min(CHECK_INTERVAL);
+                nodes.extend(core::iter::repeat_n(*hash, count));
+                remaining -= count;
+                progress(Progress {
+                    completed: nodes.len(),
+                    total,
+                });
             }
         }
-        Self { depth, nodes }
+        Ok(Self {
+            depth,
+            nodes: nodes.into(),
+            next_offset: 1 << (depth - 1),
+            padding: initial_value.to_owned(),
+            history: NodeHistory::default(),
+            population: Population::default(),
+            scheme: PhantomData,
+        })
+    }
+
+    /// creates a tree without any leaves
+    /// The tree has depth 0 and `HashScheme::empty_root` of its scheme, the hash of the empty string,
+    /// as root. The padding fills the leaves once the tree grows by pushing a leaf.
+    pub fn empty(padding: &Output<D>) -> Self {
+        Self::with_leaves(&[], padding)
+    }
+
+    /// creates a new Merkle tree from the given leaves
+    /// without leaves the tree is empty, see `empty`
+    /// the leaves are padded with the padding value to the next power of two, all intermediate
+    /// layers are then computed bottom-up in a single pass
+    pub fn from_leaves(leaves: &[Output<D>], padding: &Output<D>) -> Self {
+        let mut tree = Self::with_leaves(leaves, padding);
+        tree.build_layers();
+        tree
+    }
+
+    /// creates a new Merkle tree from the given leaves like `from_leaves`
+    /// The cancellation token is checked and the progress (in intermediate nodes hashed) is reported
+    /// while building the layers.
+    pub fn from_leaves_cancellable(
+        leaves: &[Output<D>],
+        padding: &Output<D>,
+        cancel: &CancellationToken,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<Self, MerkleTreeError> {
+        let mut tree = Self::with_leaves(leaves, padding);
+        tree.build_layers_cancellable(cancel, progress)?;
+        Ok(tree)
+    }
+
+    /// creates a new Merkle tree from raw leaf payloads
+    /// every payload is hashed with `hash_leaf` to obtain the leaf value, the tree is then built like
+    /// `from_leaves`
+    pub fn from_leaf_data<T: AsRef<[u8]>>(data: &[T], padding: &Output<D>) -> Self {
+        let leaves: Vec<Output<D>> = data.iter().map(|item| Self::hash_leaf(item)).collect();
+        Self::from_leaves(&leaves, padding)
+    }
+
+    /// creates a new Merkle tree from raw leaf payloads like `from_leaf_data`, hashing the payloads
+    /// and the nodes of each layer in parallel
+    #[cfg(feature = "rayon")]
+    pub fn par_from_leaf_data<T: AsRef<[u8]> + Sync>(data: &[T], padding: &Output<D>) -> Self {
+        use rayon::prelude::*;
+
+        let leaves: Vec<Output<D>> = data
+            .par_iter()
+            .with_min_len(PAR_MIN_LEN)
+            .map(|item| Self::hash_leaf(item))
+            .collect();
+        Self::par_from_leaves(&leaves, padding)
+    }
+
+    /// creates a new Merkle tree from the given leaves like `from_leaves`, hashing the nodes of each
+    /// layer in parallel
+    #[cfg(feature = "rayon")]
+    pub fn par_from_leaves(leaves: &[Output<D>], padding: &Output<D>) -> Self {
+        let mut tree = Self::with_leaves(leaves, padding);
+        tree.par_build_layers();
+        tree
+    }
+
+    /// creates a new Merkle tree from the given leaves like `from_leaves`, storing the nodes in the
+    /// given buffer instead of a new allocation
+    pub(crate) fn from_leaves_in(
+        nodes: Vec<Output<D>>,
+        leaves: &[Output<D>],
+        padding: &Output<D>,
+    ) -> Self {
+        let mut tree = Self::with_leaves_in(nodes, leaves, padding);
+        tree.build_layers();
+        tree
+    }
+
+    /// takes the node buffer out of the tree, leaving it without nodes
+    pub(crate) fn take_nodes(&mut self) -> Vec<Output<D>> {
+        self.nodes.take_vec()
+    }
+
+    /// creates a tree from its node buffer, the buffer has to hold a complete tree of the given depth
+    #[cfg(feature = "std")]
+    pub(crate) fn from_node_buf(
+        depth: usize,
+        nodes: NodeBuf<D>,
+        next_offset: usize,
+        padding: Output<D>,
+    ) -> Self {
+        Self {
+            depth,
+            nodes,
+            next_offset,
+            padding,
+            history: NodeHistory::default(),
+            population: Population::default(),
+            scheme: PhantomData,
+        }
+    }
+
+    /// returns the node buffer of the tree
+    #[cfg(feature = "mmap")]
+    pub(crate) fn nodes_buf(&self) -> &NodeBuf<D> {
+        &self.nodes
+    }
+
+    /// returns the node buffer of the tree for writing, the caller has to keep the nodes consistent
+    #[cfg(feature = "mmap")]
+    pub(crate) fn nodes_buf_mut(&mut self) -> &mut NodeBuf<D> {
+        &mut self.nodes
     }
 
     /// returns the root hash of the tree
@@ -46,14 +850,114 @@ where
 
     /// returns the number of leaves in the tree
     pub fn num_leaves(&self) -> usize {
-        1 << (self.depth - 1)
+        (1 << self.depth) >> 1
+    }
+
+    /// returns true if the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.depth == 0
+    }
+
+    /// returns the depth of the tree, the number of layers including the root and the leaves
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// returns the number of nodes in the tree
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// returns the value of a leaf, None if the offset is out of bounds
+    pub fn get(&self, offset: usize) -> Option<&Output<D>> {
+        if offset >= self.num_leaves() {
+            return None;
+        }
+        Some(&self.nodes[Self::index(self.depth - 1, offset)])
+    }
+
+    /// returns an iterator over all leaves including the unused ones
+    pub fn leaves(&self) -> core::slice::Iter<'_, Output<D>> {
+        match self.depth {
+            0 => [].iter(),
+            depth => self.iter_layer(depth - 1),
+        }
+    }
+
+    /// returns an iterator over the nodes of a layer from left to right, layer 0 holds the root
+    /// panics if the tree has no such layer, see `try_iter_layer` for a fallible version
+    pub fn iter_layer(&self, layer: usize) -> core::slice::Iter<'_, Output<D>> {
+        match self.try_iter_layer(layer) {
+            Ok(iter) => iter,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// returns an iterator over the nodes of a layer from left to right, layer 0 holds the root
+    /// returns an error if the tree has no such layer
+    pub fn try_iter_layer(
+        &self,
+        layer: usize,
+    ) -> Result<core::slice::Iter<'_, Output<D>>, MerkleTreeError> {
+        if layer >= self.depth {
+            return Err(MerkleTreeError::LayerOutOfRange {
+                layer,
+                depth: self.depth,
+            });
+        }
+        let start = Self::index(layer, 0);
+        Ok(self.nodes[start..start + (1 << layer)].iter())
+    }
+
+    /// returns the node at the given index
+    pub(crate) fn node(&self, index: usize) -> &Output<D> {
+        &self.nodes[index]
+    }
+
+    /// returns the value of unused leaves
+    pub(crate) fn padding(&self) -> &Output<D> {
+        &self.padding
+    }
+
+    /// creates a tree from all of its leaves, the padding value and the offset of the next pushed leaf
+    /// returns None if the number of leaves is not a power of two or the offset is out of bounds
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_parts(
+        leaves: &[Output<D>],
+        padding: &Output<D>,
+        next_offset: usize,
+    ) -> Option<Self> {
+        if leaves.is_empty() && next_offset == 0 {
+            return Some(Self::empty(padding));
+        }
+        if !leaves.len().is_power_of_two()
+            || leaves.len() > 1 << (MAX_DEPTH - 1)
+            || next_offset > leaves.len()
+        {
+            return None;
+        }
+        let mut tree = Self::from_leaves(leaves, padding);
+        tree.next_offset = next_offset;
+        Some(tree)
     }
 
     /// updates the value of a leaf node
+    /// panics if the offset is out of bounds, see `try_set` for a fallible version
     pub fn set(&mut self, offset: usize, value: &Output<D>) {
-        // find index of the node to update and set the new value
-        let index = Self::index(self.depth - 1, offset);
-        self.nodes[index] = value.to_owned();
+        if let Err(err) = self.try_set(offset, value) {
+            panic!("{err}");
+        }
+    }
+
+    /// updates the value of a leaf node
+    /// returns an error if the offset is out of bounds
+    pub fn try_set(&mut self, offset: usize, value: &Output<D>) -> Result<(), MerkleTreeError> {
+        self.write_leaf(offset, value)?;
+
+        // a tree of depth 1 consists of the root only
+        if self.depth == 1 {
+            return Ok(());
+        }
 
         // update all parent nodes
         // start from the parent of the updated node and go up to the root
@@ -61,13 +965,13 @@ where
             Self::depth_offset(Self::parent_index(self.depth - 1, offset));
         loop {
             // compute new hash
-            let mut hasher = D::new();
-            hasher.update(self.nodes[Self::first_child_index(parent_layer, parent_offset)]);
-            hasher.update(self.nodes[Self::second_child_index(parent_layer, parent_offset)]);
-            let hash = hasher.finalize();
+            let hash = Self::hash_pair(
+                &self.nodes[Self::first_child_index(parent_layer, parent_offset)],
+                &self.nodes[Self::second_child_index(parent_layer, parent_offset)],
+            );
 
             // set the new hash
-            self.nodes[Self::index(parent_layer, parent_offset)] = hash;
+            self.write_node(parent_layer, parent_offset, &hash);
 
             // check if we reached the root
             if parent_layer == 0 {
@@ -78,56 +982,780 @@ where
             parent_offset /= 2;
             parent_layer -= 1;
         }
+        Ok(())
+    }
+
+    /// updates the value of a leaf node without rehashing its ancestors, see `rehash_paths`
+    /// a memory mapped tree with logged batches is checkpointed first, replaying the write-ahead log
+    /// on `open` would overwrite the leaf with an older value otherwise
+    /// returns an error if the offset is out of bounds or the checkpoint fails
+    pub(crate) fn write_leaf(
+        &mut self,
+        offset: usize,
+        value: &Output<D>,
+    ) -> Result<(), MerkleTreeError> {
+        self.check_offset(offset)?;
+        self.nodes.checkpoint_wal()?;
+        self.write_logged_leaf(offset, value);
+        Ok(())
+    }
+
+    /// updates the value of a leaf node that is part of the write-ahead log like `write_leaf`,
+    /// without a checkpoint
+    /// the offset has to be in bounds
+    pub(crate) fn write_logged_leaf(&mut self, offset: usize, value: &Output<D>) {
+        // find index of the node to update and set the new value
+        let was_default = self.nodes[Self::index(self.depth - 1, offset)] == self.padding;
+        if was_default != (*value == self.padding) {
+            self.population.update(self.depth, offset, was_default);
+        }
+        self.write_node(self.depth - 1, offset, value);
+    }
+
+    /// rehashes the ancestors of the leaves at the given offsets layer by layer, every node shared
+    /// by several paths is hashed once
+    /// the offsets are consumed, the buffer is left empty for reuse
+    pub(crate) fn rehash_paths(&mut self, offsets: &mut Vec<usize>) {
+        offsets.sort_unstable();
+        offsets.dedup();
+        // the children of the dirty nodes of a layer are gathered, so they are hashed in one batch
+        let mut children = Vec::new();
+        let mut parents = Vec::new();
+        for layer in (0..self.depth.saturating_sub(1)).rev() {
+            for offset in offsets.iter_mut() {
+                *offset /= 2;
+            }
+            offsets.dedup();
+            children.clear();
+            for &offset in offsets.iter() {
+                let first = Self::first_child_index(layer, offset);
+                children.extend_from_slice(&self.nodes[first..first + 2]);
+            }
+            parents.resize(offsets.len(), self.padding);
+            S::hash_nodes(&children, &mut parents);
+            for (&offset, hash) in offsets.iter().zip(&parents) {
+                self.write_node(layer, offset, hash);
+            }
+        }
+        offsets.clear();
+    }
+
+    /// overwrites a leaf with the padding value, the old value is scrubbed from the node buffer and
+    /// the path to the root is rehashed without it
+    /// snapshots taken before keep the old value until the tree is dropped
+    /// panics if the offset is out of bounds, see `try_clear_leaf` for a fallible version
+    pub fn clear_leaf(&mut self, offset: usize) {
+        if let Err(err) = self.try_clear_leaf(offset) {
+            panic!("{err}");
+        }
+    }
+
+    /// overwrites a leaf with the padding value like `clear_leaf`
+    /// returns an error if the offset is out of bounds
+    pub fn try_clear_leaf(&mut self, offset: usize) -> Result<(), MerkleTreeError> {
+        let padding = self.padding;
+        self.try_set(offset, &padding)
+    }
+
+    /// returns the offset the next pushed leaf is written to
+    pub fn next_offset(&self) -> usize {
+        self.next_offset
+    }
+
+    /// appends a leaf after the last pushed leaf, returns its offset
+    /// panics if the tree can't grow any further, see `try_push` for a fallible version
+    pub fn push(&mut self, leaf: &Output<D>) -> usize {
+        match self.try_push(leaf) {
+            Ok(offset) => offset,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// appends a leaf after the last pushed leaf, returns its offset
+    /// Trees built by `from_leaves` are filled up to their padded size first, once all leaves are
+    /// in use the tree grows by one level. The old root becomes the left child of the new root and
+    /// the leaves of the new right subtree hold the padding value.
+    /// returns an error if the tree would exceed `MAX_DEPTH` or its nodes can't be allocated
+    pub fn try_push(&mut self, leaf: &Output<D>) -> Result<usize, MerkleTreeError> {
+        let offset = self.next_offset;
+        if offset == self.num_leaves() {
+            self.grow()?;
+        }
+        self.try_set(offset, leaf)?;
+        self.next_offset += 1;
+        self.nodes.store_next_offset(self.next_offset);
+        Ok(offset)
+    }
+
+    /// doubles the number of leaves by adding a new root above the current one
+    fn grow(&mut self) -> Result<(), MerkleTreeError> {
+        let depth = self.depth + 1;
+        if depth > MAX_DEPTH {
+            return Err(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            });
+        }
+        self.population.reset();
+
+        // the first leaf of an empty tree replaces the empty root
+        if self.depth == 0 {
+            self.nodes[0] = self.padding;
+            self.depth = 1;
+            return Ok(());
+        }
+
+        // hashes of the layers of the padding subtree, indexed by their layer in the grown tree
+        let mut padding_hashes = vec![self.padding; depth];
+        for d in (1..depth - 1).rev() {
+            padding_hashes[d] = Self::hash_pair(&padding_hashes[d + 1], &padding_hashes[d + 1]);
+        }
+
+        // move every layer down by one in place, starting with the leaves so that no layer is
+        // overwritten before it was moved
+        self.nodes
+            .resize(Self::nodes_in_tree(depth), &self.padding)?;
+        for d in (1..depth).rev() {
+            let width = 1 << (d - 1);
+            let start = Self::index(d, 0);
+            self.nodes
+                .copy_within(Self::index(d - 1, 0)..Self::index(d - 1, 0) + width, start);
+            self.nodes[start + width..start + 2 * width].fill(padding_hashes[d]);
+        }
+        self.nodes[0] = Self::hash_pair(&self.nodes[1], &self.nodes[2]);
+        self.depth = depth;
+        Ok(())
     }
 
     /// Create a proof for a leaf node
-    /// The proof is a list of hashes that can be used to verify the inclusion of the leaf in the tree
-    /// Returns a list of (hash, is_left) pairs, where hash is the hash of the sibling of the node on the path to the root
-    pub fn create_proof(&self, offset: usize) -> Vec<(Output<D>, bool)> {
-        let mut proof = Vec::new();
-        let mut current_offset = offset;
-        let mut current_layer = self.depth - 1;
-        while current_layer > 0 {
-            let sibling_offset = if current_offset % 2 == 0 {
-                current_offset + 1
+    /// The proof contains the hashes of the siblings of the nodes on the path to the root and can be
+    /// used to verify the inclusion of the leaf in the tree
+    /// panics if the offset is out of bounds, see `try_create_proof` for a fallible version
+    pub fn create_proof(&self, offset: usize) -> Proof<D, S> {
+        match self.try_create_proof(offset) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Create a proof for a leaf node
+    /// returns an error if the offset is out of bounds
+    pub fn try_create_proof(&self, offset: usize) -> Result<Proof<D, S>, MerkleTreeError> {
+        self.check_offset(offset)?;
+        self.nodes.read_ahead(self.depth, offset);
+        let siblings = Self::sibling_indices(self.depth, offset)
+            .into_iter()
+            .map(|(index, _)| self.nodes[index])
+            .collect();
+        Ok(Proof::new(offset, siblings))
+    }
+
+    /// creates the proofs of all leaves in leaf order, including the leaves holding the padding
+    /// Every layer is read once from left to right instead of walking the path of every leaf, the
+    /// sibling of a node is copied into the proofs of all leaves below it.
+    pub fn create_all_proofs(&self) -> Vec<Proof<D, S>> {
+        let layers = self.depth.saturating_sub(1);
+        let mut siblings: Vec<Vec<Output<D>>> = (0..self.num_leaves())
+            .map(|_| Vec::with_capacity(layers))
+            .collect();
+        for layer in (1..self.depth).rev() {
+            let nodes = self.iter_layer(layer).as_slice();
+            let shift = layers - layer;
+            for (offset, siblings) in siblings.iter_mut().enumerate() {
+                siblings.push(nodes[(offset >> shift) ^ 1]);
+            }
+        }
+        siblings
+            .into_iter()
+            .enumerate()
+            .map(|(offset, siblings)| Proof::new(offset, siblings))
+            .collect()
+    }
+
+    /// creates a proof that a leaf still holds the padding value of the tree
+    /// panics if the offset is out of bounds or the leaf was set, see
+    /// `try_create_non_inclusion_proof` for a fallible version
+    pub fn create_non_inclusion_proof(&self, offset: usize) -> NonInclusionProof<D, S> {
+        match self.try_create_non_inclusion_proof(offset) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a proof that a leaf still holds the padding value of the tree
+    /// returns an error if the offset is out of bounds or the leaf holds another value
+    pub fn try_create_non_inclusion_proof(
+        &self,
+        offset: usize,
+    ) -> Result<NonInclusionProof<D, S>, MerkleTreeError> {
+        let proof = self.try_create_proof(offset)?;
+        if self.get(offset) != Some(&self.padding) {
+            return Err(MerkleTreeError::LeafNotEmpty { offset });
+        }
+        Ok(NonInclusionProof::new(proof))
+    }
+
+    /// returns the siblings in which the proof of leaf `to` differs from the proof of leaf `from`
+    /// panics if an offset is out of bounds, see `try_proof_delta` for a fallible version
+    pub fn proof_delta(&self, from: usize, to: usize) -> ProofDelta<D> {
+        match self.try_proof_delta(from, to) {
+            Ok(delta) => delta,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// returns the siblings in which the proof of leaf `to` differs from the proof of leaf `from`
+    /// returns an error if an offset is out of bounds
+    pub fn try_proof_delta(
+        &self,
+        from: usize,
+        to: usize,
+    ) -> Result<ProofDelta<D>, MerkleTreeError> {
+        self.check_offset(from)?;
+        self.check_offset(to)?;
+        // the paths join one layer above the highest differing bit of the offsets
+        let differing_layers = (usize::BITS - (from ^ to).leading_zeros()) as usize;
+        let siblings = Self::sibling_indices(self.depth, to)
+            .into_iter()
+            .take(differing_layers)
+            .map(|(index, _)| self.nodes[index])
+            .collect();
+        Ok(ProofDelta { from, to, siblings })
+    }
+
+    /// returns the update patching the proofs of other leaves after the leaf at the offset changed
+    /// panics if the offset is out of bounds, see `try_proof_update` for a fallible version
+    pub fn proof_update(&self, offset: usize) -> ProofUpdate<D> {
+        match self.try_proof_update(offset) {
+            Ok(update) => update,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// returns the update patching the proofs of other leaves after the leaf at the offset changed
+    /// returns an error if the offset is out of bounds
+    pub fn try_proof_update(&self, offset: usize) -> Result<ProofUpdate<D>, MerkleTreeError> {
+        self.check_offset(offset)?;
+        let path = (1..self.depth)
+            .rev()
+            .map(|layer| self.nodes[Self::index(layer, offset >> (self.depth - 1 - layer))])
+            .collect();
+        Ok(ProofUpdate { offset, path })
+    }
+
+    /// returns the offsets of the leaves that differ between this tree and `other` in ascending order
+    /// only subtrees with differing roots are visited, so the cost grows with the number of changes
+    /// instead of the number of leaves
+    /// panics if the trees don't have the same depth, see `try_diff` for a fallible version
+    pub fn diff(&self, other: &Self) -> Vec<usize> {
+        match self.try_diff(other) {
+            Ok(offsets) => offsets,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// returns the offsets of the leaves that differ between this tree and `other` like `diff`
+    /// returns an error if the trees don't have the same depth
+    pub fn try_diff(&self, other: &Self) -> Result<Vec<usize>, MerkleTreeError> {
+        if self.depth != other.depth {
+            return Err(MerkleTreeError::DepthMismatch {
+                expected: self.depth,
+                actual: other.depth,
+            });
+        }
+        let mut offsets = Vec::new();
+        if self.is_empty() {
+            return Ok(offsets);
+        }
+        // depth-first, visiting the left child first to collect the offsets in ascending order
+        let mut pending = vec![(0, 0)];
+        while let Some((layer, offset)) = pending.pop() {
+            let index = Self::index(layer, offset);
+            if self.nodes[index] == other.nodes[index] {
+                continue;
+            }
+            if layer == self.depth - 1 {
+                offsets.push(offset);
             } else {
-                current_offset - 1
-            };
-            let sibling_index = Self::index(current_layer, sibling_offset);
-            let sibling_hash = self.nodes[sibling_index];
-            proof.push((sibling_hash, current_offset % 2 == 0));
-            current_offset /= 2;
-            current_layer -= 1;
+                pending.push((layer + 1, 2 * offset + 1));
+                pending.push((layer + 1, 2 * offset));
+            }
         }
-        proof
+        Ok(offsets)
     }
 
     /// Verify a proof for a leaf node
-    /// The proof is a list of hashes that can be used to verify the inclusion of the leaf in the tree
-    pub fn verify_proof(&self, value: &Output<D>, proof: &[(Output<D>, bool)]) -> Output<D> {
-        let mut current_value = *value;
-        for (hash, is_left) in proof {
-            let mut hasher = D::new();
-            if *is_left {
-                hasher.update(current_value);
-                hasher.update(hash);
-            } else {
-                hasher.update(hash);
-                hasher.update(current_value);
+    /// Returns the root hash computed from the leaf value and the proof, which has to be compared to the
+    /// expected root hash. Use the free function `verify` to check a proof against a known root.
+    pub fn verify_proof(&self, value: &Output<D>, proof: &Proof<D, S>) -> Output<D> {
+        proof.compute_root(value)
+    }
+
+    /// Verify a proof for a leaf node of this tree
+    /// In contrast to `verify_proof` this checks that the proof matches the shape of the tree
+    /// Returns the computed root hash or an error if the proof is invalid for this tree
+    pub fn try_verify_proof(
+        &self,
+        value: &Output<D>,
+        proof: &Proof<D, S>,
+    ) -> Result<Output<D>, MerkleTreeError> {
+        if proof.len() != self.depth.saturating_sub(1) {
+            return Err(MerkleTreeError::InvalidProofLength {
+                expected: self.depth.saturating_sub(1),
+                actual: proof.len(),
+            });
+        }
+        self.check_offset(proof.leaf_index())?;
+        Ok(proof.compute_root(value))
+    }
+
+    /// Create a proof for multiple leaf nodes at once
+    /// Sibling hashes which are shared between the individual paths or which can be computed from
+    /// the proven leaves are omitted, so the proof is never larger than the individual proofs combined
+    /// Duplicate offsets are ignored
+    /// panics if an offset is out of bounds, see `try_create_multi_proof` for a fallible version
+    pub fn create_multi_proof(&self, offsets: &[usize]) -> MultiProof<D, S> {
+        match self.try_create_multi_proof(offsets) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Create a proof for multiple leaf nodes at once like `create_multi_proof`
+    /// returns an error if an offset is out of bounds
+    pub fn try_create_multi_proof(
+        &self,
+        offsets: &[usize],
+    ) -> Result<MultiProof<D, S>, MerkleTreeError> {
+        for offset in offsets {
+            self.check_offset(*offset)?;
+        }
+        let mut offsets = offsets.to_vec();
+        offsets.sort_unstable();
+        offsets.dedup();
+
+        let mut hashes = Vec::new();
+        let mut current_offsets = offsets.clone();
+        let mut current_layer = self.depth.saturating_sub(1);
+        while current_layer > 0 {
+            let mut parent_offsets = Vec::with_capacity(current_offsets.len());
+            let mut i = 0;
+            while i < current_offsets.len() {
+                let current_offset = current_offsets[i];
+                if current_offset.is_multiple_of(2)
+                    && current_offsets.get(i + 1) == Some(&(current_offset + 1))
+                {
+                    // the sibling is part of the proven set, no need to include it
+                    i += 2;
+                } else {
+                    let sibling_index = Self::index(current_layer, current_offset ^ 1);
+                    hashes.push(self.nodes[sibling_index]);
+                    i += 1;
+                }
+                parent_offsets.push(current_offset / 2);
             }
-            current_value = hasher.finalize();
+            current_offsets = parent_offsets;
+            current_layer -= 1;
         }
-        current_value
+
+        Ok(MultiProof {
+            depth: self.depth,
+            offsets,
+            hashes,
+            scheme: PhantomData,
+        })
+    }
+
+    /// creates a proof for the values of the leaves in the given range
+    /// panics if the range is empty or out of bounds, see `try_create_range_proof` for a fallible
+    /// version
+    pub fn create_range_proof(&self, range: Range<usize>) -> RangeProof<D, S> {
+        match self.try_create_range_proof(range) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a proof for the values of the leaves in the given range
+    /// returns an error if the range is empty or out of bounds
+    pub fn try_create_range_proof(
+        &self,
+        range: Range<usize>,
+    ) -> Result<RangeProof<D, S>, MerkleTreeError> {
+        if range.is_empty() {
+            return Err(MerkleTreeError::EmptyRange);
+        }
+        self.check_offset(range.end - 1)?;
+        let mut hashes = Vec::new();
+        let (mut first, mut last) = (range.start, range.end - 1);
+        for layer in (1..self.depth).rev() {
+            if first % 2 == 1 {
+                hashes.push(self.nodes[Self::index(layer, first - 1)]);
+            }
+            if last % 2 == 0 {
+                hashes.push(self.nodes[Self::index(layer, last + 1)]);
+            }
+            first /= 2;
+            last /= 2;
+        }
+        Ok(RangeProof::new(self.depth, hashes))
+    }
+
+    /// returns the root of the subtree below the node at the offset of the layer, layer 0 holds the
+    /// root of the tree
+    /// panics if the tree has no such node, see `try_subtree_root` for a fallible version
+    pub fn subtree_root(&self, layer: usize, offset: usize) -> &Output<D> {
+        match self.try_subtree_root(layer, offset) {
+            Ok(root) => root,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// returns the root of the subtree below the node at the offset of the layer
+    /// returns an error if the tree has no such node
+    pub fn try_subtree_root(
+        &self,
+        layer: usize,
+        offset: usize,
+    ) -> Result<&Output<D>, MerkleTreeError> {
+        self.try_iter_layer(layer)?
+            .as_slice()
+            .get(offset)
+            .ok_or(MerkleTreeError::NodeOutOfRange { layer, offset })
+    }
+
+    /// copies the subtree below the node at the offset of the layer into a tree of its own
+    /// The subtree has `depth() - layer` layers and the padding of this tree, its next pushed leaf
+    /// follows the last pushed leaf of this tree that lies below it.
+    /// panics if the tree has no such node, see `try_extract_subtree` for a fallible version
+    pub fn extract_subtree(&self, layer: usize, offset: usize) -> Self {
+        match self.try_extract_subtree(layer, offset) {
+            Ok(subtree) => subtree,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// copies the subtree below the node at the offset of the layer into a tree of its own
+    /// returns an error if the tree has no such node
+    pub fn try_extract_subtree(
+        &self,
+        layer: usize,
+        offset: usize,
+    ) -> Result<Self, MerkleTreeError> {
+        self.try_subtree_root(layer, offset)?;
+        let depth = self.depth - layer;
+        let mut nodes = Vec::with_capacity(Self::nodes_in_tree(depth));
+        for height in 0..depth {
+            let start = Self::index(layer + height, offset << height);
+            nodes.extend_from_slice(&self.nodes[start..start + (1 << height)]);
+        }
+        let first_leaf = offset << (depth - 1);
+        Ok(Self {
+            depth,
+            nodes: nodes.into(),
+            next_offset: self
+                .next_offset
+                .saturating_sub(first_leaf)
+                .min(1 << (depth - 1)),
+            padding: self.padding,
+            history: NodeHistory::default(),
+            population: Population::default(),
+            scheme: PhantomData,
+        })
+    }
+
+    /// creates a proof of the subtree root at the offset of the layer against the root of the tree
+    /// The proof holds the siblings from the layer up to the root, it verifies the subtree root
+    /// like a leaf with `verify`.
+    /// panics if the tree has no such node, see `try_create_subtree_proof` for a fallible version
+    pub fn create_subtree_proof(&self, layer: usize, offset: usize) -> Proof<D, S> {
+        match self.try_create_subtree_proof(layer, offset) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a proof of the subtree root at the offset of the layer against the root of the tree
+    /// returns an error if the tree has no such node
+    pub fn try_create_subtree_proof(
+        &self,
+        layer: usize,
+        offset: usize,
+    ) -> Result<Proof<D, S>, MerkleTreeError> {
+        self.try_subtree_root(layer, offset)?;
+        let siblings = (1..=layer)
+            .rev()
+            .map(|current| self.nodes[Self::index(current, (offset >> (layer - current)) ^ 1)])
+            .collect();
+        Ok(Proof::new(offset, siblings))
+    }
+
+    /// Verify a proof for multiple leaf nodes
+    /// The leaves must be given in the order of `proof.offsets()`
+    /// Returns the computed root hash, or None if the proof was created for a tree of another depth
+    /// or the leaves or hashes don't match the structure of the proof. Verifiers without the tree
+    /// check the leaves against a trusted root with `MultiProof::verify`.
+    pub fn verify_multi_proof(
+        &self,
+        leaves: &[Output<D>],
+        proof: &MultiProof<D, S>,
+    ) -> Option<Output<D>> {
+        if proof.depth != self.depth {
+            return None;
+        }
+        proof.compute_root(leaves)
+    }
+
+    /// creates a tree holding the given leaves padded to the next power of two
+    /// the intermediate layers are not computed
+    pub(crate) fn with_leaves(leaves: &[Output<D>], padding: &Output<D>) -> Self {
+        Self::with_leaves_in(Vec::new(), leaves, padding)
+    }
+
+    /// creates a tree holding the given leaves like `with_leaves`, reusing the allocation of `nodes`
+    fn with_leaves_in(
+        mut nodes: Vec<Output<D>>,
+        leaves: &[Output<D>],
+        padding: &Output<D>,
+    ) -> Self {
+        nodes.clear();
+        if leaves.is_empty() {
+            nodes.push(S::empty_root());
+            return Self {
+                depth: 0,
+                nodes: nodes.into(),
+                next_offset: 0,
+                padding: padding.to_owned(),
+                history: NodeHistory::default(),
+                population: Population::default(),
+                scheme: PhantomData,
+            };
+        }
+        let depth = Self::log2(leaves.len().next_power_of_two()) + 1;
+        nodes.resize(Self::nodes_in_tree(depth), padding.to_owned());
+        let first_leaf = Self::index(depth - 1, 0);
+        nodes[first_leaf..first_leaf + leaves.len()].copy_from_slice(leaves);
+        Self {
+            depth,
+            nodes: nodes.into(),
+            next_offset: leaves.len(),
+            padding: padding.to_owned(),
+            history: NodeHistory::default(),
+            population: Population::default(),
+            scheme: PhantomData,
+        }
+    }
+
+    /// recomputes the layers above the given layer from its nodes
+    #[cfg(feature = "std")]
+    pub(crate) fn build_layers_above(&mut self, layer: usize) {
+        for d in (0..layer).rev() {
+            let (layer, children) = self.layer_and_children_mut(d);
+            S::hash_nodes(children, layer);
+        }
+    }
+
+    /// returns all nodes for writing, the caller has to keep the intermediate nodes consistent
+    #[cfg(feature = "std")]
+    pub(crate) fn nodes_mut(&mut self) -> &mut [Output<D>] {
+        &mut self.nodes
+    }
+
+    /// sets the offset the next pushed leaf is written to
+    #[cfg(feature = "std")]
+    pub(crate) fn set_next_offset(&mut self, next_offset: usize) {
+        self.next_offset = next_offset;
+    }
+
+    /// writes the leaves to the first offsets of a new tree and recomputes all intermediate layers,
+    /// the next pushed leaf is written behind them
+    /// returns an error if the leaves don't fit into the tree
+    pub(crate) fn fill_leaves(&mut self, leaves: &[Output<D>]) -> Result<(), MerkleTreeError> {
+        if leaves.len() > self.num_leaves() {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: leaves.len() - 1,
+                num_leaves: self.num_leaves(),
+            });
+        }
+        if leaves.is_empty() {
+            return Ok(());
+        }
+        let first_leaf = Self::index(self.depth - 1, 0);
+        self.nodes[first_leaf..first_leaf + leaves.len()].copy_from_slice(leaves);
+        self.build_layers();
+        self.next_offset = leaves.len();
+        self.nodes.store_next_offset(self.next_offset);
+        Ok(())
+    }
+
+    /// recomputes all intermediate layers from the leaves
+    fn build_layers(&mut self) {
+        self.build_layers_cancellable(&CancellationToken::new(), &mut |_| {})
+            .expect("building layers without cancellation can't fail");
+    }
+
+    /// recomputes all intermediate layers from the leaves, checking the cancellation token and
+    /// reporting the progress in between
+    fn build_layers_cancellable(
+        &mut self,
+        cancel: &CancellationToken,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<(), MerkleTreeError> {
+        let total = Self::nodes_in_tree(self.depth.saturating_sub(1));
+        let mut completed = 0;
+        for d in (0..self.depth.saturating_sub(1)).rev() {
+            let (layer, children) = self.layer_and_children_mut(d);
+            for (nodes, children) in layer
+                .chunks_mut(CHECK_INTERVAL)
+                .zip(children.chunks(2 * CHECK_INTERVAL))
+            {
+                cancel.check()?;
+                S::hash_nodes(children, nodes);
+                completed += nodes.len();
+                progress(Progress { completed, total });
+            }
+        }
+        Ok(())
+    }
+
+    /// recomputes all intermediate layers from the leaves, hashing the nodes of each layer in parallel
+    #[cfg(feature = "rayon")]
+    fn par_build_layers(&mut self) {
+        use rayon::prelude::*;
+
+        for d in (0..self.depth.saturating_sub(1)).rev() {
+            let (layer, children) = self.layer_and_children_mut(d);
+            layer
+                .par_chunks_mut(PAR_MIN_LEN)
+                .zip(children.par_chunks(2 * PAR_MIN_LEN))
+                .for_each(|(nodes, children)| S::hash_nodes(children, nodes));
+        }
+    }
+
+    /// checks that every intermediate node matches the hash of its children
+    /// returns an error pointing to the first inconsistent node in breadth-first order
+    pub fn check_integrity(&self) -> Result<(), MerkleTreeError> {
+        self.check_integrity_cancellable(&CancellationToken::new(), &mut |_| {})
+    }
+
+    /// checks the integrity of the tree like `check_integrity`
+    /// The cancellation token is checked and the progress (in intermediate nodes checked) is reported
+    /// while walking the tree.
+    pub fn check_integrity_cancellable(
+        &self,
+        cancel: &CancellationToken,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<(), MerkleTreeError> {
+        if self.is_empty() && self.nodes[0] != S::empty_root() {
+            return Err(MerkleTreeError::InconsistentNode { index: 0 });
+        }
+        let total = Self::nodes_in_tree(self.depth.saturating_sub(1));
+        let mut start = 0;
+        while start < total {
+            cancel.check()?;
+            let end = (start + CHECK_INTERVAL).min(total);
+            for index in start..end {
+                let (d, offset) = Self::depth_offset(index);
+                let hash = Self::hash_pair(
+                    &self.nodes[Self::first_child_index(d, offset)],
+                    &self.nodes[Self::second_child_index(d, offset)],
+                );
+                if hash != self.nodes[index] {
+                    return Err(MerkleTreeError::InconsistentNode { index });
+                }
+            }
+            start = end;
+            progress(Progress {
+                completed: start,
+                total,
+            });
+        }
+        Ok(())
+    }
+
+    /// returns the nodes of the given layer and the nodes of the layer below
+    fn layer_and_children_mut(&mut self, depth: usize) -> (&mut [Output<D>], &[Output<D>]) {
+        let (upper, lower) = self.nodes.split_at_mut(Self::index(depth + 1, 0));
+        (&mut upper[Self::index(depth, 0)..], &lower[..2 << depth])
+    }
+
+    /// overwrites a node, keeping its old value if it is part of a snapshot
+    fn write_node(&mut self, layer: usize, offset: usize, value: &Output<D>) {
+        let index = Self::index(layer, offset);
+        self.history
+            .record(self.depth - 1 - layer, offset, &self.nodes[index]);
+        self.nodes[index] = *value;
+    }
+
+    /// returns the counters of non-default leaves
+    pub(crate) fn population(&self) -> &Population {
+        &self.population
+    }
+
+    /// returns the history of overwritten nodes
+    pub(crate) fn history(&self) -> &NodeHistory<D> {
+        &self.history
+    }
+
+    /// returns an error if the offset doesn't point to a leaf of the tree
+    fn check_offset(&self, offset: usize) -> Result<(), MerkleTreeError> {
+        if offset >= self.num_leaves() {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.num_leaves(),
+            });
+        }
+        Ok(())
+    }
+
+    /// returns the leaf value of a payload as hashed by the scheme of the tree
+    /// values passed to `set`, `push` and `verify_proof` are expected to be hashed like this
+    pub fn hash_leaf(data: impl AsRef<[u8]>) -> Output<D> {
+        S::hash_leaf(data.as_ref())
+    }
+
+    /// returns the hash of two child nodes as hashed by the scheme of the tree
+    pub(crate) fn hash_pair(left: &Output<D>, right: &Output<D>) -> Output<D> {
+        S::hash_node(left, right)
     }
 
     /// returns the index of a node given its depth and offset
     /// depth is the level of the node in the tree
     /// offset is the position of the node in the level
     ///
-    fn index(depth: usize, offset: usize) -> usize {
+    pub(crate) fn index(depth: usize, offset: usize) -> usize {
         Self::nodes_in_tree(depth) + offset
     }
 
+    /// returns the indices of the nodes on the path from a leaf to the root, starting with the leaf
+    #[cfg(feature = "std")]
+    pub(crate) fn path_indices(depth: usize, offset: usize) -> Vec<usize> {
+        (0..depth)
+            .rev()
+            .map(|layer| Self::index(layer, offset >> (depth - 1 - layer)))
+            .collect()
+    }
+
+    /// returns the indices of the siblings of the nodes on the path from a leaf to the root, starting
+    /// at the leaf layer, together with the is_left flag used in proofs
+    pub(crate) fn sibling_indices(depth: usize, offset: usize) -> Vec<(usize, bool)> {
+        (1..depth)
+            .rev()
+            .map(|layer| {
+                let current_offset = offset >> (depth - 1 - layer);
+                let sibling_offset = if current_offset.is_multiple_of(2) {
+                    current_offset + 1
+                } else {
+                    current_offset - 1
+                };
+                (
+                    Self::index(layer, sibling_offset),
+                    current_offset.is_multiple_of(2),
+                )
+            })
+            .collect()
+    }
+
     /// returns the index of the parent of a node
     fn parent_index(depth: usize, offset: usize) -> usize {
         Self::index(depth - 1, offset / 2)
@@ -165,6 +1793,47 @@ where
     }
 }
 
+#[cfg(feature = "zeroize")]
+impl<D: Digest, S> Zeroize for MerkleTree<D, S> {
+    /// overwrites the nodes held in memory, the padding and the old values kept for snapshots
+    /// the nodes of a tree backed by a file are left alone, the file holds the persisted tree
+    fn zeroize(&mut self) {
+        self.nodes.zeroize_heap();
+        self.padding.as_mut_slice().zeroize();
+        self.history.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<D: Digest, S> Drop for MerkleTree<D, S> {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<D: Digest, S> ZeroizeOnDrop for MerkleTree<D, S> {}
+
+#[cfg(feature = "zeroize")]
+impl<D: Digest, S> Zeroize for Proof<D, S> {
+    fn zeroize(&mut self) {
+        for sibling in &mut self.siblings {
+            sibling.as_mut_slice().zeroize();
+        }
+        self.leaf_index.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<D: Digest, S> Drop for Proof<D, S> {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<D: Digest, S> ZeroizeOnDrop for Proof<D, S> {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -172,6 +1841,7 @@ mod tests {
     use sha3::Sha3_256;
 
     type MerkleTree = super::MerkleTree<Sha3_256>;
+    type Proof = super::Proof<Sha3_256>;
 
     #[test]
     fn test_log2() {
@@ -231,6 +1901,31 @@ mod tests {
         assert_eq!(MerkleTree::parent_index(3, 3), 4);
     }
 
+    #[test]
+    fn test_path_indices() {
+        assert_eq!(MerkleTree::path_indices(1, 0), vec![0]);
+        assert_eq!(MerkleTree::path_indices(3, 0), vec![3, 1, 0]);
+        assert_eq!(MerkleTree::path_indices(3, 3), vec![6, 2, 0]);
+        assert_eq!(MerkleTree::path_indices(4, 5), vec![12, 5, 2, 0]);
+    }
+
+    #[test]
+    fn test_sibling_indices() {
+        assert_eq!(MerkleTree::sibling_indices(1, 0), vec![]);
+        assert_eq!(
+            MerkleTree::sibling_indices(3, 0),
+            vec![(4, true), (2, true)]
+        );
+        assert_eq!(
+            MerkleTree::sibling_indices(3, 3),
+            vec![(5, false), (1, false)]
+        );
+        assert_eq!(
+            MerkleTree::sibling_indices(4, 5),
+            vec![(11, false), (6, true), (1, false)]
+        );
+    }
+
     #[test]
     fn test_first_child_index() {
         assert_eq!(MerkleTree::first_child_index(0, 0), 1);
@@ -254,26 +1949,26 @@ mod tests {
         let initial_value = [0u8; 32].into();
         let tree = MerkleTree::new(3, &initial_value);
 
+        assert_eq!(tree.depth(), 3);
+        assert_eq!(tree.num_nodes(), 7);
+
         // check leaves
-        assert_eq!(tree.nodes[3], initial_value);
-        assert_eq!(tree.nodes[4], initial_value);
-        assert_eq!(tree.nodes[5], initial_value);
-        assert_eq!(tree.nodes[6], initial_value);
+        assert!(tree.leaves().eq(&[initial_value; 4]));
 
         // check layer 2
         let mut hasher = Sha3_256::new();
         hasher.update(initial_value);
         hasher.update(initial_value);
         let hash = hasher.finalize();
-        assert_eq!(tree.nodes[1], hash);
-        assert_eq!(tree.nodes[2], hash);
+        assert!(tree.iter_layer(1).eq(&[hash; 2]));
 
         // check root
         let mut hasher = Sha3_256::new();
         hasher.update(hash);
         hasher.update(hash);
         let root = hasher.finalize();
-        assert_eq!(tree.nodes[0], root);
+        assert!(tree.iter_layer(0).eq([&root]));
+        assert_eq!(tree.root_hash(), &root);
     }
 
     #[test]
@@ -285,30 +1980,30 @@ mod tests {
         tree.set(0, &new_value);
 
         // check leaves
-        assert_eq!(tree.nodes[3], new_value);
-        assert_eq!(tree.nodes[4], initial_value);
-        assert_eq!(tree.nodes[5], initial_value);
-        assert_eq!(tree.nodes[6], initial_value);
+        assert_eq!(tree.get(0), Some(&new_value));
+        assert_eq!(tree.get(1), Some(&initial_value));
+        assert_eq!(tree.get(2), Some(&initial_value));
+        assert_eq!(tree.get(3), Some(&initial_value));
+        assert_eq!(tree.get(4), None);
 
         // check layer 2
         let mut hasher = Sha3_256::new();
         hasher.update(new_value);
         hasher.update(initial_value);
         let hash_index_1 = hasher.finalize();
-        assert_eq!(tree.nodes[1], hash_index_1);
 
         let mut hasher = Sha3_256::new();
         hasher.update(initial_value);
         hasher.update(initial_value);
         let hash_index_2 = hasher.finalize();
-        assert_eq!(tree.nodes[2], hash_index_2);
+        assert!(tree.iter_layer(1).eq(&[hash_index_1, hash_index_2]));
 
         // check root
         let mut hasher = Sha3_256::new();
         hasher.update(hash_index_1);
         hasher.update(hash_index_2);
         let root = hasher.finalize();
-        assert_eq!(tree.nodes[0], root);
+        assert_eq!(tree.root_hash(), &root);
     }
 
     #[test]
@@ -375,6 +2070,8 @@ mod tests {
             tree.set(i, &updated_value.into());
         }
         let proof = tree.create_proof(3);
+        assert_eq!(proof.leaf_index(), 3);
+        assert_eq!(proof.len(), 4);
 
         let decode_string = |s: &str| {
             let bs: [u8; 32] = hex::decode(s).unwrap().as_slice().try_into().unwrap();
@@ -382,7 +2079,7 @@ mod tests {
         };
 
         assert_eq!(
-            proof,
+            Vec::from(proof),
             vec![
                 (
                     decode_string(
@@ -434,4 +2131,848 @@ mod tests {
         let proof = tree.create_proof(5);
         assert_eq!(&tree.verify_proof(&leaf_5, &proof), root);
     }
+
+    #[test]
+    fn test_create_multi_proof() {
+        let initial_value = [0x00; 32];
+        let mut tree = MerkleTree::new(4, &initial_value.into());
+        for i in 0..tree.num_leaves() {
+            let updated_value = [(i * 0x11) as u8; 32];
+            tree.set(i, &updated_value.into());
+        }
+
+        // proving a single leaf is equivalent to a regular proof
+        let proof = tree.create_multi_proof(&[5]);
+        assert_eq!(proof.offsets(), &[5]);
+        assert_eq!(proof.hashes(), tree.create_proof(5).siblings());
+
+        // siblings of each other need no hashes on the lowest layer
+        let proof = tree.create_multi_proof(&[2, 3]);
+        assert_eq!(proof.hashes().len(), 2);
+
+        // offsets are sorted and deduplicated
+        let proof = tree.create_multi_proof(&[7, 0, 7, 3]);
+        assert_eq!(proof.offsets(), &[0, 3, 7]);
+
+        // proving all leaves requires no hashes at all
+        let all: Vec<usize> = (0..tree.num_leaves()).collect();
+        let proof = tree.create_multi_proof(&all);
+        assert!(proof.hashes().is_empty());
+
+        assert_eq!(
+            tree.try_create_multi_proof(&[3, 8]),
+            Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: 8,
+                num_leaves: 8
+            })
+        );
+    }
+
+    #[test]
+    fn test_verify_multi_proof() {
+        let initial_value = [0x00; 32];
+        let mut tree = MerkleTree::new(5, &initial_value.into());
+        for i in 0..tree.num_leaves() {
+            let updated_value = [(i * 0x11) as u8; 32];
+            tree.set(i, &updated_value.into());
+        }
+        let leaf = |i: usize| -> Output<Sha3_256> { [(i * 0x11) as u8; 32].into() };
+
+        for offsets in [
+            vec![0],
+            vec![3, 5],
+            vec![0, 1, 2, 3],
+            vec![1, 6, 7, 12, 15],
+            (0..16).collect(),
+        ] {
+            let proof = tree.create_multi_proof(&offsets);
+            let leaves: Vec<_> = proof.offsets().iter().map(|&i| leaf(i)).collect();
+            assert_eq!(
+                tree.verify_multi_proof(&leaves, &proof).as_ref(),
+                Some(tree.root_hash())
+            );
+            // the proof verifies against the trusted root without the tree
+            assert!(proof.verify(tree.root_hash(), &leaves));
+        }
+
+        // a wrong leaf value yields a different root
+        let proof = tree.create_multi_proof(&[3, 5]);
+        let root = tree.verify_multi_proof(&[leaf(3), leaf(4)], &proof);
+        assert_ne!(root.as_ref(), Some(tree.root_hash()));
+        assert!(!proof.verify(tree.root_hash(), &[leaf(3), leaf(4)]));
+        assert!(!proof.verify(&leaf(1), &[leaf(3), leaf(5)]));
+
+        // the proof hashes with the scheme of its tree
+        let leaves: Vec<_> = (0..16).map(leaf).collect();
+        let separated =
+            super::MerkleTree::<Sha3_256, crate::Rfc6962>::from_leaves(&leaves, &[0; 32].into());
+        let separated_proof = separated.create_multi_proof(&[3, 5]);
+        assert!(separated_proof.verify(separated.root_hash(), &[leaf(3), leaf(5)]));
+        assert!(!separated_proof.verify(tree.root_hash(), &[leaf(3), leaf(5)]));
+
+        // a wrong number of leaves is rejected
+        assert_eq!(tree.verify_multi_proof(&[leaf(3)], &proof), None);
+        assert_eq!(tree.verify_multi_proof(&[], &proof), None);
+
+        // a proof of a tree with another depth is rejected, even if its hashes fit
+        let shallow = MerkleTree::from_leaves(&[leaf(0), leaf(1)], &initial_value.into());
+        let proof = shallow.create_multi_proof(&[0]);
+        assert_eq!(proof.depth, 2);
+        assert_eq!(tree.verify_multi_proof(&[leaf(0)], &proof), None);
+        assert_eq!(
+            shallow.verify_multi_proof(&[leaf(0)], &proof).as_ref(),
+            Some(shallow.root_hash())
+        );
+    }
+
+    #[test]
+    fn test_proof_conversion() {
+        let mut tree = MerkleTree::new(4, &[0x00; 32].into());
+        for i in 0..tree.num_leaves() {
+            tree.set(i, &[(i * 0x11) as u8; 32].into());
+        }
+        for i in 0..tree.num_leaves() {
+            let proof = tree.create_proof(i);
+            let pairs: Vec<(Output<Sha3_256>, bool)> = proof.clone().into();
+            assert_eq!(pairs.len(), proof.len());
+            for ((hash, is_left), (expected_hash, expected_is_left)) in
+                pairs.iter().zip(proof.iter())
+            {
+                assert_eq!(hash, expected_hash);
+                assert_eq!(*is_left, expected_is_left);
+            }
+            assert_eq!(Proof::from(pairs), proof);
+        }
+    }
+
+    #[test]
+    fn test_proof_compute_root() {
+        let mut tree = MerkleTree::new(4, &[0x00; 32].into());
+        for i in 0..tree.num_leaves() {
+            tree.set(i, &[(i * 0x11) as u8; 32].into());
+        }
+        let proof = tree.create_proof(6);
+        assert_eq!(&proof.compute_root(&[0x66; 32].into()), tree.root_hash());
+        assert_ne!(&proof.compute_root(&[0x67; 32].into()), tree.root_hash());
+
+        // a single leaf tree has an empty proof
+        let tree = MerkleTree::new(1, &[0x11; 32].into());
+        let proof = tree.create_proof(0);
+        assert!(proof.is_empty());
+        assert_eq!(&proof.compute_root(&[0x11; 32].into()), tree.root_hash());
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut tree = MerkleTree::new(4, &[0x00; 32].into());
+        for i in 0..tree.num_leaves() {
+            tree.set(i, &[(i * 0x11) as u8; 32].into());
+        }
+        let root = *tree.root_hash();
+        let proof = tree.create_proof(3);
+        assert!(verify(&root, &[0x33; 32].into(), &proof));
+        assert!(!verify(&root, &[0x44; 32].into(), &proof));
+        assert!(!verify(&[0x00; 32].into(), &[0x33; 32].into(), &proof));
+
+        // the proof of another leaf doesn't verify
+        let proof = tree.create_proof(4);
+        assert!(!verify(&root, &[0x33; 32].into(), &proof));
+    }
+
+    #[test]
+    fn test_clear_leaf() {
+        let leaves: Vec<Output<Sha3_256>> = (1..4).map(|i| [i as u8; 32].into()).collect();
+        let mut tree = MerkleTree::from_leaves(&leaves, &[0; 32].into());
+        tree.clear_leaf(1);
+        assert_eq!(tree.get(1), Some(&[0; 32].into()));
+        let cleared = [leaves[0], [0; 32].into(), leaves[2]];
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::from_leaves(&cleared, &[0; 32].into()).root_hash()
+        );
+        assert!(!tree.leaves().any(|leaf| *leaf == leaves[1]));
+        assert!(tree.try_clear_leaf(4).is_err());
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_zeroize() {
+        let leaves: Vec<Output<Sha3_256>> = (1..4).map(|i| [i as u8; 32].into()).collect();
+        let mut tree = MerkleTree::from_leaves(&leaves, &[0xff; 32].into());
+        let version = tree.snapshot();
+        tree.set(0, &[9; 32].into());
+        let mut proof = tree.create_proof(2);
+        tree.zeroize();
+        assert!((0..tree.num_nodes()).all(|index| *tree.node(index) == [0; 32].into()));
+        assert_eq!(*tree.padding(), [0; 32].into());
+        // the old values kept for the snapshot are gone as well
+        assert_eq!(tree.root_at(version), [0; 32].into());
+        proof.zeroize();
+        assert_eq!(proof.leaf_index(), 0);
+        assert!(proof
+            .siblings()
+            .iter()
+            .all(|sibling| *sibling == [0; 32].into()));
+    }
+
+    #[test]
+    fn test_verify_proof_ct() {
+        let leaves: Vec<Output<Sha3_256>> = (0..6).map(|i| [(i * 0x11) as u8; 32].into()).collect();
+        let tree =
+            super::MerkleTree::<Sha3_256, crate::Rfc6962>::from_leaves(&leaves, &[0; 32].into());
+        let root = *tree.root_hash();
+        for offset in 0..tree.num_leaves() {
+            let proof = tree.create_proof(offset);
+            let leaf = tree.get(offset).unwrap();
+            assert!(bool::from(verify_proof_ct(&root, leaf, &proof)));
+            assert!(!bool::from(verify_proof_ct(
+                &root,
+                &[0xff; 32].into(),
+                &proof
+            )));
+            assert!(!bool::from(verify_proof_ct(&[0; 32].into(), leaf, &proof)));
+        }
+        let proof = tree.create_proof(4);
+        assert!(!bool::from(verify_proof_ct(&root, &leaves[3], &proof)));
+    }
+
+    #[test]
+    fn test_try_new() {
+        assert_eq!(
+            MerkleTree::try_new(MAX_DEPTH + 1, &[0u8; 32].into()).err(),
+            Some(MerkleTreeError::DepthOutOfRange {
+                depth: MAX_DEPTH + 1,
+                max: MAX_DEPTH
+            })
+        );
+        assert!(MerkleTree::try_new(0, &[0u8; 32].into())
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            MerkleTree::try_new(3, &[0u8; 32].into())
+                .unwrap()
+                .root_hash(),
+            MerkleTree::new(3, &[0u8; 32].into()).root_hash()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Merkle tree depth must be between 1 and")]
+    fn test_new_with_invalid_depth() {
+        MerkleTree::new(MAX_DEPTH + 1, &[0u8; 32].into());
+    }
+
+    #[test]
+    fn test_try_set() {
+        let mut tree = MerkleTree::new(3, &[0u8; 32].into());
+        assert_eq!(
+            tree.try_set(4, &[1u8; 32].into()),
+            Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: 4,
+                num_leaves: 4
+            })
+        );
+        assert_eq!(tree.try_set(3, &[1u8; 32].into()), Ok(()));
+
+        // the single leaf of a tree of depth 1 is the root
+        let mut tree = MerkleTree::new(1, &[0u8; 32].into());
+        tree.try_set(0, &[1u8; 32].into()).unwrap();
+        assert_eq!(tree.root_hash(), &[1u8; 32].into());
+    }
+
+    #[test]
+    fn test_try_create_proof() {
+        let tree = MerkleTree::new(3, &[0u8; 32].into());
+        assert!(matches!(
+            tree.try_create_proof(4),
+            Err(MerkleTreeError::LeafIndexOutOfBounds { .. })
+        ));
+        assert_eq!(tree.try_create_proof(2).unwrap(), tree.create_proof(2));
+    }
+
+    #[test]
+    fn test_create_all_proofs() {
+        let leaves: Vec<_> = (0..11u8).map(|i| Sha3_256::digest([i])).collect();
+        let tree = MerkleTree::from_leaves(&leaves, &[0u8; 32].into());
+        let proofs = tree.create_all_proofs();
+        assert_eq!(proofs.len(), 16);
+        for (offset, proof) in proofs.iter().enumerate() {
+            assert_eq!(*proof, tree.create_proof(offset));
+        }
+
+        let single = MerkleTree::from_leaves(&leaves[..1], &[0u8; 32].into());
+        assert_eq!(single.create_all_proofs(), vec![single.create_proof(0)]);
+        assert!(MerkleTree::empty(&[0u8; 32].into())
+            .create_all_proofs()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_range_proof() {
+        let leaves: Vec<_> = (0..13u8).map(|i| Sha3_256::digest([i])).collect();
+        let tree = MerkleTree::from_leaves(&leaves, &[0u8; 32].into());
+        let padded: Vec<_> = tree.leaves().copied().collect();
+        for start in 0..16 {
+            for end in start + 1..=16 {
+                let proof = tree.create_range_proof(start..end);
+                assert!(proof.hashes().len() <= 2 * (tree.depth() - 1));
+                assert!(verify_range_proof(
+                    tree.root_hash(),
+                    start,
+                    &padded[start..end],
+                    &proof
+                ));
+            }
+        }
+
+        let proof = tree.create_range_proof(3..11);
+        assert_eq!(proof.hashes().len(), 4);
+        // wrong values, offsets or lengths don't verify
+        let mut tampered = padded[3..11].to_vec();
+        tampered[4] = padded[0];
+        assert!(!verify_range_proof(tree.root_hash(), 3, &tampered, &proof));
+        assert!(!verify_range_proof(
+            tree.root_hash(),
+            4,
+            &padded[4..12],
+            &proof
+        ));
+        assert!(!verify_range_proof(
+            tree.root_hash(),
+            3,
+            &padded[3..10],
+            &proof
+        ));
+        assert!(!verify_range_proof(tree.root_hash(), 3, &[], &proof));
+        assert!(!verify_range_proof(
+            tree.root_hash(),
+            15,
+            &padded[3..11],
+            &proof
+        ));
+
+        assert_eq!(
+            tree.try_create_range_proof(4..4),
+            Err(MerkleTreeError::EmptyRange)
+        );
+        assert!(matches!(
+            tree.try_create_range_proof(10..17),
+            Err(MerkleTreeError::LeafIndexOutOfBounds { offset: 16, .. })
+        ));
+    }
+
+    #[test]
+    fn test_non_inclusion_proof() {
+        let empty = [0u8; 32].into();
+        let nullifier = Sha3_256::digest("nullifier");
+        let mut tree = MerkleTree::new(3, &empty);
+        tree.set(1, &nullifier);
+
+        let proof = tree.create_non_inclusion_proof(2);
+        assert_eq!(proof.leaf_index(), 2);
+        assert_eq!(proof.path(), &tree.create_proof(2));
+        assert!(proof.verify(tree.root_hash(), &empty));
+        assert!(verify_non_inclusion(tree.root_hash(), &empty, &proof));
+        // the verifier's default leaf is checked, not any leaf
+        assert!(!proof.verify(tree.root_hash(), &nullifier));
+
+        assert_eq!(
+            tree.try_create_non_inclusion_proof(1),
+            Err(MerkleTreeError::LeafNotEmpty { offset: 1 })
+        );
+        assert!(matches!(
+            tree.try_create_non_inclusion_proof(4),
+            Err(MerkleTreeError::LeafIndexOutOfBounds { .. })
+        ));
+
+        // the proof is invalidated once the leaf is used
+        tree.set(2, &nullifier);
+        assert!(!proof.verify(tree.root_hash(), &empty));
+    }
+
+    #[test]
+    fn test_try_verify_proof() {
+        let tree = MerkleTree::new(3, &[0u8; 32].into());
+        let other = MerkleTree::new(4, &[0u8; 32].into());
+        let proof = other.create_proof(5);
+        assert_eq!(
+            tree.try_verify_proof(&[0u8; 32].into(), &proof),
+            Err(MerkleTreeError::InvalidProofLength {
+                expected: 2,
+                actual: 3
+            })
+        );
+        let proof = tree.create_proof(1);
+        assert_eq!(
+            tree.try_verify_proof(&[0u8; 32].into(), &proof).as_ref(),
+            Ok(tree.root_hash())
+        );
+    }
+
+    #[test]
+    fn test_from_leaves() {
+        let leaves: Vec<Output<Sha3_256>> =
+            (0..16).map(|i| [(i * 0x11) as u8; 32].into()).collect();
+        let tree = MerkleTree::from_leaves(&leaves, &[0x00; 32].into());
+        let expected =
+            hex::decode("57054e43fa56333fd51343b09460d48b9204999c376624f52480c5593b91eff4")
+                .unwrap();
+        assert_eq!(tree.depth, 5);
+        assert_eq!(tree.root_hash().as_slice(), expected.as_slice());
+
+        // leaves are padded to the next power of two
+        let tree = MerkleTree::from_leaves(&leaves[..5], &[0xab; 32].into());
+        let mut reference = MerkleTree::new(4, &[0xab; 32].into());
+        for (i, leaf) in leaves[..5].iter().enumerate() {
+            reference.set(i, leaf);
+        }
+        assert_eq!(tree.num_leaves(), 8);
+        assert_eq!(tree.nodes, reference.nodes);
+
+        // no leaves at all result in an empty tree
+        let tree = MerkleTree::from_leaves(&[], &[0xab; 32].into());
+        assert!(tree.is_empty());
+        assert_eq!(tree.root_hash(), &Sha3_256::digest([]));
+    }
+
+    #[test]
+    fn test_empty_tree() {
+        let padding: Output<Sha3_256> = [0xab; 32].into();
+        let mut tree = MerkleTree::empty(&padding);
+        assert_eq!(tree.num_leaves(), 0);
+        assert_eq!(tree.next_offset(), 0);
+        assert_eq!(tree.root_hash(), &Sha3_256::digest([]));
+        assert!(tree.check_integrity().is_ok());
+        assert_eq!(
+            tree.try_set(0, &padding),
+            Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: 0,
+                num_leaves: 0
+            })
+        );
+        assert!(tree.try_create_proof(0).is_err());
+        assert!(tree
+            .try_verify_proof(&padding, &Proof::new(0, vec![]))
+            .is_err());
+        assert!(tree.create_multi_proof(&[]).hashes().is_empty());
+        assert_eq!(tree.leaves().count(), 0);
+        assert_eq!(tree.get(0), None);
+        assert!(tree.try_iter_layer(0).is_err());
+
+        // the first pushed leaf becomes the root
+        let leaf: Output<Sha3_256> = [0x11; 32].into();
+        assert_eq!(tree.push(&leaf), 0);
+        assert!(!tree.is_empty());
+        assert_eq!(tree.root_hash(), &leaf);
+        assert_eq!(tree.nodes, MerkleTree::from_leaves(&[leaf], &padding).nodes);
+    }
+
+    #[test]
+    fn test_empty_root_matches_rfc6962() {
+        // SHA-256 of the empty string, the root of an empty RFC 6962 log
+        let tree = super::MerkleTree::<sha2::Sha256>::empty(&Default::default());
+        assert_eq!(
+            hex::encode(tree.root_hash()),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_from_leaves() {
+        let leaves: Vec<Output<Sha3_256>> = (0..3000u32)
+            .map(|i| Sha3_256::digest(i.to_le_bytes()))
+            .collect();
+        let tree = MerkleTree::par_from_leaves(&leaves, &[0x00; 32].into());
+        let reference = MerkleTree::from_leaves(&leaves, &[0x00; 32].into());
+        assert_eq!(tree.nodes, reference.nodes);
+    }
+
+    #[test]
+    fn test_new_cancellable() {
+        let mut reports = Vec::new();
+        let tree = MerkleTree::new_cancellable(
+            14,
+            &[0x00; 32].into(),
+            &CancellationToken::new(),
+            &mut |p| reports.push(p),
+        )
+        .unwrap();
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::new(14, &[0x00; 32].into()).root_hash()
+        );
+        assert!(reports.windows(2).all(|w| w[0].completed < w[1].completed));
+        assert_eq!(
+            reports.last(),
+            Some(&Progress {
+                completed: tree.nodes.len(),
+                total: tree.nodes.len()
+            })
+        );
+
+        // a cancelled token aborts the construction
+        let token = CancellationToken::new();
+        token.cancel();
+        assert_eq!(
+            MerkleTree::new_cancellable(14, &[0x00; 32].into(), &token, &mut |_| {}).err(),
+            Some(MerkleTreeError::Cancelled)
+        );
+    }
+
+    #[test]
+    fn test_from_leaves_cancellable() {
+        let leaves: Vec<Output<Sha3_256>> = (0..10000u32)
+            .map(|i| Sha3_256::digest(i.to_le_bytes()))
+            .collect();
+        let token = CancellationToken::new();
+        let mut last = None;
+        let tree =
+            MerkleTree::from_leaves_cancellable(&leaves, &[0x00; 32].into(), &token, &mut |p| {
+                last = Some(p)
+            })
+            .unwrap();
+        assert_eq!(
+            tree.nodes,
+            MerkleTree::from_leaves(&leaves, &[0x00; 32].into()).nodes
+        );
+        assert_eq!(
+            last,
+            Some(Progress {
+                completed: 16383,
+                total: 16383
+            })
+        );
+
+        // cancel from within the progress callback after the first report
+        let cancel = token.clone();
+        let result =
+            MerkleTree::from_leaves_cancellable(&leaves, &[0x00; 32].into(), &token, &mut |_| {
+                cancel.cancel()
+            });
+        assert_eq!(result.err(), Some(MerkleTreeError::Cancelled));
+    }
+
+    #[test]
+    fn test_check_integrity() {
+        let mut tree = MerkleTree::new(5, &[0x00; 32].into());
+        tree.set(3, &[0x33; 32].into());
+        assert_eq!(tree.check_integrity(), Ok(()));
+
+        tree.nodes[4] = [0x44; 32].into();
+        assert_eq!(
+            tree.check_integrity(),
+            Err(MerkleTreeError::InconsistentNode { index: 1 })
+        );
+
+        let token = CancellationToken::new();
+        token.cancel();
+        assert_eq!(
+            tree.check_integrity_cancellable(&token, &mut |_| {}),
+            Err(MerkleTreeError::Cancelled)
+        );
+    }
+
+    #[test]
+    fn test_from_leaf_data() {
+        let data = ["alice", "bob", "carol"];
+        let tree = MerkleTree::from_leaf_data(&data, &[0x00; 32].into());
+        let leaves: Vec<_> = data.iter().map(Sha3_256::digest).collect();
+        assert_eq!(
+            tree.nodes,
+            MerkleTree::from_leaves(&leaves, &[0x00; 32].into()).nodes
+        );
+        assert_eq!(tree.num_leaves(), 4);
+        assert_eq!(tree.get(1), Some(&Sha3_256::digest("bob")));
+
+        // anything that can be viewed as bytes works
+        let data = vec![vec![1u8, 2, 3], vec![4, 5]];
+        let tree = MerkleTree::from_leaf_data(&data, &[0x00; 32].into());
+        assert_eq!(tree.get(1), Some(&Sha3_256::digest([4u8, 5])));
+    }
+
+    #[test]
+    fn test_domain_separation() {
+        type SeparatedTree = super::MerkleTree<Sha3_256, crate::Rfc6962>;
+
+        let data = ["alice", "bob", "carol", "dave"];
+        let padding = [0x00; 32].into();
+
+        // without domain separation the concatenated children of a node pass as a 64 byte leaf
+        let tree = MerkleTree::from_leaf_data(&data, &padding);
+        let forged: Vec<u8> = [*tree.get(0).unwrap(), *tree.get(1).unwrap()].concat();
+        let sibling = *tree.iter_layer(1).nth(1).unwrap();
+        let proof = Proof::new(0, vec![sibling]);
+        assert!(verify(
+            tree.root_hash(),
+            &MerkleTree::hash_leaf(&forged),
+            &proof
+        ));
+
+        let tree = SeparatedTree::from_leaf_data(&data, &padding);
+        let forged: Vec<u8> = [*tree.get(0).unwrap(), *tree.get(1).unwrap()].concat();
+        let sibling = *tree.iter_layer(1).nth(1).unwrap();
+        let proof = super::Proof::<Sha3_256, crate::Rfc6962>::new(0, vec![sibling]);
+        assert!(!verify(
+            tree.root_hash(),
+            &SeparatedTree::hash_leaf(&forged),
+            &proof
+        ));
+
+        // proofs, updates and the root follow the scheme of the tree
+        let mut tree = tree;
+        for (offset, item) in data.iter().enumerate() {
+            let proof = tree.create_proof(offset);
+            let leaf = SeparatedTree::hash_leaf(item);
+            assert!(verify(tree.root_hash(), &leaf, &proof));
+            assert_eq!(tree.try_verify_proof(&leaf, &proof), Ok(*tree.root_hash()));
+        }
+        tree.set(2, &SeparatedTree::hash_leaf("eve"));
+        assert_eq!(tree.check_integrity(), Ok(()));
+        let proof = tree.create_proof(2);
+        assert!(verify(
+            tree.root_hash(),
+            &SeparatedTree::hash_leaf("eve"),
+            &proof
+        ));
+
+        // trees with a power of two leaves have the root of an RFC 6962 log
+        let mut log = crate::LogTree::<Sha3_256>::new();
+        for item in ["alice", "bob", "eve", "dave"] {
+            log.push_leaf_data(item.as_bytes());
+        }
+        assert_eq!(*tree.root_hash(), log.root_hash());
+        assert!(SeparatedTree::empty(&padding).is_empty());
+        assert_eq!(
+            SeparatedTree::empty(&padding).root_hash(),
+            &log.root_at(0).unwrap()
+        );
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_from_leaf_data() {
+        let data: Vec<_> = (0..1000u32).map(|i| i.to_le_bytes()).collect();
+        assert_eq!(
+            MerkleTree::par_from_leaf_data(&data, &[0x00; 32].into()).nodes,
+            MerkleTree::from_leaf_data(&data, &[0x00; 32].into()).nodes
+        );
+    }
+
+    #[test]
+    fn test_push() {
+        let padding: Output<Sha3_256> = [0xab; 32].into();
+        let leaves: Vec<Output<Sha3_256>> = (0..9).map(|i| [i as u8; 32].into()).collect();
+
+        // pushing into an empty tree fills the padding first, then grows it
+        let mut tree = MerkleTree::from_leaves(&[], &padding);
+        for (i, leaf) in leaves.iter().enumerate() {
+            assert_eq!(tree.push(leaf), i);
+            let reference = MerkleTree::from_leaves(&leaves[..=i], &padding);
+            assert_eq!(tree.depth, reference.depth);
+            assert_eq!(tree.nodes, reference.nodes);
+            assert_eq!(tree.next_offset(), i + 1);
+        }
+        assert_eq!(tree.num_leaves(), 16);
+
+        // proofs keep working on the grown tree
+        for (i, leaf) in leaves.iter().enumerate() {
+            let proof = tree.create_proof(i);
+            assert_eq!(tree.verify_proof(leaf, &proof), *tree.root_hash());
+        }
+
+        // all leaves of a tree created with `new` are in use, the old root becomes the left child
+        let mut tree = MerkleTree::new(2, &padding);
+        let old_root = *tree.root_hash();
+        assert_eq!(tree.push(&leaves[0]), 2);
+        assert_eq!(tree.num_leaves(), 4);
+        assert_eq!(tree.iter_layer(1).next(), Some(&old_root));
+        assert_eq!(
+            tree.nodes,
+            MerkleTree::from_leaves(&[padding, padding, leaves[0]], &padding).nodes
+        );
+    }
+
+    #[test]
+    fn test_proof_bytes() {
+        let leaves: Vec<Output<Sha3_256>> = (0..1000).map(|i| [i as u8; 32].into()).collect();
+        let tree = MerkleTree::from_leaves(&leaves, &[0x00; 32].into());
+        for offset in [0, 1, 2, 511, 700, 1023] {
+            let proof = tree.create_proof(offset);
+            let bytes = proof.to_bytes();
+            // 10 siblings need 2 bitmap bytes
+            assert_eq!(bytes.len(), 1 + 2 + 10 * 32);
+            assert_eq!(bytes[0], 10);
+            assert_eq!(Proof::from_bytes(&bytes), Ok(proof));
+        }
+
+        let bytes = tree.create_proof(700).to_bytes();
+        assert_eq!(
+            Proof::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(MerkleTreeError::InvalidProofEncoding {
+                expected: bytes.len(),
+                actual: bytes.len() - 1
+            })
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            Proof::from_bytes(&trailing),
+            Err(MerkleTreeError::InvalidProofEncoding {
+                expected: bytes.len(),
+                actual: bytes.len() + 1
+            })
+        );
+        let mut non_canonical = bytes.clone();
+        non_canonical[2] |= 0x80;
+        assert_eq!(
+            Proof::from_bytes(&non_canonical),
+            Err(MerkleTreeError::NonCanonicalProofEncoding)
+        );
+        assert!(Proof::from_bytes(&[]).is_err());
+
+        // the proof of a single leaf tree is just the header
+        let proof = MerkleTree::new(1, &[0x00; 32].into()).create_proof(0);
+        assert_eq!(proof.to_bytes(), [0]);
+        assert_eq!(Proof::from_bytes(&[0]), Ok(proof));
+    }
+
+    #[test]
+    fn test_proof_delta() {
+        let leaves: Vec<Output<Sha3_256>> = (0..64).map(|i| [i as u8; 32].into()).collect();
+        let tree = MerkleTree::from_leaves(&leaves, &[0x00; 32].into());
+
+        // adjacent leaves sharing a parent only differ in the leaf layer
+        let delta = tree.proof_delta(4, 5);
+        assert_eq!(delta.siblings(), [leaves[4]]);
+        assert_eq!(
+            delta.apply(&tree.create_proof(4)),
+            Some(tree.create_proof(5))
+        );
+
+        // crossing a subtree boundary changes the siblings below the joining layer
+        let delta = tree.proof_delta(31, 32);
+        assert_eq!(delta.siblings().len(), 6);
+
+        for (from, to) in [(0, 0), (0, 63), (10, 11), (11, 12), (40, 17)] {
+            let delta = tree.proof_delta(from, to);
+            assert_eq!(delta.from(), from);
+            assert_eq!(delta.to(), to);
+            let proof = delta.apply(&tree.create_proof(from)).unwrap();
+            assert_eq!(proof, tree.create_proof(to));
+        }
+        assert!(tree.proof_delta(3, 3).siblings().is_empty());
+
+        assert_eq!(tree.proof_delta(1, 2).apply(&tree.create_proof(0)), None);
+        assert!(tree.try_proof_delta(0, 64).is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let leaves: Vec<Output<Sha3_256>> = (0..64).map(|i| [i as u8; 32].into()).collect();
+        let tree = MerkleTree::from_leaves(&leaves, &[0x00; 32].into());
+        let mut other = MerkleTree::from_leaves(&leaves, &[0x00; 32].into());
+        assert!(tree.diff(&other).is_empty());
+
+        for offset in [63, 0, 17, 18] {
+            other.set(offset, &[0xff; 32].into());
+        }
+        assert_eq!(tree.diff(&other), [0, 17, 18, 63]);
+        assert_eq!(other.diff(&tree), [0, 17, 18, 63]);
+
+        // restoring a leaf removes it from the diff
+        other.set(17, &leaves[17]);
+        assert_eq!(tree.diff(&other), [0, 18, 63]);
+
+        assert_eq!(
+            tree.try_diff(&MerkleTree::from_leaves(&leaves[..8], &[0x00; 32].into())),
+            Err(MerkleTreeError::DepthMismatch {
+                expected: 7,
+                actual: 4
+            })
+        );
+        let empty = MerkleTree::empty(&[0x00; 32].into());
+        assert!(empty
+            .diff(&MerkleTree::empty(&[0x11; 32].into()))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_proof_update() {
+        let leaves: Vec<Output<Sha3_256>> = (0..64).map(|i| [i as u8; 32].into()).collect();
+        let mut tree = MerkleTree::from_leaves(&leaves, &[0x00; 32].into());
+        let mut proofs = tree.create_all_proofs();
+
+        tree.set(17, &[0xff; 32].into());
+        let update = tree.proof_update(17);
+        assert_eq!(update.offset(), 17);
+        assert_eq!(update.path().len(), 6);
+        assert_eq!(update.path()[0], [0xff; 32].into());
+        for proof in &mut proofs {
+            assert!(proof.apply_update(&update));
+        }
+        assert_eq!(proofs, tree.create_all_proofs());
+
+        // updates of a batch of changes can be applied in any order
+        for offset in [0, 63, 18] {
+            tree.set(offset, &[0xee; 32].into());
+        }
+        for offset in [18, 0, 63] {
+            let update = tree.proof_update(offset);
+            for proof in &mut proofs {
+                proof.apply_update(&update);
+            }
+        }
+        for (offset, proof) in proofs.iter().enumerate() {
+            assert!(verify(tree.root_hash(), tree.get(offset).unwrap(), proof));
+        }
+
+        // updates of trees with another depth are rejected
+        let small = MerkleTree::from_leaves(&leaves[..8], &[0x00; 32].into());
+        let mut proof = tree.create_proof(3);
+        assert!(!proof.apply_update(&small.proof_update(3)));
+        assert_eq!(proof, tree.create_proof(3));
+        assert!(tree.try_proof_update(64).is_err());
+    }
+
+    #[test]
+    fn test_subtrees() {
+        let leaves: Vec<Output<Sha3_256>> = (0..13).map(|i| [i as u8; 32].into()).collect();
+        let tree = MerkleTree::from_leaves(&leaves, &[0x00; 32].into());
+
+        // the third quarter of the tree holds the leaves 8..12
+        let subtree = tree.extract_subtree(2, 2);
+        assert_eq!(subtree.depth(), 3);
+        assert_eq!(subtree.root_hash(), tree.subtree_root(2, 2));
+        assert_eq!(
+            subtree.root_hash(),
+            MerkleTree::from_leaves(&leaves[8..12], &[0x00; 32].into()).root_hash()
+        );
+        assert_eq!(subtree.check_integrity(), Ok(()));
+        assert_eq!(subtree.next_offset(), 4);
+        assert_eq!(tree.extract_subtree(2, 3).next_offset(), 1);
+        assert_eq!(tree.extract_subtree(0, 0).root_hash(), tree.root_hash());
+        assert_eq!(tree.extract_subtree(4, 5).leaves().as_slice(), [leaves[5]]);
+
+        for (layer, offset) in [(0, 0), (1, 1), (2, 2), (3, 5), (4, 12)] {
+            let proof = tree.create_subtree_proof(layer, offset);
+            assert_eq!(proof.len(), layer);
+            assert!(verify(
+                tree.root_hash(),
+                tree.subtree_root(layer, offset),
+                &proof
+            ));
+        }
+        assert_eq!(tree.create_subtree_proof(4, 9), tree.create_proof(9));
+
+        assert_eq!(
+            tree.try_subtree_root(2, 4),
+            Err(MerkleTreeError::NodeOutOfRange {
+                layer: 2,
+                offset: 4
+            })
+        );
+        assert!(tree.try_extract_subtree(5, 0).is_err());
+        assert!(tree.try_create_subtree_proof(1, 2).is_err());
+    }
 }
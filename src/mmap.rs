@@ -0,0 +1,661 @@
+//! Memory mapped file persistence for `MerkleTree`
+//!
+//! The file starts with a header followed by the nodes of the tree in breadth-first order:
+//!
+//! | bytes | content |
+//! |-------|---------|
+//! | 4 | magic `MTMM` |
+//! | 1 | format version |
+//! | 1 | depth of the tree |
+//! | 1 | hash size in bytes |
+//! | 1 | reserved, zero |
+//! | 8 | offset of the next pushed leaf, little endian |
+//! | hash size | hash algorithm id, the parameter fingerprint of the digest |
+//! | hash size | padding value |
+//!
+//! Only the pages touched by reads and writes are loaded, so trees larger than the available memory
+//! can be opened. Writes go straight to the mapping, call `flush` to make sure they hit the disk.
+//!
+//! A crash while `set` rehashes the path of a leaf leaves nodes behind that don't match their
+//! children. `set_many` logs its leaves in a write-ahead log first, see `crate::wal`, `open` replays
+//! the log and `checkpoint` empties it once the nodes are on the disk. `set` and `push` checkpoint
+//! a tree with logged batches before they write the leaf, a replay would restore the logged value.
+//!
+//! Proofs of consecutive leaves read every layer front to back. After `hint_sequential` every proof
+//! asks the kernel to read the siblings of the following `READ_AHEAD_LEAVES` leaves ahead of time,
+//! so the disk has requests queued while the proofs are assembled.
+
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use digest::{Digest, Output};
+use memmap2::MmapMut;
+
+use crate::custody::parameter_fingerprint;
+use crate::nodes::NodeBuf;
+use crate::wal::Wal;
+use crate::{HashScheme, MerkleTree, MerkleTreeError, MAX_DEPTH};
+
+/// magic bytes at the start of every tree file
+const MAGIC: &[u8; 4] = b"MTMM";
+/// version of the file format
+const VERSION: u8 = 1;
+/// length of the fixed part of the header
+const FIXED_HEADER_LEN: usize = 16;
+/// position of the depth in the header
+const DEPTH_POS: usize = 5;
+/// position of the next offset in the header
+const NEXT_OFFSET_POS: usize = 8;
+/// number of leaves whose siblings are read ahead after `hint_sequential`
+pub const READ_AHEAD_LEAVES: usize = 1 << 14;
+/// length of the write-ahead log in bytes after which `set_many` checkpoints on its own
+const CHECKPOINT_LEN: u64 = 1 << 24;
+
+/// Nodes of a tree stored in a memory mapped file
+pub(crate) struct MappedNodes {
+    /// the mapped file, kept to resize it
+    file: File,
+    /// the mapping of the whole file
+    map: MmapMut,
+    /// length of the header, the nodes start right behind it
+    header_len: usize,
+    /// number of nodes in the file
+    len: usize,
+    /// whether proofs are expected for increasing leaf offsets
+    sequential: AtomicBool,
+    /// the siblings of the leaves in this range were read ahead
+    read_ahead: (AtomicUsize, AtomicUsize),
+    /// the log of the batches written by `set_many` since the last checkpoint
+    wal: Wal,
+}
+
+impl MappedNodes {
+    /// returns the nodes as hashes of the given digest
+    pub(crate) fn as_slice<D: Digest>(&self) -> &[Output<D>] {
+        let bytes = &self.map[self.header_len..];
+        debug_assert_eq!(bytes.len(), self.len * <D as Digest>::output_size());
+        // SAFETY: `Output<D>` is a byte array without padding and with an alignment of 1, the mapping
+        // holds exactly `len` of them behind the header and lives as long as the returned borrow
+        unsafe { std::slice::from_raw_parts(bytes.as_ptr().cast(), self.len) }
+    }
+
+    /// returns the nodes as mutable hashes of the given digest
+    pub(crate) fn as_mut_slice<D: Digest>(&mut self) -> &mut [Output<D>] {
+        let bytes = &mut self.map[self.header_len..];
+        debug_assert_eq!(bytes.len(), self.len * <D as Digest>::output_size());
+        // SAFETY: see `as_slice`, the mapping is borrowed mutably for the lifetime of the slice
+        unsafe { std::slice::from_raw_parts_mut(bytes.as_mut_ptr().cast(), self.len) }
+    }
+
+    /// resizes the file to hold `len` nodes, new nodes are set to `value`
+    /// `len` has to be the number of nodes of a complete tree, the depth in the header is updated
+    pub(crate) fn resize<D: Digest>(&mut self, len: usize, value: &Output<D>) -> io::Result<()> {
+        let old_len = self.len;
+        self.map.flush()?;
+        self.file
+            .set_len((self.header_len + len * <D as Digest>::output_size()) as u64)?;
+        // SAFETY: the file is owned by this struct and not truncated while it is mapped
+        self.map = unsafe { MmapMut::map_mut(&self.file)? };
+        self.len = len;
+        self.map[DEPTH_POS] = (len + 1).trailing_zeros() as u8;
+        if len > old_len {
+            self.as_mut_slice::<D>()[old_len..].fill(value.clone());
+        }
+        Ok(())
+    }
+
+    /// writes the offset of the next pushed leaf to the header
+    pub(crate) fn store_next_offset(&mut self, next_offset: usize) {
+        self.map[NEXT_OFFSET_POS..FIXED_HEADER_LEN]
+            .copy_from_slice(&(next_offset as u64).to_le_bytes());
+    }
+
+    /// writes all changes to the disk
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        self.map.flush()
+    }
+
+    /// writes all changes to the disk and empties the write-ahead log, if it has records
+    pub(crate) fn checkpoint_wal(&mut self) -> io::Result<()> {
+        if self.wal.len() > 0 {
+            self.flush()?;
+            self.wal.clear()?;
+        }
+        Ok(())
+    }
+
+    /// reads the siblings of the leaves behind the given offset ahead if proofs are sequential and
+    /// the offset leaves the first half of the range read ahead last
+    pub(crate) fn read_ahead<D: Digest>(&self, depth: usize, offset: usize) {
+        if !self.sequential.load(Ordering::Relaxed) {
+            return;
+        }
+        let start = self.read_ahead.0.load(Ordering::Relaxed);
+        let end = self.read_ahead.1.load(Ordering::Relaxed);
+        if (start..end.saturating_sub(READ_AHEAD_LEAVES / 2)).contains(&offset) {
+            return;
+        }
+        let num_leaves = 1 << (depth - 1);
+        let end = offset.saturating_add(READ_AHEAD_LEAVES).min(num_leaves);
+        self.read_ahead.0.store(offset, Ordering::Relaxed);
+        self.read_ahead.1.store(end, Ordering::Relaxed);
+        let hash_size = <D as Digest>::output_size();
+        for layer in 1..depth {
+            let shift = depth - 1 - layer;
+            // the siblings of a range of nodes are the range itself, widened to whole pairs
+            let first = (offset >> shift) & !1;
+            let last = ((end - 1) >> shift) | 1;
+            let index = (1 << layer) - 1 + first;
+            let _ = self.advise_will_need(
+                self.header_len + index * hash_size,
+                (last - first + 1) * hash_size,
+            );
+        }
+    }
+
+    /// tells the kernel that the given bytes of the mapping will be read soon, ignored where
+    /// advice isn't supported
+    fn advise_will_need(&self, offset: usize, len: usize) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            let len = len.min(self.map.len().saturating_sub(offset));
+            self.map
+                .advise_range(memmap2::Advice::WillNeed, offset, len)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (offset, len);
+            Ok(())
+        }
+    }
+}
+
+/// shorthand for the errors returned when opening a malformed file
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// shorthand for the errors returned for invalid arguments
+fn invalid_input(err: MerkleTreeError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+}
+
+impl<D, S> MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a new Merkle tree like `new` backed by a memory mapped file at the given path
+    /// an existing file is overwritten, returns an error if the depth is not between 1 and
+    /// `MAX_DEPTH` or the file can't be created
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        depth: usize,
+        initial_value: &Output<D>,
+    ) -> io::Result<Self> {
+        if !(1..=MAX_DEPTH).contains(&depth) {
+            return Err(invalid_input(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            }));
+        }
+        let hash_size = <D as Digest>::output_size();
+        let header_len = FIXED_HEADER_LEN + 2 * hash_size;
+        let wal = Wal::create(path.as_ref())?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(header_len as u64)?;
+        // SAFETY: the file was just created and is owned by the mapped nodes
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        let mut nodes = MappedNodes {
+            file,
+            map,
+            header_len,
+            len: 0,
+            sequential: AtomicBool::new(false),
+            read_ahead: (AtomicUsize::new(0), AtomicUsize::new(0)),
+            wal,
+        };
+        nodes.map[..4].copy_from_slice(MAGIC);
+        nodes.map[4] = VERSION;
+        nodes.map[6] = hash_size as u8;
+        nodes.map[FIXED_HEADER_LEN..FIXED_HEADER_LEN + hash_size]
+            .copy_from_slice(&parameter_fingerprint::<D, S>());
+        nodes.map[FIXED_HEADER_LEN + hash_size..header_len].copy_from_slice(initial_value);
+
+        // fill the layers bottom-up, all nodes of one layer share the same hash
+        nodes.resize::<D>((1 << depth) - 1, initial_value)?;
+        let slice = nodes.as_mut_slice::<D>();
+        let mut hash = *initial_value;
+        for d in (0..depth - 1).rev() {
+            hash = S::hash_node(&hash, &hash);
+            slice[(1 << d) - 1..(1 << (d + 1)) - 1].fill(hash);
+        }
+        let next_offset = 1 << (depth - 1);
+        nodes.store_next_offset(next_offset);
+        nodes.flush()?;
+        Ok(Self::from_node_buf(
+            depth,
+            NodeBuf::Mapped(nodes),
+            next_offset,
+            *initial_value,
+        ))
+    }
+
+    /// opens a Merkle tree backed by the memory mapped file at the given path
+    /// the leaves of the batches in the write-ahead log are written again and the log is emptied
+    /// returns an error if the file can't be opened, wasn't written by `create` or was written with
+    /// a different digest
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let hash_size = <D as Digest>::output_size();
+        let header_len = FIXED_HEADER_LEN + 2 * hash_size;
+        let wal = Wal::open(path.as_ref())?;
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        // SAFETY: the file is owned by the mapped nodes, it must not be modified by other processes
+        // while the tree is open
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        if map.len() < FIXED_HEADER_LEN || &map[..4] != MAGIC {
+            return Err(invalid_data("not a merkle tree file"));
+        }
+        if map[4] != VERSION {
+            return Err(invalid_data("unsupported merkle tree file version"));
+        }
+        if map[6] as usize != hash_size || map.len() < header_len {
+            return Err(invalid_data("hash size does not match the digest"));
+        }
+        if map[FIXED_HEADER_LEN..FIXED_HEADER_LEN + hash_size]
+            != parameter_fingerprint::<D, S>()[..]
+        {
+            return Err(invalid_data("hash algorithm does not match the digest"));
+        }
+        let depth = map[DEPTH_POS] as usize;
+        if !(1..=MAX_DEPTH).contains(&depth) {
+            return Err(invalid_data("depth out of range"));
+        }
+        let len = (1usize << depth) - 1;
+        // a corrupt depth must not overflow the expected length
+        let expected_len = len
+            .checked_mul(hash_size)
+            .and_then(|nodes_len| nodes_len.checked_add(header_len));
+        if expected_len != Some(map.len()) {
+            return Err(invalid_data("file length does not match the depth"));
+        }
+        let mut next_offset = [0; 8];
+        next_offset.copy_from_slice(&map[NEXT_OFFSET_POS..FIXED_HEADER_LEN]);
+        let next_offset = u64::from_le_bytes(next_offset);
+        if next_offset > 1 << (depth - 1) {
+            return Err(invalid_data("next offset out of bounds"));
+        }
+        let padding = Output::<D>::clone_from_slice(&map[FIXED_HEADER_LEN + hash_size..header_len]);
+        let nodes = MappedNodes {
+            file,
+            map,
+            header_len,
+            len,
+            sequential: AtomicBool::new(false),
+            read_ahead: (AtomicUsize::new(0), AtomicUsize::new(0)),
+            wal,
+        };
+        let mut tree =
+            Self::from_node_buf(depth, NodeBuf::Mapped(nodes), next_offset as usize, padding);
+        tree.replay_wal()?;
+        Ok(tree)
+    }
+
+    /// writes all changes of a tree opened with `create` or `open` to the disk, the write-ahead log
+    /// is kept until `checkpoint`
+    /// does nothing for trees in memory
+    pub fn flush(&self) -> io::Result<()> {
+        self.nodes_buf().flush()
+    }
+
+    /// writes all changes to the disk like `flush` and empties the write-ahead log
+    /// does nothing for trees in memory
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        self.flush()?;
+        match self.nodes_buf_mut() {
+            NodeBuf::Mapped(nodes) => nodes.wal.clear(),
+            NodeBuf::Heap(_) => Ok(()),
+        }
+    }
+
+    /// updates the values of multiple leaf nodes, every node shared by their paths is hashed once
+    /// For trees opened with `create` or `open` the leaves are appended to the write-ahead log and
+    /// synced to the disk before any node is written, so the batch survives a crash once the log
+    /// was written and is applied on the next `open`. The log is emptied by `checkpoint`, or when
+    /// it grows beyond 16 MiB. Leaves written with `set` or `push` aren't logged, they checkpoint
+    /// the tree first if the log has records.
+    /// returns an error and leaves the tree untouched if an offset is out of bounds or the log
+    /// can't be written
+    pub fn set_many(&mut self, leaves: &[(usize, Output<D>)]) -> io::Result<()> {
+        let num_leaves = self.num_leaves();
+        if let Some(&(offset, _)) = leaves.iter().find(|(offset, _)| *offset >= num_leaves) {
+            return Err(invalid_input(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves,
+            }));
+        }
+        if leaves.is_empty() {
+            return Ok(());
+        }
+        let mut checkpoint = false;
+        if let NodeBuf::Mapped(nodes) = self.nodes_buf_mut() {
+            nodes.wal.append::<D>(leaves)?;
+            checkpoint = nodes.wal.len() > CHECKPOINT_LEN;
+        }
+        self.write_leaves(leaves);
+        if checkpoint {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// writes the leaves of every batch in the write-ahead log and empties it
+    fn replay_wal(&mut self) -> io::Result<()> {
+        let NodeBuf::Mapped(nodes) = self.nodes_buf() else {
+            return Ok(());
+        };
+        let batches = nodes.wal.read::<D>()?;
+        let num_leaves = self.num_leaves();
+        for leaves in &batches {
+            if leaves.iter().any(|(offset, _)| *offset >= num_leaves) {
+                return Err(invalid_data("logged leaf offset out of bounds"));
+            }
+            self.write_leaves(leaves);
+        }
+        self.checkpoint()
+    }
+
+    /// writes the logged leaves and rehashes their paths, the offsets have to be in bounds
+    fn write_leaves(&mut self, leaves: &[(usize, Output<D>)]) {
+        let mut offsets = Vec::with_capacity(leaves.len());
+        for (offset, value) in leaves {
+            self.write_logged_leaf(*offset, value);
+            offsets.push(*offset);
+        }
+        self.rehash_paths(&mut offsets);
+    }
+
+    /// announces that proofs will be created for increasing leaf offsets, e.g. for a bulk export
+    /// reading the siblings of the upcoming proofs ahead of time, until `clear_hints` is called
+    /// does nothing for trees in memory
+    pub fn hint_sequential(&self) {
+        if let NodeBuf::Mapped(nodes) = self.nodes_buf() {
+            nodes.sequential.store(true, Ordering::Relaxed);
+            nodes.read_ahead.0.store(0, Ordering::Relaxed);
+            nodes.read_ahead.1.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// stops reading ahead after `hint_sequential`
+    pub fn clear_hints(&self) {
+        if let NodeBuf::Mapped(nodes) = self.nodes_buf() {
+            nodes.sequential.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use sha3::{Digest, Sha3_256};
+
+    type MerkleTree = super::MerkleTree<Sha3_256>;
+
+    /// returns a path in the temp dir that is removed on drop
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("merkle-tree-rs-{}-{name}.mt", std::process::id()));
+            TempPath(path)
+        }
+    }
+
+    impl TempPath {
+        fn wal(&self) -> PathBuf {
+            let mut path = self.0.clone().into_os_string();
+            path.push(".wal");
+            path.into()
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+            let _ = std::fs::remove_file(self.wal());
+        }
+    }
+
+    #[test]
+    fn test_create_matches_new() {
+        let path = TempPath::new("create");
+        let initial_value = Sha3_256::digest(b"zero");
+        let tree = MerkleTree::create(&path.0, 4, &initial_value).unwrap();
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::new(4, &initial_value).root_hash()
+        );
+        assert_eq!(tree.num_leaves(), 8);
+    }
+
+    #[test]
+    fn test_set_persists() {
+        let path = TempPath::new("set");
+        let initial_value = Sha3_256::digest(b"zero");
+        let mut expected = MerkleTree::new(4, &initial_value);
+        {
+            let mut tree = MerkleTree::create(&path.0, 4, &initial_value).unwrap();
+            for offset in [0, 3, 7] {
+                let leaf = Sha3_256::digest(offset.to_string());
+                tree.set(offset, &leaf);
+                expected.set(offset, &leaf);
+            }
+            tree.flush().unwrap();
+        }
+        let tree = MerkleTree::open(&path.0).unwrap();
+        assert_eq!(tree.root_hash(), expected.root_hash());
+        assert_eq!(tree.create_proof(3), expected.create_proof(3));
+    }
+
+    #[test]
+    fn test_push_grows_file() {
+        let path = TempPath::new("push");
+        let padding = Sha3_256::digest(b"padding");
+        let leaves: Vec<_> = (0..5).map(|i| Sha3_256::digest([i])).collect();
+        {
+            let mut tree = MerkleTree::create(&path.0, 1, &padding).unwrap();
+            tree.set(0, &leaves[0]);
+            for leaf in &leaves[1..] {
+                tree.push(leaf);
+            }
+        }
+        let mut tree = MerkleTree::open(&path.0).unwrap();
+        assert_eq!(tree.num_leaves(), 8);
+        assert_eq!(tree.next_offset(), 5);
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::from_leaves(&leaves, &padding).root_hash()
+        );
+        // the next push continues where the last session stopped
+        tree.push(&leaves[0]);
+        assert_eq!(tree.next_offset(), 6);
+    }
+
+    #[test]
+    fn test_open_rejects_invalid_files() {
+        let path = TempPath::new("invalid");
+        std::fs::write(&path.0, b"definitely not a tree").unwrap();
+        assert!(MerkleTree::open(&path.0).is_err());
+
+        let initial_value = Sha3_256::digest(b"zero");
+        drop(MerkleTree::create(&path.0, 3, &initial_value).unwrap());
+        assert!(super::MerkleTree::<sha3::Keccak256>::open(&path.0).is_err());
+
+        // truncated node data
+        let len = std::fs::metadata(&path.0).unwrap().len();
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path.0)
+            .unwrap();
+        file.set_len(len - 1).unwrap();
+        assert!(MerkleTree::open(&path.0).is_err());
+
+        // a corrupt depth whose node data wouldn't fit into memory
+        drop(MerkleTree::create(&path.0, 3, &initial_value).unwrap());
+        let mut bytes = std::fs::read(&path.0).unwrap();
+        bytes[super::DEPTH_POS] = 60;
+        std::fs::write(&path.0, bytes).unwrap();
+        let err = MerkleTree::open(&path.0).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "file length does not match the depth");
+    }
+
+    #[test]
+    fn test_create_rejects_invalid_depth() {
+        let path = TempPath::new("depth");
+        let initial_value = Sha3_256::digest(b"zero");
+        assert!(MerkleTree::create(&path.0, 0, &initial_value).is_err());
+    }
+
+    #[test]
+    fn test_set_many_replays_wal() {
+        use super::NodeBuf;
+
+        let path = TempPath::new("wal");
+        let initial_value = Sha3_256::digest(b"zero");
+        let batch: Vec<_> = [1, 6, 7, 2]
+            .into_iter()
+            .map(|offset| (offset, Sha3_256::digest([offset as u8])))
+            .collect();
+        let mut expected = MerkleTree::new(4, &initial_value);
+        expected.set_many(&batch).unwrap();
+        {
+            let mut tree = MerkleTree::create(&path.0, 4, &initial_value).unwrap();
+            tree.set_many(&batch[..2]).unwrap();
+            // a crash after logging the second batch and writing one of its leaves
+            let NodeBuf::Mapped(nodes) = tree.nodes_buf_mut() else {
+                unreachable!()
+            };
+            nodes.wal.append::<Sha3_256>(&batch[2..]).unwrap();
+            tree.write_logged_leaf(7, &batch[2].1);
+            assert_ne!(tree.root_hash(), expected.root_hash());
+        }
+        // a record cut short by the crash is ignored
+        let mut wal = std::fs::OpenOptions::new()
+            .append(true)
+            .open(path.wal())
+            .unwrap();
+        std::io::Write::write_all(&mut wal, &[3, 0, 0, 0, 0, 0, 0, 0, 1]).unwrap();
+
+        let mut tree = MerkleTree::open(&path.0).unwrap();
+        assert_eq!(tree.root_hash(), expected.root_hash());
+        tree.check_integrity().unwrap();
+        assert_eq!(std::fs::metadata(path.wal()).unwrap().len(), 0);
+
+        tree.set_many(&[(0, initial_value)]).unwrap();
+        assert!(std::fs::metadata(path.wal()).unwrap().len() > 0);
+        tree.checkpoint().unwrap();
+        assert_eq!(std::fs::metadata(path.wal()).unwrap().len(), 0);
+        assert!(tree.set_many(&[(8, initial_value)]).is_err());
+        drop(tree);
+
+        // creating a tree at the same path drops the log of the old one
+        let mut tree = MerkleTree::create(&path.0, 4, &initial_value).unwrap();
+        tree.set_many(&batch).unwrap();
+        drop(MerkleTree::create(&path.0, 4, &initial_value).unwrap());
+        assert!(!path.wal().exists());
+        assert_eq!(
+            MerkleTree::open(&path.0).unwrap().root_hash(),
+            MerkleTree::new(4, &initial_value).root_hash()
+        );
+    }
+
+    #[test]
+    fn test_set_after_set_many_survives_reopen() {
+        let path = TempPath::new("wal_set");
+        let initial_value = Sha3_256::digest(b"zero");
+        let old = Sha3_256::digest(b"old");
+        let new = Sha3_256::digest(b"new");
+        let mut expected = MerkleTree::new(4, &initial_value);
+        {
+            let mut tree = MerkleTree::create(&path.0, 4, &initial_value).unwrap();
+            tree.set_many(&[(0, old), (3, old)]).unwrap();
+            assert!(std::fs::metadata(path.wal()).unwrap().len() > 0);
+            // the set checkpoints the logged batch, so reopening doesn't restore its value
+            tree.set(3, &new);
+            assert_eq!(std::fs::metadata(path.wal()).unwrap().len(), 0);
+            tree.set_many(&[(0, old), (5, old)]).unwrap();
+            tree.clear_leaf(0);
+            tree.set_many(&[(6, old)]).unwrap();
+            assert_eq!(tree.push(&new), 8);
+            expected.set_many(&[(3, new), (5, old), (6, old)]).unwrap();
+            expected.push(&new);
+            assert_eq!(tree.root_hash(), expected.root_hash());
+        }
+        let tree = MerkleTree::open(&path.0).unwrap();
+        assert_eq!(tree.get(0), Some(&initial_value));
+        assert_eq!(tree.get(3), Some(&new));
+        assert_eq!(tree.root_hash(), expected.root_hash());
+        tree.check_integrity().unwrap();
+    }
+
+    #[test]
+    fn test_read_ahead() {
+        use super::{NodeBuf, Ordering, READ_AHEAD_LEAVES};
+
+        let path = TempPath::new("read-ahead");
+        let depth = READ_AHEAD_LEAVES.trailing_zeros() as usize + 3;
+        let mut tree = MerkleTree::create(&path.0, depth, &Sha3_256::digest(b"zero")).unwrap();
+        tree.set(5, &Sha3_256::digest(b"five"));
+        let in_memory = MerkleTree::from_leaves(
+            &tree.leaves().copied().collect::<Vec<_>>(),
+            &tree.padding().to_owned(),
+        );
+        let read_ahead = |tree: &MerkleTree| match tree.nodes_buf() {
+            NodeBuf::Mapped(nodes) => (
+                nodes.read_ahead.0.load(Ordering::Relaxed),
+                nodes.read_ahead.1.load(Ordering::Relaxed),
+            ),
+            NodeBuf::Heap(_) => unreachable!(),
+        };
+
+        // nothing is read ahead without a hint
+        tree.create_proof(0);
+        assert_eq!(read_ahead(&tree), (0, 0));
+
+        tree.hint_sequential();
+        for offset in 0..READ_AHEAD_LEAVES {
+            assert_eq!(tree.create_proof(offset), in_memory.create_proof(offset));
+        }
+        // the window moved on once the first half of it was reached
+        let half = READ_AHEAD_LEAVES / 2;
+        assert_eq!(read_ahead(&tree), (half, half + READ_AHEAD_LEAVES));
+        // the window ends with the leaves, jumping back starts a new one
+        tree.create_proof(4 * READ_AHEAD_LEAVES - 1);
+        assert_eq!(
+            read_ahead(&tree),
+            (4 * READ_AHEAD_LEAVES - 1, 4 * READ_AHEAD_LEAVES)
+        );
+        tree.create_proof(1);
+        assert_eq!(read_ahead(&tree), (1, 1 + READ_AHEAD_LEAVES));
+
+        tree.clear_hints();
+        tree.create_proof(3 * READ_AHEAD_LEAVES);
+        assert_eq!(read_ahead(&tree), (1, 1 + READ_AHEAD_LEAVES));
+
+        // trees in memory ignore the hint
+        in_memory.hint_sequential();
+        assert_eq!(in_memory.create_proof(7), tree.create_proof(7));
+    }
+}
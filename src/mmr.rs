@@ -0,0 +1,314 @@
+//! Merkle Mountain Range
+//!
+//! An MMR is a list of perfect binary trees ("mountains") of decreasing height. Appending a leaf
+//! merges equally high mountains, so appends only ever add nodes and never rewrite existing ones.
+//! Nodes are stored in post-order, the position of a node never changes once it was written.
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use digest::{Digest, Output};
+
+use crate::{MerkleTree, Proof};
+
+/// A Merkle Mountain Range
+pub struct Mmr<D: Digest> {
+    /// nodes of all mountains in post-order
+    nodes: Vec<Output<D>>,
+    /// number of appended leaves
+    num_leaves: usize,
+}
+
+/// A proof that a leaf is included in an MMR with a certain number of leaves
+#[derive(Debug, Clone)]
+pub struct MmrProof<D: Digest> {
+    /// index of the proven leaf
+    leaf_index: usize,
+    /// number of leaves of the MMR the proof was created for
+    num_leaves: usize,
+    /// sibling hashes from the leaf up to the peak of its mountain
+    siblings: Vec<Output<D>>,
+    /// peaks of all other mountains from left to right
+    peaks: Vec<Output<D>>,
+}
+
+impl<D: Digest> PartialEq for MmrProof<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf_index == other.leaf_index
+            && self.num_leaves == other.num_leaves
+            && self.siblings == other.siblings
+            && self.peaks == other.peaks
+    }
+}
+
+impl<D: Digest> Eq for MmrProof<D> {}
+
+impl<D> MmrProof<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// returns the index of the proven leaf
+    pub fn leaf_index(&self) -> usize {
+        self.leaf_index
+    }
+
+    /// returns the number of leaves of the MMR the proof was created for
+    pub fn num_leaves(&self) -> usize {
+        self.num_leaves
+    }
+
+    /// returns the sibling hashes from the leaf up to the peak of its mountain
+    pub fn siblings(&self) -> &[Output<D>] {
+        &self.siblings
+    }
+
+    /// returns the peaks of all other mountains from left to right
+    pub fn peaks(&self) -> &[Output<D>] {
+        &self.peaks
+    }
+
+    /// computes the bagged root implied by the proof for the given leaf value
+    /// returns None if the proof doesn't fit the number of leaves
+    pub fn compute_root(&self, leaf: &Output<D>) -> Option<Output<D>> {
+        if self.leaf_index >= self.num_leaves {
+            return None;
+        }
+        let mountains = mountains(self.num_leaves);
+        let (mountain, &(first_leaf, height)) = mountains
+            .iter()
+            .enumerate()
+            .find(|(_, (first_leaf, height))| self.leaf_index < first_leaf + (1 << height))?;
+        if self.siblings.len() != height || self.peaks.len() != mountains.len() - 1 {
+            return None;
+        }
+
+        let peak =
+            Proof::<D>::new(self.leaf_index - first_leaf, self.siblings.clone()).compute_root(leaf);
+        let mut peaks = self.peaks.clone();
+        peaks.insert(mountain, peak);
+        Some(bag_peaks::<D>(&peaks))
+    }
+
+    /// verifies the proof for a leaf value against the root of the MMR
+    pub fn verify(&self, root: &Output<D>, leaf: &Output<D>) -> bool {
+        self.compute_root(leaf).as_ref() == Some(root)
+    }
+}
+
+impl<D> Default for Mmr<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> Mmr<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// creates a new empty MMR
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            num_leaves: 0,
+        }
+    }
+
+    /// returns the number of appended leaves
+    pub fn num_leaves(&self) -> usize {
+        self.num_leaves
+    }
+
+    /// returns the number of nodes of all mountains
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// returns true if no leaf was appended yet
+    pub fn is_empty(&self) -> bool {
+        self.num_leaves == 0
+    }
+
+    /// appends a leaf, returns its index
+    pub fn append(&mut self, leaf: &Output<D>) -> usize {
+        let leaf_index = self.num_leaves;
+        self.nodes.push(*leaf);
+
+        // every trailing one of the leaf index merges two mountains of equal height
+        let mut hash = *leaf;
+        for height in 0..leaf_index.trailing_ones() {
+            let left = self.nodes[self.nodes.len() - (2 << height)];
+            hash = MerkleTree::<D>::hash_pair(&left, &hash);
+            self.nodes.push(hash);
+        }
+        self.num_leaves += 1;
+        leaf_index
+    }
+
+    /// returns the peaks of all mountains from left to right
+    pub fn peaks(&self) -> Vec<Output<D>> {
+        mountains(self.num_leaves)
+            .into_iter()
+            .map(|(first_leaf, height)| self.nodes[peak_position(first_leaf, height)])
+            .collect()
+    }
+
+    /// returns the root, the peaks bagged from right to left
+    /// returns None if the MMR is empty
+    pub fn get_root(&self) -> Option<Output<D>> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(bag_peaks::<D>(&self.peaks()))
+    }
+
+    /// creates a proof for a leaf
+    /// returns None if the leaf index is out of bounds
+    pub fn create_proof(&self, leaf_index: usize) -> Option<MmrProof<D>> {
+        if leaf_index >= self.num_leaves {
+            return None;
+        }
+        let mut peaks = Vec::new();
+        let mut siblings = Vec::new();
+        for (first_leaf, height) in mountains(self.num_leaves) {
+            if !(first_leaf..first_leaf + (1 << height)).contains(&leaf_index) {
+                peaks.push(self.nodes[peak_position(first_leaf, height)]);
+                continue;
+            }
+            let mut position = leaf_position(leaf_index);
+            let mut offset = leaf_index - first_leaf;
+            for h in 0..height {
+                // a left child's sibling follows its subtree, a right child's precedes it
+                let subtree_size = (2 << h) - 1;
+                if offset.is_multiple_of(2) {
+                    siblings.push(self.nodes[position + subtree_size]);
+                    position += subtree_size + 1;
+                } else {
+                    siblings.push(self.nodes[position - subtree_size]);
+                    position += 1;
+                }
+                offset /= 2;
+            }
+        }
+        Some(MmrProof {
+            leaf_index,
+            num_leaves: self.num_leaves,
+            siblings,
+            peaks,
+        })
+    }
+}
+
+/// returns the first leaf and the height of each mountain of an MMR from left to right
+fn mountains(num_leaves: usize) -> Vec<(usize, usize)> {
+    let mut first_leaf = 0;
+    (0..usize::BITS as usize)
+        .rev()
+        .filter(|height| num_leaves & (1 << height) != 0)
+        .map(|height| {
+            let mountain = (first_leaf, height);
+            first_leaf += 1 << height;
+            mountain
+        })
+        .collect()
+}
+
+/// returns the position of a leaf in post-order
+fn leaf_position(leaf_index: usize) -> usize {
+    2 * leaf_index - leaf_index.count_ones() as usize
+}
+
+/// returns the position of the peak of a mountain in post-order
+fn peak_position(first_leaf: usize, height: usize) -> usize {
+    leaf_position(first_leaf) + (2 << height) - 2
+}
+
+/// folds the peaks from right to left into a single root
+fn bag_peaks<D>(peaks: &[Output<D>]) -> Output<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    let (last, rest) = peaks.split_last().expect("at least one peak");
+    rest.iter()
+        .rev()
+        .fold(*last, |root, peak| MerkleTree::<D>::hash_pair(peak, &root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    type Mmr = super::Mmr<Sha3_256>;
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+
+    fn leaf(i: u8) -> Output<Sha3_256> {
+        [i; 32].into()
+    }
+
+    #[test]
+    fn test_positions() {
+        assert_eq!(mountains(0), vec![]);
+        assert_eq!(mountains(11), vec![(0, 3), (8, 1), (10, 0)]);
+        assert_eq!(
+            (0..5).map(leaf_position).collect::<Vec<_>>(),
+            [0, 1, 3, 4, 7]
+        );
+        assert_eq!(peak_position(0, 2), 6);
+        assert_eq!(peak_position(8, 1), 17);
+    }
+
+    #[test]
+    fn test_append_and_root() {
+        let mut mmr = Mmr::new();
+        assert!(mmr.is_empty());
+        assert_eq!(mmr.get_root(), None);
+
+        for i in 0..11 {
+            assert_eq!(mmr.append(&leaf(i)), i as usize);
+        }
+        assert_eq!(mmr.num_leaves(), 11);
+        assert_eq!(mmr.num_nodes(), 19);
+
+        // each mountain is a perfect tree over its leaves
+        let leaves: Vec<_> = (0..11).map(leaf).collect();
+        let peaks = [
+            *MerkleTree::from_leaves(&leaves[..8], &leaf(0)).root_hash(),
+            *MerkleTree::from_leaves(&leaves[8..10], &leaf(0)).root_hash(),
+            leaves[10],
+        ];
+        assert_eq!(mmr.peaks(), peaks);
+        let bagged = MerkleTree::hash_pair(&peaks[0], &MerkleTree::hash_pair(&peaks[1], &peaks[2]));
+        assert_eq!(mmr.get_root(), Some(bagged));
+    }
+
+    #[test]
+    fn test_proofs() {
+        let mut mmr = Mmr::new();
+        for n in 0..20u8 {
+            mmr.append(&leaf(n));
+            let root = mmr.get_root().unwrap();
+            for i in 0..=n {
+                let proof = mmr.create_proof(i as usize).unwrap();
+                assert!(proof.verify(&root, &leaf(i)));
+                assert!(!proof.verify(&root, &leaf(i + 1)));
+            }
+            assert_eq!(mmr.create_proof(n as usize + 1), None);
+        }
+
+        // proofs don't verify for a different number of leaves
+        let proof = mmr.create_proof(3).unwrap();
+        let mut other = Mmr::new();
+        for i in 0..19 {
+            other.append(&leaf(i));
+        }
+        assert!(!proof.verify(&other.get_root().unwrap(), &leaf(3)));
+    }
+}
@@ -0,0 +1,413 @@
+//! Merkle trees with more than two children per node
+//!
+//! `NaryMerkleTree` hashes groups of `ARITY` children into their parent, which lowers the depth of
+//! the tree and the number of hashes needed to verify a proof, e.g. for circuits proving
+//! membership in arity-4 or arity-8 trees. An interior node is the hash of the concatenation of its
+//! children from left to right, with the default arity of 2 the roots are identical to the ones of
+//! a `MerkleTree` over the same leaves.
+//!
+//! Proofs contain one `NaryLevel` per layer below the root, holding the `ARITY - 1` siblings of
+//! the node on the path and the position of the node among its siblings.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use digest::{Digest, Output};
+
+use crate::{MerkleTreeError, MAX_DEPTH};
+
+/// The siblings of a node on the path of a proof
+#[derive(Debug, Clone)]
+pub struct NaryLevel<D: Digest> {
+    /// position of the node among the children of its parent
+    pub position: usize,
+    /// the other children of the parent from left to right
+    pub siblings: Vec<Output<D>>,
+}
+
+impl<D: Digest> PartialEq for NaryLevel<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.position == other.position && self.siblings == other.siblings
+    }
+}
+
+impl<D: Digest> Eq for NaryLevel<D> {}
+
+/// An inclusion proof of a `NaryMerkleTree`
+#[derive(Debug, Clone)]
+pub struct NaryProof<D: Digest> {
+    levels: Vec<NaryLevel<D>>,
+}
+
+impl<D: Digest> PartialEq for NaryProof<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.levels == other.levels
+    }
+}
+
+impl<D: Digest> Eq for NaryProof<D> {}
+
+impl<D: Digest> NaryProof<D> {
+    /// creates a proof from its levels starting at the leaf layer
+    pub fn new(levels: Vec<NaryLevel<D>>) -> Self {
+        Self { levels }
+    }
+
+    /// returns the levels of the proof starting at the leaf layer
+    pub fn levels(&self) -> &[NaryLevel<D>] {
+        &self.levels
+    }
+
+    /// returns the arity of the tree the proof was created for, 0 for proofs of single leaf trees
+    pub fn arity(&self) -> usize {
+        self.levels
+            .first()
+            .map_or(0, |level| level.siblings.len() + 1)
+    }
+
+    /// returns the offset of the proven leaf as given by the positions of the levels
+    pub fn leaf_index(&self) -> usize {
+        let arity = self.arity();
+        self.levels
+            .iter()
+            .rev()
+            .fold(0, |index, level| index * arity + level.position)
+    }
+
+    /// returns the root hash computed from the leaf value and the proof
+    pub fn compute_root(&self, leaf: &Output<D>) -> Output<D> {
+        let mut hash = leaf.clone();
+        for level in &self.levels {
+            let mut hasher = D::new();
+            let (left, right) = level
+                .siblings
+                .split_at(level.position.min(level.siblings.len()));
+            for sibling in left {
+                hasher.update(sibling);
+            }
+            hasher.update(&hash);
+            for sibling in right {
+                hasher.update(sibling);
+            }
+            hash = hasher.finalize();
+        }
+        hash
+    }
+
+    /// returns true if the leaf value and the proof hash up to the given root
+    /// all levels have to hold the same number of siblings and a position within the arity
+    pub fn verify(&self, root: &Output<D>, leaf: &Output<D>) -> bool {
+        let arity = self.arity();
+        self.levels.len() < MAX_DEPTH
+            && self
+                .levels
+                .iter()
+                .all(|level| level.siblings.len() + 1 == arity && level.position < arity)
+            && &self.compute_root(leaf) == root
+    }
+}
+
+/// A Merkle tree in which every interior node has `ARITY` children
+/// The nodes are stored layer by layer starting at the root like in `MerkleTree`, the node at
+/// index `i` has the children `ARITY * i + 1` to `ARITY * i + ARITY`.
+#[derive(Debug, Clone)]
+pub struct NaryMerkleTree<D: Digest, const ARITY: usize = 2> {
+    depth: usize,
+    nodes: Vec<Output<D>>,
+}
+
+impl<D, const ARITY: usize> NaryMerkleTree<D, ARITY>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// returns the maximum depth of a tree, the number of leaves is limited to the one of a
+    /// `MerkleTree` of depth `MAX_DEPTH`
+    pub const MAX_DEPTH: usize = {
+        assert!(ARITY >= 2, "the arity has to be at least 2");
+        let mut depth = 1;
+        let mut leaves: usize = 1;
+        while leaves.saturating_mul(ARITY) <= 1 << (MAX_DEPTH - 1) {
+            leaves *= ARITY;
+            depth += 1;
+        }
+        depth
+    };
+
+    /// creates a new tree of the given depth with all leaves set to the initial value
+    /// panics if the depth is 0 or larger than `MAX_DEPTH`, see `try_new` for a fallible version
+    pub fn new(depth: usize, initial_value: &Output<D>) -> Self {
+        match Self::try_new(depth, initial_value) {
+            Ok(tree) => tree,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a new tree of the given depth with all leaves set to the initial value
+    /// returns an error if the depth is 0 or larger than `MAX_DEPTH`
+    pub fn try_new(depth: usize, initial_value: &Output<D>) -> Result<Self, MerkleTreeError> {
+        if depth == 0 || depth > Self::MAX_DEPTH {
+            return Err(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: Self::MAX_DEPTH,
+            });
+        }
+        Ok(Self::build(depth, &[], initial_value))
+    }
+
+    /// creates a new tree from the given leaves, padded with the padding value to the next power of
+    /// the arity
+    /// panics if there are more leaves than a tree of depth `MAX_DEPTH` holds
+    pub fn from_leaves(leaves: &[Output<D>], padding: &Output<D>) -> Self {
+        let mut depth = 1;
+        while Self::nodes_in_layer(depth - 1) < leaves.len() {
+            depth += 1;
+        }
+        if depth > Self::MAX_DEPTH {
+            panic!(
+                "{}",
+                MerkleTreeError::DepthOutOfRange {
+                    depth,
+                    max: Self::MAX_DEPTH,
+                }
+            );
+        }
+        Self::build(depth, leaves, padding)
+    }
+
+    /// creates a new tree from raw leaf payloads, every payload is hashed to obtain the leaf value
+    pub fn from_leaf_data<T: AsRef<[u8]>>(data: &[T], padding: &Output<D>) -> Self {
+        let leaves: Vec<Output<D>> = data.iter().map(|item| D::digest(item)).collect();
+        Self::from_leaves(&leaves, padding)
+    }
+
+    /// returns the number of children of every interior node
+    pub fn arity(&self) -> usize {
+        ARITY
+    }
+
+    /// returns the depth of the tree
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// returns the root hash of the tree
+    pub fn root_hash(&self) -> &Output<D> {
+        &self.nodes[0]
+    }
+
+    /// returns the number of leaves in the tree
+    pub fn num_leaves(&self) -> usize {
+        Self::nodes_in_layer(self.depth - 1)
+    }
+
+    /// returns the leaf at the given offset or None if the offset is out of bounds
+    pub fn get(&self, offset: usize) -> Option<&Output<D>> {
+        if offset >= self.num_leaves() {
+            return None;
+        }
+        Some(&self.nodes[Self::index(self.depth - 1, offset)])
+    }
+
+    /// updates the value of a leaf node
+    /// panics if the offset is out of bounds, see `try_set` for a fallible version
+    pub fn set(&mut self, offset: usize, value: &Output<D>) {
+        if let Err(err) = self.try_set(offset, value) {
+            panic!("{err}");
+        }
+    }
+
+    /// updates the value of a leaf node
+    /// returns an error if the offset is out of bounds
+    pub fn try_set(&mut self, offset: usize, value: &Output<D>) -> Result<(), MerkleTreeError> {
+        self.check_offset(offset)?;
+        self.nodes[Self::index(self.depth - 1, offset)] = *value;
+        let mut offset = offset;
+        for layer in (0..self.depth - 1).rev() {
+            offset /= ARITY;
+            self.update_node(layer, offset);
+        }
+        Ok(())
+    }
+
+    /// creates a proof for a leaf node
+    /// panics if the offset is out of bounds, see `try_create_proof` for a fallible version
+    pub fn create_proof(&self, offset: usize) -> NaryProof<D> {
+        match self.try_create_proof(offset) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a proof for a leaf node
+    /// returns an error if the offset is out of bounds
+    pub fn try_create_proof(&self, offset: usize) -> Result<NaryProof<D>, MerkleTreeError> {
+        self.check_offset(offset)?;
+        let mut levels = Vec::with_capacity(self.depth - 1);
+        let mut offset = offset;
+        for layer in (1..self.depth).rev() {
+            let position = offset % ARITY;
+            let first = Self::index(layer, offset - position);
+            let siblings = (0..ARITY)
+                .filter(|&child| child != position)
+                .map(|child| self.nodes[first + child])
+                .collect();
+            levels.push(NaryLevel { position, siblings });
+            offset /= ARITY;
+        }
+        Ok(NaryProof::new(levels))
+    }
+
+    /// returns the index of a node given its layer and offset
+    pub(crate) fn index(layer: usize, offset: usize) -> usize {
+        Self::nodes_in_tree(layer) + offset
+    }
+
+    /// returns the index of the first child of a node
+    fn first_child_index(layer: usize, offset: usize) -> usize {
+        Self::index(layer + 1, offset * ARITY)
+    }
+
+    /// returns the number of nodes in the given layer
+    /// returns ARITY^layer
+    fn nodes_in_layer(layer: usize) -> usize {
+        ARITY.pow(layer as u32)
+    }
+
+    /// returns the number of nodes in a tree of the given depth
+    /// returns (ARITY^depth - 1) / (ARITY - 1)
+    fn nodes_in_tree(depth: usize) -> usize {
+        (Self::nodes_in_layer(depth) - 1) / (ARITY - 1)
+    }
+
+    fn build(depth: usize, leaves: &[Output<D>], padding: &Output<D>) -> Self {
+        let mut nodes = vec![*padding; Self::nodes_in_tree(depth)];
+        let first_leaf = Self::index(depth - 1, 0);
+        nodes[first_leaf..first_leaf + leaves.len()].copy_from_slice(leaves);
+        let mut tree = Self { depth, nodes };
+        for layer in (0..depth - 1).rev() {
+            for offset in 0..Self::nodes_in_layer(layer) {
+                tree.update_node(layer, offset);
+            }
+        }
+        tree
+    }
+
+    /// recomputes an intermediate node from its children
+    fn update_node(&mut self, layer: usize, offset: usize) {
+        let first = Self::first_child_index(layer, offset);
+        let mut hasher = D::new();
+        for child in &self.nodes[first..first + ARITY] {
+            hasher.update(child);
+        }
+        self.nodes[Self::index(layer, offset)] = hasher.finalize();
+    }
+
+    fn check_offset(&self, offset: usize) -> Result<(), MerkleTreeError> {
+        if offset >= self.num_leaves() {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.num_leaves(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    use crate::MerkleTree;
+
+    type QuadTree = NaryMerkleTree<Sha3_256, 4>;
+
+    #[test]
+    fn test_index_math() {
+        assert_eq!(QuadTree::nodes_in_tree(3), 21);
+        assert_eq!(QuadTree::index(2, 3), 8);
+        assert_eq!(QuadTree::first_child_index(0, 0), 1);
+        assert_eq!(QuadTree::first_child_index(1, 1), 9);
+        // the flat layout matches the layer math
+        for layer in 0..3 {
+            for offset in 0..QuadTree::nodes_in_layer(layer) {
+                let index = QuadTree::index(layer, offset);
+                assert_eq!(QuadTree::first_child_index(layer, offset), 4 * index + 1);
+                if layer > 0 {
+                    assert_eq!(QuadTree::index(layer - 1, offset / 4), (index - 1) / 4);
+                }
+            }
+        }
+        assert_eq!(QuadTree::MAX_DEPTH, 32);
+        assert_eq!(NaryMerkleTree::<Sha3_256>::MAX_DEPTH, MAX_DEPTH);
+        assert_eq!(NaryMerkleTree::<Sha3_256, 8>::MAX_DEPTH, 21);
+    }
+
+    #[test]
+    fn test_nary_proofs() {
+        let data: Vec<_> = (0..11u8).map(|i| [i]).collect();
+        let padding = [0x00; 32].into();
+        let mut tree = QuadTree::from_leaf_data(&data, &padding);
+        assert_eq!(tree.depth(), 3);
+        assert_eq!(tree.num_leaves(), 16);
+        assert_eq!(tree.get(11), Some(&padding));
+
+        for (offset, item) in data.iter().enumerate() {
+            let proof = tree.create_proof(offset);
+            assert_eq!(proof.levels().len(), 2);
+            assert_eq!(proof.arity(), 4);
+            assert_eq!(proof.leaf_index(), offset);
+            assert_eq!(proof.levels()[0].position, offset % 4);
+            let leaf = Sha3_256::digest(item);
+            assert!(proof.verify(tree.root_hash(), &leaf));
+            assert!(!proof.verify(tree.root_hash(), &padding));
+        }
+
+        // moving the node to another position changes the root
+        let proof = tree.create_proof(5);
+        let mut moved = proof.clone();
+        moved.levels[0].position = 2;
+        assert!(!moved.verify(tree.root_hash(), &Sha3_256::digest([5])));
+        // levels have to hold the siblings of the same arity
+        let mut truncated = proof;
+        truncated.levels[1].siblings.pop();
+        assert!(!truncated.verify(tree.root_hash(), &Sha3_256::digest([5])));
+
+        tree.set(11, &Sha3_256::digest([11]));
+        let data: Vec<_> = (0..12u8).map(|i| [i]).collect();
+        assert_eq!(
+            tree.root_hash(),
+            QuadTree::from_leaf_data(&data, &padding).root_hash()
+        );
+        assert!(tree.try_set(16, &padding).is_err());
+        assert!(tree.try_create_proof(16).is_err());
+        assert!(QuadTree::try_new(0, &padding).is_err());
+        assert!(QuadTree::try_new(33, &padding).is_err());
+
+        // a single leaf is the root itself
+        let single = QuadTree::from_leaves(&[padding], &padding);
+        assert_eq!(single.depth(), 1);
+        assert!(single.create_proof(0).verify(&padding, &padding));
+    }
+
+    #[test]
+    fn test_binary_matches_merkle_tree() {
+        let data = ["alice", "bob", "carol"];
+        let padding = [0x00; 32].into();
+        let tree = NaryMerkleTree::<Sha3_256>::from_leaf_data(&data, &padding);
+        let binary = MerkleTree::<Sha3_256>::from_leaf_data(&data, &padding);
+        assert_eq!(tree.root_hash(), binary.root_hash());
+        for offset in 0..4 {
+            let siblings: Vec<_> = tree
+                .create_proof(offset)
+                .levels()
+                .iter()
+                .map(|level| level.siblings[0])
+                .collect();
+            assert_eq!(siblings, binary.create_proof(offset).siblings());
+        }
+    }
+}
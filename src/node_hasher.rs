@@ -0,0 +1,307 @@
+//! Merkle trees over hashers that are not a `Digest`
+//!
+//! Algebraic hashes like Poseidon operate on field elements instead of byte strings, so they can't
+//! implement `Digest`. `NodeHasher` only combines two nodes of an arbitrary type into their parent
+//! and `HasherMerkleTree` is a Merkle tree generic over it. `DigestHasher` adapts a `Digest` and a
+//! `HashScheme`, giving the same roots as `MerkleTree`. With the `poseidon` feature the
+//! `poseidon::PoseidonBn254` hasher builds the trees of zk circuits.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug};
+use core::marker::PhantomData;
+
+use digest::{Digest, Output};
+
+use crate::scheme::{HashScheme, Plain};
+use crate::{MerkleTreeError, MAX_DEPTH};
+
+/// Combines two child nodes into their parent
+pub trait NodeHasher {
+    /// the type of the nodes, e.g. the output of a digest or a field element
+    type Node: Copy + Eq + Debug;
+
+    /// returns the parent of two child nodes
+    fn hash_pair(left: &Self::Node, right: &Self::Node) -> Self::Node;
+}
+
+/// A `NodeHasher` hashing nodes with a `Digest` like the given hash scheme
+pub struct DigestHasher<D, S = Plain>(PhantomData<fn() -> (D, S)>);
+
+impl<D, S> NodeHasher for DigestHasher<D, S>
+where
+    D: Digest,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    type Node = Output<D>;
+
+    fn hash_pair(left: &Output<D>, right: &Output<D>) -> Output<D> {
+        S::hash_node(left, right)
+    }
+}
+
+/// An inclusion proof of a `HasherMerkleTree`
+pub struct HasherProof<H: NodeHasher> {
+    leaf_index: usize,
+    siblings: Vec<H::Node>,
+}
+
+impl<H: NodeHasher> Debug for HasherProof<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HasherProof")
+            .field("leaf_index", &self.leaf_index)
+            .field("siblings", &self.siblings)
+            .finish()
+    }
+}
+
+impl<H: NodeHasher> Clone for HasherProof<H> {
+    fn clone(&self) -> Self {
+        Self::new(self.leaf_index, self.siblings.clone())
+    }
+}
+
+impl<H: NodeHasher> PartialEq for HasherProof<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf_index == other.leaf_index && self.siblings == other.siblings
+    }
+}
+
+impl<H: NodeHasher> Eq for HasherProof<H> {}
+
+impl<H: NodeHasher> HasherProof<H> {
+    /// creates a proof from the offset of the leaf and the siblings starting at the leaf layer
+    pub fn new(leaf_index: usize, siblings: Vec<H::Node>) -> Self {
+        Self {
+            leaf_index,
+            siblings,
+        }
+    }
+
+    /// returns the offset of the proven leaf
+    pub fn leaf_index(&self) -> usize {
+        self.leaf_index
+    }
+
+    /// returns the siblings starting at the leaf layer
+    pub fn siblings(&self) -> &[H::Node] {
+        &self.siblings
+    }
+
+    /// returns the root computed from the leaf and the proof
+    pub fn compute_root(&self, leaf: &H::Node) -> H::Node {
+        let mut node = *leaf;
+        for (layer, sibling) in self.siblings.iter().enumerate() {
+            node = match (self.leaf_index >> layer) & 1 {
+                0 => H::hash_pair(&node, sibling),
+                _ => H::hash_pair(sibling, &node),
+            };
+        }
+        node
+    }
+
+    /// returns true if the leaf and the proof hash up to the given root
+    pub fn verify(&self, root: &H::Node, leaf: &H::Node) -> bool {
+        self.siblings.len() < MAX_DEPTH
+            && self.leaf_index >> self.siblings.len() == 0
+            && &self.compute_root(leaf) == root
+    }
+}
+
+/// A binary Merkle tree over the nodes of a `NodeHasher`
+/// The tree is laid out like `MerkleTree`, leaves are nodes given by the caller.
+pub struct HasherMerkleTree<H: NodeHasher> {
+    depth: usize,
+    /// all nodes layer by layer starting at the root
+    nodes: Vec<H::Node>,
+}
+
+impl<H: NodeHasher> Debug for HasherMerkleTree<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HasherMerkleTree")
+            .field("depth", &self.depth)
+            .field("nodes", &self.nodes)
+            .finish()
+    }
+}
+
+impl<H: NodeHasher> Clone for HasherMerkleTree<H> {
+    fn clone(&self) -> Self {
+        Self {
+            depth: self.depth,
+            nodes: self.nodes.clone(),
+        }
+    }
+}
+
+impl<H: NodeHasher> HasherMerkleTree<H> {
+    /// creates a new tree of the given depth with all leaves set to the initial value
+    /// panics if the depth is 0 or larger than `MAX_DEPTH`, see `try_new` for a fallible version
+    pub fn new(depth: usize, initial_value: &H::Node) -> Self {
+        match Self::try_new(depth, initial_value) {
+            Ok(tree) => tree,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a new tree of the given depth with all leaves set to the initial value
+    /// returns an error if the depth is 0 or larger than `MAX_DEPTH`
+    pub fn try_new(depth: usize, initial_value: &H::Node) -> Result<Self, MerkleTreeError> {
+        if depth == 0 || depth > MAX_DEPTH {
+            return Err(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            });
+        }
+        Ok(Self::build(depth, &[], initial_value))
+    }
+
+    /// creates a new tree from the given leaves, padded with the padding value to the next power of
+    /// two like `MerkleTree::from_leaves`
+    pub fn from_leaves(leaves: &[H::Node], padding: &H::Node) -> Self {
+        let depth = leaves.len().max(1).next_power_of_two().trailing_zeros() as usize + 1;
+        Self::build(depth, leaves, padding)
+    }
+
+    /// returns the root of the tree
+    pub fn root_hash(&self) -> &H::Node {
+        &self.nodes[0]
+    }
+
+    /// returns the depth of the tree
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// returns the number of leaves in the tree
+    pub fn num_leaves(&self) -> usize {
+        1 << (self.depth - 1)
+    }
+
+    /// returns the leaf at the given offset or None if the offset is out of bounds
+    pub fn get(&self, offset: usize) -> Option<&H::Node> {
+        if offset >= self.num_leaves() {
+            return None;
+        }
+        Some(&self.nodes[Self::first_leaf(self.depth) + offset])
+    }
+
+    /// updates the value of a leaf node
+    /// panics if the offset is out of bounds, see `try_set` for a fallible version
+    pub fn set(&mut self, offset: usize, value: &H::Node) {
+        if let Err(err) = self.try_set(offset, value) {
+            panic!("{err}");
+        }
+    }
+
+    /// updates the value of a leaf node
+    /// returns an error if the offset is out of bounds
+    pub fn try_set(&mut self, offset: usize, value: &H::Node) -> Result<(), MerkleTreeError> {
+        self.check_offset(offset)?;
+        let mut index = Self::first_leaf(self.depth) + offset;
+        self.nodes[index] = *value;
+        while index > 0 {
+            index = (index - 1) / 2;
+            self.update_node(index);
+        }
+        Ok(())
+    }
+
+    /// creates a proof for a leaf node
+    /// panics if the offset is out of bounds, see `try_create_proof` for a fallible version
+    pub fn create_proof(&self, offset: usize) -> HasherProof<H> {
+        match self.try_create_proof(offset) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a proof for a leaf node
+    /// returns an error if the offset is out of bounds
+    pub fn try_create_proof(&self, offset: usize) -> Result<HasherProof<H>, MerkleTreeError> {
+        self.check_offset(offset)?;
+        let mut siblings = Vec::with_capacity(self.depth - 1);
+        let mut index = Self::first_leaf(self.depth) + offset;
+        while index > 0 {
+            // left children have odd indices
+            let sibling = if index % 2 == 1 { index + 1 } else { index - 1 };
+            siblings.push(self.nodes[sibling]);
+            index = (index - 1) / 2;
+        }
+        Ok(HasherProof::new(offset, siblings))
+    }
+
+    fn build(depth: usize, leaves: &[H::Node], padding: &H::Node) -> Self {
+        let mut nodes = vec![*padding; (1 << depth) - 1];
+        let first_leaf = Self::first_leaf(depth);
+        nodes[first_leaf..first_leaf + leaves.len()].copy_from_slice(leaves);
+        let mut tree = Self { depth, nodes };
+        for index in (0..first_leaf).rev() {
+            tree.update_node(index);
+        }
+        tree
+    }
+
+    /// returns the index of the first leaf of a tree of the given depth
+    fn first_leaf(depth: usize) -> usize {
+        (1 << (depth - 1)) - 1
+    }
+
+    /// recomputes an intermediate node from its children
+    fn update_node(&mut self, index: usize) {
+        self.nodes[index] = H::hash_pair(&self.nodes[2 * index + 1], &self.nodes[2 * index + 2]);
+    }
+
+    fn check_offset(&self, offset: usize) -> Result<(), MerkleTreeError> {
+        if offset >= self.num_leaves() {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.num_leaves(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    use crate::{MerkleTree, Rfc6962};
+
+    #[test]
+    fn test_digest_hasher() {
+        let data = ["alice", "bob", "carol"];
+        let padding = [0x00; 32].into();
+        let leaves: Vec<_> = data.iter().map(Sha3_256::digest).collect();
+        let mut tree = HasherMerkleTree::<DigestHasher<Sha3_256>>::from_leaves(&leaves, &padding);
+        let mut expected = MerkleTree::<Sha3_256>::from_leaves(&leaves, &padding);
+        assert_eq!(tree.root_hash(), expected.root_hash());
+        for offset in 0..4 {
+            let proof = tree.create_proof(offset);
+            assert_eq!(proof.siblings(), expected.create_proof(offset).siblings());
+            assert!(proof.verify(tree.root_hash(), tree.get(offset).unwrap()));
+            assert!(!proof.verify(tree.root_hash(), &Sha3_256::digest("mallory")));
+        }
+
+        let dave = Sha3_256::digest("dave");
+        tree.set(3, &dave);
+        expected.set(3, &dave);
+        assert_eq!(tree.root_hash(), expected.root_hash());
+        assert!(tree.try_set(4, &padding).is_err());
+        assert!(tree.try_create_proof(4).is_err());
+        assert!(HasherMerkleTree::<DigestHasher<Sha3_256>>::try_new(0, &padding).is_err());
+
+        // the scheme of the adapter is used for the nodes
+        let rfc =
+            HasherMerkleTree::<DigestHasher<Sha3_256, Rfc6962>>::from_leaves(&leaves, &padding);
+        assert_eq!(
+            rfc.root_hash(),
+            MerkleTree::<Sha3_256, Rfc6962>::from_leaves(&leaves, &padding).root_hash()
+        );
+        assert_ne!(rfc.root_hash(), tree.root_hash());
+    }
+}
@@ -0,0 +1,153 @@
+//! Storage of the nodes of a `MerkleTree`
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+use digest::{Digest, Output};
+
+use crate::MerkleTreeError;
+
+/// The nodes of a tree in breadth-first order, either on the heap or in a memory mapped file
+pub(crate) enum NodeBuf<D: Digest> {
+    /// nodes in a heap allocation
+    Heap(Vec<Output<D>>),
+    /// nodes in a memory mapped file
+    #[cfg(feature = "mmap")]
+    Mapped(crate::mmap::MappedNodes),
+}
+
+impl<D: Digest> NodeBuf<D> {
+    /// resizes the buffer to hold `len` nodes, new nodes are set to `value`
+    pub(crate) fn resize(&mut self, len: usize, value: &Output<D>) -> Result<(), MerkleTreeError> {
+        match self {
+            NodeBuf::Heap(nodes) => {
+                // move to a new allocation by hand, so the old one is scrubbed before it is freed
+                #[cfg(feature = "zeroize")]
+                if len > nodes.capacity() {
+                    let mut grown = Vec::new();
+                    grown
+                        .try_reserve_exact(len)
+                        .map_err(|_| MerkleTreeError::AllocationFailed { nodes: len })?;
+                    grown.extend_from_slice(nodes);
+                    zeroize_nodes::<D>(nodes);
+                    *nodes = grown;
+                }
+                nodes
+                    .try_reserve_exact(len.saturating_sub(nodes.len()))
+                    .map_err(|_| MerkleTreeError::AllocationFailed { nodes: len })?;
+                nodes.resize(len, value.clone());
+            }
+            #[cfg(feature = "mmap")]
+            NodeBuf::Mapped(nodes) => nodes
+                .resize::<D>(len, value)
+                .map_err(|_| MerkleTreeError::AllocationFailed { nodes: len })?,
+        }
+        Ok(())
+    }
+
+    /// records the offset of the next pushed leaf, mapped files keep it in their header
+    pub(crate) fn store_next_offset(&mut self, _next_offset: usize) {
+        #[cfg(feature = "mmap")]
+        if let NodeBuf::Mapped(nodes) = self {
+            nodes.store_next_offset(_next_offset);
+        }
+    }
+
+    /// syncs the nodes of a mapped buffer and empties its write-ahead log if it has records
+    pub(crate) fn checkpoint_wal(&mut self) -> Result<(), MerkleTreeError> {
+        #[cfg(feature = "mmap")]
+        if let NodeBuf::Mapped(nodes) = self {
+            return nodes
+                .checkpoint_wal()
+                .map_err(|_| MerkleTreeError::CheckpointFailed);
+        }
+        Ok(())
+    }
+
+    /// reads the siblings of upcoming proofs ahead if sequential proofs were hinted for a mapped buffer
+    pub(crate) fn read_ahead(&self, _depth: usize, _offset: usize) {
+        #[cfg(feature = "mmap")]
+        if let NodeBuf::Mapped(nodes) = self {
+            nodes.read_ahead::<D>(_depth, _offset);
+        }
+    }
+
+    /// writes all changes of a mapped buffer to the disk
+    #[cfg(feature = "mmap")]
+    pub(crate) fn flush(&self) -> std::io::Result<()> {
+        match self {
+            NodeBuf::Heap(_) => Ok(()),
+            NodeBuf::Mapped(nodes) => nodes.flush(),
+        }
+    }
+
+    /// overwrites the nodes of a heap buffer with zeros, mapped buffers are left alone
+    #[cfg(feature = "zeroize")]
+    pub(crate) fn zeroize_heap(&mut self) {
+        match self {
+            NodeBuf::Heap(nodes) => zeroize_nodes::<D>(nodes),
+            #[cfg(feature = "mmap")]
+            NodeBuf::Mapped(_) => {}
+        }
+    }
+
+    /// takes the heap allocation out of the buffer, mapped buffers have none
+    pub(crate) fn take_vec(&mut self) -> Vec<Output<D>> {
+        match self {
+            NodeBuf::Heap(nodes) => core::mem::take(nodes),
+            #[cfg(feature = "mmap")]
+            NodeBuf::Mapped(_) => Vec::new(),
+        }
+    }
+}
+
+/// overwrites the nodes with zeros in a way the compiler doesn't optimize away
+#[cfg(feature = "zeroize")]
+pub(crate) fn zeroize_nodes<D: Digest>(nodes: &mut [Output<D>]) {
+    use zeroize::Zeroize;
+
+    for node in nodes {
+        node.as_mut_slice().zeroize();
+    }
+}
+
+impl<D: Digest> From<Vec<Output<D>>> for NodeBuf<D> {
+    fn from(nodes: Vec<Output<D>>) -> Self {
+        NodeBuf::Heap(nodes)
+    }
+}
+
+impl<D: Digest> Deref for NodeBuf<D> {
+    type Target = [Output<D>];
+
+    fn deref(&self) -> &[Output<D>] {
+        match self {
+            NodeBuf::Heap(nodes) => nodes,
+            #[cfg(feature = "mmap")]
+            NodeBuf::Mapped(nodes) => nodes.as_slice::<D>(),
+        }
+    }
+}
+
+impl<D: Digest> DerefMut for NodeBuf<D> {
+    fn deref_mut(&mut self) -> &mut [Output<D>] {
+        match self {
+            NodeBuf::Heap(nodes) => nodes,
+            #[cfg(feature = "mmap")]
+            NodeBuf::Mapped(nodes) => nodes.as_mut_slice::<D>(),
+        }
+    }
+}
+
+impl<D: Digest> PartialEq for NodeBuf<D> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<D: Digest> fmt::Debug for NodeBuf<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,277 @@
+//! Notifications about root changes
+//!
+//! An `ObservedMerkleTree` reports every update that changes its root to the registered observers:
+//! callbacks, and with the `std` feature channels. Every notification carries the new root, the
+//! previous root and the offsets of the leaves written by the update, so publishers of roots don't
+//! have to poll the tree and diff its states. Updates that write the values the leaves already have
+//! leave the root as it is and are not reported.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+#[cfg(feature = "std")]
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError, Plain};
+
+/// A change of the root of an observed tree
+pub struct RootChange<D: Digest> {
+    /// the root after the update
+    pub root: Output<D>,
+    /// the root before the update
+    pub previous: Output<D>,
+    /// the offsets of the leaves written by the update in ascending order
+    pub leaves: Vec<usize>,
+}
+
+impl<D: Digest> Clone for RootChange<D> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            previous: self.previous.clone(),
+            leaves: self.leaves.clone(),
+        }
+    }
+}
+
+impl<D: Digest> Debug for RootChange<D> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RootChange")
+            .field("root", &self.root)
+            .field("previous", &self.previous)
+            .field("leaves", &self.leaves)
+            .finish()
+    }
+}
+
+impl<D: Digest> PartialEq for RootChange<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.root == other.root && self.previous == other.previous && self.leaves == other.leaves
+    }
+}
+
+impl<D: Digest> Eq for RootChange<D> {}
+
+/// a callback receiving root changes
+type Callback<D> = Box<dyn FnMut(&RootChange<D>) + Send>;
+
+/// a registered receiver of root changes
+enum Observer<D: Digest> {
+    Callback(Callback<D>),
+    #[cfg(feature = "std")]
+    Channel(Sender<RootChange<D>>),
+}
+
+/// A Merkle tree that notifies observers whenever its root changes
+pub struct ObservedMerkleTree<D: Digest, S = Plain> {
+    tree: MerkleTree<D, S>,
+    /// the observers by the id returned on registration
+    observers: Vec<(usize, Observer<D>)>,
+    next_id: usize,
+}
+
+impl<D, S> ObservedMerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// starts observing the updates of the tree, no observers are registered yet
+    pub fn new(tree: MerkleTree<D, S>) -> Self {
+        Self {
+            tree,
+            observers: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// returns the observed tree
+    pub fn tree(&self) -> &MerkleTree<D, S> {
+        &self.tree
+    }
+
+    /// returns the tree, dropping all observers
+    pub fn into_inner(self) -> MerkleTree<D, S> {
+        self.tree
+    }
+
+    /// registers a callback that is called after every update that changes the root
+    /// returns the id of the observer for `unsubscribe`
+    pub fn subscribe(&mut self, callback: impl FnMut(&RootChange<D>) + Send + 'static) -> usize {
+        self.register(Observer::Callback(Box::new(callback)))
+    }
+
+    /// returns a channel receiving every change of the root
+    /// the observer is dropped with the first change after the receiver was dropped
+    #[cfg(feature = "std")]
+    pub fn subscribe_channel(&mut self) -> Receiver<RootChange<D>> {
+        let (sender, receiver) = mpsc::channel();
+        self.register(Observer::Channel(sender));
+        receiver
+    }
+
+    /// removes the observer with the given id, returns false if it isn't registered
+    pub fn unsubscribe(&mut self, id: usize) -> bool {
+        let before = self.observers.len();
+        self.observers.retain(|(registered, _)| *registered != id);
+        self.observers.len() != before
+    }
+
+    /// returns the number of registered observers
+    pub fn observers(&self) -> usize {
+        self.observers.len()
+    }
+
+    /// updates the value of a leaf node and notifies the observers if the root changed
+    /// panics if the offset is out of bounds, see `try_set` for a fallible version
+    pub fn set(&mut self, offset: usize, value: &Output<D>) {
+        if let Err(err) = self.try_set(offset, value) {
+            panic!("{err}");
+        }
+    }
+
+    /// updates the value of a leaf node and notifies the observers if the root changed
+    /// returns an error if the offset is out of bounds
+    pub fn try_set(&mut self, offset: usize, value: &Output<D>) -> Result<(), MerkleTreeError> {
+        self.try_set_many(&[(offset, *value)])
+    }
+
+    /// updates the values of multiple leaf nodes and notifies the observers once if the root
+    /// changed
+    /// panics if an offset is out of bounds, see `try_set_many` for a fallible version
+    pub fn set_many(&mut self, leaves: &[(usize, Output<D>)]) {
+        if let Err(err) = self.try_set_many(leaves) {
+            panic!("{err}");
+        }
+    }
+
+    /// updates the values of multiple leaf nodes and notifies the observers once if the root
+    /// changed
+    /// returns an error and leaves the tree untouched if an offset is out of bounds
+    pub fn try_set_many(&mut self, leaves: &[(usize, Output<D>)]) -> Result<(), MerkleTreeError> {
+        let num_leaves = self.tree.num_leaves();
+        if let Some(&(offset, _)) = leaves.iter().find(|(offset, _)| *offset >= num_leaves) {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds { offset, num_leaves });
+        }
+        let previous = *self.tree.root_hash();
+        for (offset, value) in leaves {
+            self.tree.set(*offset, value);
+        }
+        let mut offsets: Vec<usize> = leaves.iter().map(|(offset, _)| *offset).collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        self.notify(previous, offsets);
+        Ok(())
+    }
+
+    /// appends a leaf after the last pushed leaf and notifies the observers, returns its offset
+    /// panics if the tree can't grow any further, see `try_push` for a fallible version
+    pub fn push(&mut self, leaf: &Output<D>) -> usize {
+        match self.try_push(leaf) {
+            Ok(offset) => offset,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// appends a leaf after the last pushed leaf and notifies the observers, returns its offset
+    /// returns an error if the tree would exceed `MAX_DEPTH` or its nodes can't be allocated
+    pub fn try_push(&mut self, leaf: &Output<D>) -> Result<usize, MerkleTreeError> {
+        let previous = *self.tree.root_hash();
+        let offset = self.tree.try_push(leaf)?;
+        self.notify(previous, alloc::vec![offset]);
+        Ok(offset)
+    }
+
+    fn register(&mut self, observer: Observer<D>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.observers.push((id, observer));
+        id
+    }
+
+    /// reports the change to all observers unless the root stayed the same
+    fn notify(&mut self, previous: Output<D>, leaves: Vec<usize>) {
+        let root = *self.tree.root_hash();
+        if root == previous {
+            return;
+        }
+        let change = RootChange {
+            root,
+            previous,
+            leaves,
+        };
+        self.observers.retain_mut(|(_, observer)| match observer {
+            Observer::Callback(callback) => {
+                callback(&change);
+                true
+            }
+            #[cfg(feature = "std")]
+            Observer::Channel(sender) => sender.send(change.clone()).is_ok(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use sha3::Sha3_256;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type ObservedMerkleTree = super::ObservedMerkleTree<Sha3_256>;
+
+    #[test]
+    fn test_root_changes() {
+        let leaves: Vec<Output<Sha3_256>> = (0..6).map(|i| [i as u8; 32].into()).collect();
+        let mut tree = ObservedMerkleTree::new(MerkleTree::from_leaves(&leaves, &[0; 32].into()));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let id = {
+            let seen = Arc::clone(&seen);
+            tree.subscribe(move |change| seen.lock().unwrap().push(change.clone()))
+        };
+        let receiver = tree.subscribe_channel();
+        assert_eq!(tree.observers(), 2);
+
+        let initial = *tree.tree().root_hash();
+        tree.set_many(&[
+            (5, [0xaa; 32].into()),
+            (1, [0xbb; 32].into()),
+            (5, [0xcc; 32].into()),
+        ]);
+        let change = receiver.try_recv().unwrap();
+        assert_eq!(change.previous, initial);
+        assert_eq!(change.root, *tree.tree().root_hash());
+        assert_eq!(change.leaves, [1, 5]);
+        assert_eq!(tree.tree().get(5), Some(&[0xcc; 32].into()));
+
+        // writing the current value doesn't change the root
+        tree.set(1, &[0xbb; 32].into());
+        assert!(receiver.try_recv().is_err());
+
+        assert_eq!(tree.push(&[0xdd; 32].into()), 6);
+        let pushed = receiver.try_recv().unwrap();
+        assert_eq!(pushed.leaves, [6]);
+        assert_eq!(pushed.previous, change.root);
+        assert_eq!(*seen.lock().unwrap(), [change, pushed]);
+
+        // a failed update neither changes the tree nor notifies
+        let root = *tree.tree().root_hash();
+        assert!(tree
+            .try_set_many(&[(0, [1; 32].into()), (8, [1; 32].into())])
+            .is_err());
+        assert_eq!(*tree.tree().root_hash(), root);
+        assert!(receiver.try_recv().is_err());
+
+        // dropped receivers and unsubscribed callbacks stop being notified
+        drop(receiver);
+        assert!(tree.unsubscribe(id));
+        assert!(!tree.unsubscribe(id));
+        tree.set(0, &[0xee; 32].into());
+        assert_eq!(tree.observers(), 0);
+        assert_eq!(seen.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,309 @@
+//! Trees over any number of leaves with a choice of how odd layers are completed
+//!
+//! `MerkleTree::from_leaves` fills the leaves up to the next power of two with a padding value.
+//! Other systems complete a layer with an odd number of nodes differently: Bitcoin pairs the last
+//! node with itself, RFC 6962 moves it up to the next layer unchanged. A `PaddedMerkleTree` is
+//! built with one of these rules, `Padding`, so its root matches the system it is checked against.
+//!
+//! Proofs are `InclusionProof`s of the log trees, their verification skips the layers on which
+//! the node has no sibling. For `Fill` and `RepeatLast` no layer is skipped, the proofs are
+//! created for a tree with the leaves padded to the next power of two and carry one sibling per
+//! layer, the siblings of a `MerkleTree` proof or Bitcoin's Merkle branch.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, InclusionProof, MerkleTreeError, Plain};
+
+/// How a layer with an odd number of nodes is completed
+pub enum Padding<D: Digest>
+where
+    Output<D>: Copy,
+{
+    /// the leaves are filled up to the next power of two with the value like in
+    /// `MerkleTree::from_leaves`, `Fill(Default::default())` fills with the all-zero hash
+    Fill(Output<D>),
+    /// the last node is paired with itself like in Bitcoin's block trees
+    RepeatLast,
+    /// the last node is moved up to the next layer unchanged like in RFC 6962
+    PromoteOdd,
+}
+
+impl<D: Digest> Clone for Padding<D>
+where
+    Output<D>: Copy,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<D: Digest> Copy for Padding<D> where Output<D>: Copy {}
+
+impl<D: Digest> PartialEq for Padding<D>
+where
+    Output<D>: Copy,
+{
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Padding::Fill(value), Padding::Fill(other)) => value == other,
+            (Padding::RepeatLast, Padding::RepeatLast) => true,
+            (Padding::PromoteOdd, Padding::PromoteOdd) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<D: Digest> Eq for Padding<D> where Output<D>: Copy {}
+
+impl<D: Digest> Debug for Padding<D>
+where
+    Output<D>: Copy,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Padding::Fill(value) => f.debug_tuple("Fill").field(&hex::encode(value)).finish(),
+            Padding::RepeatLast => write!(f, "RepeatLast"),
+            Padding::PromoteOdd => write!(f, "PromoteOdd"),
+        }
+    }
+}
+
+/// A Merkle tree over any number of leaves, see the module docs
+pub struct PaddedMerkleTree<D: Digest, S = Plain>
+where
+    Output<D>: Copy,
+{
+    /// the nodes of every layer, the leaves first, without the nodes added by the padding except
+    /// for the leaves of `Fill`
+    layers: Vec<Vec<Output<D>>>,
+    /// the number of given leaves
+    len: usize,
+    padding: Padding<D>,
+    scheme: PhantomData<fn() -> S>,
+}
+
+impl<D, S> PaddedMerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a tree from the given leaves, the layers are completed according to the padding
+    /// without leaves the root is `HashScheme::empty_root` of the scheme
+    pub fn from_leaves(leaves: &[Output<D>], padding: Padding<D>) -> Self {
+        let mut layer = leaves.to_vec();
+        if let Padding::Fill(value) = padding {
+            if !layer.is_empty() {
+                layer.resize(layer.len().next_power_of_two(), value);
+            }
+        }
+        let mut layers = vec![layer];
+        while let Some(layer) = layers.last().filter(|layer| layer.len() > 1) {
+            let parents = layer
+                .chunks(2)
+                .map(|pair| match (pair, padding) {
+                    ([left, right], _) => S::hash_node(left, right),
+                    ([last], Padding::PromoteOdd) => *last,
+                    // filled layers always have an even number of nodes
+                    ([last], _) => S::hash_node(last, last),
+                    _ => unreachable!(),
+                })
+                .collect();
+            layers.push(parents);
+        }
+        Self {
+            layers,
+            len: leaves.len(),
+            padding,
+            scheme: PhantomData,
+        }
+    }
+
+    /// creates a tree from raw leaf payloads hashed with `hash_leaf` of the scheme, see
+    /// `from_leaves`
+    pub fn from_leaf_data<T: AsRef<[u8]>>(data: &[T], padding: Padding<D>) -> Self {
+        let leaves: Vec<Output<D>> = data
+            .iter()
+            .map(|item| S::hash_leaf(item.as_ref()))
+            .collect();
+        Self::from_leaves(&leaves, padding)
+    }
+
+    /// returns the number of given leaves
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// returns true if the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// returns the padding the tree was built with
+    pub fn padding(&self) -> Padding<D> {
+        self.padding
+    }
+
+    /// returns the root hash of the tree
+    pub fn root_hash(&self) -> Output<D> {
+        match self.layers.last().and_then(|layer| layer.first()) {
+            Some(root) => *root,
+            None => S::empty_root(),
+        }
+    }
+
+    /// returns the value of a given leaf, None if the offset is out of bounds
+    pub fn get(&self, offset: usize) -> Option<&Output<D>> {
+        self.layers[0][..self.len].get(offset)
+    }
+
+    /// creates a proof for a given leaf against the root of the tree
+    /// panics if the offset is out of bounds, see `try_create_proof` for a fallible version
+    pub fn create_proof(&self, offset: usize) -> InclusionProof<D, S> {
+        match self.try_create_proof(offset) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a proof for a given leaf against the root of the tree
+    /// returns an error if the offset is out of bounds
+    pub fn try_create_proof(&self, offset: usize) -> Result<InclusionProof<D, S>, MerkleTreeError> {
+        if offset >= self.len {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.len,
+            });
+        }
+        let mut path = Vec::with_capacity(self.layers.len() - 1);
+        let mut index = offset;
+        for layer in &self.layers[..self.layers.len() - 1] {
+            match layer.get(index ^ 1) {
+                Some(sibling) => path.push(*sibling),
+                None if matches!(self.padding, Padding::RepeatLast) => path.push(layer[index]),
+                // the node is moved up, the layer has no sibling
+                None => {}
+            }
+            index /= 2;
+        }
+        let tree_size = match self.padding {
+            Padding::PromoteOdd => self.len,
+            _ => self.len.next_power_of_two(),
+        };
+        Ok(InclusionProof::new(offset, tree_size, path))
+    }
+}
+
+impl<D: Digest, S> Clone for PaddedMerkleTree<D, S>
+where
+    Output<D>: Copy,
+{
+    fn clone(&self) -> Self {
+        Self {
+            layers: self.layers.clone(),
+            len: self.len,
+            padding: self.padding,
+            scheme: PhantomData,
+        }
+    }
+}
+
+impl<D: Digest, S> Debug for PaddedMerkleTree<D, S>
+where
+    Output<D>: Copy,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let root = self.layers.last().and_then(|layer| layer.first());
+        f.debug_struct("PaddedMerkleTree")
+            .field("root", &root.map(hex::encode))
+            .field("len", &self.len)
+            .field("padding", &self.padding)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha2::Sha256;
+    use sha3::Sha3_256;
+
+    use crate::{bitcoin, DoubleHash, LogTree, MerkleTree, Rfc6962};
+
+    #[test]
+    fn test_fill_matches_merkle_tree() {
+        let padding = Padding::Fill([0xee; 32].into());
+        for len in 1..=9u8 {
+            let leaves: Vec<Output<Sha3_256>> = (0..len).map(|i| [i; 32].into()).collect();
+            let tree = PaddedMerkleTree::<Sha3_256>::from_leaves(&leaves, padding);
+            let expected = MerkleTree::<Sha3_256>::from_leaves(&leaves, &[0xee; 32].into());
+            assert_eq!(tree.root_hash(), *expected.root_hash());
+            for (offset, leaf) in leaves.iter().enumerate() {
+                let proof = tree.create_proof(offset);
+                assert_eq!(proof.path(), expected.create_proof(offset).siblings());
+                assert!(proof.verify(leaf, &tree.root_hash()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_repeat_last_matches_bitcoin() {
+        for len in 1..=9u8 {
+            let txids: Vec<Output<Sha256>> = (0..len).map(|i| [i; 32].into()).collect();
+            let tree =
+                PaddedMerkleTree::<Sha256, DoubleHash>::from_leaves(&txids, Padding::RepeatLast);
+            assert_eq!(Some(tree.root_hash()), bitcoin::merkle_root(&txids));
+            for (offset, txid) in txids.iter().enumerate() {
+                let proof = tree.create_proof(offset);
+                let expected = bitcoin::create_proof(&txids, offset).unwrap();
+                assert_eq!(proof.path(), expected.siblings);
+                assert!(proof.verify(txid, &tree.root_hash()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_promote_odd_matches_rfc6962() {
+        let mut log = LogTree::<Sha256, Rfc6962>::new();
+        for len in 1..=9u8 {
+            log.push_leaf_data(&[len]);
+            let data: Vec<[u8; 1]> = (1..=len).map(|i| [i]).collect();
+            let tree =
+                PaddedMerkleTree::<Sha256, Rfc6962>::from_leaf_data(&data, Padding::PromoteOdd);
+            assert_eq!(tree.root_hash(), log.root_hash());
+            for (offset, data) in data.iter().enumerate() {
+                let proof = tree.create_proof(offset);
+                assert_eq!(proof, log.create_proof(offset).unwrap());
+                assert!(proof.verify_data(data, &tree.root_hash()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_and_out_of_bounds() {
+        let tree = PaddedMerkleTree::<Sha256, Rfc6962>::from_leaves(&[], Padding::PromoteOdd);
+        assert!(tree.is_empty());
+        assert_eq!(tree.root_hash(), LogTree::<Sha256>::new().root_hash());
+        assert!(tree.get(0).is_none());
+
+        let tree = PaddedMerkleTree::<Sha3_256>::from_leaves(
+            &[[1; 32].into(); 3],
+            Padding::Fill(Default::default()),
+        );
+        assert_eq!(tree.len(), 3);
+        assert!(tree.get(3).is_none());
+        assert_eq!(
+            tree.clone().try_create_proof(3),
+            Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: 3,
+                num_leaves: 3
+            })
+        );
+    }
+}
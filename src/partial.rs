@@ -0,0 +1,291 @@
+//! Partial trees for stateless clients
+//!
+//! A `PartialTree` knows the root of a tree and the nodes revealed by inclusion proofs against it:
+//! the proven leaves, the nodes on their paths and the siblings. Every added proof is verified
+//! against the root first, so all known nodes belong to the tree.
+//!
+//! A leaf whose siblings are known can be updated like in a full tree: its path is rehashed and the
+//! root changes to the one the full tree would have after the same `set`. The paths of the other
+//! proven leaves stay valid, a sibling on the path of an updated leaf is updated with it. Light
+//! clients holding proofs of the leaves they touch can therefore simulate updates and compute the
+//! resulting root without holding all nodes of the tree.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError, Plain, Proof, MAX_DEPTH};
+
+/// A tree of which only the root and the nodes of some proofs are known
+pub struct PartialTree<D: Digest, S = Plain> {
+    /// the current root, changed by updates
+    root: Output<D>,
+    depth: usize,
+    /// known nodes by node index
+    nodes: BTreeMap<usize, Output<D>>,
+    scheme: PhantomData<fn() -> S>,
+}
+
+impl<D: Digest, S> Clone for PartialTree<D, S> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            depth: self.depth,
+            nodes: self.nodes.clone(),
+            scheme: PhantomData,
+        }
+    }
+}
+
+impl<D, S> PartialTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a partial tree with the given root and depth that knows no nodes yet
+    /// returns an error if the depth is not between 1 and `MAX_DEPTH`
+    pub fn new(root: &Output<D>, depth: usize) -> Result<Self, MerkleTreeError> {
+        if !(1..=MAX_DEPTH).contains(&depth) {
+            return Err(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            });
+        }
+        let mut nodes = BTreeMap::new();
+        nodes.insert(0, *root);
+        Ok(Self {
+            root: *root,
+            depth,
+            nodes,
+            scheme: PhantomData,
+        })
+    }
+
+    /// creates a partial tree from the root and proofs of leaves given as (leaf, proof) pairs
+    /// returns an error if the depth is out of range or a proof doesn't lead to the root
+    pub fn from_proofs(
+        root: &Output<D>,
+        depth: usize,
+        proofs: &[(Output<D>, Proof<D, S>)],
+    ) -> Result<Self, MerkleTreeError> {
+        let mut tree = Self::new(root, depth)?;
+        for (leaf, proof) in proofs {
+            tree.add_proof(leaf, proof)?;
+        }
+        Ok(tree)
+    }
+
+    /// returns the current root, including all updates
+    pub fn root_hash(&self) -> &Output<D> {
+        &self.root
+    }
+
+    /// returns the depth of the tree, the number of layers including the root and the leaves
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// returns the number of leaves of the full tree
+    pub fn num_leaves(&self) -> usize {
+        1 << (self.depth - 1)
+    }
+
+    /// returns the number of known nodes
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// returns the value of a leaf if it is known
+    pub fn get(&self, offset: usize) -> Option<&Output<D>> {
+        self.check_offset(offset).ok()?;
+        self.nodes
+            .get(&MerkleTree::<D, S>::index(self.depth - 1, offset))
+    }
+
+    /// returns true if the proof leads from the leaf to the current root
+    pub fn verify(&self, leaf: &Output<D>, proof: &Proof<D, S>) -> bool {
+        proof.len() == self.depth - 1
+            && proof.leaf_index() < self.num_leaves()
+            && proof.compute_root(leaf) == self.root
+    }
+
+    /// verifies the proof of a leaf against the current root and adds the nodes it reveals
+    /// returns an error and adds nothing if the proof doesn't lead to the root
+    pub fn add_proof(
+        &mut self,
+        leaf: &Output<D>,
+        proof: &Proof<D, S>,
+    ) -> Result<(), MerkleTreeError> {
+        if proof.len() != self.depth - 1 {
+            return Err(MerkleTreeError::InvalidProofLength {
+                expected: self.depth - 1,
+                actual: proof.len(),
+            });
+        }
+        let offset = proof.leaf_index();
+        self.check_offset(offset)?;
+        if proof.compute_root(leaf) != self.root {
+            return Err(MerkleTreeError::RootMismatch);
+        }
+        let mut current = *leaf;
+        for (layer, (sibling, is_left)) in (1..self.depth).rev().zip(proof.iter()) {
+            let node = offset >> (self.depth - 1 - layer);
+            self.nodes
+                .insert(MerkleTree::<D, S>::index(layer, node), current);
+            self.nodes
+                .insert(MerkleTree::<D, S>::index(layer, node ^ 1), *sibling);
+            current = if is_left {
+                S::hash_node(&current, sibling)
+            } else {
+                S::hash_node(sibling, &current)
+            };
+        }
+        Ok(())
+    }
+
+    /// assembles the proof of a known leaf against the current root
+    /// returns an error if a sibling of the leaf is unknown
+    pub fn create_proof(&self, offset: usize) -> Result<Proof<D, S>, MerkleTreeError> {
+        self.check_offset(offset)?;
+        let siblings = (1..self.depth)
+            .rev()
+            .map(|layer| self.sibling(layer, offset))
+            .collect::<Result<_, _>>()?;
+        Ok(Proof::new(offset, siblings))
+    }
+
+    /// updates the value of a leaf whose siblings are known and returns the new root
+    /// panics if the offset is out of bounds or a sibling is unknown, see `try_set` for a fallible
+    /// version
+    pub fn set(&mut self, offset: usize, value: &Output<D>) -> &Output<D> {
+        if let Err(err) = self.try_set(offset, value) {
+            panic!("{err}");
+        }
+        &self.root
+    }
+
+    /// updates the value of a leaf whose siblings are known and returns the new root
+    /// returns an error and leaves the tree untouched if the offset is out of bounds or a sibling
+    /// is unknown
+    pub fn try_set(
+        &mut self,
+        offset: usize,
+        value: &Output<D>,
+    ) -> Result<&Output<D>, MerkleTreeError> {
+        self.check_offset(offset)?;
+        let siblings: Vec<Output<D>> = (1..self.depth)
+            .rev()
+            .map(|layer| self.sibling(layer, offset))
+            .collect::<Result<_, _>>()?;
+        let mut current = *value;
+        for (layer, sibling) in (1..self.depth).rev().zip(&siblings) {
+            let node = offset >> (self.depth - 1 - layer);
+            self.nodes
+                .insert(MerkleTree::<D, S>::index(layer, node), current);
+            current = if node.is_multiple_of(2) {
+                S::hash_node(&current, sibling)
+            } else {
+                S::hash_node(sibling, &current)
+            };
+        }
+        self.nodes.insert(0, current);
+        self.root = current;
+        Ok(&self.root)
+    }
+
+    /// returns the root the tree would have after the given (offset, value) updates without
+    /// applying them
+    /// returns an error if an offset is out of bounds or a sibling on the way is unknown
+    pub fn root_after(&self, updates: &[(usize, Output<D>)]) -> Result<Output<D>, MerkleTreeError> {
+        let mut tree = self.clone();
+        for (offset, value) in updates {
+            tree.try_set(*offset, value)?;
+        }
+        Ok(tree.root)
+    }
+
+    /// returns the known sibling of the node on the path of a leaf in the given layer
+    fn sibling(&self, layer: usize, offset: usize) -> Result<Output<D>, MerkleTreeError> {
+        let sibling = (offset >> (self.depth - 1 - layer)) ^ 1;
+        self.nodes
+            .get(&MerkleTree::<D, S>::index(layer, sibling))
+            .copied()
+            .ok_or(MerkleTreeError::MissingNode {
+                layer,
+                offset: sibling,
+            })
+    }
+
+    fn check_offset(&self, offset: usize) -> Result<(), MerkleTreeError> {
+        let num_leaves = self.num_leaves();
+        if offset >= num_leaves {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds { offset, num_leaves });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type PartialTree = super::PartialTree<Sha3_256>;
+
+    #[test]
+    fn test_partial_tree() {
+        let leaves: Vec<Output<Sha3_256>> = (0..16).map(|i| [i as u8; 32].into()).collect();
+        let mut full = MerkleTree::from_leaves(&leaves, &[0; 32].into());
+        let proofs: Vec<_> = [2, 3, 9]
+            .into_iter()
+            .map(|offset| (leaves[offset], full.create_proof(offset)))
+            .collect();
+        let mut partial = PartialTree::from_proofs(full.root_hash(), 5, &proofs).unwrap();
+        assert_eq!(partial.get(9), Some(&leaves[9]));
+        assert_eq!(partial.get(4), None);
+        assert!(partial.verify(&leaves[12], &full.create_proof(12)));
+        assert!(!partial.verify(&leaves[11], &full.create_proof(12)));
+
+        // simulated updates lead to the roots of the full tree
+        let updates = [(3, [0xaa; 32].into()), (9, [0xbb; 32].into())];
+        let expected = partial.root_after(&updates).unwrap();
+        for (offset, value) in updates {
+            let root = *partial.set(offset, &value);
+            full.set(offset, &value);
+            assert_eq!(root, *full.root_hash());
+        }
+        assert_eq!(expected, *full.root_hash());
+
+        // the proofs of all known leaves still hold after the updates
+        for offset in [2, 3, 9] {
+            let proof = partial.create_proof(offset).unwrap();
+            assert_eq!(proof, full.create_proof(offset));
+            assert!(partial.verify(full.get(offset).unwrap(), &proof));
+        }
+        assert_eq!(
+            partial.try_set(7, &[1; 32].into()),
+            Err(MerkleTreeError::MissingNode {
+                layer: 4,
+                offset: 6
+            })
+        );
+        assert!(partial.root_after(&[(15, [1; 32].into())]).is_err());
+
+        // proofs against another root are rejected
+        let stale = (
+            leaves[4],
+            MerkleTree::from_leaves(&leaves, &[0; 32].into()).create_proof(4),
+        );
+        assert_eq!(
+            partial.add_proof(&stale.0, &stale.1),
+            Err(MerkleTreeError::RootMismatch)
+        );
+        assert!(PartialTree::new(full.root_hash(), 0).is_err());
+    }
+}
@@ -0,0 +1,410 @@
+//! PKCS#11-backed leaf hashing and tree head signing
+//!
+//! For deployments where key material and the hashing of regulated data have to stay inside an
+//! HSM. `Pkcs11Sessions` opens a pool of logged-in sessions on a token, `Pkcs11Hasher` computes
+//! leaf hashes with a digest mechanism of the token and `Pkcs11Signer` signs tree heads with a
+//! private key stored on it. Interior nodes only combine hashes and are computed in software.
+//!
+//! HSM calls have a high latency, so the batch operations spread their items over all sessions of
+//! the pool and process them concurrently. Mechanisms are passed as functions creating them, e.g.
+//! `|| Mechanism::Sha256`, as every worker needs its own instance.
+
+use std::fmt::{self, Debug};
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+
+use cryptoki::context::{CInitializeArgs, CInitializeFlags, Pkcs11};
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::types::AuthPin;
+use digest::{Digest, Output};
+
+use crate::custody::{SignError, SthSigner};
+
+/// Errors returned by the PKCS#11 integration
+#[derive(Debug)]
+pub enum Pkcs11Error {
+    /// the PKCS#11 module returned an error
+    Pkcs11(cryptoki::error::Error),
+    /// no slot holds a token with the given label
+    TokenNotFound { label: String },
+    /// the token holds no private key with the given label
+    KeyNotFound { label: String },
+    /// the digest returned by the token doesn't have the size of the digest output
+    InvalidDigestLength { expected: usize, actual: usize },
+}
+
+impl fmt::Display for Pkcs11Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pkcs11(err) => write!(f, "PKCS#11 error: {err}"),
+            Self::TokenNotFound { label } => write!(f, "no token with label {label} found"),
+            Self::KeyNotFound { label } => write!(f, "no private key with label {label} found"),
+            Self::InvalidDigestLength { expected, actual } => {
+                write!(
+                    f,
+                    "token digest must be {expected} bytes long, got {actual}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Pkcs11Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Pkcs11(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<cryptoki::error::Error> for Pkcs11Error {
+    fn from(err: cryptoki::error::Error) -> Self {
+        Self::Pkcs11(err)
+    }
+}
+
+/// A pool of logged-in sessions on a PKCS#11 token
+pub struct Pkcs11Sessions {
+    sessions: Vec<Mutex<Session>>,
+    /// keeps the module initialized while the sessions are open
+    _context: Pkcs11,
+}
+
+impl Debug for Pkcs11Sessions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pkcs11Sessions")
+            .field("sessions", &self.sessions.len())
+            .finish()
+    }
+}
+
+impl Pkcs11Sessions {
+    /// loads the PKCS#11 module, opens `count` sessions on the token with the given label and logs
+    /// them in with the user PIN
+    pub fn open(
+        module: impl AsRef<Path>,
+        token_label: &str,
+        pin: &str,
+        count: usize,
+    ) -> Result<Arc<Self>, Pkcs11Error> {
+        let context = Pkcs11::new(module.as_ref())?;
+        context.initialize(CInitializeArgs::new(CInitializeFlags::OS_LOCKING_OK))?;
+        let mut slot = None;
+        for candidate in context.get_slots_with_token()? {
+            if context.get_token_info(candidate)?.label() == token_label {
+                slot = Some(candidate);
+                break;
+            }
+        }
+        let slot = slot.ok_or_else(|| Pkcs11Error::TokenNotFound {
+            label: token_label.to_owned(),
+        })?;
+        let sessions = (0..count.max(1))
+            .map(|_| context.open_ro_session(slot).map(Mutex::new))
+            .collect::<Result<Vec<_>, _>>()?;
+        // the login state is shared by all sessions of the application on the token
+        sessions[0]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .login(UserType::User, Some(&AuthPin::from(pin)))?;
+        Ok(Arc::new(Self {
+            sessions,
+            _context: context,
+        }))
+    }
+
+    /// returns the number of sessions in the pool
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// returns true if the pool has no sessions, which never happens for an opened pool
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// runs the operation on a single item with the first idle session
+    fn run<R>(
+        &self,
+        operation: impl FnOnce(&Session) -> cryptoki::error::Result<R>,
+    ) -> Result<R, Pkcs11Error> {
+        let session = self
+            .sessions
+            .iter()
+            .find_map(|session| session.try_lock().ok())
+            .unwrap_or_else(|| {
+                self.sessions[0]
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+            });
+        Ok(operation(&session)?)
+    }
+
+    /// runs the operation on all items, spreading them over the sessions of the pool
+    /// the results are returned in the order of the items
+    fn run_batch<T, R>(
+        &self,
+        items: &[T],
+        operation: impl Fn(&Session, &T) -> Result<R, Pkcs11Error> + Sync,
+    ) -> Result<Vec<R>, Pkcs11Error>
+    where
+        T: Sync,
+        R: Send,
+    {
+        spread(&self.sessions, items, operation)
+    }
+}
+
+/// runs the operation on all items, one thread per session processes a contiguous chunk of them
+/// the results are returned in the order of the items, or the error of the first failed item
+fn spread<W, T, R, E>(
+    sessions: &[Mutex<W>],
+    items: &[T],
+    operation: impl Fn(&W, &T) -> Result<R, E> + Sync,
+) -> Result<Vec<R>, E>
+where
+    W: Send,
+    T: Sync,
+    R: Send,
+    E: Send,
+{
+    if items.is_empty() {
+        return Ok(Vec::new());
+    }
+    let chunk_size = items.len().div_ceil(sessions.len());
+    let operation = &operation;
+    thread::scope(|scope| {
+        let workers: Vec<_> = items
+            .chunks(chunk_size)
+            .zip(sessions)
+            .map(|(chunk, session)| {
+                scope.spawn(move || {
+                    let session = session.lock().unwrap_or_else(PoisonError::into_inner);
+                    chunk
+                        .iter()
+                        .map(|item| operation(&session, item))
+                        .collect::<Result<Vec<R>, _>>()
+                })
+            })
+            .collect();
+        let mut results = Vec::with_capacity(items.len());
+        for worker in workers {
+            match worker.join() {
+                Ok(chunk) => results.extend(chunk?),
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        }
+        Ok(results)
+    })
+}
+
+/// creates the mechanism of an operation
+pub type MechanismFn = fn() -> Mechanism<'static>;
+
+/// Computes leaf hashes with a digest mechanism of a PKCS#11 token
+/// The mechanism has to implement the digest `D`, e.g. `Mechanism::Sha256` for `sha2::Sha256`, so
+/// the leaves match the ones computed in software.
+pub struct Pkcs11Hasher<D> {
+    sessions: Arc<Pkcs11Sessions>,
+    mechanism: MechanismFn,
+    /// domain separation prefix hashed in front of the payloads
+    prefix: Vec<u8>,
+    _digest: PhantomData<fn() -> D>,
+}
+
+impl<D> Debug for Pkcs11Hasher<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pkcs11Hasher")
+            .field("mechanism", &(self.mechanism)())
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+impl<D: Digest> Pkcs11Hasher<D> {
+    /// creates a hasher using the given digest mechanism
+    pub fn new(sessions: Arc<Pkcs11Sessions>, mechanism: MechanismFn) -> Self {
+        Self {
+            sessions,
+            mechanism,
+            prefix: Vec::new(),
+            _digest: PhantomData,
+        }
+    }
+
+    /// hashes the prefix in front of every payload, e.g. `Rfc6962::LEAF_PREFIX` for the leaves of a
+    /// `LogTree`
+    pub fn with_prefix(mut self, prefix: &[u8]) -> Self {
+        self.prefix = prefix.to_vec();
+        self
+    }
+
+    /// returns the hash of a leaf payload
+    pub fn hash_leaf(&self, data: &[u8]) -> Result<Output<D>, Pkcs11Error> {
+        let digest = self.sessions.run(|session| self.digest(session, data))?;
+        Self::to_output(digest)
+    }
+
+    /// returns the hashes of the leaf payloads, hashed concurrently with all sessions of the pool
+    pub fn hash_leaves<T: AsRef<[u8]> + Sync>(
+        &self,
+        data: &[T],
+    ) -> Result<Vec<Output<D>>, Pkcs11Error> {
+        self.sessions.run_batch(data, |session, item| {
+            Self::to_output(self.digest(session, item.as_ref())?)
+        })
+    }
+
+    fn digest(&self, session: &Session, data: &[u8]) -> cryptoki::error::Result<Vec<u8>> {
+        let mechanism = (self.mechanism)();
+        if self.prefix.is_empty() {
+            return session.digest(&mechanism, data);
+        }
+        session.digest_init(&mechanism)?;
+        session.digest_update(&self.prefix)?;
+        session.digest_update(data)?;
+        session.digest_final()
+    }
+
+    fn to_output(digest: Vec<u8>) -> Result<Output<D>, Pkcs11Error> {
+        if digest.len() != <D as Digest>::output_size() {
+            return Err(Pkcs11Error::InvalidDigestLength {
+                expected: <D as Digest>::output_size(),
+                actual: digest.len(),
+            });
+        }
+        Ok(Output::<D>::clone_from_slice(&digest))
+    }
+}
+
+/// Signs tree heads with a private key stored on a PKCS#11 token
+pub struct Pkcs11Signer {
+    sessions: Arc<Pkcs11Sessions>,
+    key: ObjectHandle,
+    mechanism: MechanismFn,
+}
+
+impl Debug for Pkcs11Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pkcs11Signer")
+            .field("key", &self.key)
+            .field("mechanism", &(self.mechanism)())
+            .finish()
+    }
+}
+
+impl Pkcs11Signer {
+    /// creates a signer using the private key with the given label and the signature mechanism,
+    /// e.g. `|| Mechanism::EcdsaSha256`
+    pub fn new(
+        sessions: Arc<Pkcs11Sessions>,
+        key_label: &str,
+        mechanism: MechanismFn,
+    ) -> Result<Self, Pkcs11Error> {
+        let template = [
+            Attribute::Class(ObjectClass::PRIVATE_KEY),
+            Attribute::Label(key_label.as_bytes().to_vec()),
+        ];
+        let key = sessions
+            .run(|session| session.find_objects(&template))?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Pkcs11Error::KeyNotFound {
+                label: key_label.to_owned(),
+            })?;
+        Ok(Self {
+            sessions,
+            key,
+            mechanism,
+        })
+    }
+
+    /// returns the signature of a message
+    pub fn try_sign(&self, message: &[u8]) -> Result<Vec<u8>, Pkcs11Error> {
+        self.sessions
+            .run(|session| session.sign(&(self.mechanism)(), self.key, message))
+    }
+
+    /// returns the signatures of the messages, signed concurrently with all sessions of the pool
+    pub fn sign_batch<T: AsRef<[u8]> + Sync>(
+        &self,
+        messages: &[T],
+    ) -> Result<Vec<Vec<u8>>, Pkcs11Error> {
+        self.sessions.run_batch(messages, |session, message| {
+            Ok(session.sign(&(self.mechanism)(), self.key, message.as_ref())?)
+        })
+    }
+}
+
+impl SthSigner for Pkcs11Signer {
+    /// signs the message on the token
+    /// panics if the token fails, `CustodyLog::try_publish_head` signs with `try_sign` instead
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        match Pkcs11Signer::try_sign(self, message) {
+            Ok(signature) => signature,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// signs the message on the token, returns the error of the token if it fails
+    fn try_sign(&self, message: &[u8]) -> Result<Vec<u8>, SignError> {
+        Pkcs11Signer::try_sign(self, message).map_err(SignError::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread::ThreadId;
+
+    #[test]
+    fn test_spread_keeps_item_order() {
+        for (sessions, items) in [(4, 10), (4, 3), (1, 5), (3, 3)] {
+            let sessions: Vec<_> = (0..sessions).map(Mutex::new).collect();
+            let items: Vec<usize> = (0..items).collect();
+            let results = spread(&sessions, &items, |session, item| {
+                Ok::<_, Pkcs11Error>((*session, thread::current().id(), item * 2))
+            })
+            .unwrap();
+            let doubled: Vec<usize> = results.iter().map(|(_, _, item)| *item).collect();
+            assert_eq!(
+                doubled,
+                items.iter().map(|item| item * 2).collect::<Vec<_>>()
+            );
+            // every session works on its own thread, the sessions take turns in item order
+            let mut used: Vec<(usize, ThreadId)> = results
+                .iter()
+                .map(|(session, thread, _)| (*session, *thread))
+                .collect();
+            assert!(used.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+            used.dedup();
+            assert_eq!(used.len(), sessions.len().min(items.len()));
+        }
+        let sessions = [Mutex::new(())];
+        assert!(spread(&sessions, &[] as &[u8], |_, _| Ok::<_, ()>(()))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_spread_returns_first_error() {
+        let sessions: Vec<_> = (0..3).map(Mutex::new).collect();
+        let items: Vec<usize> = (0..9).collect();
+        let result = spread(&sessions, &items, |_, item| match item {
+            4 | 7 => Err(Pkcs11Error::KeyNotFound {
+                label: item.to_string(),
+            }),
+            _ => Ok(*item),
+        });
+        assert!(matches!(
+            result,
+            Err(Pkcs11Error::KeyNotFound { label }) if label == "4"
+        ));
+    }
+}
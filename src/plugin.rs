@@ -0,0 +1,561 @@
+//! Hashers loaded at runtime through a stable C ABI
+//!
+//! A plugin is a shared library exporting the function `merkle_tree_hasher_plugin` which returns a
+//! pointer to a `HasherVTable` (see `include/merkle_tree_plugin.h`). The table holds an opaque
+//! context and the functions hashing leaf payloads and combining two child nodes, so proprietary
+//! or HSM-backed hashing can be dropped in without recompiling the crate. `PluginMerkleTree` is a
+//! Merkle tree using such a hasher, with nodes of the size announced by the plugin.
+//!
+//! The functions of a plugin may be called from several threads at the same time.
+
+use std::ffi::{c_int, c_void};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::MAX_DEPTH;
+
+/// version of the function table layout, plugins announce the version they implement
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// name of the function a plugin library exports to return its function table
+pub const PLUGIN_ENTRY_POINT: &str = "merkle_tree_hasher_plugin";
+
+/// function returning the function table of a plugin, the table has to stay valid until `release`
+/// is called
+pub type PluginEntryPoint = unsafe extern "C" fn() -> *const HasherVTable;
+
+/// The function table of a hasher plugin
+/// The hash functions write `output_size` bytes to `out` and return 0 on success or a plugin
+/// specific error code.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct HasherVTable {
+    /// has to be `PLUGIN_ABI_VERSION`
+    pub abi_version: u32,
+    /// size of the hashes in bytes
+    pub output_size: u32,
+    /// opaque context passed to all functions
+    pub context: *mut c_void,
+    /// hashes a leaf payload of `len` bytes
+    pub hash_leaf: unsafe extern "C" fn(
+        context: *mut c_void,
+        data: *const u8,
+        len: usize,
+        out: *mut u8,
+    ) -> c_int,
+    /// hashes two child nodes of `output_size` bytes each into their parent
+    pub hash_node: unsafe extern "C" fn(
+        context: *mut c_void,
+        left: *const u8,
+        right: *const u8,
+        out: *mut u8,
+    ) -> c_int,
+    /// releases the context, called once the plugin is dropped
+    pub release: Option<unsafe extern "C" fn(context: *mut c_void)>,
+}
+
+/// Errors returned when loading or calling a hasher plugin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PluginError {
+    /// the library or its entry point could not be loaded
+    Load { message: String },
+    /// the entry point returned a null pointer
+    MissingVTable,
+    /// the plugin implements another version of the function table
+    AbiVersionMismatch { expected: u32, actual: u32 },
+    /// the plugin announced an output size of 0 bytes
+    InvalidOutputSize,
+    /// the plugin returned an error code
+    HashFailed { code: i32 },
+    /// a node passed to the plugin doesn't have its output size
+    InvalidHashLength { expected: usize, actual: usize },
+    /// the requested depth is not supported
+    DepthOutOfRange { depth: usize, max: usize },
+    /// the leaf offset is not smaller than the number of leaves
+    LeafIndexOutOfBounds { offset: usize, num_leaves: usize },
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Load { message } => write!(f, "failed to load hasher plugin: {message}"),
+            Self::MissingVTable => write!(f, "hasher plugin returned no function table"),
+            Self::AbiVersionMismatch { expected, actual } => write!(
+                f,
+                "hasher plugin implements ABI version {actual}, expected {expected}"
+            ),
+            Self::InvalidOutputSize => write!(f, "hasher plugin has an output size of 0 bytes"),
+            Self::HashFailed { code } => write!(f, "hasher plugin failed with code {code}"),
+            Self::InvalidHashLength { expected, actual } => {
+                write!(f, "hashes must be {expected} bytes long, got {actual}")
+            }
+            Self::DepthOutOfRange { depth, max } => {
+                write!(
+                    f,
+                    "Merkle tree depth must be between 1 and {max}, got {depth}"
+                )
+            }
+            Self::LeafIndexOutOfBounds { offset, num_leaves } => write!(
+                f,
+                "leaf index {offset} is out of bounds for a tree with {num_leaves} leaves"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+/// A hasher implemented by a plugin
+#[derive(Debug)]
+pub struct HasherPlugin {
+    vtable: HasherVTable,
+    /// keeps the library loaded while the function table is in use
+    _library: Option<libloading::Library>,
+}
+
+// plugins have to be thread safe, see the module documentation
+unsafe impl Send for HasherPlugin {}
+unsafe impl Sync for HasherPlugin {}
+
+impl HasherPlugin {
+    /// loads a plugin from the shared library at the given path
+    ///
+    /// # Safety
+    ///
+    /// Loading a library runs its initialization code, and the library has to export a
+    /// `PluginEntryPoint` named `PLUGIN_ENTRY_POINT` returning a valid function table.
+    pub unsafe fn load(path: impl AsRef<Path>) -> Result<Self, PluginError> {
+        let error = |err: libloading::Error| PluginError::Load {
+            message: err.to_string(),
+        };
+        let library = libloading::Library::new(path.as_ref()).map_err(error)?;
+        let entry_point = *library
+            .get::<PluginEntryPoint>(PLUGIN_ENTRY_POINT.as_bytes())
+            .map_err(error)?;
+        let vtable = entry_point();
+        if vtable.is_null() {
+            return Err(PluginError::MissingVTable);
+        }
+        let mut plugin = Self::from_vtable(*vtable)?;
+        plugin._library = Some(library);
+        Ok(plugin)
+    }
+
+    /// creates a plugin from a function table, e.g. of a statically linked hasher
+    ///
+    /// # Safety
+    ///
+    /// The functions have to be valid for the context and write exactly `output_size` bytes.
+    pub unsafe fn from_vtable(vtable: HasherVTable) -> Result<Self, PluginError> {
+        // the layout of other versions is unknown, so the context can't be released
+        if vtable.abi_version != PLUGIN_ABI_VERSION {
+            return Err(PluginError::AbiVersionMismatch {
+                expected: PLUGIN_ABI_VERSION,
+                actual: vtable.abi_version,
+            });
+        }
+        if vtable.output_size == 0 {
+            return Err(PluginError::InvalidOutputSize);
+        }
+        Ok(Self {
+            vtable,
+            _library: None,
+        })
+    }
+
+    /// returns the size of the hashes in bytes
+    pub fn output_size(&self) -> usize {
+        self.vtable.output_size as usize
+    }
+
+    /// returns the hash of a leaf payload
+    pub fn hash_leaf(&self, data: &[u8]) -> Result<Vec<u8>, PluginError> {
+        let mut out = vec![0; self.output_size()];
+        // SAFETY: the output buffer has the announced size
+        let code = unsafe {
+            (self.vtable.hash_leaf)(
+                self.vtable.context,
+                data.as_ptr(),
+                data.len(),
+                out.as_mut_ptr(),
+            )
+        };
+        Self::check(code)?;
+        Ok(out)
+    }
+
+    /// returns the hash of an interior node given its children
+    pub fn hash_node(&self, left: &[u8], right: &[u8]) -> Result<Vec<u8>, PluginError> {
+        let mut out = vec![0; self.output_size()];
+        self.hash_node_into(left, right, &mut out)?;
+        Ok(out)
+    }
+
+    /// writes the hash of an interior node to `out`
+    fn hash_node_into(&self, left: &[u8], right: &[u8], out: &mut [u8]) -> Result<(), PluginError> {
+        for node in [left, right, &*out] {
+            self.check_length(node)?;
+        }
+        // SAFETY: all buffers have the announced size
+        let code = unsafe {
+            (self.vtable.hash_node)(
+                self.vtable.context,
+                left.as_ptr(),
+                right.as_ptr(),
+                out.as_mut_ptr(),
+            )
+        };
+        Self::check(code)
+    }
+
+    fn check_length(&self, hash: &[u8]) -> Result<(), PluginError> {
+        if hash.len() != self.output_size() {
+            return Err(PluginError::InvalidHashLength {
+                expected: self.output_size(),
+                actual: hash.len(),
+            });
+        }
+        Ok(())
+    }
+
+    fn check(code: c_int) -> Result<(), PluginError> {
+        match code {
+            0 => Ok(()),
+            code => Err(PluginError::HashFailed { code }),
+        }
+    }
+}
+
+impl Drop for HasherPlugin {
+    fn drop(&mut self) {
+        if let Some(release) = self.vtable.release {
+            // SAFETY: the context is released exactly once
+            unsafe { release(self.vtable.context) }
+        }
+    }
+}
+
+/// An inclusion proof of a `PluginMerkleTree`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginProof {
+    leaf_index: usize,
+    siblings: Vec<Vec<u8>>,
+}
+
+impl PluginProof {
+    /// creates a proof from the offset of the leaf and the sibling hashes starting at the leaf layer
+    pub fn new(leaf_index: usize, siblings: Vec<Vec<u8>>) -> Self {
+        Self {
+            leaf_index,
+            siblings,
+        }
+    }
+
+    /// returns the offset of the proven leaf
+    pub fn leaf_index(&self) -> usize {
+        self.leaf_index
+    }
+
+    /// returns the sibling hashes starting at the leaf layer
+    pub fn siblings(&self) -> &[Vec<u8>] {
+        &self.siblings
+    }
+
+    /// returns the root hash computed from the leaf value and the proof
+    pub fn compute_root(&self, plugin: &HasherPlugin, leaf: &[u8]) -> Result<Vec<u8>, PluginError> {
+        let mut hash = leaf.to_vec();
+        for (layer, sibling) in self.siblings.iter().enumerate() {
+            hash = match (self.leaf_index >> layer) & 1 {
+                0 => plugin.hash_node(&hash, sibling)?,
+                _ => plugin.hash_node(sibling, &hash)?,
+            };
+        }
+        plugin.check_length(&hash)?;
+        Ok(hash)
+    }
+
+    /// returns true if the leaf value and the proof hash up to the given root
+    pub fn verify(&self, plugin: &HasherPlugin, root: &[u8], leaf: &[u8]) -> bool {
+        self.siblings.len() < MAX_DEPTH
+            && self.leaf_index >> self.siblings.len() == 0
+            && self
+                .compute_root(plugin, leaf)
+                .is_ok_and(|hash| hash == root)
+    }
+}
+
+/// A binary Merkle tree hashing its nodes with a plugin
+/// The tree is laid out like `MerkleTree`, so a plugin hashing the concatenation of the children
+/// with a digest yields the roots and proofs of a `MerkleTree` with that digest.
+#[derive(Debug, Clone)]
+pub struct PluginMerkleTree {
+    plugin: Arc<HasherPlugin>,
+    depth: usize,
+    /// all nodes layer by layer starting at the root, `output_size` bytes each
+    nodes: Vec<u8>,
+}
+
+impl PluginMerkleTree {
+    /// creates a new tree of the given depth with all leaves set to the initial value
+    pub fn new(
+        plugin: Arc<HasherPlugin>,
+        depth: usize,
+        initial_value: &[u8],
+    ) -> Result<Self, PluginError> {
+        if depth == 0 || depth > MAX_DEPTH {
+            return Err(PluginError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            });
+        }
+        Self::build::<&[u8]>(plugin, depth, &[], initial_value)
+    }
+
+    /// creates a new tree from the given leaves, padded with the padding value to the next power of
+    /// two like `MerkleTree::from_leaves`
+    pub fn from_leaves<T: AsRef<[u8]>>(
+        plugin: Arc<HasherPlugin>,
+        leaves: &[T],
+        padding: &[u8],
+    ) -> Result<Self, PluginError> {
+        let depth = leaves.len().max(1).next_power_of_two().trailing_zeros() as usize + 1;
+        Self::build(plugin, depth, leaves, padding)
+    }
+
+    /// creates a new tree from raw leaf payloads hashed with the plugin
+    pub fn from_leaf_data<T: AsRef<[u8]>>(
+        plugin: Arc<HasherPlugin>,
+        data: &[T],
+        padding: &[u8],
+    ) -> Result<Self, PluginError> {
+        let leaves = data
+            .iter()
+            .map(|item| plugin.hash_leaf(item.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::from_leaves(plugin, &leaves, padding)
+    }
+
+    /// returns the plugin hashing the nodes
+    pub fn plugin(&self) -> &Arc<HasherPlugin> {
+        &self.plugin
+    }
+
+    /// returns the root hash of the tree
+    pub fn root_hash(&self) -> &[u8] {
+        self.node(0)
+    }
+
+    /// returns the number of leaves in the tree
+    pub fn num_leaves(&self) -> usize {
+        1 << (self.depth - 1)
+    }
+
+    /// updates the value of a leaf node
+    pub fn set(&mut self, offset: usize, value: &[u8]) -> Result<(), PluginError> {
+        self.check_offset(offset)?;
+        self.plugin.check_length(value)?;
+        let mut index = Self::index(self.depth - 1, offset);
+        self.node_mut(index).copy_from_slice(value);
+        while index > 0 {
+            index = (index - 1) / 2;
+            self.update_node(index)?;
+        }
+        Ok(())
+    }
+
+    /// creates a proof for a leaf node
+    pub fn create_proof(&self, offset: usize) -> Result<PluginProof, PluginError> {
+        self.check_offset(offset)?;
+        let siblings = (1..self.depth)
+            .rev()
+            .map(|layer| {
+                let sibling = (offset >> (self.depth - 1 - layer)) ^ 1;
+                self.node(Self::index(layer, sibling)).to_vec()
+            })
+            .collect();
+        Ok(PluginProof::new(offset, siblings))
+    }
+
+    fn build<T: AsRef<[u8]>>(
+        plugin: Arc<HasherPlugin>,
+        depth: usize,
+        leaves: &[T],
+        padding: &[u8],
+    ) -> Result<Self, PluginError> {
+        for leaf in leaves.iter().map(AsRef::as_ref).chain([padding]) {
+            plugin.check_length(leaf)?;
+        }
+        let num_nodes = (1 << depth) - 1;
+        let mut tree = Self {
+            nodes: padding.repeat(num_nodes),
+            plugin,
+            depth,
+        };
+        let first_leaf = Self::index(depth - 1, 0);
+        for (offset, leaf) in leaves.iter().enumerate() {
+            tree.node_mut(first_leaf + offset)
+                .copy_from_slice(leaf.as_ref());
+        }
+        for index in (0..first_leaf).rev() {
+            tree.update_node(index)?;
+        }
+        Ok(tree)
+    }
+
+    /// recomputes an intermediate node from its children
+    fn update_node(&mut self, index: usize) -> Result<(), PluginError> {
+        let size = self.plugin.output_size();
+        let (parent, children) = self.nodes.split_at_mut((2 * index + 1) * size);
+        let (left, right) = children[..2 * size].split_at(size);
+        let out = &mut parent[index * size..(index + 1) * size];
+        self.plugin.hash_node_into(left, right, out)
+    }
+
+    fn node(&self, index: usize) -> &[u8] {
+        let size = self.plugin.output_size();
+        &self.nodes[index * size..(index + 1) * size]
+    }
+
+    fn node_mut(&mut self, index: usize) -> &mut [u8] {
+        let size = self.plugin.output_size();
+        &mut self.nodes[index * size..(index + 1) * size]
+    }
+
+    fn index(layer: usize, offset: usize) -> usize {
+        (1 << layer) - 1 + offset
+    }
+
+    fn check_offset(&self, offset: usize) -> Result<(), PluginError> {
+        if offset >= self.num_leaves() {
+            return Err(PluginError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.num_leaves(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::{Digest, Sha3_256};
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+
+    unsafe extern "C" fn hash_leaf(
+        _context: *mut c_void,
+        data: *const u8,
+        len: usize,
+        out: *mut u8,
+    ) -> c_int {
+        let hash = Sha3_256::digest(std::slice::from_raw_parts(data, len));
+        out.copy_from_nonoverlapping(hash.as_ptr(), 32);
+        0
+    }
+
+    unsafe extern "C" fn hash_node(
+        context: *mut c_void,
+        left: *const u8,
+        right: *const u8,
+        out: *mut u8,
+    ) -> c_int {
+        // the context counts the calls and fails after the configured number
+        let budget = &mut *(context as *mut usize);
+        if *budget == 0 {
+            return 42;
+        }
+        *budget -= 1;
+        let mut hasher = Sha3_256::new();
+        hasher.update(std::slice::from_raw_parts(left, 32));
+        hasher.update(std::slice::from_raw_parts(right, 32));
+        out.copy_from_nonoverlapping(hasher.finalize().as_ptr(), 32);
+        0
+    }
+
+    unsafe extern "C" fn release(context: *mut c_void) {
+        drop(Box::from_raw(context as *mut usize));
+    }
+
+    fn plugin(budget: usize) -> Arc<HasherPlugin> {
+        let vtable = HasherVTable {
+            abi_version: PLUGIN_ABI_VERSION,
+            output_size: 32,
+            context: Box::into_raw(Box::new(budget)) as *mut c_void,
+            hash_leaf,
+            hash_node,
+            release: Some(release),
+        };
+        Arc::new(unsafe { HasherPlugin::from_vtable(vtable) }.unwrap())
+    }
+
+    #[test]
+    fn test_matches_merkle_tree() {
+        let plugin = plugin(usize::MAX);
+        let data = [b"a".as_slice(), b"b", b"c", b"d", b"e"];
+        let mut tree = PluginMerkleTree::from_leaf_data(plugin.clone(), &data, &[0; 32]).unwrap();
+        let mut reference = MerkleTree::from_leaf_data(&data, &[0; 32].into());
+        assert_eq!(tree.root_hash(), reference.root_hash().as_slice());
+
+        tree.set(6, &[7; 32]).unwrap();
+        reference.set(6, &[7; 32].into());
+        assert_eq!(tree.root_hash(), reference.root_hash().as_slice());
+        for offset in 0..tree.num_leaves() {
+            let proof = tree.create_proof(offset).unwrap();
+            let expected = reference.create_proof(offset);
+            assert_eq!(proof.leaf_index(), expected.leaf_index());
+            assert!(proof
+                .siblings()
+                .iter()
+                .eq(expected.siblings().iter().map(|s| s.as_slice())));
+        }
+        let proof = tree.create_proof(6).unwrap();
+        assert!(proof.verify(&plugin, tree.root_hash(), &[7; 32]));
+        assert!(!proof.verify(&plugin, tree.root_hash(), &[8; 32]));
+
+        assert!(matches!(
+            tree.set(8, &[0; 32]),
+            Err(PluginError::LeafIndexOutOfBounds { .. })
+        ));
+        assert!(matches!(
+            tree.set(0, &[0; 31]),
+            Err(PluginError::InvalidHashLength { .. })
+        ));
+        assert!(PluginMerkleTree::new(plugin.clone(), 0, &[0; 32]).is_err());
+        assert_eq!(
+            PluginMerkleTree::new(plugin, 4, &[0; 32])
+                .unwrap()
+                .root_hash(),
+            crate::MerkleTree::<Sha3_256>::new(4, &[0; 32].into())
+                .root_hash()
+                .as_slice()
+        );
+    }
+
+    #[test]
+    fn test_plugin_errors() {
+        let tree = PluginMerkleTree::from_leaves(plugin(2), &[[1; 32]; 4], &[0; 32]);
+        assert_eq!(tree.unwrap_err(), PluginError::HashFailed { code: 42 });
+
+        let vtable = HasherVTable {
+            abi_version: PLUGIN_ABI_VERSION + 1,
+            output_size: 32,
+            context: std::ptr::null_mut(),
+            hash_leaf,
+            hash_node,
+            release: None,
+        };
+        assert_eq!(
+            unsafe { HasherPlugin::from_vtable(vtable) }.unwrap_err(),
+            PluginError::AbiVersionMismatch {
+                expected: PLUGIN_ABI_VERSION,
+                actual: PLUGIN_ABI_VERSION + 1
+            }
+        );
+
+        let err = unsafe { HasherPlugin::load("/nonexistent/libhasher.so") }.unwrap_err();
+        assert!(matches!(err, PluginError::Load { .. }));
+    }
+}
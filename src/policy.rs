@@ -0,0 +1,321 @@
+//! Verification policies for evidence bundles
+//!
+//! A `PolicyVerifier` bundles the checks every integrator needs on top of the cryptographic
+//! verification of an `EvidenceBundle`: which tree parameters are accepted, which signers are
+//! trusted, which roots were revoked and how old the latest tree head may be.
+
+use std::collections::HashSet;
+use std::fmt::{self, Debug};
+use std::marker::PhantomData;
+use std::time::{Duration, SystemTime};
+
+use digest::{Digest, Output};
+
+use crate::custody::{parameter_fingerprint, SthVerifier};
+use crate::scheme::{HashScheme, Rfc6962};
+use crate::{EvidenceBundle, EvidenceError};
+
+/// The reason an evidence bundle was rejected by a policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyError {
+    /// the bundle was created with parameters that are not accepted
+    FingerprintNotAccepted,
+    /// a tree head is not signed by a trusted signer
+    UntrustedSigner,
+    /// a tree head of the bundle has a revoked root
+    RevokedRoot {
+        /// hex encoded revoked root
+        root: String,
+    },
+    /// the latest tree head of the bundle is older than the maximum age
+    RootTooOld {
+        /// age of the latest tree head
+        age: Duration,
+        /// maximum accepted age
+        max_age: Duration,
+    },
+    /// the bundle failed cryptographic verification
+    Evidence(EvidenceError),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::FingerprintNotAccepted => {
+                write!(f, "tree parameters are not accepted by the policy")
+            }
+            PolicyError::UntrustedSigner => {
+                write!(f, "tree head is not signed by a trusted signer")
+            }
+            PolicyError::RevokedRoot { root } => write!(f, "root {root} has been revoked"),
+            PolicyError::RootTooOld { age, max_age } => write!(
+                f,
+                "latest tree head is {}s old, at most {}s are accepted",
+                age.as_secs(),
+                max_age.as_secs()
+            ),
+            PolicyError::Evidence(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PolicyError::Evidence(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Evaluates evidence bundles against a verification policy
+pub struct PolicyVerifier<D: Digest, S = Rfc6962> {
+    /// accepted parameter fingerprints
+    fingerprints: Vec<Output<D>>,
+    /// trusted signers, a tree head is accepted if any of them verifies its signature
+    signers: Vec<Box<dyn SthVerifier>>,
+    /// revoked roots
+    revoked: HashSet<Output<D>>,
+    /// maximum age of the latest tree head
+    max_age: Option<Duration>,
+    scheme: PhantomData<S>,
+}
+
+impl<D, S> Default for PolicyVerifier<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D, S> PolicyVerifier<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a policy accepting the parameters of `D` and `S`, without trusted signers,
+    /// revoked roots or maximum age
+    pub fn new() -> Self {
+        Self {
+            fingerprints: vec![parameter_fingerprint::<D, S>()],
+            signers: Vec::new(),
+            revoked: HashSet::new(),
+            max_age: None,
+            scheme: PhantomData,
+        }
+    }
+
+    /// replaces the accepted parameter fingerprints
+    /// The proofs of a bundle are always verified with `D` and `S`, the fingerprints only decide
+    /// which parameter labels are accepted, e.g. to keep accepting bundles of a deployment that
+    /// recorded another fingerprint for the same parameters.
+    pub fn with_accepted_fingerprints(
+        mut self,
+        fingerprints: impl IntoIterator<Item = Output<D>>,
+    ) -> Self {
+        self.fingerprints = fingerprints.into_iter().collect();
+        self
+    }
+
+    /// adds a trusted signer
+    pub fn with_trusted_signer(mut self, signer: impl SthVerifier + 'static) -> Self {
+        self.signers.push(Box::new(signer));
+        self
+    }
+
+    /// sets the maximum age of the latest tree head of a bundle
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// revokes a compromised root, bundles referencing it are rejected from now on
+    pub fn revoke_root(&mut self, root: &Output<D>) {
+        self.revoked.insert(*root);
+    }
+
+    /// returns true if the root was revoked
+    pub fn is_revoked(&self, root: &Output<D>) -> bool {
+        self.revoked.contains(root)
+    }
+
+    /// evaluates the policy for a bundle at the current time
+    pub fn verify(&self, bundle: &EvidenceBundle<D, S>) -> Result<(), PolicyError> {
+        self.verify_at(bundle, SystemTime::now())
+    }
+
+    /// evaluates the policy for a bundle at the given time
+    pub fn verify_at(
+        &self,
+        bundle: &EvidenceBundle<D, S>,
+        now: SystemTime,
+    ) -> Result<(), PolicyError> {
+        if !self.fingerprints.contains(&bundle.fingerprint) {
+            return Err(PolicyError::FingerprintNotAccepted);
+        }
+        for head in [&bundle.tree_head, &bundle.latest_head] {
+            if self.is_revoked(&head.root) {
+                return Err(PolicyError::RevokedRoot {
+                    root: hex::encode(head.root),
+                });
+            }
+        }
+        if let Some(max_age) = self.max_age {
+            // heads from the future count as fresh
+            let age = now
+                .duration_since(bundle.latest_head.published_at())
+                .unwrap_or_default();
+            if age > max_age {
+                return Err(PolicyError::RootTooOld { age, max_age });
+            }
+        }
+        // the fingerprint was checked against the accepted ones above
+        bundle.verify_contents(self).map_err(|err| match err {
+            EvidenceError::InvalidSignature => PolicyError::UntrustedSigner,
+            err => PolicyError::Evidence(err),
+        })
+    }
+}
+
+impl<D: Digest, S> SthVerifier for PolicyVerifier<D, S> {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        self.signers
+            .iter()
+            .any(|signer| signer.verify(message, signature))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha2::Sha256;
+
+    use crate::custody::SthSigner;
+    use crate::Plain;
+
+    type CustodyLog = crate::CustodyLog<Sha256>;
+    type PolicyVerifier = super::PolicyVerifier<Sha256>;
+
+    /// keyed hash standing in for a real signature scheme
+    #[derive(Clone, Copy)]
+    struct TestKey(&'static [u8]);
+
+    impl SthSigner for TestKey {
+        fn sign(&self, message: &[u8]) -> Vec<u8> {
+            Sha256::new()
+                .chain_update(self.0)
+                .chain_update(message)
+                .finalize()
+                .to_vec()
+        }
+    }
+
+    impl SthVerifier for TestKey {
+        fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+            self.sign(message) == signature
+        }
+    }
+
+    fn published_at() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    fn test_bundle(key: TestKey) -> EvidenceBundle<Sha256> {
+        let mut log = CustodyLog::new();
+        for i in 0..4u32 {
+            log.append(&i.to_le_bytes(), b"salt");
+        }
+        log.publish_head_at(&key, published_at());
+        log.export_evidence(1).unwrap()
+    }
+
+    #[test]
+    fn test_trusted_signers() {
+        let bundle = test_bundle(TestKey(b"log key"));
+        let now = published_at();
+
+        let policy = PolicyVerifier::new();
+        assert_eq!(
+            policy.verify_at(&bundle, now),
+            Err(PolicyError::UntrustedSigner)
+        );
+
+        let policy = PolicyVerifier::new()
+            .with_trusted_signer(TestKey(b"other key"))
+            .with_trusted_signer(TestKey(b"log key"));
+        assert_eq!(policy.verify_at(&bundle, now), Ok(()));
+    }
+
+    #[test]
+    fn test_fingerprints_and_revocation() {
+        let bundle = test_bundle(TestKey(b"log key"));
+        let now = published_at();
+
+        let policy = PolicyVerifier::new()
+            .with_trusted_signer(TestKey(b"log key"))
+            .with_accepted_fingerprints([parameter_fingerprint::<Sha256, Plain>()]);
+        assert_eq!(
+            policy.verify_at(&bundle, now),
+            Err(PolicyError::FingerprintNotAccepted)
+        );
+
+        // an accepted fingerprint other than the one of `D` and `S` passes
+        let mut relabeled = bundle.clone();
+        relabeled.fingerprint = parameter_fingerprint::<Sha256, Plain>();
+        assert_eq!(policy.verify_at(&relabeled, now), Ok(()));
+        let policy = PolicyVerifier::new().with_trusted_signer(TestKey(b"log key"));
+        assert_eq!(
+            policy.verify_at(&relabeled, now),
+            Err(PolicyError::FingerprintNotAccepted)
+        );
+        assert_eq!(
+            relabeled.verify(&TestKey(b"log key")),
+            Err(EvidenceError::FingerprintMismatch)
+        );
+
+        let mut policy = PolicyVerifier::new().with_trusted_signer(TestKey(b"log key"));
+        policy.revoke_root(&bundle.latest_head.root);
+        assert!(policy.is_revoked(&bundle.latest_head.root));
+        assert_eq!(
+            policy.verify_at(&bundle, now),
+            Err(PolicyError::RevokedRoot {
+                root: hex::encode(bundle.latest_head.root)
+            })
+        );
+
+        let mut tampered = bundle.clone();
+        tampered.payload = b"tampered".to_vec();
+        let policy = PolicyVerifier::new().with_trusted_signer(TestKey(b"log key"));
+        assert_eq!(
+            policy.verify_at(&tampered, now),
+            Err(PolicyError::Evidence(EvidenceError::InvalidInclusionProof))
+        );
+    }
+
+    #[test]
+    fn test_max_age() {
+        let bundle = test_bundle(TestKey(b"log key"));
+        let policy = PolicyVerifier::new()
+            .with_trusted_signer(TestKey(b"log key"))
+            .with_max_age(Duration::from_secs(60));
+
+        assert_eq!(policy.verify_at(&bundle, published_at()), Ok(()));
+        assert_eq!(
+            policy.verify_at(&bundle, published_at() + Duration::from_secs(60)),
+            Ok(())
+        );
+        assert_eq!(
+            policy.verify_at(&bundle, published_at() + Duration::from_secs(61)),
+            Err(PolicyError::RootTooOld {
+                age: Duration::from_secs(61),
+                max_age: Duration::from_secs(60)
+            })
+        );
+    }
+}
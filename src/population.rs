@@ -0,0 +1,177 @@
+//! Occupancy queries counting the leaves that differ from the padding value
+//!
+//! The counters of all intermediate nodes are built on the first query and kept up to date by `set`
+//! afterwards, so trees that are never queried don't pay for them. Growing the tree drops the
+//! counters, they are rebuilt on the next query. Without the `std` feature the counters are kept in
+//! a `OnceCell`, which makes `MerkleTree` `Send` but not `Sync`.
+
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+use core::cell::OnceCell as OnceLock;
+use core::fmt::Debug;
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError};
+
+/// Number of non-default leaves below every intermediate node, in breadth-first order
+#[derive(Debug, Default)]
+pub(crate) struct Population(OnceLock<Vec<usize>>);
+
+impl Population {
+    /// adjusts the counters of all ancestors of a leaf that became non-default (`added`) or default
+    pub(crate) fn update(&mut self, depth: usize, offset: usize, added: bool) {
+        let Some(counts) = self.0.get_mut() else {
+            return;
+        };
+        let mut offset = offset;
+        for layer in (0..depth.saturating_sub(1)).rev() {
+            offset /= 2;
+            let count = &mut counts[(1 << layer) - 1 + offset];
+            if added {
+                *count += 1;
+            } else {
+                *count -= 1;
+            }
+        }
+    }
+
+    /// drops the counters, they are rebuilt on the next query
+    pub(crate) fn reset(&mut self) {
+        self.0 = OnceLock::new();
+    }
+}
+
+impl<D, S> MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// returns true if the leaf at the given offset differs from the padding value
+    /// panics if the offset is out of bounds, see `try_has_non_default_leaf` for a fallible version
+    pub fn has_non_default_leaf(&self, offset: usize) -> bool {
+        match self.try_has_non_default_leaf(offset) {
+            Ok(found) => found,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// returns true if the leaf at the given offset differs from the padding value
+    /// returns an error if the offset is out of bounds
+    pub fn try_has_non_default_leaf(&self, offset: usize) -> Result<bool, MerkleTreeError> {
+        if offset >= self.num_leaves() {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.num_leaves(),
+            });
+        }
+        Ok(self.node(Self::index(self.depth() - 1, offset)) != self.padding())
+    }
+
+    /// returns the number of leaves differing from the padding value below the node at the given
+    /// layer and offset, layer 0 holds the root
+    /// panics if there is no such node, see `try_non_default_count_in_subtree` for a fallible version
+    pub fn non_default_count_in_subtree(&self, layer: usize, offset: usize) -> usize {
+        match self.try_non_default_count_in_subtree(layer, offset) {
+            Ok(count) => count,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// returns the number of leaves differing from the padding value below the node at the given
+    /// layer and offset, layer 0 holds the root
+    /// returns an error if the tree has no such layer or the offset is out of bounds
+    pub fn try_non_default_count_in_subtree(
+        &self,
+        layer: usize,
+        offset: usize,
+    ) -> Result<usize, MerkleTreeError> {
+        if layer >= self.depth() {
+            return Err(MerkleTreeError::LayerOutOfRange {
+                layer,
+                depth: self.depth(),
+            });
+        }
+        if offset >= 1 << layer {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: 1 << layer,
+            });
+        }
+        if layer == self.depth() - 1 {
+            return self.try_has_non_default_leaf(offset).map(usize::from);
+        }
+        let counts = self.population().0.get_or_init(|| self.count_population());
+        Ok(counts[Self::index(layer, offset)])
+    }
+
+    /// counts the non-default leaves below every intermediate node bottom-up
+    fn count_population(&self) -> Vec<usize> {
+        let leaf_layer = self.depth() - 1;
+        let mut counts = vec![0; Self::index(leaf_layer, 0)];
+        for layer in (0..leaf_layer).rev() {
+            for offset in 0..1 << layer {
+                let children = [2 * offset, 2 * offset + 1];
+                counts[Self::index(layer, offset)] = if layer + 1 == leaf_layer {
+                    children
+                        .iter()
+                        .filter(|&&child| {
+                            self.node(Self::index(leaf_layer, child)) != self.padding()
+                        })
+                        .count()
+                } else {
+                    children
+                        .iter()
+                        .map(|&child| counts[Self::index(layer + 1, child)])
+                        .sum()
+                };
+            }
+        }
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha3::{digest::Output, Sha3_256};
+
+    type MerkleTree = super::MerkleTree<Sha3_256>;
+
+    #[test]
+    fn test_population() {
+        let padding: Output<Sha3_256> = [0x00; 32].into();
+        let leaves: Vec<Output<Sha3_256>> = (1..=5).map(|i| [i as u8; 32].into()).collect();
+        let mut tree = MerkleTree::from_leaves(&leaves, &padding);
+        assert_eq!(tree.non_default_count_in_subtree(0, 0), 5);
+        assert_eq!(tree.non_default_count_in_subtree(1, 0), 4);
+        assert_eq!(tree.non_default_count_in_subtree(1, 1), 1);
+        assert!(tree.has_non_default_leaf(4));
+        assert!(!tree.has_non_default_leaf(5));
+        assert_eq!(tree.non_default_count_in_subtree(3, 5), 0);
+
+        // updates keep the counters in sync
+        tree.set(1, &padding);
+        tree.set(6, &[0xff; 32].into());
+        tree.set(6, &[0xfe; 32].into());
+        assert_eq!(tree.non_default_count_in_subtree(0, 0), 5);
+        assert_eq!(tree.non_default_count_in_subtree(2, 0), 1);
+        assert_eq!(tree.non_default_count_in_subtree(2, 3), 1);
+
+        // growing the tree rebuilds them, the pushes fill leaves 5 to 8
+        for _ in 0..4 {
+            tree.push(&[0xaa; 32].into());
+        }
+        assert_eq!(tree.num_leaves(), 16);
+        assert_eq!(tree.non_default_count_in_subtree(0, 0), 8);
+        assert_eq!(tree.non_default_count_in_subtree(1, 0), 7);
+        assert_eq!(tree.non_default_count_in_subtree(1, 1), 1);
+
+        assert!(tree.try_non_default_count_in_subtree(5, 0).is_err());
+        assert!(tree.try_non_default_count_in_subtree(1, 2).is_err());
+        assert!(tree.try_has_non_default_leaf(16).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+//! Poseidon over the BN254 scalar field
+//!
+//! `PoseidonBn254` combines two field elements with the circom parameters of Poseidon (width 3),
+//! like the Merkle trees of circomlib, Semaphore and most zk rollups, so the roots and proofs of a
+//! `PoseidonMerkleTree` can be checked inside circuits. Nodes are BN254 scalar field elements,
+//! `node_from_bytes` and `node_to_bytes` convert them from and to their 32 byte big endian form.
+
+use std::cell::RefCell;
+
+use ark_ff::{BigInteger, PrimeField};
+use light_poseidon::{Poseidon, PoseidonError, PoseidonHasher};
+
+use crate::node_hasher::{HasherMerkleTree, HasherProof, NodeHasher};
+
+pub use ark_bn254::Fr;
+
+/// A Merkle tree hashing its nodes with Poseidon
+pub type PoseidonMerkleTree = HasherMerkleTree<PoseidonBn254>;
+
+/// An inclusion proof of a `PoseidonMerkleTree`
+pub type PoseidonProof = HasherProof<PoseidonBn254>;
+
+thread_local! {
+    /// the sponge of every thread, creating it parses the round constants
+    static POSEIDON: RefCell<Poseidon<Fr>> =
+        RefCell::new(Poseidon::<Fr>::new_circom(2).expect("circom parameters exist for 2 inputs"));
+}
+
+/// Poseidon with the circom parameters for two inputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoseidonBn254;
+
+impl NodeHasher for PoseidonBn254 {
+    type Node = Fr;
+
+    fn hash_pair(left: &Fr, right: &Fr) -> Fr {
+        POSEIDON.with(|poseidon| {
+            poseidon
+                .borrow_mut()
+                .hash(&[*left, *right])
+                .expect("the sponge takes two inputs")
+        })
+    }
+}
+
+/// returns the field element of its big endian encoding
+/// returns an error if the value is not smaller than the modulus of the field
+pub fn node_from_bytes(bytes: &[u8; 32]) -> Result<Fr, PoseidonError> {
+    light_poseidon::bytes_to_prime_field_element_be(bytes)
+}
+
+/// returns the big endian encoding of a field element
+pub fn node_to_bytes(node: &Fr) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(&node.into_bigint().to_bytes_be());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poseidon() {
+        // poseidon([1, 2]) of circomlib
+        let expected = "115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a";
+        let node = PoseidonBn254::hash_pair(&Fr::from(1u64), &Fr::from(2u64));
+        assert_eq!(hex::encode(node_to_bytes(&node)), expected);
+        let mut bytes = [0; 32];
+        hex::decode_to_slice(expected, &mut bytes).unwrap();
+        assert_eq!(node_from_bytes(&bytes), Ok(node));
+        assert!(node_from_bytes(&[0xff; 32]).is_err());
+
+        let leaves: Vec<Fr> = (1..=3u64).map(Fr::from).collect();
+        let tree = PoseidonMerkleTree::from_leaves(&leaves, &Fr::from(0u64));
+        assert_eq!(
+            *tree.root_hash(),
+            PoseidonBn254::hash_pair(
+                &node,
+                &PoseidonBn254::hash_pair(&leaves[2], &Fr::from(0u64))
+            )
+        );
+        for (offset, leaf) in leaves.iter().enumerate() {
+            assert!(tree.create_proof(offset).verify(tree.root_hash(), leaf));
+        }
+        assert!(!tree.create_proof(0).verify(tree.root_hash(), &leaves[1]));
+    }
+}
@@ -0,0 +1,284 @@
+//! Trees that only store their leaves
+//!
+//! A `MerkleTree` stores all `2n - 1` nodes of a tree with `n` leaves. A `PrunedMerkleTree` stores
+//! the leaf layer and optionally the top `K` layers as a cache, all other nodes are recomputed from
+//! the leaves when they are needed. This halves the memory of a tree at the cost of hashing: a node
+//! below the cache is recomputed from all leaves of its subtree, so a proof without cache hashes
+//! the whole tree once.
+//!
+//! With `K` cached layers `set` recomputes the subtree below the lowest cached node on the path of
+//! the leaf (`n / 2^(K-1)` leaves) and the cached path above it, `root_hash` is a lookup. Without
+//! cache `set` only writes the leaf and `root_hash` hashes the whole tree. Roots and proofs are the
+//! same as the ones of a `MerkleTree` over the same leaves.
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError, Plain, Proof};
+
+/// A Merkle tree storing its leaves and a cache of its top layers
+pub struct PrunedMerkleTree<D: Digest, S = Plain> {
+    depth: usize,
+    /// all leaves including the padded ones
+    leaves: Vec<Output<D>>,
+    /// the nodes of the cached top layers in the order of `MerkleTree`, the root first
+    cache: Vec<Output<D>>,
+    /// the number of cached layers, at most `depth - 1`
+    cached_layers: usize,
+    scheme: PhantomData<fn() -> S>,
+}
+
+impl<D, S> PrunedMerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a new tree from the given leaves padded to the next power of two like
+    /// `MerkleTree::from_leaves`, caching up to `cached_layers` top layers
+    pub fn from_leaves(leaves: &[Output<D>], padding: &Output<D>, cached_layers: usize) -> Self {
+        let depth = match leaves.len() {
+            0 => 0,
+            len => len.next_power_of_two().trailing_zeros() as usize + 1,
+        };
+        let mut all = Vec::with_capacity((1 << depth) >> 1);
+        all.extend_from_slice(leaves);
+        all.resize((1 << depth) >> 1, *padding);
+        Self::with_cache(depth, all, cached_layers)
+    }
+
+    /// creates a new tree holding the leaves of the given tree, caching up to `cached_layers` top
+    /// layers
+    pub fn from_tree(tree: &MerkleTree<D, S>, cached_layers: usize) -> Self {
+        let cached_layers = cached_layers.min(tree.depth().saturating_sub(1));
+        let mut cache = Vec::with_capacity((1 << cached_layers) - 1);
+        for layer in 0..cached_layers {
+            cache.extend_from_slice(tree.iter_layer(layer).as_slice());
+        }
+        Self {
+            depth: tree.depth(),
+            leaves: tree.leaves().as_slice().to_vec(),
+            cache,
+            cached_layers,
+            scheme: PhantomData,
+        }
+    }
+
+    /// returns the root hash of the tree, computed from the leaves if no layer is cached
+    pub fn root_hash(&self) -> Output<D> {
+        match self.depth {
+            0 => S::empty_root(),
+            _ => self.node(0, 0),
+        }
+    }
+
+    /// returns the number of leaves in the tree
+    pub fn num_leaves(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// returns true if the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.depth == 0
+    }
+
+    /// returns the depth of the tree, the number of layers including the root and the leaves
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// returns the number of cached top layers
+    pub fn cached_layers(&self) -> usize {
+        self.cached_layers
+    }
+
+    /// returns the number of stored nodes, the leaves and the cached nodes
+    pub fn num_nodes(&self) -> usize {
+        self.leaves.len() + self.cache.len()
+    }
+
+    /// returns the value of a leaf node, None if the offset is out of bounds
+    pub fn get(&self, offset: usize) -> Option<&Output<D>> {
+        self.leaves.get(offset)
+    }
+
+    /// returns an iterator over all leaves including the unused ones
+    pub fn leaves(&self) -> core::slice::Iter<'_, Output<D>> {
+        self.leaves.iter()
+    }
+
+    /// updates the value of a leaf node
+    /// panics if the offset is out of bounds, see `try_set` for a fallible version
+    pub fn set(&mut self, offset: usize, value: &Output<D>) {
+        if let Err(err) = self.try_set(offset, value) {
+            panic!("{err}");
+        }
+    }
+
+    /// updates the value of a leaf node and the cached nodes on its path
+    /// returns an error if the offset is out of bounds
+    pub fn try_set(&mut self, offset: usize, value: &Output<D>) -> Result<(), MerkleTreeError> {
+        self.check_offset(offset)?;
+        self.leaves[offset] = *value;
+        if self.cached_layers == 0 {
+            return Ok(());
+        }
+        let lowest = self.cached_layers - 1;
+        let mut current = offset >> (self.depth - 1 - lowest);
+        self.cache[MerkleTree::<D, S>::index(lowest, current)] = self.compute(lowest, current);
+        for layer in (0..lowest).rev() {
+            current /= 2;
+            let left = self.cache[MerkleTree::<D, S>::index(layer + 1, current * 2)];
+            let right = self.cache[MerkleTree::<D, S>::index(layer + 1, current * 2 + 1)];
+            self.cache[MerkleTree::<D, S>::index(layer, current)] = S::hash_node(&left, &right);
+        }
+        Ok(())
+    }
+
+    /// Create a proof for a leaf node
+    /// panics if the offset is out of bounds, see `try_create_proof` for a fallible version
+    pub fn create_proof(&self, offset: usize) -> Proof<D, S> {
+        match self.try_create_proof(offset) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Create a proof for a leaf node, recomputing the siblings below the cache
+    /// returns an error if the offset is out of bounds
+    pub fn try_create_proof(&self, offset: usize) -> Result<Proof<D, S>, MerkleTreeError> {
+        self.check_offset(offset)?;
+        let siblings = (1..self.depth)
+            .rev()
+            .map(|layer| self.node(layer, (offset >> (self.depth - 1 - layer)) ^ 1))
+            .collect();
+        Ok(Proof::new(offset, siblings))
+    }
+
+    /// creates the tree from all of its leaves and fills the cache
+    fn with_cache(depth: usize, leaves: Vec<Output<D>>, cached_layers: usize) -> Self {
+        let cached_layers = cached_layers.min(depth.saturating_sub(1));
+        let mut tree = Self {
+            depth,
+            leaves,
+            cache: Vec::new(),
+            cached_layers: 0,
+            scheme: PhantomData,
+        };
+        if cached_layers == 0 {
+            return tree;
+        }
+        // compute the lowest cached layer from the leaves and the layers above from it
+        let lowest = cached_layers - 1;
+        let mut cache = alloc::vec![Output::<D>::default(); (1 << cached_layers) - 1];
+        let start = MerkleTree::<D, S>::index(lowest, 0);
+        for offset in 0..1 << lowest {
+            cache[start + offset] = tree.compute(lowest, offset);
+        }
+        for layer in (0..lowest).rev() {
+            for offset in 0..1 << layer {
+                let left = cache[MerkleTree::<D, S>::index(layer + 1, offset * 2)];
+                let right = cache[MerkleTree::<D, S>::index(layer + 1, offset * 2 + 1)];
+                cache[MerkleTree::<D, S>::index(layer, offset)] = S::hash_node(&left, &right);
+            }
+        }
+        tree.cache = cache;
+        tree.cached_layers = cached_layers;
+        tree
+    }
+
+    /// returns a node, looked up if it is stored and computed from the leaves otherwise
+    fn node(&self, layer: usize, offset: usize) -> Output<D> {
+        if layer < self.cached_layers {
+            self.cache[MerkleTree::<D, S>::index(layer, offset)]
+        } else {
+            self.compute(layer, offset)
+        }
+    }
+
+    /// computes a node from the leaves of its subtree
+    fn compute(&self, layer: usize, offset: usize) -> Output<D> {
+        let height = self.depth - 1 - layer;
+        let leaves = &self.leaves[offset << height..(offset + 1) << height];
+        if height == 0 {
+            return leaves[0];
+        }
+        let mut nodes: Vec<Output<D>> = leaves
+            .chunks_exact(2)
+            .map(|pair| S::hash_node(&pair[0], &pair[1]))
+            .collect();
+        while nodes.len() > 1 {
+            for i in 0..nodes.len() / 2 {
+                nodes[i] = S::hash_node(&nodes[2 * i], &nodes[2 * i + 1]);
+            }
+            nodes.truncate(nodes.len() / 2);
+        }
+        nodes[0]
+    }
+
+    fn check_offset(&self, offset: usize) -> Result<(), MerkleTreeError> {
+        if offset >= self.leaves.len() {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.leaves.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::verify;
+    use sha3::Sha3_256;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type PrunedMerkleTree = super::PrunedMerkleTree<Sha3_256>;
+
+    #[test]
+    fn test_pruned_tree() {
+        let leaves: Vec<Output<Sha3_256>> = (0..11).map(|i| [i as u8; 32].into()).collect();
+        let mut tree = MerkleTree::from_leaves(&leaves, &[0; 32].into());
+        let mut pruned: Vec<PrunedMerkleTree> = (0..=6)
+            .map(|cached| PrunedMerkleTree::from_leaves(&leaves, &[0; 32].into(), cached))
+            .collect();
+        pruned.push(PrunedMerkleTree::from_tree(&tree, 2));
+        assert_eq!(pruned[0].num_nodes(), 16);
+        assert_eq!(pruned[2].num_nodes(), 19);
+        // the leaf layer is never cached twice
+        assert_eq!(pruned[6].cached_layers(), 4);
+
+        for (offset, value) in [(3, 0xaa), (12, 0xbb), (0, 0xcc)] {
+            tree.set(offset, &[value; 32].into());
+            for pruned in &mut pruned {
+                pruned.set(offset, &[value; 32].into());
+            }
+        }
+        for pruned in &mut pruned {
+            assert_eq!(pruned.root_hash(), *tree.root_hash());
+            for offset in [0, 5, 12, 15] {
+                let proof = pruned.create_proof(offset);
+                assert_eq!(proof, tree.create_proof(offset));
+                assert!(verify(
+                    tree.root_hash(),
+                    pruned.get(offset).unwrap(),
+                    &proof
+                ));
+            }
+            assert!(pruned.try_set(16, &[0; 32].into()).is_err());
+            assert!(pruned.try_create_proof(16).is_err());
+        }
+
+        let empty = PrunedMerkleTree::from_leaves(&[], &[0; 32].into(), 3);
+        assert!(empty.is_empty());
+        assert_eq!(
+            empty.root_hash(),
+            *MerkleTree::empty(&[0; 32].into()).root_hash()
+        );
+    }
+}
@@ -0,0 +1,674 @@
+//! Batched publication of roots
+//!
+//! Deployments rarely publish every root: anchoring a root in a contract or pushing it to a
+//! transparency service costs money or bandwidth. A `Publisher` is told about every update of a
+//! tree and decides with its `PublicationPolicy` when the current root is published: after a
+//! number of updates, after some time with pending updates, or only on demand. Published roots are
+//! handed to all sinks in order and recorded in a `RootIndex`, the chain of published roots
+//! verifiers check roots against.
+//!
+//! A root is recorded only once all sinks accepted it. If a sink fails the updates stay pending
+//! and the next publication hands the (possibly new) root to all sinks again, so sinks have to
+//! tolerate receiving a version more than once.
+
+use std::fmt::{self, Debug};
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use digest::{Digest, Output};
+use sha3::Keccak256;
+
+use crate::RootIndex;
+
+#[cfg(feature = "ethereum")]
+pub mod ethereum;
+
+/// Errors returned by sinks
+#[derive(Debug)]
+pub enum SinkError {
+    /// writing to the file or connection failed
+    Io(io::Error),
+    /// the URL of an HTTP sink is not of the form `http://host[:port][/path]`
+    InvalidUrl { url: String },
+    /// the HTTP endpoint answered with a non-success status code
+    HttpStatus { status: u16 },
+    /// the sink rejected the root for another reason
+    Rejected { reason: String },
+    /// a JSON-RPC call of a node failed
+    Rpc { message: String },
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "I/O error: {err}"),
+            Self::InvalidUrl { url } => write!(f, "invalid HTTP URL {url}"),
+            Self::HttpStatus { status } => write!(f, "endpoint answered with status {status}"),
+            Self::Rejected { reason } => write!(f, "root was rejected: {reason}"),
+            Self::Rpc { message } => write!(f, "RPC call failed: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for SinkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SinkError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// The error returned when a sink failed to publish a root
+#[derive(Debug)]
+pub struct PublishError {
+    /// position of the failed sink in the order the sinks were added
+    pub sink: usize,
+    /// the error of the sink
+    pub error: SinkError,
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sink {} failed to publish the root: {}",
+            self.sink, self.error
+        )
+    }
+}
+
+impl std::error::Error for PublishError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// A root handed to the sinks
+#[derive(Debug, Clone)]
+pub struct Publication<D: Digest> {
+    /// the version the root is recorded as in the root index
+    pub version: u64,
+    /// the published root
+    pub root: Output<D>,
+    /// number of updates since the previous publication
+    pub updates: u64,
+    /// time of the publication
+    pub published_at: SystemTime,
+}
+
+impl<D: Digest> PartialEq for Publication<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.version == other.version
+            && self.root == other.root
+            && self.updates == other.updates
+            && self.published_at == other.published_at
+    }
+}
+
+impl<D: Digest> Eq for Publication<D> {}
+
+impl<D: Digest> Publication<D> {
+    /// returns the publication time in seconds since the Unix epoch
+    pub fn timestamp(&self) -> u64 {
+        self.published_at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs())
+    }
+
+    /// returns the publication as a JSON object with the root as hex string
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"version":{},"root":"{}","updates":{},"published_at":{}}}"#,
+            self.version,
+            hex::encode(&self.root),
+            self.updates,
+            self.timestamp()
+        )
+    }
+}
+
+/// Receives published roots
+pub trait RootSink<D: Digest> {
+    /// publishes the root, returning an error makes the publisher retry with the next publication
+    fn publish(&mut self, publication: &Publication<D>) -> Result<(), SinkError>;
+}
+
+/// Appends every publication as a line of JSON to a file
+#[derive(Debug, Clone)]
+pub struct FileSink {
+    path: PathBuf,
+}
+
+impl FileSink {
+    /// creates a sink appending to the file, the file is created on the first publication
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl<D: Digest> RootSink<D> for FileSink {
+    fn publish(&mut self, publication: &Publication<D>) -> Result<(), SinkError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", publication.to_json())?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// POSTs every publication as JSON to an HTTP endpoint
+/// Only plain `http://` URLs are supported, put a TLS terminating proxy in front of HTTPS
+/// endpoints.
+#[derive(Debug, Clone)]
+pub struct HttpSink {
+    host: String,
+    port: u16,
+    path: String,
+    timeout: Duration,
+}
+
+impl HttpSink {
+    /// default timeout of connecting, sending the request and receiving the response
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// creates a sink posting to a URL like `http://localhost:8080/roots`
+    pub fn new(url: &str) -> Result<Self, SinkError> {
+        let invalid = || SinkError::InvalidUrl {
+            url: url.to_owned(),
+        };
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |i| rest.split_at(i));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+            timeout: Self::DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// sets the timeout of connecting, sending the request and receiving the response
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl<D: Digest> RootSink<D> for HttpSink {
+    fn publish(&mut self, publication: &Publication<D>) -> Result<(), SinkError> {
+        let body = publication.to_json();
+        let address = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no address"))?;
+        let mut stream = TcpStream::connect_timeout(&address, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.path,
+            self.host,
+            body.len(),
+        );
+        stream.write_all(request.as_bytes())?;
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        // HTTP/1.1 200 OK
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))?;
+        if !(200..300).contains(&status) {
+            return Err(SinkError::HttpStatus { status });
+        }
+        Ok(())
+    }
+}
+
+/// Encodes every publication as a call of a contract function taking the root as `bytes32`, e.g.
+/// `updateRoot(bytes32)`, and hands the calldata to a function submitting it
+/// The submission itself, signing and sending the transaction, is left to the deployment.
+pub struct ContractSink<F> {
+    selector: [u8; 4],
+    submit: F,
+}
+
+impl<F> Debug for ContractSink<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContractSink")
+            .field("selector", &hex::encode(self.selector))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F> ContractSink<F>
+where
+    F: FnMut(&[u8]) -> Result<(), SinkError>,
+{
+    /// creates a sink calling the function with the given signature, the selector is the first four
+    /// bytes of the keccak256 hash of the signature like in Solidity
+    pub fn new(signature: &str, submit: F) -> Self {
+        Self {
+            selector: selector(signature),
+            submit,
+        }
+    }
+
+    /// returns the calldata of the contract call publishing the root
+    /// returns an error if the root is longer than a `bytes32`
+    pub fn calldata(&self, root: &[u8]) -> Result<Vec<u8>, SinkError> {
+        calldata(self.selector, root)
+    }
+}
+
+/// returns the selector of a function signature like Solidity
+fn selector(signature: &str) -> [u8; 4] {
+    let mut selector = [0; 4];
+    selector.copy_from_slice(&Keccak256::digest(signature.as_bytes())[..4]);
+    selector
+}
+
+/// returns the calldata of a call passing the root as `bytes32`
+fn calldata(selector: [u8; 4], root: &[u8]) -> Result<Vec<u8>, SinkError> {
+    let mut calldata = selector.to_vec();
+    calldata.extend_from_slice(&root_word(root)?);
+    Ok(calldata)
+}
+
+/// returns the root as `bytes32`, roots shorter than a word are left aligned like `bytesN` values
+/// returns an error for roots longer than a word instead of anchoring a truncated root
+fn root_word(root: &[u8]) -> Result<[u8; 32], SinkError> {
+    if root.len() > 32 {
+        return Err(SinkError::Rejected {
+            reason: format!("a {} byte root doesn't fit into a bytes32", root.len()),
+        });
+    }
+    let mut word = [0; 32];
+    word[..root.len()].copy_from_slice(root);
+    Ok(word)
+}
+
+impl<D, F> RootSink<D> for ContractSink<F>
+where
+    D: Digest,
+    F: FnMut(&[u8]) -> Result<(), SinkError>,
+{
+    fn publish(&mut self, publication: &Publication<D>) -> Result<(), SinkError> {
+        let calldata = self.calldata(&publication.root)?;
+        (self.submit)(&calldata)
+    }
+}
+
+/// When a `Publisher` publishes the current root
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PublicationPolicy {
+    /// publish once this many updates are pending
+    pub every_updates: Option<u64>,
+    /// publish pending updates once this much time has passed since the last publication
+    pub every: Option<Duration>,
+}
+
+impl PublicationPolicy {
+    /// publishes only when `Publisher::publish` is called
+    pub fn on_demand() -> Self {
+        Self::default()
+    }
+
+    /// publishes after every `count` updates
+    pub fn every_updates(count: u64) -> Self {
+        Self {
+            every_updates: Some(count.max(1)),
+            every: None,
+        }
+    }
+
+    /// publishes pending updates once the interval has passed since the last publication
+    pub fn every(interval: Duration) -> Self {
+        Self {
+            every_updates: None,
+            every: Some(interval),
+        }
+    }
+
+    /// additionally publishes pending updates once the interval has passed
+    pub fn or_every(mut self, interval: Duration) -> Self {
+        self.every = Some(interval);
+        self
+    }
+
+    /// returns true if the pending updates are due for publication
+    fn is_due(&self, pending: u64, since_last: Duration) -> bool {
+        pending > 0
+            && (self.every_updates.is_some_and(|count| pending >= count)
+                || self.every.is_some_and(|interval| since_last >= interval))
+    }
+}
+
+/// Decides when roots are published, hands them to the sinks and records them in a root index
+pub struct Publisher<D: Digest> {
+    policy: PublicationPolicy,
+    sinks: Vec<Box<dyn RootSink<D> + Send>>,
+    index: RootIndex<D>,
+    /// updates since the last publication
+    pending: u64,
+    /// time of the last publication or of the creation of the publisher
+    last_publication: SystemTime,
+}
+
+impl<D> Debug for Publisher<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Publisher")
+            .field("policy", &self.policy)
+            .field("sinks", &self.sinks.len())
+            .field("published", &self.index.len())
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
+
+impl<D> Publisher<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// creates a publisher without sinks recording into a new root index
+    pub fn new(policy: PublicationPolicy) -> Self {
+        Self::with_index(policy, RootIndex::new())
+    }
+
+    /// creates a publisher continuing an existing root index
+    pub fn with_index(policy: PublicationPolicy, index: RootIndex<D>) -> Self {
+        Self {
+            policy,
+            sinks: Vec::new(),
+            index,
+            pending: 0,
+            last_publication: SystemTime::now(),
+        }
+    }
+
+    /// adds a sink, sinks receive the roots in the order they were added
+    pub fn with_sink(mut self, sink: impl RootSink<D> + Send + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// returns the policy deciding when roots are published
+    pub fn policy(&self) -> &PublicationPolicy {
+        &self.policy
+    }
+
+    /// returns the index of all published roots
+    pub fn index(&self) -> &RootIndex<D> {
+        &self.index
+    }
+
+    /// returns the number of updates since the last publication
+    pub fn pending(&self) -> u64 {
+        self.pending
+    }
+
+    /// records an update of the tree resulting in the given root and publishes the root if the
+    /// policy says so, returns the published version
+    pub fn record_update(&mut self, root: &Output<D>) -> Result<Option<u64>, PublishError> {
+        self.record_update_at(root, SystemTime::now())
+    }
+
+    /// records an update at the given time, see `record_update`
+    pub fn record_update_at(
+        &mut self,
+        root: &Output<D>,
+        time: SystemTime,
+    ) -> Result<Option<u64>, PublishError> {
+        self.pending += 1;
+        self.poll_at(root, time)
+    }
+
+    /// publishes the root if pending updates are due, e.g. from a timer for time-based policies,
+    /// returns the published version
+    pub fn poll(&mut self, root: &Output<D>) -> Result<Option<u64>, PublishError> {
+        self.poll_at(root, SystemTime::now())
+    }
+
+    /// checks the policy at the given time, see `poll`
+    pub fn poll_at(
+        &mut self,
+        root: &Output<D>,
+        time: SystemTime,
+    ) -> Result<Option<u64>, PublishError> {
+        let since_last = time
+            .duration_since(self.last_publication)
+            .unwrap_or_default();
+        if !self.policy.is_due(self.pending, since_last) {
+            return Ok(None);
+        }
+        self.publish_at(root, time).map(Some)
+    }
+
+    /// publishes the root regardless of the policy, returns the published version
+    pub fn publish(&mut self, root: &Output<D>) -> Result<u64, PublishError> {
+        self.publish_at(root, SystemTime::now())
+    }
+
+    /// publishes the root with the given publication time, see `publish`
+    pub fn publish_at(&mut self, root: &Output<D>, time: SystemTime) -> Result<u64, PublishError> {
+        let publication = Publication {
+            version: self.index.len() as u64,
+            root: *root,
+            updates: self.pending,
+            published_at: time,
+        };
+        for (sink, target) in self.sinks.iter_mut().enumerate() {
+            target
+                .publish(&publication)
+                .map_err(|error| PublishError { sink, error })?;
+        }
+        self.pending = 0;
+        self.last_publication = time;
+        Ok(self.index.publish(root))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use sha3::{Sha3_256, Sha3_512};
+
+    use crate::MerkleTree;
+
+    /// sink keeping the publications in memory, failing while `fail` is set
+    #[derive(Clone, Default)]
+    struct MemorySink {
+        publications: Arc<Mutex<Vec<Publication<Sha3_256>>>>,
+        fail: Arc<Mutex<bool>>,
+    }
+
+    impl RootSink<Sha3_256> for MemorySink {
+        fn publish(&mut self, publication: &Publication<Sha3_256>) -> Result<(), SinkError> {
+            if *self.fail.lock().unwrap() {
+                return Err(SinkError::Rejected {
+                    reason: "offline".to_owned(),
+                });
+            }
+            self.publications.lock().unwrap().push(publication.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_policies() {
+        let mut tree = MerkleTree::<Sha3_256>::new(4, &[0x00; 32].into());
+        let sink = MemorySink::default();
+        let mut publisher =
+            Publisher::new(PublicationPolicy::every_updates(3)).with_sink(sink.clone());
+        let start = SystemTime::now();
+        let mut published = Vec::new();
+        for i in 0..7 {
+            tree.set(i, &[i as u8; 32].into());
+            published.push(publisher.record_update_at(tree.root_hash(), start).unwrap());
+        }
+        assert_eq!(published, [None, None, Some(0), None, None, Some(1), None]);
+        assert_eq!(publisher.pending(), 1);
+        let publications = sink.publications.lock().unwrap().clone();
+        assert_eq!(publications.len(), 2);
+        assert_eq!(publications[1].updates, 3);
+        assert!(publisher.index().contains(&publications[1].root));
+
+        // a failing sink keeps the updates pending
+        *sink.fail.lock().unwrap() = true;
+        let err = publisher.publish(tree.root_hash()).unwrap_err();
+        assert_eq!(err.sink, 0);
+        assert_eq!(publisher.pending(), 1);
+        assert_eq!(publisher.index().len(), 2);
+        *sink.fail.lock().unwrap() = false;
+        assert_eq!(publisher.publish(tree.root_hash()).unwrap(), 2);
+        assert_eq!(publisher.index().version_of(tree.root_hash()), Some(2));
+
+        // time-based policies publish pending updates once the interval has passed
+        let mut publisher = Publisher::<Sha3_256>::new(
+            PublicationPolicy::every_updates(100).or_every(Duration::from_secs(60)),
+        );
+        let now = publisher.last_publication;
+        assert_eq!(
+            publisher
+                .poll_at(tree.root_hash(), now + Duration::from_secs(61))
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            publisher.record_update_at(tree.root_hash(), now).unwrap(),
+            None
+        );
+        assert_eq!(
+            publisher
+                .poll_at(tree.root_hash(), now + Duration::from_secs(61))
+                .unwrap(),
+            Some(0)
+        );
+
+        // on-demand publishers never publish by themselves
+        let mut publisher = Publisher::<Sha3_256>::new(PublicationPolicy::on_demand());
+        for _ in 0..10 {
+            assert_eq!(publisher.record_update(tree.root_hash()).unwrap(), None);
+        }
+        assert_eq!(publisher.publish(tree.root_hash()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_sinks() {
+        let root = Sha3_256::digest(b"root");
+        let publication = Publication::<Sha3_256> {
+            version: 3,
+            root,
+            updates: 2,
+            published_at: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        };
+        let json = format!(
+            r#"{{"version":3,"root":"{}","updates":2,"published_at":1700000000}}"#,
+            hex::encode(root)
+        );
+        assert_eq!(publication.to_json(), json);
+
+        let path = std::env::temp_dir().join(format!("merkle-publish-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut file = FileSink::new(&path);
+        RootSink::<Sha3_256>::publish(&mut file, &publication).unwrap();
+        RootSink::<Sha3_256>::publish(&mut file, &publication).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{json}\n{json}\n")
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut contract = ContractSink::new("updateRoot(bytes32)", {
+            let calls = Arc::clone(&calls);
+            move |calldata: &[u8]| {
+                calls.lock().unwrap().push(calldata.to_vec());
+                Ok(())
+            }
+        });
+        contract.publish(&publication).unwrap();
+        let calldata = calls.lock().unwrap()[0].clone();
+        assert_eq!(calldata.len(), 36);
+        assert_eq!(
+            calldata[..4],
+            Keccak256::digest(b"updateRoot(bytes32)")[..4]
+        );
+        assert_eq!(calldata[4..], root[..]);
+        // a root longer than a word is rejected instead of truncated
+        let long = Publication::<Sha3_512> {
+            version: 4,
+            root: Sha3_512::digest(b"root"),
+            updates: 1,
+            published_at: UNIX_EPOCH,
+        };
+        assert!(matches!(
+            contract.publish(&long),
+            Err(SinkError::Rejected { .. })
+        ));
+        assert_eq!(calls.lock().unwrap().len(), 1);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in ["200 OK", "503 Service Unavailable"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                // the body is the last part of the request and ends with the JSON object
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"}") {
+                    let len = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..len]);
+                }
+                requests.push(String::from_utf8(request).unwrap());
+                write!(stream, "HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").unwrap();
+            }
+            requests
+        });
+        let mut http = HttpSink::new(&format!("http://127.0.0.1:{port}/roots")).unwrap();
+        RootSink::<Sha3_256>::publish(&mut http, &publication).unwrap();
+        assert!(matches!(
+            RootSink::<Sha3_256>::publish(&mut http, &publication),
+            Err(SinkError::HttpStatus { status: 503 })
+        ));
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /roots HTTP/1.1\r\n"));
+        assert!(requests[0].ends_with(&json));
+
+        assert!(HttpSink::new("https://example.com").is_err());
+        assert!(HttpSink::new("http://:80/").is_err());
+        assert!(HttpSink::new("http://example.com:port/").is_err());
+    }
+}
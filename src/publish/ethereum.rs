@@ -0,0 +1,924 @@
+//! Anchoring roots in an Ethereum contract
+//!
+//! `EthereumSink` submits every publication as a transaction calling a contract function that takes
+//! the root as `bytes32`, encoded like the calldata of `ContractSink`. Transactions are signed with
+//! a local key and sent to a node over JSON-RPC with alloy, gas and fees are estimated by the node.
+//!
+//! Nonces are managed by the sink instead of asking the node for every transaction: the pending
+//! nonce of the account is fetched once and incremented with every accepted transaction. If the
+//! node rejects a transaction the nonce is fetched again before the next one. A transaction that
+//! was not confirmed in time keeps its nonce reserved: the next publication reuses it, replacing
+//! the stale root in the mempool instead of queueing behind it, unless it got mined meanwhile. Nodes
+//! only accept a replacement paying more than the pending transaction, so it raises both fees by an
+//! eighth over the replaced ones if the current estimate isn't higher anyway.
+//!
+//! A publication succeeds once its transaction is included with the configured number of
+//! confirmations, confirmed publications are recorded with their transaction hash and block.
+//!
+//! The sink is also an `Anchor`: anchoring a root returns the hash of its transaction, which
+//! resolves to the root once the transaction has the configured confirmations.
+//!
+//! On the other end `AnchorVerifier` checks receipts, a leaf with its proof and the epoch (the
+//! publication version) of the root it was proven against, against the root the contract stores for
+//! that epoch. It reads the root with a view function like `roots(uint256)` returning `bytes32`.
+
+use std::error::Error;
+use std::fmt::{self, Debug};
+use std::future::IntoFuture;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use alloy::consensus::Transaction;
+use alloy::network::{EthereumWallet, TransactionBuilder};
+use alloy::primitives::{Address, Bytes, TxHash};
+use alloy::providers::{DynProvider, Provider, ProviderBuilder};
+use alloy::rpc::types::TransactionRequest;
+use alloy::signers::local::PrivateKeySigner;
+use alloy::transports::http::reqwest::Url;
+use alloy::transports::{RpcError, TransportErrorKind};
+use digest::{Digest, Output};
+use tokio::runtime::{self, Runtime};
+
+use super::{calldata, root_word, selector, Publication, RootSink, SinkError};
+use crate::anchor::{root_from_bytes, Anchor, AnchorError, AnchorRef};
+use crate::scheme::{HashScheme, Plain};
+use crate::Proof;
+
+/// the system name of Ethereum anchor references
+pub const SYSTEM: &str = "ethereum";
+
+/// A publication included on chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Confirmation {
+    /// the version of the published root
+    pub version: u64,
+    /// the published root
+    pub root: Vec<u8>,
+    /// the nonce of the transaction
+    pub nonce: u64,
+    /// the hash of the transaction
+    pub tx_hash: TxHash,
+    /// the block the transaction was included in
+    pub block_number: u64,
+}
+
+/// a sent transaction waiting for its confirmations
+#[derive(Debug, Clone, Copy)]
+struct Submission {
+    nonce: u64,
+    tx_hash: TxHash,
+    max_fee_per_gas: u128,
+    max_priority_fee_per_gas: u128,
+}
+
+/// Publishes roots by calling a contract function, e.g. `updateRoot(bytes32)`
+pub struct EthereumSink {
+    runtime: Runtime,
+    provider: DynProvider,
+    from: Address,
+    contract: Address,
+    selector: [u8; 4],
+    confirmations: u64,
+    poll_interval: Duration,
+    timeout: Duration,
+    /// the nonce of the next transaction, None if it has to be fetched from the node
+    nonce: Option<u64>,
+    /// the last transaction if it is not confirmed yet
+    unconfirmed: Option<Submission>,
+    confirmed: Vec<Confirmation>,
+}
+
+impl Debug for EthereumSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EthereumSink")
+            .field("from", &self.from)
+            .field("contract", &self.contract)
+            .field("selector", &hex::encode(self.selector))
+            .field("confirmations", &self.confirmations)
+            .field("nonce", &self.nonce)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EthereumSink {
+    /// creates a sink sending transactions signed by the signer to the node at the RPC URL, calling
+    /// the function with the given signature of the contract
+    /// By default a transaction counts as confirmed once it is included in a block, polled every
+    /// second for at most two minutes.
+    pub fn new(
+        rpc_url: &str,
+        signer: PrivateKeySigner,
+        contract: Address,
+        signature: &str,
+    ) -> Result<Self, SinkError> {
+        let url = parse_url(rpc_url).ok_or_else(|| SinkError::InvalidUrl {
+            url: rpc_url.to_string(),
+        })?;
+        let runtime = new_runtime()?;
+        let from = signer.address();
+        let provider = ProviderBuilder::new()
+            .wallet(EthereumWallet::from(signer))
+            .connect_http(url)
+            .erased();
+        Ok(Self {
+            runtime,
+            provider,
+            from,
+            contract,
+            selector: selector(signature),
+            confirmations: 1,
+            poll_interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(120),
+            nonce: None,
+            unconfirmed: None,
+            confirmed: Vec::new(),
+        })
+    }
+
+    /// sets the number of blocks, including the one with the transaction, before a publication
+    /// succeeds, 0 returns as soon as the node accepted the transaction
+    pub fn with_confirmations(mut self, confirmations: u64) -> Self {
+        self.confirmations = confirmations;
+        self
+    }
+
+    /// sets how often the node is asked for the receipt while waiting for confirmations
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// sets how long a publication waits for its confirmations
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// returns the address sending the transactions
+    pub fn address(&self) -> Address {
+        self.from
+    }
+
+    /// returns the nonce of the next transaction if it is known without asking the node
+    pub fn next_nonce(&self) -> Option<u64> {
+        self.unconfirmed
+            .map(|submission| submission.nonce)
+            .or(self.nonce)
+    }
+
+    /// returns the hash of the last transaction if it is still waiting for its confirmations
+    pub fn unconfirmed(&self) -> Option<TxHash> {
+        self.unconfirmed.map(|submission| submission.tx_hash)
+    }
+
+    /// returns all publications confirmed on chain in order
+    pub fn confirmed(&self) -> &[Confirmation] {
+        &self.confirmed
+    }
+
+    /// returns the calldata of the contract call publishing the root
+    /// returns an error if the root is longer than a `bytes32`
+    pub fn calldata(&self, root: &[u8]) -> Result<Vec<u8>, SinkError> {
+        calldata(self.selector, root)
+    }
+
+    /// returns the nonce for the next transaction
+    fn reserve_nonce(&mut self) -> Result<u64, SinkError> {
+        if let Some(submission) = self.unconfirmed {
+            match self.receipt_block(submission.tx_hash) {
+                // still pending, the next transaction replaces it
+                Ok(None) => return Ok(submission.nonce),
+                // mined meanwhile or reverted, either way its nonce is used up
+                Ok(Some(_)) | Err(SinkError::Rejected { .. }) => self.unconfirmed = None,
+                Err(err) => return Err(err),
+            }
+        }
+        match self.nonce {
+            Some(nonce) => Ok(nonce),
+            None => {
+                let count = self.provider.get_transaction_count(self.from).pending();
+                self.runtime
+                    .block_on(count.into_future())
+                    .map_err(rpc_error)
+            }
+        }
+    }
+
+    /// sends a transaction publishing the root and waits for its confirmations, returns the
+    /// transaction and its block, None if the sink doesn't wait for confirmations
+    fn submit(&mut self, root: &[u8]) -> Result<(Submission, Option<u64>), SinkError> {
+        let input = Bytes::from(self.calldata(root)?);
+        let nonce = self.reserve_nonce()?;
+        let estimate = self
+            .runtime
+            .block_on(self.provider.estimate_eip1559_fees())
+            .map_err(rpc_error)?;
+        let (mut max_fee_per_gas, mut max_priority_fee_per_gas) =
+            (estimate.max_fee_per_gas, estimate.max_priority_fee_per_gas);
+        // still set after reserving the nonce only if the transaction is pending, the node rejects
+        // a replacement that doesn't pay more than it
+        if let Some(replaced) = self.unconfirmed {
+            max_fee_per_gas = max_fee_per_gas.max(bump_fee(replaced.max_fee_per_gas));
+            max_priority_fee_per_gas =
+                max_priority_fee_per_gas.max(bump_fee(replaced.max_priority_fee_per_gas));
+        }
+        let tx = TransactionRequest::default()
+            .with_from(self.from)
+            .with_to(self.contract)
+            .with_input(input)
+            .with_nonce(nonce)
+            .with_max_fee_per_gas(max_fee_per_gas)
+            .with_max_priority_fee_per_gas(max_priority_fee_per_gas);
+        let tx_hash = match self.runtime.block_on(self.provider.send_transaction(tx)) {
+            Ok(pending) => *pending.tx_hash(),
+            Err(err) => {
+                // the nonce may be out of sync with the node, e.g. after a transaction sent by
+                // another client of the account
+                self.nonce = None;
+                return Err(rpc_error(err));
+            }
+        };
+        self.nonce = Some(nonce + 1);
+        let submission = Submission {
+            nonce,
+            tx_hash,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+        };
+        if self.confirmations == 0 {
+            return Ok((submission, None));
+        }
+        self.unconfirmed = Some(submission);
+        let block_number = match self.wait_for_confirmations(tx_hash) {
+            Ok(block_number) => block_number,
+            Err(SinkError::Rejected { reason }) => {
+                // a reverted transaction uses up its nonce as well
+                self.unconfirmed = None;
+                return Err(SinkError::Rejected { reason });
+            }
+            Err(err) => return Err(err),
+        };
+        self.unconfirmed = None;
+        Ok((submission, Some(block_number)))
+    }
+
+    /// returns the block of a mined transaction, an error if it reverted
+    fn receipt_block(&self, tx_hash: TxHash) -> Result<Option<u64>, SinkError> {
+        let receipt = self
+            .runtime
+            .block_on(self.provider.get_transaction_receipt(tx_hash))
+            .map_err(rpc_error)?;
+        match receipt {
+            Some(receipt) if !receipt.status() => Err(SinkError::Rejected {
+                reason: format!("transaction {tx_hash} reverted"),
+            }),
+            Some(receipt) => Ok(receipt.block_number),
+            None => Ok(None),
+        }
+    }
+
+    /// polls the node until the transaction has enough confirmations, returns its block
+    fn wait_for_confirmations(&self, tx_hash: TxHash) -> Result<u64, SinkError> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some(block_number) = self.receipt_block(tx_hash)? {
+                let head = self
+                    .runtime
+                    .block_on(self.provider.get_block_number())
+                    .map_err(rpc_error)?;
+                if head + 1 >= block_number + self.confirmations {
+                    return Ok(block_number);
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(SinkError::Io(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("transaction {tx_hash} was not confirmed in time"),
+                )));
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+}
+
+impl<D: Digest> RootSink<D> for EthereumSink {
+    fn publish(&mut self, publication: &Publication<D>) -> Result<(), SinkError> {
+        let (submission, block_number) = self.submit(&publication.root)?;
+        if let Some(block_number) = block_number {
+            self.confirmed.push(Confirmation {
+                version: publication.version,
+                root: publication.root.to_vec(),
+                nonce: submission.nonce,
+                tx_hash: submission.tx_hash,
+                block_number,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<D: Digest> Anchor<D> for EthereumSink {
+    fn publish(&mut self, root: &Output<D>) -> Result<AnchorRef, AnchorError> {
+        let (submission, _) = self.submit(root)?;
+        Ok(AnchorRef::new(SYSTEM, submission.tx_hash.to_string()))
+    }
+
+    fn resolve(&self, anchor: &AnchorRef) -> Result<Output<D>, AnchorError> {
+        anchor.check_system(SYSTEM)?;
+        let tx_hash: TxHash = anchor.locator.parse().map_err(|_| AnchorError::Malformed {
+            reason: format!("{} is not a transaction hash", anchor.locator),
+        })?;
+        let not_found = || AnchorError::NotFound {
+            locator: anchor.locator.clone(),
+        };
+        let tx = self
+            .runtime
+            .block_on(self.provider.get_transaction_by_hash(tx_hash))
+            .map_err(rpc_error)?
+            .ok_or_else(not_found)?;
+        let input = tx.input();
+        // only calls of the publishing function of the contract carry roots
+        if tx.to() != Some(self.contract) || input.len() != 36 || input[..4] != self.selector {
+            return Err(not_found());
+        }
+        let confirmed = match self.receipt_block(tx_hash)? {
+            Some(block_number) => {
+                let head = self
+                    .runtime
+                    .block_on(self.provider.get_block_number())
+                    .map_err(rpc_error)?;
+                head + 1 >= block_number + self.confirmations.max(1)
+            }
+            None => false,
+        };
+        if !confirmed {
+            return Err(AnchorError::Unconfirmed {
+                locator: anchor.locator.clone(),
+            });
+        }
+        let size = <D as Digest>::output_size();
+        root_from_bytes::<D>(&input[4..4 + size.min(32)])
+    }
+}
+
+/// The reason a receipt failed verification
+#[derive(Debug)]
+pub enum ReceiptError {
+    /// the RPC URL is not a valid URL
+    InvalidUrl { url: String },
+    /// creating the runtime of the RPC client failed
+    Io(io::Error),
+    /// the call of the contract failed or didn't return a `bytes32`
+    Rpc { message: String },
+    /// the contract holds no root for the epoch
+    NotAnchored { epoch: u64 },
+    /// the proof doesn't lead to the root anchored for the epoch
+    RootMismatch { epoch: u64 },
+}
+
+impl fmt::Display for ReceiptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUrl { url } => write!(f, "invalid RPC URL {url}"),
+            Self::Io(err) => write!(f, "I/O error: {err}"),
+            Self::Rpc { message } => write!(f, "RPC call failed: {message}"),
+            Self::NotAnchored { epoch } => write!(f, "no root is anchored for epoch {epoch}"),
+            Self::RootMismatch { epoch } => {
+                write!(
+                    f,
+                    "proof doesn't lead to the root anchored for epoch {epoch}"
+                )
+            }
+        }
+    }
+}
+
+impl Error for ReceiptError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// A leaf with its inclusion proof in the root anchored for an epoch
+#[derive(Debug, Clone)]
+pub struct Receipt<D: Digest, S = Plain> {
+    /// the publication version of the root the proof was created for
+    pub epoch: u64,
+    /// the proven leaf hash
+    pub leaf: Output<D>,
+    /// the inclusion proof of the leaf
+    pub proof: Proof<D, S>,
+}
+
+/// Verifies receipts against the roots anchored in a contract
+pub struct AnchorVerifier {
+    runtime: Runtime,
+    provider: DynProvider,
+    contract: Address,
+    selector: [u8; 4],
+}
+
+impl Debug for AnchorVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnchorVerifier")
+            .field("contract", &self.contract)
+            .field("selector", &hex::encode(self.selector))
+            .finish_non_exhaustive()
+    }
+}
+
+impl AnchorVerifier {
+    /// creates a verifier reading roots from the contract through the node at the RPC URL with the
+    /// view function of the given signature, e.g. `roots(uint256)`
+    pub fn new(rpc_url: &str, contract: Address, signature: &str) -> Result<Self, ReceiptError> {
+        let url = parse_url(rpc_url).ok_or_else(|| ReceiptError::InvalidUrl {
+            url: rpc_url.to_string(),
+        })?;
+        Ok(Self {
+            runtime: new_runtime().map_err(ReceiptError::Io)?,
+            provider: ProviderBuilder::new().connect_http(url).erased(),
+            contract,
+            selector: selector(signature),
+        })
+    }
+
+    /// returns the root the contract stores for the epoch as `bytes32`
+    /// returns an error if the call fails or the contract holds no root for the epoch
+    pub fn anchored_root(&self, epoch: u64) -> Result<[u8; 32], ReceiptError> {
+        let mut input = self.selector.to_vec();
+        input.extend_from_slice(&[0; 24]);
+        input.extend_from_slice(&epoch.to_be_bytes());
+        let tx = TransactionRequest::default()
+            .with_to(self.contract)
+            .with_input(Bytes::from(input));
+        let output = self
+            .runtime
+            .block_on(self.provider.call(tx).into_future())
+            .map_err(|err| ReceiptError::Rpc {
+                message: err.to_string(),
+            })?;
+        let root: [u8; 32] = output.as_ref().try_into().map_err(|_| ReceiptError::Rpc {
+            message: format!(
+                "expected a bytes32 return value, got {} bytes",
+                output.len()
+            ),
+        })?;
+        if root == [0; 32] {
+            return Err(ReceiptError::NotAnchored { epoch });
+        }
+        Ok(root)
+    }
+
+    /// verifies that the proof of the receipt leads to the root anchored for its epoch
+    pub fn verify<D, S>(&self, receipt: &Receipt<D, S>) -> Result<(), ReceiptError>
+    where
+        D: Digest + Default + Clone + Debug,
+        Output<D>: Copy,
+        S: HashScheme<D>,
+    {
+        let anchored = self.anchored_root(receipt.epoch)?;
+        let root = receipt.proof.compute_root(&receipt.leaf);
+        // a root longer than a `bytes32` can't have been anchored
+        if root_word(&root).ok() != Some(anchored) {
+            return Err(ReceiptError::RootMismatch {
+                epoch: receipt.epoch,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// verifies a receipt against the root anchored in the contract in one call, see `AnchorVerifier`
+pub fn verify_receipt<D, S>(
+    rpc_url: &str,
+    contract: Address,
+    signature: &str,
+    receipt: &Receipt<D, S>,
+) -> Result<(), ReceiptError>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    AnchorVerifier::new(rpc_url, contract, signature)?.verify(receipt)
+}
+
+/// raises a fee of a replaced transaction by an eighth, above the 10% bump nodes require
+fn bump_fee(fee: u128) -> u128 {
+    fee.saturating_add(fee / 8 + 1)
+}
+
+fn parse_url(rpc_url: &str) -> Option<Url> {
+    rpc_url.parse().ok()
+}
+
+/// creates the runtime the RPC calls are driven on
+fn new_runtime() -> io::Result<Runtime> {
+    runtime::Builder::new_current_thread().enable_all().build()
+}
+
+fn rpc_error(err: RpcError<TransportErrorKind>) -> SinkError {
+    SinkError::Rpc {
+        message: err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::time::SystemTime;
+
+    use alloy::consensus::transaction::Recovered;
+    use alloy::consensus::TxEnvelope;
+    use alloy::eips::eip2718::Decodable2718;
+    use alloy::primitives::keccak256;
+    use alloy::rpc::types::Transaction as RpcTransaction;
+    use serde_json::{json, Value};
+    use sha2::Sha256;
+
+    /// the transactions received by the fake node and the current block
+    #[derive(Default)]
+    struct Chain {
+        from: Address,
+        nonce_requests: usize,
+        transactions: Vec<TxEnvelope>,
+        head: u64,
+        /// keeps all transactions pending
+        stalled: bool,
+    }
+
+    /// answers the JSON-RPC calls of the sink and the verifier like a node mining every transaction
+    /// into the block after the current head, a transaction with the nonce of a pending one replaces
+    /// it if it raises both fees by at least 10%
+    fn answer(chain: &mut Chain, method: &str, params: &Value) -> Result<Value, String> {
+        Ok(match method {
+            "eth_chainId" => json!("0x1"),
+            "eth_getTransactionCount" => {
+                chain.nonce_requests += 1;
+                json!("0x7")
+            }
+            "eth_estimateGas" => json!("0x10000"),
+            "eth_feeHistory" => json!({
+                "oldestBlock": "0x1",
+                "baseFeePerGas": ["0x1", "0x1"],
+                "gasUsedRatio": [0.5],
+                "reward": [["0x1"]],
+            }),
+            "eth_sendRawTransaction" => {
+                let raw = hex::decode(&params[0].as_str().unwrap()[2..]).unwrap();
+                let tx = TxEnvelope::decode_2718(&mut raw.as_slice()).unwrap();
+                let pending = chain
+                    .transactions
+                    .iter()
+                    .position(|pending| pending.nonce() == tx.nonce());
+                match pending {
+                    Some(position) => {
+                        let pending = &chain.transactions[position];
+                        if tx.max_fee_per_gas() * 10 < pending.max_fee_per_gas() * 11
+                            || tx.max_priority_fee_per_gas().unwrap() * 10
+                                < pending.max_priority_fee_per_gas().unwrap() * 11
+                        {
+                            return Err("replacement transaction underpriced".to_string());
+                        }
+                        chain.transactions[position] = tx;
+                    }
+                    None => chain.transactions.push(tx),
+                }
+                json!(keccak256(&raw))
+            }
+            "eth_getTransactionReceipt" => {
+                let hash: TxHash = serde_json::from_value(params[0].clone()).unwrap();
+                let position = chain
+                    .transactions
+                    .iter()
+                    .position(|tx| *tx.tx_hash() == hash);
+                let Some(position) = position.filter(|_| !chain.stalled) else {
+                    return Ok(Value::Null);
+                };
+                let block = position as u64 + 2;
+                // every poll mines a block
+                chain.head = chain.head.max(block);
+                json!({
+                    "transactionHash": hash,
+                    "transactionIndex": "0x0",
+                    "blockHash": TxHash::repeat_byte(block as u8),
+                    "blockNumber": format!("{block:#x}"),
+                    "from": chain.from,
+                    "to": chain.transactions[position].to(),
+                    "cumulativeGasUsed": "0x5208",
+                    "gasUsed": "0x5208",
+                    "effectiveGasPrice": "0x1",
+                    "contractAddress": null,
+                    "logs": [],
+                    "logsBloom": format!("0x{}", "00".repeat(256)),
+                    "type": "0x2",
+                    "status": "0x1",
+                })
+            }
+            "eth_getTransactionByHash" => {
+                let hash: TxHash = serde_json::from_value(params[0].clone()).unwrap();
+                let Some(position) = chain
+                    .transactions
+                    .iter()
+                    .position(|tx| *tx.tx_hash() == hash)
+                else {
+                    return Ok(Value::Null);
+                };
+                let tx = RpcTransaction {
+                    inner: Recovered::new_unchecked(
+                        chain.transactions[position].clone(),
+                        chain.from,
+                    ),
+                    block_hash: None,
+                    block_number: None,
+                    transaction_index: None,
+                    effective_gas_price: None,
+                    block_timestamp: None,
+                };
+                serde_json::to_value(tx).unwrap()
+            }
+            "eth_call" => {
+                // a contract storing the root of every `updateRoot` call under its epoch
+                let call = &params[0];
+                let input = call["input"].as_str().or(call["data"].as_str()).unwrap();
+                let input = hex::decode(&input[2..]).unwrap();
+                assert_eq!(input[..4], selector("roots(uint256)"));
+                let epoch = u64::from_be_bytes(input[28..36].try_into().unwrap());
+                let root = chain
+                    .transactions
+                    .get(epoch as usize)
+                    .map_or([0; 32], |tx| tx.input()[4..36].try_into().unwrap());
+                json!(format!("0x{}", hex::encode(root)))
+            }
+            "eth_blockNumber" => {
+                let head = chain.head;
+                chain.head += 1;
+                json!(format!("{head:#x}"))
+            }
+            _ => panic!("unexpected call of {method}"),
+        })
+    }
+
+    /// serves JSON-RPC over HTTP on a local port, returns the URL
+    fn serve(chain: Arc<Mutex<Chain>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let chain = Arc::clone(&chain);
+                thread::spawn(move || {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    // keep-alive connections carry many requests
+                    loop {
+                        let mut content_length = 0;
+                        loop {
+                            let mut line = String::new();
+                            if reader.read_line(&mut line).unwrap() == 0 {
+                                return;
+                            }
+                            if line == "\r\n" {
+                                break;
+                            }
+                            if let Some((name, value)) = line.split_once(':') {
+                                if name.eq_ignore_ascii_case("content-length") {
+                                    content_length = value.trim().parse().unwrap();
+                                }
+                            }
+                        }
+                        let mut body = vec![0; content_length];
+                        reader.read_exact(&mut body).unwrap();
+                        let request: Value = serde_json::from_slice(&body).unwrap();
+                        let result = answer(
+                            &mut chain.lock().unwrap(),
+                            request["method"].as_str().unwrap(),
+                            &request["params"],
+                        );
+                        let response = match result {
+                            Ok(result) => {
+                                json!({"jsonrpc": "2.0", "id": request["id"], "result": result})
+                            }
+                            Err(message) => json!({
+                                "jsonrpc": "2.0",
+                                "id": request["id"],
+                                "error": {"code": -32000, "message": message},
+                            }),
+                        }
+                        .to_string();
+                        write!(
+                            stream,
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{response}",
+                            response.len()
+                        )
+                        .unwrap();
+                    }
+                });
+            }
+        });
+        url
+    }
+
+    fn publication(version: u64) -> Publication<Sha256> {
+        Publication {
+            version,
+            root: Sha256::digest(version.to_le_bytes()),
+            updates: 1,
+            published_at: SystemTime::now(),
+        }
+    }
+
+    #[test]
+    fn test_ethereum_sink() {
+        let signer = PrivateKeySigner::random();
+        let chain = Arc::new(Mutex::new(Chain {
+            from: signer.address(),
+            ..Chain::default()
+        }));
+        let contract = Address::repeat_byte(0xcc);
+        let mut sink = EthereumSink::new(
+            &serve(Arc::clone(&chain)),
+            signer,
+            contract,
+            "updateRoot(bytes32)",
+        )
+        .unwrap()
+        .with_confirmations(2)
+        .with_poll_interval(Duration::from_millis(10));
+        assert_eq!(sink.next_nonce(), None);
+
+        for version in 0..3 {
+            RootSink::<Sha256>::publish(&mut sink, &publication(version)).unwrap();
+        }
+        let chain = chain.lock().unwrap();
+        // the nonce was fetched once and counted up locally
+        assert_eq!(chain.nonce_requests, 1);
+        assert_eq!(sink.next_nonce(), Some(10));
+        assert_eq!(sink.unconfirmed(), None);
+        for (version, tx) in chain.transactions.iter().enumerate() {
+            let root = publication(version as u64).root;
+            assert_eq!(tx.nonce(), 7 + version as u64);
+            assert_eq!(tx.to(), Some(contract));
+            assert_eq!(
+                tx.input().as_ref(),
+                sink.calldata(&root).unwrap().as_slice()
+            );
+
+            let confirmation = &sink.confirmed()[version];
+            assert_eq!(confirmation.version, version as u64);
+            assert_eq!(confirmation.root, root.to_vec());
+            assert_eq!(confirmation.nonce, tx.nonce());
+            assert_eq!(confirmation.tx_hash, *tx.tx_hash());
+            assert_eq!(confirmation.block_number, version as u64 + 2);
+        }
+
+        assert!(matches!(
+            EthereumSink::new("not a url", PrivateKeySigner::random(), contract, "f()"),
+            Err(SinkError::InvalidUrl { .. })
+        ));
+    }
+
+    #[test]
+    fn test_ethereum_sink_replacement() {
+        let signer = PrivateKeySigner::random();
+        let chain = Arc::new(Mutex::new(Chain {
+            from: signer.address(),
+            stalled: true,
+            ..Chain::default()
+        }));
+        let mut sink = EthereumSink::new(
+            &serve(Arc::clone(&chain)),
+            signer,
+            Address::repeat_byte(0xcc),
+            "updateRoot(bytes32)",
+        )
+        .unwrap()
+        .with_poll_interval(Duration::from_millis(10))
+        .with_timeout(Duration::from_millis(50));
+
+        // both publications time out, the second one replaces the first at the same nonce
+        for version in 0..2 {
+            assert!(matches!(
+                RootSink::<Sha256>::publish(&mut sink, &publication(version)),
+                Err(SinkError::Io(err)) if err.kind() == io::ErrorKind::TimedOut
+            ));
+        }
+        let replacement = {
+            let mut chain = chain.lock().unwrap();
+            assert_eq!(chain.transactions.len(), 1);
+            chain.stalled = false;
+            chain.transactions[0].clone()
+        };
+        assert_eq!(replacement.nonce(), 7);
+        assert_eq!(*replacement.tx_hash(), sink.unconfirmed().unwrap());
+        assert_eq!(
+            replacement.input().as_ref(),
+            sink.calldata(&publication(1).root).unwrap().as_slice()
+        );
+
+        // the replacement got mined, the next publication takes the next nonce
+        RootSink::<Sha256>::publish(&mut sink, &publication(2)).unwrap();
+        let chain = chain.lock().unwrap();
+        assert_eq!(chain.transactions.len(), 2);
+        assert_eq!(chain.transactions[1].nonce(), 8);
+        assert_eq!(sink.unconfirmed(), None);
+        assert_eq!(sink.confirmed().len(), 1);
+        assert_eq!(sink.confirmed()[0].version, 2);
+    }
+
+    #[test]
+    fn test_anchor_verifier() {
+        let signer = PrivateKeySigner::random();
+        let chain = Arc::new(Mutex::new(Chain {
+            from: signer.address(),
+            ..Chain::default()
+        }));
+        let url = serve(Arc::clone(&chain));
+        let contract = Address::repeat_byte(0xcc);
+        let mut sink = EthereumSink::new(&url, signer, contract, "updateRoot(bytes32)")
+            .unwrap()
+            .with_poll_interval(Duration::from_millis(10));
+
+        let leaves: Vec<_> = (0..5u8).map(|i| Sha256::digest([i])).collect();
+        let mut tree = crate::MerkleTree::<Sha256>::from_leaves(&leaves, &[0; 32].into());
+        let first = tree.create_proof(3);
+        for version in 0..2 {
+            let publication = Publication {
+                root: *tree.root_hash(),
+                ..publication(version)
+            };
+            RootSink::<Sha256>::publish(&mut sink, &publication).unwrap();
+            tree.set(7, &Sha256::digest("new leaf"));
+        }
+
+        let receipt = Receipt {
+            epoch: 0,
+            leaf: leaves[3],
+            proof: first,
+        };
+        assert!(verify_receipt(&url, contract, "roots(uint256)", &receipt).is_ok());
+        let verifier = AnchorVerifier::new(&url, contract, "roots(uint256)").unwrap();
+        assert_eq!(
+            verifier.anchored_root(0).unwrap(),
+            root_word(&receipt.proof.compute_root(&leaves[3])).unwrap()
+        );
+        // the proof was created before the update anchored in epoch 1
+        assert!(matches!(
+            verifier.verify(&Receipt {
+                epoch: 1,
+                ..receipt.clone()
+            }),
+            Err(ReceiptError::RootMismatch { epoch: 1 })
+        ));
+        assert!(matches!(
+            verifier.verify(&Receipt {
+                epoch: 2,
+                ..receipt
+            }),
+            Err(ReceiptError::NotAnchored { epoch: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_ethereum_anchor() {
+        let signer = PrivateKeySigner::random();
+        let chain = Arc::new(Mutex::new(Chain {
+            from: signer.address(),
+            ..Chain::default()
+        }));
+        let mut sink = EthereumSink::new(
+            &serve(Arc::clone(&chain)),
+            signer,
+            Address::repeat_byte(0xcc),
+            "updateRoot(bytes32)",
+        )
+        .unwrap()
+        .with_poll_interval(Duration::from_millis(10));
+
+        let root = Sha256::digest("root");
+        let anchor = Anchor::<Sha256>::publish(&mut sink, &root).unwrap();
+        assert_eq!(anchor.system, SYSTEM);
+        assert_eq!(
+            anchor.locator,
+            chain.lock().unwrap().transactions[0].tx_hash().to_string()
+        );
+        assert_eq!(Anchor::<Sha256>::resolve(&sink, &anchor).unwrap(), root);
+
+        let unknown = AnchorRef::new(SYSTEM, TxHash::repeat_byte(1).to_string());
+        assert!(matches!(
+            Anchor::<Sha256>::resolve(&sink, &unknown),
+            Err(AnchorError::NotFound { .. })
+        ));
+        assert!(matches!(
+            Anchor::<Sha256>::resolve(&sink, &AnchorRef::new(SYSTEM, "0x12")),
+            Err(AnchorError::Malformed { .. })
+        ));
+    }
+}
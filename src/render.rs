@@ -0,0 +1,252 @@
+//! Visualization of trees for debugging
+//!
+//! `MerkleTree::render` returns a `TreeRender`, which draws the top layers of a tree either as text
+//! (its `Display` impl, also used by `Display` for `MerkleTree`) or as a Graphviz graph (`to_dot`).
+//! Every node is labeled with the first bytes of its hash in hex, which is usually enough to spot
+//! the first node where two implementations disagree. A proof can be highlighted: the nodes on the
+//! path of its leaf and its siblings are marked.
+//!
+//! Only the top `max_depth` layers are drawn (`DEFAULT_RENDER_DEPTH` by default), a tree of depth
+//! 20 would otherwise print a million nodes.
+
+use alloc::format;
+use alloc::string::String;
+use core::fmt::{self, Debug, Display, Write};
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, Proof};
+
+/// number of layers drawn unless `TreeRender::max_depth` is set
+pub const DEFAULT_RENDER_DEPTH: usize = 5;
+/// number of bytes of every hash shown in the labels
+const LABEL_BYTES: usize = 4;
+
+/// A drawing of the top layers of a tree, see `MerkleTree::render`
+pub struct TreeRender<'a, D: Digest, S> {
+    tree: &'a MerkleTree<D, S>,
+    /// the number of layers to draw
+    max_depth: usize,
+    /// the offset of the leaf whose proof is highlighted
+    highlight: Option<usize>,
+}
+
+/// the role of a node in the highlighted proof
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    None,
+    Path,
+    Sibling,
+}
+
+impl<D, S> MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// returns a drawing of the top `DEFAULT_RENDER_DEPTH` layers of the tree
+    pub fn render(&self) -> TreeRender<'_, D, S> {
+        TreeRender {
+            tree: self,
+            max_depth: DEFAULT_RENDER_DEPTH,
+            highlight: None,
+        }
+    }
+
+    /// returns the top `DEFAULT_RENDER_DEPTH` layers of the tree as a Graphviz graph
+    pub fn to_dot(&self) -> String {
+        self.render().to_dot()
+    }
+}
+
+impl<D, S> TreeRender<'_, D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// sets the number of layers to draw, the root is always drawn
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// marks the path of the leaf of the proof and its siblings
+    pub fn highlight(mut self, proof: &Proof<D, S>) -> Self {
+        self.highlight = Some(proof.leaf_index());
+        self
+    }
+
+    /// returns the drawing as a Graphviz graph, highlighted path nodes are filled blue and their
+    /// siblings grey
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph merkle_tree {\n");
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for layer in 0..self.layers() {
+            for offset in 0..1 << layer {
+                let index = MerkleTree::<D, S>::index(layer, offset);
+                let style = match self.mark(layer, offset) {
+                    Mark::None => "",
+                    Mark::Path => ", style=filled, fillcolor=lightblue",
+                    Mark::Sibling => ", style=filled, fillcolor=lightgrey",
+                };
+                dot.push_str(&format!(
+                    "    n{index} [label=\"{}\"{style}];\n",
+                    self.label(layer, offset)
+                ));
+                if layer > 0 {
+                    let parent = MerkleTree::<D, S>::index(layer - 1, offset / 2);
+                    dot.push_str(&format!("    n{parent} -> n{index};\n"));
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// returns the number of drawn layers
+    fn layers(&self) -> usize {
+        self.tree.depth().clamp(1, self.max_depth.max(1))
+    }
+
+    fn label(&self, layer: usize, offset: usize) -> String {
+        let hash = self.tree.node(MerkleTree::<D, S>::index(layer, offset));
+        hex::encode(&hash[..LABEL_BYTES.min(hash.len())])
+    }
+
+    fn mark(&self, layer: usize, offset: usize) -> Mark {
+        let Some(leaf) = self.highlight else {
+            return Mark::None;
+        };
+        let Some(height) = self.tree.depth().checked_sub(layer + 1) else {
+            return Mark::None;
+        };
+        let on_path = leaf >> height;
+        if offset == on_path {
+            Mark::Path
+        } else if layer > 0 && offset == on_path ^ 1 {
+            Mark::Sibling
+        } else {
+            Mark::None
+        }
+    }
+
+    /// writes a node and the subtrees below it, `prefix` continues the lines of its ancestors
+    fn write_node(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        layer: usize,
+        offset: usize,
+        prefix: &mut String,
+        last: bool,
+    ) -> fmt::Result {
+        let connector = match (layer, last) {
+            (0, _) => "",
+            (_, false) => "├─ ",
+            (_, true) => "└─ ",
+        };
+        f.write_str(prefix)?;
+        f.write_str(connector)?;
+        f.write_str(&self.label(layer, offset))?;
+        match self.mark(layer, offset) {
+            Mark::None => {}
+            Mark::Path => f.write_str(" <- path")?,
+            Mark::Sibling => f.write_str(" <- sibling")?,
+        }
+        f.write_char('\n')?;
+        if layer + 1 >= self.layers() {
+            return Ok(());
+        }
+        let len = prefix.len();
+        if layer > 0 {
+            prefix.push_str(if last { "   " } else { "│  " });
+        }
+        self.write_node(f, layer + 1, offset * 2, prefix, false)?;
+        self.write_node(f, layer + 1, offset * 2 + 1, prefix, true)?;
+        prefix.truncate(len);
+        Ok(())
+    }
+}
+
+impl<D, S> Display for TreeRender<'_, D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_node(f, 0, 0, &mut String::new(), true)?;
+        let hidden = self.tree.depth().saturating_sub(self.layers());
+        if hidden > 0 {
+            writeln!(f, "({hidden} more layers not shown)")?;
+        }
+        Ok(())
+    }
+}
+
+impl<D, S> Display for MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.render(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+
+    fn short(hash: &Output<Sha3_256>) -> String {
+        hex::encode(&hash[..4])
+    }
+
+    #[test]
+    fn test_display() {
+        let leaves: Vec<Output<Sha3_256>> = (0..4).map(|i| [i as u8; 32].into()).collect();
+        let tree = MerkleTree::from_leaves(&leaves, &[0; 32].into());
+        let left = short(tree.subtree_root(1, 0));
+        let right = short(tree.subtree_root(1, 1));
+        let expected = format!(
+            "{}\n├─ {left}\n│  ├─ 00000000\n│  └─ 01010101\n└─ {right}\n   ├─ 02020202\n   └─ 03030303\n",
+            short(tree.root_hash())
+        );
+        assert_eq!(tree.to_string(), expected);
+
+        let highlighted = tree.render().highlight(&tree.create_proof(2)).to_string();
+        assert!(highlighted.contains(&format!("├─ {left} <- sibling\n")));
+        assert!(highlighted.contains("├─ 02020202 <- path\n"));
+        assert!(highlighted.contains("└─ 03030303 <- sibling\n"));
+
+        let truncated = tree.render().max_depth(2).to_string();
+        assert!(truncated.ends_with(&format!("└─ {right}\n(1 more layers not shown)\n")));
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let leaves: Vec<Output<Sha3_256>> = (0..3).map(|i| [i as u8; 32].into()).collect();
+        let tree = MerkleTree::from_leaves(&leaves, &[0; 32].into());
+        let dot = tree.render().highlight(&tree.create_proof(1)).to_dot();
+        assert!(dot.starts_with("digraph merkle_tree {\n"));
+        assert!(dot.contains("    n0 -> n1;\n"));
+        assert!(dot.contains("    n2 -> n6;\n"));
+        assert!(dot.contains("    n4 [label=\"01010101\", style=filled, fillcolor=lightblue];\n"));
+        assert!(dot.contains("    n3 [label=\"00000000\", style=filled, fillcolor=lightgrey];\n"));
+        assert!(dot.contains("    n6 [label=\"00000000\"];\n"));
+        assert_eq!(dot.matches("->").count(), 6);
+        assert_eq!(
+            MerkleTree::empty(&[0; 32].into())
+                .to_dot()
+                .matches("[label")
+                .count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! A log of all roots of a tree with proofs against each of them
+//!
+//! A `RootHistoryTree` records the root after every update together with a sequence number, the
+//! root of the wrapped tree is recorded as sequence number 0. Every record holds a snapshot of the
+//! tree (see `MerkleTree::snapshot`), so proofs valid against any recorded root can be created later,
+//! e.g. for a verifier that only accepts the roots published at checkpoints.
+//!
+//! The snapshots keep the old value of every node overwritten after them, so the history grows by
+//! at most one hash per node on the path of every update and is never released while the tree
+//! lives.
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError, Plain, Proof, TreeVersion};
+
+/// A root of a tree recorded after an update
+pub struct RootRecord<D: Digest> {
+    /// the number of updates before the root was recorded
+    sequence: u64,
+    root: Output<D>,
+    /// the snapshot of the tree with the root
+    version: TreeVersion,
+}
+
+impl<D: Digest> RootRecord<D> {
+    /// returns the sequence number of the root, the number of updates before it
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// returns the recorded root
+    pub fn root(&self) -> &Output<D> {
+        &self.root
+    }
+
+    /// returns the number of leaves of the tree with the root
+    pub fn num_leaves(&self) -> usize {
+        self.version.num_leaves()
+    }
+}
+
+impl<D: Digest> Clone for RootRecord<D> {
+    fn clone(&self) -> Self {
+        Self {
+            sequence: self.sequence,
+            root: self.root.clone(),
+            version: self.version,
+        }
+    }
+}
+
+impl<D: Digest> Debug for RootRecord<D> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RootRecord")
+            .field("sequence", &self.sequence)
+            .field("root", &self.root)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<D: Digest> PartialEq for RootRecord<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.sequence == other.sequence && self.root == other.root && self.version == other.version
+    }
+}
+
+impl<D: Digest> Eq for RootRecord<D> {}
+
+/// A Merkle tree that records its root after every update
+pub struct RootHistoryTree<D: Digest, S = Plain> {
+    tree: MerkleTree<D, S>,
+    /// the recorded roots by sequence number
+    history: Vec<RootRecord<D>>,
+}
+
+impl<D, S> RootHistoryTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// starts recording the roots of the tree, its current root gets sequence number 0
+    pub fn new(tree: MerkleTree<D, S>) -> Self {
+        let mut tree = Self {
+            tree,
+            history: Vec::new(),
+        };
+        tree.record();
+        tree
+    }
+
+    /// returns the tree
+    pub fn tree(&self) -> &MerkleTree<D, S> {
+        &self.tree
+    }
+
+    /// returns the tree, dropping the recorded roots
+    pub fn into_inner(self) -> MerkleTree<D, S> {
+        self.tree
+    }
+
+    /// returns all recorded roots in the order of their sequence numbers, the last one is the
+    /// current root
+    pub fn history(&self) -> &[RootRecord<D>] {
+        &self.history
+    }
+
+    /// returns the sequence number of the current root
+    pub fn sequence(&self) -> u64 {
+        self.history.len() as u64 - 1
+    }
+
+    /// returns the root recorded with the sequence number, None if there is no such root yet
+    pub fn root_at(&self, sequence: u64) -> Option<&Output<D>> {
+        self.record_at(sequence).map(RootRecord::root)
+    }
+
+    /// updates the value of a leaf node and records the new root, returns its sequence number
+    /// panics if the offset is out of bounds, see `try_set` for a fallible version
+    pub fn set(&mut self, offset: usize, value: &Output<D>) -> u64 {
+        match self.try_set(offset, value) {
+            Ok(sequence) => sequence,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// updates the value of a leaf node and records the new root, returns its sequence number
+    /// returns an error and records nothing if the offset is out of bounds
+    pub fn try_set(&mut self, offset: usize, value: &Output<D>) -> Result<u64, MerkleTreeError> {
+        self.tree.try_set(offset, value)?;
+        Ok(self.record())
+    }
+
+    /// appends a leaf after the last pushed leaf and records the new root, returns its sequence
+    /// number
+    /// panics if the tree can't grow any further, see `try_push` for a fallible version
+    pub fn push(&mut self, leaf: &Output<D>) -> u64 {
+        match self.try_push(leaf) {
+            Ok(sequence) => sequence,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// appends a leaf after the last pushed leaf and records the new root, returns its sequence
+    /// number
+    /// returns an error and records nothing if the tree would exceed `MAX_DEPTH` or its nodes
+    /// can't be allocated
+    pub fn try_push(&mut self, leaf: &Output<D>) -> Result<u64, MerkleTreeError> {
+        self.tree.try_push(leaf)?;
+        Ok(self.record())
+    }
+
+    /// creates a proof for the leaf at the given offset against the root with the sequence number
+    /// panics if no root was recorded with the sequence number or the offset is out of bounds of
+    /// the tree at that time, see `try_create_proof_for_root` for a fallible version
+    pub fn create_proof_for_root(&self, sequence: u64, offset: usize) -> Proof<D, S> {
+        match self.try_create_proof_for_root(sequence, offset) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a proof for the leaf at the given offset against the root with the sequence number
+    /// returns an error if no root was recorded with the sequence number or the offset is out of
+    /// bounds of the tree at that time
+    pub fn try_create_proof_for_root(
+        &self,
+        sequence: u64,
+        offset: usize,
+    ) -> Result<Proof<D, S>, MerkleTreeError> {
+        let record = self
+            .record_at(sequence)
+            .ok_or(MerkleTreeError::UnknownSequence { sequence })?;
+        self.tree.try_create_proof_at(record.version, offset)
+    }
+
+    fn record_at(&self, sequence: u64) -> Option<&RootRecord<D>> {
+        self.history.get(usize::try_from(sequence).ok()?)
+    }
+
+    /// records the current root with the next sequence number and returns it
+    fn record(&mut self) -> u64 {
+        let sequence = self.history.len() as u64;
+        self.history.push(RootRecord {
+            sequence,
+            root: *self.tree.root_hash(),
+            version: self.tree.snapshot(),
+        });
+        sequence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    use crate::verify;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type RootHistoryTree = super::RootHistoryTree<Sha3_256>;
+
+    #[test]
+    fn test_proofs_for_past_roots() {
+        let leaves: Vec<Output<Sha3_256>> = (1..4).map(|i| [i as u8; 32].into()).collect();
+        let mut tree = RootHistoryTree::new(MerkleTree::from_leaves(&leaves, &[0; 32].into()));
+        assert_eq!(tree.sequence(), 0);
+        assert_eq!(tree.set(1, &[9; 32].into()), 1);
+        assert_eq!(tree.push(&[8; 32].into()), 2);
+        // the tree grows with the fifth leaf
+        assert_eq!(tree.push(&[7; 32].into()), 3);
+        tree.set(1, &[6; 32].into());
+        assert!(tree.try_set(8, &[1; 32].into()).is_err());
+        assert_eq!(tree.history().len(), 5);
+        assert_eq!(tree.root_at(4), Some(tree.tree().root_hash()));
+
+        let values = [[2; 32], [9; 32], [9; 32], [9; 32], [6; 32]];
+        for (record, value) in tree.history().iter().zip(values) {
+            let proof = tree.create_proof_for_root(record.sequence(), 1);
+            assert!(verify(record.root(), &value.into(), &proof));
+        }
+        assert_eq!(tree.history()[2].num_leaves(), 4);
+        assert_eq!(tree.history()[3].num_leaves(), 8);
+        assert!(tree.try_create_proof_for_root(2, 4).is_err());
+        assert_eq!(
+            tree.try_create_proof_for_root(5, 0),
+            Err(MerkleTreeError::UnknownSequence { sequence: 5 })
+        );
+    }
+}
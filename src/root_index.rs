@@ -0,0 +1,412 @@
+//! Index of historically published roots
+//!
+//! Verifiers presented with an arbitrary root need to know whether it was ever published. The
+//! `RootIndex` answers this in O(1) and proves which version a root belonged to by committing to
+//! all published roots in a Merkle tree of its own. The compact `RootBloom` can be shipped to
+//! verifiers that only need a fast (probabilistic) pre-check.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{self, Read, Write};
+
+use digest::{Digest, Output};
+
+use crate::{MerkleTree, Proof};
+
+/// magic bytes at the start of a serialized root index
+const INDEX_MAGIC: &[u8; 4] = b"MTRI";
+/// magic bytes at the start of a serialized bloom filter
+const BLOOM_MAGIC: &[u8; 4] = b"MTRB";
+/// version of the serialization formats
+const FORMAT_VERSION: u8 = 1;
+/// number of roots the bloom filter of a new index is sized for
+const DEFAULT_CAPACITY: usize = 1024;
+/// false positive rate the bloom filter of an index is sized for
+const FALSE_POSITIVE_RATE: f64 = 0.001;
+
+/// A compact bloom filter over roots
+/// Roots are outputs of a cryptographic hash function, so the bit positions are derived from the
+/// root bytes directly instead of hashing them again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootBloom {
+    /// bit array
+    bits: Vec<u64>,
+    /// number of bits in use
+    num_bits: u64,
+    /// number of bits set per root
+    num_hashes: u32,
+}
+
+impl RootBloom {
+    /// creates a bloom filter sized for the given number of roots and false positive rate
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-capacity * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0);
+        let num_hashes = (num_bits / capacity * ln2).round().max(1.0);
+        Self::with_params(num_bits as u64, num_hashes as u32)
+    }
+
+    /// creates a bloom filter with the given number of bits and bits set per root
+    pub fn with_params(num_bits: u64, num_hashes: u32) -> Self {
+        let num_bits = num_bits.max(1);
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes: num_hashes.max(1),
+        }
+    }
+
+    /// adds a root to the filter
+    pub fn insert(&mut self, root: &[u8]) {
+        for bit in bit_positions(root, self.num_bits, self.num_hashes) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// returns false if the root was definitely never added, true if it probably was
+    pub fn might_contain(&self, root: &[u8]) -> bool {
+        bit_positions(root, self.num_bits, self.num_hashes)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// serializes the filter
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(17 + self.bits.len() * 8);
+        bytes.extend_from_slice(BLOOM_MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.extend_from_slice(&self.num_bits.to_le_bytes());
+        bytes.extend_from_slice(&self.num_hashes.to_le_bytes());
+        for word in &self.bits {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// deserializes a filter serialized with `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut reader = bytes;
+        read_header(&mut reader, BLOOM_MAGIC)?;
+        let num_bits = read_u64(&mut reader)?;
+        let mut num_hashes = [0; 4];
+        reader.read_exact(&mut num_hashes)?;
+        let num_hashes = u32::from_le_bytes(num_hashes);
+        if num_bits == 0 || num_hashes == 0 || reader.len() as u64 != num_bits.div_ceil(64) * 8 {
+            return Err(invalid_data("invalid bloom filter parameters"));
+        }
+        let bits = reader
+            .chunks_exact(8)
+            .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        Ok(Self {
+            bits,
+            num_bits,
+            num_hashes,
+        })
+    }
+}
+
+/// A proof that a root was published as a certain version of a `RootIndex`
+#[derive(Debug, Clone)]
+pub struct RootMembershipProof<D: Digest> {
+    /// inclusion proof of the root in the tree over all published roots, its leaf index is the version
+    proof: Proof<D>,
+}
+
+impl<D> RootMembershipProof<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// returns the version the root was published as
+    pub fn version(&self) -> u64 {
+        self.proof.leaf_index() as u64
+    }
+
+    /// returns the inclusion proof of the root in the tree over all published roots
+    pub fn proof(&self) -> &Proof<D> {
+        &self.proof
+    }
+
+    /// verifies that the root was published as `version()` in the index with the given commitment
+    pub fn verify(&self, root: &Output<D>, commitment: &Output<D>) -> bool {
+        crate::verify(commitment, root, &self.proof)
+    }
+}
+
+impl<D: Digest> PartialEq for RootMembershipProof<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.proof == other.proof
+    }
+}
+
+impl<D: Digest> Eq for RootMembershipProof<D> {}
+
+/// Index of all published roots with O(1) membership queries and version proofs
+pub struct RootIndex<D: Digest> {
+    /// bloom filter over all published roots
+    bloom: RootBloom,
+    /// number of roots the bloom filter is sized for
+    bloom_capacity: usize,
+    /// first version each root was published as
+    versions: HashMap<Output<D>, u64>,
+    /// published roots indexed by version
+    roots: Vec<Output<D>>,
+    /// tree over all published roots, grown by doubling when full
+    tree: Option<MerkleTree<D>>,
+}
+
+impl<D> Default for RootIndex<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> RootIndex<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// creates a new empty index
+    pub fn new() -> Self {
+        Self {
+            bloom: RootBloom::new(DEFAULT_CAPACITY, FALSE_POSITIVE_RATE),
+            bloom_capacity: DEFAULT_CAPACITY,
+            versions: HashMap::new(),
+            roots: Vec::new(),
+            tree: None,
+        }
+    }
+
+    /// returns the number of published roots
+    pub fn len(&self) -> usize {
+        self.roots.len()
+    }
+
+    /// returns true if no root was published yet
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// records a newly published root, returns the version it was published as
+    pub fn publish(&mut self, root: &Output<D>) -> u64 {
+        let version = self.roots.len() as u64;
+        self.roots.push(*root);
+        self.versions.entry(*root).or_insert(version);
+
+        if self.roots.len() > self.bloom_capacity {
+            // resize the bloom filter to keep the false positive rate in check
+            self.bloom_capacity *= 2;
+            self.bloom = RootBloom::new(self.bloom_capacity, FALSE_POSITIVE_RATE);
+            for root in &self.roots {
+                self.bloom.insert(root);
+            }
+        } else {
+            self.bloom.insert(root);
+        }
+
+        match &mut self.tree {
+            Some(tree) if self.roots.len() <= tree.num_leaves() => tree.set(version as usize, root),
+            // double the capacity of the tree
+            _ => {
+                self.tree = Some(MerkleTree::from_leaves(
+                    &self.roots,
+                    &Output::<D>::default(),
+                ))
+            }
+        }
+        version
+    }
+
+    /// returns the bloom filter over all published roots
+    pub fn bloom(&self) -> &RootBloom {
+        &self.bloom
+    }
+
+    /// returns true if the root was ever published
+    pub fn contains(&self, root: &Output<D>) -> bool {
+        self.bloom.might_contain(root) && self.versions.contains_key(root)
+    }
+
+    /// returns the first version the root was published as
+    pub fn version_of(&self, root: &Output<D>) -> Option<u64> {
+        if !self.bloom.might_contain(root) {
+            return None;
+        }
+        self.versions.get(root).copied()
+    }
+
+    /// returns the root published as the given version
+    pub fn root(&self, version: u64) -> Option<&Output<D>> {
+        self.roots.get(usize::try_from(version).ok()?)
+    }
+
+    /// returns the root of the tree over all published roots
+    /// membership proofs verify against this commitment
+    pub fn commitment(&self) -> Option<&Output<D>> {
+        self.tree.as_ref().map(|tree| tree.root_hash())
+    }
+
+    /// proves which version the root was first published as
+    pub fn prove(&self, root: &Output<D>) -> Option<RootMembershipProof<D>> {
+        let version = self.version_of(root)?;
+        let proof = self.tree.as_ref()?.create_proof(version as usize);
+        Some(RootMembershipProof { proof })
+    }
+
+    /// writes the published roots, the index is rebuilt from them by `load`
+    pub fn save(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_all(&[FORMAT_VERSION, <D as Digest>::output_size() as u8])?;
+        writer.write_all(&(self.roots.len() as u64).to_le_bytes())?;
+        for root in &self.roots {
+            writer.write_all(root)?;
+        }
+        Ok(())
+    }
+
+    /// reads an index written by `save`
+    pub fn load(mut reader: impl Read) -> io::Result<Self> {
+        read_header(&mut reader, INDEX_MAGIC)?;
+        let mut hash_size = [0; 1];
+        reader.read_exact(&mut hash_size)?;
+        if hash_size[0] as usize != <D as Digest>::output_size() {
+            return Err(invalid_data("hash size doesn't match the digest"));
+        }
+        let count = read_u64(&mut reader)?;
+
+        let mut index = Self::new();
+        let mut root = Output::<D>::default();
+        for _ in 0..count {
+            reader.read_exact(&mut root)?;
+            index.publish(&root);
+        }
+        Ok(index)
+    }
+}
+
+/// returns the bit positions of a root using double hashing over its first 16 bytes
+fn bit_positions(root: &[u8], num_bits: u64, num_hashes: u32) -> impl Iterator<Item = u64> {
+    let mut bytes = [0u8; 16];
+    let len = root.len().min(16);
+    bytes[..len].copy_from_slice(&root[..len]);
+    let h1 = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    // an odd step makes sure the positions don't collapse onto a single bit
+    let h2 = u64::from_le_bytes(bytes[8..].try_into().unwrap()) | 1;
+    (0..num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+}
+
+/// reads and checks the magic bytes and format version
+fn read_header(reader: &mut impl Read, magic: &[u8; 4]) -> io::Result<()> {
+    let mut header = [0; 5];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != magic {
+        return Err(invalid_data("invalid magic bytes"));
+    }
+    if header[4] != FORMAT_VERSION {
+        return Err(invalid_data("unsupported format version"));
+    }
+    Ok(())
+}
+
+/// reads a little endian u64
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    type RootIndex = super::RootIndex<Sha3_256>;
+
+    fn root(i: u32) -> Output<Sha3_256> {
+        Sha3_256::digest(i.to_le_bytes())
+    }
+
+    #[test]
+    fn test_bloom() {
+        let mut bloom = RootBloom::new(100, 0.01);
+        for i in 0..100 {
+            bloom.insert(&root(i));
+        }
+        assert!((0..100).all(|i| bloom.might_contain(&root(i))));
+        let false_positives = (100..10100)
+            .filter(|&i| bloom.might_contain(&root(i)))
+            .count();
+        assert!(false_positives < 300, "{false_positives} false positives");
+
+        let decoded = RootBloom::from_bytes(&bloom.to_bytes()).unwrap();
+        assert_eq!(decoded, bloom);
+        assert!(RootBloom::from_bytes(&bloom.to_bytes()[1..]).is_err());
+    }
+
+    #[test]
+    fn test_publish_and_prove() {
+        let mut index = RootIndex::new();
+        assert!(index.is_empty());
+        assert_eq!(index.commitment(), None);
+        for i in 0..37 {
+            assert_eq!(index.publish(&root(i)), i as u64);
+        }
+
+        let commitment = *index.commitment().unwrap();
+        for i in 0..37 {
+            assert!(index.contains(&root(i)));
+            let proof = index.prove(&root(i)).unwrap();
+            assert_eq!(proof.version(), i as u64);
+            assert!(proof.verify(&root(i), &commitment));
+            assert!(!proof.verify(&root(i + 1), &commitment));
+        }
+        assert!(!index.contains(&root(37)));
+        assert_eq!(index.version_of(&root(37)), None);
+        assert_eq!(index.prove(&root(37)), None);
+
+        // republishing a root keeps its first version
+        assert_eq!(index.publish(&root(3)), 37);
+        assert_eq!(index.version_of(&root(3)), Some(3));
+        assert_eq!(index.root(37), Some(&root(3)));
+    }
+
+    #[test]
+    fn test_bloom_grows_with_index() {
+        let mut index = RootIndex::new();
+        for i in 0..(DEFAULT_CAPACITY as u32 * 3) {
+            index.publish(&root(i));
+        }
+        assert!(index.bloom_capacity >= index.len());
+        assert!((0..index.len() as u32).all(|i| index.contains(&root(i))));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut index = RootIndex::new();
+        for i in 0..10 {
+            index.publish(&root(i));
+        }
+        let mut bytes = Vec::new();
+        index.save(&mut bytes).unwrap();
+        let loaded = RootIndex::load(bytes.as_slice()).unwrap();
+        assert_eq!(loaded.len(), 10);
+        assert_eq!(loaded.commitment(), index.commitment());
+        assert_eq!(loaded.bloom(), index.bloom());
+
+        assert!(RootIndex::load(&bytes[..bytes.len() - 1]).is_err());
+        bytes[0] = b'X';
+        assert!(RootIndex::load(bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+//! Hashing schemes defining how leaves and interior nodes are hashed
+
+use digest::{Digest, Output};
+
+/// Defines how leaf payloads and pairs of child nodes are hashed
+pub trait HashScheme<D: Digest> {
+    /// name identifying the scheme in parameter fingerprints
+    const NAME: &'static str;
+
+    /// returns the hash of a leaf payload
+    fn hash_leaf(data: &[u8]) -> Output<D>;
+
+    /// returns the hash of an interior node given its children
+    fn hash_node(left: &Output<D>, right: &Output<D>) -> Output<D>;
+
+    /// hashes consecutive pairs of children into their parents, `children` holds two nodes per
+    /// parent
+    /// Layers are rebuilt through this method, schemes with a multi-lane backend hash several pairs
+    /// at once here. The default hashes one pair after the other with `hash_node`.
+    fn hash_nodes(children: &[Output<D>], parents: &mut [Output<D>]) {
+        debug_assert_eq!(children.len(), 2 * parents.len());
+        for (parent, pair) in parents.iter_mut().zip(children.chunks_exact(2)) {
+            *parent = Self::hash_node(&pair[0], &pair[1]);
+        }
+    }
+
+    /// returns the root of a tree without leaves, the hash of the empty string like in RFC 6962
+    fn empty_root() -> Output<D> {
+        D::digest([])
+    }
+}
+
+/// Hashes leaves and nodes without domain separation, as `MerkleTree` does
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Plain;
+
+impl<D: Digest> HashScheme<D> for Plain {
+    const NAME: &'static str = "plain";
+
+    fn hash_leaf(data: &[u8]) -> Output<D> {
+        D::digest(data)
+    }
+
+    fn hash_node(left: &Output<D>, right: &Output<D>) -> Output<D> {
+        let mut hasher = D::new();
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize()
+    }
+}
+
+/// Certificate Transparency hashing as specified in RFC 6962
+/// Leaf hashes are prefixed with `0x00` and interior node hashes with `0x01`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rfc6962;
+
+impl Rfc6962 {
+    /// prefix of leaf hashes
+    pub const LEAF_PREFIX: u8 = 0x00;
+    /// prefix of interior node hashes
+    pub const NODE_PREFIX: u8 = 0x01;
+}
+
+impl<D: Digest> HashScheme<D> for Rfc6962 {
+    const NAME: &'static str = "rfc6962";
+
+    fn hash_leaf(data: &[u8]) -> Output<D> {
+        let mut hasher = D::new();
+        hasher.update([Self::LEAF_PREFIX]);
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    fn hash_node(left: &Output<D>, right: &Output<D>) -> Output<D> {
+        let mut hasher = D::new();
+        hasher.update([Self::NODE_PREFIX]);
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize()
+    }
+}
+
+/// Hashes interior nodes with their children in ascending order, as OpenZeppelin's `MerkleProof`
+/// Proofs don't need to tell on which side the siblings are, see `ethereum` for keccak256 trees.
+/// Leaf payloads are hashed without domain separation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SortedPair;
+
+impl<D: Digest> HashScheme<D> for SortedPair {
+    const NAME: &'static str = "sorted-pair";
+
+    fn hash_leaf(data: &[u8]) -> Output<D> {
+        D::digest(data)
+    }
+
+    fn hash_node(left: &Output<D>, right: &Output<D>) -> Output<D> {
+        let (first, second) = if left <= right {
+            (left, right)
+        } else {
+            (right, left)
+        };
+        let mut hasher = D::new();
+        hasher.update(first);
+        hasher.update(second);
+        hasher.finalize()
+    }
+}
+
+/// Hashes leaves and nodes twice without domain separation, as Bitcoin's double SHA-256
+/// See `bitcoin` for block Merkle trees.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DoubleHash;
+
+impl<D: Digest> HashScheme<D> for DoubleHash {
+    const NAME: &'static str = "double";
+
+    fn hash_leaf(data: &[u8]) -> Output<D> {
+        D::digest(D::digest(data))
+    }
+
+    fn hash_node(left: &Output<D>, right: &Output<D>) -> Output<D> {
+        let mut hasher = D::new();
+        hasher.update(left);
+        hasher.update(right);
+        D::digest(hasher.finalize())
+    }
+}
@@ -0,0 +1,288 @@
+//! Serde support for trees and proofs
+//!
+//! Hashes are encoded as lowercase hex strings in human-readable formats like JSON and as raw bytes
+//! in binary formats. Trees are serialized as their leaves, the intermediate layers are recomputed
+//! when deserializing so a deserialized tree is always consistent. Subtree packages are serialized
+//! as they are, the coordinator validates them when they are added.
+
+use std::fmt::{self, Debug};
+use std::marker::PhantomData;
+
+use digest::{Digest, Output};
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::{SerializeSeq, SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+
+use crate::{HashScheme, MerkleTree, Proof, SubtreePackage};
+
+/// serializes a single hash
+struct Hash<'a, D: Digest>(&'a Output<D>);
+
+impl<D: Digest> Serialize for Hash<'_, D> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode(self.0))
+        } else {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+}
+
+/// serializes a list of hashes
+struct Hashes<'a, D: Digest>(&'a [Output<D>]);
+
+impl<D: Digest> Serialize for Hashes<'_, D> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for hash in self.0 {
+            seq.serialize_element(&Hash::<D>(hash))?;
+        }
+        seq.end()
+    }
+}
+
+/// deserializes a single hash
+struct HashBuf<D: Digest>(Output<D>);
+
+impl<'de, D: Digest> Deserialize<'de> for HashBuf<D> {
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(HashVisitor(PhantomData))
+        } else {
+            deserializer.deserialize_bytes(HashVisitor(PhantomData))
+        }
+    }
+}
+
+struct HashVisitor<D>(PhantomData<D>);
+
+impl<'de, D: Digest> Visitor<'de> for HashVisitor<D> {
+    type Value = HashBuf<D>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a hash of {} bytes", <D as Digest>::output_size())
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        let bytes = hex::decode(value).map_err(E::custom)?;
+        self.visit_bytes(&bytes)
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+        if value.len() != <D as Digest>::output_size() {
+            return Err(E::invalid_length(value.len(), &self));
+        }
+        let mut hash = Output::<D>::default();
+        hash.copy_from_slice(value);
+        Ok(HashBuf(hash))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::with_capacity(<D as Digest>::output_size());
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+        self.visit_bytes(&bytes)
+    }
+}
+
+fn into_hashes<D: Digest>(hashes: Vec<HashBuf<D>>) -> Vec<Output<D>> {
+    hashes.into_iter().map(|hash| hash.0).collect()
+}
+
+impl<D, H> Serialize for Proof<D, H>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    H: HashScheme<D>,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut proof = serializer.serialize_struct("Proof", 2)?;
+        proof.serialize_field("leaf_index", &self.leaf_index())?;
+        proof.serialize_field("siblings", &Hashes::<D>(self.siblings()))?;
+        proof.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(bound = "", rename = "Proof")]
+struct ProofRepr<D: Digest> {
+    leaf_index: usize,
+    siblings: Vec<HashBuf<D>>,
+}
+
+impl<'de, D, H> Deserialize<'de> for Proof<D, H>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    H: HashScheme<D>,
+{
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        let proof = ProofRepr::<D>::deserialize(deserializer)?;
+        Ok(Proof::new(proof.leaf_index, into_hashes(proof.siblings)))
+    }
+}
+
+impl<D, H> Serialize for MerkleTree<D, H>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    H: HashScheme<D>,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tree = serializer.serialize_struct("MerkleTree", 3)?;
+        tree.serialize_field("leaves", &Hashes::<D>(self.leaves().as_slice()))?;
+        tree.serialize_field("padding", &Hash::<D>(self.padding()))?;
+        tree.serialize_field("next_offset", &self.next_offset())?;
+        tree.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(bound = "", rename = "MerkleTree")]
+struct TreeRepr<D: Digest> {
+    leaves: Vec<HashBuf<D>>,
+    padding: HashBuf<D>,
+    next_offset: usize,
+}
+
+impl<'de, D, H> Deserialize<'de> for MerkleTree<D, H>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    H: HashScheme<D>,
+{
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        let tree = TreeRepr::<D>::deserialize(deserializer)?;
+        let leaves = into_hashes(tree.leaves);
+        MerkleTree::from_parts(&leaves, &tree.padding.0, tree.next_offset).ok_or_else(|| {
+            de::Error::custom(format!(
+                "invalid tree with {} leaves and next offset {}",
+                leaves.len(),
+                tree.next_offset
+            ))
+        })
+    }
+}
+
+impl<D: Digest> Serialize for SubtreePackage<D> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut package = serializer.serialize_struct("SubtreePackage", 6)?;
+        package.serialize_field("start", &self.range.start)?;
+        package.serialize_field("end", &self.range.end)?;
+        package.serialize_field("depth", &self.depth)?;
+        package.serialize_field("root", &Hash::<D>(&self.root))?;
+        package.serialize_field("frontier", &Hashes::<D>(&self.frontier))?;
+        package.serialize_field("nodes", &self.nodes.as_deref().map(Hashes::<D>))?;
+        package.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(bound = "", rename = "SubtreePackage")]
+struct PackageRepr<D: Digest> {
+    start: usize,
+    end: usize,
+    depth: usize,
+    root: HashBuf<D>,
+    frontier: Vec<HashBuf<D>>,
+    nodes: Option<Vec<HashBuf<D>>>,
+}
+
+impl<'de, D: Digest> Deserialize<'de> for SubtreePackage<D> {
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        let package = PackageRepr::<D>::deserialize(deserializer)?;
+        Ok(SubtreePackage {
+            range: package.start..package.end,
+            depth: package.depth,
+            root: package.root.0,
+            frontier: into_hashes(package.frontier),
+            nodes: package.nodes.map(into_hashes),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type Proof = crate::Proof<Sha3_256>;
+
+    fn test_tree() -> MerkleTree {
+        let leaves: Vec<Output<Sha3_256>> = (0..5).map(|i| [i as u8; 32].into()).collect();
+        MerkleTree::from_leaves(&leaves, &[0xab; 32].into())
+    }
+
+    #[test]
+    fn test_proof_json() {
+        let proof = test_tree().create_proof(2);
+        let json = serde_json::to_value(&proof).unwrap();
+        assert_eq!(json["leaf_index"], 2);
+        assert_eq!(
+            json["siblings"][0],
+            hex::encode(proof.siblings()[0]).as_str()
+        );
+        let decoded: Proof = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded, proof);
+
+        let invalid = r#"{"leaf_index": 0, "siblings": ["abcd"]}"#;
+        assert!(serde_json::from_str::<Proof>(invalid).is_err());
+    }
+
+    #[test]
+    fn test_tree_json() {
+        let tree = test_tree();
+        let json = serde_json::to_string(&tree).unwrap();
+        let decoded: MerkleTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.root_hash(), tree.root_hash());
+        assert_eq!(decoded.next_offset(), 5);
+
+        let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        value["next_offset"] = 9.into();
+        assert!(serde_json::from_value::<MerkleTree>(value.clone()).is_err());
+        value["next_offset"] = 5.into();
+        value["leaves"].as_array_mut().unwrap().pop();
+        assert!(serde_json::from_value::<MerkleTree>(value).is_err());
+        // empty trees round trip
+        let empty = MerkleTree::empty(&[0xab; 32].into());
+        let decoded: MerkleTree =
+            serde_json::from_str(&serde_json::to_string(&empty).unwrap()).unwrap();
+        assert!(decoded.is_empty());
+        assert_eq!(decoded.root_hash(), empty.root_hash());
+    }
+
+    #[test]
+    fn test_binary() {
+        let tree = test_tree();
+        let bytes = bincode::serialize(&tree).unwrap();
+        // 8 leaves and the padding as raw bytes with a length prefix each
+        assert!(bytes.len() < 9 * 40 + 32);
+        let decoded: MerkleTree = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.root_hash(), tree.root_hash());
+
+        let proof = tree.create_proof(4);
+        let decoded: Proof = bincode::deserialize(&bincode::serialize(&proof).unwrap()).unwrap();
+        assert_eq!(decoded, proof);
+    }
+
+    #[test]
+    fn test_package() {
+        let tree = test_tree();
+        for with_nodes in [false, true] {
+            let package = SubtreePackage::<Sha3_256>::new(2, &tree, with_nodes);
+            let json = serde_json::to_value(&package).unwrap();
+            assert_eq!(json["start"], 16);
+            assert_eq!(json["end"], 21);
+            assert_eq!(json["nodes"].is_null(), !with_nodes);
+            let decoded: SubtreePackage<Sha3_256> = serde_json::from_value(json).unwrap();
+            assert_eq!(decoded, package);
+
+            let bytes = bincode::serialize(&package).unwrap();
+            let decoded: SubtreePackage<Sha3_256> = bincode::deserialize(&bytes).unwrap();
+            assert_eq!(decoded, package);
+        }
+    }
+}
@@ -0,0 +1,834 @@
+//! Sharded tree construction
+//!
+//! The leaves of a tree are split into `K` contiguous shards of equal size (`K` a power of two),
+//! each shard is the subtree below one node of layer `log2(K)`. `from_leaves_sharded` builds the
+//! shards on `K` threads directly inside the node buffer of the final tree and then computes the
+//! layers above them. Shards built elsewhere, e.g. on other machines, are joined with
+//! `from_shards`.
+//!
+//! For builds spread over several machines every worker sends a `SubtreePackage` of its shard to a
+//! `BuildCoordinator`. A package holds the root and the frontier of the subtree, which prove the
+//! number of used leaves, and optionally all nodes of the subtree. The coordinator validates each
+//! package on arrival, computes the root of the tree from the subtree roots alone and assembles
+//! the complete tree if all packages carry their nodes.
+
+use std::fmt;
+use std::thread;
+
+use core::fmt::Debug;
+use core::ops::Range;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError, MAX_DEPTH};
+
+impl<D, S> MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug + Send,
+    Output<D>: Copy + Send + Sync,
+    S: HashScheme<D>,
+{
+    /// creates a new Merkle tree from the given leaves like `from_leaves`, building the subtrees of
+    /// up to `shards` contiguous leaf ranges on separate threads
+    /// the number of shards is rounded up to the next power of two and limited to the number of
+    /// leaves
+    pub fn from_leaves_sharded(leaves: &[Output<D>], padding: &Output<D>, shards: usize) -> Self {
+        let mut tree = Self::with_leaves(leaves, padding);
+        let depth = tree.depth();
+        if depth <= 1 {
+            return tree;
+        }
+        // the shard roots are on this layer
+        let shard_layer = shards.max(1).next_power_of_two().trailing_zeros() as usize;
+        let shard_layer = shard_layer.min(depth - 1);
+        let num_shards = 1 << shard_layer;
+
+        // split every layer below the shard roots into one chunk per shard
+        let mut chunks: Vec<Vec<&mut [Output<D>]>> = (0..num_shards).map(|_| Vec::new()).collect();
+        let mut rest = &mut tree.nodes_mut()[Self::index(shard_layer, 0)..];
+        for layer in shard_layer..depth {
+            let (nodes, lower) = rest.split_at_mut(1 << layer);
+            rest = lower;
+            for (shard, chunk) in nodes.chunks_mut(1 << (layer - shard_layer)).enumerate() {
+                chunks[shard].push(chunk);
+            }
+        }
+        thread::scope(|scope| {
+            for mut layers in chunks {
+                scope.spawn(move || {
+                    for layer in (0..layers.len() - 1).rev() {
+                        let (upper, lower) = layers.split_at_mut(layer + 1);
+                        let children = &lower[0];
+                        for (node, pair) in upper[layer].iter_mut().zip(children.chunks_exact(2)) {
+                            *node = Self::hash_pair(&pair[0], &pair[1]);
+                        }
+                    }
+                });
+            }
+        });
+        tree.build_layers_above(shard_layer);
+        tree
+    }
+}
+
+impl<D, S> MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// joins subtrees of the same depth into one tree, shard `i` holds the leaves
+    /// `i * n..(i + 1) * n` of the tree with `n` leaves per shard
+    /// missing shards up to the next power of two are filled with the padding value, the next
+    /// pushed leaf follows the last used leaf of the last shard with leaves
+    /// returns an error if the shards don't have the same depth or the tree would be too deep
+    pub fn from_shards(shards: &[Self], padding: &Output<D>) -> Result<Self, MerkleTreeError> {
+        let Some(first) = shards.first() else {
+            return Ok(Self::empty(padding));
+        };
+        let shard_depth = first.depth();
+        if let Some(shard) = shards.iter().find(|shard| shard.depth() != shard_depth) {
+            return Err(MerkleTreeError::DepthMismatch {
+                expected: shard_depth,
+                actual: shard.depth(),
+            });
+        }
+        let shard_layer = shards.len().next_power_of_two().trailing_zeros() as usize;
+        let depth = shard_layer + shard_depth;
+        if shard_depth == 0 || depth > MAX_DEPTH {
+            return Err(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            });
+        }
+
+        let mut tree = Self::try_new(depth, padding)?;
+        let nodes = tree.nodes_mut();
+        for (i, shard) in shards.iter().enumerate() {
+            for layer in 0..shard_depth {
+                let width = 1 << layer;
+                let start = Self::index(shard_layer + layer, i * width);
+                nodes[start..start + width].copy_from_slice(shard.iter_layer(layer).as_slice());
+            }
+        }
+        tree.build_layers_above(shard_layer);
+        let next_offset = shards
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, shard)| shard.next_offset() > 0)
+            .map_or(0, |(i, shard)| i * first.num_leaves() + shard.next_offset());
+        tree.set_next_offset(next_offset);
+        Ok(tree)
+    }
+
+    /// joins two trees of the same depth into a tree one layer deeper holding the leaves of `left`
+    /// followed by the leaves of `right`
+    /// The nodes are moved into the node buffer of `left` layer by layer, only the new root is
+    /// hashed. The merged tree keeps the padding of `left`.
+    /// panics if the depths differ or the tree would be too deep, see `try_merge` for a fallible
+    /// version
+    pub fn merge(left: Self, right: Self) -> Self {
+        match Self::try_merge(left, right) {
+            Ok(tree) => tree,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// joins two trees of the same depth into a tree one layer deeper like `merge`
+    /// returns an error if the depths differ, the trees are empty or the tree would be too deep
+    pub fn try_merge(mut left: Self, right: Self) -> Result<Self, MerkleTreeError> {
+        let child_depth = left.depth();
+        if right.depth() != child_depth {
+            return Err(MerkleTreeError::DepthMismatch {
+                expected: child_depth,
+                actual: right.depth(),
+            });
+        }
+        let depth = child_depth + 1;
+        if child_depth == 0 || depth > MAX_DEPTH {
+            return Err(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            });
+        }
+
+        let padding = *left.padding();
+        let mut nodes = left.take_nodes();
+        if nodes.is_empty() {
+            // mapped trees have no heap allocation to reuse
+            nodes = (0..child_depth)
+                .flat_map(|layer| left.iter_layer(layer).copied())
+                .collect();
+        }
+        nodes.resize(Self::index(depth, 0), padding);
+        // every layer moves one layer down, starting at the leaves so no layer is overwritten
+        // before it was moved
+        for layer in (0..child_depth).rev() {
+            let width = 1 << layer;
+            let src = Self::index(layer, 0);
+            let dst = Self::index(layer + 1, 0);
+            nodes.copy_within(src..src + width, dst);
+            nodes[dst + width..dst + 2 * width].copy_from_slice(right.iter_layer(layer).as_slice());
+        }
+        nodes[0] = Self::hash_pair(&nodes[1], &nodes[2]);
+
+        let next_offset = match right.next_offset() {
+            0 => left.next_offset(),
+            offset => right.num_leaves() + offset,
+        };
+        Ok(Self::from_node_buf(
+            depth,
+            nodes.into(),
+            next_offset,
+            padding,
+        ))
+    }
+}
+
+/// Errors returned when validating and assembling subtree packages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackageError {
+    /// the shard index is not smaller than the number of shards of the build
+    ShardOutOfRange { shard: usize, num_shards: usize },
+    /// a package of the shard was already added
+    DuplicateShard { shard: usize },
+    /// the subtree doesn't have the depth of the shards of the build
+    DepthMismatch {
+        shard: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// the leaf range is not aligned to the shard or longer than the shard
+    InvalidRange { shard: usize },
+    /// the frontier doesn't match the number of used leaves or doesn't lead to the subtree root
+    InvalidFrontier { shard: usize },
+    /// the nodes don't form a consistent subtree with the root and the used leaves of the package
+    InvalidNodes { shard: usize },
+    /// no package of the shard was added
+    MissingShard { shard: usize },
+    /// the package of the shard doesn't carry its nodes
+    MissingNodes { shard: usize },
+    /// the shard is not full but followed by shards with leaves
+    IncompleteShard { shard: usize },
+}
+
+impl fmt::Display for PackageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ShardOutOfRange { shard, num_shards } => {
+                write!(f, "shard {shard} is out of range for {num_shards} shards")
+            }
+            Self::DuplicateShard { shard } => write!(f, "duplicate package for shard {shard}"),
+            Self::DepthMismatch {
+                shard,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "subtree of shard {shard} must have depth {expected}, got {actual}"
+            ),
+            Self::InvalidRange { shard } => write!(f, "invalid leaf range for shard {shard}"),
+            Self::InvalidFrontier { shard } => write!(f, "invalid frontier for shard {shard}"),
+            Self::InvalidNodes { shard } => write!(f, "invalid nodes for shard {shard}"),
+            Self::MissingShard { shard } => write!(f, "missing package for shard {shard}"),
+            Self::MissingNodes { shard } => write!(f, "package of shard {shard} has no nodes"),
+            Self::IncompleteShard { shard } => {
+                write!(f, "shard {shard} is not full but followed by leaves")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PackageError {}
+
+/// The result of building one shard, as exchanged between a worker and the coordinator
+#[derive(Debug, Clone)]
+pub struct SubtreePackage<D: Digest> {
+    /// leaf offsets of the final tree holding the used leaves of the shard, the range starts at the
+    /// first leaf of the shard
+    pub range: Range<usize>,
+    /// depth of the subtree
+    pub depth: usize,
+    /// root hash of the subtree
+    pub root: Output<D>,
+    /// roots of the largest complete subtrees covering the used leaves from left to right, the
+    /// remaining leaves of the shard are padding
+    pub frontier: Vec<Output<D>>,
+    /// all nodes of the subtree in breadth-first order, root first
+    pub nodes: Option<Vec<Output<D>>>,
+}
+
+impl<D: Digest> PartialEq for SubtreePackage<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.range == other.range
+            && self.depth == other.depth
+            && self.root == other.root
+            && self.frontier == other.frontier
+            && self.nodes == other.nodes
+    }
+}
+
+impl<D: Digest> Eq for SubtreePackage<D> {}
+
+impl<D> SubtreePackage<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// creates the package of a shard from its subtree, the used leaves are the ones below the next
+    /// offset of the subtree
+    /// the nodes are only included if `with_nodes` is set
+    pub fn new(shard: usize, subtree: &MerkleTree<D>, with_nodes: bool) -> Self {
+        let depth = subtree.depth();
+        let start = shard * subtree.num_leaves();
+        let used = subtree.next_offset();
+        let mut frontier = Vec::new();
+        let mut offset = 0;
+        for height in (0..depth).rev() {
+            if used & (1 << height) != 0 {
+                let layer = depth - 1 - height;
+                frontier.push(*subtree.node(MerkleTree::<D>::index(layer, offset >> height)));
+                offset += 1 << height;
+            }
+        }
+        Self {
+            range: start..start + used,
+            depth,
+            root: *subtree.root_hash(),
+            frontier,
+            nodes: with_nodes.then(|| {
+                (0..depth)
+                    .flat_map(|layer| subtree.iter_layer(layer).copied())
+                    .collect()
+            }),
+        }
+    }
+
+    /// builds the subtree of a shard with the given depth from its used leaves and creates its
+    /// package like `new`
+    /// returns an error if the depth is out of range or the leaves don't fit into the subtree
+    pub fn from_leaves(
+        shard: usize,
+        depth: usize,
+        leaves: &[Output<D>],
+        padding: &Output<D>,
+        with_nodes: bool,
+    ) -> Result<Self, MerkleTreeError> {
+        if depth == 0 {
+            return Err(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            });
+        }
+        let mut subtree = MerkleTree::try_new(depth, padding)?;
+        if leaves.len() > subtree.num_leaves() {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: leaves.len() - 1,
+                num_leaves: subtree.num_leaves(),
+            });
+        }
+        let first_leaf = MerkleTree::<D>::index(depth - 1, 0);
+        subtree.nodes_mut()[first_leaf..first_leaf + leaves.len()].copy_from_slice(leaves);
+        subtree.build_layers_above(depth - 1);
+        subtree.set_next_offset(leaves.len());
+        Ok(Self::new(shard, &subtree, with_nodes))
+    }
+
+    /// returns the number of leaves of the shard, 0 if the depth is out of range
+    pub fn capacity(&self) -> usize {
+        if self.depth > MAX_DEPTH {
+            return 0;
+        }
+        (1 << self.depth) >> 1
+    }
+
+    /// returns the index of the shard
+    pub fn shard(&self) -> usize {
+        self.range.start.checked_div(self.capacity()).unwrap_or(0)
+    }
+
+    /// checks that the range fits the shard, that the frontier leads to the root and that the nodes,
+    /// if included, are consistent with both
+    pub fn validate(&self, padding: &Output<D>) -> Result<(), PackageError> {
+        let shard = self.shard();
+        let capacity = self.capacity();
+        if self.depth == 0
+            || self.depth > MAX_DEPTH
+            || !self.range.start.is_multiple_of(capacity)
+            || self.range.end < self.range.start
+            || self.range.len() > capacity
+        {
+            return Err(PackageError::InvalidRange { shard });
+        }
+        let used = self.range.len();
+        if self.frontier.len() != used.count_ones() as usize {
+            return Err(PackageError::InvalidFrontier { shard });
+        }
+        let padding_roots = padding_roots::<D>(padding, self.depth);
+        let mut frontier = self.frontier.iter();
+        let root = frontier_root::<D>(&padding_roots, self.depth - 1, used, &mut frontier);
+        if root != self.root {
+            return Err(PackageError::InvalidFrontier { shard });
+        }
+        if let Some(nodes) = &self.nodes {
+            let subtree = self
+                .subtree(nodes, padding)
+                .ok_or(PackageError::InvalidNodes { shard })?;
+            if subtree.root_hash() != &self.root {
+                return Err(PackageError::InvalidNodes { shard });
+            }
+        }
+        Ok(())
+    }
+
+    /// returns the subtree holding the nodes if they are consistent and the leaves behind the used
+    /// ones are padding
+    fn subtree(&self, nodes: &[Output<D>], padding: &Output<D>) -> Option<MerkleTree<D>> {
+        let mut subtree = MerkleTree::try_new(self.depth, padding).ok()?;
+        if nodes.len() != subtree.num_nodes() {
+            return None;
+        }
+        subtree.nodes_mut().copy_from_slice(nodes);
+        subtree.set_next_offset(self.range.len());
+        subtree.check_integrity().ok()?;
+        let mut unused = subtree.leaves().skip(self.range.len());
+        unused.all(|leaf| leaf == padding).then_some(subtree)
+    }
+}
+
+/// returns the roots of subtrees holding only padding, indexed by their height
+fn padding_roots<D>(padding: &Output<D>, depth: usize) -> Vec<Output<D>>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    let mut roots = vec![*padding];
+    for height in 1..depth {
+        let below = roots[height - 1];
+        roots.push(MerkleTree::<D>::hash_pair(&below, &below));
+    }
+    roots
+}
+
+/// returns the root of a subtree of the given height whose first `used` leaves are covered by the
+/// frontier, consuming the frontier from the left
+fn frontier_root<'a, D>(
+    padding_roots: &[Output<D>],
+    height: usize,
+    used: usize,
+    frontier: &mut impl Iterator<Item = &'a Output<D>>,
+) -> Output<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    if used == 0 {
+        return padding_roots[height];
+    }
+    if used == 1 << height {
+        // the frontier has one entry per set bit of the number of used leaves
+        return *frontier.next().expect("frontier length was checked");
+    }
+    let half = 1 << (height - 1);
+    let left = frontier_root::<D>(padding_roots, height - 1, used.min(half), frontier);
+    let right = frontier_root::<D>(
+        padding_roots,
+        height - 1,
+        used.saturating_sub(half),
+        frontier,
+    );
+    MerkleTree::<D>::hash_pair(&left, &right)
+}
+
+/// Collects the subtree packages of a distributed build and joins them into one tree
+/// Shard `i` holds the leaves `i * n..(i + 1) * n` of the tree with `n` leaves per shard, a shard
+/// may only be partially used if all shards behind it are empty.
+#[derive(Debug)]
+pub struct BuildCoordinator<D: Digest> {
+    shard_depth: usize,
+    padding: Output<D>,
+    packages: Vec<Option<SubtreePackage<D>>>,
+}
+
+impl<D> BuildCoordinator<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// creates a coordinator for a build of `num_shards` subtrees of the given depth
+    /// returns an error if the depth is 0 or the tree would be too deep
+    pub fn new(
+        num_shards: usize,
+        shard_depth: usize,
+        padding: &Output<D>,
+    ) -> Result<Self, MerkleTreeError> {
+        let depth = shard_depth + num_shards.next_power_of_two().trailing_zeros() as usize;
+        if shard_depth == 0 || depth > MAX_DEPTH {
+            return Err(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            });
+        }
+        Ok(Self {
+            shard_depth,
+            padding: *padding,
+            packages: (0..num_shards).map(|_| None).collect(),
+        })
+    }
+
+    /// validates a package and adds it to the build
+    pub fn add(&mut self, package: SubtreePackage<D>) -> Result<(), PackageError> {
+        let shard = package.shard();
+        if package.depth != self.shard_depth {
+            return Err(PackageError::DepthMismatch {
+                shard,
+                expected: self.shard_depth,
+                actual: package.depth,
+            });
+        }
+        package.validate(&self.padding)?;
+        let num_shards = self.packages.len();
+        let slot = self
+            .packages
+            .get_mut(shard)
+            .ok_or(PackageError::ShardOutOfRange { shard, num_shards })?;
+        if slot.is_some() {
+            return Err(PackageError::DuplicateShard { shard });
+        }
+        *slot = Some(package);
+        Ok(())
+    }
+
+    /// returns the shards without a package
+    pub fn missing(&self) -> Vec<usize> {
+        (0..self.packages.len())
+            .filter(|&shard| self.packages[shard].is_none())
+            .collect()
+    }
+
+    /// returns true if all shards have a package
+    pub fn is_complete(&self) -> bool {
+        self.packages.iter().all(Option::is_some)
+    }
+
+    /// returns the root hash of the tree, computed from the subtree roots
+    /// returns an error if a package is missing or a partially used shard is followed by leaves
+    pub fn root(&self) -> Result<Output<D>, PackageError> {
+        let packages = self.packages()?;
+        let padding_root =
+            padding_roots::<D>(&self.padding, self.shard_depth)[self.shard_depth - 1];
+        let mut layer: Vec<Output<D>> = packages.iter().map(|package| package.root).collect();
+        layer.resize(layer.len().next_power_of_two(), padding_root);
+        while layer.len() > 1 {
+            layer = layer
+                .chunks_exact(2)
+                .map(|pair| MerkleTree::<D>::hash_pair(&pair[0], &pair[1]))
+                .collect();
+        }
+        Ok(layer[0])
+    }
+
+    /// joins the subtrees into one tree like `MerkleTree::from_shards`
+    /// returns an error if a package is missing, doesn't carry its nodes or a partially used shard
+    /// is followed by leaves
+    pub fn assemble(&self) -> Result<MerkleTree<D>, PackageError> {
+        let subtrees = self
+            .packages()?
+            .into_iter()
+            .map(|package| {
+                let shard = package.shard();
+                let nodes = package
+                    .nodes
+                    .as_ref()
+                    .ok_or(PackageError::MissingNodes { shard })?;
+                package
+                    .subtree(nodes, &self.padding)
+                    .ok_or(PackageError::InvalidNodes { shard })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(MerkleTree::from_shards(&subtrees, &self.padding)
+            .expect("the coordinator checked the depths of the shards"))
+    }
+
+    /// returns all packages if the leaves of the build are contiguous
+    fn packages(&self) -> Result<Vec<&SubtreePackage<D>>, PackageError> {
+        let mut packages: Vec<&SubtreePackage<D>> = Vec::with_capacity(self.packages.len());
+        for (shard, package) in self.packages.iter().enumerate() {
+            let package = package
+                .as_ref()
+                .ok_or(PackageError::MissingShard { shard })?;
+            if let Some(previous) = packages.last() {
+                if !package.range.is_empty() && previous.range.len() < previous.capacity() {
+                    return Err(PackageError::IncompleteShard { shard: shard - 1 });
+                }
+            }
+            packages.push(package);
+        }
+        Ok(packages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha3::{digest::Output, Sha3_256};
+
+    use super::PackageError;
+    use crate::MerkleTreeError;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type SubtreePackage = super::SubtreePackage<Sha3_256>;
+    type BuildCoordinator = super::BuildCoordinator<Sha3_256>;
+
+    fn layers(tree: &MerkleTree) -> Vec<Vec<Output<Sha3_256>>> {
+        (0..tree.depth())
+            .map(|layer| tree.iter_layer(layer).copied().collect())
+            .collect()
+    }
+
+    fn leaves(count: usize) -> Vec<Output<Sha3_256>> {
+        (0..count)
+            .map(|i| Output::<Sha3_256>::from([i as u8; 32]))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_leaves_sharded() {
+        let padding = [0xab; 32].into();
+        for count in [0, 1, 2, 5, 64, 100] {
+            let reference = MerkleTree::from_leaves(&leaves(count), &padding);
+            for shards in [0, 1, 3, 4, 16, 1024] {
+                let tree = MerkleTree::from_leaves_sharded(&leaves(count), &padding, shards);
+                assert_eq!(
+                    layers(&tree),
+                    layers(&reference),
+                    "{count} leaves in {shards} shards"
+                );
+                assert_eq!(tree.next_offset(), reference.next_offset());
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_shards() {
+        let padding = [0xab; 32].into();
+        let all = leaves(13);
+        let mut shards: Vec<MerkleTree> = all
+            .chunks(4)
+            .map(|chunk| MerkleTree::from_leaves(chunk, &padding))
+            .collect();
+        // the last shard has a single leaf and a depth of 1, so it doesn't fit
+        assert_eq!(
+            MerkleTree::from_shards(&shards, &padding).err(),
+            Some(MerkleTreeError::DepthMismatch {
+                expected: 3,
+                actual: 1
+            })
+        );
+
+        let mut last = MerkleTree::new(3, &padding);
+        last.set(0, &all[12]);
+        shards[3] = last;
+        let tree = MerkleTree::from_shards(&shards, &padding).unwrap();
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::from_leaves(&all, &padding).root_hash()
+        );
+        assert_eq!(tree.num_leaves(), 16);
+        let proof = tree.create_proof(9);
+        assert_eq!(tree.verify_proof(&all[9], &proof), *tree.root_hash());
+
+        // three shards are padded to four
+        let tree = MerkleTree::from_shards(&shards[..3], &padding).unwrap();
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::from_leaves(&all[..12], &padding).root_hash()
+        );
+        assert_eq!(tree.next_offset(), 12);
+
+        assert!(MerkleTree::from_shards(&[], &padding).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_merge() {
+        let padding = [0xab; 32].into();
+        let all = leaves(14);
+        let left = MerkleTree::from_leaves(&all[..8], &padding);
+        let right = MerkleTree::from_leaves(&all[8..], &padding);
+        let tree = MerkleTree::merge(left, right);
+        assert_eq!(tree.depth(), 5);
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::from_leaves(&all, &padding).root_hash()
+        );
+        assert_eq!(tree.check_integrity(), Ok(()));
+        assert_eq!(tree.next_offset(), 14);
+        let proof = tree.create_proof(11);
+        assert_eq!(tree.verify_proof(&all[11], &proof), *tree.root_hash());
+
+        // merged trees of one leaf each
+        let tree = MerkleTree::merge(
+            MerkleTree::from_leaves(&all[..1], &padding),
+            MerkleTree::from_leaves(&all[1..2], &padding),
+        );
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::from_leaves(&all[..2], &padding).root_hash()
+        );
+
+        assert_eq!(
+            MerkleTree::try_merge(
+                MerkleTree::from_leaves(&all[..4], &padding),
+                MerkleTree::from_leaves(&all[..2], &padding)
+            )
+            .err(),
+            Some(MerkleTreeError::DepthMismatch {
+                expected: 3,
+                actual: 2
+            })
+        );
+        assert!(
+            MerkleTree::try_merge(MerkleTree::empty(&padding), MerkleTree::empty(&padding))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_package_validation() {
+        let padding = [0xab; 32].into();
+        for count in 0..=8 {
+            for with_nodes in [false, true] {
+                let package =
+                    SubtreePackage::from_leaves(3, 4, &leaves(count), &padding, with_nodes)
+                        .unwrap();
+                assert_eq!(package.shard(), 3);
+                assert_eq!(package.range, 24..24 + count);
+                assert_eq!(package.frontier.len(), count.count_ones() as usize);
+                assert_eq!(package.validate(&padding), Ok(()));
+            }
+        }
+
+        let tree = MerkleTree::from_leaves(&leaves(5), &padding);
+        let package = SubtreePackage::new(1, &tree, true);
+        assert_eq!(
+            SubtreePackage::from_leaves(1, 4, &leaves(5), &padding, true),
+            Ok(package.clone())
+        );
+        assert!(SubtreePackage::from_leaves(1, 3, &leaves(5), &padding, true).is_err());
+
+        let mut invalid = package.clone();
+        invalid.range = 9..14;
+        assert_eq!(
+            invalid.validate(&padding),
+            Err(PackageError::InvalidRange { shard: 1 })
+        );
+        let mut invalid = package.clone();
+        invalid.range = 8..17;
+        assert_eq!(
+            invalid.validate(&padding),
+            Err(PackageError::InvalidRange { shard: 1 })
+        );
+        // claiming fewer leaves than the subtree holds
+        let mut invalid = package.clone();
+        invalid.range = 8..12;
+        invalid.frontier = vec![invalid.frontier[0]];
+        assert_eq!(
+            invalid.validate(&padding),
+            Err(PackageError::InvalidFrontier { shard: 1 })
+        );
+        let mut invalid = package.clone();
+        invalid.frontier.swap(0, 1);
+        assert_eq!(
+            invalid.validate(&padding),
+            Err(PackageError::InvalidFrontier { shard: 1 })
+        );
+        let mut invalid = package.clone();
+        invalid.nodes.as_mut().unwrap()[9] = [0; 32].into();
+        assert_eq!(
+            invalid.validate(&padding),
+            Err(PackageError::InvalidNodes { shard: 1 })
+        );
+        let mut invalid = package;
+        invalid.nodes.as_mut().unwrap().pop();
+        assert_eq!(
+            invalid.validate(&padding),
+            Err(PackageError::InvalidNodes { shard: 1 })
+        );
+    }
+
+    #[test]
+    fn test_coordinator() {
+        let padding = [0xab; 32].into();
+        let all = leaves(21);
+        let reference = MerkleTree::from_leaves(&all, &padding);
+        let packages: Vec<SubtreePackage> = (0..4)
+            .map(|shard| {
+                let start = (shard * 8).min(all.len());
+                let end = (start + 8).min(all.len());
+                SubtreePackage::from_leaves(shard, 4, &all[start..end], &padding, shard != 1)
+                    .unwrap()
+            })
+            .collect();
+
+        let mut coordinator = BuildCoordinator::new(4, 4, &padding).unwrap();
+        for package in packages.iter().rev() {
+            assert_eq!(
+                coordinator.root(),
+                Err(PackageError::MissingShard { shard: 0 })
+            );
+            coordinator.add(package.clone()).unwrap();
+        }
+        assert!(coordinator.is_complete());
+        assert_eq!(
+            coordinator.add(packages[2].clone()),
+            Err(PackageError::DuplicateShard { shard: 2 })
+        );
+        assert_eq!(coordinator.root(), Ok(*reference.root_hash()));
+        // the roots don't need the nodes, the assembled tree does
+        assert_eq!(
+            coordinator.assemble().err(),
+            Some(PackageError::MissingNodes { shard: 1 })
+        );
+
+        let mut coordinator = BuildCoordinator::new(3, 4, &padding).unwrap();
+        for package in [&packages[0], &packages[2]] {
+            coordinator.add(package.clone()).unwrap();
+        }
+        assert_eq!(coordinator.missing(), vec![1]);
+        assert_eq!(
+            coordinator.add(packages[3].clone()),
+            Err(PackageError::ShardOutOfRange {
+                shard: 3,
+                num_shards: 3
+            })
+        );
+        let second = MerkleTree::from_leaves(&all[8..16], &padding);
+        coordinator
+            .add(SubtreePackage::new(1, &second, true))
+            .unwrap();
+        let tree = coordinator.assemble().unwrap();
+        assert_eq!(layers(&tree), layers(&reference));
+        assert_eq!(tree.next_offset(), 21);
+
+        // partially used shards must be the last ones with leaves
+        let mut coordinator = BuildCoordinator::new(2, 4, &padding).unwrap();
+        coordinator.add(packages[1].clone()).unwrap();
+        let partial = SubtreePackage::from_leaves(0, 4, &all[..1], &padding, false).unwrap();
+        coordinator.add(partial).unwrap();
+        assert_eq!(
+            coordinator.root(),
+            Err(PackageError::IncompleteShard { shard: 0 })
+        );
+
+        let shallow = MerkleTree::from_leaves(&all[..2], &padding);
+        assert_eq!(
+            coordinator.add(SubtreePackage::new(1, &shallow, false)),
+            Err(PackageError::DepthMismatch {
+                shard: 1,
+                expected: 4,
+                actual: 2
+            })
+        );
+        assert!(BuildCoordinator::new(4, 0, &padding).is_err());
+    }
+}
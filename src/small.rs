@@ -0,0 +1,211 @@
+//! Stack allocated trees for small numbers of leaves
+//!
+//! `SmallMerkleTree` holds up to 128 leaves (depth 8) in fixed size arrays instead of a heap
+//! allocated node vector. The number of leaves is a const generic, so all loops have constant
+//! bounds and are unrolled by the compiler. Roots and proofs are identical to the ones of a
+//! `MerkleTree` over the same leaves.
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use digest::{Digest, Output};
+
+use crate::{MerkleTree, MerkleTreeError, Proof};
+
+/// maximum number of leaves of a `SmallMerkleTree`
+pub const SMALL_TREE_MAX_LEAVES: usize = 128;
+
+/// A Merkle tree with `N` leaves stored on the stack
+/// `N` has to be a power of two not larger than `SMALL_TREE_MAX_LEAVES`.
+#[derive(Debug, Clone, Copy)]
+pub struct SmallMerkleTree<D: Digest, const N: usize>
+where
+    Output<D>: Copy,
+{
+    /// inner nodes in heap order, the root is at index 1 and node `i` has the children `2i` and
+    /// `2i + 1`, children from index `N` on are leaves
+    inner: [Output<D>; N],
+    /// leaf nodes
+    leaves: [Output<D>; N],
+}
+
+impl<D, const N: usize> SmallMerkleTree<D, N>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// depth of the tree, the root counts as the first layer like for `MerkleTree`
+    pub const DEPTH: usize = N.trailing_zeros() as usize + 1;
+
+    /// creates a new tree holding the given leaves
+    pub fn from_leaves(leaves: [Output<D>; N]) -> Self {
+        const {
+            assert!(
+                N.is_power_of_two() && N <= SMALL_TREE_MAX_LEAVES,
+                "the number of leaves has to be a power of two not larger than 128"
+            )
+        };
+        let mut tree = Self {
+            inner: [Output::<D>::default(); N],
+            leaves,
+        };
+        tree.build();
+        tree
+    }
+
+    /// creates a new tree with all leaves set to the given value
+    pub fn new(initial_value: &Output<D>) -> Self {
+        Self::from_leaves([*initial_value; N])
+    }
+
+    /// creates a new tree from raw leaf payloads, every payload is hashed to obtain the leaf value
+    pub fn from_leaf_data<T: AsRef<[u8]>>(data: &[T; N]) -> Self {
+        Self::from_leaves(core::array::from_fn(|i| D::digest(&data[i])))
+    }
+
+    /// returns the root hash of the tree
+    pub fn root_hash(&self) -> &Output<D> {
+        if N == 1 {
+            &self.leaves[0]
+        } else {
+            &self.inner[1]
+        }
+    }
+
+    /// returns the number of leaves in the tree
+    pub fn num_leaves(&self) -> usize {
+        N
+    }
+
+    /// returns the leaves of the tree
+    pub fn leaves(&self) -> &[Output<D>; N] {
+        &self.leaves
+    }
+
+    /// updates the value of a leaf node
+    /// panics if the offset is out of bounds, see `try_set` for a fallible version
+    pub fn set(&mut self, offset: usize, value: &Output<D>) {
+        if let Err(err) = self.try_set(offset, value) {
+            panic!("{err}");
+        }
+    }
+
+    /// updates the value of a leaf node
+    /// returns an error if the offset is out of bounds
+    pub fn try_set(&mut self, offset: usize, value: &Output<D>) -> Result<(), MerkleTreeError> {
+        self.check_offset(offset)?;
+        self.leaves[offset] = *value;
+        let mut index = (N + offset) / 2;
+        while index > 0 {
+            self.inner[index] =
+                MerkleTree::<D>::hash_pair(self.child(2 * index), self.child(2 * index + 1));
+            index /= 2;
+        }
+        Ok(())
+    }
+
+    /// creates a proof for a leaf node, see `MerkleTree::create_proof`
+    /// panics if the offset is out of bounds, see `try_create_proof` for a fallible version
+    pub fn create_proof(&self, offset: usize) -> Proof<D> {
+        match self.try_create_proof(offset) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a proof for a leaf node, see `MerkleTree::create_proof`
+    /// returns an error if the offset is out of bounds
+    pub fn try_create_proof(&self, offset: usize) -> Result<Proof<D>, MerkleTreeError> {
+        self.check_offset(offset)?;
+        let mut siblings = Vec::with_capacity(Self::DEPTH - 1);
+        let mut index = N + offset;
+        while index > 1 {
+            siblings.push(*self.child(index ^ 1));
+            index /= 2;
+        }
+        Ok(Proof::new(offset, siblings))
+    }
+
+    /// computes all inner nodes bottom-up
+    fn build(&mut self) {
+        for i in ((N / 2).max(1)..N).rev() {
+            self.inner[i] =
+                MerkleTree::<D>::hash_pair(&self.leaves[2 * i - N], &self.leaves[2 * i + 1 - N]);
+        }
+        for i in (1..N / 2).rev() {
+            self.inner[i] = MerkleTree::<D>::hash_pair(&self.inner[2 * i], &self.inner[2 * i + 1]);
+        }
+    }
+
+    /// returns the node at the given heap index
+    fn child(&self, index: usize) -> &Output<D> {
+        if index >= N {
+            &self.leaves[index - N]
+        } else {
+            &self.inner[index]
+        }
+    }
+
+    fn check_offset(&self, offset: usize) -> Result<(), MerkleTreeError> {
+        if offset >= N {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: N,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+
+    fn leaves<const N: usize>() -> [Output<Sha3_256>; N] {
+        std::array::from_fn(|i| [i as u8; 32].into())
+    }
+
+    fn check<const N: usize>() {
+        let mut tree = SmallMerkleTree::<Sha3_256, N>::from_leaves(leaves());
+        let mut reference = MerkleTree::from_leaves(&leaves::<N>(), &[0x00; 32].into());
+        assert_eq!(SmallMerkleTree::<Sha3_256, N>::DEPTH, reference.depth());
+        assert_eq!(tree.root_hash(), reference.root_hash());
+        for offset in 0..N {
+            assert_eq!(tree.create_proof(offset), reference.create_proof(offset));
+        }
+
+        let value = [0xff; 32].into();
+        tree.set(N - 1, &value);
+        reference.set(N - 1, &value);
+        assert_eq!(tree.root_hash(), reference.root_hash());
+        assert_eq!(
+            tree.try_set(N, &value),
+            Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: N,
+                num_leaves: N
+            })
+        );
+    }
+
+    #[test]
+    fn test_matches_merkle_tree() {
+        check::<1>();
+        check::<2>();
+        check::<8>();
+        check::<128>();
+    }
+
+    #[test]
+    fn test_from_leaf_data() {
+        let fields = [b"name".as_slice(), b"email", b"role", b"team"];
+        let tree = SmallMerkleTree::<Sha3_256, 4>::from_leaf_data(&fields);
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::from_leaf_data(&fields, &[0x00; 32].into()).root_hash()
+        );
+    }
+}
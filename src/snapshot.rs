@@ -0,0 +1,233 @@
+//! Copy-on-write snapshots of a `MerkleTree`
+//!
+//! `MerkleTree::snapshot` only bumps a version counter. Nodes that are part of a snapshot are copied
+//! into a per-node history the first time they are overwritten afterwards, so taking a snapshot is
+//! O(1) and every update costs at most one extra copy per touched node. Nodes are identified by
+//! their height above the leaves and their offset within the layer, which stays the same when the
+//! tree grows, so snapshots remain valid across `push`.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError, Proof};
+
+/// A version of a tree returned by `MerkleTree::snapshot`
+/// Versions are only meaningful for the tree that created them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TreeVersion {
+    /// number of snapshots taken before this one
+    version: u64,
+    /// depth of the tree at the time of the snapshot
+    depth: usize,
+}
+
+impl TreeVersion {
+    /// returns the number of snapshots taken before this one
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// returns the number of leaves of the tree at the time of the snapshot
+    pub fn num_leaves(&self) -> usize {
+        (1 << self.depth) >> 1
+    }
+}
+
+/// old values of a node, each with the last version the value was part of, sorted by version
+type Versions<D> = Vec<(u64, Output<D>)>;
+
+/// Old values of nodes that were overwritten after a snapshot
+#[derive(Debug, Default)]
+pub(crate) struct NodeHistory<D: Digest> {
+    /// number of snapshots taken so far, updates belong to the version of the next snapshot
+    next_version: AtomicUsize,
+    /// old values indexed by height and offset of the node
+    nodes: BTreeMap<(usize, usize), Versions<D>>,
+}
+
+impl<D: Digest> NodeHistory<D> {
+    /// starts a new version and returns the number of the finished one
+    fn snapshot(&self) -> u64 {
+        self.next_version.fetch_add(1, Ordering::Relaxed) as u64
+    }
+
+    /// records the value of a node before it is overwritten
+    pub(crate) fn record(&mut self, height: usize, offset: usize, old_value: &Output<D>) {
+        let next_version = *self.next_version.get_mut() as u64;
+        if next_version == 0 {
+            return;
+        }
+        let entries = self.nodes.entry((height, offset)).or_default();
+        // the value was written after the last snapshot if it was already recorded for it
+        if entries
+            .last()
+            .is_some_and(|(version, _)| *version == next_version - 1)
+        {
+            return;
+        }
+        entries.push((next_version - 1, old_value.clone()));
+    }
+
+    /// overwrites all recorded values with zeros and drops them
+    #[cfg(feature = "zeroize")]
+    pub(crate) fn zeroize(&mut self) {
+        for versions in self.nodes.values_mut() {
+            for (_, value) in versions.iter_mut() {
+                zeroize::Zeroize::zeroize(value.as_mut_slice());
+            }
+        }
+        self.nodes.clear();
+    }
+
+    /// returns the value of a node at the given version if it was overwritten since then
+    fn get(&self, height: usize, offset: usize, version: u64) -> Option<&Output<D>> {
+        let entries = self.nodes.get(&(height, offset))?;
+        let index = entries.partition_point(|(until, _)| *until < version);
+        entries.get(index).map(|(_, value)| value)
+    }
+}
+
+impl<D, S> MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// takes a snapshot of the current state of the tree
+    /// the root and proofs of the snapshot stay available via `root_at` and `create_proof_at`
+    pub fn snapshot(&self) -> TreeVersion {
+        TreeVersion {
+            version: self.history().snapshot(),
+            depth: self.depth(),
+        }
+    }
+
+    /// returns the root hash of the tree at the given version
+    pub fn root_at(&self, version: TreeVersion) -> Output<D> {
+        if version.depth == 0 {
+            return S::empty_root();
+        }
+        self.node_at(version, version.depth - 1, 0)
+    }
+
+    /// creates a proof for the leaf at the given offset against the root at the given version
+    /// panics if the offset is out of bounds of the snapshot, see `try_create_proof_at` for a
+    /// fallible version
+    pub fn create_proof_at(&self, version: TreeVersion, offset: usize) -> Proof<D, S> {
+        match self.try_create_proof_at(version, offset) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a proof for the leaf at the given offset against the root at the given version
+    /// returns an error if the offset is out of bounds of the snapshot
+    pub fn try_create_proof_at(
+        &self,
+        version: TreeVersion,
+        offset: usize,
+    ) -> Result<Proof<D, S>, MerkleTreeError> {
+        if offset >= version.num_leaves() {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: version.num_leaves(),
+            });
+        }
+        let siblings = (0..version.depth - 1)
+            .map(|height| self.node_at(version, height, (offset >> height) ^ 1))
+            .collect();
+        Ok(Proof::new(offset, siblings))
+    }
+
+    /// returns the node at the given height and offset at the given version
+    fn node_at(&self, version: TreeVersion, height: usize, offset: usize) -> Output<D> {
+        match self.history().get(height, offset, version.version) {
+            Some(value) => *value,
+            None => *self.node(Self::index(self.depth() - 1 - height, offset)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha3::{Digest, Sha3_256};
+
+    use crate::verify;
+
+    type MerkleTree = super::MerkleTree<Sha3_256>;
+
+    #[test]
+    fn test_root_at() {
+        let initial_value = Sha3_256::digest(b"zero");
+        let mut tree = MerkleTree::new(4, &initial_value);
+        let mut expected = Vec::new();
+        let mut versions = Vec::new();
+        for batch in 0..4u8 {
+            for offset in 0..batch as usize * 2 {
+                tree.set(offset, &Sha3_256::digest([batch, offset as u8]));
+            }
+            versions.push(tree.snapshot());
+            expected.push(*tree.root_hash());
+        }
+        // updates after the last snapshot don't change it
+        tree.set(0, &initial_value);
+        for (version, root) in versions.iter().zip(&expected) {
+            assert_eq!(tree.root_at(*version), *root);
+        }
+    }
+
+    #[test]
+    fn test_create_proof_at() {
+        let initial_value = Sha3_256::digest(b"zero");
+        let mut tree = MerkleTree::new(4, &initial_value);
+        tree.set(2, &Sha3_256::digest(b"two"));
+        let mut snapshot = MerkleTree::new(4, &initial_value);
+        snapshot.set(2, &Sha3_256::digest(b"two"));
+        let version = tree.snapshot();
+        tree.set(3, &Sha3_256::digest(b"three"));
+        tree.set(6, &Sha3_256::digest(b"six"));
+
+        for offset in 0..8 {
+            let proof = tree.create_proof_at(version, offset);
+            assert_eq!(proof, snapshot.create_proof(offset));
+            assert!(verify(
+                &tree.root_at(version),
+                snapshot.node(MerkleTree::index(3, offset)),
+                &proof
+            ));
+        }
+        assert!(tree.try_create_proof_at(version, 8).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_survives_growth() {
+        let padding = Sha3_256::digest(b"padding");
+        let leaves: Vec<_> = (0..6u8).map(|i| Sha3_256::digest([i])).collect();
+        let mut tree = MerkleTree::from_leaves(&leaves[..2], &padding);
+        let version = tree.snapshot();
+        let old = MerkleTree::from_leaves(&leaves[..2], &padding);
+        for leaf in &leaves[2..] {
+            tree.push(leaf);
+        }
+        tree.set(1, &padding);
+        assert_eq!(tree.root_at(version), *old.root_hash());
+        assert_eq!(tree.create_proof_at(version, 1), old.create_proof(1));
+        assert_eq!(version.num_leaves(), 2);
+    }
+
+    #[test]
+    fn test_snapshot_of_empty_tree() {
+        let padding = Sha3_256::digest(b"padding");
+        let mut tree = MerkleTree::empty(&padding);
+        let version = tree.snapshot();
+        tree.push(&Sha3_256::digest(b"leaf"));
+        tree.push(&Sha3_256::digest(b"leaf"));
+        assert_eq!(tree.root_at(version), Sha3_256::digest([]));
+        assert_eq!(version.num_leaves(), 0);
+        assert!(tree.try_create_proof_at(version, 0).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! Solidity verifiers and calldata for inclusion proofs
+//!
+//! `verifier_contract` emits the source of a contract whose
+//! `verify(bytes32[] proof, uint256 index, bytes32 leaf)` recomputes the root the way `verify` does
+//! for the digest `D` and the scheme `S`, and `verify_calldata` ABI encodes a `Proof` as the
+//! arguments of that function. Both come from the same `SolidityHash` and `SolidityScheme`
+//! implementations, so the off-chain tree and the on-chain verifier follow the same child ordering
+//! and prefix rules.
+//!
+//! Only keccak256 and SHA-256 are available in the EVM, the latter as a precompile. The verifier
+//! ignores the bits of the index above the proof length like `verify`. Its root is set once in the
+//! constructor, trees with changing roots need their own storage of the trusted root.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use digest::{Digest, Output};
+use sha2::Sha256;
+use sha3::Keccak256;
+
+use crate::ethereum::abi_word;
+use crate::{DoubleHash, HashScheme, Plain, Proof, Rfc6962, SortedPair};
+
+/// the signature of the function of the generated verifier
+pub const VERIFY_SIGNATURE: &str = "verify(bytes32[],uint256,bytes32)";
+
+/// A digest with a Solidity builtin
+pub trait SolidityHash: Digest {
+    /// the name of the builtin function
+    const FUNCTION: &'static str;
+}
+
+impl SolidityHash for Keccak256 {
+    const FUNCTION: &'static str = "keccak256";
+}
+
+impl SolidityHash for Sha256 {
+    const FUNCTION: &'static str = "sha256";
+}
+
+/// A hash scheme whose node hashing can be expressed in Solidity
+pub trait SolidityScheme {
+    /// true if the children are hashed in ascending order instead of the order given by the index
+    const SORTED: bool = false;
+
+    /// returns the expression hashing the two `bytes32` expressions with the builtin `hash`
+    fn node_expression(hash: &str, left: &str, right: &str) -> String;
+}
+
+impl SolidityScheme for Plain {
+    fn node_expression(hash: &str, left: &str, right: &str) -> String {
+        format!("{hash}(abi.encodePacked({left}, {right}))")
+    }
+}
+
+impl SolidityScheme for Rfc6962 {
+    fn node_expression(hash: &str, left: &str, right: &str) -> String {
+        format!(
+            "{hash}(abi.encodePacked(bytes1(0x{:02x}), {left}, {right}))",
+            Rfc6962::NODE_PREFIX
+        )
+    }
+}
+
+impl SolidityScheme for SortedPair {
+    const SORTED: bool = true;
+
+    fn node_expression(hash: &str, left: &str, right: &str) -> String {
+        format!("{hash}(abi.encodePacked({left}, {right}))")
+    }
+}
+
+impl SolidityScheme for DoubleHash {
+    fn node_expression(hash: &str, left: &str, right: &str) -> String {
+        format!("{hash}(abi.encodePacked({hash}(abi.encodePacked({left}, {right}))))")
+    }
+}
+
+/// returns the 4 byte selector of the verifier's `verify` function
+pub fn verify_selector() -> [u8; 4] {
+    let hash = Keccak256::digest(VERIFY_SIGNATURE);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// returns the ABI encoded arguments `(bytes32[] proof, uint256 index, bytes32 leaf)` of the
+/// verifier's `verify` function for the proof of the leaf
+pub fn abi_encode_verify_args<D, S>(proof: &Proof<D, S>, leaf: &Output<D>) -> Vec<u8>
+where
+    D: SolidityHash + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D> + SolidityScheme,
+{
+    let siblings = proof.siblings();
+    let mut encoded = Vec::with_capacity(32 * (4 + siblings.len()));
+    // the array is the only dynamic argument, its data follows the three head words
+    encoded.extend_from_slice(&abi_word(3 * 32));
+    encoded.extend_from_slice(&abi_word(proof.leaf_index() as u64));
+    encoded.extend_from_slice(leaf);
+    encoded.extend_from_slice(&abi_word(siblings.len() as u64));
+    for sibling in siblings {
+        encoded.extend_from_slice(sibling);
+    }
+    encoded
+}
+
+/// returns the calldata of a call of the verifier's `verify` function for the proof of the leaf,
+/// the selector followed by `abi_encode_verify_args`
+pub fn verify_calldata<D, S>(proof: &Proof<D, S>, leaf: &Output<D>) -> Vec<u8>
+where
+    D: SolidityHash + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D> + SolidityScheme,
+{
+    [
+        verify_selector().as_slice(),
+        &abi_encode_verify_args(proof, leaf),
+    ]
+    .concat()
+}
+
+/// returns the source of a contract named `name` verifying proofs of trees with the digest `D` and
+/// the scheme `S` against the root passed to its constructor
+pub fn verifier_contract<D, S>(name: &str) -> String
+where
+    D: SolidityHash,
+    S: HashScheme<D> + SolidityScheme,
+{
+    let step = if S::SORTED {
+        format!(
+            "            hash = hash <= proof[i]\n                ? {}\n                : {};\n",
+            S::node_expression(D::FUNCTION, "hash", "proof[i]"),
+            S::node_expression(D::FUNCTION, "proof[i]", "hash"),
+        )
+    } else {
+        format!(
+            "            if (index & 1 == 0) {{\n                hash = {};\n            }} else {{\n                hash = {};\n            }}\n            index >>= 1;\n",
+            S::node_expression(D::FUNCTION, "hash", "proof[i]"),
+            S::node_expression(D::FUNCTION, "proof[i]", "hash"),
+        )
+    };
+    format!(
+        "// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.20;
+
+/// Verifies inclusion proofs of merkle-tree-rs trees hashed with {function} and the \"{scheme}\" scheme
+contract {name} {{
+    bytes32 public immutable root;
+
+    constructor(bytes32 root_) {{
+        root = root_;
+    }}
+
+    /// returns true if the leaf at the index and the proof hash up to the root
+    function verify(bytes32[] calldata proof, uint256 index, bytes32 leaf) external view returns (bool) {{
+        bytes32 hash = leaf;
+        for (uint256 i = 0; i < proof.length; i++) {{
+{step}        }}
+        return hash == root;
+    }}
+}}
+",
+        function = D::FUNCTION,
+        scheme = S::NAME,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::MerkleTree;
+
+    #[test]
+    fn test_verify_calldata() {
+        let leaves: Vec<Output<Keccak256>> = (0..5u8).map(|i| Keccak256::digest([i])).collect();
+        let tree = MerkleTree::<Keccak256, Rfc6962>::from_leaves(&leaves, &[0; 32].into());
+        let proof = tree.create_proof(3);
+        let calldata = verify_calldata(&proof, &leaves[3]);
+
+        // the selector of verify(bytes32[],uint256,bytes32)
+        assert_eq!(calldata[..4], [0xfc, 0x95, 0x23, 0x4d]);
+        let args = &calldata[4..];
+        assert_eq!(args.len(), 32 * (4 + 3));
+        assert_eq!(args[..32], abi_word(96));
+        assert_eq!(args[32..64], abi_word(3));
+        assert_eq!(args[64..96], leaves[3][..]);
+        assert_eq!(args[96..128], abi_word(3));
+        for (word, sibling) in args[128..].chunks(32).zip(proof.siblings()) {
+            assert_eq!(word, &sibling[..]);
+        }
+    }
+
+    #[test]
+    fn test_verifier_contract() {
+        let source = verifier_contract::<Keccak256, Plain>("TreeVerifier");
+        assert!(source.contains("contract TreeVerifier {"));
+        assert!(source.contains("with keccak256 and the \"plain\" scheme"));
+        assert!(source.contains("hash = keccak256(abi.encodePacked(hash, proof[i]));"));
+        assert!(source.contains("hash = keccak256(abi.encodePacked(proof[i], hash));"));
+        assert!(source.contains("index >>= 1;"));
+
+        let source = verifier_contract::<Sha256, Rfc6962>("CtVerifier");
+        assert!(source.contains("sha256(abi.encodePacked(bytes1(0x01), hash, proof[i]))"));
+
+        let source = verifier_contract::<Keccak256, SortedPair>("OzVerifier");
+        assert!(source.contains("hash = hash <= proof[i]"));
+        assert!(!source.contains("index >>= 1;"));
+
+        let source = verifier_contract::<Sha256, DoubleHash>("BtcVerifier");
+        assert!(
+            source.contains("sha256(abi.encodePacked(sha256(abi.encodePacked(hash, proof[i]))))")
+        );
+    }
+}
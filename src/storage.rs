@@ -0,0 +1,573 @@
+//! Pluggable node storage for Merkle trees that should not (or cannot) live in memory
+
+use std::collections::{BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::fmt::{self, Debug};
+use std::time::Instant;
+
+use digest::{Digest, Output};
+
+use crate::{MerkleTree, MerkleTreeError, Proof, MAX_DEPTH};
+
+#[cfg(feature = "postgres")]
+pub mod postgres;
+
+/// A backend holding the nodes of a Merkle tree, addressed by their breadth-first index
+/// Nodes that were never written are reported as missing and hold the default value of their layer
+pub trait NodeStore<D: Digest> {
+    /// error returned by the backend
+    type Error;
+
+    /// reads the nodes at the given indices in one batch
+    /// returns None for every node that was never written
+    fn get_nodes(&self, indices: &[usize]) -> Result<Vec<Option<Output<D>>>, Self::Error>;
+
+    /// writes (inserts or overwrites) the given nodes in one batch
+    fn set_nodes(&mut self, nodes: &[(usize, Output<D>)]) -> Result<(), Self::Error>;
+
+    /// reads the nodes at the given indices in one batch like `get_nodes`, giving up once the deadline has passed
+    /// The default implementation only checks the deadline before and after the read, backends that
+    /// can abort reads in flight should override it.
+    fn get_nodes_before(
+        &self,
+        indices: &[usize],
+        deadline: Instant,
+    ) -> Result<Vec<Option<Output<D>>>, DeadlineError<Self::Error>> {
+        if Instant::now() >= deadline {
+            return Err(DeadlineError::DeadlineExceeded);
+        }
+        let nodes = self.get_nodes(indices).map_err(DeadlineError::Store)?;
+        if Instant::now() > deadline {
+            return Err(DeadlineError::DeadlineExceeded);
+        }
+        Ok(nodes)
+    }
+}
+
+/// Error returned by store operations with a deadline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeadlineError<E> {
+    /// the deadline passed before the operation completed
+    DeadlineExceeded,
+    /// the backend returned an error
+    Store(E),
+    /// the request doesn't fit the tree, e.g. the offset is out of bounds
+    Tree(MerkleTreeError),
+}
+
+impl<E: fmt::Display> fmt::Display for DeadlineError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DeadlineExceeded => write!(f, "deadline exceeded"),
+            Self::Store(err) => write!(f, "store error: {err}"),
+            Self::Tree(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for DeadlineError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::DeadlineExceeded => None,
+            Self::Store(err) => Some(err),
+            Self::Tree(err) => Some(err),
+        }
+    }
+}
+
+/// Error returned by the operations of a `StoredMerkleTree`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoredTreeError<E> {
+    /// the request doesn't fit the tree, e.g. an offset is out of bounds
+    Tree(MerkleTreeError),
+    /// the backend returned an error
+    Store(E),
+}
+
+impl<E: fmt::Display> fmt::Display for StoredTreeError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tree(err) => write!(f, "{err}"),
+            Self::Store(err) => write!(f, "store error: {err}"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for StoredTreeError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Tree(err) => Some(err),
+            Self::Store(err) => Some(err),
+        }
+    }
+}
+
+impl<E> From<MerkleTreeError> for StoredTreeError<E> {
+    fn from(err: MerkleTreeError) -> Self {
+        Self::Tree(err)
+    }
+}
+
+/// A simple in-memory node store, mostly useful for testing
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore<D: Digest> {
+    nodes: HashMap<usize, Output<D>>,
+}
+
+impl<D: Digest> MemoryStore<D> {
+    /// creates a new empty store
+    pub fn new() -> Self {
+        Self {
+            nodes: HashMap::new(),
+        }
+    }
+
+    /// returns the number of nodes that were written to the store
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// returns true if no node was written to the store yet
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+impl<D: Digest> NodeStore<D> for MemoryStore<D>
+where
+    Output<D>: Copy,
+{
+    type Error = Infallible;
+
+    fn get_nodes(&self, indices: &[usize]) -> Result<Vec<Option<Output<D>>>, Self::Error> {
+        Ok(indices.iter().map(|i| self.nodes.get(i).copied()).collect())
+    }
+
+    fn set_nodes(&mut self, nodes: &[(usize, Output<D>)]) -> Result<(), Self::Error> {
+        self.nodes.extend(nodes.iter().copied());
+        Ok(())
+    }
+}
+
+/// A Merkle tree whose nodes live in a `NodeStore`
+/// Only nodes that differ from the initial state of the tree are ever written, so creating a
+/// tree is cheap and opening an existing store with the same parameters restores its state
+pub struct StoredMerkleTree<D: Digest, S> {
+    /// depth of the tree
+    depth: usize,
+    /// hash of an untouched node per layer, starting at the root
+    defaults: Vec<Output<D>>,
+    /// backend holding the nodes
+    store: S,
+}
+
+impl<D, S> StoredMerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: NodeStore<D>,
+{
+    /// creates a new tree with the given depth and initial value for the leaves on top of the given store
+    /// nodes already present in the store take precedence over the initial value
+    /// panics if the depth is 0 or larger than `MAX_DEPTH`, see `try_new` for a fallible version
+    pub fn new(store: S, depth: usize, initial_value: &Output<D>) -> Self {
+        match Self::try_new(store, depth, initial_value) {
+            Ok(tree) => tree,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// creates a new tree like `new`
+    /// returns an error if the depth is 0 or larger than `MAX_DEPTH`
+    pub fn try_new(
+        store: S,
+        depth: usize,
+        initial_value: &Output<D>,
+    ) -> Result<Self, MerkleTreeError> {
+        if depth == 0 || depth > MAX_DEPTH {
+            return Err(MerkleTreeError::DepthOutOfRange {
+                depth,
+                max: MAX_DEPTH,
+            });
+        }
+
+        let mut defaults = vec![initial_value.to_owned(); depth];
+        for d in (0..depth - 1).rev() {
+            defaults[d] = MerkleTree::<D>::hash_pair(&defaults[d + 1], &defaults[d + 1]);
+        }
+        Ok(Self {
+            depth,
+            defaults,
+            store,
+        })
+    }
+
+    /// returns a reference to the underlying store
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// consumes the tree and returns the underlying store
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    /// returns the number of leaves in the tree
+    pub fn num_leaves(&self) -> usize {
+        1 << (self.depth - 1)
+    }
+
+    /// returns the root hash of the tree
+    pub fn root_hash(&self) -> Result<Output<D>, S::Error> {
+        let root = self.store.get_nodes(&[0])?.pop().flatten();
+        Ok(root.unwrap_or(self.defaults[0]))
+    }
+
+    /// updates the value of a leaf node
+    /// returns an error if the offset is out of bounds or the store fails
+    pub fn set(
+        &mut self,
+        offset: usize,
+        value: &Output<D>,
+    ) -> Result<(), StoredTreeError<S::Error>> {
+        self.set_many(&[(offset, value.to_owned())])
+    }
+
+    /// updates the values of multiple leaf nodes
+    /// every layer is read with a single batched read and all changed nodes are written in a single batch
+    /// returns an error before touching the store if an offset is out of bounds
+    pub fn set_many(
+        &mut self,
+        leaves: &[(usize, Output<D>)],
+    ) -> Result<(), StoredTreeError<S::Error>> {
+        for (offset, _) in leaves {
+            self.check_offset(*offset)?;
+        }
+        if leaves.is_empty() {
+            return Ok(());
+        }
+
+        // nodes that were updated, keyed by index
+        let mut updated: HashMap<usize, Output<D>> = HashMap::new();
+        let mut current_offsets = BTreeSet::new();
+        for (offset, value) in leaves {
+            updated.insert(MerkleTree::<D>::index(self.depth - 1, *offset), *value);
+            current_offsets.insert(*offset);
+        }
+
+        for layer in (0..self.depth - 1).rev() {
+            let parent_offsets: BTreeSet<usize> = current_offsets.iter().map(|o| o / 2).collect();
+
+            // fetch all children that were not updated themselves in one batch
+            let missing: Vec<usize> = parent_offsets
+                .iter()
+                .flat_map(|o| [o * 2, o * 2 + 1])
+                .map(|o| MerkleTree::<D>::index(layer + 1, o))
+                .filter(|i| !updated.contains_key(i))
+                .collect();
+            let fetched = self
+                .store
+                .get_nodes(&missing)
+                .map_err(StoredTreeError::Store)?;
+            let mut children: HashMap<usize, Output<D>> = missing
+                .into_iter()
+                .zip(fetched)
+                .map(|(i, node)| (i, node.unwrap_or(self.defaults[layer + 1])))
+                .collect();
+            children.extend(updated.iter().map(|(i, node)| (*i, *node)));
+
+            for parent_offset in &parent_offsets {
+                let left = children[&MerkleTree::<D>::index(layer + 1, parent_offset * 2)];
+                let right = children[&MerkleTree::<D>::index(layer + 1, parent_offset * 2 + 1)];
+                updated.insert(
+                    MerkleTree::<D>::index(layer, *parent_offset),
+                    MerkleTree::<D>::hash_pair(&left, &right),
+                );
+            }
+            current_offsets = parent_offsets;
+        }
+
+        let mut nodes: Vec<(usize, Output<D>)> = updated.into_iter().collect();
+        nodes.sort_unstable_by_key(|(i, _)| *i);
+        self.store.set_nodes(&nodes).map_err(StoredTreeError::Store)
+    }
+
+    /// Create a proof for a leaf node, see `MerkleTree::create_proof`
+    /// All siblings are fetched from the store in a single batched read
+    /// returns an error if the offset is out of bounds or the store fails
+    pub fn create_proof(&self, offset: usize) -> Result<Proof<D>, StoredTreeError<S::Error>> {
+        self.check_offset(offset)?;
+        let siblings = self
+            .store
+            .get_nodes(&self.proof_indices(offset))
+            .map_err(StoredTreeError::Store)?;
+        Ok(self.proof_from_siblings(offset, siblings))
+    }
+
+    /// Create proofs for multiple leaf nodes, see `create_proof`
+    /// The siblings of all proofs are collected first and every distinct node is fetched once in a
+    /// single batched read, proofs of nearby leaves share most of their siblings. The proofs are
+    /// returned in the order of the offsets.
+    /// returns an error before reading from the store if an offset is out of bounds
+    pub fn create_proofs(
+        &self,
+        offsets: &[usize],
+    ) -> Result<Vec<Proof<D>>, StoredTreeError<S::Error>> {
+        for offset in offsets {
+            self.check_offset(*offset)?;
+        }
+        let indices: BTreeSet<usize> = offsets
+            .iter()
+            .flat_map(|&offset| self.proof_indices(offset))
+            .collect();
+        let indices: Vec<usize> = indices.into_iter().collect();
+        let fetched = self
+            .store
+            .get_nodes(&indices)
+            .map_err(StoredTreeError::Store)?;
+        let nodes: HashMap<usize, Option<Output<D>>> = indices.into_iter().zip(fetched).collect();
+        Ok(offsets
+            .iter()
+            .map(|&offset| {
+                let siblings = self
+                    .proof_indices(offset)
+                    .into_iter()
+                    .map(|index| nodes[&index])
+                    .collect();
+                self.proof_from_siblings(offset, siblings)
+            })
+            .collect())
+    }
+
+    /// Create a proof for a leaf node like `create_proof`, giving up once the deadline has passed
+    /// This allows request handlers to answer with a timeout instead of blocking on slow storage.
+    pub fn create_proof_with_deadline(
+        &self,
+        offset: usize,
+        deadline: Instant,
+    ) -> Result<Proof<D>, DeadlineError<S::Error>> {
+        self.check_offset(offset).map_err(DeadlineError::Tree)?;
+        let siblings = self
+            .store
+            .get_nodes_before(&self.proof_indices(offset), deadline)?;
+        Ok(self.proof_from_siblings(offset, siblings))
+    }
+
+    /// returns an error if the offset is not smaller than the number of leaves
+    fn check_offset(&self, offset: usize) -> Result<(), MerkleTreeError> {
+        if offset >= self.num_leaves() {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset,
+                num_leaves: self.num_leaves(),
+            });
+        }
+        Ok(())
+    }
+
+    /// returns the indices of the siblings needed for the proof of a leaf node
+    fn proof_indices(&self, offset: usize) -> Vec<usize> {
+        MerkleTree::<D>::sibling_indices(self.depth, offset)
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// assembles a proof from the siblings read from the store, filling in default values for missing nodes
+    fn proof_from_siblings(&self, offset: usize, siblings: Vec<Option<Output<D>>>) -> Proof<D> {
+        let siblings = siblings
+            .into_iter()
+            .zip((1..self.depth).rev())
+            .map(|(sibling, layer)| sibling.unwrap_or(self.defaults[layer]))
+            .collect();
+        Proof::new(offset, siblings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    use sha3::Sha3_256;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type StoredMerkleTree = super::StoredMerkleTree<Sha3_256, MemoryStore<Sha3_256>>;
+
+    /// a store that takes a while for every read
+    struct SlowStore(MemoryStore<Sha3_256>, Duration);
+
+    impl NodeStore<Sha3_256> for SlowStore {
+        type Error = Infallible;
+
+        fn get_nodes(
+            &self,
+            indices: &[usize],
+        ) -> Result<Vec<Option<Output<Sha3_256>>>, Infallible> {
+            std::thread::sleep(self.1);
+            self.0.get_nodes(indices)
+        }
+
+        fn set_nodes(&mut self, nodes: &[(usize, Output<Sha3_256>)]) -> Result<(), Infallible> {
+            self.0.set_nodes(nodes)
+        }
+    }
+
+    #[test]
+    fn test_stored_tree_matches_in_memory_tree() {
+        let initial_value = [0xab; 32].into();
+        let mut tree = MerkleTree::new(5, &initial_value);
+        let mut stored = StoredMerkleTree::new(MemoryStore::new(), 5, &initial_value);
+        assert_eq!(stored.root_hash().unwrap(), *tree.root_hash());
+        assert!(stored.store().is_empty());
+
+        for i in [3, 7, 8, 15, 0] {
+            let updated_value = [(i * 0x11) as u8; 32].into();
+            tree.set(i, &updated_value);
+            stored.set(i, &updated_value).unwrap();
+            assert_eq!(stored.root_hash().unwrap(), *tree.root_hash());
+        }
+        for i in 0..tree.num_leaves() {
+            assert_eq!(stored.create_proof(i).unwrap(), tree.create_proof(i));
+        }
+    }
+
+    #[test]
+    fn test_stored_tree_set_many() {
+        let initial_value = [0x00; 32].into();
+        let mut tree = MerkleTree::new(5, &initial_value);
+        let mut stored = StoredMerkleTree::new(MemoryStore::new(), 5, &initial_value);
+
+        let leaves: Vec<_> = [1, 2, 9, 10, 11, 31]
+            .iter()
+            .map(|&i| (i % 16, [i as u8; 32].into()))
+            .collect();
+        for (i, value) in &leaves {
+            tree.set(*i, value);
+        }
+        stored.set_many(&leaves).unwrap();
+        assert_eq!(stored.root_hash().unwrap(), *tree.root_hash());
+    }
+
+    #[test]
+    fn test_create_proofs() {
+        /// a store counting its reads and the nodes requested by them
+        struct CountingStore(MemoryStore<Sha3_256>, Cell<(usize, usize)>);
+
+        impl NodeStore<Sha3_256> for CountingStore {
+            type Error = Infallible;
+
+            fn get_nodes(
+                &self,
+                indices: &[usize],
+            ) -> Result<Vec<Option<Output<Sha3_256>>>, Infallible> {
+                let (reads, nodes) = self.1.get();
+                self.1.set((reads + 1, nodes + indices.len()));
+                self.0.get_nodes(indices)
+            }
+
+            fn set_nodes(&mut self, nodes: &[(usize, Output<Sha3_256>)]) -> Result<(), Infallible> {
+                self.0.set_nodes(nodes)
+            }
+        }
+
+        let initial_value = [0x00; 32].into();
+        let mut tree = MerkleTree::new(6, &initial_value);
+        let mut stored = super::StoredMerkleTree::new(
+            CountingStore(MemoryStore::new(), Cell::new((0, 0))),
+            6,
+            &initial_value,
+        );
+        for i in [1, 4, 9, 30] {
+            tree.set(i, &[i as u8; 32].into());
+            stored.set(i, &[i as u8; 32].into()).unwrap();
+        }
+        stored.store().1.set((0, 0));
+
+        let offsets = [0, 1, 2, 3, 31, 2];
+        let proofs = stored.create_proofs(&offsets).unwrap();
+        for (offset, proof) in offsets.iter().zip(&proofs) {
+            assert_eq!(proof, &tree.create_proof(*offset));
+        }
+        // the leaves 0..4 need 4 distinct leaf siblings, 2 on the next layer and share the 3 above,
+        // the path of leaf 31 shares no sibling with them
+        assert_eq!(stored.store().1.get(), (1, 9 + 5));
+        assert_eq!(stored.create_proofs(&[]).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_stored_tree_rejects_out_of_bounds() {
+        let initial_value = [0x00; 32].into();
+        assert_eq!(
+            StoredMerkleTree::try_new(MemoryStore::new(), 0, &initial_value).err(),
+            Some(MerkleTreeError::DepthOutOfRange {
+                depth: 0,
+                max: MAX_DEPTH
+            })
+        );
+        assert!(
+            StoredMerkleTree::try_new(MemoryStore::new(), MAX_DEPTH + 1, &initial_value).is_err()
+        );
+
+        let mut stored = StoredMerkleTree::new(MemoryStore::new(), 4, &initial_value);
+        let out_of_bounds = StoredTreeError::Tree(MerkleTreeError::LeafIndexOutOfBounds {
+            offset: 8,
+            num_leaves: 8,
+        });
+        // offset 8 would be the index of the first node of the layer below the leaves
+        assert_eq!(stored.set(8, &[1; 32].into()), Err(out_of_bounds.clone()));
+        assert_eq!(
+            stored.set_many(&[(0, [1; 32].into()), (8, [1; 32].into())]),
+            Err(out_of_bounds.clone())
+        );
+        assert!(stored.store().is_empty());
+        assert_eq!(stored.create_proof(8), Err(out_of_bounds.clone()));
+        assert_eq!(stored.create_proofs(&[0, 8]), Err(out_of_bounds));
+        assert!(matches!(
+            stored.create_proof_with_deadline(8, Instant::now() + Duration::from_secs(60)),
+            Err(DeadlineError::Tree(
+                MerkleTreeError::LeafIndexOutOfBounds { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_stored_tree_reopen() {
+        let initial_value = [0x00; 32].into();
+        let mut stored = StoredMerkleTree::new(MemoryStore::new(), 4, &initial_value);
+        stored.set(5, &[0x55; 32].into()).unwrap();
+        let root = stored.root_hash().unwrap();
+
+        let reopened = StoredMerkleTree::new(stored.into_store(), 4, &initial_value);
+        assert_eq!(reopened.root_hash().unwrap(), root);
+    }
+
+    #[test]
+    fn test_create_proof_with_deadline() {
+        let initial_value = [0x00; 32].into();
+        let mut stored = StoredMerkleTree::new(MemoryStore::new(), 4, &initial_value);
+        stored.set(5, &[0x55; 32].into()).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert_eq!(
+            stored.create_proof_with_deadline(5, deadline).unwrap(),
+            stored.create_proof(5).unwrap()
+        );
+        assert_eq!(
+            stored.create_proof_with_deadline(5, Instant::now()),
+            Err(DeadlineError::DeadlineExceeded)
+        );
+
+        let slow = super::StoredMerkleTree::new(
+            SlowStore(stored.into_store(), Duration::from_millis(50)),
+            4,
+            &initial_value,
+        );
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(
+            slow.create_proof_with_deadline(5, deadline),
+            Err(DeadlineError::DeadlineExceeded)
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! PostgreSQL-backed node store
+//!
+//! Nodes are kept in a single table keyed by `(tree_id, idx)`, so multiple trees can share one
+//! database. Writes are issued as batched upserts inside a transaction.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+use digest::{Digest, Output};
+use postgres::error::SqlState;
+use postgres::{Client, GenericClient};
+
+use super::{DeadlineError, NodeStore};
+
+/// SQL creating the nodes table used by `PostgresStore`
+pub const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS merkle_nodes (
+    tree_id BIGINT NOT NULL,
+    idx BIGINT NOT NULL,
+    hash BYTEA NOT NULL,
+    PRIMARY KEY (tree_id, idx)
+);
+";
+
+/// SQL creating a `merkle_proof(tree_id, depth, offset)` function that generates a proof inside the database
+/// It returns one row per layer (bottom-up) with the sibling index, its hash and whether the node on the
+/// path is the left child, matching the (hash, is_left) pairs of `Proof::iter`. The hash is NULL for siblings that were never written, these
+/// hold the default value of their layer.
+pub const PROOF_FUNCTION: &str = "
+CREATE OR REPLACE FUNCTION merkle_proof(p_tree_id BIGINT, p_depth INT, p_offset BIGINT)
+RETURNS TABLE (layer INT, sibling_idx BIGINT, hash BYTEA, is_left BOOLEAN)
+LANGUAGE plpgsql STABLE AS $$
+DECLARE
+    current_offset BIGINT := p_offset;
+BEGIN
+    FOR l IN REVERSE (p_depth - 1)..1 LOOP
+        layer := l;
+        sibling_idx := ((1::BIGINT << l) - 1) + (current_offset # 1);
+        is_left := current_offset % 2 = 0;
+        SELECT n.hash INTO hash FROM merkle_nodes n
+            WHERE n.tree_id = p_tree_id AND n.idx = sibling_idx;
+        RETURN NEXT;
+        current_offset := current_offset / 2;
+    END LOOP;
+END;
+$$;
+";
+
+/// default number of nodes written per upsert statement
+const DEFAULT_BATCH_SIZE: usize = 1024;
+
+/// Error returned by `PostgresStore`
+#[derive(Debug)]
+pub enum PostgresStoreError {
+    /// the database returned an error
+    Postgres(postgres::Error),
+    /// a stored hash doesn't have the length of the digest output
+    InvalidHash { index: usize },
+}
+
+impl fmt::Display for PostgresStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Postgres(err) => write!(f, "postgres error: {err}"),
+            Self::InvalidHash { index } => write!(f, "invalid hash stored for node {index}"),
+        }
+    }
+}
+
+impl std::error::Error for PostgresStoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Postgres(err) => Some(err),
+            Self::InvalidHash { .. } => None,
+        }
+    }
+}
+
+impl From<postgres::Error> for PostgresStoreError {
+    fn from(err: postgres::Error) -> Self {
+        Self::Postgres(err)
+    }
+}
+
+/// A node store keeping the nodes of one tree in the `merkle_nodes` table
+pub struct PostgresStore {
+    /// connection to the database
+    client: Mutex<Client>,
+    /// id of the tree within the nodes table
+    tree_id: i64,
+    /// maximum number of nodes written per upsert statement
+    batch_size: usize,
+}
+
+impl PostgresStore {
+    /// creates a new store for the tree with the given id
+    pub fn new(client: Client, tree_id: i64) -> Self {
+        Self {
+            client: Mutex::new(client),
+            tree_id,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    /// sets the maximum number of nodes written per upsert statement
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// creates the nodes table and the `merkle_proof` function if they don't exist yet
+    pub fn create_schema(&self) -> Result<(), PostgresStoreError> {
+        let mut client = self.client();
+        client.batch_execute(SCHEMA)?;
+        client.batch_execute(PROOF_FUNCTION)?;
+        Ok(())
+    }
+
+    /// consumes the store and returns the underlying connection
+    pub fn into_client(self) -> Client {
+        self.client
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn client(&self) -> std::sync::MutexGuard<'_, Client> {
+        self.client.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl PostgresStore {
+    /// reads the nodes at the given indices using the given client or transaction
+    fn query_nodes<D: Digest>(
+        &self,
+        client: &mut impl GenericClient,
+        indices: &[usize],
+    ) -> Result<Vec<Option<Output<D>>>, PostgresStoreError> {
+        let idx: Vec<i64> = indices.iter().map(|&i| i as i64).collect();
+        let rows = client.query(
+            "SELECT idx, hash FROM merkle_nodes WHERE tree_id = $1 AND idx = ANY($2)",
+            &[&self.tree_id, &idx],
+        )?;
+
+        let mut found = HashMap::with_capacity(rows.len());
+        for row in rows {
+            let index = row.get::<_, i64>(0) as usize;
+            let hash: &[u8] = row.get(1);
+            if hash.len() != <D as Digest>::output_size() {
+                return Err(PostgresStoreError::InvalidHash { index });
+            }
+            found.insert(index, Output::<D>::clone_from_slice(hash));
+        }
+        Ok(indices.iter().map(|i| found.get(i).cloned()).collect())
+    }
+}
+
+impl<D: Digest> NodeStore<D> for PostgresStore {
+    type Error = PostgresStoreError;
+
+    fn get_nodes(&self, indices: &[usize]) -> Result<Vec<Option<Output<D>>>, Self::Error> {
+        self.query_nodes::<D>(&mut *self.client(), indices)
+    }
+
+    fn set_nodes(&mut self, nodes: &[(usize, Output<D>)]) -> Result<(), Self::Error> {
+        let mut client = self.client();
+        let mut transaction = client.transaction()?;
+        for batch in nodes.chunks(self.batch_size) {
+            let idx: Vec<i64> = batch.iter().map(|(i, _)| *i as i64).collect();
+            let hashes: Vec<&[u8]> = batch.iter().map(|(_, hash)| hash.as_slice()).collect();
+            transaction.execute(
+                "INSERT INTO merkle_nodes (tree_id, idx, hash)
+                 SELECT $1, * FROM UNNEST($2::BIGINT[], $3::BYTEA[])
+                 ON CONFLICT (tree_id, idx) DO UPDATE SET hash = EXCLUDED.hash",
+                &[&self.tree_id, &idx, &hashes],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// reads the nodes with the remaining time as statement timeout, so the database aborts slow reads
+    fn get_nodes_before(
+        &self,
+        indices: &[usize],
+        deadline: Instant,
+    ) -> Result<Vec<Option<Output<D>>>, DeadlineError<Self::Error>> {
+        let mut client = self.client();
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        // a statement timeout of 0 disables the timeout, so we need at least 1ms
+        let timeout = remaining.as_millis();
+        if timeout == 0 {
+            return Err(DeadlineError::DeadlineExceeded);
+        }
+
+        let result = (|| {
+            let mut transaction = client.transaction()?;
+            transaction.batch_execute(&format!("SET LOCAL statement_timeout = {timeout}"))?;
+            let nodes = self.query_nodes::<D>(&mut transaction, indices)?;
+            transaction.commit()?;
+            Ok(nodes)
+        })();
+        match result {
+            Err(PostgresStoreError::Postgres(err))
+                if err.code() == Some(&SqlState::QUERY_CANCELED) =>
+            {
+                Err(DeadlineError::DeadlineExceeded)
+            }
+            Err(err) => Err(DeadlineError::Store(err)),
+            Ok(_) if Instant::now() > deadline => Err(DeadlineError::DeadlineExceeded),
+            Ok(nodes) => Ok(nodes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use postgres::NoTls;
+    use sha3::Sha3_256;
+
+    use crate::{MerkleTree, StoredMerkleTree};
+
+    /// connects to the database named by `MERKLE_TREE_POSTGRES_URL`,
+    /// e.g. `host=localhost user=postgres dbname=merkle`
+    fn connect() -> Client {
+        let url = std::env::var("MERKLE_TREE_POSTGRES_URL")
+            .expect("MERKLE_TREE_POSTGRES_URL must name a database for the postgres tests");
+        Client::connect(&url, NoTls).unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs a PostgreSQL server, run with MERKLE_TREE_POSTGRES_URL and --ignored"]
+    fn test_postgres_store() {
+        // a tree id of its own, so concurrent runs against the same database don't interfere
+        let tree_id = i64::from(std::process::id());
+        let mut client = connect();
+        client
+            .execute("DELETE FROM merkle_nodes WHERE tree_id = $1", &[&tree_id])
+            .ok();
+        // batches of 3 nodes split every update into several upserts
+        let store = PostgresStore::new(client, tree_id).with_batch_size(3);
+        store.create_schema().unwrap();
+
+        let initial_value = [0x00; 32].into();
+        let mut tree = MerkleTree::<Sha3_256>::new(5, &initial_value);
+        let mut stored = StoredMerkleTree::new(store, 5, &initial_value);
+        let leaves: Vec<_> = [1, 2, 9, 15]
+            .into_iter()
+            .map(|offset| (offset, [offset as u8 + 1; 32].into()))
+            .collect();
+        for (offset, value) in &leaves {
+            tree.set(*offset, value);
+        }
+        stored.set_many(&leaves).unwrap();
+        // overwriting existing rows goes through the conflict clause
+        tree.set(9, &[0xaa; 32].into());
+        stored.set(9, &[0xaa; 32].into()).unwrap();
+        assert_eq!(stored.root_hash().unwrap(), *tree.root_hash());
+        for offset in 0..tree.num_leaves() {
+            assert_eq!(
+                stored.create_proof(offset).unwrap(),
+                tree.create_proof(offset)
+            );
+        }
+
+        // the proof generated inside the database has the same siblings and directions
+        let proof = tree.create_proof(9);
+        let mut client = stored.into_store().into_client();
+        let rows = client
+            .query(
+                "SELECT hash, is_left FROM merkle_proof($1, 5, 9)",
+                &[&tree_id],
+            )
+            .unwrap();
+        assert_eq!(rows.len(), proof.len());
+        for ((sibling, is_left), row) in proof.iter().zip(&rows) {
+            let hash: Option<&[u8]> = row.get(0);
+            if let Some(hash) = hash {
+                assert_eq!(hash, sibling.as_slice());
+            }
+            assert_eq!(row.get::<_, bool>(1), is_left);
+        }
+        client
+            .execute("DELETE FROM merkle_nodes WHERE tree_id = $1", &[&tree_id])
+            .unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+//! Streaming construction from unhashed items
+//!
+//! `MerkleTree::from_iter_data` hashes every item of an iterator into a leaf as it arrives, so only
+//! the leaf hashes of a stream are held in memory, never the items themselves. When only the root
+//! is needed a `StreamingRoot` goes further and keeps one pending node per level: a full subtree of
+//! height `h` is stored until its right sibling is complete, so `n` leaves take `log2(n)` hashes of
+//! memory. The root is padded like `MerkleTree::from_leaves` and equals the root of a tree built
+//! from the same leaves.
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, Plain};
+
+/// Computes the root of a tree over a stream of leaves with one pending node per level
+pub struct StreamingRoot<D: Digest, S = Plain> {
+    /// the root of the last complete subtree of every height not yet joined with its right sibling
+    frontier: Vec<Option<Output<D>>>,
+    len: usize,
+    scheme: PhantomData<fn() -> S>,
+}
+
+impl<D, S> Default for StreamingRoot<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D, S> StreamingRoot<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a stream without leaves
+    pub fn new() -> Self {
+        Self {
+            frontier: Vec::new(),
+            len: 0,
+            scheme: PhantomData,
+        }
+    }
+
+    /// returns the number of leaves pushed so far
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// returns true if no leaves were pushed
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// appends an already hashed leaf
+    pub fn push(&mut self, leaf: &Output<D>) {
+        let mut node = *leaf;
+        let mut height = 0;
+        // join the new node with the complete subtrees on its left as long as they have its height
+        while let Some(left) = self.frontier.get_mut(height).and_then(Option::take) {
+            node = S::hash_node(&left, &node);
+            height += 1;
+        }
+        if height == self.frontier.len() {
+            self.frontier.push(None);
+        }
+        self.frontier[height] = Some(node);
+        self.len += 1;
+    }
+
+    /// hashes a leaf payload with the scheme of the tree and appends it
+    pub fn push_data(&mut self, data: impl AsRef<[u8]>) {
+        self.push(&S::hash_leaf(data.as_ref()));
+    }
+
+    /// returns the root of the leaves pushed so far, padded with the padding value to the next power
+    /// of two
+    pub fn root(&self, padding: &Output<D>) -> Output<D> {
+        if self.len == 0 {
+            return S::empty_root();
+        }
+        let top = self.len.next_power_of_two().trailing_zeros() as usize;
+        if self.len.is_power_of_two() {
+            return self.frontier[top].expect("a power of two of leaves is one complete subtree");
+        }
+        // the root of the subtree right of the pending nodes, it covers the last leaves and padding
+        let mut right: Option<Output<D>> = None;
+        let mut padding_root = *padding;
+        for height in 0..top {
+            right = match (self.frontier.get(height).copied().flatten(), right) {
+                (Some(left), Some(right)) => Some(S::hash_node(&left, &right)),
+                (Some(left), None) => Some(S::hash_node(&left, &padding_root)),
+                (None, Some(right)) => Some(S::hash_node(&right, &padding_root)),
+                (None, None) => None,
+            };
+            padding_root = S::hash_node(&padding_root, &padding_root);
+        }
+        right.expect("a number of leaves that is no power of two has pending nodes")
+    }
+}
+
+impl<D, S> MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    /// creates a new Merkle tree from a stream of raw leaf payloads like `from_leaf_data`
+    /// every payload is hashed as it arrives and dropped afterwards, only the leaf hashes are kept
+    /// until the tree is built
+    pub fn from_iter_data<I>(data: I, padding: &Output<D>) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let leaves: Vec<Output<D>> = data.into_iter().map(Self::hash_leaf).collect();
+        Self::from_leaves(&leaves, padding)
+    }
+
+    /// returns the root of the tree `from_iter_data` would build from the stream, holding one node
+    /// per level instead of the leaves, see `StreamingRoot`
+    pub fn root_from_iter_data<I>(data: I, padding: &Output<D>) -> Output<D>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut stream = StreamingRoot::<D, S>::new();
+        for item in data {
+            stream.push_data(item);
+        }
+        stream.root(padding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    use crate::Rfc6962;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+
+    #[test]
+    fn test_streaming_root() {
+        for len in 0..40u32 {
+            let items = || (0..len).map(|i| i.to_le_bytes());
+            let expected =
+                MerkleTree::from_leaf_data(&items().collect::<Vec<_>>(), &[7; 32].into());
+            let tree = MerkleTree::from_iter_data(items(), &[7; 32].into());
+            assert_eq!(tree.root_hash(), expected.root_hash());
+            assert_eq!(tree.next_offset(), len as usize);
+            assert_eq!(
+                MerkleTree::root_from_iter_data(items(), &[7; 32].into()),
+                *expected.root_hash()
+            );
+        }
+    }
+
+    #[test]
+    fn test_streaming_root_scheme() {
+        let mut stream = StreamingRoot::<Sha3_256, Rfc6962>::new();
+        let mut tree = crate::MerkleTree::<Sha3_256, Rfc6962>::empty(&[0; 32].into());
+        for i in 0..9u8 {
+            stream.push_data([i]);
+            tree.push(&crate::MerkleTree::<Sha3_256, Rfc6962>::hash_leaf([i]));
+            assert_eq!(stream.root(&[0; 32].into()), *tree.root_hash());
+        }
+        assert_eq!(stream.len(), 9);
+        // the frontier holds one node per level
+        assert_eq!(stream.frontier.len(), 4);
+    }
+}
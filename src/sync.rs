@@ -0,0 +1,255 @@
+//! Anti-entropy between replicas of a tree
+//!
+//! `MerkleTree::diff` compares two trees in memory. `differing_ranges` does the same against a
+//! replica behind a `RemotePeer`, e.g. another process reached over the network, without
+//! transferring the other tree: it walks down from the root one layer at a time, only asking the
+//! peer for the children of nodes that differ, and returns the ranges of differing leaves. The
+//! caller fetches the leaves of these ranges from the peer, how is up to the protocol between them.
+//!
+//! Every layer is requested with a single `get_nodes` call, so a sync takes at most one round trip
+//! per layer and transfers two hashes per differing node.
+
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Display};
+use core::ops::Range;
+
+use digest::{Digest, Output};
+
+use crate::{HashScheme, MerkleTree, MerkleTreeError};
+
+/// A replica of a tree that answers requests for its nodes
+pub trait RemotePeer<D: Digest> {
+    /// the reason a request to the peer failed
+    type Error;
+
+    /// returns the depth of the peer's tree
+    fn depth(&mut self) -> Result<usize, Self::Error>;
+
+    /// returns the node at the offset of the layer at the given depth below the root, 0 is the root
+    fn get_node(&mut self, depth: usize, offset: usize) -> Result<Output<D>, Self::Error>;
+
+    /// returns the nodes at the offsets of one layer in the order of the offsets
+    /// The default requests every node with `get_node`, peers behind a network should answer the
+    /// whole batch with one request.
+    fn get_nodes(
+        &mut self,
+        depth: usize,
+        offsets: &[usize],
+    ) -> Result<Vec<Output<D>>, Self::Error> {
+        offsets
+            .iter()
+            .map(|&offset| self.get_node(depth, offset))
+            .collect()
+    }
+}
+
+/// The reason a sync with a peer failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncError<E> {
+    /// the trees can't be compared, e.g. because their depths differ
+    Tree(MerkleTreeError),
+    /// a request to the peer failed
+    Remote(E),
+    /// the peer answered a batch with the wrong number of nodes
+    InvalidResponse { expected: usize, actual: usize },
+}
+
+impl<E: Display> Display for SyncError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Tree(err) => write!(f, "{err}"),
+            SyncError::Remote(err) => write!(f, "request to the peer failed: {err}"),
+            SyncError::InvalidResponse { expected, actual } => {
+                write!(f, "peer answered with {actual} nodes instead of {expected}")
+            }
+        }
+    }
+}
+
+impl<E: core::error::Error + 'static> core::error::Error for SyncError<E> {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            SyncError::Tree(err) => Some(err),
+            SyncError::Remote(err) => Some(err),
+            SyncError::InvalidResponse { .. } => None,
+        }
+    }
+}
+
+impl<E> From<MerkleTreeError> for SyncError<E> {
+    fn from(err: MerkleTreeError) -> Self {
+        SyncError::Tree(err)
+    }
+}
+
+/// A local tree serves as a peer, e.g. for replicas in the same process
+impl<D, S> RemotePeer<D> for MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    type Error = MerkleTreeError;
+
+    fn depth(&mut self) -> Result<usize, MerkleTreeError> {
+        Ok(MerkleTree::depth(self))
+    }
+
+    fn get_node(&mut self, depth: usize, offset: usize) -> Result<Output<D>, MerkleTreeError> {
+        self.try_iter_layer(depth)?
+            .as_slice()
+            .get(offset)
+            .copied()
+            .ok_or(MerkleTreeError::NodeOutOfRange {
+                layer: depth,
+                offset,
+            })
+    }
+}
+
+/// returns the ranges of leaves that differ between the local tree and the peer's tree in
+/// ascending order, adjacent differing leaves are merged into one range
+/// returns an error if the trees don't have the same depth, a request to the peer fails or the
+/// peer answers with the wrong number of nodes
+pub fn differing_ranges<D, S, P>(
+    local: &MerkleTree<D, S>,
+    peer: &mut P,
+) -> Result<Vec<Range<usize>>, SyncError<P::Error>>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+    P: RemotePeer<D>,
+{
+    let depth = peer.depth().map_err(SyncError::Remote)?;
+    if depth != local.depth() {
+        return Err(MerkleTreeError::DepthMismatch {
+            expected: local.depth(),
+            actual: depth,
+        }
+        .into());
+    }
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    if local.is_empty() {
+        return Ok(ranges);
+    }
+    // the offsets of the differing nodes of the current layer in ascending order
+    let mut differing = alloc::vec![0];
+    for layer in 0..depth {
+        let offsets: Vec<usize> = match layer {
+            0 => differing,
+            _ => differing
+                .iter()
+                .flat_map(|&offset| [2 * offset, 2 * offset + 1])
+                .collect(),
+        };
+        let remote = peer.get_nodes(layer, &offsets).map_err(SyncError::Remote)?;
+        if remote.len() != offsets.len() {
+            return Err(SyncError::InvalidResponse {
+                expected: offsets.len(),
+                actual: remote.len(),
+            });
+        }
+        let nodes = local.iter_layer(layer).as_slice();
+        differing = offsets
+            .into_iter()
+            .zip(remote)
+            .filter(|(offset, hash)| nodes[*offset] != *hash)
+            .map(|(offset, _)| offset)
+            .collect();
+        if differing.is_empty() {
+            return Ok(ranges);
+        }
+    }
+    for offset in differing {
+        match ranges.last_mut() {
+            Some(range) if range.end == offset => range.end += 1,
+            _ => ranges.push(offset..offset + 1),
+        }
+    }
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+
+    /// counts the requests to a local tree
+    struct CountingPeer {
+        tree: MerkleTree,
+        requests: usize,
+        nodes: usize,
+    }
+
+    impl RemotePeer<Sha3_256> for CountingPeer {
+        type Error = MerkleTreeError;
+
+        fn depth(&mut self) -> Result<usize, MerkleTreeError> {
+            Ok(self.tree.depth())
+        }
+
+        fn get_node(
+            &mut self,
+            depth: usize,
+            offset: usize,
+        ) -> Result<Output<Sha3_256>, MerkleTreeError> {
+            self.nodes += 1;
+            self.tree.get_node(depth, offset)
+        }
+
+        fn get_nodes(
+            &mut self,
+            depth: usize,
+            offsets: &[usize],
+        ) -> Result<Vec<Output<Sha3_256>>, MerkleTreeError> {
+            self.requests += 1;
+            offsets
+                .iter()
+                .map(|&offset| self.get_node(depth, offset))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_differing_ranges() {
+        let local = MerkleTree::new(9, &[0; 32].into());
+        let mut remote = MerkleTree::new(9, &[0; 32].into());
+        assert_eq!(differing_ranges(&local, &mut remote), Ok(Vec::new()));
+
+        for offset in [3, 4, 5, 100, 255] {
+            remote.set(offset, &[1; 32].into());
+        }
+        let mut peer = CountingPeer {
+            tree: remote,
+            requests: 0,
+            nodes: 0,
+        };
+        assert_eq!(
+            differing_ranges(&local, &mut peer),
+            Ok(vec![3..6, 100..101, 255..256])
+        );
+        assert_eq!(peer.requests, 9);
+        assert!(peer.nodes < 2 * 5 * 9);
+
+        let ranges = differing_ranges(&local, &mut peer.tree).unwrap();
+        let offsets: Vec<usize> = ranges.into_iter().flatten().collect();
+        assert_eq!(offsets, local.diff(&peer.tree));
+
+        assert_eq!(
+            differing_ranges(&local, &mut MerkleTree::new(8, &[0; 32].into())),
+            Err(SyncError::Tree(MerkleTreeError::DepthMismatch {
+                expected: 9,
+                actual: 8
+            }))
+        );
+        let empty = MerkleTree::empty(&[0; 32].into());
+        assert_eq!(
+            differing_ranges(&empty, &mut MerkleTree::empty(&[0; 32].into())),
+            Ok(Vec::new())
+        );
+    }
+}
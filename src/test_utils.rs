@@ -0,0 +1,172 @@
+//! Property testing support for downstream crates
+//!
+//! The strategies in this module generate hashes, leaf sets, trees and sequences of leaf updates for
+//! `proptest`, and `MerkleTree` and `Proof` implement `Arbitrary`, so `any::<MerkleTree<Sha3_256>>()`
+//! works as well. Invariants like "every proof created by a tree verifies against its root" can then
+//! be checked against this implementation for random inputs.
+//!
+//! Generated trees are small, `any` keeps them at `MAX_ARBITRARY_LEAVES` leaves, so a test case
+//! runs in microseconds. The strategies take the maximum number of leaves where deeper trees matter.
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use digest::{Digest, Output};
+use proptest::arbitrary::Arbitrary;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::strategy::BoxedStrategy;
+
+use crate::{HashScheme, MerkleTree, Proof};
+
+/// maximum number of leaves of the trees generated by `any`
+pub const MAX_ARBITRARY_LEAVES: usize = 64;
+
+/// updates of leaves in the order they are applied, as offset and new value
+pub type Updates<D> = Vec<(usize, Output<D>)>;
+
+/// generates hashes with the output size of the digest
+pub fn arb_hash<D: Digest>() -> impl Strategy<Value = Output<D>> {
+    vec(any::<u8>(), <D as Digest>::output_size())
+        .prop_map(|bytes| Output::<D>::clone_from_slice(&bytes))
+}
+
+/// generates up to `max_leaves` leaves, possibly none
+pub fn arb_leaves<D: Digest>(max_leaves: usize) -> impl Strategy<Value = Vec<Output<D>>> {
+    vec(arb_hash::<D>(), 0..=max_leaves)
+}
+
+/// generates up to `max_updates` updates of leaves below `num_leaves`, possibly none
+/// `num_leaves` has to be positive
+pub fn arb_updates<D: Digest>(
+    num_leaves: usize,
+    max_updates: usize,
+) -> impl Strategy<Value = Updates<D>> {
+    vec((0..num_leaves, arb_hash::<D>()), 0..=max_updates)
+}
+
+/// generates trees built by `from_leaves` from up to `max_leaves` leaves and a random padding
+pub fn arb_tree<D, S>(max_leaves: usize) -> impl Strategy<Value = MerkleTree<D, S>>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    (arb_leaves::<D>(max_leaves), arb_hash::<D>())
+        .prop_map(|(leaves, padding)| MerkleTree::from_leaves(&leaves, &padding))
+}
+
+/// generates non-empty trees with up to `max_leaves` leaves and the offset of one of their leaves,
+/// the offset may point into the padding
+pub fn arb_tree_and_offset<D, S>(
+    max_leaves: usize,
+) -> impl Strategy<Value = (MerkleTree<D, S>, usize)>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    (vec(arb_hash::<D>(), 1..=max_leaves.max(1)), arb_hash::<D>()).prop_flat_map(
+        |(leaves, padding)| {
+            let num_leaves = leaves.len().next_power_of_two();
+            (0..num_leaves)
+                .prop_map(move |offset| (MerkleTree::from_leaves(&leaves, &padding), offset))
+        },
+    )
+}
+
+/// generates non-empty trees with up to `max_leaves` leaves and up to `max_updates` updates of their
+/// leaves
+pub fn arb_tree_and_updates<D, S>(
+    max_leaves: usize,
+    max_updates: usize,
+) -> impl Strategy<Value = (MerkleTree<D, S>, Updates<D>)>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+    S: HashScheme<D>,
+{
+    (vec(arb_hash::<D>(), 1..=max_leaves.max(1)), arb_hash::<D>()).prop_flat_map(
+        move |(leaves, padding)| {
+            let num_leaves = leaves.len().next_power_of_two();
+            arb_updates::<D>(num_leaves, max_updates)
+                .prop_map(move |updates| (MerkleTree::from_leaves(&leaves, &padding), updates))
+        },
+    )
+}
+
+impl<D, S> Arbitrary for MerkleTree<D, S>
+where
+    D: Digest + Default + Clone + Debug + 'static,
+    Output<D>: Copy,
+    S: HashScheme<D> + 'static,
+{
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        arb_tree::<D, S>(MAX_ARBITRARY_LEAVES).boxed()
+    }
+}
+
+/// Proofs with random siblings for a leaf of a tree of up to `MAX_ARBITRARY_LEAVES` leaves, they
+/// don't belong to any tree and are meant for encoding round trips and negative tests
+impl<D, S> Arbitrary for Proof<D, S>
+where
+    D: Digest + Default + Clone + Debug + 'static,
+    Output<D>: Copy,
+    S: HashScheme<D> + Debug + 'static,
+{
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let max_len = MAX_ARBITRARY_LEAVES.trailing_zeros() as usize;
+        vec(arb_hash::<D>(), 0..=max_len)
+            .prop_flat_map(|siblings| {
+                (0..1usize << siblings.len())
+                    .prop_map(move |leaf_index| Proof::new(leaf_index, siblings.clone()))
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    use crate::{verify, Plain, Rfc6962};
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type Proof = crate::Proof<Sha3_256>;
+
+    proptest! {
+        #[test]
+        fn test_proofs_verify((tree, offset) in arb_tree_and_offset::<Sha3_256, Rfc6962>(100)) {
+            let leaf = tree.get(offset).unwrap();
+            prop_assert!(verify(tree.root_hash(), leaf, &tree.create_proof(offset)));
+        }
+
+        #[test]
+        fn test_set_and_restore((mut tree, updates) in arb_tree_and_updates::<Sha3_256, Plain>(40, 20)) {
+            let root = *tree.root_hash();
+            let previous: Vec<_> = updates.iter().map(|(offset, _)| *tree.get(*offset).unwrap()).collect();
+            for (offset, value) in &updates {
+                tree.set(*offset, value);
+            }
+            for ((offset, _), value) in updates.iter().zip(previous).rev() {
+                tree.set(*offset, &value);
+            }
+            prop_assert_eq!(*tree.root_hash(), root);
+        }
+
+        #[test]
+        fn test_arbitrary(tree in any::<MerkleTree>(), proof in any::<Proof>()) {
+            prop_assert!(tree.num_leaves() <= MAX_ARBITRARY_LEAVES);
+            prop_assert_eq!(tree.check_integrity(), Ok(()));
+            prop_assert_eq!(Proof::from_bytes(&proof.to_bytes()), Ok(proof));
+        }
+    }
+}
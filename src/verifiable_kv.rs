@@ -0,0 +1,430 @@
+//! An embedded key-value store with verifiable reads
+//!
+//! `VerifiableKv` keeps its entries in a `MerkleMap` and every write in an append-only log file,
+//! the write-ahead log (WAL). A write only takes effect once its record is appended and synced, so
+//! `open` restores the store by replaying the log. Every log record carries the root after the write,
+//! replaying checks it and detects a log that was modified. A record cut off by a crash while it
+//! was written is dropped.
+//!
+//! Reads are served with proofs: `get_with_proof` returns the value or its absence together with
+//! a `MapProof` against the current root. `snapshot` freezes the current state, so reads against
+//! one root can continue to be served while writes go on, and `root_at` returns the root of every
+//! version in the log.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::Path;
+
+use core::fmt::Debug;
+
+use digest::{Digest, Output};
+use sha3::Sha3_256;
+
+use crate::custody::{invalid_data, read_bytes, read_hash, write_bytes};
+use crate::{MapProof, MerkleMap};
+
+/// magic bytes at the start of the log file
+const LOG_MAGIC: &[u8; 4] = b"MTKV";
+/// version of the log format
+const FORMAT_VERSION: u8 = 1;
+/// record type of a write setting the value of a key
+const PUT: u8 = 0;
+/// record type of a write removing a key
+const DELETE: u8 = 1;
+
+/// A value or its absence read from the store, with the proof against the root of its version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedRead<D: Digest = Sha3_256> {
+    /// the value of the key, None if the key has no value
+    pub value: Option<Vec<u8>>,
+    /// proof of the value or of the absence of the key
+    pub proof: MapProof<D>,
+    /// version of the store the read was served from
+    pub version: u64,
+}
+
+impl<D> VerifiedRead<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// returns true if the proof shows the value or the absence of the key under the root
+    pub fn verify(&self, root: &Output<D>, key: &[u8]) -> bool {
+        match &self.value {
+            Some(value) => self.proof.verify_inclusion(root, key, value),
+            None => self.proof.verify_absence(root, key),
+        }
+    }
+}
+
+/// A frozen state of a `VerifiableKv` serving reads against its root
+#[derive(Debug, Clone)]
+pub struct KvSnapshot<D: Digest = Sha3_256> {
+    map: MerkleMap<Vec<u8>, Vec<u8>, D>,
+    version: u64,
+}
+
+impl<D> KvSnapshot<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// returns the version of the store the snapshot was taken at
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// returns the root of the snapshot
+    pub fn root(&self) -> Output<D> {
+        self.map.root_hash()
+    }
+
+    /// returns the value of the key with a proof against the root of the snapshot
+    pub fn get_with_proof(&self, key: &[u8]) -> VerifiedRead<D> {
+        read(&self.map, self.version, key)
+    }
+}
+
+/// An embedded key-value store persisted in a write-ahead log and serving verifiable reads
+#[derive(Debug)]
+pub struct VerifiableKv<D: Digest = Sha3_256> {
+    map: MerkleMap<Vec<u8>, Vec<u8>, D>,
+    /// the log file, positioned at its end
+    log: File,
+    /// length of the complete records in the log, a failed append is cut off here
+    len: u64,
+    /// number of bytes an append writes before it fails, to test the rollback
+    #[cfg(test)]
+    fail_append: Option<usize>,
+    /// the root of every version, starting with the empty store
+    roots: Vec<Output<D>>,
+}
+
+impl<D> VerifiableKv<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// opens the store logged to the file at the path, creating it if it doesn't exist
+    /// returns an error if the file is not a log of this digest or a record doesn't match its root
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        log.read_to_end(&mut bytes)?;
+        let mut store = Self {
+            map: MerkleMap::new(),
+            log,
+            len: bytes.len() as u64,
+            roots: Vec::new(),
+            #[cfg(test)]
+            fail_append: None,
+        };
+        store.roots.push(store.map.root_hash());
+        if bytes.is_empty() {
+            let mut header = LOG_MAGIC.to_vec();
+            header.extend_from_slice(&[FORMAT_VERSION, <D as Digest>::output_size() as u8]);
+            store.append(&header)?;
+            return Ok(store);
+        }
+        store.replay(&bytes)?;
+        Ok(store)
+    }
+
+    /// returns the root of the current version
+    pub fn root(&self) -> Output<D> {
+        self.map.root_hash()
+    }
+
+    /// returns the number of writes applied to the store
+    pub fn version(&self) -> u64 {
+        self.roots.len() as u64 - 1
+    }
+
+    /// returns the root after the given number of writes
+    pub fn root_at(&self, version: u64) -> Option<Output<D>> {
+        self.roots.get(usize::try_from(version).ok()?).copied()
+    }
+
+    /// returns the number of keys in the store
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// returns true if the store holds no keys
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// returns the value of the key
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.map.get(&key.to_vec()).map(Vec::as_slice)
+    }
+
+    /// returns the value of the key with a proof against the current root
+    pub fn get_with_proof(&self, key: &[u8]) -> VerifiedRead<D> {
+        read(&self.map, self.version(), key)
+    }
+
+    /// sets the value of the key, returns the new root
+    /// the write is only kept if it was logged and synced
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> io::Result<Output<D>> {
+        let previous = self.map.insert(key.to_vec(), value.to_vec());
+        self.log_write(PUT, key, value, previous)
+    }
+
+    /// removes the key, returns the new root
+    /// the write is only kept if it was logged and synced, removing a missing key is a no-op
+    pub fn delete(&mut self, key: &[u8]) -> io::Result<Output<D>> {
+        match self.map.remove(&key.to_vec()) {
+            Some(previous) => self.log_write(DELETE, key, &[], Some(previous)),
+            None => Ok(self.root()),
+        }
+    }
+
+    /// takes a snapshot of the current version, copying the entries of the store
+    pub fn snapshot(&self) -> KvSnapshot<D> {
+        KvSnapshot {
+            map: self.map.clone(),
+            version: self.version(),
+        }
+    }
+
+    /// logs a write that was applied to the map, restores the previous value if that fails
+    fn log_write(
+        &mut self,
+        kind: u8,
+        key: &[u8],
+        value: &[u8],
+        previous: Option<Vec<u8>>,
+    ) -> io::Result<Output<D>> {
+        let root = self.map.root_hash();
+        let mut record = vec![kind];
+        write_bytes(&mut record, key);
+        write_bytes(&mut record, value);
+        record.extend_from_slice(&root);
+        if let Err(err) = self.append(&record) {
+            match previous {
+                Some(previous) => self.map.insert(key.to_vec(), previous),
+                None => self.map.remove(&key.to_vec()),
+            };
+            return Err(err);
+        }
+        self.roots.push(root);
+        Ok(root)
+    }
+
+    /// appends the bytes to the log and syncs it
+    /// a partially written record is cut off again, the next record would be appended behind it
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if let Err(err) = self.write_synced(bytes) {
+            let _ = self.log.set_len(self.len);
+            return Err(err);
+        }
+        self.len += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// writes the bytes to the end of the log and waits until they are on the disk
+    fn write_synced(&mut self, bytes: &[u8]) -> io::Result<()> {
+        #[cfg(test)]
+        if let Some(written) = self.fail_append {
+            self.log.write_all(&bytes[..written.min(bytes.len())])?;
+            return Err(io::Error::other("injected append failure"));
+        }
+        self.log.write_all(bytes)?;
+        self.log.sync_data()
+    }
+
+    /// applies the records of the log, dropping a record cut off at the end
+    fn replay(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut reader = bytes;
+        let mut header = [0; 6];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != LOG_MAGIC {
+            return Err(invalid_data("invalid magic bytes"));
+        }
+        if header[4] != FORMAT_VERSION {
+            return Err(invalid_data("unsupported format version"));
+        }
+        if header[5] as usize != <D as Digest>::output_size() {
+            return Err(invalid_data("hash size doesn't match the digest"));
+        }
+        while !reader.is_empty() {
+            let complete = bytes.len() - reader.len();
+            let record = match read_record::<D>(&mut reader) {
+                Ok(record) => record,
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                    self.log.set_len(complete as u64)?;
+                    self.len = complete as u64;
+                    return Ok(());
+                }
+                Err(err) => return Err(err),
+            };
+            match record.kind {
+                PUT => self.map.insert(record.key, record.value),
+                DELETE => self.map.remove(&record.key),
+                _ => return Err(invalid_data("unknown record type")),
+            };
+            if self.map.root_hash() != record.root {
+                return Err(invalid_data("log record doesn't match its root"));
+            }
+            self.roots.push(record.root);
+        }
+        Ok(())
+    }
+}
+
+fn read<D>(map: &MerkleMap<Vec<u8>, Vec<u8>, D>, version: u64, key: &[u8]) -> VerifiedRead<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    let key = key.to_vec();
+    let value = map.get(&key).cloned();
+    let proof = match value {
+        Some(_) => map.prove(&key),
+        None => map.prove_absence(&key),
+    };
+    VerifiedRead {
+        value,
+        proof: proof.expect("the proof matches the presence of the key"),
+        version,
+    }
+}
+
+/// a write read from the log
+struct Record<D: Digest> {
+    kind: u8,
+    key: Vec<u8>,
+    value: Vec<u8>,
+    /// the root after the write
+    root: Output<D>,
+}
+
+fn read_record<D: Digest>(reader: &mut &[u8]) -> io::Result<Record<D>> {
+    let mut kind = [0];
+    reader.read_exact(&mut kind)?;
+    Ok(Record {
+        kind: kind[0],
+        key: read_bytes(reader)?,
+        value: read_bytes(reader)?,
+        root: read_hash::<D>(reader)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    type VerifiableKv = super::VerifiableKv<Sha3_256>;
+
+    fn log_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("merkle-kv-{name}-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_verifiable_kv() {
+        let path = log_path("reads");
+        let mut store = VerifiableKv::open(&path).unwrap();
+        let empty = store.root();
+        store.put(b"alice", b"10").unwrap();
+        store.put(b"bob", b"20").unwrap();
+        let snapshot = store.snapshot();
+        store.put(b"alice", b"15").unwrap();
+        let root = store.delete(b"bob").unwrap();
+        assert_eq!(store.delete(b"carol").unwrap(), root);
+        assert_eq!(store.version(), 4);
+        assert_eq!(store.root_at(0), Some(empty));
+
+        let read = store.get_with_proof(b"alice");
+        assert_eq!(read.value.as_deref(), Some(&b"15"[..]));
+        assert!(read.verify(&store.root(), b"alice"));
+        assert!(!read.verify(&snapshot.root(), b"alice"));
+        let read = store.get_with_proof(b"bob");
+        assert_eq!(read.value, None);
+        assert!(read.verify(&store.root(), b"bob"));
+
+        // the snapshot still serves the old version
+        let read = snapshot.get_with_proof(b"bob");
+        assert_eq!(read.version, 2);
+        assert_eq!(read.value.as_deref(), Some(&b"20"[..]));
+        assert!(read.verify(&store.root_at(2).unwrap(), b"bob"));
+
+        // reopening replays the log
+        drop(store);
+        let store = VerifiableKv::open(&path).unwrap();
+        assert_eq!(store.root(), root);
+        assert_eq!(store.version(), 4);
+        assert_eq!(store.root_at(2), Some(snapshot.root()));
+        assert_eq!(store.get(b"alice"), Some(&b"15"[..]));
+        assert_eq!(store.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_verifiable_kv_recovery() {
+        let path = log_path("recovery");
+        let mut store = VerifiableKv::open(&path).unwrap();
+        store.put(b"key", b"value").unwrap();
+        let root = store.root();
+        store.put(b"other", b"value").unwrap();
+        drop(store);
+
+        // a record cut off by a crash is dropped
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+        let mut store = VerifiableKv::open(&path).unwrap();
+        assert_eq!(store.root(), root);
+        assert_eq!(store.get(b"other"), None);
+        store.put(b"other", b"value").unwrap();
+        drop(store);
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+
+        // a modified record doesn't match its root
+        let mut tampered = bytes.clone();
+        let value = tampered.len() - 32 - 1;
+        tampered[value] ^= 1;
+        fs::write(&path, &tampered).unwrap();
+        assert_eq!(
+            VerifiableKv::open(&path).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert!(super::VerifiableKv::<sha2::Sha512>::open(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_verifiable_kv_failed_append() {
+        let path = log_path("failed-append");
+        let mut store = VerifiableKv::open(&path).unwrap();
+        store.put(b"key", b"value").unwrap();
+        let root = store.root();
+        let len = fs::metadata(&path).unwrap().len();
+
+        // an append failing after writing part of the record leaves neither the write nor the bytes
+        store.fail_append = Some(5);
+        assert!(store.put(b"other", b"value").is_err());
+        assert!(store.delete(b"key").is_err());
+        assert_eq!(store.root(), root);
+        assert_eq!(store.version(), 1);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+
+        store.fail_append = None;
+        let root = store.put(b"other", b"value").unwrap();
+        drop(store);
+        let store = VerifiableKv::open(&path).unwrap();
+        assert_eq!(store.root(), root);
+        assert_eq!(store.get(b"key"), Some(&b"value"[..]));
+        assert_eq!(store.version(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+//! Versioned Merkle trees that can answer queries about past states
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::time::SystemTime;
+
+use digest::{Digest, Output};
+
+use crate::{MerkleTree, MerkleTreeError, Proof};
+
+/// A Merkle tree that keeps track of all committed versions
+/// Every `set` journals the previous values of the nodes it overwrites, so roots and proofs of every
+/// committed version can be reconstructed later. Committed versions are indexed by their commit
+/// timestamp, so past states can be queried both by version number and by wall-clock time.
+pub struct VersionedMerkleTree<D: Digest> {
+    /// the current state of the tree, including uncommitted changes
+    tree: MerkleTree<D>,
+    /// previous values of overwritten nodes by node index
+    /// each entry holds the version in which the node was overwritten and the value it had before
+    journal: HashMap<usize, Vec<(u64, Output<D>)>>,
+    /// commit timestamps indexed by version, timestamps are non-decreasing
+    timestamps: Vec<SystemTime>,
+}
+
+impl<D> VersionedMerkleTree<D>
+where
+    D: Digest + Default + Clone + Debug,
+    Output<D>: Copy,
+{
+    /// creates a new versioned tree with the given depth and initial value for the leaves
+    /// the initial state is committed as version 0 with the current time as timestamp
+    pub fn new(depth: usize, initial_value: &Output<D>) -> Self {
+        Self::from_tree(MerkleTree::new(depth, initial_value), SystemTime::now())
+    }
+
+    /// creates a new versioned tree from an existing tree, committing its state as version 0 at the given timestamp
+    pub fn from_tree(tree: MerkleTree<D>, timestamp: SystemTime) -> Self {
+        Self {
+            tree,
+            journal: HashMap::new(),
+            timestamps: vec![timestamp],
+        }
+    }
+
+    /// returns the current state of the tree, including uncommitted changes
+    pub fn tree(&self) -> &MerkleTree<D> {
+        &self.tree
+    }
+
+    /// returns the latest committed version
+    pub fn version(&self) -> u64 {
+        self.timestamps.len() as u64 - 1
+    }
+
+    /// returns the timestamp the given version was committed at
+    pub fn timestamp(&self, version: u64) -> Option<SystemTime> {
+        self.timestamps.get(version as usize).copied()
+    }
+
+    /// updates the value of a leaf node
+    /// the change becomes part of the next committed version
+    /// panics if the offset is out of bounds, see `try_set` for a fallible version
+    pub fn set(&mut self, offset: usize, value: &Output<D>) {
+        if let Err(err) = self.try_set(offset, value) {
+            panic!("{err}");
+        }
+    }
+
+    /// updates the value of a leaf node
+    /// the change becomes part of the next committed version
+    /// returns an error if the offset is out of bounds
+    pub fn try_set(&mut self, offset: usize, value: &Output<D>) -> Result<(), MerkleTreeError> {
+        let num_leaves = self.tree.num_leaves();
+        if offset >= num_leaves {
+            return Err(MerkleTreeError::LeafIndexOutOfBounds { offset, num_leaves });
+        }
+        let pending = self.timestamps.len() as u64;
+        for index in MerkleTree::<D>::path_indices(self.tree.depth(), offset) {
+            let entries = self.journal.entry(index).or_default();
+            // only the first overwrite within a version needs to be journaled
+            if entries.last().map(|(version, _)| *version) != Some(pending) {
+                entries.push((pending, *self.tree.node(index)));
+            }
+        }
+        self.tree.try_set(offset, value)
+    }
+
+    /// commits all changes since the last commit as a new version, using the current time as timestamp
+    /// if the clock was set back since the previous commit, the timestamp of the previous version is
+    /// used, so timestamps never decrease
+    /// returns the new version
+    pub fn commit(&mut self) -> u64 {
+        let now = SystemTime::now();
+        let timestamp = match self.timestamps.last() {
+            Some(last) => now.max(*last),
+            None => now,
+        };
+        self.timestamps.push(timestamp);
+        self.version()
+    }
+
+    /// commits all changes since the last commit as a new version with the given timestamp
+    /// returns the new version, or an error if the timestamp is earlier than the timestamp of the
+    /// previous version
+    pub fn commit_at(&mut self, timestamp: SystemTime) -> Result<u64, MerkleTreeError> {
+        if self.timestamps.last().is_some_and(|last| timestamp < *last) {
+            return Err(MerkleTreeError::DecreasingTimestamp);
+        }
+        self.timestamps.push(timestamp);
+        Ok(self.version())
+    }
+
+    /// returns the latest version that was committed at or before the given timestamp
+    pub fn version_at(&self, timestamp: SystemTime) -> Option<u64> {
+        let count = self.timestamps.partition_point(|t| *t <= timestamp);
+        count.checked_sub(1).map(|version| version as u64)
+    }
+
+    /// returns the root hash of the given committed version
+    pub fn root_at_version(&self, version: u64) -> Option<Output<D>> {
+        self.node_at_version(0, version)
+    }
+
+    /// Create a proof for a leaf node against the root of the given committed version
+    /// returns None if the offset is out of bounds or the version wasn't committed yet
+    pub fn create_proof_at_version(&self, offset: usize, version: u64) -> Option<Proof<D>> {
+        if offset >= self.tree.num_leaves() {
+            return None;
+        }
+        let siblings = MerkleTree::<D>::sibling_indices(self.tree.depth(), offset)
+            .into_iter()
+            .map(|(index, _)| self.node_at_version(index, version))
+            .collect::<Option<_>>()?;
+        Some(Proof::new(offset, siblings))
+    }
+
+    /// returns the root hash of the tree as it was at the given point in time
+    /// returns None if the timestamp is earlier than the first version
+    pub fn root_at(&self, timestamp: SystemTime) -> Option<Output<D>> {
+        self.root_at_version(self.version_at(timestamp)?)
+    }
+
+    /// Create a proof for a leaf node against the root of the tree as it was at the given point in time
+    /// returns None if the offset is out of bounds or the timestamp is earlier than the first version
+    pub fn create_proof_at(&self, offset: usize, timestamp: SystemTime) -> Option<Proof<D>> {
+        self.create_proof_at_version(offset, self.version_at(timestamp)?)
+    }
+
+    /// returns the value of a node in the given committed version
+    fn node_at_version(&self, index: usize, version: u64) -> Option<Output<D>> {
+        if version > self.version() {
+            return None;
+        }
+        // the first overwrite after the requested version holds the value the node had back then
+        let overwrite = self.journal.get(&index).and_then(|entries| {
+            let i = entries.partition_point(|(v, _)| *v <= version);
+            entries.get(i)
+        });
+        Some(match overwrite {
+            Some((_, value)) => *value,
+            None => *self.tree.node(index),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use sha3::Sha3_256;
+
+    type MerkleTree = crate::MerkleTree<Sha3_256>;
+    type VersionedMerkleTree = super::VersionedMerkleTree<Sha3_256>;
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_root_and_proof_at_version() {
+        let initial_value = [0x00; 32].into();
+        let mut reference = MerkleTree::new(4, &initial_value);
+        let mut tree = VersionedMerkleTree::from_tree(MerkleTree::new(4, &initial_value), at(100));
+
+        let mut roots = vec![*reference.root_hash()];
+        let mut proofs = vec![reference.create_proof(2)];
+        for version in 1..4u8 {
+            for i in [2, 5, version as usize] {
+                let updated_value = [version * 0x11 + i as u8; 32].into();
+                tree.set(i, &updated_value);
+                reference.set(i, &updated_value);
+            }
+            assert_eq!(
+                tree.commit_at(at(100 + version as u64 * 10)),
+                Ok(version as u64)
+            );
+            roots.push(*reference.root_hash());
+            proofs.push(reference.create_proof(2));
+        }
+
+        // uncommitted changes don't affect committed versions
+        tree.set(2, &[0xff; 32].into());
+
+        for (version, (root, proof)) in roots.iter().zip(&proofs).enumerate() {
+            assert_eq!(tree.root_at_version(version as u64).as_ref(), Some(root));
+            assert_eq!(
+                tree.create_proof_at_version(2, version as u64).as_ref(),
+                Some(proof)
+            );
+        }
+        assert_eq!(tree.root_at_version(4), None);
+        assert_ne!(tree.tree().root_hash(), &roots[3]);
+    }
+
+    #[test]
+    fn test_root_at_timestamp() {
+        let initial_value = [0x00; 32].into();
+        let mut tree = VersionedMerkleTree::from_tree(MerkleTree::new(3, &initial_value), at(100));
+        let root_0 = *tree.tree().root_hash();
+        tree.set(1, &[0x11; 32].into());
+        tree.commit_at(at(200)).unwrap();
+        let root_1 = *tree.tree().root_hash();
+
+        assert_eq!(tree.version_at(at(99)), None);
+        assert_eq!(tree.root_at(at(99)), None);
+        assert_eq!(tree.root_at(at(100)), Some(root_0));
+        assert_eq!(tree.root_at(at(199)), Some(root_0));
+        assert_eq!(tree.root_at(at(200)), Some(root_1));
+        assert_eq!(tree.root_at(at(1000)), Some(root_1));
+        assert_eq!(
+            tree.create_proof_at(1, at(150)),
+            Some(MerkleTree::new(3, &initial_value).create_proof(1))
+        );
+        assert_eq!(tree.timestamp(1), Some(at(200)));
+
+        assert_eq!(tree.create_proof_at(4, at(150)), None);
+        assert_eq!(tree.create_proof_at_version(4, 1), None);
+        assert_eq!(
+            tree.try_set(4, &[0x11; 32].into()),
+            Err(MerkleTreeError::LeafIndexOutOfBounds {
+                offset: 4,
+                num_leaves: 4
+            })
+        );
+        assert_eq!(tree.root_at_version(1), Some(root_1));
+    }
+
+    #[test]
+    fn test_commit_with_decreasing_timestamp() {
+        let mut tree = VersionedMerkleTree::from_tree(MerkleTree::new(3, &[0; 32].into()), at(100));
+        assert_eq!(
+            tree.commit_at(at(50)),
+            Err(MerkleTreeError::DecreasingTimestamp)
+        );
+        assert_eq!(tree.version(), 0);
+
+        // a clock behind the previous commit reuses its timestamp
+        let future = SystemTime::now() + Duration::from_secs(3600);
+        tree.commit_at(future).unwrap();
+        assert_eq!(tree.commit(), 2);
+        assert_eq!(tree.timestamp(2), Some(future));
+    }
+}
@@ -0,0 +1,333 @@
+//! Write-ahead log of the leaf updates of a memory mapped tree
+//!
+//! `MerkleTree::set_many` appends the new leaves of a batch to a log file next to the tree file and
+//! syncs it before the nodes are written, so a crash while the paths of the batch are rehashed
+//! leaves a record that restores a consistent tree: `MerkleTree::open` sets the leaves of every
+//! record again and rehashes their paths. `MerkleTree::checkpoint` syncs the nodes and empties
+//! the log. Leaves written outside of `set_many` aren't logged, their writes checkpoint first, so
+//! no record holds a value older than the one in the tree.
+//!
+//! The log is the file name of the tree with `.wal` appended. It starts with the magic `MTWL` and
+//! the format version, followed by one record per batch:
+//!
+//! | bytes | content |
+//! |-------|---------|
+//! | 8 | number of leaves, little endian |
+//! | 8 + hash size per leaf | offset of the leaf, little endian, and its new value |
+//! | hash size | digest of the bytes above, the checksum of the record |
+//!
+//! A record that is cut short or doesn't match its checksum was being appended when the process
+//! crashed, none of its leaves were written yet, it is ignored together with everything behind it.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use digest::{Digest, Output};
+
+/// magic bytes at the start of every log
+const MAGIC: &[u8; 4] = b"MTWL";
+/// version of the log format
+const VERSION: u8 = 1;
+/// length of the magic and the version in front of the first record
+const HEADER_LEN: usize = MAGIC.len() + 1;
+
+/// the offsets and new values of the leaves of one record
+type Batch<D> = Vec<(usize, Output<D>)>;
+
+/// The log file of a memory mapped tree, opened on the first append
+pub(crate) struct Wal {
+    path: PathBuf,
+    file: Option<File>,
+    /// length of the log in bytes
+    len: u64,
+}
+
+impl Wal {
+    /// returns the path of the log of the tree file at the given path
+    fn path_of(tree_path: &Path) -> PathBuf {
+        let mut path = OsString::from(tree_path.as_os_str());
+        path.push(".wal");
+        PathBuf::from(path)
+    }
+
+    /// returns an empty log for a new tree file, a log of an older tree at the same path is removed
+    pub(crate) fn create(tree_path: &Path) -> io::Result<Self> {
+        let path = Self::path_of(tree_path);
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        Ok(Self {
+            path,
+            file: None,
+            len: 0,
+        })
+    }
+
+    /// opens the log of an existing tree file, if it has one
+    pub(crate) fn open(tree_path: &Path) -> io::Result<Self> {
+        let path = Self::path_of(tree_path);
+        let file = match OpenOptions::new().read(true).append(true).open(&path) {
+            Ok(file) => Some(file),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        let len = match &file {
+            Some(file) => file.metadata()?.len(),
+            None => 0,
+        };
+        Ok(Self { path, file, len })
+    }
+
+    /// returns the length of the log in bytes
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// returns the leaves of every complete record in the order they were appended
+    pub(crate) fn read<D: Digest>(&self) -> io::Result<Vec<Batch<D>>> {
+        if self.len == 0 {
+            return Ok(Vec::new());
+        }
+        let bytes = fs::read(&self.path)?;
+        // the header itself may have been cut short
+        if bytes.len() < HEADER_LEN && MAGIC.starts_with(&bytes[..bytes.len().min(MAGIC.len())]) {
+            return Ok(Vec::new());
+        }
+        if !bytes.starts_with(MAGIC) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a merkle tree write-ahead log",
+            ));
+        }
+        if bytes[MAGIC.len()] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported write-ahead log version",
+            ));
+        }
+        let hash_size = <D as Digest>::output_size();
+        let mut batches = Vec::new();
+        let mut rest = &bytes[HEADER_LEN..];
+        while rest.len() >= 8 {
+            let count = u64::from_le_bytes(rest[..8].try_into().unwrap());
+            let Some(len) = usize::try_from(count)
+                .ok()
+                .and_then(|count| count.checked_mul(8 + hash_size))
+                .and_then(|len| len.checked_add(8 + hash_size))
+                .filter(|len| *len <= rest.len())
+            else {
+                break;
+            };
+            let (record, checksum) = rest[..len].split_at(len - hash_size);
+            if D::digest(record)[..] != *checksum {
+                break;
+            }
+            let leaves = record[8..]
+                .chunks_exact(8 + hash_size)
+                .map(|leaf| {
+                    let offset = u64::from_le_bytes(leaf[..8].try_into().unwrap());
+                    let offset = usize::try_from(offset).unwrap_or(usize::MAX);
+                    (offset, Output::<D>::clone_from_slice(&leaf[8..]))
+                })
+                .collect();
+            batches.push(leaves);
+            rest = &rest[len..];
+        }
+        Ok(batches)
+    }
+
+    /// appends a record of the leaves and waits until it is on the disk
+    pub(crate) fn append<D: Digest>(&mut self, leaves: &[(usize, Output<D>)]) -> io::Result<()> {
+        let hash_size = <D as Digest>::output_size();
+        let mut record = Vec::with_capacity(HEADER_LEN + 8 + leaves.len() * (8 + hash_size));
+        if self.len == 0 {
+            record.extend_from_slice(MAGIC);
+            record.push(VERSION);
+        }
+        let start = record.len();
+        record.extend_from_slice(&(leaves.len() as u64).to_le_bytes());
+        for (offset, value) in leaves {
+            record.extend_from_slice(&(*offset as u64).to_le_bytes());
+            record.extend_from_slice(value);
+        }
+        let checksum = D::digest(&record[start..]);
+        record.extend_from_slice(&checksum);
+
+        let file = match &mut self.file {
+            Some(file) => file,
+            file => file.insert(
+                OpenOptions::new()
+                    .read(true)
+                    .append(true)
+                    .create(true)
+                    .open(&self.path)?,
+            ),
+        };
+        if let Err(err) = file.write_all(&record).and_then(|()| file.sync_data()) {
+            // drop the partial record, records appended later would be hidden behind it
+            let _ = file.set_len(self.len);
+            return Err(err);
+        }
+        self.len += record.len() as u64;
+        Ok(())
+    }
+
+    /// removes all records, the nodes they describe have to be on the disk
+    pub(crate) fn clear(&mut self) -> io::Result<()> {
+        if let Some(file) = &self.file {
+            if self.len > 0 {
+                file.set_len(0)?;
+                file.sync_data()?;
+            }
+        }
+        self.len = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha3::Sha3_256;
+
+    /// a tree path in the temp directory whose log is removed on drop
+    struct TempLog(PathBuf);
+
+    impl TempLog {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "merkle-tree-rs-{}-wal-{name}.mt",
+                std::process::id()
+            ));
+            let log = TempLog(path);
+            let _ = fs::remove_file(log.wal());
+            log
+        }
+
+        fn wal(&self) -> PathBuf {
+            Wal::path_of(&self.0)
+        }
+
+        fn open(&self) -> Wal {
+            Wal::open(&self.0).unwrap()
+        }
+
+        /// rewrites the log with the bytes returned by the function
+        fn rewrite(&self, rewrite: impl FnOnce(&mut Vec<u8>)) {
+            let mut bytes = fs::read(self.wal()).unwrap();
+            rewrite(&mut bytes);
+            fs::write(self.wal(), bytes).unwrap();
+        }
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(self.wal());
+        }
+    }
+
+    fn batch(offsets: &[usize]) -> Batch<Sha3_256> {
+        offsets
+            .iter()
+            .map(|&offset| (offset, Sha3_256::digest([offset as u8])))
+            .collect()
+    }
+
+    /// a log holding two records, returns the length of the log after the first one
+    fn two_records(log: &TempLog) -> u64 {
+        let mut wal = Wal::create(&log.0).unwrap();
+        wal.append::<Sha3_256>(&batch(&[1, 2])).unwrap();
+        let first = wal.len();
+        wal.append::<Sha3_256>(&batch(&[3])).unwrap();
+        first
+    }
+
+    #[test]
+    fn test_wal_roundtrip() {
+        let log = TempLog::new("roundtrip");
+        assert!(log.open().read::<Sha3_256>().unwrap().is_empty());
+        let first = two_records(&log);
+        assert_eq!(first, HEADER_LEN as u64 + 8 + 2 * 40 + 32);
+        let wal = log.open();
+        assert_eq!(wal.len(), first + 8 + 40 + 32);
+        assert_eq!(
+            wal.read::<Sha3_256>().unwrap(),
+            vec![batch(&[1, 2]), batch(&[3])]
+        );
+    }
+
+    #[test]
+    fn test_wal_torn_tail() {
+        let log = TempLog::new("torn");
+        let first = two_records(&log);
+        let len = log.open().len();
+        // a crash anywhere in the second record keeps the first one
+        for cut in first..len {
+            log.rewrite(|bytes| bytes.truncate(cut as usize));
+            assert_eq!(log.open().read::<Sha3_256>().unwrap(), vec![batch(&[1, 2])]);
+            two_records(&log);
+        }
+        // a crash in the first record or the header leaves nothing to replay
+        for cut in 0..first {
+            log.rewrite(|bytes| bytes.truncate(cut as usize));
+            assert!(log.open().read::<Sha3_256>().unwrap().is_empty());
+            two_records(&log);
+        }
+    }
+
+    #[test]
+    fn test_wal_checksum_mismatch() {
+        let log = TempLog::new("checksum");
+        let first = two_records(&log) as usize;
+        // a corrupt record is ignored together with everything behind it
+        log.rewrite(|bytes| bytes[first + 8] ^= 1);
+        assert_eq!(log.open().read::<Sha3_256>().unwrap(), vec![batch(&[1, 2])]);
+        two_records(&log);
+        log.rewrite(|bytes| bytes[first - 1] ^= 1);
+        assert!(log.open().read::<Sha3_256>().unwrap().is_empty());
+        // a count larger than the log is a torn record as well
+        two_records(&log);
+        log.rewrite(|bytes| bytes[first..first + 8].copy_from_slice(&u64::MAX.to_le_bytes()));
+        assert_eq!(log.open().read::<Sha3_256>().unwrap(), vec![batch(&[1, 2])]);
+    }
+
+    #[test]
+    fn test_wal_rejects_other_files() {
+        let log = TempLog::new("magic");
+        two_records(&log);
+        log.rewrite(|bytes| bytes[0] = b'X');
+        let err = log.open().read::<Sha3_256>().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "not a merkle tree write-ahead log");
+
+        two_records(&log);
+        log.rewrite(|bytes| bytes[MAGIC.len()] = VERSION + 1);
+        let err = log.open().read::<Sha3_256>().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "unsupported write-ahead log version");
+    }
+
+    #[test]
+    fn test_wal_replay_is_idempotent() {
+        let log = TempLog::new("replay");
+        let first = two_records(&log);
+        log.rewrite(|bytes| bytes.truncate(first as usize + 5));
+        // reading doesn't modify the log, a replay interrupted before the checkpoint starts over
+        let mut wal = log.open();
+        let batches = wal.read::<Sha3_256>().unwrap();
+        assert_eq!(batches, vec![batch(&[1, 2])]);
+        assert_eq!(log.open().read::<Sha3_256>().unwrap(), batches);
+        assert_eq!(wal.read::<Sha3_256>().unwrap(), batches);
+
+        // the checkpoint after the replay drops the torn record, new records follow a new header
+        wal.clear().unwrap();
+        assert_eq!(fs::metadata(log.wal()).unwrap().len(), 0);
+        assert!(wal.read::<Sha3_256>().unwrap().is_empty());
+        wal.append::<Sha3_256>(&batch(&[4])).unwrap();
+        assert_eq!(log.open().read::<Sha3_256>().unwrap(), vec![batch(&[4])]);
+    }
+}
@@ -0,0 +1,43 @@
+//! Merkle proof verification as a WASI preview 2 component
+//!
+//! Build with `cargo build -p merkle-tree-verifier --target wasm32-wasip2 --release`, the resulting
+//! component exports the `merkle-tree:verifier/verify` interface defined in `wit/verifier.wit`.
+
+use digest::Output;
+use merkle_tree_rs::{verify as verify_proof, Proof};
+use sha3::Sha3_256;
+
+wit_bindgen::generate!({
+    world: "verifier",
+    path: "wit",
+});
+
+use exports::merkle_tree::verifier::verify;
+
+struct Verifier;
+
+impl verify::Guest for Verifier {
+    fn verify(root: Vec<u8>, leaf: Vec<u8>, proof: verify::Proof) -> bool {
+        let (Some(root), Some(leaf)) = (to_hash(&root), to_hash(&leaf)) else {
+            return false;
+        };
+        let Some(siblings) = proof.siblings.iter().map(|s| to_hash(s)).collect() else {
+            return false;
+        };
+        let Ok(leaf_index) = usize::try_from(proof.leaf_index) else {
+            return false;
+        };
+        verify_proof::<Sha3_256, merkle_tree_rs::Plain>(
+            &root,
+            &leaf,
+            &Proof::new(leaf_index, siblings),
+        )
+    }
+}
+
+/// converts a byte slice to a hash, returns None if the length doesn't match
+fn to_hash(bytes: &[u8]) -> Option<Output<Sha3_256>> {
+    (bytes.len() == 32).then(|| Output::<Sha3_256>::clone_from_slice(bytes))
+}
+
+export!(Verifier);